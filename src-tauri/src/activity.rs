@@ -0,0 +1,101 @@
+//! Feed of what happened to the keys while the app runs: connects, device operations and warnings.
+//!
+//! The feed is kept in memory, bounded to the most recent events. Every event gets an increasing
+//! ID, so the activity panel pages back through older events by passing the ID of the oldest
+//! event it shows as `before`. In the app, new events are also broadcast as they happen.
+
+use crate::{inventory::now, types::*};
+use std::collections::VecDeque;
+use std::sync::{Mutex, OnceLock};
+
+/// Broadcast to every window for each new event of the feed
+#[cfg(feature = "gui")]
+pub const ACTIVITY_EVENT: &str = "activity";
+
+/// Sends a new event wherever it is shown
+type Broadcast = Box<dyn Fn(&ActivityEvent) + Send + Sync>;
+
+/// Events kept, older ones are dropped
+const MAX_EVENTS: usize = 1000;
+#[cfg(feature = "gui")]
+const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// Optional context of an event
+#[derive(Default)]
+pub struct ActivityDetail {
+	/// Product name of the key concerned
+	pub device: Option<String>,
+	pub operation: Option<String>,
+	pub duration_ms: Option<u64>,
+}
+
+struct Feed {
+	next_id: u64,
+	events: VecDeque<ActivityEvent>,
+}
+
+static FEED: Mutex<Feed> = Mutex::new(Feed {
+	next_id: 1,
+	events: VecDeque::new(),
+});
+static BROADCAST: OnceLock<Broadcast> = OnceLock::new();
+
+/// Starts broadcasting new events. Events recorded before are only available through `page`.
+#[cfg(feature = "gui")]
+pub fn start(broadcast: impl Fn(&ActivityEvent) + Send + Sync + 'static) {
+	let _ = BROADCAST.set(Box::new(broadcast));
+}
+
+/// Adds an event to the feed.
+///
+/// Must not log: warnings are fed back into the feed by the logger.
+pub fn record(kind: ActivityKind, summary: String, detail: ActivityDetail) {
+	let event = {
+		let Ok(mut feed) = FEED.lock() else {
+			return;
+		};
+		let event = ActivityEvent {
+			id: feed.next_id,
+			kind,
+			timestamp: now(),
+			summary,
+			device: detail.device,
+			operation: detail.operation,
+			duration_ms: detail.duration_ms,
+		};
+		feed.next_id += 1;
+		if feed.events.len() == MAX_EVENTS {
+			feed.events.pop_front();
+		}
+		feed.events.push_back(event.clone());
+		event
+	};
+
+	if let Some(broadcast) = BROADCAST.get() {
+		broadcast(&event);
+	}
+}
+
+/// Newest events older than the event `before`, optionally only of the given kinds
+#[cfg(feature = "gui")]
+pub fn page(before: Option<u64>, limit: Option<usize>, kinds: &[ActivityKind]) -> ActivityPage {
+	let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).max(1);
+	let Ok(feed) = FEED.lock() else {
+		return ActivityPage {
+			events: Vec::new(),
+			has_more: false,
+		};
+	};
+
+	let mut matching = feed
+		.events
+		.iter()
+		.rev()
+		.filter(|e| before.is_none_or(|before| e.id < before))
+		.filter(|e| kinds.is_empty() || kinds.contains(&e.kind));
+	let events: Vec<ActivityEvent> = matching.by_ref().take(limit).cloned().collect();
+	ActivityPage {
+		events,
+		has_more: matching.next().is_some(),
+	}
+}
@@ -0,0 +1,108 @@
+//! Versioning of the command payloads shared with the frontend.
+//!
+//! During an auto-update the running frontend bundle can be older than the backend. Commands whose
+//! payload changed take the `api_version` the bundle was built against and answer in that shape,
+//! keeping the older shapes under `types::v1` and so on. Bundles that send no version predate
+//! versioning and get version 1. Their errors are versioned too: version 1 bundles get the plain
+//! message they were built to show, later ones a `PFError` object.
+
+#[cfg(feature = "gui")]
+use crate::types::ApiVersion;
+use crate::{
+	error::PFError,
+	types::{CommandError, v1},
+};
+
+/// Version of the payloads as the bundled frontend expects them
+pub const CURRENT: u32 = 2;
+pub const OLDEST_SUPPORTED: u32 = 1;
+
+#[cfg(feature = "gui")]
+pub fn version() -> ApiVersion {
+	ApiVersion {
+		current: CURRENT,
+		oldest_supported: OLDEST_SUPPORTED,
+		app_version: env!("CARGO_PKG_VERSION").to_string(),
+	}
+}
+
+/// Version to answer a caller asking for `requested`
+pub fn negotiate(requested: Option<u32>) -> Result<u32, PFError> {
+	match requested.unwrap_or(OLDEST_SUPPORTED) {
+		version if (OLDEST_SUPPORTED..=CURRENT).contains(&version) => Ok(version),
+		version => Err(PFError::Io(format!(
+			"The interface speaks API version {}, the backend supports versions {} to {}. Restart PicoForge to finish updating.",
+			version, OLDEST_SUPPORTED, CURRENT
+		))),
+	}
+}
+
+/// `e` in the shape of the negotiated `version`
+pub fn error(version: u32, e: PFError) -> CommandError {
+	match version {
+		1 => CommandError::V1(v1::error(e)),
+		_ => CommandError::Current(e),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::types::{CredentialStore, StoredCredential};
+	use serde_json::json;
+
+	fn credential() -> StoredCredential {
+		StoredCredential {
+			rp_id: "example.com".into(),
+			rp_name: "Example".into(),
+			user_name: "alice".into(),
+			user_display_name: "Alice".into(),
+			user_id: "0102".into(),
+			credential_id: "a1b2".into(),
+			rp_display_name: None,
+			account_url: Some("https://example.com/account".into()),
+			cred_protect: Some(2),
+			algorithm: Some(-7),
+		}
+	}
+
+	#[test]
+	fn unversioned_callers_get_version_1() {
+		assert_eq!(negotiate(None).unwrap(), 1);
+		assert_eq!(negotiate(Some(CURRENT)).unwrap(), CURRENT);
+		assert!(negotiate(Some(CURRENT + 1)).is_err());
+	}
+
+	#[test]
+	fn version_1_credentials_are_a_plain_list() {
+		let store = CredentialStore::new(vec![credential()], 1, 99);
+		assert_eq!(
+			serde_json::to_value(v1::credentials(store)).unwrap(),
+			json!([{
+				"rpId": "example.com",
+				"rpName": "Example",
+				"userName": "alice",
+				"userDisplayName": "Alice",
+				"userId": "0102",
+				"credentialId": "a1b2",
+				"accountUrl": "https://example.com/account",
+			}])
+		);
+	}
+
+	#[test]
+	fn version_1_errors_are_plain_messages() {
+		assert_eq!(
+			serde_json::to_value(error(1, PFError::NoDevice)).unwrap(),
+			json!("No device found")
+		);
+		assert_eq!(
+			serde_json::to_value(error(1, PFError::Device("Timeout".into()))).unwrap(),
+			json!("Device Error: Timeout")
+		);
+		assert_eq!(
+			serde_json::to_value(error(2, PFError::NoDevice)).unwrap(),
+			json!({ "type": "NoDevice", "message": "No device found" })
+		);
+	}
+}
@@ -0,0 +1,5 @@
+//! Headless command line frontend, see `picoforge_lib::cli`
+
+fn main() -> std::process::ExitCode {
+	picoforge_lib::cli::main()
+}
@@ -0,0 +1,203 @@
+//! Shared PC/SC helpers for the applets reached through the CCID interface.
+
+use crate::{
+	error::PFError,
+	operation::report_phase,
+	rescue::constants::{APDU_CLA_ISO, APDU_INS_SELECT, APDU_P1_SELECT_BY_DF_NAME},
+	types::{PhaseVerb, UserAction},
+};
+use pcsc::{Context, Protocols, Scope, ShareMode};
+
+/// ISO 7816-4 GET RESPONSE instruction
+pub const APDU_INS_GET_RESPONSE: u8 = 0xC0;
+
+/// Status word prefix signalling more response data is available (61 XX)
+pub const SW1_MORE_DATA: u8 = 0x61;
+
+/// Response of an APDU exchange with continuations already collected
+#[derive(Debug, Clone)]
+pub struct ApduResponse {
+	pub data: Vec<u8>,
+	pub sw: u16,
+}
+
+impl ApduResponse {
+	pub fn is_success(&self) -> bool {
+		self.sw == 0x9000
+	}
+}
+
+/// Connects to the card on the first available reader, without selecting an applet
+pub fn connect() -> Result<pcsc::Card, PFError> {
+	connect_where(|_| true).map(|(card, _)| card)
+}
+
+/// Connects to the card on the reader of the FIDO device at `device_path`, found through the
+/// serial number its Rescue Applet reports. Without a path, the first available reader.
+#[cfg(feature = "gui")]
+pub fn connect_device(device_path: Option<&str>) -> Result<pcsc::Card, PFError> {
+	let Some(path) = device_path else {
+		return connect();
+	};
+	let serial = crate::inventory::device_serial(Some(path)).ok_or_else(|| {
+		PFError::Device("The key reports no serial number to find its reader by".into())
+	})?;
+	let reader = crate::rescue::list_devices()?
+		.into_iter()
+		.find(|device| device.serial == serial)
+		.ok_or_else(|| {
+			PFError::Device(format!(
+				"No smart card reader found for the key with serial {}",
+				serial
+			))
+		})?
+		.reader;
+	log::debug!("Key {} is on reader {}", serial, reader);
+	connect_where(|name| name == reader).map(|(card, _)| card)
+}
+
+/// Whether the PC/SC reader `name` is a contactless (NFC) one. Drivers mark them in the name,
+/// e.g. "ACS ACR122U PICC Interface" or "Identiv uTrust 3700 F Contactless Reader".
+pub fn is_contactless(name: &str) -> bool {
+	let name = name.to_lowercase();
+	["contactless", "picc", "nfc"]
+		.iter()
+		.any(|marker| name.contains(marker))
+}
+
+/// Connects to the card on the first contactless reader, returning the card and reader name
+pub fn connect_contactless() -> Result<(pcsc::Card, String), PFError> {
+	connect_where(is_contactless)
+}
+
+/// Connects to the card on the first reader whose name matches `wanted`
+fn connect_where(wanted: impl Fn(&str) -> bool) -> Result<(pcsc::Card, String), PFError> {
+	let ctx = Context::establish(Scope::User).map_err(|e| {
+		log::error!("Failed to establish PCSC context: {}", e);
+		PFError::Pcsc(e)
+	})?;
+
+	let mut readers_buf = [0; 2048];
+	let mut readers = ctx.list_readers(&mut readers_buf)?;
+
+	let reader = readers
+		.find(|reader| wanted(&reader.to_string_lossy()))
+		.ok_or_else(|| {
+			log::info!("No matching Smart Card Reader found");
+			PFError::NoDevice
+		})?;
+
+	let name = reader.to_string_lossy().into_owned();
+	Ok((
+		ctx.connect(reader, ShareMode::Shared, Protocols::ANY)?,
+		name,
+	))
+}
+
+/// Connects to the first available reader and selects the applet identified by `aid`
+pub fn connect_and_select(aid: &[u8], applet: &str) -> Result<(pcsc::Card, Vec<u8>), PFError> {
+	report_phase(
+		PhaseVerb::Connect,
+		&format!("{} applet", applet),
+		UserAction::None,
+	);
+	let card = connect()?;
+	let data = select(&card, aid, applet)?;
+	Ok((card, data))
+}
+
+/// Selects the applet identified by `aid` on `card`, returning the SELECT response data
+pub fn select(card: &pcsc::Card, aid: &[u8], applet: &str) -> Result<Vec<u8>, PFError> {
+	// Select Applet APDU: 00 A4 04 00 [Len] [AID]
+	let mut apdu = vec![
+		APDU_CLA_ISO,
+		APDU_INS_SELECT,
+		APDU_P1_SELECT_BY_DF_NAME,
+		0x00,
+		aid.len() as u8,
+	];
+	apdu.extend_from_slice(aid);
+
+	let resp = transmit(card, &apdu)?;
+	if !resp.is_success() {
+		log::error!("{} Applet not found on the device!", applet);
+		return Err(PFError::Device(format!(
+			"{} Applet not found on device",
+			applet
+		)));
+	}
+
+	log::info!("Successfully connected to {} Applet", applet);
+	Ok(resp.data)
+}
+
+/// Sends an APDU and collects the full response, following `61 XX` with GET RESPONSE.
+pub fn transmit(card: &pcsc::Card, apdu: &[u8]) -> Result<ApduResponse, PFError> {
+	transmit_with_remaining(card, apdu, APDU_INS_GET_RESPONSE)
+}
+
+/// Sends an APDU and collects the full response, following `61 XX` continuations with
+/// the applet-specific `remaining_ins` instruction.
+pub fn transmit_with_remaining(
+	card: &pcsc::Card,
+	apdu: &[u8],
+	remaining_ins: u8,
+) -> Result<ApduResponse, PFError> {
+	let mut rx_buf = [0; pcsc::MAX_BUFFER_SIZE_EXTENDED];
+	let mut rx = card.transmit(apdu, &mut rx_buf)?.to_vec();
+	let mut data = Vec::new();
+
+	loop {
+		if rx.len() < 2 {
+			return Err(PFError::Device("Truncated APDU response".into()));
+		}
+		let sw = rx.split_off(rx.len() - 2);
+		data.extend_from_slice(&rx);
+
+		if sw[0] != SW1_MORE_DATA {
+			return Ok(ApduResponse {
+				data,
+				sw: u16::from_be_bytes([sw[0], sw[1]]),
+			});
+		}
+
+		let apdu = [APDU_CLA_ISO, remaining_ins, 0x00, 0x00, sw[1]];
+		rx = card.transmit(&apdu, &mut rx_buf)?.to_vec();
+	}
+}
+
+/// Walks a BER-TLV structure and returns the value of the first `wanted` tag, at any depth
+#[cfg(feature = "gui")]
+pub fn find_tlv(mut data: &[u8], wanted: u16) -> Option<&[u8]> {
+	while !data.is_empty() {
+		let first = data[0];
+		let (tag, tag_len) = if first & 0x1F == 0x1F {
+			(u16::from_be_bytes([first, *data.get(1)?]), 2)
+		} else {
+			(first as u16, 1)
+		};
+
+		let (len, len_len) = match *data.get(tag_len)? {
+			l @ 0x00..=0x7F => (l as usize, 1),
+			0x81 => (*data.get(tag_len + 1)? as usize, 2),
+			0x82 => (
+				u16::from_be_bytes([*data.get(tag_len + 1)?, *data.get(tag_len + 2)?]) as usize,
+				3,
+			),
+			_ => return None,
+		};
+
+		let start = tag_len + len_len;
+		let value = data.get(start..start + len)?;
+		if tag == wanted {
+			return Some(value);
+		}
+		if first & 0x20 != 0
+			&& let Some(found) = find_tlv(value, wanted)
+		{
+			return Some(found);
+		}
+		data = &data[start + len..];
+	}
+	None
+}
@@ -0,0 +1,264 @@
+//! Headless frontend for CI pipelines and batch commissioning, run as `picoforge-cli`.
+//!
+//! Each subcommand calls the same device functions as the Tauri commands, without a window to
+//! report phases to. With `--json`, stdout carries exactly one JSON document, the result or
+//! `{ "error": ... }` in the shape the frontend receives, and logs go to stderr.
+//!
+//! The PIN is read from `--pin` or, so it stays out of the process list, `PICOFORGE_PIN`.
+
+use crate::{error::PFError, fido, firmware, inventory, logging, profiles, rescue, types::*};
+use serde::Serialize;
+use std::path::Path;
+use std::process::ExitCode;
+
+const USAGE: &str = "Usage: picoforge-cli [--json] [--device PATH] [--override-quarantine] COMMAND
+
+Commands:
+  info                               Show the device status and configuration
+  list-creds [--pin PIN]             List the resident passkeys
+  delete-cred --id HEX [--pin PIN]   Delete a resident passkey
+  write-config --profile FILE [--pin PIN]
+                                     Write a configuration profile to the device
+  set-pin --new PIN [--current PIN]  Set the FIDO PIN, or change it with the current one
+  reset --yes                        Factory reset the FIDO application
+  flash-firmware --uf2 FILE          Flash a UF2 firmware image
+
+PIN arguments default to the PICOFORGE_PIN environment variable.";
+
+/// Environment variable the PIN is taken from when `--pin` is not given
+const PIN_ENV: &str = "PICOFORGE_PIN";
+
+/// Command line, split into the global options, the subcommand and its options
+struct Args {
+	json: bool,
+	device: Option<String>,
+	override_quarantine: bool,
+	command: String,
+	options: Vec<(String, Option<String>)>,
+}
+
+impl Args {
+	fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, PFError> {
+		let mut parsed = Args {
+			json: false,
+			device: None,
+			override_quarantine: false,
+			command: String::new(),
+			options: Vec::new(),
+		};
+		let mut args = args.into_iter().peekable();
+		while let Some(arg) = args.next() {
+			match arg.as_str() {
+				"--json" => parsed.json = true,
+				"--override-quarantine" => parsed.override_quarantine = true,
+				"--device" => {
+					parsed.device = Some(
+						args.next()
+							.ok_or_else(|| PFError::Io("--device needs a HID path".into()))?,
+					)
+				}
+				"--yes" => parsed.options.push((arg, None)),
+				option if option.starts_with("--") => {
+					let value = args
+						.next_if(|value| !value.starts_with("--"))
+						.ok_or_else(|| PFError::Io(format!("{} needs a value", option)))?;
+					parsed.options.push((arg, Some(value)));
+				}
+				_ if parsed.command.is_empty() => parsed.command = arg,
+				_ => return Err(PFError::Io(format!("Unexpected argument {}", arg))),
+			}
+		}
+		Ok(parsed)
+	}
+
+	fn option(&self, name: &str) -> Option<&str> {
+		self.options
+			.iter()
+			.find(|(option, _)| option == name)
+			.and_then(|(_, value)| value.as_deref())
+	}
+
+	fn required(&self, name: &str) -> Result<&str, PFError> {
+		self.option(name)
+			.ok_or_else(|| PFError::Io(format!("{} is required for {}", name, self.command)))
+	}
+
+	fn flag(&self, name: &str) -> bool {
+		self.options.iter().any(|(option, _)| option == name)
+	}
+
+	fn pin(&self) -> Option<String> {
+		self.option("--pin")
+			.map(str::to_string)
+			.or_else(|| std::env::var(PIN_ENV).ok())
+			.filter(|pin| !pin.is_empty())
+	}
+
+	fn device(&self) -> Option<&str> {
+		self.device.as_deref()
+	}
+}
+
+/// Result of a subcommand, as printed for humans and as JSON
+enum Output {
+	Message(String),
+	Json(serde_json::Value, String),
+}
+
+impl Output {
+	fn new(value: impl Serialize, text: String) -> Result<Self, PFError> {
+		let value = serde_json::to_value(value).map_err(|e| PFError::Io(e.to_string()))?;
+		Ok(Output::Json(value, text))
+	}
+}
+
+fn info(args: &Args) -> Result<Output, PFError> {
+	let status = match args.device() {
+		Some(path) => fido::read_device_details(Some(path))?,
+		None => rescue::read_device_details().or_else(|e| {
+			log::warn!("Rescue method failed: {}. Falling back to FIDO...", e);
+			fido::read_device_details(None)
+		})?,
+	};
+	let text = format!(
+		"Serial:   {}\nFirmware: {}\nVID:PID:  {}:{}\nProduct:  {}\nFlash:    {} of {} KiB used",
+		status.info.serial,
+		status.info.firmware_version,
+		status.config.vid,
+		status.config.pid,
+		status.config.product_name,
+		status.info.flash_used,
+		status.info.flash_total,
+	);
+	Output::new(status, text)
+}
+
+fn list_creds(args: &Args) -> Result<Output, PFError> {
+	inventory::check_quarantine("list-creds", args.device(), Some(args.override_quarantine))?;
+	let mut credentials = fido::get_credentials(args.device(), args.pin().as_deref())?;
+	fido::rp_metadata::enrich(&mut credentials);
+	let text = credentials
+		.iter()
+		.map(|c| format!("{}  {}  {}", c.credential_id, c.rp_id, c.user_name))
+		.collect::<Vec<_>>()
+		.join("\n");
+	Output::new(credentials, text)
+}
+
+fn delete_cred(args: &Args) -> Result<Output, PFError> {
+	let credential_id = args.required("--id")?.to_string();
+	inventory::check_quarantine("delete-cred", args.device(), Some(args.override_quarantine))?;
+	fido::delete_credential(args.device(), args.pin().as_deref(), credential_id)
+		.map(Output::Message)
+}
+
+fn write_config(args: &Args) -> Result<Output, PFError> {
+	let profile = profiles::load(Path::new(args.required("--profile")?))?;
+	inventory::check_quarantine(
+		"write-config",
+		args.device(),
+		Some(args.override_quarantine),
+	)?;
+	log::info!("Writing the configuration profile {}", profile.name);
+	let report = fido::write_config(args.device(), profile.config, args.pin())?;
+	let mut text = report.message.clone();
+	for issue in &report.issues {
+		text.push_str(&format!("\n{}", issue));
+	}
+	Output::new(report, text)
+}
+
+fn set_pin(args: &Args) -> Result<Output, PFError> {
+	let new_pin = args.required("--new")?.to_string();
+	inventory::check_quarantine("set-pin", args.device(), Some(args.override_quarantine))?;
+	let current = args.option("--current").map(str::to_string);
+	fido::change_fido_pin(args.device(), current, new_pin).map(Output::Message)
+}
+
+fn reset(args: &Args) -> Result<Output, PFError> {
+	inventory::check_quarantine("reset", args.device(), Some(args.override_quarantine))?;
+	let serial = rescue::connected_serial();
+	fido::reset_device(args.device(), args.flag("--yes"))?;
+	if let Some(serial) = serial {
+		inventory::log_device_event(&serial, DeviceEventKind::Reset, "Factory reset");
+	}
+	Ok(Output::Message(
+		"FIDO application reset. All credentials and the PIN were deleted.".into(),
+	))
+}
+
+fn flash_firmware(args: &Args) -> Result<Output, PFError> {
+	let uf2_path = args.required("--uf2")?;
+	// Fail early on a bad image rather than after rebooting the device
+	firmware::read_uf2(uf2_path)?;
+	inventory::check_quarantine(
+		"flash-firmware",
+		args.device(),
+		Some(args.override_quarantine),
+	)?;
+	let json = args.json;
+	firmware::flash_fido_device(args.device(), uf2_path, |written, total| {
+		if !json {
+			eprint!(
+				"\rCopying firmware ({} of {} KiB)",
+				written / 1024,
+				total / 1024
+			);
+			if written == total {
+				eprintln!();
+			}
+		}
+	})
+	.map(Output::Message)
+}
+
+fn run_command(args: &Args) -> Result<Output, PFError> {
+	let _log_context = logging::enter(&args.command, args.device());
+	match args.command.as_str() {
+		"info" => info(args),
+		"list-creds" => list_creds(args),
+		"delete-cred" => delete_cred(args),
+		"write-config" => write_config(args),
+		"set-pin" => set_pin(args),
+		"reset" => reset(args),
+		"flash-firmware" => flash_firmware(args),
+		"" => Err(PFError::Io("No command given".into())),
+		command => Err(PFError::Io(format!("Unknown command {}", command))),
+	}
+}
+
+/// Entry point of `picoforge-cli`
+pub fn main() -> ExitCode {
+	let raw: Vec<String> = std::env::args().skip(1).collect();
+	if raw.iter().any(|arg| arg == "--help" || arg == "-h") {
+		println!("{}", USAGE);
+		return ExitCode::SUCCESS;
+	}
+	logging::cli_logger_init();
+
+	let json = raw.iter().any(|arg| arg == "--json");
+	let result = Args::parse(raw).and_then(|args| run_command(&args));
+	match (result, json) {
+		(Ok(Output::Message(message)), true) => {
+			println!("{}", serde_json::json!({ "message": message }));
+			ExitCode::SUCCESS
+		}
+		(Ok(Output::Message(text)), false) | (Ok(Output::Json(_, text)), false) => {
+			println!("{}", text);
+			ExitCode::SUCCESS
+		}
+		(Ok(Output::Json(value, _)), true) => {
+			println!("{}", value);
+			ExitCode::SUCCESS
+		}
+		(Err(e), true) => {
+			println!("{}", serde_json::json!({ "error": e }));
+			ExitCode::FAILURE
+		}
+		(Err(e), false) => {
+			eprintln!("Error: {}", e);
+			eprintln!("Run picoforge-cli --help for usage");
+			ExitCode::FAILURE
+		}
+	}
+}
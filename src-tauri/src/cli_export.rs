@@ -0,0 +1,73 @@
+//! Renders a configuration as invocations of the upstream `pico-fido-tool.py`, so the same
+//! commissioning can be reproduced from a headless script.
+//!
+//! The upstream tool sets one PHY option per invocation. Settings it has no subcommand for are
+//! reported separately instead of being silently dropped.
+
+use crate::{error::PFError, types::*};
+
+const TOOL: &str = "python3 pico-fido-tool.py";
+
+fn enable_disable(enabled: bool) -> &'static str {
+	if enabled { "enable" } else { "disable" }
+}
+
+fn parse_hex_id(value: &str, what: &str) -> Result<u16, PFError> {
+	u16::from_str_radix(value, 16).map_err(|_| PFError::Io(format!("Invalid {}", what)))
+}
+
+pub fn export_pico_fido_tool(config: &AppConfigInput) -> Result<CliExport, PFError> {
+	let mut commands = Vec::new();
+	let mut unsupported = Vec::new();
+
+	match (&config.vid, &config.pid) {
+		(Some(vid), Some(pid)) => {
+			let vid = parse_hex_id(vid, "VID")?;
+			let pid = parse_hex_id(pid, "PID")?;
+			commands.push(format!("{} phy vidpid {:04X}:{:04X}", TOOL, vid, pid));
+		}
+		(None, None) => {}
+		_ => return Err(PFError::Io("VID and PID must be set together".into())),
+	}
+
+	if let Some(gpio) = config.led_gpio {
+		commands.push(format!("{} phy led_gpio {}", TOOL, gpio));
+	}
+	if let Some(brightness) = config.led_brightness {
+		commands.push(format!("{} phy led_brightness {}", TOOL, brightness));
+	}
+	if let Some(dimmable) = config.led_dimmable {
+		commands.push(format!(
+			"{} phy led_dimmable {}",
+			TOOL,
+			enable_disable(dimmable)
+		));
+	}
+
+	if config
+		.product_name
+		.as_ref()
+		.is_some_and(|name| !name.is_empty())
+	{
+		unsupported.push("productName".to_string());
+	}
+	let others = [
+		("touchTimeout", config.touch_timeout.is_some()),
+		("ledDriver", config.led_driver.is_some()),
+		("powerCycleOnReset", config.power_cycle_on_reset.is_some()),
+		("ledSteady", config.led_steady.is_some()),
+		("enableSecp256k1", config.enable_secp256k1.is_some()),
+	];
+	unsupported.extend(
+		others
+			.iter()
+			.filter(|(_, set)| *set)
+			.map(|(name, _)| name.to_string()),
+	);
+
+	Ok(CliExport {
+		script: commands.join("\n"),
+		commands,
+		unsupported,
+	})
+}
@@ -0,0 +1,363 @@
+//! Batch commissioning, writing one configuration profile to every key plugged in.
+//!
+//! While a session runs, each key the hotplug watcher reports is identified, configured with the
+//! profile and read back to verify the configuration stuck. Keys are processed as soon as they
+//! show up, so an operator can plug a whole hub of them at once; the `DeviceManager` keeps to the
+//! concurrency limit of the session policy.
+//!
+//! Every key ends up as a record in the session log, which survives a restart and can be exported
+//! for the paperwork. Progress is broadcast per key, told apart by HID path.
+
+use crate::{
+	csv,
+	error::PFError,
+	fido,
+	inventory::{self, Inventory},
+	manager::DeviceManager,
+	paths, profiles, storage,
+	types::*,
+};
+use rand::Rng;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter, Manager};
+
+/// Broadcast to every window as each key moves through commissioning
+pub const COMMISSIONING_PROGRESS_EVENT: &str = "commissioning-progress";
+
+const SESSION_FILE: &str = "commissioning.json";
+
+/// What the running session writes. Only kept in memory, so the PIN is gone after a restart.
+#[derive(Clone)]
+struct ActiveSession {
+	id: String,
+	profile_name: String,
+	/// Window the device operations are run for
+	owner: String,
+	config: AppConfigInput,
+	pin: String,
+}
+
+/// Running session and the log of the latest one
+#[derive(Default)]
+pub struct Commissioning {
+	active: Mutex<Option<ActiveSession>>,
+	session: Mutex<Option<CommissioningSession>>,
+}
+
+fn session_path() -> PathBuf {
+	paths::data_dir().join(SESSION_FILE)
+}
+
+impl Commissioning {
+	/// Restores the log of the latest session. A session that was running is marked as finished,
+	/// keys plugged in after a restart are not commissioned until a new one is started.
+	pub fn load() -> Self {
+		let session = match storage::read(&session_path()) {
+			Ok(data) => serde_json::from_slice::<CommissioningSession>(&data)
+				.map_err(|e| log::error!("Commissioning log is corrupted, ignoring it: {}", e))
+				.ok(),
+			Err(_) => None,
+		}
+		.map(|mut session| {
+			session.finished_at.get_or_insert_with(inventory::now);
+			session
+		});
+
+		Self {
+			active: Mutex::new(None),
+			session: Mutex::new(session),
+		}
+	}
+
+	fn persist(session: &CommissioningSession) {
+		let result = serde_json::to_vec_pretty(session)
+			.map_err(|e| e.to_string())
+			.and_then(|data| storage::write(&session_path(), &data).map_err(|e| e.to_string()));
+		if let Err(e) = result {
+			log::error!("Failed to persist the commissioning log: {}", e);
+		}
+	}
+
+	/// Starts commissioning every key plugged in from now on with `profile`, on behalf of the
+	/// window `owner`. Replaces the log of the previous session.
+	pub fn start(
+		&self,
+		owner: &str,
+		profile: ConfigProfile,
+		pin: String,
+	) -> Result<CommissioningSession, PFError> {
+		let mut active = self.active.lock().unwrap_or_else(|p| p.into_inner());
+		if let Some(running) = &*active {
+			return Err(PFError::Io(format!(
+				"Already commissioning keys with profile {}",
+				running.profile_name
+			)));
+		}
+		if pin.is_empty() {
+			return Err(PFError::Io(
+				"Commissioning needs the PIN of the keys".into(),
+			));
+		}
+
+		let id_bytes: [u8; 8] = rand::rng().random();
+		let session = CommissioningSession {
+			id: hex::encode(id_bytes),
+			profile_name: profile.name.clone(),
+			profile_path: profile.path,
+			profile_hash: Some(profiles::config_hash(&profile.config)?),
+			started_at: inventory::now(),
+			finished_at: None,
+			records: Vec::new(),
+		};
+		log::info!(
+			"Commissioning session {} started with profile {}",
+			session.id,
+			profile.name
+		);
+
+		*active = Some(ActiveSession {
+			id: session.id.clone(),
+			profile_name: profile.name,
+			owner: owner.to_string(),
+			config: profile.config,
+			pin,
+		});
+		Self::persist(&session);
+		if let Ok(mut current) = self.session.lock() {
+			*current = Some(session.clone());
+		}
+		Ok(session)
+	}
+
+	/// Stops commissioning newly plugged keys. Keys already being commissioned are finished.
+	pub fn stop(&self) -> Option<CommissioningSession> {
+		let stopped = self
+			.active
+			.lock()
+			.unwrap_or_else(|p| p.into_inner())
+			.take()?;
+		log::info!("Commissioning session {} stopped", stopped.id);
+
+		let mut current = self.session.lock().ok()?;
+		let session = current.as_mut().filter(|s| s.id == stopped.id)?;
+		session.finished_at = Some(inventory::now());
+		Self::persist(session);
+		Some(session.clone())
+	}
+
+	/// The running session, or the latest one
+	pub fn session(&self) -> Option<CommissioningSession> {
+		self.session.lock().ok()?.clone()
+	}
+
+	/// Adds `record` to the log of the session `id`, replacing an earlier attempt on the same key
+	fn record(&self, id: &str, record: CommissioningRecord) {
+		let Ok(mut current) = self.session.lock() else {
+			return;
+		};
+		let Some(session) = current.as_mut().filter(|s| s.id == id) else {
+			return;
+		};
+		if record.serial.is_some() {
+			session.records.retain(|r| r.serial != record.serial);
+		}
+		session.records.push(record);
+		Self::persist(session);
+	}
+
+	/// Whether the key with `serial` was already commissioned by the session `id`, e.g. before it
+	/// re-enumerated with the VID/PID of the profile
+	fn is_done(&self, id: &str, serial: &str) -> bool {
+		self.session.lock().is_ok_and(|current| {
+			current.as_ref().is_some_and(|s| {
+				s.id == id
+					&& s.records
+						.iter()
+						.any(|r| r.success && r.serial.as_deref() == Some(serial))
+			})
+		})
+	}
+}
+
+/// Identifies, configures and verifies the key at `path`. `None` if the session already
+/// commissioned it.
+fn commission(
+	commissioning: &Commissioning,
+	session: &ActiveSession,
+	path: &str,
+	record: &mut CommissioningRecord,
+	progress: &dyn Fn(&CommissioningRecord, CommissioningStage, String),
+) -> Result<Option<String>, PFError> {
+	progress(
+		record,
+		CommissioningStage::Identifying,
+		"Identifying the key".into(),
+	);
+	let status = fido::read_device_details(Some(path))?;
+	let serial = status.info.serial;
+	record.serial = Some(serial.clone());
+	if commissioning.is_done(&session.id, &serial) {
+		return Ok(None);
+	}
+	let info = fido::get_fido_info(Some(path))?;
+	record.aaguid = Some(info.aaguid);
+	record.min_pin_length = Some(info.min_pin_length);
+	inventory::check_quarantine("commissioning", Some(path), Some(false))?;
+
+	progress(
+		record,
+		CommissioningStage::Writing,
+		format!("Writing profile {}", session.profile_name),
+	);
+	let written = fido::write_config(
+		Some(path),
+		session.config.clone(),
+		Some(session.pin.clone()),
+	)?;
+
+	progress(
+		record,
+		CommissioningStage::Verifying,
+		"Checking the configuration read back".into(),
+	);
+	record.mismatches = written.issues;
+	let status = fido::read_device_details(Some(path))?;
+	record.firmware_version = Some(status.info.firmware_version.clone());
+	// Like `read_device_details`, the Rescue Applet can only be asked with one key connected
+	if fido::list_devices().is_ok_and(|devices| devices.len() == 1) {
+		record.secure_boot = Some(status.secure_boot);
+	}
+
+	let summary = format!("Commissioned with profile {}", session.profile_name);
+	let result = Inventory::update(|inventory| {
+		inventory.record_config(&serial, &status.config)?;
+		inventory.record_event(&serial, DeviceEventKind::ConfigChange, &summary);
+		Ok(())
+	});
+	if let Err(e) = result {
+		log::warn!("Failed to record the commissioning of {}: {}", serial, e);
+	}
+
+	if !record.mismatches.is_empty() {
+		return Err(PFError::Device(format!(
+			"Configuration did not verify: {}",
+			record.mismatches.join("; ")
+		)));
+	}
+	Ok(Some(summary))
+}
+
+/// Commissions the key at `device` if a session is running. Called by the hotplug watcher for
+/// every key plugged in.
+pub fn device_connected(app: &AppHandle, device: &FidoHidDevice) {
+	let Some(commissioning) = app.try_state::<Commissioning>() else {
+		return;
+	};
+	let Some(session) = commissioning
+		.active
+		.lock()
+		.unwrap_or_else(|p| p.into_inner())
+		.clone()
+	else {
+		return;
+	};
+
+	let app = app.clone();
+	let path = device.path.clone();
+	let port_path = device.port_path.clone();
+	tauri::async_runtime::spawn_blocking(move || {
+		let commissioning = app.state::<Commissioning>();
+		let progress =
+			|record: &CommissioningRecord, stage: CommissioningStage, message: String| {
+				let progress = CommissioningProgress {
+					session_id: session.id.clone(),
+					device_path: path.clone(),
+					port_path: record.port_path.clone(),
+					serial: record.serial.clone(),
+					stage,
+					message,
+				};
+				if let Err(e) = app.emit(COMMISSIONING_PROGRESS_EVENT, &progress) {
+					log::warn!("Failed to emit commissioning progress: {}", e);
+				}
+			};
+
+		let mut record = CommissioningRecord {
+			serial: None,
+			aaguid: None,
+			port_path,
+			success: false,
+			message: String::new(),
+			mismatches: Vec::new(),
+			firmware_version: None,
+			min_pin_length: None,
+			secure_boot: None,
+			commissioned_at: 0,
+		};
+		let result = app
+			.state::<DeviceManager>()
+			.run(&app, &session.owner, "commissioning", Some(&path), || {
+				commission(&commissioning, &session, &path, &mut record, &progress)
+			})
+			.and_then(|result| result);
+
+		record.commissioned_at = inventory::now();
+		match result {
+			Ok(None) => {
+				log::debug!("{} was already commissioned", path);
+				progress(
+					&record,
+					CommissioningStage::Done,
+					"Already commissioned in this session".into(),
+				);
+				return;
+			}
+			Ok(Some(summary)) => {
+				record.success = true;
+				record.message = summary;
+				progress(&record, CommissioningStage::Done, record.message.clone());
+			}
+			Err(e) => {
+				log::error!("Commissioning of {} failed: {}", path, e);
+				record.message = e.to_string();
+				progress(&record, CommissioningStage::Failed, record.message.clone());
+			}
+		}
+		commissioning.record(&session.id, record);
+	});
+}
+
+fn to_csv(session: &CommissioningSession) -> String {
+	let mut csv =
+		String::from("serial,aaguid,port_path,result,message,mismatches,commissioned_at\n");
+	for record in &session.records {
+		let fields = [
+			record.serial.clone().unwrap_or_default(),
+			record.aaguid.clone().unwrap_or_default(),
+			record.port_path.clone().unwrap_or_default(),
+			if record.success { "ok" } else { "failed" }.to_string(),
+			record.message.clone(),
+			record.mismatches.join("; "),
+			record.commissioned_at.to_string(),
+		];
+		let line: Vec<String> = fields.iter().map(|f| csv::field(f)).collect();
+		csv.push_str(&line.join(","));
+		csv.push('\n');
+	}
+	csv
+}
+
+/// Writes the log of `session` to `path`, as CSV if it ends with `.csv`, else as JSON
+pub fn export(session: &CommissioningSession, path: &Path) -> Result<(), PFError> {
+	let is_csv = path
+		.extension()
+		.is_some_and(|ext| ext.eq_ignore_ascii_case("csv"));
+	let data = if is_csv {
+		to_csv(session).into_bytes()
+	} else {
+		serde_json::to_vec_pretty(session).map_err(|e| PFError::Io(e.to_string()))?
+	};
+	storage::write(path, &data)
+		.map_err(|e| PFError::Io(format!("Failed to write the commissioning log: {}", e)))
+}
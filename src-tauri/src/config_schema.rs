@@ -0,0 +1,117 @@
+//! Schema versioning for device configurations (`AppConfig`, `AppConfigInput`) saved on the host.
+//!
+//! Saved configurations are wrapped in a `VersionedConfig` envelope. When the layout of the
+//! configuration changes (for example because the firmware TLV map gains or repurposes a tag),
+//! bump `CONFIG_SCHEMA_VERSION` and append a migration, so configurations saved by older
+//! PicoForge versions are upgraded on load instead of being misread.
+
+#[cfg(feature = "gui")]
+use crate::error::PFError;
+#[cfg(feature = "gui")]
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Schema version written by this PicoForge version
+#[cfg(feature = "gui")]
+pub const CONFIG_SCHEMA_VERSION: u32 = 1;
+
+/// Upgrades a configuration payload by one schema version
+#[cfg(feature = "gui")]
+type Migration = fn(Value) -> Result<Value, PFError>;
+
+/// `MIGRATIONS[n]` upgrades a payload from version `n` to version `n + 1`
+#[cfg(feature = "gui")]
+const MIGRATIONS: &[Migration] = &[migrate_v0_to_v1];
+
+/// Version 0 are the bare configuration objects saved before versioning was introduced.
+/// Their layout is the version 1 layout, only the envelope is new.
+#[cfg(feature = "gui")]
+fn migrate_v0_to_v1(config: Value) -> Result<Value, PFError> {
+	if !config.is_object() {
+		return Err(PFError::Io(
+			"Saved configuration is not a JSON object".into(),
+		));
+	}
+	Ok(config)
+}
+
+/// Any configuration found on disk, with or without envelope
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredConfig {
+	#[serde(rename_all = "camelCase")]
+	Versioned {
+		schema_version: u32,
+		config: Value,
+	},
+	Legacy(Value),
+}
+
+impl From<StoredConfig> for VersionedConfig {
+	fn from(stored: StoredConfig) -> Self {
+		match stored {
+			StoredConfig::Versioned {
+				schema_version,
+				config,
+			} => Self {
+				schema_version,
+				config,
+			},
+			StoredConfig::Legacy(config) => Self {
+				schema_version: 0,
+				config,
+			},
+		}
+	}
+}
+
+/// Saved configuration with the schema version it was written with. Deserializing also
+/// accepts unversioned legacy objects, which become version 0.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", from = "StoredConfig")]
+pub struct VersionedConfig {
+	pub schema_version: u32,
+	pub config: Value,
+}
+
+#[cfg(feature = "gui")]
+impl VersionedConfig {
+	/// Wraps `config` with the current schema version
+	pub fn new<T: Serialize>(config: &T) -> Result<Self, PFError> {
+		Ok(Self {
+			schema_version: CONFIG_SCHEMA_VERSION,
+			config: serde_json::to_value(config).map_err(|e| PFError::Io(e.to_string()))?,
+		})
+	}
+
+	/// Runs the pending migrations and deserializes the configuration.
+	///
+	/// Configurations saved by a newer PicoForge version are rejected rather than guessed at.
+	pub fn into_config<T: DeserializeOwned>(self) -> Result<T, PFError> {
+		if self.schema_version > CONFIG_SCHEMA_VERSION {
+			return Err(PFError::Io(format!(
+				"Configuration was saved with schema version {}, this version of PicoForge only \
+				 understands up to version {}",
+				self.schema_version, CONFIG_SCHEMA_VERSION
+			)));
+		}
+
+		let mut config = self.config;
+		for (version, migrate) in MIGRATIONS
+			.iter()
+			.enumerate()
+			.skip(self.schema_version as usize)
+		{
+			log::info!(
+				"Migrating saved configuration from schema version {} to {}",
+				version,
+				version + 1
+			);
+			config = migrate(config)?;
+		}
+
+		serde_json::from_value(config)
+			.map_err(|e| PFError::Io(format!("Saved configuration is invalid: {}", e)))
+	}
+}
@@ -0,0 +1,174 @@
+//! Export of the resident credentials of a key, for auditing what is on it before a reset.
+//!
+//! The export lists the relying party, user and credential ID of each passkey, along with the
+//! credProtect level and key algorithm the authenticator reports. The key keeps no creation
+//! time, so none is exported. Nothing in it allows signing in, the private keys never leave the
+//! authenticator.
+//!
+//! A previous export, JSON or CSV, can be compared against the passkeys on the key now.
+
+use crate::{csv, error::PFError, inventory, storage, types::*};
+use std::path::Path;
+
+const EXPORT_FORMAT: &str = "picoforge-credential-export";
+const EXPORT_VERSION: u32 = 1;
+
+const CSV_HEADER: &str =
+	"rp_id,rp_name,user_id,user_name,user_display_name,credential_id,cred_protect,algorithm";
+
+fn to_csv(credentials: &[StoredCredential]) -> String {
+	let mut csv = format!("{}\n", CSV_HEADER);
+	for credential in credentials {
+		let fields = [
+			credential.rp_id.clone(),
+			credential.rp_name.clone(),
+			credential.user_id.clone(),
+			credential.user_name.clone(),
+			credential.user_display_name.clone(),
+			credential.credential_id.clone(),
+			credential
+				.cred_protect
+				.map(|level| level.to_string())
+				.unwrap_or_default(),
+			credential
+				.algorithm
+				.map(|alg| alg.to_string())
+				.unwrap_or_default(),
+		];
+		let line: Vec<String> = fields.iter().map(|f| csv::field(f)).collect();
+		csv.push_str(&line.join(","));
+		csv.push('\n');
+	}
+	csv
+}
+
+fn from_csv(text: &str) -> Result<Vec<StoredCredential>, PFError> {
+	let mut records = csv::parse(text).into_iter();
+	let header = records.next().unwrap_or_default();
+	if header.join(",") != CSV_HEADER {
+		return Err(PFError::Io("Not a PicoForge credential export".into()));
+	}
+	records
+		.filter(|record| record.iter().any(|field| !field.is_empty()))
+		.map(|record| {
+			let [
+				rp_id,
+				rp_name,
+				user_id,
+				user_name,
+				user_display_name,
+				credential_id,
+				cred_protect,
+				algorithm,
+			] = <[String; 8]>::try_from(record)
+				.map_err(|_| PFError::Io("The credential export is corrupted".into()))?;
+			Ok(StoredCredential {
+				rp_id,
+				rp_name,
+				user_name,
+				user_display_name,
+				user_id,
+				credential_id,
+				rp_display_name: None,
+				account_url: None,
+				cred_protect: cred_protect.parse().ok(),
+				algorithm: algorithm.parse().ok(),
+			})
+		})
+		.collect()
+}
+
+/// Writes `credentials` of the key `serial` to `path`
+pub fn export(
+	credentials: Vec<StoredCredential>,
+	serial: Option<String>,
+	path: &Path,
+	format: CredentialExportFormat,
+) -> Result<CredentialExportReport, PFError> {
+	let count = credentials.len();
+	let data = match format {
+		CredentialExportFormat::Csv => to_csv(&credentials).into_bytes(),
+		CredentialExportFormat::Json => {
+			let export = CredentialExport {
+				format: EXPORT_FORMAT.into(),
+				version: EXPORT_VERSION,
+				exported_at: inventory::now(),
+				serial: serial.clone(),
+				credentials,
+			};
+			serde_json::to_vec_pretty(&export).map_err(|e| PFError::Io(e.to_string()))?
+		}
+	};
+	storage::write(path, &data)
+		.map_err(|e| PFError::Io(format!("Failed to write the credential export: {}", e)))?;
+
+	log::info!("Exported {} passkeys to {:?}", count, path);
+	Ok(CredentialExportReport {
+		path: path.to_string_lossy().into_owned(),
+		count,
+		serial,
+	})
+}
+
+/// Reads the export at `path`, JSON or CSV. CSV exports carry no export time.
+fn load(path: &Path) -> Result<(Option<u64>, Vec<StoredCredential>), PFError> {
+	let data = storage::read(path)
+		.map_err(|e| PFError::Io(format!("Failed to read the credential export: {}", e)))?;
+	if let Ok(export) = serde_json::from_slice::<CredentialExport>(&data) {
+		if export.format != EXPORT_FORMAT {
+			return Err(PFError::Io("Not a PicoForge credential export".into()));
+		}
+		if export.version != EXPORT_VERSION {
+			return Err(PFError::Io(format!(
+				"Credential export version {} is not supported",
+				export.version
+			)));
+		}
+		return Ok((Some(export.exported_at), export.credentials));
+	}
+	let text = String::from_utf8(data)
+		.map_err(|_| PFError::Io("Not a PicoForge credential export".into()))?;
+	Ok((None, from_csv(&text)?))
+}
+
+/// Compares the export at `path` against `current`, the passkeys on the key now
+pub fn compare(
+	path: &Path,
+	current: Vec<StoredCredential>,
+) -> Result<CredentialComparison, PFError> {
+	let (exported_at, exported) = load(path)?;
+	let find = |credentials: &[StoredCredential], id: &str| {
+		credentials
+			.iter()
+			.position(|c| c.credential_id.eq_ignore_ascii_case(id))
+	};
+
+	let mut comparison = CredentialComparison {
+		exported_at,
+		added: Vec::new(),
+		removed: Vec::new(),
+		changed: Vec::new(),
+		unchanged: 0,
+	};
+	for credential in &current {
+		if find(&exported, &credential.credential_id).is_none() {
+			comparison.added.push(credential.clone());
+		}
+	}
+	for credential in exported {
+		match find(&current, &credential.credential_id) {
+			None => comparison.removed.push(credential),
+			Some(i)
+				if current[i].user_name != credential.user_name
+					|| current[i].user_display_name != credential.user_display_name =>
+			{
+				comparison.changed.push(CredentialChange {
+					exported: credential,
+					current: current[i].clone(),
+				})
+			}
+			Some(_) => comparison.unchanged += 1,
+		}
+	}
+	Ok(comparison)
+}
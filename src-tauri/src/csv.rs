@@ -0,0 +1,114 @@
+//! CSV fields for the files PicoForge exports, and the reader for the ones it imports again.
+//!
+//! Exports are opened in spreadsheets, which run a cell starting with `=`, `+`, `-` or `@` as a
+//! formula. A credential name or device message chosen by someone else must not become one, so
+//! such cells are written with a leading `'`, which spreadsheets hide. `parse` strips it again, so
+//! every value reads back as it was written.
+
+/// Whether `value` needs a leading `'` to not be taken for a formula. Values that already start
+/// with quotes get one more, so `parse` can tell the added quote from one of the value.
+fn formula_like(value: &str) -> bool {
+	value
+		.trim_start_matches('\'')
+		.starts_with(['=', '+', '-', '@'])
+}
+
+/// Quotes a CSV field if needed, and keeps spreadsheets from running it as a formula
+pub fn field(value: &str) -> String {
+	let value = if formula_like(value) {
+		format!("'{}", value)
+	} else {
+		value.to_string()
+	};
+	if value.contains([',', '"', '\n', '\r']) {
+		format!("\"{}\"", value.replace('"', "\"\""))
+	} else {
+		value
+	}
+}
+
+/// Removes the quote `field` added in front of a formula-like value
+fn unescape(value: String) -> String {
+	match value.strip_prefix('\'') {
+		Some(rest) if formula_like(rest) => rest.to_string(),
+		_ => value,
+	}
+}
+
+/// Splits the CSV records of `text` into fields, honouring quoted fields
+pub fn parse(text: &str) -> Vec<Vec<String>> {
+	let mut records = Vec::new();
+	let mut record = Vec::new();
+	let mut value = String::new();
+	let mut quoted = false;
+	let mut chars = text.chars().peekable();
+	while let Some(c) = chars.next() {
+		match c {
+			'"' if quoted && chars.peek() == Some(&'"') => {
+				value.push('"');
+				chars.next();
+			}
+			'"' => quoted = !quoted,
+			',' if !quoted => record.push(unescape(std::mem::take(&mut value))),
+			'\r' if !quoted => {}
+			'\n' if !quoted => {
+				record.push(unescape(std::mem::take(&mut value)));
+				records.push(std::mem::take(&mut record));
+			}
+			c => value.push(c),
+		}
+	}
+	if !value.is_empty() || !record.is_empty() {
+		record.push(unescape(value));
+		records.push(record);
+	}
+	records
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn round_trip(values: &[&str]) -> Vec<String> {
+		let line: Vec<String> = values.iter().map(|v| field(v)).collect();
+		let mut records = parse(&format!("{}\n", line.join(",")));
+		assert_eq!(records.len(), 1);
+		records.remove(0)
+	}
+
+	#[test]
+	fn quoted_commas_quotes_and_newlines_round_trip() {
+		let values = [
+			"example.com",
+			"Doe, Jane",
+			"say \"hi\"",
+			"first line\nsecond line",
+			"crlf\r\nline",
+			"",
+		];
+		assert_eq!(round_trip(&values), values);
+	}
+
+	#[test]
+	fn formulas_are_neutralised() {
+		assert_eq!(field("=HYPERLINK(\"x\")"), "\"'=HYPERLINK(\"\"x\"\")\"");
+		assert_eq!(field("+1"), "'+1");
+		assert_eq!(field("-1"), "'-1");
+		assert_eq!(field("@SUM(A1)"), "'@SUM(A1)");
+		assert_eq!(field("a=b"), "a=b");
+	}
+
+	#[test]
+	fn formula_like_values_round_trip() {
+		let values = [
+			"=1+1", "+1", "-1", "@me", "'=1", "''@", "'plain", "''plain", "'",
+		];
+		assert_eq!(round_trip(&values), values);
+	}
+
+	#[test]
+	fn several_records_are_split() {
+		let records = parse("a,b\r\n\"c\nd\",e\nf");
+		assert_eq!(records, [vec!["a", "b"], vec!["c\nd", "e"], vec!["f"]]);
+	}
+}
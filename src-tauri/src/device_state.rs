@@ -0,0 +1,59 @@
+//! Single authoritative state of the connected device.
+//!
+//! Views theme themselves by this state instead of each re-deriving it from the status, PIN
+//! retries and firmware version on their own.
+
+use crate::{
+	error::PFError,
+	fido, schema, simulator,
+	types::{DeviceState, FidoDeviceInfo},
+};
+
+/// Whether `installed` is older than the newest firmware this build knows of
+fn update_available(installed: &str) -> bool {
+	schema::version_key(installed) < schema::version_key(schema::latest_bundled_version())
+}
+
+/// Queries the connected device and derives its state
+pub fn current() -> DeviceState {
+	if ctap_hid_fido2::get_fidokey_devices().is_empty() {
+		return DeviceState::NotConnected;
+	}
+	derive(fido::get_fido_info(None), || fido::get_pin_retries(None))
+}
+
+/// State of the training device
+pub fn simulated() -> DeviceState {
+	derive(simulator::get_fido_info(), simulator::get_pin_retries)
+}
+
+fn derive(
+	info: Result<FidoDeviceInfo, PFError>,
+	pin_retries: impl FnOnce() -> Result<i32, PFError>,
+) -> DeviceState {
+	let info = match info {
+		Ok(info) => info,
+		Err(e) => {
+			log::warn!("Device state: could not read device info: {}", e);
+			return DeviceState::Error;
+		}
+	};
+	if info.options.get("clientPin") != Some(&true) {
+		return DeviceState::NeedsSetup;
+	}
+
+	match pin_retries() {
+		Ok(0) => return DeviceState::Locked,
+		Ok(_) => {}
+		Err(e) => {
+			log::warn!("Device state: could not read PIN retries: {}", e);
+			return DeviceState::Error;
+		}
+	}
+
+	if update_available(&info.firmware_version) {
+		DeviceState::UpdateAvailable
+	} else {
+		DeviceState::Healthy
+	}
+}
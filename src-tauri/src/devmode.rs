@@ -0,0 +1,39 @@
+//! Protected developer mode.
+//!
+//! Raw consoles send arbitrary commands to the device. They stay locked until the user touches
+//! the connected key, so someone controlling the UI remotely cannot use them silently. The
+//! unlock only lasts for the current session.
+
+use crate::{error::PFError, fido};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static UNLOCKED: AtomicBool = AtomicBool::new(false);
+
+/// Unlocks developer mode after the user confirmed presence on the key
+pub fn unlock() -> Result<(), PFError> {
+	fido::confirm_presence()?;
+	log::info!("Developer mode unlocked");
+	UNLOCKED.store(true, Ordering::SeqCst);
+	Ok(())
+}
+
+pub fn lock() {
+	if UNLOCKED.swap(false, Ordering::SeqCst) {
+		log::info!("Developer mode locked");
+	}
+}
+
+pub fn is_unlocked() -> bool {
+	UNLOCKED.load(Ordering::SeqCst)
+}
+
+/// Guard for commands only available in developer mode
+pub fn ensure_unlocked() -> Result<(), PFError> {
+	if is_unlocked() {
+		Ok(())
+	} else {
+		Err(PFError::Device(
+			"Developer mode is locked. Unlock it by touching the key.".into(),
+		))
+	}
+}
@@ -1,4 +1,7 @@
 /// Custom error types for Pico Forge application.
+///
+/// Serialized as `{ "type": ..., "message": ... }`, plus the fields of the variant, so the frontend
+/// can branch on the kind of error instead of matching messages.
 #[derive(Debug, thiserror::Error)]
 pub enum PFError {
 	#[error("No device found")]
@@ -9,33 +12,112 @@ pub enum PFError {
 	Io(String),
 	#[error("Device Error: {0}")]
 	Device(String),
+	#[error("The key only accepts a reset within 10 seconds of being plugged in")]
+	ResetNotAllowed,
+	#[error("Wrong PIN{}", .retries_left.map(|n| format!(", {} attempt(s) left", n)).unwrap_or_default())]
+	PinInvalid { retries_left: Option<u32> },
+	#[error("The PIN is blocked, only a reset of the FIDO application helps")]
+	PinBlocked,
+	#[error("Enter the PIN, the session no longer holds a PIN token for this key")]
+	PinRequired,
+	#[error("{0}")]
+	PinPolicyViolation(crate::types::PinViolation),
+	#[error("The key is busy with {operation}")]
+	#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+	DeviceBusy { operation: String },
+	#[error("The key was not touched in time")]
+	UserPresenceTimeout,
+	/// A touch timed out with several keys connected, most likely another key was touched. The
+	/// key the operation was bound to blinks.
+	#[error("The key was not touched in time. Touch the blinking key: {product_name}{}", .port_path.as_ref().map(|p| format!(" at USB port {}", p)).unwrap_or_default())]
+	WrongDevice {
+		product_name: String,
+		port_path: Option<String>,
+	},
+	#[error("The operation was cancelled")]
+	Cancelled,
+	#[error("Invalid CBOR: {0}")]
+	CborDecode(String),
+	#[error("The firmware does not support {0}")]
+	VendorUnsupported(String),
+	#[error("Transport Error: {0}")]
+	TransportIo(String),
+	/// A destructive command was invoked from a window whose view may not run it
+	#[error("{command} cannot be run from {}", .context.map(|c| format!("the {:?} view", c)).unwrap_or_else(|| "this window".into()))]
+	#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+	CommandNotPermitted {
+		command: String,
+		context: Option<crate::types::UiContext>,
+	},
 }
 
-// Allow error to be serialized to string for Tauri
+impl PFError {
+	/// Name of the variant, the `type` field of the serialized error
+	pub fn kind(&self) -> &'static str {
+		match self {
+			PFError::NoDevice => "NoDevice",
+			PFError::Pcsc(_) => "Pcsc",
+			PFError::Io(_) => "Io",
+			PFError::Device(_) => "Device",
+			PFError::ResetNotAllowed => "ResetNotAllowed",
+			PFError::PinInvalid { .. } => "PinInvalid",
+			PFError::PinBlocked => "PinBlocked",
+			PFError::PinRequired => "PinRequired",
+			PFError::PinPolicyViolation(_) => "PinPolicyViolation",
+			PFError::DeviceBusy { .. } => "DeviceBusy",
+			PFError::UserPresenceTimeout => "UserPresenceTimeout",
+			PFError::WrongDevice { .. } => "WrongDevice",
+			PFError::Cancelled => "Cancelled",
+			PFError::CborDecode(_) => "CborDecode",
+			PFError::VendorUnsupported(_) => "VendorUnsupported",
+			PFError::TransportIo(_) => "TransportIo",
+			PFError::CommandNotPermitted { .. } => "CommandNotPermitted",
+		}
+	}
+}
+
+// Allow error to be serialized for Tauri
 impl serde::Serialize for PFError {
 	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
 	where
 		S: serde::Serializer,
 	{
-		use serde::ser::SerializeStruct;
-		let mut state = serializer.serialize_struct("PFError", 2)?;
+		use serde::ser::SerializeMap;
+		let mut state = serializer.serialize_map(None)?;
+		state.serialize_entry("type", self.kind())?;
 		match self {
-			PFError::NoDevice => {
-				state.serialize_field("type", "NoDevice")?;
-				state.serialize_field("message", "No device found")?;
+			// Plain messages, without the prefix of the Display output
+			PFError::Io(msg)
+			| PFError::Device(msg)
+			| PFError::CborDecode(msg)
+			| PFError::TransportIo(msg) => state.serialize_entry("message", msg)?,
+			PFError::Pcsc(err) => state.serialize_entry("message", &err.to_string())?,
+			PFError::PinInvalid { retries_left } => {
+				state.serialize_entry("message", &self.to_string())?;
+				state.serialize_entry("retriesLeft", retries_left)?;
+			}
+			PFError::WrongDevice {
+				product_name,
+				port_path,
+			} => {
+				state.serialize_entry("message", &self.to_string())?;
+				state.serialize_entry("productName", product_name)?;
+				state.serialize_entry("portPath", port_path)?;
 			}
-			PFError::Pcsc(err) => {
-				state.serialize_field("type", "Pcsc")?;
-				state.serialize_field("message", &err.to_string())?;
+			PFError::PinPolicyViolation(violation) => {
+				state.serialize_entry("message", &self.to_string())?;
+				state.serialize_entry("violation", violation)?;
 			}
-			PFError::Io(msg) => {
-				state.serialize_field("type", "Io")?;
-				state.serialize_field("message", msg)?;
+			PFError::DeviceBusy { operation } => {
+				state.serialize_entry("message", &self.to_string())?;
+				state.serialize_entry("operation", operation)?;
 			}
-			PFError::Device(msg) => {
-				state.serialize_field("type", "Device")?;
-				state.serialize_field("message", msg)?;
+			PFError::CommandNotPermitted { command, context } => {
+				state.serialize_entry("message", &self.to_string())?;
+				state.serialize_entry("command", command)?;
+				state.serialize_entry("context", context)?;
 			}
+			_ => state.serialize_entry("message", &self.to_string())?,
 		}
 		state.end()
 	}
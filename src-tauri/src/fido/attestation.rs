@@ -0,0 +1,225 @@
+//! Attestation check with a throwaway, non-resident credential.
+//!
+//! pico-fido generates its attestation certificate on the device, so there is no vendor root to
+//! anchor the chain to. The check therefore covers what can be verified locally: the attestation
+//! signature, the signatures and validity of the certificates in `x5c`, and that the AAGUID in
+//! the certificate and in the authenticator data match the one the device reports.
+//!
+//! `attestation_info` lists the certificates of the chain as well, so users can compare the
+//! issuer with the attestation CA they expect.
+
+use super::{get_fido_info, open_device};
+use crate::{
+	error::PFError,
+	operation::report_phase,
+	types::{AttestationCertificate, AttestationCheck, AttestationInfo, PhaseVerb, UserAction},
+};
+use ctap_hid_fido2::{
+	Cfg, FidoKeyHid, FidoKeyHidFactory,
+	fidokey::make_credential::{Attestation, MakeCredentialArgsBuilder},
+	verifier,
+};
+use rand::Rng;
+use x509_parser::prelude::*;
+
+/// Relying party of the throwaway credential, never used for anything else
+const ATTESTATION_RP_ID: &str = "attestation.picoforge.local";
+
+/// id-fido-gen-ce-aaguid, carries the AAGUID in attestation certificates
+const OID_FIDO_GEN_CE_AAGUID: &str = "1.3.6.1.4.1.45724.1.1.4";
+
+/// AAGUID from the certificate extension, an OCTET STRING of 16 bytes
+fn certificate_aaguid(cert: &X509Certificate) -> Option<Vec<u8>> {
+	let ext = cert
+		.extensions()
+		.iter()
+		.find(|ext| ext.oid.to_id_string() == OID_FIDO_GEN_CE_AAGUID)?;
+	match ext.value {
+		[0x04, 0x10, aaguid @ ..] if aaguid.len() == 16 => Some(aaguid.to_vec()),
+		_ => None,
+	}
+}
+
+/// Verifies the certificates of `x5c`, leaf first, each signed by the next one. The last
+/// certificate is checked against itself if it is self-signed.
+fn check_chain(x5c: &[Vec<u8>], aaguid: &[u8], issues: &mut Vec<String>) -> bool {
+	let mut certs = Vec::new();
+	for der in x5c {
+		match X509Certificate::from_der(der) {
+			Ok((_, cert)) => certs.push(cert),
+			Err(e) => {
+				issues.push(format!("Attestation certificate cannot be parsed: {}", e));
+				return false;
+			}
+		}
+	}
+	let Some(leaf) = certs.first() else {
+		issues.push("Attestation statement contains no certificate".into());
+		return false;
+	};
+
+	let mut valid = true;
+	for (index, cert) in certs.iter().enumerate() {
+		if !cert.validity().is_valid() {
+			issues.push(format!(
+				"Certificate {} ({}) is not valid at this time",
+				index,
+				cert.subject()
+			));
+			valid = false;
+		}
+		let issuer = match certs.get(index + 1) {
+			Some(issuer) => Some(issuer.public_key()),
+			None if cert.subject() == cert.issuer() => None,
+			// The chain ends below its root, nothing to check the signature against
+			None => continue,
+		};
+		if cert.verify_signature(issuer).is_err() {
+			issues.push(format!(
+				"Signature of certificate {} ({}) does not verify",
+				index,
+				cert.subject()
+			));
+			valid = false;
+		}
+	}
+
+	match certificate_aaguid(leaf) {
+		Some(cert_aaguid) if cert_aaguid != aaguid => {
+			issues.push(format!(
+				"Certificate AAGUID {} differs from the attested AAGUID",
+				hex::encode_upper(cert_aaguid)
+			));
+			valid = false;
+		}
+		Some(_) => {}
+		None => log::debug!("Attestation certificate has no AAGUID extension"),
+	}
+	valid
+}
+
+/// Creates a throwaway credential on `device`, returning its attestation and the challenge
+fn make_test_credential(
+	device: &FidoKeyHid,
+	pin: Option<&str>,
+) -> Result<(Attestation, [u8; 32]), String> {
+	let challenge: [u8; 32] = rand::rng().random();
+	let builder = MakeCredentialArgsBuilder::new(ATTESTATION_RP_ID, &challenge);
+	let args = match pin {
+		Some(pin) => builder.pin(pin),
+		None => builder.without_pin_and_uv(),
+	}
+	.build();
+
+	report_phase(
+		PhaseVerb::Create,
+		"a test credential",
+		UserAction::TouchDevice,
+	);
+	let attestation = device
+		.make_credential_with_args(&args)
+		.map_err(|e| format!("Failed to create test credential: {:?}", e))?;
+	Ok((attestation, challenge))
+}
+
+/// Checks `attestation`, made for `challenge`, against `expected_aaguid`
+fn evaluate(
+	attestation: &Attestation,
+	challenge: &[u8],
+	expected_aaguid: &str,
+) -> AttestationCheck {
+	let mut issues = Vec::new();
+	let aaguid = hex::encode_upper(&attestation.aaguid);
+	if !aaguid.eq_ignore_ascii_case(expected_aaguid) {
+		issues.push(format!(
+			"Attested AAGUID {} differs from the AAGUID {} reported by GetInfo",
+			aaguid, expected_aaguid
+		));
+	}
+
+	let signature_valid =
+		verifier::verify_attestation(ATTESTATION_RP_ID, challenge, attestation).is_success;
+	if !signature_valid {
+		issues.push("Attestation signature does not verify".into());
+	}
+	let chain_valid = check_chain(&attestation.attstmt_x5c, &attestation.aaguid, &mut issues);
+
+	AttestationCheck {
+		fmt: attestation.fmt.clone(),
+		aaguid,
+		signature_valid,
+		chain_valid,
+		certificate_count: attestation.attstmt_x5c.len(),
+		issues,
+	}
+}
+
+/// Creates a throwaway credential and checks its attestation against `expected_aaguid`, the
+/// AAGUID reported by GetInfo. Needs a touch, and the PIN if one is set.
+pub(crate) fn check_attestation(
+	pin: Option<&str>,
+	expected_aaguid: &str,
+) -> Result<AttestationCheck, String> {
+	let cfg = Cfg::init();
+	let device = FidoKeyHidFactory::create(&cfg)
+		.map_err(|e| format!("Failed to connect to FIDO device: {:?}", e))?;
+	let (attestation, challenge) = make_test_credential(&device, pin)?;
+	Ok(evaluate(&attestation, &challenge, expected_aaguid))
+}
+
+/// Subject, issuer and validity of a certificate of `x5c`
+fn describe_certificate(der: &[u8]) -> Result<AttestationCertificate, PFError> {
+	let (_, cert) = X509Certificate::from_der(der)
+		.map_err(|e| PFError::Device(format!("Attestation certificate cannot be parsed: {}", e)))?;
+	let validity = cert.validity();
+	Ok(AttestationCertificate {
+		subject: cert.subject().to_string(),
+		issuer: cert.issuer().to_string(),
+		serial: hex::encode_upper(cert.raw_serial()),
+		not_before: validity.not_before.timestamp(),
+		not_after: validity.not_after.timestamp(),
+		valid_now: validity.is_valid(),
+		self_signed: cert.subject() == cert.issuer(),
+		aaguid: certificate_aaguid(&cert).map(hex::encode_upper),
+	})
+}
+
+/// Creates a throwaway credential on the key at `device_path` and returns its attestation
+/// certificate chain along with the outcome of checking it. Needs a touch, and the PIN if one
+/// is set.
+pub(crate) fn attestation_info(
+	device_path: Option<&str>,
+	pin: Option<&str>,
+) -> Result<AttestationInfo, PFError> {
+	let expected_aaguid = get_fido_info(device_path)?.aaguid;
+	let device = open_device(device_path)
+		.map_err(|e| PFError::TransportIo(format!("Could not connect to FIDO device: {:#}", e)))?;
+	let (attestation, challenge) = make_test_credential(&device, pin).map_err(PFError::Device)?;
+
+	let certificates = attestation
+		.attstmt_x5c
+		.iter()
+		.map(|der| describe_certificate(der))
+		.collect::<Result<Vec<_>, _>>()?;
+	let check = evaluate(&attestation, &challenge, &expected_aaguid);
+	let aaguid_matches = check.aaguid.eq_ignore_ascii_case(&expected_aaguid)
+		&& certificates
+			.first()
+			.and_then(|leaf| leaf.aaguid.as_deref())
+			.is_none_or(|aaguid| aaguid.eq_ignore_ascii_case(&expected_aaguid));
+	log::info!(
+		"Attestation of {} has {} certificates, issued by {}",
+		expected_aaguid,
+		certificates.len(),
+		certificates
+			.first()
+			.map_or("nobody", |leaf| leaf.issuer.as_str())
+	);
+
+	Ok(AttestationInfo {
+		expected_aaguid,
+		aaguid_matches,
+		certificates,
+		check,
+	})
+}
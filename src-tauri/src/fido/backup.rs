@@ -0,0 +1,303 @@
+//! Backup and restore of the device secret, the master key every FIDO credential derives from.
+//!
+//! The firmware exports the secret encrypted for itself, authorized by the PIN. The file written
+//! here wraps that blob again under a passphrase, with AES-256-GCM and a PBKDF2 key, so a copied
+//! file alone is not enough to clone the key.
+//!
+//! Whoever holds the file and its passphrase can make a key that answers for every credential of
+//! the original, without the original ever knowing. Restoring replaces the secret of the key it
+//! is restored to, and every credential made with the old one stops working. Both operations
+//! therefore refuse to run unless the caller explicitly acknowledges this.
+
+use super::{
+	cache, config_pin_token, ctap_error, get_fido_info, hid::HidTransport, transport::Transport,
+};
+use crate::{
+	error::PFError,
+	fido::constants::*,
+	inventory,
+	operation::report_phase,
+	storage,
+	types::{DeviceBackupReport, DeviceEventKind, PhaseVerb, UserAction},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use rand::Rng;
+use ring::aead::{AES_256_GCM, Aad, LessSafeKey, Nonce, UnboundKey};
+use ring::pbkdf2;
+use serde::{Deserialize, Serialize};
+use serde_cbor_2::{Value, from_slice};
+use std::num::NonZeroU32;
+use std::path::Path;
+
+const BACKUP_FORMAT: &str = "picoforge-device-backup";
+const BACKUP_VERSION: u32 = 1;
+
+/// PBKDF2-HMAC-SHA256 iterations for new backups, the OWASP recommendation
+const KDF_ITERATIONS: u32 = 600_000;
+
+/// Most PBKDF2 iterations a backup may ask for on restore. The count comes from the file, and
+/// without a ceiling a crafted one keeps the restore busy for hours.
+const MAX_KDF_ITERATIONS: u32 = 10_000_000;
+
+/// Shortest passphrase accepted for a new backup
+const MIN_PASSPHRASE_LENGTH: usize = 12;
+
+/// Shown when a backup is requested without acknowledging what the file allows
+pub const BACKUP_WARNING: &str = "A device backup holds the secret all FIDO credentials of the \
+	key derive from. Anyone with the file and its passphrase can clone the key and sign in as \
+	its owner. Store it offline and acknowledge this to continue.";
+
+/// Shown when a restore is requested without confirming it replaces the secret of the key
+pub const RESTORE_WARNING: &str = "Restoring a device backup replaces the secret of the key. \
+	Every credential registered with the key so far stops working, and cannot be recovered \
+	without a backup of the current secret. Confirm this to continue.";
+
+/// Backup file as written to disk. Binary fields are hex, the ciphertext is base64.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BackupEnvelope {
+	format: String,
+	version: u32,
+	created_at: u64,
+	/// Key the backup was made from, for the operator's information
+	serial: String,
+	aaguid: String,
+	kdf_iterations: u32,
+	salt: String,
+	nonce: String,
+	ciphertext: String,
+}
+
+/// Associated data binding the ciphertext to the format version and the key it came from
+fn backup_aad(serial: &str, aaguid: &str) -> Vec<u8> {
+	format!(
+		"{} v{} {} {}",
+		BACKUP_FORMAT, BACKUP_VERSION, serial, aaguid
+	)
+	.into_bytes()
+}
+
+/// Checks the iteration count of a backup file before any key is derived with it. Fewer than
+/// PicoForge writes means the file was not made by it.
+fn check_kdf_iterations(iterations: u32) -> Result<u32, PFError> {
+	if !(KDF_ITERATIONS..=MAX_KDF_ITERATIONS).contains(&iterations) {
+		return Err(PFError::Io(format!(
+			"The device backup asks for {} key derivation iterations, only {} to {} are accepted",
+			iterations, KDF_ITERATIONS, MAX_KDF_ITERATIONS
+		)));
+	}
+	Ok(iterations)
+}
+
+fn passphrase_key(passphrase: &str, salt: &[u8], iterations: u32) -> Result<LessSafeKey, PFError> {
+	let iterations = NonZeroU32::new(iterations)
+		.ok_or_else(|| PFError::Io("The device backup is corrupted".into()))?;
+	let mut secret = [0u8; 32];
+	pbkdf2::derive(
+		pbkdf2::PBKDF2_HMAC_SHA256,
+		iterations,
+		salt,
+		passphrase.as_bytes(),
+		&mut secret,
+	);
+	let key = UnboundKey::new(&AES_256_GCM, &secret)
+		.map_err(|_| PFError::Io("Failed to build backup key".into()))?;
+	Ok(LessSafeKey::new(key))
+}
+
+/// Exports the device secret into `path`, encrypted under `passphrase`.
+///
+/// Refuses to run unless `acknowledge_risk` is set, see [`BACKUP_WARNING`].
+pub fn create_backup(
+	device_path: Option<&str>,
+	pin: Option<&str>,
+	passphrase: &str,
+	path: &Path,
+	acknowledge_risk: bool,
+) -> Result<DeviceBackupReport, PFError> {
+	if !acknowledge_risk {
+		return Err(PFError::Device(BACKUP_WARNING.into()));
+	}
+	if passphrase.chars().count() < MIN_PASSPHRASE_LENGTH {
+		return Err(PFError::Io(format!(
+			"The backup passphrase must have at least {} characters",
+			MIN_PASSPHRASE_LENGTH
+		)));
+	}
+
+	let serial = super::read_device_details(device_path)?.info.serial;
+	let aaguid = get_fido_info(device_path)?.aaguid;
+	log::warn!("Exporting the device secret of {} to {:?}", serial, path);
+
+	let pin_token = config_pin_token(device_path, pin)?;
+	let transport =
+		HidTransport::open_path(device_path).map_err(|e| ctap_error(e, "HID connection"))?;
+	report_phase(PhaseVerb::Read, "the device secret", UserAction::None);
+	let res = transport
+		.send_signed_vendor_command(
+			&pin_token,
+			VendorCommand::Backup,
+			BackupSubCommand::GetEncryptedBackup as i128,
+			None,
+		)
+		.map_err(|e| ctap_error(e, "device backup"))?;
+	let Ok(Value::Map(m)) = from_slice(&res) else {
+		return Err(PFError::CborDecode(
+			"Backup response was not a valid CBOR map".into(),
+		));
+	};
+	let Some(Value::Bytes(blob)) =
+		m.get(&Value::Integer(BackupResponseKey::EncryptedBackup as i128))
+	else {
+		return Err(PFError::CborDecode(
+			"Backup missing from the response".into(),
+		));
+	};
+
+	let salt: [u8; 32] = rand::rng().random();
+	let nonce: [u8; 12] = rand::rng().random();
+	let key = passphrase_key(passphrase, &salt, KDF_ITERATIONS)?;
+	let mut ciphertext = blob.clone();
+	key.seal_in_place_append_tag(
+		Nonce::assume_unique_for_key(nonce),
+		Aad::from(backup_aad(&serial, &aaguid)),
+		&mut ciphertext,
+	)
+	.map_err(|_| PFError::Io("Failed to encrypt the backup".into()))?;
+
+	let envelope = BackupEnvelope {
+		format: BACKUP_FORMAT.into(),
+		version: BACKUP_VERSION,
+		created_at: inventory::now(),
+		serial: serial.clone(),
+		aaguid: aaguid.clone(),
+		kdf_iterations: KDF_ITERATIONS,
+		salt: hex::encode(salt),
+		nonce: hex::encode(nonce),
+		ciphertext: STANDARD.encode(&ciphertext),
+	};
+	let data = serde_json::to_vec_pretty(&envelope).map_err(|e| PFError::Io(e.to_string()))?;
+	storage::write(path, &data)
+		.map_err(|e| PFError::Io(format!("Failed to write the device backup: {}", e)))?;
+
+	inventory::log_device_event(
+		&serial,
+		DeviceEventKind::SecretBackup,
+		"Device secret backed up",
+	);
+	Ok(DeviceBackupReport {
+		path: path.to_string_lossy().into_owned(),
+		serial,
+		aaguid,
+		created_at: envelope.created_at,
+	})
+}
+
+/// Replaces the device secret with the one in the backup at `path`, decrypted with `passphrase`.
+///
+/// Refuses to run unless `confirm_replace` is set, see [`RESTORE_WARNING`]. Nothing is sent to
+/// the key unless the backup decrypts.
+pub fn restore_backup(
+	device_path: Option<&str>,
+	pin: Option<&str>,
+	path: &Path,
+	passphrase: &str,
+	confirm_replace: bool,
+) -> Result<DeviceBackupReport, PFError> {
+	if !confirm_replace {
+		return Err(PFError::Device(RESTORE_WARNING.into()));
+	}
+
+	let data = storage::read(path)
+		.map_err(|e| PFError::Io(format!("Failed to read the device backup: {}", e)))?;
+	let envelope: BackupEnvelope = serde_json::from_slice(&data)
+		.map_err(|_| PFError::Io("Not a PicoForge device backup".into()))?;
+	if envelope.format != BACKUP_FORMAT {
+		return Err(PFError::Io("Not a PicoForge device backup".into()));
+	}
+	if envelope.version != BACKUP_VERSION {
+		return Err(PFError::Io(format!(
+			"Device backup version {} is not supported",
+			envelope.version
+		)));
+	}
+
+	let corrupted = || PFError::Io("The device backup is corrupted".into());
+	let salt = hex::decode(&envelope.salt).map_err(|_| corrupted())?;
+	let nonce = hex::decode(&envelope.nonce).map_err(|_| corrupted())?;
+	let mut ciphertext = STANDARD
+		.decode(&envelope.ciphertext)
+		.map_err(|_| corrupted())?;
+	let iterations = check_kdf_iterations(envelope.kdf_iterations)?;
+	let key = passphrase_key(passphrase, &salt, iterations)?;
+	let nonce = Nonce::try_assume_unique_for_key(&nonce).map_err(|_| corrupted())?;
+	let blob = key
+		.open_in_place(
+			nonce,
+			Aad::from(backup_aad(&envelope.serial, &envelope.aaguid)),
+			&mut ciphertext,
+		)
+		.map_err(|_| {
+			PFError::Io("The device backup could not be decrypted, check the passphrase".into())
+		})?
+		.to_vec();
+
+	let serial = super::read_device_details(device_path)?.info.serial;
+	log::warn!(
+		"Restoring the device secret of {} onto {}",
+		envelope.serial,
+		serial
+	);
+
+	let pin_token = config_pin_token(device_path, pin)?;
+	let transport =
+		HidTransport::open_path(device_path).map_err(|e| ctap_error(e, "HID connection"))?;
+	cache::invalidate();
+	report_phase(PhaseVerb::Write, "the device secret", UserAction::None);
+	transport
+		.send_signed_vendor_command(
+			&pin_token,
+			VendorCommand::Backup,
+			BackupSubCommand::RestoreEncryptedBackup as i128,
+			Some(blob),
+		)
+		.map_err(|e| ctap_error(e, "device restore"))?;
+
+	inventory::log_device_event(
+		&serial,
+		DeviceEventKind::SecretRestore,
+		&format!(
+			"Device secret restored from a backup of {}",
+			envelope.serial
+		),
+	);
+	Ok(DeviceBackupReport {
+		path: path.to_string_lossy().into_owned(),
+		serial: envelope.serial,
+		aaguid: envelope.aaguid,
+		created_at: envelope.created_at,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn kdf_iterations_are_bounded() {
+		assert_eq!(
+			check_kdf_iterations(KDF_ITERATIONS).unwrap(),
+			KDF_ITERATIONS
+		);
+		assert_eq!(
+			check_kdf_iterations(MAX_KDF_ITERATIONS).unwrap(),
+			MAX_KDF_ITERATIONS
+		);
+		for iterations in [0, 1, KDF_ITERATIONS - 1, MAX_KDF_ITERATIONS + 1, u32::MAX] {
+			assert!(matches!(
+				check_kdf_iterations(iterations),
+				Err(PFError::Io(_))
+			));
+		}
+	}
+}
@@ -0,0 +1,174 @@
+//! Fingerprint management (authenticatorBioEnrollment) for keys built with a bio sensor.
+//!
+//! Enrolling takes several good samples. Every sample is reported as progress, with the number
+//! of touches still needed and the reason when a sample was rejected.
+
+use super::{cache, open_device};
+use crate::{
+	operation::{cancel_requested, report_phase, report_progress},
+	types::{BioEnrollment, BioSensorInfo, PhaseVerb, UserAction},
+};
+use ctap_hid_fido2::{
+	FidoKeyHid,
+	fidokey::bio::{FingerprintKind, TemplateInfo},
+};
+
+/// How long the key waits for each sample before giving up
+const SAMPLE_TIMEOUT_MS: u16 = 30_000;
+
+/// Opens the device, failing early if it has no fingerprint sensor
+fn open_bio_device(device_path: Option<&str>) -> Result<FidoKeyHid, String> {
+	let device = open_device(device_path)
+		.map_err(|e| format!("Failed to connect to FIDO device: {:?}", e))?;
+	let info = device
+		.get_info()
+		.map_err(|e| format!("Error reading device info: {:?}", e))?;
+	if !info.options.iter().any(|(name, _)| name == "bioEnroll") {
+		return Err("This key has no fingerprint sensor".into());
+	}
+	Ok(device)
+}
+
+fn to_enrollment(template: TemplateInfo) -> BioEnrollment {
+	BioEnrollment {
+		template_id: hex::encode(template.template_id),
+		name: template.template_friendly_name,
+	}
+}
+
+fn decode_template_id(template_id_hex: &str) -> Result<Vec<u8>, String> {
+	hex::decode(template_id_hex).map_err(|_| "Invalid template ID hex string".to_string())
+}
+
+pub(crate) fn get_sensor_info(device_path: Option<&str>) -> Result<BioSensorInfo, String> {
+	let device = open_bio_device(device_path)?;
+
+	report_phase(PhaseVerb::Read, "fingerprint sensor info", UserAction::None);
+	let info = device
+		.bio_enrollment_get_fingerprint_sensor_info()
+		.map_err(|e| format!("Failed to read sensor info: {:?}", e))?;
+
+	Ok(BioSensorInfo {
+		fingerprint_kind: match info.fingerprint_kind {
+			FingerprintKind::TouchType => "touch",
+			FingerprintKind::SwipeType => "swipe",
+			FingerprintKind::Unknown => "unknown",
+		}
+		.into(),
+		samples_required: info.max_capture_samples_required_for_enroll,
+		max_name_length: info.max_template_friendly_name,
+	})
+}
+
+/// Enrolls a new fingerprint, optionally naming it. The sensor has to be touched until the key
+/// has enough good samples, each sample is sent as progress of the current operation.
+pub(crate) fn enroll(
+	device_path: Option<&str>,
+	pin: &str,
+	name: Option<&str>,
+) -> Result<BioEnrollment, String> {
+	let device = open_bio_device(device_path)?;
+	let samples_required = device
+		.bio_enrollment_get_fingerprint_sensor_info()
+		.map_err(|e| format!("Failed to read sensor info: {:?}", e))?
+		.max_capture_samples_required_for_enroll;
+
+	report_phase(PhaseVerb::Create, "a fingerprint", UserAction::TouchSensor);
+	let (enrollment, mut sample) = device
+		.bio_enrollment_begin(pin, Some(SAMPLE_TIMEOUT_MS))
+		.map_err(|e| format!("Failed to start enrollment: {:?}", e))?;
+
+	loop {
+		// Keys may ask for more samples than announced after rejecting some
+		let total = samples_required.max(sample.remaining_samples + 1);
+		let captured = total.saturating_sub(sample.remaining_samples);
+		let message = match (sample.status, sample.remaining_samples) {
+			(_, 0) if sample.is_finish => "Fingerprint enrolled".to_string(),
+			(0x00, 1) => "Sample captured, touch the sensor 1 more time".to_string(),
+			(0x00, remaining) => {
+				format!("Sample captured, touch the sensor {} more times", remaining)
+			}
+			(_, remaining) => format!(
+				"{}, touch the sensor {} more times",
+				sample.message, remaining
+			),
+		};
+		log::info!("Bio enrollment: {}", message);
+		report_progress(message, captured, total);
+
+		if sample.is_finish {
+			break;
+		}
+		if cancel_requested() {
+			if let Err(e) = device.bio_enrollment_cancel() {
+				log::warn!("Failed to cancel enrollment on the device: {:?}", e);
+			}
+			return Err("Fingerprint enrollment was cancelled".into());
+		}
+		sample = device
+			.bio_enrollment_next(&enrollment, Some(SAMPLE_TIMEOUT_MS))
+			.map_err(|e| format!("Failed to capture fingerprint sample: {:?}", e))?;
+	}
+
+	if let Some(name) = name {
+		report_phase(PhaseVerb::Write, "the fingerprint name", UserAction::None);
+		device
+			.bio_enrollment_set_friendly_name(pin, &enrollment.template_id, name)
+			.map_err(|e| format!("Fingerprint enrolled, but naming it failed: {:?}", e))?;
+	}
+	cache::invalidate();
+
+	Ok(BioEnrollment {
+		template_id: hex::encode(&enrollment.template_id),
+		name: name.map(str::to_string),
+	})
+}
+
+pub(crate) fn list_enrollments(
+	device_path: Option<&str>,
+	pin: &str,
+) -> Result<Vec<BioEnrollment>, String> {
+	let device = open_bio_device(device_path)?;
+
+	report_phase(PhaseVerb::Read, "enrolled fingerprints", UserAction::None);
+	match device.bio_enrollment_enumerate_enrollments(pin) {
+		Ok(templates) => Ok(templates.into_iter().map(to_enrollment).collect()),
+		// The key answers with CTAP2_ERR_INVALID_OPTION when nothing is enrolled
+		Err(e) if e.to_string().contains("CTAP2_ERR_INVALID_OPTION") => Ok(Vec::new()),
+		Err(e) => Err(format!("Failed to list fingerprints: {:?}", e)),
+	}
+}
+
+pub(crate) fn rename_enrollment(
+	device_path: Option<&str>,
+	pin: &str,
+	template_id_hex: &str,
+	name: &str,
+) -> Result<String, String> {
+	let device = open_bio_device(device_path)?;
+	let template_id = decode_template_id(template_id_hex)?;
+
+	report_phase(PhaseVerb::Write, "the fingerprint name", UserAction::None);
+	device
+		.bio_enrollment_set_friendly_name(pin, &template_id, name)
+		.map_err(|e| format!("Failed to rename fingerprint: {:?}", e))?;
+
+	Ok("Fingerprint renamed successfully".into())
+}
+
+pub(crate) fn delete_enrollment(
+	device_path: Option<&str>,
+	pin: &str,
+	template_id_hex: &str,
+) -> Result<String, String> {
+	let device = open_bio_device(device_path)?;
+	let template_id = decode_template_id(template_id_hex)?;
+
+	report_phase(PhaseVerb::Delete, "the fingerprint", UserAction::None);
+	cache::invalidate();
+	device
+		.bio_enrollment_remove(pin, &template_id)
+		.map_err(|e| format!("Failed to delete fingerprint: {:?}", e))?;
+
+	Ok("Fingerprint deleted successfully".into())
+}
@@ -0,0 +1,98 @@
+//! Short-lived cache of vendor command results that rarely change.
+//!
+//! UI refreshes call `read_device_details` often, and the memory stats and physical options
+//! exchanges make up half of its round trips. Their parsed results are kept for `VENDOR_TTL`,
+//! per HID device path so a different or re-plugged key is never answered from the cache.
+//! Everything writing to the device calls `invalidate` so edits show up right away.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long cached vendor results are served
+pub const VENDOR_TTL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+	pub used: u32,
+	pub total: u32,
+	/// Left out by firmware that only reports the used and total space
+	pub free: Option<u32>,
+	pub files: Option<u32>,
+	pub flash_size: Option<u32>,
+}
+
+impl MemoryStats {
+	/// Breakdown of the flash, if the firmware reported all of it
+	pub fn breakdown(&self) -> Option<crate::types::FlashBreakdown> {
+		Some(crate::types::FlashBreakdown::new(
+			self.free?,
+			self.used,
+			self.total,
+			self.files?,
+			self.flash_size?,
+		))
+	}
+}
+
+/// Physical options, decoded like the PHY configuration of the Rescue Applet
+pub type PhysicalOptions = super::phy::PhyConfig;
+
+struct Entry<T> {
+	device: String,
+	value: T,
+	fetched_at: Instant,
+}
+
+pub struct TtlCache<T> {
+	entry: Mutex<Option<Entry<T>>>,
+}
+
+impl<T: Clone> TtlCache<T> {
+	const fn new() -> Self {
+		Self {
+			entry: Mutex::new(None),
+		}
+	}
+
+	/// Cached value for `device` if still fresh, otherwise the result of `fetch`, which is cached
+	/// when it succeeds.
+	pub fn get_or_fetch<E>(
+		&self,
+		device: &str,
+		fetch: impl FnOnce() -> Result<T, E>,
+	) -> Result<T, E> {
+		if let Ok(entry) = self.entry.lock()
+			&& let Some(entry) = entry.as_ref()
+			&& entry.device == device
+			&& entry.fetched_at.elapsed() < VENDOR_TTL
+		{
+			return Ok(entry.value.clone());
+		}
+
+		let value = fetch()?;
+		if let Ok(mut entry) = self.entry.lock() {
+			*entry = Some(Entry {
+				device: device.to_string(),
+				value: value.clone(),
+				fetched_at: Instant::now(),
+			});
+		}
+		Ok(value)
+	}
+
+	fn clear(&self) {
+		if let Ok(mut entry) = self.entry.lock() {
+			*entry = None;
+		}
+	}
+}
+
+pub static MEMORY_STATS: TtlCache<MemoryStats> = TtlCache::new();
+pub static PHYSICAL_OPTIONS: TtlCache<PhysicalOptions> = TtlCache::new();
+
+/// Drops all cached results, to be called after writing to the device
+pub fn invalidate() {
+	log::debug!("Invalidating cached vendor results");
+	MEMORY_STATS.clear();
+	PHYSICAL_OPTIONS.clear();
+}
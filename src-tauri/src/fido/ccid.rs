@@ -0,0 +1,530 @@
+//! FIDO over PC/SC: the CCID interface, for systems where HID access is blocked, and
+//! contactless readers, for keys reached over NFC.
+//!
+//! CTAP2 messages are wrapped in NFCCTAP_MSG APDUs sent to the FIDO applet. The pico-fido vendor
+//! commands, their own CTAPHID command over HID, travel in the same APDU with that command in P1.
+//! The PIN token is obtained with PIN protocol 1 here, ctap-hid-fido2 only speaks HID.
+
+use super::{
+	cache,
+	constants::*,
+	cred_mgmt, ctap_error,
+	hid::{
+		CTAPHID_CBOR, CtapStatus, KEEPALIVE_STATUS_UPNEEDED, trace_payload, vendor_config_payload,
+	},
+	transport::Transport,
+	vendor,
+};
+use crate::{
+	card,
+	error::PFError,
+	operation::report_phase,
+	types::{
+		AppConfig, AppConfigInput, DeviceInfo, FirmwareVersion, FormatHints, FullDeviceStatus,
+		PhaseVerb, PinStatus, StoredCredential, UserAction,
+	},
+};
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit, generic_array::GenericArray};
+use ring::{agreement, digest};
+use serde_cbor_2::{Value, from_slice, to_vec};
+use std::collections::BTreeMap;
+
+/// AES-256-CBC with the all-zero IV of PIN protocol 1, on whole blocks
+fn aes256_cbc(key: &[u8], data: &[u8], encrypt: bool) -> Vec<u8> {
+	let cipher = aes::Aes256::new(GenericArray::from_slice(key));
+	let mut prev = [0u8; 16];
+	let mut out = Vec::with_capacity(data.len());
+	for chunk in data.chunks_exact(16) {
+		let mut block = GenericArray::clone_from_slice(chunk);
+		if encrypt {
+			block.iter_mut().zip(prev).for_each(|(b, p)| *b ^= p);
+			cipher.encrypt_block(&mut block);
+			prev.copy_from_slice(&block);
+		} else {
+			cipher.decrypt_block(&mut block);
+			block.iter_mut().zip(prev).for_each(|(b, p)| *b ^= p);
+			prev.copy_from_slice(chunk);
+		}
+		out.extend_from_slice(&block);
+	}
+	out
+}
+
+fn int(key: impl Into<i128>) -> Value {
+	Value::Integer(key.into())
+}
+
+/// PC/SC reader the FIDO applet is reached on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reader {
+	/// The CCID interface of a key plugged in over USB, the first reader
+	Usb,
+	/// A contactless reader with the key on it, talking NFC
+	Contactless,
+}
+
+impl Reader {
+	/// Name of the method, as reported in `FullDeviceStatus`
+	fn method(self) -> &'static str {
+		match self {
+			Reader::Usb => "CCID",
+			Reader::Contactless => "NFC",
+		}
+	}
+}
+
+pub struct CcidTransport {
+	card: pcsc::Card,
+	/// Name of the reader, standing in for the HID path the device caches are keyed by
+	reader: String,
+}
+
+impl CcidTransport {
+	/// Selects the FIDO applet on `reader`
+	pub fn open(reader: Reader) -> Result<Self, PFError> {
+		match reader {
+			Reader::Usb => {
+				let (card, _) = card::connect_and_select(FIDO_AID, "FIDO")?;
+				Ok(Self {
+					card,
+					reader: "ccid".into(),
+				})
+			}
+			Reader::Contactless => {
+				report_phase(
+					PhaseVerb::Connect,
+					"FIDO applet over NFC",
+					UserAction::KeepConnected,
+				);
+				let (card, name) = card::connect_contactless()?;
+				card::select(&card, FIDO_AID, "FIDO")?;
+				log::info!("Using the contactless reader {}", name);
+				Ok(Self {
+					card,
+					reader: format!("nfc:{}", name),
+				})
+			}
+		}
+	}
+
+	/// Sends a CTAP message, `cmd` being `CTAPHID_CBOR` or a pico-fido vendor command, and
+	/// returns the payload without the status byte. A non-zero status is returned as
+	/// `CtapStatus`, like over HID.
+	fn exchange(&self, cmd: u8, payload: &[u8]) -> anyhow::Result<Vec<u8>> {
+		log::debug!(
+			"Sending CBOR Command over CCID: 0x{:02X}, Payload Size: {} bytes",
+			cmd,
+			payload.len()
+		);
+		log::trace!(
+			"CTAP request 0x{:02X}: {}",
+			cmd,
+			trace_payload(payload, payload)
+		);
+		let p1 = if cmd == CTAPHID_CBOR { 0x00 } else { cmd };
+
+		// Extended length APDU: 80 10 [P1] 00 00 [Lc (2)] [payload] 00 00
+		let mut apdu = vec![NFCCTAP_CLA, NFCCTAP_MSG, p1, 0x00, 0x00];
+		apdu.extend((payload.len() as u16).to_be_bytes());
+		apdu.extend_from_slice(payload);
+		apdu.extend([0x00, 0x00]);
+
+		let mut resp = card::transmit(&self.card, &apdu)?;
+		let mut presence_requested = false;
+		while resp.sw == SW_NFCCTAP_STATUS_UPDATE {
+			if resp.data.first() == Some(&KEEPALIVE_STATUS_UPNEEDED) && !presence_requested {
+				presence_requested = true;
+				report_phase(PhaseVerb::Verify, "your presence", UserAction::TouchDevice);
+			}
+			let poll = [NFCCTAP_CLA, NFCCTAP_GETRESPONSE, 0x00, 0x00, 0x00];
+			resp = card::transmit(&self.card, &poll)?;
+		}
+		if !resp.is_success() {
+			return Err(
+				PFError::Device(format!("FIDO applet returned status {:04X}", resp.sw)).into(),
+			);
+		}
+
+		match resp.data.split_first() {
+			Some((0x00, data)) => {
+				log::trace!(
+					"CTAP response 0x{:02X}: {}",
+					cmd,
+					trace_payload(payload, data)
+				);
+				Ok(data.to_vec())
+			}
+			Some((status, _)) => {
+				log::error!("FIDO Operation returned failure status: 0x{:02X}", status);
+				Err(CtapStatus(*status).into())
+			}
+			None => Err(PFError::Device("Empty response".into()).into()),
+		}
+	}
+
+	fn client_pin(
+		&self,
+		params: BTreeMap<Value, Value>,
+	) -> Result<BTreeMap<Value, Value>, PFError> {
+		let mut payload = vec![CtapCommand::ClientPin as u8];
+		payload.extend(to_vec(&Value::Map(params)).map_err(|e| PFError::Io(e.to_string()))?);
+		let res = self
+			.exchange(CTAPHID_CBOR, &payload)
+			.map_err(|e| ctap_error(e, "clientPin"))?;
+		if res.is_empty() {
+			return Ok(BTreeMap::new());
+		}
+		match from_slice(&res) {
+			Ok(Value::Map(m)) => Ok(m),
+			_ => Err(PFError::Io(
+				"ClientPIN response was not a valid CBOR map".into(),
+			)),
+		}
+	}
+
+	/// Key agreement of PIN protocol 1, returning the shared secret and the platform key to
+	/// send along requests encrypted with it
+	fn key_agreement(&self) -> Result<(Vec<u8>, Value), PFError> {
+		let resp = self.client_pin(BTreeMap::from([
+			(int(ClientPinParam::PinUvAuthProtocol as u8), int(1)),
+			(
+				int(ClientPinParam::SubCommand as u8),
+				int(ClientPinSubCommand::GetKeyAgreement as u8),
+			),
+		]))?;
+		let Some(Value::Map(cose)) = resp.get(&int(ClientPinResponse::KeyAgreement as u8)) else {
+			return Err(PFError::Device(
+				"Key agreement missing from the response".into(),
+			));
+		};
+		let coordinate = |key: i128| match cose.get(&int(key)) {
+			Some(Value::Bytes(b)) if b.len() == 32 => Ok(b.clone()),
+			_ => Err(PFError::Device("Malformed key agreement".into())),
+		};
+		let mut peer = vec![0x04];
+		peer.extend(coordinate(-2)?);
+		peer.extend(coordinate(-3)?);
+
+		let rng = ring::rand::SystemRandom::new();
+		let private = agreement::EphemeralPrivateKey::generate(&agreement::ECDH_P256, &rng)
+			.map_err(|_| PFError::Io("Failed to generate the key agreement key".into()))?;
+		let public = private
+			.compute_public_key()
+			.map_err(|_| PFError::Io("Failed to compute the key agreement key".into()))?;
+		let shared = agreement::agree_ephemeral(
+			private,
+			&agreement::UnparsedPublicKey::new(&agreement::ECDH_P256, &peer),
+			|z| digest::digest(&digest::SHA256, z).as_ref().to_vec(),
+		)
+		.map_err(|_| PFError::Device("Key agreement with the device failed".into()))?;
+
+		let public = public.as_ref();
+		let platform_key = Value::Map(BTreeMap::from([
+			(int(1), int(2)),
+			(int(3), int(-25)),
+			(int(-1), int(1)),
+			(int(-2), Value::Bytes(public[1..33].to_vec())),
+			(int(-3), Value::Bytes(public[33..65].to_vec())),
+		]));
+		Ok((shared, platform_key))
+	}
+
+	/// PIN token with the authenticatorConfig permission, or a plain one on CTAP 2.0 firmware
+	pub fn pin_token(&self, pin: &str) -> Result<Vec<u8>, PFError> {
+		self.pin_token_with(pin, PinUvAuthTokenPermissions::AUTHENTICATOR_CONFIG)
+	}
+
+	/// PIN token with `permissions`, or a plain one on CTAP 2.0 firmware
+	pub fn pin_token_with(
+		&self,
+		pin: &str,
+		permissions: PinUvAuthTokenPermissions,
+	) -> Result<Vec<u8>, PFError> {
+		let protocol = (int(ClientPinParam::PinUvAuthProtocol as u8), int(1));
+		let (shared, platform_key) = self.key_agreement()?;
+
+		// Encrypted PIN hash, sent along our public key
+		let pin_hash = digest::digest(&digest::SHA256, pin.as_bytes());
+		let pin_hash_enc = aes256_cbc(&shared, &pin_hash.as_ref()[..16], true);
+		let token_request = |sub_command: ClientPinSubCommand, permissions: Option<u8>| {
+			let mut params = BTreeMap::from([
+				protocol.clone(),
+				(
+					int(ClientPinParam::SubCommand as u8),
+					int(sub_command as u8),
+				),
+				(
+					int(ClientPinParam::KeyAgreement as u8),
+					platform_key.clone(),
+				),
+				(
+					int(ClientPinParam::PinHashEnc as u8),
+					Value::Bytes(pin_hash_enc.clone()),
+				),
+			]);
+			if let Some(permissions) = permissions {
+				params.insert(int(ClientPinParam::Permissions as u8), int(permissions));
+			}
+			self.client_pin(params)
+		};
+
+		// Token, decrypted with the shared secret
+		let resp = token_request(
+			ClientPinSubCommand::GetPinUvAuthTokenUsingPinWithPermissions,
+			Some(permissions.bits()),
+		)
+		.or_else(|e| match e {
+			PFError::PinInvalid { .. } | PFError::PinBlocked => Err(e),
+			e => {
+				log::warn!(
+					"Failed to get PIN token with permissions ({}). Falling back to standard token.",
+					e
+				);
+				token_request(ClientPinSubCommand::GetPinToken, None)
+			}
+		})?;
+		match resp.get(&int(ClientPinResponse::PinUvAuthToken as u8)) {
+			Some(Value::Bytes(enc)) if !enc.is_empty() && enc.len() % 16 == 0 => {
+				Ok(aes256_cbc(&shared, enc, false))
+			}
+			_ => Err(PFError::Device(
+				"PIN token missing from the response".into(),
+			)),
+		}
+	}
+
+	/// Sets the first PIN, or changes it if `current_pin` is given
+	pub fn set_pin(&self, current_pin: Option<&str>, new_pin: &str) -> Result<(), PFError> {
+		let (shared, platform_key) = self.key_agreement()?;
+
+		// The new PIN is padded with zeros to 64 bytes before encryption
+		let mut padded = new_pin.as_bytes().to_vec();
+		if padded.len() > 63 {
+			return Err(PFError::Device("The PIN must be at most 63 bytes".into()));
+		}
+		padded.resize(64, 0);
+		let new_pin_enc = aes256_cbc(&shared, &padded, true);
+
+		let mut params = BTreeMap::from([
+			(int(ClientPinParam::PinUvAuthProtocol as u8), int(1)),
+			(int(ClientPinParam::KeyAgreement as u8), platform_key),
+			(
+				int(ClientPinParam::NewPinEnc as u8),
+				Value::Bytes(new_pin_enc.clone()),
+			),
+		]);
+		// pinAuth = HMAC(shared, newPinEnc [|| pinHashEnc]), first 16 bytes
+		let mut message = new_pin_enc;
+		let sub_command = match current_pin {
+			Some(current) => {
+				let pin_hash = digest::digest(&digest::SHA256, current.as_bytes());
+				let pin_hash_enc = aes256_cbc(&shared, &pin_hash.as_ref()[..16], true);
+				message.extend(&pin_hash_enc);
+				params.insert(
+					int(ClientPinParam::PinHashEnc as u8),
+					Value::Bytes(pin_hash_enc),
+				);
+				ClientPinSubCommand::ChangePin
+			}
+			None => ClientPinSubCommand::SetPin,
+		};
+		let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, &shared);
+		params.insert(
+			int(ClientPinParam::PinUvAuthParam as u8),
+			Value::Bytes(ring::hmac::sign(&key, &message).as_ref()[..16].to_vec()),
+		);
+		params.insert(
+			int(ClientPinParam::SubCommand as u8),
+			int(sub_command as u8),
+		);
+		self.client_pin(params)?;
+		Ok(())
+	}
+}
+
+impl Transport for CcidTransport {
+	fn send_cbor(&self, cmd: u8, payload: &[u8]) -> anyhow::Result<Vec<u8>> {
+		self.exchange(cmd, payload)
+	}
+
+	fn path(&self) -> &str {
+		&self.reader
+	}
+
+	/// Not visible over PC/SC
+	fn vid(&self) -> u16 {
+		0
+	}
+
+	fn pid(&self) -> u16 {
+		0
+	}
+
+	fn product_name(&self) -> &str {
+		""
+	}
+
+	fn serial_number(&self) -> Option<&str> {
+		None
+	}
+}
+
+/// Reads the same details as `fido::read_device_details`, over CCID or NFC
+pub fn read_device_details(reader: Reader) -> Result<FullDeviceStatus, PFError> {
+	log::info!(
+		"Starting FIDO device details read over {}...",
+		reader.method()
+	);
+	report_phase(PhaseVerb::Read, "device configuration", UserAction::None);
+	let transport = CcidTransport::open(reader)?;
+
+	let info_res = transport
+		.send_cbor(CTAPHID_CBOR, &[CtapCommand::GetInfo as u8])
+		.map_err(|e| ctap_error(e, "GetInfo"))?;
+	let (aaguid, fw_version) = super::parse_get_info(&info_res)?;
+	log::info!("Device identified: AAGUID={}, FW={}", aaguid, fw_version);
+
+	let memory = vendor::memory_stats(&transport).unwrap_or_else(|e| {
+		vendor::log_unavailable("memory stats", &e);
+		cache::MemoryStats::default()
+	});
+	let phy = vendor::physical_options(&transport).unwrap_or_else(|e| {
+		vendor::log_unavailable("physical config", &e);
+		cache::PhysicalOptions::default()
+	});
+	// Release the reader before the Rescue Applet is selected on it
+	drop(transport);
+
+	// The VID/PID are not visible over PC/SC, unless the PHY configuration overrides them
+	let mut config = AppConfig::default();
+	phy.apply_to(&mut config);
+	// The Rescue Applet, which also reports the serial number, is only reachable over the USB
+	// CCID interface
+	let serial = match reader {
+		Reader::Usb => crate::rescue::connected_serial(),
+		Reader::Contactless => None,
+	}
+	.unwrap_or_else(|| "?".to_string());
+	let (secure_boot, secure_lock) = match reader {
+		Reader::Usb => crate::rescue::read_secure_boot_status().unwrap_or_else(|e| {
+			log::warn!("Failed to read secure boot status: {}", e);
+			(false, false)
+		}),
+		Reader::Contactless => (false, false),
+	};
+
+	Ok(FullDeviceStatus {
+		info: DeviceInfo {
+			serial,
+			flash_used: memory.used / 1024,
+			flash_total: memory.total / 1024,
+			flash_used_bytes: memory.used,
+			flash_total_bytes: memory.total,
+			firmware: FirmwareVersion::parse(&fw_version),
+			firmware_version: fw_version,
+		},
+		flash: memory.breakdown(),
+		config,
+		secure_boot,
+		secure_lock,
+		method: reader.method().to_string(),
+		format_hints: FormatHints::default(),
+	})
+}
+
+/// Remaining PIN and UV attempts, like `fido::get_pin_status`
+pub fn get_pin_status(reader: Reader) -> Result<PinStatus, PFError> {
+	let transport = CcidTransport::open(reader)?;
+	report_phase(PhaseVerb::Read, "PIN retry counters", UserAction::None);
+	super::read_pin_status(&transport)
+}
+
+/// Sets the PIN, or changes it with `current_pin`, like `fido::change_fido_pin`
+pub fn change_pin(
+	reader: Reader,
+	current_pin: Option<String>,
+	new_pin: String,
+) -> Result<String, PFError> {
+	let transport = CcidTransport::open(reader)?;
+	if current_pin.is_some() {
+		super::check_pin_attempts(&super::read_pin_status(&transport)?)?;
+	}
+	super::pin_policy::check_new_pin(
+		&new_pin,
+		current_pin.as_deref(),
+		super::read_min_pin_length(&transport)?,
+	)?;
+	report_phase(PhaseVerb::Write, "FIDO PIN", UserAction::None);
+	transport.set_pin(current_pin.as_deref(), &new_pin)?;
+	Ok(match current_pin {
+		Some(_) => "PIN Changed Successfully".into(),
+		None => "PIN Set Successfully".into(),
+	})
+}
+
+/// Passkeys and slot usage, as (existing, max remaining), like `fido::get_credentials`.
+///
+/// The session PIN token cache is keyed by HID path, so the PIN is needed on every call here.
+pub fn get_credentials(
+	reader: Reader,
+	pin: Option<&str>,
+) -> Result<(Vec<StoredCredential>, (u32, u32)), PFError> {
+	let pin = pin.ok_or(PFError::PinRequired)?;
+	let transport = CcidTransport::open(reader)?;
+	report_phase(PhaseVerb::Verify, "the PIN", UserAction::None);
+	let token = transport.pin_token_with(pin, PinUvAuthTokenPermissions::CREDENTIAL_MANAGEMENT)?;
+	report_phase(PhaseVerb::Read, "passkeys", UserAction::None);
+	let metadata = cred_mgmt::metadata_on(&transport, &token)
+		.map_err(|e| ctap_error(e, "credential metadata read"))?;
+	let credentials = cred_mgmt::enumerate_on(&transport, &token)
+		.map_err(|e| ctap_error(e, "credential enumeration"))?;
+	Ok((credentials, metadata))
+}
+
+/// Validates `config` and the PIN over CCID, returning the commands `write_config` would send
+pub fn plan_write_config(
+	reader: Reader,
+	config: &AppConfigInput,
+	pin: Option<&str>,
+) -> Result<Vec<String>, PFError> {
+	let actions = super::describe_config(config)?;
+	let pin = pin.ok_or_else(|| PFError::Device("PIN is required for configuration".into()))?;
+	report_phase(PhaseVerb::Authenticate, "configuration", UserAction::None);
+	CcidTransport::open(reader)?.pin_token(pin)?;
+	Ok(actions)
+}
+
+/// Writes the same vendor configuration as `fido::write_config`, over CCID
+pub fn write_config(
+	reader: Reader,
+	config: AppConfigInput,
+	pin: Option<String>,
+) -> Result<String, PFError> {
+	log::info!("Starting FIDO write_config over CCID...");
+	cache::invalidate();
+	report_phase(
+		PhaseVerb::Write,
+		"device configuration",
+		UserAction::KeepConnected,
+	);
+
+	let steps = super::config_steps(&config)?;
+	let pin = pin.ok_or_else(|| PFError::Device("PIN is required for configuration".into()))?;
+	let transport = CcidTransport::open(reader)?;
+	let pin_token = transport.pin_token(&pin)?;
+
+	for step in steps {
+		let payload = vendor_config_payload(&pin_token, step.command, int(step.value))?;
+		match transport.send_cbor(CTAPHID_CBOR, &payload) {
+			Err(e) if step.optional => log::debug!("{} failed: {}", step.description, e),
+			result => {
+				result.map_err(|e| ctap_error(e, &step.description))?;
+			}
+		}
+	}
+
+	Ok(
+		"Configuration updated successfully! Unplug and re-plug the device to apply VID/PID changes."
+			.to_string(),
+	)
+}
@@ -1,6 +1,7 @@
 //! Constants, enums, bitflags and data structures for FIDO2 protocol for pico-fido firmware.
 #![allow(unused)]
 
+use crate::{schema::registry::ProtocolConstant, types::SchemaGroup};
 use std::fmt;
 
 #[repr(u8)]
@@ -26,15 +27,38 @@ pub enum U2fCommand {
 	Version = 0x03,
 }
 
-#[repr(u8)]
+/// Vendor commands, valued by the firmware definitions
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum VendorCommand {
-	Backup = 0x01,
-	ManageSecurityEnvironment = 0x02,
-	Unlock = 0x03,
-	EnterpriseAttestation = 0x04,
-	PhysicalOptions = 0x05,
-	Memory = 0x06,
+	Backup,
+	ManageSecurityEnvironment,
+	Unlock,
+	EnterpriseAttestation,
+	PhysicalOptions,
+	Memory,
+}
+
+impl ProtocolConstant for VendorCommand {
+	const GROUP: SchemaGroup = SchemaGroup::VendorCommand;
+	const ALL: &'static [Self] = &[
+		Self::Backup,
+		Self::ManageSecurityEnvironment,
+		Self::Unlock,
+		Self::EnterpriseAttestation,
+		Self::PhysicalOptions,
+		Self::Memory,
+	];
+
+	fn definition(self) -> &'static str {
+		match self {
+			Self::Backup => "CTAP_VENDOR_BACKUP",
+			Self::ManageSecurityEnvironment => "CTAP_VENDOR_MSE",
+			Self::Unlock => "CTAP_VENDOR_UNLOCK",
+			Self::EnterpriseAttestation => "CTAP_VENDOR_EA",
+			Self::PhysicalOptions => "CTAP_VENDOR_PHY_OPTS",
+			Self::Memory => "CTAP_VENDOR_MEMORY",
+		}
+	}
 }
 
 #[repr(u8)]
@@ -54,7 +78,7 @@ pub enum ClientPinSubCommand {
 	GetPinToken = 0x05,
 	GetPinUvAuthTokenUsingUvWithPermissions = 0x06,
 	GetUvRetries = 0x07,
-	GetPinUvAuthTokenUsingPinWithPermissions = 0x08,
+	GetPinUvAuthTokenUsingPinWithPermissions = 0x09,
 }
 
 #[repr(u8)]
@@ -84,6 +108,51 @@ pub enum GetAssertionParam {
 	PinUvAuthProtocol = 0x07,
 }
 
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CredentialMgmtSubCommand {
+	GetCredsMetadata = 0x01,
+	EnumerateRpsBegin = 0x02,
+	EnumerateRpsGetNextRp = 0x03,
+	EnumerateCredentialsBegin = 0x04,
+	EnumerateCredentialsGetNextCredential = 0x05,
+	DeleteCredential = 0x06,
+	UpdateUserInformation = 0x07,
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CredentialMgmtParam {
+	SubCommand = 0x01,
+	SubCommandParams = 0x02,
+	PinUvAuthProtocol = 0x03,
+	PinUvAuthParam = 0x04,
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CredentialMgmtSubParam {
+	RpIdHash = 0x01,
+	CredentialId = 0x02,
+	User = 0x03,
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CredentialMgmtResponse {
+	ExistingResidentCredentialsCount = 0x01,
+	MaxPossibleRemainingResidentCredentialsCount = 0x02,
+	Rp = 0x03,
+	RpIdHash = 0x04,
+	TotalRps = 0x05,
+	User = 0x06,
+	CredentialId = 0x07,
+	PublicKey = 0x08,
+	TotalCredentials = 0x09,
+	CredProtect = 0x0A,
+	LargeBlobKey = 0x0B,
+}
+
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ClientPinParam {
@@ -97,6 +166,16 @@ pub enum ClientPinParam {
 	PermissionsRpId = 0x0A,
 }
 
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientPinResponse {
+	KeyAgreement = 0x01,
+	PinUvAuthToken = 0x02,
+	PinRetries = 0x03,
+	PowerCycleState = 0x04,
+	UvRetries = 0x05,
+}
+
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ConfigParam {
@@ -154,6 +233,7 @@ pub enum VendorConfigCommand {
 }
 
 impl VendorConfigCommand {
+	#[allow(clippy::wrong_self_convention)]
 	pub fn to_u64(&self) -> u64 {
 		match self {
 			Self::AuthEncryptionEnable => 0x03e43f56b34285e2,
@@ -204,6 +284,12 @@ pub enum BackupSubCommand {
 	RestoreEncryptedBackup = 0x02,
 }
 
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackupResponseKey {
+	EncryptedBackup = 0x01,
+}
+
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MseSubCommand {
@@ -216,6 +302,12 @@ pub enum EnterpriseAttestationSubCommand {
 	GenerateCsr = 0x01,
 }
 
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnterpriseAttestationResponseKey {
+	Csr = 0x01,
+}
+
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PhysicalOptionsSubCommand {
@@ -228,14 +320,35 @@ pub enum MemorySubCommand {
 	GetStats = 0x01,
 }
 
-#[repr(u8)]
+/// Keys of the memory stats response, valued by the firmware definitions
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MemoryResponseKey {
-	FreeSpace = 0x01,
-	UsedSpace = 0x02,
-	TotalSpace = 0x03,
-	NumFiles = 0x04,
-	FlashSize = 0x05,
+	FreeSpace,
+	UsedSpace,
+	TotalSpace,
+	NumFiles,
+	FlashSize,
+}
+
+impl ProtocolConstant for MemoryResponseKey {
+	const GROUP: SchemaGroup = SchemaGroup::MemoryResponseKey;
+	const ALL: &'static [Self] = &[
+		Self::FreeSpace,
+		Self::UsedSpace,
+		Self::TotalSpace,
+		Self::NumFiles,
+		Self::FlashSize,
+	];
+
+	fn definition(self) -> &'static str {
+		match self {
+			Self::FreeSpace => "CTAP_VENDOR_MEMORY_FREE",
+			Self::UsedSpace => "CTAP_VENDOR_MEMORY_USED",
+			Self::TotalSpace => "CTAP_VENDOR_MEMORY_TOTAL",
+			Self::NumFiles => "CTAP_VENDOR_MEMORY_FILES",
+			Self::FlashSize => "CTAP_VENDOR_MEMORY_SIZE",
+		}
+	}
 }
 
 bitflags::bitflags! {
@@ -322,6 +435,7 @@ pub enum CoseKeyParam {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Ctap2Error {
 	Success = 0x00,
+	InvalidCommand = 0x01,
 	CborUnexpectedType = 0x11,
 	InvalidCbor = 0x12,
 	MissingParameter = 0x14,
@@ -362,6 +476,16 @@ pub enum Ctap2Error {
 
 pub const CTAP_VENDOR_CBOR_CMD: u8 = 0xC1;
 pub const CTAP_VENDOR_CONFIG_CMD: u8 = 0xC2;
+/// Reboots the device, into BOOTSEL mode if the payload is `[1]`. Not answered on success.
+pub const CTAP_VENDOR_REBOOT_CMD: u8 = 0xC3;
+
+/// FIDO applet, reached over CCID and NFC
+pub const FIDO_AID: &[u8] = &[0xA0, 0x00, 0x00, 0x06, 0x47, 0x2F, 0x00, 0x01];
+pub const NFCCTAP_CLA: u8 = 0x80;
+pub const NFCCTAP_MSG: u8 = 0x10;
+pub const NFCCTAP_GETRESPONSE: u8 = 0x11;
+/// Status word of NFCCTAP_MSG while the authenticator is still processing
+pub const SW_NFCCTAP_STATUS_UPDATE: u16 = 0x9100;
 
 pub const CTAP_APPID_SIZE: usize = 32;
 pub const CTAP_CHAL_SIZE: usize = 32;
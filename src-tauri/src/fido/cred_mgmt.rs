@@ -0,0 +1,281 @@
+//! authenticatorCredentialManagement over the HID transport, signed with the session PIN token.
+//!
+//! ctap_hid_fido2 turns the PIN into a new token for every call, so listing the passkeys and then
+//! deleting one took the PIN twice. These requests take the token `pin_token` keeps instead.
+//! The `_on` variants run over any transport with a token the caller obtained, e.g. over NFC.
+
+use super::{hid::*, pin_token, transport::Transport};
+use crate::{error::PFError, fido::constants::*, types::StoredCredential};
+use anyhow::anyhow;
+use serde_cbor_2::{Value, from_slice, to_vec};
+use std::collections::BTreeMap;
+
+/// Credential management request `sub_command`, signed with `pin_token` unless it continues an
+/// enumeration.
+fn request(
+	transport: &impl Transport,
+	pin_token: Option<&[u8]>,
+	sub_command: CredentialMgmtSubCommand,
+	sub_params: Option<Value>,
+) -> anyhow::Result<BTreeMap<Value, Value>> {
+	let mut params = BTreeMap::new();
+	params.insert(
+		Value::Integer(CredentialMgmtParam::SubCommand as i128),
+		Value::Integer(sub_command as i128),
+	);
+	let sub_params_bytes = match &sub_params {
+		Some(sub_params) => to_vec(sub_params)?,
+		None => Vec::new(),
+	};
+	if let Some(sub_params) = sub_params {
+		params.insert(
+			Value::Integer(CredentialMgmtParam::SubCommandParams as i128),
+			sub_params,
+		);
+	}
+	if let Some(pin_token) = pin_token {
+		// authenticate(pinUvAuthToken, subCommand || subCommandParams)
+		let mut message = vec![sub_command as u8];
+		message.extend(&sub_params_bytes);
+		use ring::hmac;
+		let key = hmac::Key::new(hmac::HMAC_SHA256, pin_token);
+		let pin_auth = hmac::sign(&key, &message).as_ref()[..16].to_vec();
+		params.insert(
+			Value::Integer(CredentialMgmtParam::PinUvAuthProtocol as i128),
+			Value::Integer(1),
+		);
+		params.insert(
+			Value::Integer(CredentialMgmtParam::PinUvAuthParam as i128),
+			Value::Bytes(pin_auth),
+		);
+	}
+
+	let mut payload = vec![CtapCommand::CredentialMgmt as u8];
+	payload.extend(to_vec(&Value::Map(params))?);
+	let res = transport.send_cbor(CTAPHID_CBOR, &payload)?;
+	match from_slice(&res)? {
+		Value::Map(m) => Ok(m),
+		_ => Err(anyhow!("Credential management response is not a CBOR map")),
+	}
+}
+
+fn field(m: &BTreeMap<Value, Value>, key: CredentialMgmtResponse) -> Option<&Value> {
+	m.get(&Value::Integer(key as i128))
+}
+
+fn count(m: &BTreeMap<Value, Value>, key: CredentialMgmtResponse) -> u32 {
+	match field(m, key) {
+		Some(Value::Integer(n)) => *n as u32,
+		_ => 0,
+	}
+}
+
+fn text(m: &BTreeMap<Value, Value>, key: &str) -> String {
+	match m.get(&Value::Text(key.into())) {
+		Some(Value::Text(value)) => value.clone(),
+		_ => String::new(),
+	}
+}
+
+fn bytes(m: &BTreeMap<Value, Value>, key: &str) -> Vec<u8> {
+	match m.get(&Value::Text(key.into())) {
+		Some(Value::Bytes(value)) => value.clone(),
+		_ => Vec::new(),
+	}
+}
+
+fn no_credentials(e: &anyhow::Error) -> bool {
+	e.downcast_ref::<CtapStatus>()
+		.is_some_and(|CtapStatus(status)| *status == Ctap2Error::NoCredentials as u8)
+}
+
+fn descriptor(credential_id: &[u8]) -> Value {
+	Value::Map(BTreeMap::from([
+		(
+			Value::Text("id".into()),
+			Value::Bytes(credential_id.to_vec()),
+		),
+		(Value::Text("type".into()), Value::Text("public-key".into())),
+	]))
+}
+
+/// Passkey slot usage, as (existing, max remaining) resident credentials
+pub fn creds_metadata(device: &str, pin: Option<&str>) -> Result<(u32, u32), PFError> {
+	pin_token::with_token(device, pin, "credential metadata read", |token| {
+		metadata_on(&HidTransport::open_path(Some(device))?, token)
+	})
+}
+
+/// Passkey slot usage over `transport`, with the PIN token `token`
+pub fn metadata_on(transport: &impl Transport, token: &[u8]) -> anyhow::Result<(u32, u32)> {
+	let m = request(
+		transport,
+		Some(token),
+		CredentialMgmtSubCommand::GetCredsMetadata,
+		None,
+	)?;
+	Ok((
+		count(&m, CredentialMgmtResponse::ExistingResidentCredentialsCount),
+		count(
+			&m,
+			CredentialMgmtResponse::MaxPossibleRemainingResidentCredentialsCount,
+		),
+	))
+}
+
+/// Every resident credential, by relying party
+pub fn enumerate(device: &str, pin: Option<&str>) -> Result<Vec<StoredCredential>, PFError> {
+	pin_token::with_token(device, pin, "credential enumeration", |token| {
+		enumerate_on(&HidTransport::open_path(Some(device))?, token)
+	})
+}
+
+/// Every resident credential over `transport`, with the PIN token `token`
+pub fn enumerate_on(
+	transport: &impl Transport,
+	token: &[u8],
+) -> anyhow::Result<Vec<StoredCredential>> {
+	let first = match request(
+		transport,
+		Some(token),
+		CredentialMgmtSubCommand::EnumerateRpsBegin,
+		None,
+	) {
+		Ok(m) => m,
+		Err(e) if no_credentials(&e) => {
+			log::info!("No credentials stored on device (CTAP2_ERR_NO_CREDENTIALS)");
+			return Ok(Vec::new());
+		}
+		Err(e) => return Err(e),
+	};
+
+	let total_rps = count(&first, CredentialMgmtResponse::TotalRps);
+	let mut rps = vec![first];
+	for _ in 1..total_rps {
+		rps.push(request(
+			transport,
+			None,
+			CredentialMgmtSubCommand::EnumerateRpsGetNextRp,
+			None,
+		)?);
+	}
+
+	let mut credentials = Vec::new();
+	for rp in rps {
+		let (Some(Value::Map(entity)), Some(Value::Bytes(rp_id_hash))) = (
+			field(&rp, CredentialMgmtResponse::Rp),
+			field(&rp, CredentialMgmtResponse::RpIdHash),
+		) else {
+			return Err(anyhow!("Relying party missing from the response"));
+		};
+		let rp_id = text(entity, "id");
+		let rp_name = text(entity, "name");
+
+		let params = Value::Map(BTreeMap::from([(
+			Value::Integer(CredentialMgmtSubParam::RpIdHash as i128),
+			Value::Bytes(rp_id_hash.clone()),
+		)]));
+		let first = request(
+			transport,
+			Some(token),
+			CredentialMgmtSubCommand::EnumerateCredentialsBegin,
+			Some(params),
+		)?;
+		let total = count(&first, CredentialMgmtResponse::TotalCredentials);
+		let mut entries = vec![first];
+		for _ in 1..total {
+			entries.push(request(
+				transport,
+				None,
+				CredentialMgmtSubCommand::EnumerateCredentialsGetNextCredential,
+				None,
+			)?);
+		}
+
+		for entry in entries {
+			let (Some(Value::Map(user)), Some(Value::Map(credential))) = (
+				field(&entry, CredentialMgmtResponse::User),
+				field(&entry, CredentialMgmtResponse::CredentialId),
+			) else {
+				return Err(anyhow!("Credential missing from the response"));
+			};
+			credentials.push(StoredCredential {
+				credential_id: hex::encode(bytes(credential, "id")),
+				rp_id: rp_id.clone(),
+				rp_name: rp_name.clone(),
+				user_name: text(user, "name"),
+				user_display_name: text(user, "displayName"),
+				user_id: hex::encode(bytes(user, "id")),
+				rp_display_name: None,
+				account_url: None,
+				cred_protect: match field(&entry, CredentialMgmtResponse::CredProtect) {
+					Some(Value::Integer(level)) => u8::try_from(*level).ok(),
+					_ => None,
+				},
+				algorithm: match field(&entry, CredentialMgmtResponse::PublicKey) {
+					Some(Value::Map(key)) => {
+						match key.get(&Value::Integer(CoseKeyParam::Alg as i128)) {
+							Some(Value::Integer(alg)) => i64::try_from(*alg).ok(),
+							_ => None,
+						}
+					}
+					_ => None,
+				},
+			});
+		}
+	}
+	Ok(credentials)
+}
+
+pub fn delete(device: &str, pin: Option<&str>, credential_id: &[u8]) -> Result<(), PFError> {
+	pin_token::with_token(device, pin, "credential deletion", |token| {
+		let transport = HidTransport::open_path(Some(device))?;
+		let params = Value::Map(BTreeMap::from([(
+			Value::Integer(CredentialMgmtSubParam::CredentialId as i128),
+			descriptor(credential_id),
+		)]));
+		request(
+			&transport,
+			Some(token),
+			CredentialMgmtSubCommand::DeleteCredential,
+			Some(params),
+		)?;
+		Ok(())
+	})
+}
+
+/// Replaces the user names of a credential. `user_id` must be the one stored with it.
+pub fn update_user(
+	device: &str,
+	pin: Option<&str>,
+	credential_id: &[u8],
+	user_id: &[u8],
+	user_name: &str,
+	user_display_name: &str,
+) -> Result<(), PFError> {
+	pin_token::with_token(device, pin, "credential update", |token| {
+		let transport = HidTransport::open_path(Some(device))?;
+		let user = Value::Map(BTreeMap::from([
+			(Value::Text("id".into()), Value::Bytes(user_id.to_vec())),
+			(Value::Text("name".into()), Value::Text(user_name.into())),
+			(
+				Value::Text("displayName".into()),
+				Value::Text(user_display_name.into()),
+			),
+		]));
+		let params = Value::Map(BTreeMap::from([
+			(
+				Value::Integer(CredentialMgmtSubParam::CredentialId as i128),
+				descriptor(credential_id),
+			),
+			(Value::Integer(CredentialMgmtSubParam::User as i128), user),
+		]));
+		request(
+			&transport,
+			Some(token),
+			CredentialMgmtSubCommand::UpdateUserInformation,
+			Some(params),
+		)?;
+		Ok(())
+	})
+}
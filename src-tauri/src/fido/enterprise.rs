@@ -0,0 +1,104 @@
+//! Enterprise attestation, letting an organization's relying parties identify its own keys.
+//!
+//! The attestation key is generated on the device and never leaves it: commissioning reads a
+//! certificate signing request, has it signed by the organization's CA and uploads the resulting
+//! certificate. Enterprise attestation is then enabled through authenticatorConfig. CTAP 2.1 has
+//! no command to disable it again, only a reset of the FIDO application does.
+
+use super::{
+	config_pin_token, ctap_error, get_fido_info, hid::HidTransport, transport::Transport, vendor,
+};
+use crate::{
+	error::PFError,
+	fido::constants::*,
+	hsm::x509::{certificate_der, pem_block},
+	operation::report_phase,
+	types::{EnterpriseAttestationCsr, EnterpriseAttestationStatus, PhaseVerb, UserAction},
+};
+use serde_cbor_2::{Value, from_slice};
+use x509_parser::prelude::*;
+
+/// Reads whether the device supports enterprise attestation and whether it is enabled
+pub fn status(device_path: Option<&str>) -> Result<EnterpriseAttestationStatus, PFError> {
+	let info = get_fido_info(device_path)?;
+	// "ep" is absent if unsupported, false while supported but disabled
+	let ep = info.options.get("ep").copied();
+	Ok(EnterpriseAttestationStatus {
+		supported: ep.is_some(),
+		enabled: ep == Some(true),
+	})
+}
+
+/// Enables enterprise attestation, authorized by the PIN
+pub fn enable(device_path: Option<&str>, pin: Option<&str>) -> Result<(), PFError> {
+	log::info!("Enabling enterprise attestation...");
+	if !status(device_path)?.supported {
+		return Err(PFError::Device(
+			"The device does not support enterprise attestation".into(),
+		));
+	}
+	let pin_token = config_pin_token(device_path, pin)?;
+	let transport =
+		HidTransport::open_path(device_path).map_err(|e| ctap_error(e, "HID connection"))?;
+	report_phase(
+		PhaseVerb::Write,
+		"enterprise attestation setting",
+		UserAction::None,
+	);
+	transport.send_config(
+		&pin_token,
+		ConfigSubCommand::EnableEnterpriseAttestation,
+		None,
+	)
+}
+
+/// Certificate signing request for the attestation key of the device, to be signed by the CA of
+/// the organization
+pub fn generate_csr(device_path: Option<&str>) -> Result<EnterpriseAttestationCsr, PFError> {
+	log::info!("Requesting the enterprise attestation CSR...");
+	let transport =
+		HidTransport::open_path(device_path).map_err(|e| ctap_error(e, "HID connection"))?;
+	report_phase(
+		PhaseVerb::Create,
+		"enterprise attestation CSR",
+		UserAction::None,
+	);
+	let der = vendor::enterprise_attestation_csr(&transport)?;
+
+	Ok(EnterpriseAttestationCsr {
+		pem: pem_block("CERTIFICATE REQUEST", &der),
+		der,
+	})
+}
+
+/// Uploads the DER or PEM attestation certificate issued for the CSR of the device, returning its
+/// subject
+pub fn upload_certificate(
+	device_path: Option<&str>,
+	pin: Option<&str>,
+	certificate: &[u8],
+) -> Result<String, PFError> {
+	let der = certificate_der(certificate)?;
+	let subject = X509Certificate::from_der(&der)
+		.map(|(_, cert)| cert.subject().to_string())
+		.map_err(|e| PFError::Io(format!("Invalid X.509 certificate: {}", e)))?;
+	log::info!(
+		"Uploading enterprise attestation certificate of {}...",
+		subject
+	);
+
+	let pin_token = config_pin_token(device_path, pin)?;
+	let transport =
+		HidTransport::open_path(device_path).map_err(|e| ctap_error(e, "HID connection"))?;
+	report_phase(
+		PhaseVerb::Write,
+		"enterprise attestation certificate",
+		UserAction::None,
+	);
+	transport.send_vendor_config(
+		&pin_token,
+		VendorConfigCommand::EnterpriseAttestationUpload,
+		Value::Bytes(der),
+	)?;
+	Ok(subject)
+}
@@ -4,30 +4,146 @@ use anyhow::{Result, anyhow};
 use rand::Rng;
 use serde_cbor_2::{Value, to_vec};
 use std::collections::BTreeMap;
-use std::time::Duration;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
+use super::transport::Transport;
 use crate::error::PFError;
 use crate::fido::constants::*;
+use crate::operation::{cancel_requested, report_keepalive, report_phase};
+use crate::schema::registry::ProtocolConstant;
+use crate::types::{FidoHidDevice, KeepaliveStatus, PhaseVerb, UserAction};
 
 // HID Transport Constants
 const HID_REPORT_SIZE: usize = 64;
 const HID_USAGE_PAGE_FIDO: u16 = 0xF1D0;
 const CTAPHID_CID_BROADCAST: u32 = 0xFFFFFFFF;
 const CTAPHID_INIT: u8 = 0x86;
+/// Makes the key blink its LED, the way it signals a pending touch
+const CTAPHID_WINK: u8 = 0x88;
+/// Aborts the request pending on the channel, answered with CTAP2_ERR_KEEPALIVE_CANCEL
+const CTAPHID_CANCEL: u8 = 0x91;
 pub const CTAPHID_CBOR: u8 = 0x90;
 const CTAPHID_ERROR: u8 = 0xBF;
 const CTAPHID_KEEPALIVE: u8 = 0xBB;
+/// Keepalive status: the authenticator is still processing the request
+pub const KEEPALIVE_STATUS_PROCESSING: u8 = 0x01;
+/// Keepalive status: the authenticator waits for user presence
+pub const KEEPALIVE_STATUS_UPNEEDED: u8 = 0x02;
+
+// CTAPHID_ERROR codes
+pub const ERR_INVALID_CMD: u8 = 0x01;
+const ERR_INVALID_SEQ: u8 = 0x04;
+const ERR_MSG_TIMEOUT: u8 = 0x05;
+const ERR_CHANNEL_BUSY: u8 = 0x06;
+const ERR_INVALID_CHANNEL: u8 = 0x0B;
+/// Errors after which the request is sent again, it never reached the authenticator
+const RETRYABLE_ERRORS: &[u8] = &[ERR_INVALID_SEQ, ERR_MSG_TIMEOUT, ERR_CHANNEL_BUSY];
+const MAX_ATTEMPTS: u32 = 3;
+/// Wait before the second attempt, growing with each one
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Longest a command may take by default. Above the 30 seconds the firmware waits for a touch,
+/// so the firmware reports that timeout itself.
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(45);
+/// Longest the key may stay silent. While busy it sends a keepalive every 100 ms.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Non-zero CTAP status byte returned by the authenticator
+#[derive(Debug, thiserror::Error)]
+#[error("FIDO Operation Failed with Status: 0x{0:02X}")]
+pub struct CtapStatus(pub u8);
+
+/// Whether `name` is a USB topology path like "1-4.2", bus number then the port chain
+fn is_port_path(name: &str) -> bool {
+	let Some((bus, ports)) = name.split_once('-') else {
+		return false;
+	};
+	let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+	is_number(bus) && ports.split('.').all(is_number)
+}
+
+/// USB topology path (bus and port chain, e.g. "1-4.2") of the HID device at `hid_path`.
+///
+/// Unlike the HID path, it stays the same when a key is re-plugged into the same port. Only
+/// available where the platform exposes it: the libusb backend uses it as the HID path, and on
+/// Linux it is part of the sysfs path of the hidraw node.
+pub fn usb_port_path(hid_path: &str) -> Option<String> {
+	// libusb backend: "<bus>-<ports>:<config>.<interface>"
+	if let Some((port, _)) = hid_path.split_once(':')
+		&& is_port_path(port)
+	{
+		return Some(port.to_string());
+	}
+
+	let node = hid_path.strip_prefix("/dev/")?;
+	let sysfs = std::fs::canonicalize(format!("/sys/class/{}/device", node)).ok()?;
+	sysfs
+		.iter()
+		.rev()
+		.filter_map(|component| component.to_str())
+		.find(|component| is_port_path(component))
+		.map(str::to_string)
+}
+
+/// FIDO interfaces known to `api`
+pub fn enumerate_devices(api: &hidapi::HidApi) -> Vec<FidoHidDevice> {
+	api.device_list()
+		.filter(|d| d.usage_page() == HID_USAGE_PAGE_FIDO)
+		.map(|d| {
+			let path = d.path().to_string_lossy().to_string();
+			FidoHidDevice {
+				port_path: usb_port_path(&path),
+				path,
+				vid: format!("{:04X}", d.vendor_id()),
+				pid: format!("{:04X}", d.product_id()),
+				product_name: d.product_string().unwrap_or_default().to_string(),
+			}
+		})
+		.collect()
+}
+
+/// Channel allocated on each device, by HID path. Transports opened later on the same device
+/// resynchronize it instead of allocating another, the firmware only has a few.
+static CHANNELS: Mutex<BTreeMap<String, u32>> = Mutex::new(BTreeMap::new());
+
+/// Forgets the channel allocated on the device at `path`, e.g. once it was unplugged
+pub fn forget_channel(path: &str) {
+	if let Ok(mut channels) = CHANNELS.lock() {
+		channels.remove(path);
+	}
+}
+
+/// CTAPHID_ERROR code returned by the transport layer, not the authenticator
+#[derive(Debug, thiserror::Error)]
+#[error("CTAPHID error 0x{0:02X}")]
+pub struct ChannelError(pub u8);
+
+fn timed_out(message: &str) -> anyhow::Error {
+	std::io::Error::new(std::io::ErrorKind::TimedOut, message.to_string()).into()
+}
 
 pub struct HidTransport {
 	device: hidapi::HidDevice,
 	cid: u32,
+	/// Longest a command may take, keepalives included
+	timeout: Duration,
 	pub vid: u16,
 	pub pid: u16,
 	pub product_name: String,
+	/// USB serial number string, empty on platforms that do not expose it
+	pub serial_number: String,
+	/// Platform path of the HID device, changes when the key is re-plugged
+	pub path: String,
 }
 
 impl HidTransport {
 	pub fn open() -> Result<Self> {
+		Self::open_path(None)
+	}
+
+	/// Opens the FIDO device at the HID path `device_path`, or the first one found.
+	pub fn open_path(device_path: Option<&str>) -> Result<Self> {
 		log::info!("Attempting to open HID transport for FIDO device...");
 		let api = hidapi::HidApi::new().map_err(|e| {
 			log::error!("Failed to initialize HidApi: {}", e);
@@ -37,7 +153,8 @@ impl HidTransport {
 		// Find device with FIDO Usage Page (0xF1D0)
 		let info = api
 			.device_list()
-			.find(|d| d.usage_page() == HID_USAGE_PAGE_FIDO)
+			.filter(|d| d.usage_page() == HID_USAGE_PAGE_FIDO)
+			.find(|d| device_path.is_none_or(|path| d.path().to_string_lossy() == path))
 			.ok_or_else(|| {
 				log::warn!("No FIDO device found with Usage Page 0xF1D0.");
 				PFError::NoDevice
@@ -55,30 +172,66 @@ impl HidTransport {
 			.product_string()
 			.unwrap_or("Unknown FIDO Device")
 			.to_string();
+		let serial_number = info.serial_number().unwrap_or_default().trim().to_string();
+
+		let path = info.path().to_string_lossy().to_string();
 
 		let device = info.open_device(&api).map_err(|e| {
 			log::error!("Failed to open HID device: {}", e);
 			e
 		})?;
 
-		// Negotiate Channel ID (CID)
-		let cid = Self::init_channel(&device).map_err(|e| {
-			log::error!("Failed to negotiate Channel ID: {}", e);
-			e
-		})?;
+		// Reuse the channel of the device if it still has one, else negotiate a Channel ID (CID)
+		let cached = CHANNELS
+			.lock()
+			.ok()
+			.and_then(|channels| channels.get(&path).copied());
+		let cid = match cached.map(|cid| (cid, Self::init_channel(&device, cid))) {
+			Some((_, Ok(cid))) => {
+				log::debug!("Resynchronized channel 0x{:08X}", cid);
+				cid
+			}
+			resumed => {
+				if let Some((cid, Err(e))) = resumed {
+					log::debug!("Channel 0x{:08X} is gone, allocating another: {}", cid, e);
+				}
+				Self::init_channel(&device, CTAPHID_CID_BROADCAST).map_err(|e| {
+					log::error!("Failed to negotiate Channel ID: {}", e);
+					e
+				})?
+			}
+		};
+		if let Ok(mut channels) = CHANNELS.lock() {
+			channels.insert(path.clone(), cid);
+		}
 
 		log::info!("HID Transport established successfully. CID: 0x{:08X}", cid);
 		Ok(Self {
 			device,
 			cid,
+			timeout: DEFAULT_COMMAND_TIMEOUT,
 			vid,
 			pid,
 			product_name,
+			serial_number,
+			path,
 		})
 	}
 
-	fn init_channel(device: &hidapi::HidDevice) -> Result<u32> {
-		log::debug!("Initializing CTAPHID channel...");
+	/// Sets the longest a command may take, for commands waiting on the user longer than usual
+	pub fn with_timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = timeout;
+		self
+	}
+
+	pub fn set_timeout(&mut self, timeout: Duration) {
+		self.timeout = timeout;
+	}
+
+	/// Sends CTAPHID_INIT on `cid`. On the broadcast channel this allocates a new channel, on an
+	/// allocated one it aborts whatever was pending and keeps the channel.
+	fn init_channel(device: &hidapi::HidDevice, cid: u32) -> Result<u32> {
+		log::debug!("Initializing CTAPHID channel 0x{:08X}...", cid);
 
 		// --- Drain Step ---
 		// Read and discard any pending packets to avoid using a stale response for CID negotiation.
@@ -95,47 +248,63 @@ impl HidTransport {
 
 		// Construct Init Packet: [CID(4) | CMD(1) | LEN(2) | NONCE(8)]
 		let mut report = [0u8; HID_REPORT_SIZE + 1]; // +1 for Report ID (always 0)
-		report[1..5].copy_from_slice(&CTAPHID_CID_BROADCAST.to_be_bytes());
+		report[1..5].copy_from_slice(&cid.to_be_bytes());
 		report[5] = CTAPHID_INIT;
 		report[6] = 0; // Len MSB
 		report[7] = 8; // Len LSB
 		report[8..16].copy_from_slice(&nonce);
 
-		log::trace!("Sending CTAPHID_INIT broadcast with nonce: {:02X?}", nonce);
+		log::trace!("Sending CTAPHID_INIT with nonce: {:02X?}", nonce);
 		device.write(&report[..]).map_err(|e| {
 			log::error!("Failed to write INIT packet: {}", e);
 			e
 		})?;
 
 		// Read Response until we find our nonce
-		let start = std::time::Instant::now();
+		let start = Instant::now();
 		while start.elapsed() < Duration::from_secs(1) {
 			let mut buf = [0u8; HID_REPORT_SIZE];
-			if device.read_timeout(&mut buf[..], 100).is_ok() {
-				// Check if response matches our broadcast and nonce
-				if buf[0..4] == CTAPHID_CID_BROADCAST.to_be_bytes()
-					&& buf[4] == CTAPHID_INIT
-					&& buf[7..15] == nonce
-				{
-					// New CID is at bytes 16..20
-					let new_cid = u32::from_be_bytes([buf[15], buf[16], buf[17], buf[18]]);
-					log::debug!("Channel negotiation successful. New CID: 0x{:08X}", new_cid);
-					return Ok(new_cid);
-				}
+			if !matches!(device.read_timeout(&mut buf[..], 100), Ok(n) if n > 0)
+				|| buf[0..4] != cid.to_be_bytes()
+			{
+				continue;
+			}
+			if buf[4] == CTAPHID_ERROR {
+				return Err(ChannelError(buf[7]).into());
+			}
+			if buf[4] == CTAPHID_INIT && buf[7..15] == nonce {
+				// New CID is at bytes 16..20
+				let new_cid = u32::from_be_bytes([buf[15], buf[16], buf[17], buf[18]]);
+				log::debug!("Channel negotiation successful. CID: 0x{:08X}", new_cid);
+				return Ok(new_cid);
 			}
 		}
 		log::error!("Timeout waiting for CTAPHID_INIT response.");
 		Err(anyhow!("Timeout waiting for FIDO Init response"))
 	}
 
-	pub fn send_cbor(&self, cmd: u8, payload: &[u8]) -> Result<Vec<u8>> {
-		log::debug!(
-			"Sending CBOR Command: 0x{:02X}, Payload Size: {} bytes",
-			cmd,
-			payload.len()
-		);
+	/// Reads the next packet on the channel, `None` if none arrives within `timeout`
+	fn read_packet(&self, timeout: Duration) -> Result<Option<[u8; HID_REPORT_SIZE]>> {
+		let deadline = Instant::now() + timeout;
+		let mut buf = [0u8; HID_REPORT_SIZE];
+		loop {
+			let left = deadline.saturating_duration_since(Instant::now());
+			if left.is_zero() {
+				return Ok(None);
+			}
+			let millis = left.as_millis().clamp(1, i32::MAX as u128) as i32;
+			if self.device.read_timeout(&mut buf[..], millis)? == 0 {
+				continue;
+			}
+			if u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) != self.cid {
+				log::trace!("Received packet from different CID, ignoring...");
+				continue;
+			}
+			return Ok(Some(buf));
+		}
+	}
 
-		// --- Transmit ---
+	fn write_message(&self, cmd: u8, payload: &[u8]) -> Result<()> {
 		let mut sequence = 0u8;
 		let total_len = payload.len();
 		let mut sent = 0;
@@ -151,7 +320,6 @@ impl HidTransport {
 		report[8..8 + to_copy].copy_from_slice(&payload[0..to_copy]);
 		sent += to_copy;
 
-		// log::trace!("Writing Init Packet (Sent: {}/{})", sent, total_len);
 		if let Err(e) = self.device.write(&report[..]) {
 			log::error!("Failed to write initial HID packet: {}", e);
 			return Err(e.into());
@@ -168,7 +336,6 @@ impl HidTransport {
 			report[6..6 + to_copy].copy_from_slice(&payload[sent..sent + to_copy]);
 			sent += to_copy;
 
-			// log::trace!("Writing Cont Packet Seq {} (Sent: {}/{})", sequence - 1, sent, total_len);
 			if let Err(e) = self.device.write(&report[..]) {
 				log::error!(
 					"Failed to write continuation HID packet (Seq {}): {}",
@@ -178,57 +345,64 @@ impl HidTransport {
 				return Err(e.into());
 			}
 		}
+		Ok(())
+	}
 
-		// --- Receive ---
-		let mut response_data = Vec::new();
-		let mut expected_len = 0;
-		let mut read_len = 0;
-		let mut last_seq = 0;
-
+	/// Reads the response to `cmd`, following keepalives until the command timeout
+	fn read_message(&self, cmd: u8) -> Result<Vec<u8>> {
 		log::debug!("Waiting for response...");
-
-		// Read First Packet (Loop to handle Keepalives)
-		let mut buf = [0u8; HID_REPORT_SIZE];
-
-		let mut buf = [0u8; HID_REPORT_SIZE];
-		loop {
-			if let Err(e) = self.device.read_timeout(&mut buf[..], 2000) {
-				log::error!("Timeout reading response packet: {}", e);
-				return Err(e.into());
+		let deadline = Instant::now() + self.timeout;
+		let mut status = None;
+		let mut cancel_sent = false;
+
+		// 1. Read First Packet (Loop to handle Keepalives)
+		let buf = loop {
+			let wait = RESPONSE_TIMEOUT.min(deadline.saturating_duration_since(Instant::now()));
+			let Some(buf) = self.read_packet(wait)? else {
+				if Instant::now() < deadline {
+					log::error!("No response or keepalive within {:?}", RESPONSE_TIMEOUT);
+					return Err(timed_out("The key stopped responding"));
+				}
+				log::error!("Command 0x{:02X} timed out after {:?}", cmd, self.timeout);
+				if !cancel_sent && let Err(e) = self.send_cancel() {
+					log::warn!("Failed to cancel the timed out command: {}", e);
+				}
+				return Err(match status {
+					Some(KEEPALIVE_STATUS_UPNEEDED) => {
+						CtapStatus(Ctap2Error::UserActionTimeout as u8).into()
+					}
+					_ => timed_out("The key did not answer in time"),
+				});
+			};
+
+			if buf[4] != CTAPHID_KEEPALIVE {
+				break buf;
 			}
-
-			// Check CID mismatch
-			if u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) != self.cid {
-				log::warn!("Received packet from different CID, ignoring...");
-				continue;
+			let keepalive = buf[7];
+			if status != Some(keepalive) {
+				log::debug!("Device sent KEEPALIVE (Status: 0x{:02X})", keepalive);
+				status = Some(keepalive);
+				match keepalive {
+					KEEPALIVE_STATUS_PROCESSING => report_keepalive(KeepaliveStatus::Processing),
+					KEEPALIVE_STATUS_UPNEEDED => {
+						report_keepalive(KeepaliveStatus::UserPresenceNeeded);
+						report_phase(PhaseVerb::Verify, "your presence", UserAction::TouchDevice);
+					}
+					_ => {}
+				}
 			}
-
-			// Check for KEEPALIVE (0xBB)
-			if buf[4] == CTAPHID_KEEPALIVE {
-				let status = buf[5]; // Keepalive status byte
-				log::debug!(
-					"Device sent KEEPALIVE (Status: 0x{:02X}), waiting...",
-					status
-				);
-				continue; // Go back to start of loop and read again
+			// Keepalives keep coming while the key waits, so a cancellation is seen quickly
+			if !cancel_sent && cancel_requested() {
+				cancel_sent = true;
+				self.send_cancel()?;
 			}
-
-			// If we are here, it's a real response
-			break;
-		}
+		};
 
 		if buf[4] == CTAPHID_ERROR {
-			log::error!("Device returned CTAP Error code: 0x{:02X}", buf[5]);
-			return Err(anyhow!("Device returned CTAP Error: 0x{:02X}", buf[5]));
+			log::error!("Device returned CTAPHID error code: 0x{:02X}", buf[7]);
+			return Err(ChannelError(buf[7]).into());
 		}
-
-		if buf[4] == cmd {
-			expected_len = u16::from_be_bytes([buf[5], buf[6]]) as usize;
-			let in_pkt = std::cmp::min(expected_len, HID_REPORT_SIZE - 7);
-			response_data.extend_from_slice(&buf[7..7 + in_pkt]);
-			read_len += in_pkt;
-			// log::trace!("Received Init Response. Expecting {} bytes total.", expected_len);
-		} else {
+		if buf[4] != cmd {
 			log::error!(
 				"Unexpected command response: 0x{:02X} (Expected 0x{:02X})",
 				buf[4],
@@ -241,16 +415,17 @@ impl HidTransport {
 			));
 		}
 
-		// 2. Read Continuation Packets
-		while read_len < expected_len {
-			if let Err(e) = self.device.read_timeout(&mut buf[..], 500) {
-				log::error!("Timeout reading continuation packet: {}", e);
-				return Err(e.into());
-			}
+		let expected_len = u16::from_be_bytes([buf[5], buf[6]]) as usize;
+		let in_pkt = std::cmp::min(expected_len, HID_REPORT_SIZE - 7);
+		let mut response_data = buf[7..7 + in_pkt].to_vec();
 
-			if u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) != self.cid {
-				continue; // Ignore packets from other channels
-			}
+		// 2. Read Continuation Packets
+		let mut last_seq = 0;
+		while response_data.len() < expected_len {
+			let Some(buf) = self.read_packet(RESPONSE_TIMEOUT)? else {
+				log::error!("Timeout reading continuation packet");
+				return Err(timed_out("The response of the key was cut short"));
+			};
 
 			let seq = buf[4];
 			if seq != last_seq {
@@ -263,10 +438,33 @@ impl HidTransport {
 			}
 			last_seq += 1;
 
-			let in_pkt = std::cmp::min(expected_len - read_len, HID_REPORT_SIZE - 5);
+			let in_pkt = std::cmp::min(expected_len - response_data.len(), HID_REPORT_SIZE - 5);
 			response_data.extend_from_slice(&buf[5..5 + in_pkt]);
-			read_len += in_pkt;
 		}
+		Ok(response_data)
+	}
+
+	/// Sends a CTAPHID command and returns the payload of the response without its status byte.
+	///
+	/// Requests the key could not take, because the channel was busy or a packet got lost, are
+	/// sent again a few times. Gives up after the command timeout, cancelling the request.
+	pub fn send_cbor(&self, cmd: u8, payload: &[u8]) -> Result<Vec<u8>> {
+		log::debug!(
+			"Sending CBOR Command: 0x{:02X}, Payload Size: {} bytes",
+			cmd,
+			payload.len()
+		);
+
+		log::trace!(
+			"CTAP request 0x{:02X}: {}",
+			cmd,
+			trace_payload(payload, payload)
+		);
+
+		let response_data = send_with_retries(&self.path, cmd, || {
+			self.write_message(cmd, payload)?;
+			self.read_message(cmd)
+		})?;
 
 		// 3. Check CTAP Status Byte (First byte of payload)
 		if response_data.is_empty() {
@@ -276,10 +474,7 @@ impl HidTransport {
 		let status = response_data[0];
 		if status != 0x00 {
 			log::error!("FIDO Operation returned failure status: 0x{:02X}", status);
-			return Err(anyhow!(
-				"FIDO Operation Failed with Status: 0x{:02X}",
-				status
-			));
+			return Err(CtapStatus(status).into());
 		}
 
 		log::debug!(
@@ -287,179 +482,391 @@ impl HidTransport {
 			cmd,
 			response_data.len() - 1
 		);
+		log::trace!(
+			"CTAP response 0x{:02X}: {}",
+			cmd,
+			trace_payload(payload, &response_data[1..])
+		);
 		// Return payload without status byte
 		Ok(response_data[1..].to_vec())
 	}
 
-	pub fn send_vendor_config(
-		&self,
-		pin_token: &[u8],
-		vendor_cmd: VendorConfigCommand,
-		param: Value,
-	) -> Result<(), PFError> {
-		log::debug!("Sending vendor config command: {}...", vendor_cmd);
-
-		// Build subCommandParams (Key 0x02)
-		// This map contains:
-		// 0x01: vendorCommandId (u64)
-		// 0x02/0x03/0x04: param
-		let mut sub_params_inner = BTreeMap::new();
-		sub_params_inner.insert(
-			Value::Integer(0x01),
-			Value::Integer(vendor_cmd.to_u64() as i128),
-		);
+	/// Asks the firmware to reboot, into BOOTSEL mode if `bootsel`.
+	///
+	/// The device drops off the bus without answering, so only an error reply within a short
+	/// time counts as failure.
+	pub fn send_reboot(&self, bootsel: bool) -> Result<()> {
+		let mut report = [0u8; HID_REPORT_SIZE + 1];
+		report[1..5].copy_from_slice(&self.cid.to_be_bytes());
+		report[5] = CTAP_VENDOR_REBOOT_CMD;
+		report[7] = 1; // Len LSB
+		report[8] = bootsel as u8;
+		self.device.write(&report[..])?;
 
-		match param {
-			Value::Bytes(_) => {
-				sub_params_inner.insert(Value::Integer(0x02), param.clone());
+		let mut buf = [0u8; HID_REPORT_SIZE];
+		match self.device.read_timeout(&mut buf[..], 500) {
+			Ok(n)
+				if n > 5
+					&& u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) == self.cid
+					&& buf[4] == CTAPHID_ERROR =>
+			{
+				Err(anyhow!(
+					"Device rejected the reboot command: 0x{:02X}",
+					buf[7]
+				))
 			}
-			Value::Integer(_) => {
-				sub_params_inner.insert(Value::Integer(0x03), param.clone());
+			// Nothing within the timeout, or the device already went away
+			_ => Ok(()),
+		}
+	}
+
+	/// Asks the key to abort the request pending on the channel (CTAPHID_CANCEL). The key still
+	/// answers the request, with CTAP2_ERR_KEEPALIVE_CANCEL.
+	fn send_cancel(&self) -> Result<()> {
+		log::info!("Sending CTAPHID_CANCEL");
+		let mut report = [0u8; HID_REPORT_SIZE + 1];
+		report[1..5].copy_from_slice(&self.cid.to_be_bytes());
+		report[5] = CTAPHID_CANCEL;
+		self.device.write(&report[..])?;
+		Ok(())
+	}
+
+	/// Asks the key to blink its LED (CTAPHID_WINK), with its current LED configuration.
+	///
+	/// Keys built without wink support answer with ERR_INVALID_CMD, returned as a `ChannelError`.
+	pub fn send_wink(&self) -> Result<()> {
+		log::debug!("Sending CTAPHID_WINK");
+		let mut report = [0u8; HID_REPORT_SIZE + 1];
+		report[1..5].copy_from_slice(&self.cid.to_be_bytes());
+		report[5] = CTAPHID_WINK;
+		self.device.write(&report[..])?;
+
+		let mut buf = [0u8; HID_REPORT_SIZE];
+		loop {
+			if self.device.read_timeout(&mut buf[..], 1000)? == 0 {
+				return Err(anyhow!("Timeout waiting for the wink response"));
 			}
-			Value::Text(_) => {
-				sub_params_inner.insert(Value::Integer(0x04), param.clone());
+			if u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) != self.cid {
+				continue;
 			}
-			_ => return Err(PFError::Io("Unsupported parameter type".into())),
+			return match buf[4] {
+				CTAPHID_WINK => Ok(()),
+				CTAPHID_ERROR => Err(ChannelError(buf[7]).into()),
+				cmd => Err(anyhow!("Unexpected wink response: 0x{:02X}", cmd)),
+			};
 		}
+	}
+}
 
-		let sub_params = Value::Map(sub_params_inner);
-		let sub_params_bytes = to_vec(&sub_params).map_err(|e| PFError::Io(e.to_string()))?;
+/// Runs `exchange`, writing a request and reading its response, again while the key rejects the
+/// request with a CTAPHID error meaning it never reached the authenticator. Forgets the channel
+/// of the device at `path` once the key no longer knows it.
+fn send_with_retries(
+	path: &str,
+	cmd: u8,
+	mut exchange: impl FnMut() -> Result<Vec<u8>>,
+) -> Result<Vec<u8>> {
+	let mut attempt = 1;
+	loop {
+		let e = match exchange() {
+			Ok(response) => return Ok(response),
+			Err(e) => e,
+		};
+		match e.downcast_ref::<ChannelError>() {
+			Some(ChannelError(code))
+				if RETRYABLE_ERRORS.contains(code) && attempt < MAX_ATTEMPTS =>
+			{
+				log::warn!(
+					"Command 0x{:02X} rejected with CTAPHID error 0x{:02X}, sending it again",
+					cmd,
+					code
+				);
+				std::thread::sleep(RETRY_DELAY * attempt);
+				attempt += 1;
+			}
+			Some(ChannelError(ERR_INVALID_CHANNEL)) => {
+				forget_channel(path);
+				return Err(e);
+			}
+			_ => return Err(e),
+		}
+	}
+}
 
-		// Build HMAC message for signing
-		// According to FIDO 2.1: authenticate(pinUvAuthToken, 32×0xff || 0x0d || uint8(subCommand) || subCommandParams)
-		let mut message = vec![0xff; 32];
-		message.push(CtapCommand::Config as u8);
-		message.push(ConfigSubCommand::VendorPrototype as u8);
-		message.extend(&sub_params_bytes);
+impl Transport for HidTransport {
+	fn send_cbor(&self, cmd: u8, payload: &[u8]) -> Result<Vec<u8>> {
+		HidTransport::send_cbor(self, cmd, payload)
+	}
 
-		// Sign using provided PIN token
-		use ring::hmac;
-		let hmac_key = hmac::Key::new(hmac::HMAC_SHA256, pin_token);
-		let sig = hmac::sign(&hmac_key, &message);
-		let pin_auth = sig.as_ref()[0..16].to_vec();
+	fn path(&self) -> &str {
+		&self.path
+	}
 
-		// Build full authenticatorConfig map
-		let mut config_map = BTreeMap::new();
-		config_map.insert(
-			Value::Integer(ConfigParam::SubCommand as i128),
-			Value::Integer(ConfigSubCommand::VendorPrototype as i128),
-		);
-		config_map.insert(
-			Value::Integer(ConfigParam::SubCommandParams as i128),
-			sub_params,
-		);
-		config_map.insert(
-			Value::Integer(ConfigParam::PinUvAuthProtocol as i128),
-			Value::Integer(1),
-		);
-		config_map.insert(
-			Value::Integer(ConfigParam::PinUvAuthParam as i128),
-			Value::Bytes(pin_auth),
-		);
+	fn vid(&self) -> u16 {
+		self.vid
+	}
 
-		let config_payload_cbor =
-			to_vec(&Value::Map(config_map)).map_err(|e| PFError::Io(e.to_string()))?;
+	fn pid(&self) -> u16 {
+		self.pid
+	}
 
-		// Encapsulate for CTAP
-		let mut payload = vec![CtapCommand::Config as u8];
-		payload.extend(config_payload_cbor);
+	fn product_name(&self) -> &str {
+		&self.product_name
+	}
 
-		// Send via HID
-		self.send_cbor(CTAPHID_CBOR, &payload).map_err(|e| {
-			log::error!("Failed to send FIDO config: {}", e);
-			PFError::Device(format!("FIDO config failed: {}", e))
-		})?;
+	fn serial_number(&self) -> Option<&str> {
+		Some(self.serial_number.as_str()).filter(|serial| !serial.is_empty())
+	}
+}
 
-		Ok(())
+/// Payload of a read-only vendor command, `[command] {1: sub_command}`
+/// Hex of `data`, a CTAP message or the response to `request`, for trace logs.
+///
+/// clientPin exchanges carry the PIN hash, new PIN or PIN token, even if encrypted, and are
+/// reduced to their length.
+pub fn trace_payload(request: &[u8], data: &[u8]) -> String {
+	if request.first() == Some(&(CtapCommand::ClientPin as u8)) {
+		return format!("<{} bytes of clientPin, redacted>", data.len());
 	}
+	hex::encode(data)
+}
 
-	/// Send authenticatorConfig command to set minimum PIN length.
-	///
-	/// This bypasses the ctap-hid-fido2 library which has a bug where it sends
-	/// CBOR map keys out of order (0x01, 0x03, 0x04, 0x02) instead of the required
-	/// ascending order (0x01, 0x02, 0x03, 0x04). The pico-fido firmware strictly
-	/// enforces canonical CBOR ordering per CTAP2 spec.
-	pub fn send_config_set_min_pin_length(
-		&self,
-		pin_token: &[u8],
-		new_min_pin_length: u8,
-	) -> Result<(), PFError> {
-		log::debug!(
-			"Sending setMinPINLength config command (new length: {})...",
-			new_min_pin_length
-		);
+pub fn vendor_command_payload(command: VendorCommand, sub_command: i128) -> Result<Vec<u8>> {
+	let mut params = BTreeMap::new();
+	params.insert(Value::Integer(1), Value::Integer(sub_command));
 
-		// Build subCommandParams (Key 0x02): { 0x01: newMinPINLength }
-		let mut sub_params_map = BTreeMap::new();
-		sub_params_map.insert(
-			Value::Integer(ConfigSubCommandParam::NewMinPinLength as i128),
-			Value::Integer(new_min_pin_length as i128),
-		);
-		let sub_params = Value::Map(sub_params_map);
-		let sub_params_bytes = to_vec(&sub_params).map_err(|e| PFError::Io(e.to_string()))?;
-
-		// Build HMAC message for signing
-		// Per FIDO 2.1 spec: authenticate(pinUvAuthToken, 32×0xff || 0x0d || uint8(subCommand) || subCommandParams)
-		let mut message = vec![0xff; 32];
-		message.push(CtapCommand::Config as u8); // 0x0d
-		message.push(ConfigSubCommand::SetMinPinLength as u8); // 0x03
-		message.extend(&sub_params_bytes);
-
-		// Sign using provided PIN token (Protocol 1 uses HMAC-SHA256, first 16 bytes)
-		use ring::hmac;
-		let hmac_key = hmac::Key::new(hmac::HMAC_SHA256, pin_token);
-		let sig = hmac::sign(&hmac_key, &message);
-		let pin_auth = sig.as_ref()[0..16].to_vec();
-
-		// Build full authenticatorConfig map with keys in ASCENDING ORDER
-		// This is critical - the firmware parser rejects out-of-order keys with CTAP2_ERR_INVALID_CBOR
-		let mut config_map = BTreeMap::new();
-		config_map.insert(
-			Value::Integer(ConfigParam::SubCommand as i128), // 0x01
-			Value::Integer(ConfigSubCommand::SetMinPinLength as i128), // 0x03
+	let mut payload = vec![command.value()? as u8];
+	payload.extend(to_vec(&Value::Map(params))?);
+	Ok(payload)
+}
+
+/// Payload of a vendor command signed with `pin_token`,
+/// `[command] {1: sub_command, 2: {1: param}, 3: 1, 4: pinUvAuthParam}`.
+///
+/// The signature covers `32×0xff || command || uint8(sub_command) || vendorSubParams`, like
+/// authenticatorConfig does.
+pub fn signed_vendor_command_payload(
+	pin_token: &[u8],
+	command: VendorCommand,
+	sub_command: i128,
+	param: Option<Vec<u8>>,
+) -> Result<Vec<u8>> {
+	let command = command.value()? as u8;
+	let sub_params = param.map(|param| {
+		let mut sub_params = BTreeMap::new();
+		sub_params.insert(
+			Value::Integer(VendorSubParam::VendorParam as i128),
+			Value::Bytes(param),
 		);
-		config_map.insert(
-			Value::Integer(ConfigParam::SubCommandParams as i128), // 0x02
+		Value::Map(sub_params)
+	});
+	let sub_params_bytes = match &sub_params {
+		Some(params) => to_vec(params)?,
+		None => Vec::new(),
+	};
+
+	let mut message = vec![0xff; 32];
+	message.push(command);
+	message.push(sub_command as u8);
+	message.extend(&sub_params_bytes);
+	use ring::hmac;
+	let hmac_key = hmac::Key::new(hmac::HMAC_SHA256, pin_token);
+	let pin_auth = hmac::sign(&hmac_key, &message).as_ref()[0..16].to_vec();
+
+	let mut params = BTreeMap::new();
+	params.insert(
+		Value::Integer(VendorParam::VendorCommand as i128),
+		Value::Integer(sub_command),
+	);
+	if let Some(sub_params) = sub_params {
+		params.insert(
+			Value::Integer(VendorParam::VendorSubParams as i128),
 			sub_params,
 		);
+	}
+	params.insert(
+		Value::Integer(VendorParam::PinUvAuthProtocol as i128),
+		Value::Integer(1),
+	);
+	params.insert(
+		Value::Integer(VendorParam::PinUvAuthParam as i128),
+		Value::Bytes(pin_auth),
+	);
+
+	let mut payload = vec![command];
+	payload.extend(to_vec(&Value::Map(params))?);
+	Ok(payload)
+}
+
+/// authenticatorConfig payload of a vendor configuration command, signed with `pin_token`
+pub fn vendor_config_payload(
+	pin_token: &[u8],
+	vendor_cmd: VendorConfigCommand,
+	param: Value,
+) -> Result<Vec<u8>, PFError> {
+	log::debug!("Sending vendor config command: {}...", vendor_cmd);
+
+	// Build subCommandParams (Key 0x02)
+	// This map contains:
+	// 0x01: vendorCommandId (u64)
+	// 0x02/0x03/0x04: param
+	let mut sub_params_inner = BTreeMap::new();
+	sub_params_inner.insert(
+		Value::Integer(0x01),
+		Value::Integer(vendor_cmd.to_u64() as i128),
+	);
+
+	match param {
+		Value::Bytes(_) => {
+			sub_params_inner.insert(Value::Integer(0x02), param.clone());
+		}
+		Value::Integer(_) => {
+			sub_params_inner.insert(Value::Integer(0x03), param.clone());
+		}
+		Value::Text(_) => {
+			sub_params_inner.insert(Value::Integer(0x04), param.clone());
+		}
+		_ => return Err(PFError::Io("Unsupported parameter type".into())),
+	}
+
+	config_payload(
+		pin_token,
+		ConfigSubCommand::VendorPrototype,
+		Some(Value::Map(sub_params_inner)),
+	)
+}
+
+/// authenticatorConfig payload of `sub_command`, signed with `pin_token`
+pub fn config_payload(
+	pin_token: &[u8],
+	sub_command: ConfigSubCommand,
+	sub_params: Option<Value>,
+) -> Result<Vec<u8>, PFError> {
+	let sub_params_bytes = match &sub_params {
+		Some(params) => to_vec(params).map_err(|e| PFError::Io(e.to_string()))?,
+		None => Vec::new(),
+	};
+
+	// Build HMAC message for signing
+	// According to FIDO 2.1: authenticate(pinUvAuthToken, 32×0xff || 0x0d || uint8(subCommand) || subCommandParams)
+	let mut message = vec![0xff; 32];
+	message.push(CtapCommand::Config as u8);
+	message.push(sub_command as u8);
+	message.extend(&sub_params_bytes);
+
+	// Sign using provided PIN token
+	use ring::hmac;
+	let hmac_key = hmac::Key::new(hmac::HMAC_SHA256, pin_token);
+	let sig = hmac::sign(&hmac_key, &message);
+	let pin_auth = sig.as_ref()[0..16].to_vec();
+
+	// Build full authenticatorConfig map
+	let mut config_map = BTreeMap::new();
+	config_map.insert(
+		Value::Integer(ConfigParam::SubCommand as i128),
+		Value::Integer(sub_command as i128),
+	);
+	if let Some(params) = sub_params {
 		config_map.insert(
-			Value::Integer(ConfigParam::PinUvAuthProtocol as i128), // 0x03
-			Value::Integer(1),                                      // PIN protocol version 1
+			Value::Integer(ConfigParam::SubCommandParams as i128),
+			params,
 		);
-		config_map.insert(
-			Value::Integer(ConfigParam::PinUvAuthParam as i128), // 0x04
-			Value::Bytes(pin_auth),
+	}
+	config_map.insert(
+		Value::Integer(ConfigParam::PinUvAuthProtocol as i128),
+		Value::Integer(1),
+	);
+	config_map.insert(
+		Value::Integer(ConfigParam::PinUvAuthParam as i128),
+		Value::Bytes(pin_auth),
+	);
+
+	let config_payload_cbor =
+		to_vec(&Value::Map(config_map)).map_err(|e| PFError::Io(e.to_string()))?;
+
+	// Encapsulate for CTAP
+	let mut payload = vec![CtapCommand::Config as u8];
+	payload.extend(config_payload_cbor);
+	Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::cell::Cell;
+
+	/// Runs `send_with_retries` against replies popped from `replies`, returning the result and
+	/// the number of attempts
+	fn exchange(path: &str, replies: Vec<Result<Vec<u8>>>) -> (Result<Vec<u8>>, u32) {
+		let mut replies = replies.into_iter();
+		let attempts = Cell::new(0);
+		let result = send_with_retries(path, CTAPHID_CBOR, || {
+			attempts.set(attempts.get() + 1);
+			replies.next().expect("More attempts than scripted replies")
+		});
+		(result, attempts.get())
+	}
+
+	fn channel_error(code: u8) -> Result<Vec<u8>> {
+		Err(ChannelError(code).into())
+	}
+
+	#[test]
+	fn busy_channel_is_retried() {
+		let (result, attempts) = exchange(
+			"retry-busy",
+			vec![channel_error(ERR_CHANNEL_BUSY), Ok(vec![0x00, 0xA0])],
 		);
+		assert_eq!(result.unwrap(), vec![0x00, 0xA0]);
+		assert_eq!(attempts, 2);
+	}
 
-		let config_payload_cbor =
-			to_vec(&Value::Map(config_map)).map_err(|e| PFError::Io(e.to_string()))?;
+	#[test]
+	fn lost_packets_are_retried_a_few_times() {
+		let replies = (0..MAX_ATTEMPTS)
+			.map(|_| channel_error(ERR_MSG_TIMEOUT))
+			.collect();
+		let (result, attempts) = exchange("retry-timeout", replies);
+		let e = result.unwrap_err();
+		assert!(matches!(
+			e.downcast_ref::<ChannelError>(),
+			Some(ChannelError(ERR_MSG_TIMEOUT))
+		));
+		assert_eq!(attempts, MAX_ATTEMPTS);
+	}
 
-		// Prepend CTAP command byte
-		let mut payload = vec![CtapCommand::Config as u8];
-		payload.extend(config_payload_cbor);
+	#[test]
+	fn invalid_channel_is_forgotten() {
+		CHANNELS
+			.lock()
+			.unwrap()
+			.insert("retry-invalid-channel".into(), 0x1234_5678);
+		let (result, attempts) = exchange(
+			"retry-invalid-channel",
+			vec![channel_error(ERR_INVALID_CHANNEL)],
+		);
+		assert!(result.is_err());
+		assert_eq!(attempts, 1);
+		assert!(
+			!CHANNELS
+				.lock()
+				.unwrap()
+				.contains_key("retry-invalid-channel")
+		);
+	}
 
-		// Send via HID
-		match self.send_cbor(CTAPHID_CBOR, &payload) {
-			Ok(_) => {
-				log::info!(
-					"Successfully set minimum PIN length to {}",
-					new_min_pin_length
-				);
-				Ok(())
-			}
-			Err(e) => {
-				let err_str = e.to_string();
-				log::error!("Failed to send setMinPINLength config: {}", err_str);
-
-				// Check for PIN policy violation (0x37) - cannot decrease min PIN length
-				if err_str.contains("0x37") {
-					return Err(PFError::Device(
-						"Cannot decrease minimum PIN length. The FIDO2 security policy only allows increasing the minimum PIN length, not decreasing it. A device reset is required to lower the minimum.".into()
-					));
-				}
+	#[test]
+	fn requests_that_may_have_arrived_are_not_sent_again() {
+		let (result, attempts) = exchange(
+			"retry-silent",
+			vec![Err(timed_out("The key did not answer in time"))],
+		);
+		assert!(result.is_err());
+		assert_eq!(attempts, 1);
 
-				Err(PFError::Device(format!("setMinPINLength failed: {}", e)))
-			}
-		}
+		let (result, attempts) =
+			exchange("retry-invalid-cmd", vec![channel_error(ERR_INVALID_CMD)]);
+		assert!(result.is_err());
+		assert_eq!(attempts, 1);
 	}
 }
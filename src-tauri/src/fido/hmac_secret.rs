@@ -0,0 +1,192 @@
+//! Keys derived on the authenticator through the `hmac-secret` extension.
+//!
+//! Each use gets its own resident credential, so its key cannot be derived through the
+//! credential of another and deleting one credential only loses the data of that use.
+
+use super::{
+	connect, constants::Ctap2Error, get_fido_info, hid::CtapStatus, message_status, pin_error,
+};
+use crate::{
+	error::PFError,
+	operation::report_phase,
+	types::{HmacSecretOutput, PhaseVerb, UserAction},
+};
+use ctap_hid_fido2::{
+	FidoKeyHid,
+	fidokey::{
+		get_assertion::{Extension as AssertionExtension, GetAssertionArgsBuilder},
+		make_credential::{Extension as CredentialExtension, MakeCredentialArgsBuilder},
+	},
+	public_key_credential_user_entity::PublicKeyCredentialUserEntity,
+};
+use rand::Rng;
+use ring::digest;
+
+/// Whether `e` is CTAP2_ERR_NO_CREDENTIALS, from `HidTransport` or in a ctap_hid_fido2 message
+fn no_credentials(e: &anyhow::Error) -> bool {
+	let status = match e.downcast_ref::<CtapStatus>() {
+		Some(CtapStatus(status)) => Some(*status),
+		None => message_status(&format!("{:#}", e)),
+	};
+	status == Some(Ctap2Error::NoCredentials as u8)
+}
+
+/// Resident credential backing the keys of one use
+pub(crate) struct SecretCredential<'a> {
+	pub rp_id: &'a str,
+	pub user_id: &'a [u8],
+	pub user_name: &'a str,
+	/// Shown in the progress phases, e.g. "the secure notes"
+	pub label: &'a str,
+}
+
+impl SecretCredential<'_> {
+	/// Credential ID of the credential, or `None` if the device definitely does not hold it.
+	///
+	/// Only CTAP2_ERR_NO_CREDENTIALS means "absent". Any other failure, e.g. a wrong PIN or a
+	/// transport error, is returned, so the credential is never recreated over an existing one.
+	pub fn find(&self, device: &FidoKeyHid, pin: &str) -> Result<Option<Vec<u8>>, PFError> {
+		let rp_hash = digest::digest(&digest::SHA256, self.rp_id.as_bytes());
+
+		match device.credential_management_enumerate_credentials(Some(pin), rp_hash.as_ref()) {
+			Ok(credentials) => Ok(credentials
+				.into_iter()
+				.find(|c| c.public_key_credential_user_entity.id == self.user_id)
+				.map(|c| c.public_key_credential_descriptor.id)),
+			Err(e) if no_credentials(&e) => Ok(None),
+			Err(e) => Err(pin_error(
+				device,
+				e,
+				&format!("Lookup of the {} credential", self.rp_id),
+			)),
+		}
+	}
+
+	/// Returns the credential ID, creating the credential on first use.
+	pub fn ensure(&self, device: &FidoKeyHid, pin: &str) -> Result<Vec<u8>, PFError> {
+		if let Some(credential_id) = self.find(device, pin)? {
+			log::debug!("Found existing {} credential", self.rp_id);
+			return Ok(credential_id);
+		}
+
+		log::info!("Creating resident credential for {}...", self.rp_id);
+		report_phase(
+			PhaseVerb::Create,
+			&format!("{} passkey", self.label),
+			UserAction::TouchDevice,
+		);
+		let challenge: [u8; 32] = rand::rng().random();
+		let user = PublicKeyCredentialUserEntity::new(
+			Some(self.user_id),
+			Some(self.user_name),
+			Some(self.user_name),
+		);
+		let args = MakeCredentialArgsBuilder::new(self.rp_id, &challenge)
+			.pin(pin)
+			.user_entity(&user)
+			.resident_key()
+			.extensions(&[CredentialExtension::HmacSecret(Some(true))])
+			.build();
+
+		let attestation = device.make_credential_with_args(&args).map_err(|e| {
+			pin_error(
+				device,
+				e,
+				&format!("Creation of the {} credential", self.rp_id),
+			)
+		})?;
+
+		Ok(attestation.credential_descriptor.id)
+	}
+
+	/// Derives the 32 byte secret of `salt` through a getAssertion with the hmac-secret extension.
+	pub fn derive(
+		&self,
+		device: &FidoKeyHid,
+		pin: &str,
+		credential_id: &[u8],
+		salt: [u8; 32],
+	) -> Result<[u8; 32], PFError> {
+		let challenge: [u8; 32] = rand::rng().random();
+		let args = GetAssertionArgsBuilder::new(self.rp_id, &challenge)
+			.pin(pin)
+			.credential_id(credential_id)
+			.extensions(&[AssertionExtension::HmacSecret(Some(salt))])
+			.build();
+
+		report_phase(
+			PhaseVerb::Read,
+			&format!("{} key", self.label),
+			UserAction::TouchDevice,
+		);
+		let assertions = device
+			.get_assertion_with_args(&args)
+			.map_err(|e| pin_error(device, e, "hmac-secret assertion"))?;
+
+		assertions
+			.first()
+			.and_then(|a| {
+				a.extensions.iter().find_map(|ext| match ext {
+					AssertionExtension::HmacSecret(Some(output)) => Some(*output),
+					_ => None,
+				})
+			})
+			.ok_or_else(|| {
+				PFError::Device(format!(
+					"No hmac-secret output, the {} credential was not created with the extension",
+					self.rp_id
+				))
+			})
+	}
+}
+
+/// Derives the hmac-secret output of `salt` with any resident credential, e.g. one enrolled by a
+/// disk encryption tool. Requires a touch.
+///
+/// The output only depends on the credential and the salt, so comparing it across keys restored
+/// from the same backup shows whether they would unlock the same disk.
+pub fn derive_hmac_secret(
+	device_path: Option<&str>,
+	pin: &str,
+	rp_id: &str,
+	credential_id_hex: &str,
+	salt_hex: &str,
+) -> Result<HmacSecretOutput, PFError> {
+	let credential_id = hex::decode(credential_id_hex)
+		.map_err(|_| PFError::Io("Invalid Credential ID Hex string".into()))?;
+	let salt: [u8; 32] = hex::decode(salt_hex.trim())
+		.ok()
+		.and_then(|salt| salt.try_into().ok())
+		.ok_or_else(|| PFError::Io("The salt must be 32 bytes of hex".into()))?;
+
+	if !get_fido_info(device_path)?
+		.extensions
+		.iter()
+		.any(|ext| ext == "hmac-secret")
+	{
+		return Err(PFError::Device(
+			"The device does not support the hmac-secret extension".into(),
+		));
+	}
+
+	let device = connect(device_path)?;
+	// Only the relying party is needed to assert with an existing credential
+	let credential = SecretCredential {
+		rp_id,
+		user_id: &[],
+		user_name: "",
+		label: &format!("the {}", rp_id),
+	};
+	let output = credential.derive(&device, pin, &credential_id, salt)?;
+
+	log::info!(
+		"Derived an hmac-secret output with the passkey for {}",
+		rp_id
+	);
+	Ok(HmacSecretOutput {
+		rp_id: rp_id.to_string(),
+		credential_id: credential_id_hex.to_string(),
+		salt: hex::encode(salt),
+		output: hex::encode(output),
+	})
+}
@@ -0,0 +1,132 @@
+//! LED drivers and the GPIOs they can drive on each board.
+//!
+//! A wrong driver or GPIO leaves the key without a working LED, and with it without a visible
+//! touch prompt. Combinations that cannot work are rejected before they are written, and
+//! `test_led` blinks the LED of the key so the choice can be confirmed once it took effect.
+
+use super::{ctap_error, hid::HidTransport};
+use crate::{
+	error::PFError,
+	operation::report_phase,
+	types::{LedDriver, PhaseVerb, UserAction},
+};
+use std::ops::RangeInclusive;
+
+/// Chip families the firmware is built for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Chip {
+	Rp2040,
+	/// QFN-60 package, 30 GPIOs like the RP2040
+	Rp2350A,
+	/// QFN-80 package, 48 GPIOs
+	Rp2350B,
+	Esp32S3,
+}
+
+impl Chip {
+	/// Chip of a board named like the release images, e.g. "pico2" or "waveshare_rp2040_zero"
+	fn of_board(board: &str) -> Option<Self> {
+		let board = board.to_ascii_lowercase().replace('-', "_");
+		if board.contains("esp32") {
+			Some(Self::Esp32S3)
+		} else if board.contains("rp2350b") {
+			Some(Self::Rp2350B)
+		} else if board.contains("rp2350") || board.contains("pico2") {
+			Some(Self::Rp2350A)
+		} else if board.contains("rp2040") || board.contains("pico") {
+			Some(Self::Rp2040)
+		} else {
+			None
+		}
+	}
+
+	fn gpios(self) -> RangeInclusive<u8> {
+		match self {
+			Self::Rp2040 | Self::Rp2350A => 0..=29,
+			Self::Rp2350B => 0..=47,
+			Self::Esp32S3 => 0..=48,
+		}
+	}
+
+	fn is_rp(self) -> bool {
+		self != Self::Esp32S3
+	}
+}
+
+/// Whether `board` has the CYW43 wireless chip, i.e. is a Pico W or Pico 2 W
+fn is_wireless(board: &str) -> bool {
+	let board = board.to_ascii_lowercase();
+	board.ends_with("_w") || board.ends_with("-w")
+}
+
+/// Rejects LED settings that cannot work on `board`, or on any board the driver supports if the
+/// board is not known
+pub fn validate(
+	driver: Option<LedDriver>,
+	gpio: Option<u8>,
+	board: Option<&str>,
+) -> Result<(), PFError> {
+	let chip = board.and_then(Chip::of_board);
+	if let (Some(driver), Some(board)) = (driver, board) {
+		let problem = match (driver, chip) {
+			(LedDriver::Cyw43, _) if !is_wireless(board) => {
+				Some("The CYW43 LED driver only works on a Pico W")
+			}
+			(LedDriver::Neopixel, Some(chip)) if chip.is_rp() => {
+				Some("The NeoPixel driver is for ESP32-S3 boards, use WS2812")
+			}
+			(LedDriver::Pico | LedDriver::Pimoroni | LedDriver::Ws2812, Some(Chip::Esp32S3)) => {
+				Some("This LED driver is for RP2040/RP2350 boards, use NeoPixel")
+			}
+			_ => None,
+		};
+		if let Some(problem) = problem {
+			return Err(PFError::Io(format!("{} (board {})", problem, board)));
+		}
+	}
+
+	let Some(gpio) = gpio else {
+		return Ok(());
+	};
+	// The CYW43 LED is not on a GPIO of the chip, the setting is ignored
+	if driver == Some(LedDriver::Cyw43) {
+		return Ok(());
+	}
+	let chip = chip.or(match driver {
+		Some(LedDriver::Neopixel) => Some(Chip::Esp32S3),
+		Some(LedDriver::Pico | LedDriver::Pimoroni | LedDriver::Ws2812) => Some(Chip::Rp2350B),
+		_ => None,
+	});
+	let Some(chip) = chip else {
+		return Ok(());
+	};
+
+	if !chip.gpios().contains(&gpio) {
+		return Err(PFError::Io(format!(
+			"GPIO {} does not exist on the {:?}, use GPIO {} to {}",
+			gpio,
+			chip,
+			chip.gpios().start(),
+			chip.gpios().end()
+		)));
+	}
+	// Used by the SPI flash and not bonded out
+	if chip == Chip::Esp32S3 && (22..=25).contains(&gpio) {
+		return Err(PFError::Io(format!(
+			"GPIO {} is not available on the ESP32-S3",
+			gpio
+		)));
+	}
+	Ok(())
+}
+
+/// Blinks the LED of the key with its current configuration. LED settings take effect once the
+/// key is re-plugged, so a new driver or GPIO can be tested before the rest of the configuration
+/// is committed.
+pub fn test_led(device_path: Option<&str>) -> Result<(), PFError> {
+	log::info!("Testing the LED...");
+	let transport =
+		HidTransport::open_path(device_path).map_err(|e| ctap_error(e, "HID connection"))?;
+	report_phase(PhaseVerb::Verify, "the LED", UserAction::None);
+	transport.send_wink().map_err(|e| ctap_error(e, "LED test"))
+}
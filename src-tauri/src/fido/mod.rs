@@ -1,37 +1,194 @@
 #![allow(unused)]
 
+pub mod attestation;
+pub mod backup;
+pub mod bio;
+pub mod cache;
+pub mod ccid;
 pub mod constants;
+pub mod cred_mgmt;
+#[cfg(feature = "gui")]
+pub mod enterprise;
 pub mod hid;
+pub mod hmac_secret;
+pub mod led;
+pub mod notes;
+pub mod phy;
+pub mod pin_policy;
+pub mod pin_token;
+pub mod rp_metadata;
+pub mod self_test;
+pub mod transport;
+pub mod vendor;
+pub mod watcher;
+pub mod webauthn;
 
 use crate::{
 	error::PFError,
+	inventory::Inventory,
+	operation::{cancel_requested, report_phase},
+	schema::registry::ProtocolConstant,
 	types::{
-		AppConfig, AppConfigInput, DeviceInfo, FidoDeviceInfo, FullDeviceStatus, StoredCredential,
+		AppConfig, AppConfigInput, CredentialSignCount, CredentialVerification, DeviceInfo,
+		FidoDeviceInfo, FidoHidDevice, FirmwareVersion, FormatHints, FullDeviceStatus, PhaseVerb,
+		PinPolicy, PinStatus, RawVendorResponse, SignCountStatus, StoredCredential, UserAction,
+		VendorCapabilities, WriteFieldResult, WriteFieldStatus, WriteReport,
 	},
 };
 use constants::*;
 use ctap_hid_fido2::{
-	Cfg, FidoKeyHidFactory,
-	fidokey::make_credential::{MakeCredentialArgs, MakeCredentialArgsBuilder},
+	Cfg, FidoKeyHid, FidoKeyHidFactory, HidParam,
+	fidokey::{
+		get_assertion::{GetAssertionArgsBuilder, get_assertion_params::Assertion},
+		make_credential::{MakeCredentialArgs, MakeCredentialArgsBuilder},
+	},
 	public_key_credential_descriptor::PublicKeyCredentialDescriptor,
 	public_key_credential_user_entity::PublicKeyCredentialUserEntity,
+	verifier,
 };
 use hid::*;
+use phy::{PhyConfig, PhyOptions, PhyTag};
 use rand::Rng;
 use serde_cbor_2::{Value, from_slice, to_vec};
 use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+use transport::Transport;
 
 // Fido functions that require pin: ( Uses ctap_hid_fido2 crate)
 
-pub(crate) fn get_fido_info() -> Result<FidoDeviceInfo, String> {
+/// Opens the FIDO device at the HID path `device_path`, or the only connected one.
+pub(crate) fn open_device(device_path: Option<&str>) -> anyhow::Result<FidoKeyHid> {
 	let cfg = Cfg::init();
+	match device_path {
+		Some(path) => {
+			FidoKeyHidFactory::create_by_params(&[HidParam::Path(path.to_string())], &cfg)
+		}
+		None => FidoKeyHidFactory::create(&cfg),
+	}
+}
+
+/// Opens the FIDO device like `open_device`, for operations reporting a `PFError`
+pub(crate) fn connect(device_path: Option<&str>) -> Result<FidoKeyHid, PFError> {
+	open_device(device_path).map_err(|e| {
+		if e.to_string().contains("not found") {
+			PFError::NoDevice
+		} else {
+			PFError::TransportIo(format!("Could not connect to FIDO device: {:#}", e))
+		}
+	})
+}
 
-	let device = FidoKeyHidFactory::create(&cfg)
-		.map_err(|_| "Could not connect to FIDO device. Is it plugged in?".to_string())?;
+/// Parses the status ctap_hid_fido2 puts in its messages, e.g. "0x31 CTAP2_ERR_PIN_INVALID"
+fn message_status(message: &str) -> Option<u8> {
+	message.match_indices("0x").find_map(|(i, _)| {
+		let rest = &message[i + 2..];
+		if !rest.get(2..)?.starts_with(" CTAP") {
+			return None;
+		}
+		u8::from_str_radix(&rest[..2], 16).ok()
+	})
+}
 
-	let info = device
-		.get_info()
-		.map_err(|e| format!("Error reading device info: {:?}", e))?;
+/// Maps a CTAP status returned during `action`, e.g. "PIN change", to a `PFError`
+pub(crate) fn ctap_status_error(status: u8, action: &str) -> PFError {
+	match status {
+		s if s == Ctap2Error::PinInvalid as u8 => PFError::PinInvalid { retries_left: None },
+		s if s == Ctap2Error::PinBlocked as u8 => PFError::PinBlocked,
+		s if s == Ctap2Error::PinAuthBlocked as u8 => PFError::Device(
+			"Too many wrong PINs in a row, reconnect the key before trying again".into(),
+		),
+		s if s == Ctap2Error::UserActionTimeout as u8 || s == Ctap2Error::ActionTimeout as u8 => {
+			PFError::UserPresenceTimeout
+		}
+		s if s == Ctap2Error::KeepaliveCancel as u8 => PFError::Cancelled,
+		s if s == Ctap2Error::InvalidCbor as u8 || s == Ctap2Error::CborUnexpectedType as u8 => {
+			PFError::CborDecode(format!("The key could not decode the {} request", action))
+		}
+		s if s == Ctap2Error::InvalidCommand as u8
+			|| s == Ctap2Error::InvalidSubcommand as u8
+			|| s == Ctap2Error::UnsupportedOption as u8 =>
+		{
+			PFError::VendorUnsupported(action.to_string())
+		}
+		_ => PFError::Device(format!(
+			"{} failed with CTAP status 0x{:02X}",
+			action, status
+		)),
+	}
+}
+
+/// Classifies an error of ctap_hid_fido2 or `HidTransport` during `action`, e.g. "PIN change"
+pub(crate) fn ctap_error(e: anyhow::Error, action: &str) -> PFError {
+	if let Some(PFError::NoDevice) = e.downcast_ref::<PFError>() {
+		return PFError::NoDevice;
+	}
+	if let Some(CtapStatus(status)) = e.downcast_ref::<CtapStatus>() {
+		return ctap_status_error(*status, action);
+	}
+	if let Some(ChannelError(code)) = e.downcast_ref::<ChannelError>() {
+		return match *code {
+			ERR_INVALID_CMD => PFError::VendorUnsupported(action.to_string()),
+			code => PFError::TransportIo(format!(
+				"{} failed with CTAPHID error 0x{:02X}",
+				action, code
+			)),
+		};
+	}
+	if let Some(e) = e.downcast_ref::<serde_cbor_2::Error>() {
+		return PFError::CborDecode(format!("{} response: {}", action, e));
+	}
+	if e.downcast_ref::<hidapi::HidError>().is_some()
+		|| e.downcast_ref::<std::io::Error>().is_some()
+	{
+		return PFError::TransportIo(format!("{} failed: {:#}", action, e));
+	}
+	let message = format!("{:#}", e);
+	match message_status(&message) {
+		Some(status) => ctap_status_error(status, action),
+		None => PFError::Device(format!("{} failed: {}", action, message)),
+	}
+}
+
+/// Like `ctap_error`, adding the attempts left after a wrong PIN
+fn pin_error(device: &FidoKeyHid, e: anyhow::Error, action: &str) -> PFError {
+	match ctap_error(e, action) {
+		PFError::PinInvalid { .. } => PFError::PinInvalid {
+			retries_left: device.get_pin_retries().ok().map(|n| n.max(0) as u32),
+		},
+		other => other,
+	}
+}
+
+/// FIDO devices currently connected, to pick the target of an operation from
+pub fn list_devices() -> Result<Vec<FidoHidDevice>, PFError> {
+	let api = hidapi::HidApi::new().map_err(|e| PFError::Device(e.to_string()))?;
+	Ok(enumerate_devices(&api))
+}
+
+/// Reboots the FIDO device at `device_path` into BOOTSEL mode over CTAPHID
+pub fn reboot_to_bootsel(device_path: Option<&str>) -> Result<(), PFError> {
+	let transport =
+		HidTransport::open_path(device_path).map_err(|e| ctap_error(e, "HID connection"))?;
+	report_phase(PhaseVerb::Reboot, "device", UserAction::None);
+	cache::invalidate();
+	transport
+		.send_reboot(true)
+		.map_err(|e| PFError::Device(e.to_string()))
+}
+
+/// USB port of the FIDO device at `device_path`, or of the first one connected
+pub fn port_path(device_path: Option<&str>) -> Option<String> {
+	match device_path {
+		Some(path) => usb_port_path(path),
+		None => list_devices().ok()?.into_iter().next()?.port_path,
+	}
+}
+
+pub(crate) fn get_fido_info(device_path: Option<&str>) -> Result<FidoDeviceInfo, PFError> {
+	let device = connect(device_path)?;
+
+	report_phase(PhaseVerb::Read, "FIDO device info", UserAction::None);
+	let info = device.get_info().map_err(|e| ctap_error(e, "GetInfo"))?;
 
 	let options_map: HashMap<String, bool> = info.options.into_iter().collect();
 
@@ -48,44 +205,203 @@ pub(crate) fn get_fido_info() -> Result<FidoDeviceInfo, String> {
 			(info.firmware_version >> 8) & 0xFF,
 			info.firmware_version & 0xFF
 		),
+		firmware: FirmwareVersion::from_packed(info.firmware_version),
+	})
+}
+
+/// Sends a clientPin sub command that needs no authentication, returning the response map
+fn client_pin_query(
+	transport: &impl Transport,
+	sub_command: ClientPinSubCommand,
+) -> Result<BTreeMap<Value, Value>, PFError> {
+	let mut params = BTreeMap::new();
+	params.insert(
+		Value::Integer(ClientPinParam::PinUvAuthProtocol as i128),
+		Value::Integer(1),
+	);
+	params.insert(
+		Value::Integer(ClientPinParam::SubCommand as i128),
+		Value::Integer(sub_command as i128),
+	);
+	let mut payload = vec![CtapCommand::ClientPin as u8];
+	payload.extend(to_vec(&Value::Map(params)).map_err(|e| PFError::Io(e.to_string()))?);
+
+	let res = transport
+		.send_cbor(CTAPHID_CBOR, &payload)
+		.map_err(|e| ctap_error(e, &format!("clientPin {:?}", sub_command)))?;
+	match from_slice(&res) {
+		Ok(Value::Map(m)) => Ok(m),
+		_ => Err(PFError::CborDecode(format!(
+			"clientPin {:?} response was not a valid CBOR map",
+			sub_command
+		))),
+	}
+}
+
+/// Options of a GetInfo response, e.g. "clientPin" or "uv"
+fn read_info(transport: &impl Transport) -> Result<BTreeMap<Value, Value>, PFError> {
+	let res = transport
+		.send_cbor(CTAPHID_CBOR, &[CtapCommand::GetInfo as u8])
+		.map_err(|e| ctap_error(e, "GetInfo"))?;
+	let Ok(Value::Map(info)) = from_slice(&res) else {
+		return Err(PFError::CborDecode(
+			"GetInfo response was not a valid CBOR map".into(),
+		));
+	};
+	Ok(info)
+}
+
+fn info_options(transport: &impl Transport) -> Result<BTreeMap<String, bool>, PFError> {
+	let info = read_info(transport)?;
+	let mut options = BTreeMap::new();
+	if let Some(Value::Map(m)) = info.get(&Value::Integer(0x04)) {
+		for (key, value) in m {
+			if let (Value::Text(key), Value::Bool(value)) = (key, value) {
+				options.insert(key.clone(), *value);
+			}
+		}
+	}
+	Ok(options)
+}
+
+/// Minimum PIN length from GetInfo, 4 if the key does not report one
+pub(crate) fn read_min_pin_length(transport: &impl Transport) -> Result<u32, PFError> {
+	Ok(match read_info(transport)?.get(&Value::Integer(0x0D)) {
+		Some(Value::Integer(length)) => *length as u32,
+		_ => 4,
 	})
 }
 
+/// Remaining PIN and UV attempts, and whether the key is blocked or has to be reconnected
+pub(crate) fn get_pin_status(device_path: Option<&str>) -> Result<PinStatus, PFError> {
+	let transport =
+		HidTransport::open_path(device_path).map_err(|e| ctap_error(e, "HID connection"))?;
+	report_phase(PhaseVerb::Read, "PIN retry counters", UserAction::None);
+	read_pin_status(&transport)
+}
+
+/// Reads the PIN status over `transport`
+pub(crate) fn read_pin_status(transport: &impl Transport) -> Result<PinStatus, PFError> {
+	let options = info_options(transport)?;
+	let retries = client_pin_query(transport, ClientPinSubCommand::GetPinRetries)?;
+	let pin_retries = match retries.get(&Value::Integer(ClientPinResponse::PinRetries as i128)) {
+		Some(Value::Integer(n)) => *n as u32,
+		_ => {
+			return Err(PFError::Device(
+				"PIN retries missing from the response".into(),
+			));
+		}
+	};
+	let power_cycle_required = matches!(
+		retries.get(&Value::Integer(ClientPinResponse::PowerCycleState as i128)),
+		Some(Value::Bool(true))
+	);
+
+	// Only authenticators with built-in user verification keep a UV counter
+	let uv_retries = if options.contains_key("uv") {
+		client_pin_query(transport, ClientPinSubCommand::GetUvRetries)?
+			.get(&Value::Integer(ClientPinResponse::UvRetries as i128))
+			.and_then(|v| match v {
+				Value::Integer(n) => Some(*n as u32),
+				_ => None,
+			})
+	} else {
+		None
+	};
+
+	Ok(PinStatus {
+		pin_set: options.get("clientPin") == Some(&true),
+		pin_retries,
+		uv_retries,
+		blocked: pin_retries == 0,
+		power_cycle_required,
+	})
+}
+
+/// Refuses a PIN attempt that would burn one of the last attempts, or is refused anyway
+pub(crate) fn check_pin_attempts(status: &PinStatus) -> Result<(), PFError> {
+	if status.blocked {
+		return Err(PFError::PinBlocked);
+	}
+	if status.power_cycle_required {
+		return Err(ctap_status_error(
+			Ctap2Error::PinAuthBlocked as u8,
+			"PIN change",
+		));
+	}
+	Ok(())
+}
+
 pub(crate) fn change_fido_pin(
+	device_path: Option<&str>,
 	current_pin: Option<String>,
 	new_pin: String,
-) -> Result<String, String> {
-	let cfg = Cfg::init();
-	let device = FidoKeyHidFactory::create(&cfg)
-		.map_err(|e| format!("Failed to connect to FIDO device: {:?}", e))?;
+) -> Result<String, PFError> {
+	if current_pin.is_some() {
+		check_pin_attempts(&get_pin_status(device_path)?)?;
+	}
+
+	let device = connect(device_path)?;
+	let info = device.get_info().map_err(|e| ctap_error(e, "GetInfo"))?;
+	pin_policy::check_new_pin(&new_pin, current_pin.as_deref(), info.min_pin_length)?;
 
+	report_phase(PhaseVerb::Write, "FIDO PIN", UserAction::None);
 	match current_pin {
 		Some(old) => {
 			device
 				.change_pin(&old, &new_pin)
-				.map_err(|e| format!("Failed to change PIN: {:?}", e))?;
+				.map_err(|e| pin_error(&device, e, "PIN change"))?;
 			Ok("PIN Changed Successfully".into())
 		}
 		None => {
 			device
 				.set_new_pin(&new_pin)
-				.map_err(|e| format!("Failed to set PIN: {:?}", e))?;
+				.map_err(|e| ctap_error(e, "PIN setup"))?;
 			Ok("PIN Set Successfully".into())
 		}
 	}
 }
 
+/// Minimum PIN length and pending PIN change, from GetInfo
+pub(crate) fn get_pin_policy(device_path: Option<&str>) -> Result<PinPolicy, PFError> {
+	let device = connect(device_path)?;
+	report_phase(PhaseVerb::Read, "PIN policy", UserAction::None);
+	let info = device.get_info().map_err(|e| ctap_error(e, "GetInfo"))?;
+	Ok(PinPolicy {
+		min_pin_length: info.min_pin_length,
+		force_pin_change: info.force_pin_change,
+		max_rp_ids: info.max_rpids_for_set_min_pin_length,
+		set_min_pin_length_supported: info
+			.options
+			.iter()
+			.any(|(option, enabled)| option == "setMinPINLength" && *enabled),
+	})
+}
+
+/// Raises the minimum PIN length, and lets `rp_ids` read it with the minPinLength extension.
+/// Returns the policy the key reports afterwards.
 pub(crate) fn set_min_pin_length(
+	device_path: Option<&str>,
 	current_pin: String,
 	min_pin_length: u8,
-) -> Result<String, String> {
+	rp_ids: Vec<String>,
+) -> Result<PinPolicy, PFError> {
 	log::info!("Starting set_min_pin_length (custom implementation)...");
 
+	if !rp_ids.is_empty() {
+		let max_rp_ids = get_pin_policy(device_path)?.max_rp_ids;
+		if rp_ids.len() > max_rp_ids as usize {
+			return Err(PFError::Device(format!(
+				"The key accepts at most {} RP IDs for the minimum PIN length, not {}",
+				max_rp_ids,
+				rp_ids.len()
+			)));
+		}
+	}
+
 	// 1. Obtain PIN token using the library handle
 	let pin_token = {
-		let cfg = Cfg::init();
-		let device = FidoKeyHidFactory::create(&cfg)
-			.map_err(|e| format!("Could not connect to FIDO device: {:?}", e))?;
+		let device = connect(device_path)?;
 
 		use ctap_hid_fido2::fidokey::pin::Permission;
 		// Obtain a token with AuthenticatorConfiguration permission (CTAP 2.1)
@@ -99,7 +415,7 @@ pub(crate) fn set_min_pin_length(
 			}
 			Err(e) => {
 				log::error!("Failed to get PIN token with ACFG permission: {:?}", e);
-				return Err(format!("Failed to obtain PIN token: {:?}", e));
+				return Err(pin_error(&device, e, "PIN token request"));
 			}
 		}
 		// Library handle 'device' is dropped here, closing the HID session.
@@ -107,110 +423,378 @@ pub(crate) fn set_min_pin_length(
 
 	// 2. Open custom HidTransport and send command using the token because ctap-hid-fido2 has a bug where it sends CBOR map keys out of order (0x01, 0x03, 0x04, 0x02) instead of the required ascending order (0x01, 0x02, 0x03, 0x04). The pico-fido firmware strictly requires ascending order.
 	let transport =
-		HidTransport::open().map_err(|e| format!("Could not open HID transport: {}", e))?;
+		HidTransport::open_path(device_path).map_err(|e| ctap_error(e, "HID connection"))?;
 
-	transport
-		.send_config_set_min_pin_length(&pin_token, min_pin_length)
-		.map_err(|e| format!("Failed to set minimum PIN length: {}", e))?;
+	report_phase(PhaseVerb::Write, "minimum PIN length", UserAction::None);
+	transport.send_config_set_min_pin_length(&pin_token, min_pin_length, &rp_ids)?;
+	drop(transport);
 
-	Ok(format!(
-		"Minimum PIN length successfully set to {}",
-		min_pin_length
-	))
+	log::info!("Minimum PIN length successfully set to {}", min_pin_length);
+	get_pin_policy(device_path)
 }
 
-pub(crate) fn get_credentials(pin: String) -> Result<Vec<StoredCredential>, String> {
-	let cfg = Cfg::init();
-	let device = FidoKeyHidFactory::create(&cfg)
-		.map_err(|e| format!("Failed to connect to FIDO device: {:?}", e))?;
-
-	let rps = match device.credential_management_enumerate_rps(Some(&pin)) {
-		Ok(rps) => rps,
-		Err(e) => {
-			// CTAP2_ERR_NO_CREDENTIALS (0x2E) means no credentials exist - return empty list
-			let err_str = format!("{:?}", e);
-			if err_str.contains("0x2E") || err_str.contains("NO_CREDENTIALS") {
-				log::info!("No credentials stored on device (CTAP2_ERR_NO_CREDENTIALS)");
-				return Ok(Vec::new());
-			}
-			return Err(format!("Failed to enumerate Relying Parties: {:?}", e));
-		}
+/// Makes the device refuse PIN use until the PIN is changed, for keys handed out with an initial
+/// PIN the end user has to replace.
+pub(crate) fn force_pin_change(device_path: Option<&str>, pin: &str) -> Result<(), PFError> {
+	log::info!("Forcing a PIN change on first use...");
+	let info = get_fido_info(device_path)?;
+	if info.options.get("setMinPINLength") != Some(&true) {
+		return Err(PFError::Device(
+			"The device does not support forcing a PIN change".into(),
+		));
+	}
+	let pin_token = config_pin_token(device_path, Some(pin))?;
+	let transport =
+		HidTransport::open_path(device_path).map_err(|e| ctap_error(e, "HID connection"))?;
+
+	// forceChangePin is a parameter of setMinPINLength, sent without a new minimum length
+	let mut params = BTreeMap::new();
+	params.insert(
+		Value::Integer(ConfigSubCommandParam::ForceChangePin as i128),
+		Value::Bool(true),
+	);
+	report_phase(PhaseVerb::Write, "PIN change requirement", UserAction::None);
+	transport.send_config(
+		&pin_token,
+		ConfigSubCommand::SetMinPinLength,
+		Some(Value::Map(params)),
+	)
+}
+
+/// Turns alwaysUv on or off, returning whether it changed. With alwaysUv, every operation
+/// requires user verification, even those that would otherwise only need a touch.
+pub(crate) fn set_always_uv(
+	device_path: Option<&str>,
+	pin: &str,
+	enabled: bool,
+) -> Result<bool, PFError> {
+	let info = get_fido_info(device_path)?;
+	let Some(&current) = info.options.get("alwaysUv") else {
+		return Err(PFError::Device(
+			"The device does not support alwaysUv".into(),
+		));
 	};
+	if current == enabled {
+		log::info!("alwaysUv is already {}", if enabled { "on" } else { "off" });
+		return Ok(false);
+	}
 
-	let mut all_credentials = Vec::new();
-
-	for rp in rps {
-		let creds = device
-			.credential_management_enumerate_credentials(Some(&pin), &rp.rpid_hash)
-			.map_err(|e| {
-				format!(
-					"Failed to enumerate credentials for RP {}: {:?}",
-					rp.public_key_credential_rp_entity.id, e
-				)
-			})?;
-
-		for cred in creds {
-			all_credentials.push(StoredCredential {
-				credential_id: hex::encode(&cred.public_key_credential_descriptor.id),
-				rp_id: rp.public_key_credential_rp_entity.id.clone(),
-				rp_name: rp.public_key_credential_rp_entity.name.clone(),
-				user_name: cred.public_key_credential_user_entity.name.clone(),
-				user_display_name: cred.public_key_credential_user_entity.display_name.clone(),
-				user_id: hex::encode(&cred.public_key_credential_user_entity.id).clone(),
-			});
-		}
+	log::info!("Turning alwaysUv {}...", if enabled { "on" } else { "off" });
+	let pin_token = config_pin_token(device_path, Some(pin))?;
+	let transport =
+		HidTransport::open_path(device_path).map_err(|e| ctap_error(e, "HID connection"))?;
+	// The sub command only toggles, hence the check of the current state
+	report_phase(PhaseVerb::Write, "alwaysUv setting", UserAction::None);
+	transport.send_config(&pin_token, ConfigSubCommand::ToggleAlwaysUv, None)?;
+	Ok(true)
+}
+
+pub(crate) fn get_pin_retries(device_path: Option<&str>) -> Result<i32, PFError> {
+	let device = connect(device_path)?;
+
+	report_phase(PhaseVerb::Read, "PIN retry counter", UserAction::None);
+	device
+		.get_pin_retries()
+		.map_err(|e| ctap_error(e, "PIN retries read"))
+}
+
+/// HID path of the key at `device_path`, or of the only connected one
+fn resolve_path(device_path: Option<&str>) -> Result<String, PFError> {
+	match device_path {
+		Some(path) => Ok(path.to_string()),
+		None => list_devices()?
+			.into_iter()
+			.next()
+			.map(|device| device.path)
+			.ok_or(PFError::NoDevice),
 	}
+}
 
-	Ok(all_credentials)
+/// Resident passkeys of the key. `pin` is only needed if the session holds no PIN token.
+pub(crate) fn get_credentials(
+	device_path: Option<&str>,
+	pin: Option<&str>,
+) -> Result<Vec<StoredCredential>, PFError> {
+	let path = resolve_path(device_path)?;
+	report_phase(PhaseVerb::Read, "stored passkeys", UserAction::None);
+	cred_mgmt::enumerate(&path, pin)
 }
 
-pub(crate) fn delete_credential(pin: String, credential_id_hex: String) -> Result<String, String> {
-	let cfg = Cfg::init();
-	let device = FidoKeyHidFactory::create(&cfg)
-		.map_err(|e| format!("Failed to connect to FIDO device: {:?}", e))?;
+/// Passkey slot usage, as (existing, max remaining) resident credentials
+pub(crate) fn get_creds_metadata(
+	device_path: Option<&str>,
+	pin: Option<&str>,
+) -> Result<(u32, u32), PFError> {
+	let path = resolve_path(device_path)?;
+	report_phase(PhaseVerb::Read, "passkey slot usage", UserAction::None);
+	cred_mgmt::creds_metadata(&path, pin)
+}
 
+pub(crate) fn delete_credential(
+	device_path: Option<&str>,
+	pin: Option<&str>,
+	credential_id_hex: String,
+) -> Result<String, PFError> {
 	let cred_id_bytes = hex::decode(&credential_id_hex)
-		.map_err(|_| "Invalid Credential ID Hex string".to_string())?;
+		.map_err(|_| PFError::Io("Invalid Credential ID Hex string".into()))?;
+	let path = resolve_path(device_path)?;
 
-	let descriptor = PublicKeyCredentialDescriptor {
-		ctype: "public-key".to_string(),
-		id: cred_id_bytes,
-	};
+	report_phase(PhaseVerb::Delete, "the passkey", UserAction::None);
+	cache::invalidate();
+	cred_mgmt::delete(&path, pin, &cred_id_bytes)?;
+
+	Ok("Credential deleted successfully".into())
+}
+
+/// Replaces the user name and display name stored with a resident passkey
+/// (credentialManagement updateUserInformation), keeping its user ID.
+pub(crate) fn update_credential(
+	device_path: Option<&str>,
+	pin: Option<&str>,
+	credential_id_hex: String,
+	user_name: String,
+	user_display_name: String,
+) -> Result<String, PFError> {
+	let cred_id_bytes = hex::decode(&credential_id_hex)
+		.map_err(|_| PFError::Io("Invalid Credential ID Hex string".into()))?;
+
+	// The authenticator refuses the update unless the user ID matches the stored one
+	let credential = get_credentials(device_path, pin)?
+		.into_iter()
+		.find(|c| c.credential_id.eq_ignore_ascii_case(&credential_id_hex))
+		.ok_or_else(|| {
+			PFError::Device("No passkey with this credential ID is stored on the device".into())
+		})?;
+	let user_id = hex::decode(&credential.user_id)
+		.map_err(|_| PFError::Device("Invalid user ID on the device".into()))?;
+
+	let path = resolve_path(device_path)?;
+	report_phase(PhaseVerb::Write, "the passkey user", UserAction::None);
+	cred_mgmt::update_user(
+		&path,
+		pin,
+		&cred_id_bytes,
+		&user_id,
+		&user_name,
+		&user_display_name,
+	)?;
+
+	log::info!(
+		"Updated the user of the passkey for {} to {}",
+		credential.rp_id,
+		user_name
+	);
+	Ok("Credential updated successfully".into())
+}
+
+/// Checks that the credential exists, returning what `delete_credential` would delete
+pub(crate) fn plan_delete_credential(
+	device_path: Option<&str>,
+	pin: Option<&str>,
+	credential_id_hex: String,
+) -> Result<Vec<String>, PFError> {
+	hex::decode(&credential_id_hex)
+		.map_err(|_| PFError::Io("Invalid Credential ID Hex string".into()))?;
+	let credential = get_credentials(device_path, pin)?
+		.into_iter()
+		.find(|c| c.credential_id.eq_ignore_ascii_case(&credential_id_hex))
+		.ok_or_else(|| {
+			PFError::Device("No passkey with this credential ID is stored on the device".into())
+		})?;
+
+	Ok(vec![format!(
+		"Delete the passkey of {} for {}",
+		credential.user_name, credential.rp_id
+	)])
+}
+
+/// Performs a test assertion with the credential. Requires a touch.
+fn test_assertion(
+	device: &FidoKeyHid,
+	pin: &str,
+	rp_id: &str,
+	credential_id: &[u8],
+	challenge: &[u8],
+) -> Result<Assertion, PFError> {
+	let args = GetAssertionArgsBuilder::new(rp_id, challenge)
+		.pin(pin)
+		.credential_id(credential_id)
+		.build();
+
+	report_phase(
+		PhaseVerb::Sign,
+		&format!("the passkey for {}", rp_id),
+		UserAction::TouchDevice,
+	);
 
 	device
-		.credential_management_delete_credential(Some(&pin), descriptor)
-		.map_err(|e| format!("Failed to delete credential: {:?}", e))?;
+		.get_assertion_with_args(&args)
+		.map_err(|e| pin_error(device, e, "test assertion"))?
+		.into_iter()
+		.next()
+		.ok_or_else(|| PFError::Device("Device returned no assertion".into()))
+}
 
-	Ok("Credential deleted successfully".into())
+/// Blinks the key and waits for the user to touch it (authenticatorSelection)
+pub(crate) fn confirm_presence() -> Result<(), PFError> {
+	let device = connect(None)?;
+
+	if let Err(e) = device.wink() {
+		log::debug!("Wink not supported: {}", e);
+	}
+	report_phase(PhaseVerb::Verify, "your presence", UserAction::TouchDevice);
+	device
+		.selection()
+		.map_err(|e| ctap_error(e, "presence check"))
 }
 
-// Custom Fido functions ( works only with pico-fido firmware )
+/// Performs a test assertion with the credential to read its current signature counter.
+///
+/// Requires a touch and increments the counter by one on authenticators that keep per-credential
+/// counters.
+pub(crate) fn get_credential_sign_count(
+	device_path: Option<&str>,
+	pin: String,
+	rp_id: String,
+	credential_id_hex: String,
+) -> Result<CredentialSignCount, PFError> {
+	let device = connect(device_path)?;
 
-pub fn read_device_details() -> Result<FullDeviceStatus, PFError> {
-	log::info!("Starting FIDO device details read...");
+	let cred_id_bytes = hex::decode(&credential_id_hex)
+		.map_err(|_| PFError::Io("Invalid Credential ID Hex string".into()))?;
 
-	let transport = HidTransport::open().map_err(|e| {
-		if let Some(PFError::NoDevice) = e.downcast_ref::<PFError>() {
-			PFError::NoDevice
-		} else {
-			log::error!("Failed to open HID transport: {}", e);
-			PFError::Device(e.to_string())
+	let challenge: [u8; 32] = rand::rng().random();
+	let assertion = test_assertion(&device, &pin, &rp_id, &cred_id_bytes, &challenge)?;
+
+	log::info!(
+		"Credential for {} reports signCount {}",
+		rp_id,
+		assertion.sign_count
+	);
+
+	Ok(CredentialSignCount {
+		credential_id: credential_id_hex,
+		rp_id,
+		sign_count: assertion.sign_count,
+	})
+}
+
+/// Classifies a signature counter against the last value recorded for the credential
+fn classify_sign_count(previous: Option<u32>, current: u32) -> SignCountStatus {
+	match previous {
+		None if current == 0 => SignCountStatus::NotSupported,
+		None => SignCountStatus::FirstSeen,
+		Some(0) if current == 0 => SignCountStatus::NotSupported,
+		Some(_) if current == 0 => SignCountStatus::Reset,
+		Some(prev) if current <= prev => SignCountStatus::Regressed,
+		Some(_) => SignCountStatus::Increased,
+	}
+}
+
+/// Verifies a credential with a test assertion and checks its signature counter against the
+/// local inventory.
+///
+/// A counter that did not increase since the last verification hints at a cloned authenticator
+/// or a rolled back device state.
+pub(crate) fn verify_credential(
+	device_path: Option<&str>,
+	pin: String,
+	rp_id: String,
+	credential_id_hex: String,
+) -> Result<CredentialVerification, PFError> {
+	let device = connect(device_path)?;
+
+	let cred_id_bytes = hex::decode(&credential_id_hex)
+		.map_err(|_| PFError::Io("Invalid Credential ID Hex string".into()))?;
+
+	// The public key is only exposed through credential management
+	let rp_hash = ring::digest::digest(&ring::digest::SHA256, rp_id.as_bytes());
+	let public_key = device
+		.credential_management_enumerate_credentials(Some(&pin), rp_hash.as_ref())
+		.map_err(|e| {
+			pin_error(
+				&device,
+				e,
+				&format!("credential enumeration for RP {}", rp_id),
+			)
+		})?
+		.into_iter()
+		.find(|c| c.public_key_credential_descriptor.id == cred_id_bytes)
+		.map(|c| c.public_key)
+		.ok_or_else(|| PFError::Device(format!("Credential not found for RP {}", rp_id)))?;
+
+	let challenge: [u8; 32] = rand::rng().random();
+	let assertion = test_assertion(&device, &pin, &rp_id, &cred_id_bytes, &challenge)?;
+	let signature_valid = verifier::verify_assertion(&rp_id, &public_key, &challenge, &assertion);
+	if !signature_valid {
+		log::warn!("Assertion signature for {} did not verify", rp_id);
+	}
+
+	let (previous_sign_count, status) = Inventory::update(|inventory| {
+		let previous_sign_count = inventory
+			.credentials
+			.get(&credential_id_hex)
+			.filter(|record| record.rp_id == rp_id)
+			.map(|record| record.sign_count);
+		let status = classify_sign_count(previous_sign_count, assertion.sign_count);
+
+		match status {
+			SignCountStatus::Regressed | SignCountStatus::Reset => log::warn!(
+				"signCount anomaly for {}: last recorded {:?}, device returned {}",
+				rp_id,
+				previous_sign_count,
+				assertion.sign_count
+			),
+			_ => log::info!(
+				"signCount for {} is {} ({:?})",
+				rp_id,
+				assertion.sign_count,
+				status
+			),
 		}
+
+		// Keep the highest value seen so an anomaly is reported again on the next check
+		let recorded =
+			previous_sign_count.map_or(assertion.sign_count, |prev| prev.max(assertion.sign_count));
+		inventory.record_sign_count(&credential_id_hex, &rp_id, recorded);
+		Ok((previous_sign_count, status))
 	})?;
 
-	// --- 1. Get Info ---
-	log::debug!("Sending GetInfo command (0x04)...");
-	let info_payload = [CtapCommand::GetInfo as u8];
-	let info_res = transport
-		.send_cbor(CTAPHID_CBOR, &info_payload)
-		.map_err(|e| {
-			log::error!("GetInfo CTAP command failed: {}", e);
-			PFError::Device(format!("GetInfo failed: {}", e))
-		})?;
+	Ok(CredentialVerification {
+		credential_id: credential_id_hex,
+		rp_id,
+		signature_valid,
+		sign_count: assertion.sign_count,
+		previous_sign_count,
+		sign_count_status: status,
+	})
+}
 
-	log::debug!("GetInfo response received ({} bytes)", info_res.len());
+// Custom Fido functions ( works only with pico-fido firmware )
+
+/// Probes which pico-fido vendor command categories the firmware supports
+pub fn probe_vendor_capabilities(device_path: Option<&str>) -> Result<VendorCapabilities, PFError> {
+	let transport =
+		HidTransport::open_path(device_path).map_err(|e| ctap_error(e, "HID connection"))?;
+	report_phase(PhaseVerb::Read, "firmware capabilities", UserAction::None);
+	vendor::probe_capabilities(&transport)
+}
+
+/// Sends a raw vendor command for the developer console, see `vendor::send_raw`
+pub fn send_raw_vendor(
+	device_path: Option<&str>,
+	command: u8,
+	cbor: &[u8],
+) -> Result<RawVendorResponse, PFError> {
+	let transport =
+		HidTransport::open_path(device_path).map_err(|e| ctap_error(e, "HID connection"))?;
+	report_phase(PhaseVerb::Write, "raw vendor command", UserAction::None);
+	vendor::send_raw(&transport, command, cbor)
+}
 
-	let info_val: Value = from_slice(&info_res).map_err(|e| {
+/// AAGUID and firmware version from a GetInfo response
+fn parse_get_info(info_res: &[u8]) -> Result<(String, String), PFError> {
+	let info_val: Value = from_slice(info_res).map_err(|e| {
 		log::error!("Failed to parse GetInfo CBOR: {}", e);
 		PFError::Io(e.to_string())
 	})?;
@@ -250,57 +834,100 @@ pub fn read_device_details() -> Result<FullDeviceStatus, PFError> {
 		"Unknown".into()
 	};
 
-	log::info!(
-		"Device identified: AAGUID={}, FW={}",
-		aaguid_str,
-		fw_version
-	);
+	Ok((aaguid_str, fw_version))
+}
 
-	// --- 2. Get Memory Stats ---
-	log::debug!("Preparing Memory Stats vendor command...");
+/// Runs one step of a multi-step read, recording how long it took
+fn timed<T>(
+	timings: &mut Vec<(&'static str, Duration)>,
+	step: &'static str,
+	f: impl FnOnce() -> T,
+) -> T {
+	let started = Instant::now();
+	let result = f();
+	timings.push((step, started.elapsed()));
+	result
+}
 
-	// FIX: The CBOR map should only contain the arguments ({1: 1}), not the command category.
-	let mut mem_req = BTreeMap::new();
-	mem_req.insert(
-		Value::Integer(1), // Sub-command key (usually 1)
-		Value::Integer(MemorySubCommand::GetStats as i128),
-	);
+pub fn read_device_details(device_path: Option<&str>) -> Result<FullDeviceStatus, PFError> {
+	log::info!("Starting FIDO device details read...");
+	report_phase(PhaseVerb::Read, "device configuration", UserAction::None);
+	let started = Instant::now();
+	let mut timings = Vec::new();
 
-	let mem_cbor = to_vec(&Value::Map(mem_req)).map_err(|e| {
-		log::error!("Failed to encode Memory Stats CBOR: {}", e);
-		PFError::Io(format!("CBOR encode error: {}", e))
+	// All exchanges below run back to back on this one initialized channel
+	let transport = timed(&mut timings, "open", || {
+		HidTransport::open_path(device_path)
+	})
+	.map_err(|e| {
+		log::error!("Failed to open HID transport: {}", e);
+		ctap_error(e, "HID connection")
 	})?;
 
-	// FIX: Prepend the Vendor Command ID (0x06 for Memory) to the payload
-	// The firmware expects: [VendorCmdByte] [CBOR Map]
-	let mut mem_payload = vec![VendorCommand::Memory as u8];
-	mem_payload.extend(mem_cbor);
-
-	log::debug!("Sending Memory Stats command...");
-	let mem_res = transport
-		.send_cbor(CTAP_VENDOR_CBOR_CMD, &mem_payload)
+	let mut status = read_status(&transport, &mut timings)?;
+
+	// Secure boot state lives in the OTP and is only readable through the Rescue Applet, which
+	// cannot be matched to a HID path. Only ask it when this is the only key connected.
+	let only_key = list_devices().is_ok_and(|devices| devices.len() == 1);
+	if only_key {
+		(status.secure_boot, status.secure_lock) = timed(
+			&mut timings,
+			"secure boot",
+			crate::rescue::read_secure_boot_status,
+		)
 		.unwrap_or_else(|e| {
-			log::warn!("Failed to fetch memory stats (Vendor Cmd): {}", e);
-			Vec::new()
+			log::warn!("Failed to read secure boot status: {}", e);
+			(false, false)
 		});
+	}
 
-	let mem_map: BTreeMap<i128, i128> = if !mem_res.is_empty() {
-		from_slice(&mem_res).unwrap_or_else(|e| {
-			log::error!("Failed to parse Memory Stats CBOR response: {}", e);
-			BTreeMap::new()
-		})
-	} else {
-		BTreeMap::new()
-	};
+	let steps: Vec<String> = timings
+		.iter()
+		.map(|(step, elapsed)| format!("{} {}ms", step, elapsed.as_millis()))
+		.collect();
+	log::info!(
+		"Successfully read all device details in {}ms ({})",
+		started.elapsed().as_millis(),
+		steps.join(", ")
+	);
+	Ok(status)
+}
+
+/// Reads the device status over `transport`, leaving out the secure boot state
+fn read_status(
+	transport: &impl Transport,
+	timings: &mut Vec<(&'static str, Duration)>,
+) -> Result<FullDeviceStatus, PFError> {
+	// --- 1. Get Info ---
+	log::debug!("Sending GetInfo command (0x04)...");
+	let info_payload = [CtapCommand::GetInfo as u8];
+	let info_res = timed(timings, "GetInfo", || {
+		transport.send_cbor(CTAPHID_CBOR, &info_payload)
+	})
+	.map_err(|e| {
+		log::error!("GetInfo CTAP command failed: {}", e);
+		PFError::Device(format!("GetInfo failed: {}", e))
+	})?;
+
+	log::debug!("GetInfo response received ({} bytes)", info_res.len());
+
+	let (aaguid_str, fw_version) = parse_get_info(&info_res)?;
+
+	log::info!(
+		"Device identified: AAGUID={}, FW={}",
+		aaguid_str,
+		fw_version
+	);
 
-	let used = mem_map
-		.get(&(MemoryResponseKey::UsedSpace as i128))
-		.cloned()
-		.unwrap_or(0) as u32;
-	let total = mem_map
-		.get(&(MemoryResponseKey::TotalSpace as i128))
-		.cloned()
-		.unwrap_or(0) as u32;
+	// --- 2. Get Memory Stats ---
+	let memory = timed(timings, "memory", || {
+		cache::MEMORY_STATS.get_or_fetch(transport.path(), || vendor::memory_stats(transport))
+	})
+	.unwrap_or_else(|e| {
+		vendor::log_unavailable("memory stats", &e);
+		cache::MemoryStats::default()
+	});
+	let (used, total) = (memory.used, memory.total);
 
 	log::debug!(
 		"Memory Stats: Used={}KB, Total={}KB",
@@ -308,187 +935,727 @@ pub fn read_device_details() -> Result<FullDeviceStatus, PFError> {
 		total / 1024
 	);
 
-	// --- 3. Get Physical Config ---
-	log::debug!("Preparing Physical Config vendor command...");
-
-	// FIX: Only arguments in CBOR map
-	let mut phy_params = BTreeMap::new();
-	phy_params.insert(
-		Value::Integer(1), // Sub-command key
-		Value::Integer(PhysicalOptionsSubCommand::GetOptions as i128),
+	// --- 3. Serial number, from the USB descriptor ---
+	let serial = transport.serial_number().map_or_else(
+		|| {
+			log::info!("Serial number not available over HID");
+			"?".to_string()
+		},
+		str::to_string,
 	);
 
-	// Note: The previous code nested this inside another map with key 2.
-	// Based on cbor_vendor.c, we usually just send the sub-command params directly
-	// or wrapped depending on the specific vendor command logic.
-	// For 'PhysicalOptions', looking at cbor_vendor.c, it expects a map where key 1 is subcommand.
-	// So the map we built above `phy_params` ( {1: GetOptions} ) is correct as the top-level CBOR.
-
-	let phy_cbor = to_vec(&Value::Map(phy_params)).map_err(|e| {
-		log::error!("Failed to encode Physical Config CBOR: {}", e);
-		PFError::Io(format!("CBOR encode error: {}", e))
-	})?;
-
-	// FIX: Prepend Vendor Command ID (0x05 for PhysicalOptions)
-	let mut phy_payload = vec![VendorCommand::PhysicalOptions as u8];
-	phy_payload.extend(phy_cbor);
-
-	log::debug!("Sending Physical Config command...");
-	let phy_res = transport
-		.send_cbor(CTAP_VENDOR_CBOR_CMD, &phy_payload)
-		.unwrap_or_else(|e| {
-			log::warn!("Failed to fetch physical config (Vendor Cmd): {}", e);
-			Vec::new()
-		});
+	// --- 4. Get Physical Config ---
+	let phy = timed(timings, "physical options", || {
+		cache::PHYSICAL_OPTIONS
+			.get_or_fetch(transport.path(), || vendor::physical_options(transport))
+	})
+	.unwrap_or_else(|e| {
+		vendor::log_unavailable("physical config", &e);
+		cache::PhysicalOptions::default()
+	});
 
 	let mut config = AppConfig {
-		vid: format!("{:04X}", transport.vid),
-		pid: format!("{:04X}", transport.pid),
-		product_name: transport.product_name.clone(),
+		vid: format!("{:04X}", transport.vid()),
+		pid: format!("{:04X}", transport.pid()),
+		product_name: transport.product_name().to_string(),
 		..Default::default()
 	};
-
-	if let Ok(Value::Map(m)) = from_slice(&phy_res) {
-		log::debug!("Parsed Physical Config map successfully");
-		// These keys might need adjustment based on exact firmware response structure
-		// usually they are integer keys in CBOR, but if your firmware returns text keys:
-		if let Some(Value::Integer(v)) = m.get(&Value::Text("gpio".into())) {
-			config.led_gpio = *v as u8;
-		}
-		if let Some(Value::Integer(v)) = m.get(&Value::Text("brightness".into())) {
-			config.led_brightness = *v as u8;
-		}
-	} else if !phy_res.is_empty() {
-		log::warn!("Physical config response was not a valid CBOR map or empty");
-	}
-
-	log::info!("Successfully read all device details.");
+	// The PHY override takes precedence over the USB descriptors, which only change at the
+	// next plug-in
+	phy.apply_to(&mut config);
 
 	Ok(FullDeviceStatus {
 		info: DeviceInfo {
-			serial: "?".to_string(), // Serial number is not available through fido. Previous code was using AAGUID as serial but it is too long to display in place of serial it is already displayed somewhere else.
+			serial,
 			flash_used: used / 1024,
 			flash_total: total / 1024,
+			flash_used_bytes: used,
+			flash_total_bytes: total,
+			firmware: FirmwareVersion::parse(&fw_version),
 			firmware_version: fw_version,
 		},
+		flash: memory.breakdown(),
 		config,
 		secure_boot: false,
 		secure_lock: false,
 		method: "FIDO".to_string(),
+		format_hints: FormatHints::default(),
 	})
 }
 
-pub fn write_config(config: AppConfigInput, pin: Option<String>) -> Result<String, PFError> {
-	log::info!("Starting FIDO write_config...");
+/// Time after power-up in which the firmware accepts authenticatorReset, with some margin for
+/// the hotplug polling delay
+const RESET_WINDOW: Duration = Duration::from_secs(8);
+const REPLUG_TIMEOUT: Duration = Duration::from_secs(60);
+const REPLUG_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Asks the user to unplug and replug the key at `path`, returning the HID path it comes back at
+fn wait_for_replug(path: &str) -> Result<String, PFError> {
+	report_phase(
+		PhaseVerb::Delete,
+		"all FIDO credentials",
+		UserAction::Reconnect,
+	);
+	let mut api = hidapi::HidApi::new().map_err(|e| PFError::Device(e.to_string()))?;
+	let deadline = Instant::now() + REPLUG_TIMEOUT;
+	let mut removed = false;
+	while Instant::now() < deadline {
+		if cancel_requested() {
+			return Err(PFError::Cancelled);
+		}
+		std::thread::sleep(REPLUG_POLL_INTERVAL);
+		if api.refresh_devices().is_err() {
+			continue;
+		}
+		let devices = enumerate_devices(&api);
+		if !removed {
+			removed = devices.iter().all(|d| d.path != path);
+		} else if let Some(device) = devices.into_iter().next() {
+			log::info!("Key reconnected at {}", device.path);
+			return Ok(device.path);
+		}
+	}
+	Err(PFError::UserPresenceTimeout)
+}
 
-	let pin_val = pin.as_deref().ok_or_else(|| {
-		log::error!("PIN is required for configuration");
-		PFError::Device("PIN is required for configuration".into())
-	})?;
+/// Turns a touch timeout of the operation bound to the key at `path` into `WrongDevice` when
+/// other keys are connected, as the user most likely touched one of those. The bound key is
+/// blinked so the user can tell it apart on a crowded hub.
+fn attention_on_timeout(path: &str, e: PFError) -> PFError {
+	if !matches!(e, PFError::UserPresenceTimeout) {
+		return e;
+	}
+	let Ok(devices) = list_devices() else {
+		return e;
+	};
+	if devices.len() < 2 {
+		return e;
+	}
+	let Some(device) = devices.into_iter().find(|d| d.path == path) else {
+		return e;
+	};
 
-	// 1. Obtain PIN token using the library handle
-	let pin_token = {
-		let cfg = Cfg::init();
-		let device = FidoKeyHidFactory::create(&cfg)
-			.map_err(|e| PFError::Device(format!("Could not connect to FIDO device: {:?}", e)))?;
+	log::warn!(
+		"Touch timed out on {} ({}) with other keys connected",
+		device.product_name,
+		path
+	);
+	if let Err(e) = open_device(Some(path)).and_then(|key| key.wink()) {
+		log::debug!("Wink not supported: {}", e);
+	}
+	PFError::WrongDevice {
+		product_name: device.product_name,
+		port_path: usb_port_path(path),
+	}
+}
 
-		use ctap_hid_fido2::fidokey::pin::Permission;
-		// Try to obtain a token with AuthenticatorConfiguration permission (CTAP 2.1)
-		match device
-			.get_pinuv_auth_token_with_permission(pin_val, Permission::AuthenticatorConfiguration)
-		{
-			Ok(token) => {
-				log::debug!("Successfully obtained PIN token with ACFG permission.");
-				token.key
-			}
-			Err(e) => {
-				log::warn!(
-					"Failed to get PIN token with ACFG permission (Error: {:?}). Falling back to standard token.",
-					e
-				);
-				// Fallback to standard PIN token (Subcommand 0x05)
-				let token = device.get_pin_token(pin_val).map_err(|e2| {
-					log::error!("Failed to obtain even a standard PIN token: {:?}", e2);
-					PFError::Device(format!("PIN token acquisition failed: {:?}", e2))
-				})?;
-				log::debug!("Successfully obtained standard PIN token (fallback).");
-				token.key
-			}
+/// Checks that the key can be found, returning what `reset_device` would do
+pub fn plan_reset_device(device_path: Option<&str>) -> Result<Vec<String>, PFError> {
+	let path = match device_path {
+		Some(path) => path.to_string(),
+		None => list_devices()?
+			.into_iter()
+			.next()
+			.map(|device| device.path)
+			.ok_or(PFError::NoDevice)?,
+	};
+	let info = get_fido_info(Some(&path))?;
+
+	let mut actions = Vec::new();
+	if watcher::plugged_in_at(&path).is_none_or(|at| at.elapsed() >= RESET_WINDOW) {
+		actions.push("Ask to replug the key, it only accepts a reset right after power-up".into());
+	}
+	actions.push("Ask to touch the key".into());
+	actions.push(format!(
+		"Delete all FIDO credentials{} and reset the minimum PIN length",
+		if info.options.get("clientPin") == Some(&true) {
+			" and the PIN"
+		} else {
+			""
 		}
-		// Library handle 'device' is dropped here, closing the HID session.
+	));
+	Ok(actions)
+}
+
+/// Factory resets the FIDO application (authenticatorReset), deleting all credentials and the PIN.
+///
+/// The firmware only accepts the reset within 10 seconds of power-up. Unless the key was plugged
+/// in just now, the user is asked to replug it first. The key then asks for a touch, which is
+/// reported as a phase once the firmware signals it waits for it.
+///
+/// Both waits end early when the operation is cancelled, the touch by sending CTAPHID_CANCEL.
+pub fn reset_device(device_path: Option<&str>, confirm: bool) -> Result<(), PFError> {
+	if !confirm {
+		return Err(PFError::Device("Factory reset was not confirmed".into()));
+	}
+	log::info!("Starting FIDO authenticatorReset...");
+
+	let path = match device_path {
+		Some(path) => path.to_string(),
+		None => list_devices()?
+			.into_iter()
+			.next()
+			.map(|device| device.path)
+			.ok_or(PFError::NoDevice)?,
+	};
+	let path = match watcher::plugged_in_at(&path) {
+		Some(at) if at.elapsed() < RESET_WINDOW => path,
+		_ => wait_for_replug(&path)?,
 	};
 
-	// 2. Open custom HidTransport and send vendor commands using the token
-	let transport = HidTransport::open().map_err(|e| {
-		log::error!("Failed to open HID transport: {}", e);
-		PFError::Device(format!("Could not open HID transport: {}", e))
-	})?;
+	let transport =
+		HidTransport::open_path(Some(&path)).map_err(|e| ctap_error(e, "HID connection"))?;
+
+	cache::invalidate();
+	report_phase(PhaseVerb::Delete, "all FIDO credentials", UserAction::None);
+	transport
+		.send_cbor(CTAPHID_CBOR, &[CtapCommand::Reset as u8])
+		.map_err(|e| match e.downcast_ref::<CtapStatus>() {
+			Some(CtapStatus(status)) if *status == Ctap2Error::NotAllowed as u8 => {
+				PFError::ResetNotAllowed
+			}
+			_ => attention_on_timeout(&path, ctap_error(e, "Reset")),
+		})?;
+	log::info!("FIDO application reset");
+	Ok(())
+}
+
+/// Vendor configuration command sent by `write_config`
+struct ConfigStep {
+	command: VendorConfigCommand,
+	value: i128,
+	description: String,
+	/// Failures are ignored, the firmware may not support it
+	optional: bool,
+}
+
+/// Vendor configuration commands writing `config`, in the order they are sent
+fn config_steps(config: &AppConfigInput) -> Result<Vec<ConfigStep>, PFError> {
+	let mut steps = Vec::new();
+	let mut step = |command, value: i128, description: String, optional| {
+		steps.push(ConfigStep {
+			command,
+			value,
+			description,
+			optional,
+		})
+	};
+
+	// Same parsing and validation as the PHY blob the Rescue Applet writes
+	let phy = PhyConfig::from_input(config)?;
 
 	// VID/PID config
-	if let (Some(vid_str), Some(pid_str)) = (&config.vid, &config.pid) {
-		let vid = u16::from_str_radix(vid_str, 16).map_err(|e| PFError::Io(e.to_string()))?;
-		let pid = u16::from_str_radix(pid_str, 16).map_err(|e| PFError::Io(e.to_string()))?;
+	if let Some((vid, pid)) = phy.vid_pid {
 		let vidpid = ((vid as u32) << 16) | (pid as u32);
-		transport.send_vendor_config(
-			&pin_token,
+		step(
 			VendorConfigCommand::PhysicalVidPid,
-			Value::Integer(vidpid as i128),
-		)?;
+			vidpid as i128,
+			format!("Set VID:PID to {:04X}:{:04X}", vid, pid),
+			false,
+		);
 	}
 
 	// LED GPIO config
-	if let Some(gpio) = config.led_gpio {
-		transport.send_vendor_config(
-			&pin_token,
+	if let Some(gpio) = phy.led_gpio {
+		step(
 			VendorConfigCommand::PhysicalLedGpio,
-			Value::Integer(gpio as i128),
-		)?;
+			gpio as i128,
+			format!("Set LED GPIO to {}", gpio),
+			false,
+		);
 	}
 
 	// LED brightness config
-	if let Some(brightness) = config.led_brightness {
-		transport.send_vendor_config(
-			&pin_token,
+	if let Some(brightness) = phy.led_brightness {
+		step(
 			VendorConfigCommand::PhysicalLedBrightness,
-			Value::Integer(brightness as i128),
-		)?;
+			brightness as i128,
+			format!("Set LED brightness to {}", brightness),
+			false,
+		);
 	}
 
-	// Options config
-	let mut opts = 0u16;
-	if config.led_dimmable.unwrap_or(false) {
-		opts |= 0x02; // PHY_OPT_DIMM
-	}
-	if !config.power_cycle_on_reset.unwrap_or(true) {
-		opts |= 0x04; // PHY_OPT_DISABLE_POWER_RESET
-	}
-	if config.led_steady.unwrap_or(false) {
-		opts |= 0x08; // PHY_OPT_LED_STEADY
-	}
+	// Options config, always written as a whole with the defaults for options left out
+	let mut opts = PhyOptions::empty();
+	opts.set(
+		PhyOptions::LED_DIMMABLE,
+		config.led_dimmable.unwrap_or(false),
+	);
+	opts.set(
+		PhyOptions::DISABLE_POWER_RESET,
+		!config.power_cycle_on_reset.unwrap_or(true),
+	);
+	opts.set(PhyOptions::LED_STEADY, config.led_steady.unwrap_or(false));
 	// Touch_timeout config
 	if let Some(timeout) = config.touch_timeout {
 		// In the firmware's phy_data, touch_timeout is often part of opts or separate.
 		// Looking at the previous code, it was separate (0x08).
 		// However, in vendor configuration, we usually send parameters individually.
-		transport
-			.send_vendor_config(
-				&pin_token,
-				VendorConfigCommand::PhysicalOptions, // Assuming there's a command for it or it's in opts
-				Value::Integer(timeout as i128),      // Wait, let's check VendorConfigCommand again
-			)
-			.ok(); // If it fails, maybe it's not supported as a standalone vendor cmd
+		step(
+			VendorConfigCommand::PhysicalOptions, // Assuming there's a command for it or it's in opts
+			timeout as i128,                      // Wait, let's check VendorConfigCommand again
+			format!("Set touch timeout to {} s, if supported", timeout),
+			true, // If it fails, maybe it's not supported as a standalone vendor cmd
+		);
 	}
 
-	transport.send_vendor_config(
-		&pin_token,
+	step(
 		VendorConfigCommand::PhysicalOptions,
-		Value::Integer(opts as i128),
-	)?;
+		opts.bits() as i128,
+		format!("Set PHY options to 0x{:04X}", opts.bits()),
+		false,
+	);
+	Ok(steps)
+}
+
+/// PIN token for configuring the device, with the AuthenticatorConfiguration permission if the
+/// device supports it
+fn config_pin_token(device_path: Option<&str>, pin: Option<&str>) -> Result<Vec<u8>, PFError> {
+	let pin_val = pin.ok_or(PFError::PinRequired)?;
+	let device = connect(device_path)?;
+
+	use ctap_hid_fido2::fidokey::pin::Permission;
+	// Try to obtain a token with AuthenticatorConfiguration permission (CTAP 2.1)
+	match device
+		.get_pinuv_auth_token_with_permission(pin_val, Permission::AuthenticatorConfiguration)
+	{
+		Ok(token) => {
+			log::debug!("Successfully obtained PIN token with ACFG permission.");
+			Ok(token.key)
+		}
+		// Only keys without CTAP 2.1 tokens get the fallback, a wrong PIN is not sent twice
+		Err(e) => match pin_error(&device, e, "PIN token") {
+			PFError::VendorUnsupported(_) => {
+				log::warn!("No PIN token with ACFG permission, falling back to standard token.");
+				// Fallback to standard PIN token (Subcommand 0x05)
+				let token = device
+					.get_pin_token(pin_val)
+					.map_err(|e| pin_error(&device, e, "PIN token"))?;
+				log::debug!("Successfully obtained standard PIN token (fallback).");
+				Ok(token.key)
+			}
+			e => Err(e),
+		},
+	}
+	// Library handle 'device' is dropped here, closing the HID session.
+}
+
+/// Validates `config`, describing the commands `write_config` would send
+pub fn describe_config(config: &AppConfigInput) -> Result<Vec<String>, PFError> {
+	Ok(config_steps(config)?
+		.into_iter()
+		.map(|step| step.description)
+		.collect())
+}
+
+/// Validates `config` and the PIN, returning the commands `write_config` would send without
+/// sending them
+pub fn plan_write_config(
+	device_path: Option<&str>,
+	config: &AppConfigInput,
+	pin: Option<&str>,
+) -> Result<Vec<String>, PFError> {
+	let actions = describe_config(config)?;
+	report_phase(PhaseVerb::Authenticate, "configuration", UserAction::None);
+	config_pin_token(device_path, pin)?;
+	Ok(actions)
+}
+
+/// Compares a setting written by `write_config` with the values read back before and after
+fn check_field<T: PartialEq + ToString>(
+	field: &str,
+	requested: T,
+	before: Option<T>,
+	after: Option<T>,
+) -> WriteFieldResult {
+	let status = match &after {
+		None => WriteFieldStatus::Unverified,
+		Some(after) if *after == requested => WriteFieldStatus::Applied,
+		Some(after) if before.as_ref() == Some(after) => WriteFieldStatus::Rejected,
+		Some(_) => WriteFieldStatus::Clamped,
+	};
+	WriteFieldResult {
+		field: field.to_string(),
+		requested: requested.to_string(),
+		actual: after.map(|value| value.to_string()),
+		status,
+	}
+}
+
+/// Settings of `config` as the device reports them after the write
+fn verify_write(
+	config: &AppConfigInput,
+	before: &cache::PhysicalOptions,
+	after: &cache::PhysicalOptions,
+) -> Vec<WriteFieldResult> {
+	let mut fields = Vec::new();
+	let vid_pid = |phy: &cache::PhysicalOptions| {
+		phy.vid_pid
+			.map(|(vid, pid)| format!("{:04X}:{:04X}", vid, pid))
+	};
+	if let (Some(vid), Some(pid)) = (&config.vid, &config.pid) {
+		fields.push(check_field(
+			"vidPid",
+			format!("{}:{}", vid.to_uppercase(), pid.to_uppercase()),
+			vid_pid(before),
+			vid_pid(after),
+		));
+	}
+	if let Some(gpio) = config.led_gpio {
+		fields.push(check_field(
+			"ledGpio",
+			gpio,
+			before.led_gpio,
+			after.led_gpio,
+		));
+	}
+	if let Some(brightness) = config.led_brightness {
+		fields.push(check_field(
+			"ledBrightness",
+			brightness,
+			before.led_brightness,
+			after.led_brightness,
+		));
+	}
+	if let Some(timeout) = config.touch_timeout {
+		fields.push(check_field(
+			"touchTimeout",
+			timeout,
+			before.presence_timeout,
+			after.presence_timeout,
+		));
+	}
+
+	// The options word is always written, with the defaults for options left out
+	let option = |phy: &cache::PhysicalOptions, flag: PhyOptions, set: bool| {
+		phy.opts.map(|opts| opts.contains(flag) == set)
+	};
+	let options = [
+		(
+			"ledDimmable",
+			config.led_dimmable.unwrap_or(false),
+			PhyOptions::LED_DIMMABLE,
+			true,
+		),
+		(
+			"powerCycleOnReset",
+			config.power_cycle_on_reset.unwrap_or(true),
+			PhyOptions::DISABLE_POWER_RESET,
+			false,
+		),
+		(
+			"ledSteady",
+			config.led_steady.unwrap_or(false),
+			PhyOptions::LED_STEADY,
+			true,
+		),
+	];
+	for (field, requested, flag, set) in options {
+		fields.push(check_field(
+			field,
+			requested,
+			option(before, flag, set),
+			option(after, flag, set),
+		));
+	}
+	fields
+}
+
+/// Writes `config` and reads the physical options back, reporting the settings the firmware
+/// rejected or clamped
+pub fn write_config(
+	device_path: Option<&str>,
+	config: AppConfigInput,
+	pin: Option<String>,
+) -> Result<WriteReport, PFError> {
+	log::info!("Starting FIDO write_config...");
+	cache::invalidate();
+	report_phase(
+		PhaseVerb::Write,
+		"device configuration",
+		UserAction::TouchDevice,
+	);
+
+	let steps = config_steps(&config)?;
+
+	// 1. Obtain PIN token using the library handle
+	let pin_token = config_pin_token(device_path, pin.as_deref())?;
+
+	// 2. Open custom HidTransport and send vendor commands using the token
+	let transport =
+		HidTransport::open_path(device_path).map_err(|e| ctap_error(e, "HID connection"))?;
+
+	write_steps(&transport, &pin_token, &config, steps)
+}
+
+/// Sends the `steps` writing `config` over `transport` and verifies them by reading the
+/// physical options back
+fn write_steps(
+	transport: &impl Transport,
+	pin_token: &[u8],
+	config: &AppConfigInput,
+	steps: Vec<ConfigStep>,
+) -> Result<WriteReport, PFError> {
+	// Tells settings the firmware left alone from those it changed to another value
+	let before = vendor::physical_options(transport).unwrap_or_else(|e| {
+		log::warn!("Failed to read physical options before the write: {}", e);
+		cache::PhysicalOptions::default()
+	});
+
+	for step in steps {
+		let result =
+			transport.send_vendor_config(pin_token, step.command, Value::Integer(step.value));
+		match result {
+			Err(e) if step.optional => log::debug!("{} failed: {}", step.description, e),
+			result => result?,
+		}
+	}
 
 	// ToDo : Product name configuration is not implemented in pico-fido firmware (cbor_config.c)?
 
-	Ok(
+	// 3. Read the configuration back
+	report_phase(PhaseVerb::Verify, "device configuration", UserAction::None);
+	let after = vendor::physical_options(transport).unwrap_or_else(|e| {
+		log::warn!("Failed to read physical options back: {}", e);
+		cache::PhysicalOptions::default()
+	});
+	let fields = verify_write(config, &before, &after);
+	let issues: Vec<String> = fields
+		.iter()
+		.filter_map(|field| {
+			let actual = field.actual.as_deref().unwrap_or("?");
+			match field.status {
+				WriteFieldStatus::Rejected => Some(format!(
+					"{} was rejected, it is still {}",
+					field.field, actual
+				)),
+				WriteFieldStatus::Clamped => Some(format!(
+					"{} was set to {} instead of {}",
+					field.field, actual, field.requested
+				)),
+				_ => None,
+			}
+		})
+		.collect();
+
+	let message = if issues.is_empty() {
 		"Configuration updated successfully! Unplug and re-plug the device to apply VID/PID changes."
-			.to_string(),
-	)
+			.to_string()
+	} else {
+		log::warn!(
+			"Device did not apply the whole configuration: {}",
+			issues.join("; ")
+		);
+		format!(
+			"Configuration written, but the device did not apply all of it: {}",
+			issues.join("; ")
+		)
+	};
+	Ok(WriteReport {
+		message,
+		fields,
+		issues,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::transport::MockTransport;
+	use super::*;
+
+	fn map(entries: Vec<(Value, Value)>) -> Value {
+		Value::Map(entries.into_iter().collect())
+	}
+
+	fn int(value: i128) -> Value {
+		Value::Integer(value)
+	}
+
+	fn key(key: impl ProtocolConstant) -> Value {
+		Value::Integer(key.value().unwrap() as i128)
+	}
+
+	fn get_info() -> Value {
+		map(vec![
+			(int(0x03), Value::Bytes(vec![0xAA; 16])),
+			(int(0x0E), int(0x0702)),
+		])
+	}
+
+	fn phy(gpio: i128) -> Value {
+		map(vec![
+			(Value::Text("vidpid".into()), int(0xCAFE_4242)),
+			(Value::Text("gpio".into()), int(gpio)),
+		])
+	}
+
+	fn input() -> AppConfigInput {
+		AppConfigInput {
+			vid: None,
+			pid: None,
+			product_name: None,
+			led_gpio: None,
+			led_brightness: None,
+			touch_timeout: None,
+			led_driver: None,
+			led_dimmable: None,
+			power_cycle_on_reset: None,
+			led_steady: None,
+			enable_secp256k1: None,
+		}
+	}
+
+	#[test]
+	fn read_status_decodes_vendor_responses() {
+		let transport = MockTransport::new("mock-read-status")
+			.serial_number("E6613897")
+			.respond(get_info())
+			.respond(map(vec![
+				(key(MemoryResponseKey::UsedSpace), int(8192)),
+				(key(MemoryResponseKey::TotalSpace), int(1_048_576)),
+			]))
+			.respond(phy(16));
+
+		let status = read_status(&transport, &mut Vec::new()).unwrap();
+		assert!(transport.exhausted());
+		assert_eq!(status.info.serial, "E6613897");
+		assert_eq!(status.info.firmware_version, "7.2");
+		assert_eq!(status.info.flash_used, 8);
+		assert_eq!(status.info.flash_total_bytes, 1_048_576);
+		assert_eq!(status.config.vid, "CAFE");
+		assert_eq!(status.config.pid, "4242");
+		assert_eq!(status.config.led_gpio, 16);
+		assert_eq!(status.method, "FIDO");
+		// Firmware that leaves out the free space and file count gives no breakdown
+		assert_eq!(status.flash, None);
+
+		let requests = transport.requests();
+		assert_eq!(
+			requests[0],
+			(CTAPHID_CBOR, vec![CtapCommand::GetInfo as u8])
+		);
+		assert_eq!(
+			requests[1],
+			(
+				CTAP_VENDOR_CBOR_CMD,
+				vendor_command_payload(VendorCommand::Memory, MemorySubCommand::GetStats as i128)
+					.unwrap()
+			)
+		);
+	}
+
+	#[test]
+	fn read_status_decodes_flash_breakdown() {
+		let transport = MockTransport::new("mock-read-status-flash")
+			.respond(get_info())
+			.respond(map(vec![
+				(key(MemoryResponseKey::FreeSpace), int(1_040_384)),
+				(key(MemoryResponseKey::UsedSpace), int(8192)),
+				(key(MemoryResponseKey::TotalSpace), int(1_048_576)),
+				(key(MemoryResponseKey::NumFiles), int(12)),
+				(key(MemoryResponseKey::FlashSize), int(4_194_304)),
+			]))
+			.fail(Ctap2Error::InvalidCommand as u8);
+
+		let status = read_status(&transport, &mut Vec::new()).unwrap();
+		let flash = status.flash.unwrap();
+		assert_eq!(flash.free, 1_040_384);
+		assert_eq!(flash.used, 8192);
+		assert_eq!(flash.files, 12);
+		assert_eq!(flash.reserved, 4_194_304 - 1_048_576);
+	}
+
+	#[test]
+	fn read_status_falls_back_without_vendor_commands() {
+		let transport = MockTransport::new("mock-read-status-stock")
+			.respond(get_info())
+			.fail(Ctap2Error::InvalidCommand as u8)
+			.fail(Ctap2Error::InvalidCommand as u8);
+
+		let status = read_status(&transport, &mut Vec::new()).unwrap();
+		assert_eq!(status.info.serial, "?");
+		assert_eq!(status.info.flash_total, 0);
+		// The USB descriptors stand in for the PHY configuration
+		assert_eq!(status.config.vid, "2E8A");
+		assert_eq!(status.config.product_name, "Mock Pico Key");
+	}
+
+	#[test]
+	fn read_status_fails_without_get_info() {
+		let transport =
+			MockTransport::new("mock-read-status-dead").fail(Ctap2Error::InvalidCbor as u8);
+		assert!(matches!(
+			read_status(&transport, &mut Vec::new()),
+			Err(PFError::Device(_))
+		));
+	}
+
+	#[test]
+	fn write_steps_signs_each_step_and_reports_rejected_fields() {
+		let token = [0x42; 32];
+		let config = AppConfigInput {
+			led_gpio: Some(16),
+			..input()
+		};
+		let steps = config_steps(&config).unwrap();
+		let sent = steps.len();
+		let mut transport = MockTransport::new("mock-write").respond(phy(25));
+		for _ in 0..sent {
+			transport = transport.respond_raw(Vec::new());
+		}
+		transport = transport.respond(phy(25));
+
+		let report = write_steps(&transport, &token, &config, steps).unwrap();
+		assert!(transport.exhausted());
+		assert_eq!(report.issues, vec!["ledGpio was rejected, it is still 25"]);
+		assert!(matches!(
+			report.fields[0].status,
+			WriteFieldStatus::Rejected
+		));
+
+		let requests = transport.requests();
+		assert_eq!(requests.len(), sent + 2);
+		assert_eq!(
+			requests[1],
+			(
+				CTAPHID_CBOR,
+				vendor_config_payload(&token, VendorConfigCommand::PhysicalLedGpio, int(16))
+					.unwrap()
+			)
+		);
+	}
+
+	#[test]
+	fn write_steps_ignores_failed_optional_steps() {
+		let config = AppConfigInput {
+			touch_timeout: Some(15),
+			..input()
+		};
+		let steps = config_steps(&config).unwrap();
+		assert!(steps[0].optional);
+		let transport = MockTransport::new("mock-write-optional")
+			.respond(phy(25))
+			.fail(Ctap2Error::InvalidCommand as u8)
+			.respond_raw(Vec::new())
+			.respond(phy(25));
+
+		let report = write_steps(&transport, &[0x42; 32], &config, steps).unwrap();
+		assert!(transport.exhausted());
+		assert!(report.issues.is_empty());
+	}
+
+	#[test]
+	fn write_steps_stops_at_failed_required_step() {
+		let config = AppConfigInput {
+			led_gpio: Some(16),
+			..input()
+		};
+		let steps = config_steps(&config).unwrap();
+		let transport = MockTransport::new("mock-write-failed")
+			.respond(phy(25))
+			.fail(Ctap2Error::PinAuthInvalid as u8);
+
+		assert!(write_steps(&transport, &[0x42; 32], &config, steps).is_err());
+		assert_eq!(transport.requests().len(), 2);
+	}
 }
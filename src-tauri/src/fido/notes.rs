@@ -0,0 +1,237 @@
+//! Secure notes stored in the authenticator's largeBlob array.
+//!
+//! Notes are encrypted with AES-256-GCM using a key derived via the `hmac-secret` extension
+//! from a dedicated resident credential. Entries use the standard large-blob map layout
+//! (`{1: ciphertext, 2: nonce, 3: origSize}`) so other clients simply fail to decrypt them
+//! and leave them untouched.
+
+use crate::{
+	error::PFError,
+	fido::{connect, constants::*, hmac_secret::SecretCredential},
+	operation::report_phase,
+	types::{PhaseVerb, SecureNote, UserAction},
+};
+use ctap_hid_fido2::FidoKeyHid;
+use rand::Rng;
+use ring::{
+	aead::{AES_256_GCM, Aad, LessSafeKey, Nonce, UnboundKey},
+	digest,
+};
+use serde_cbor_2::{Value, from_slice, to_vec};
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Resident credential backing the notes key
+const NOTES_CREDENTIAL: SecretCredential = SecretCredential {
+	rp_id: "picoforge.notes",
+	user_id: b"picoforge-notes",
+	user_name: "PicoForge Secure Notes",
+	label: "the secure notes",
+};
+
+/// Fixed hmac-secret salt input, hashed with SHA-256 before use
+const NOTES_SALT_INPUT: &[u8] = b"picoforge secure notes v1";
+
+/// ctap-hid-fido2 reads at most 1024 bytes of the large-blob array (including the trailing hash),
+/// so anything at or above this size may be truncated and must not be rewritten.
+const NOTES_READ_LIMIT: usize = 1024;
+const LARGE_BLOB_HASH_LEN: usize = 16;
+
+// Large-blob map keys (CTAP 2.1 section 6.10.3)
+const BLOB_CIPHERTEXT: i128 = 0x01;
+const BLOB_NONCE: i128 = 0x02;
+const BLOB_ORIG_SIZE: i128 = 0x03;
+
+/// Derives the notes encryption key from the notes credential, or `None` if the key holds no
+/// notes credential yet. Only `create` may make it, reads never do: a new credential has a new
+/// hmac-secret and the notes stored under the old one could never be decrypted again.
+fn notes_key(device: &FidoKeyHid, pin: &str, create: bool) -> Result<Option<LessSafeKey>, PFError> {
+	let credential_id = if create {
+		NOTES_CREDENTIAL.ensure(device, pin)?
+	} else {
+		match NOTES_CREDENTIAL.find(device, pin)? {
+			Some(credential_id) => credential_id,
+			None => return Ok(None),
+		}
+	};
+	let salt: [u8; 32] = digest::digest(&digest::SHA256, NOTES_SALT_INPUT)
+		.as_ref()
+		.try_into()
+		.map_err(|_| PFError::Io("Invalid salt length".into()))?;
+	let secret = NOTES_CREDENTIAL.derive(device, pin, &credential_id, salt)?;
+
+	let key = UnboundKey::new(&AES_256_GCM, &secret)
+		.map_err(|_| PFError::Io("Failed to build notes key".into()))?;
+	Ok(Some(LessSafeKey::new(key)))
+}
+
+/// Associated data for a large-blob entry: "blob" || uint64LE(origSize)
+fn blob_aad(orig_size: u64) -> Vec<u8> {
+	let mut aad = b"blob".to_vec();
+	aad.extend_from_slice(&orig_size.to_le_bytes());
+	aad
+}
+
+fn encrypt_note(key: &LessSafeKey, note: &SecureNote) -> Result<Value, PFError> {
+	let mut plaintext = serde_json::to_vec(note).map_err(|e| PFError::Io(e.to_string()))?;
+	let orig_size = plaintext.len() as u64;
+	let nonce_bytes: [u8; 12] = rand::rng().random();
+
+	key.seal_in_place_append_tag(
+		Nonce::assume_unique_for_key(nonce_bytes),
+		Aad::from(blob_aad(orig_size)),
+		&mut plaintext,
+	)
+	.map_err(|_| PFError::Io("Failed to encrypt note".into()))?;
+
+	let mut entry = BTreeMap::new();
+	entry.insert(Value::Integer(BLOB_CIPHERTEXT), Value::Bytes(plaintext));
+	entry.insert(
+		Value::Integer(BLOB_NONCE),
+		Value::Bytes(nonce_bytes.to_vec()),
+	);
+	entry.insert(
+		Value::Integer(BLOB_ORIG_SIZE),
+		Value::Integer(orig_size as i128),
+	);
+	Ok(Value::Map(entry))
+}
+
+/// Tries to decrypt a large-blob entry with the notes key. Returns `None` for foreign entries.
+fn decrypt_note(key: &LessSafeKey, entry: &Value) -> Option<SecureNote> {
+	let Value::Map(m) = entry else {
+		return None;
+	};
+	let (
+		Some(Value::Bytes(ciphertext)),
+		Some(Value::Bytes(nonce)),
+		Some(Value::Integer(orig_size)),
+	) = (
+		m.get(&Value::Integer(BLOB_CIPHERTEXT)),
+		m.get(&Value::Integer(BLOB_NONCE)),
+		m.get(&Value::Integer(BLOB_ORIG_SIZE)),
+	)
+	else {
+		return None;
+	};
+
+	let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+	let mut buf = ciphertext.clone();
+	let plaintext = key
+		.open_in_place(nonce, Aad::from(blob_aad(*orig_size as u64)), &mut buf)
+		.ok()?;
+	serde_json::from_slice(plaintext).ok()
+}
+
+/// Reads and parses the serialized large-blob array
+fn read_blob_array(device: &FidoKeyHid) -> Result<Vec<Value>, PFError> {
+	let data = device
+		.get_large_blob()
+		.map_err(|e| PFError::Device(format!("Failed to read largeBlob array: {:?}", e)))?;
+
+	if data.large_blob_array.len() + LARGE_BLOB_HASH_LEN >= NOTES_READ_LIMIT {
+		return Err(PFError::Device(
+			"largeBlob array is too large to be safely rewritten".into(),
+		));
+	}
+
+	match from_slice(&data.large_blob_array) {
+		Ok(Value::Array(entries)) => Ok(entries),
+		_ => {
+			log::warn!("largeBlob array is empty or not a CBOR array, starting fresh");
+			Ok(Vec::new())
+		}
+	}
+}
+
+fn write_blob_array(device: &FidoKeyHid, pin: &str, entries: Vec<Value>) -> Result<(), PFError> {
+	super::cache::invalidate();
+	let serialized = to_vec(&Value::Array(entries)).map_err(|e| PFError::Io(e.to_string()))?;
+
+	if serialized.len() + LARGE_BLOB_HASH_LEN >= NOTES_READ_LIMIT
+		|| serialized.len() > MAX_LARGE_BLOB_SIZE
+	{
+		return Err(PFError::Device(
+			"Not enough largeBlob storage left for this note".into(),
+		));
+	}
+
+	report_phase(PhaseVerb::Write, "secure notes", UserAction::KeepConnected);
+	device
+		.write_large_blob(Some(pin), serialized)
+		.map_err(|e| PFError::Device(format!("Failed to write largeBlob array: {:?}", e)))?;
+	Ok(())
+}
+
+pub(crate) fn list_secure_notes(
+	device_path: Option<&str>,
+	pin: String,
+) -> Result<Vec<SecureNote>, PFError> {
+	log::info!("Reading secure notes from largeBlob storage...");
+	let device = connect(device_path)?;
+	let Some(key) = notes_key(&device, &pin, false)? else {
+		log::info!("No secure notes credential on the device, so no notes");
+		return Ok(Vec::new());
+	};
+
+	let notes: Vec<SecureNote> = read_blob_array(&device)?
+		.iter()
+		.filter_map(|entry| decrypt_note(&key, entry))
+		.collect();
+
+	log::info!("Found {} secure note(s)", notes.len());
+	Ok(notes)
+}
+
+pub(crate) fn add_secure_note(
+	device_path: Option<&str>,
+	pin: String,
+	title: String,
+	body: String,
+) -> Result<SecureNote, PFError> {
+	log::info!("Adding secure note...");
+	let device = connect(device_path)?;
+	let key = notes_key(&device, &pin, true)?
+		.ok_or_else(|| PFError::Device("The secure notes credential was not created".into()))?;
+
+	let id_bytes: [u8; 8] = rand::rng().random();
+	let note = SecureNote {
+		id: hex::encode(id_bytes),
+		title,
+		body,
+		created_at: SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or(0),
+	};
+
+	let mut entries = read_blob_array(&device)?;
+	entries.push(encrypt_note(&key, &note)?);
+	write_blob_array(&device, &pin, entries)?;
+
+	log::info!("Secure note {} stored", note.id);
+	Ok(note)
+}
+
+pub(crate) fn delete_secure_note(
+	device_path: Option<&str>,
+	pin: String,
+	note_id: String,
+) -> Result<String, PFError> {
+	log::info!("Deleting secure note {}...", note_id);
+	let device = connect(device_path)?;
+	let Some(key) = notes_key(&device, &pin, false)? else {
+		return Err(PFError::Device("Secure note not found".into()));
+	};
+
+	let mut entries = read_blob_array(&device)?;
+	let before = entries.len();
+	entries.retain(|entry| decrypt_note(&key, entry).is_none_or(|note| note.id != note_id));
+
+	if entries.len() == before {
+		return Err(PFError::Device("Secure note not found".into()));
+	}
+
+	write_blob_array(&device, &pin, entries)?;
+	Ok("Secure note deleted successfully".into())
+}
@@ -0,0 +1,572 @@
+//! Typed PHY configuration and its TLV encoding.
+//!
+//! The blob is a flat list of `tag`, `len`, `value...` entries, see `src/fs/phy.c` of the
+//! pico-keys-sdk. Multi-byte integers are big-endian. The Rescue Applet reads and writes it as
+//! is, and the fields of the FIDO physical options response are put back into the same form, so
+//! both methods share one decoder.
+
+use crate::{error::PFError, schema::registry::ProtocolConstant, types::*};
+
+/// Longest USB product name accepted by the firmware, including the null terminator
+pub const PHY_USB_PRODUCT_MAX_LEN: usize = 32;
+
+/// PHY tags from src/fs/phy.h, valued by the firmware definitions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhyTag {
+	VidPid,
+	LedGpio,
+	LedBrightness,
+	Opts,
+	PresenceTimeout, // Previously TAG_UP_BTN
+	UsbProduct,
+	Curves,
+	UsbInterfaces,
+	LedDriver,
+}
+
+impl ProtocolConstant for PhyTag {
+	const GROUP: SchemaGroup = SchemaGroup::PhyTag;
+	const ALL: &'static [Self] = &[
+		Self::VidPid,
+		Self::LedGpio,
+		Self::LedBrightness,
+		Self::Opts,
+		Self::PresenceTimeout,
+		Self::UsbProduct,
+		Self::Curves,
+		Self::UsbInterfaces,
+		Self::LedDriver,
+	];
+
+	fn definition(self) -> &'static str {
+		match self {
+			Self::VidPid => "PHY_VIDPID",
+			Self::LedGpio => "PHY_LED_GPIO",
+			Self::LedBrightness => "PHY_LED_BTNESS",
+			Self::Opts => "PHY_OPTS",
+			Self::PresenceTimeout => "PHY_UP_BTN",
+			Self::UsbProduct => "PHY_USB_PRODUCT",
+			Self::Curves => "PHY_ENABLED_CURVES",
+			Self::UsbInterfaces => "PHY_ENABLED_USB_ITF",
+			Self::LedDriver => "PHY_LED_DRIVER",
+		}
+	}
+}
+
+bitflags::bitflags! {
+	/// Configuration options of PHY_OPTS
+	#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+	pub struct PhyOptions: u16 {
+		const WCID = 0x01;
+		const LED_DIMMABLE = 0x02;
+		const DISABLE_POWER_RESET = 0x04;
+		const LED_STEADY = 0x08;
+	}
+}
+
+bitflags::bitflags! {
+	/// Enabled curves of PHY_ENABLED_CURVES
+	#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+	pub struct PhyCurves: u32 {
+		const SECP256K1 = 0x08;
+	}
+}
+
+impl PhyTag {
+	/// Helper to convert raw u8 from device back to Enum
+	pub fn from_u8(val: u8) -> Option<Self> {
+		Self::from_value(val as u64)
+	}
+
+	/// Tag byte of the blob
+	pub fn code(self) -> Result<u8, PFError> {
+		Ok(self.value()? as u8)
+	}
+
+	/// Length every value of this tag must have, `None` for variable length tags
+	pub fn value_len(self) -> Option<usize> {
+		match self {
+			Self::VidPid => Some(4),
+			Self::LedGpio
+			| Self::LedBrightness
+			| Self::PresenceTimeout
+			| Self::UsbInterfaces
+			| Self::LedDriver => Some(1),
+			Self::Opts => Some(2),
+			Self::Curves => Some(4),
+			Self::UsbProduct => None,
+		}
+	}
+}
+
+/// Typed view of the PHY configuration. Fields left `None` are not part of the blob.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PhyConfig {
+	pub vid_pid: Option<(u16, u16)>,
+	pub led_gpio: Option<u8>,
+	pub led_brightness: Option<u8>,
+	pub presence_timeout: Option<u8>,
+	pub opts: Option<PhyOptions>,
+	pub curves: Option<PhyCurves>,
+	/// Bitmask of the enabled USB interfaces, not part of the app configuration
+	pub usb_interfaces: Option<u8>,
+	pub led_driver: Option<u8>,
+	pub usb_product: Option<String>,
+	/// Tags this version of PicoForge does not know, kept so they survive a round trip
+	pub unknown: Vec<(u8, Vec<u8>)>,
+}
+
+fn push_entry(out: &mut Vec<u8>, tag: u8, value: &[u8]) -> Result<(), PFError> {
+	let len = u8::try_from(value.len())
+		.map_err(|_| PFError::Io(format!("PHY tag 0x{:02X} value is too long", tag)))?;
+	out.push(tag);
+	out.push(len);
+	out.extend_from_slice(value);
+	Ok(())
+}
+
+impl PhyConfig {
+	/// Serializes the set fields, in the order the firmware writes them.
+	pub fn encode(&self) -> Result<Vec<u8>, PFError> {
+		let mut out = Vec::new();
+
+		if let Some((vid, pid)) = self.vid_pid {
+			let mut value = vid.to_be_bytes().to_vec();
+			value.extend_from_slice(&pid.to_be_bytes());
+			push_entry(&mut out, PhyTag::VidPid.code()?, &value)?;
+		}
+		if let Some(val) = self.led_gpio {
+			push_entry(&mut out, PhyTag::LedGpio.code()?, &[val])?;
+		}
+		if let Some(val) = self.led_brightness {
+			push_entry(&mut out, PhyTag::LedBrightness.code()?, &[val])?;
+		}
+		if let Some(val) = self.presence_timeout {
+			push_entry(&mut out, PhyTag::PresenceTimeout.code()?, &[val])?;
+		}
+		if let Some(opts) = &self.opts {
+			push_entry(&mut out, PhyTag::Opts.code()?, &opts.bits().to_be_bytes())?;
+		}
+		if let Some(curves) = &self.curves {
+			push_entry(
+				&mut out,
+				PhyTag::Curves.code()?,
+				&curves.bits().to_be_bytes(),
+			)?;
+		}
+		if let Some(val) = self.usb_interfaces {
+			push_entry(&mut out, PhyTag::UsbInterfaces.code()?, &[val])?;
+		}
+		if let Some(val) = self.led_driver {
+			push_entry(&mut out, PhyTag::LedDriver.code()?, &[val])?;
+		}
+		if let Some(name) = &self.usb_product {
+			let mut value = name.as_bytes().to_vec();
+			value.push(0x00); // Null terminator
+			if value.len() > PHY_USB_PRODUCT_MAX_LEN {
+				return Err(PFError::Io("Product name too long".into()));
+			}
+			push_entry(&mut out, PhyTag::UsbProduct.code()?, &value)?;
+		}
+		for (tag, value) in &self.unknown {
+			push_entry(&mut out, *tag, value)?;
+		}
+
+		Ok(out)
+	}
+
+	/// Parses a PHY blob, rejecting truncated entries and known tags with a wrong length.
+	pub fn decode(mut data: &[u8]) -> Result<Self, PFError> {
+		let mut phy = Self::default();
+
+		while !data.is_empty() {
+			let (tag_byte, len) = match data {
+				[tag, len, ..] => (*tag, *len as usize),
+				_ => return Err(PFError::Device("Truncated PHY tag".into())),
+			};
+			let val = data.get(2..2 + len).ok_or_else(|| {
+				PFError::Device(format!("PHY tag 0x{:02X} overruns the blob", tag_byte))
+			})?;
+			data = &data[2 + len..];
+
+			let Some(tag) = PhyTag::from_u8(tag_byte) else {
+				log::debug!("Keeping unknown PHY tag 0x{:02X}", tag_byte);
+				phy.unknown.push((tag_byte, val.to_vec()));
+				continue;
+			};
+			if let Some(expected) = tag.value_len()
+				&& expected != len
+			{
+				return Err(PFError::Device(format!(
+					"PHY tag {:?} has length {}, expected {}",
+					tag, len, expected
+				)));
+			}
+
+			match tag {
+				PhyTag::VidPid => {
+					phy.vid_pid = Some((
+						u16::from_be_bytes([val[0], val[1]]),
+						u16::from_be_bytes([val[2], val[3]]),
+					))
+				}
+				PhyTag::LedGpio => phy.led_gpio = Some(val[0]),
+				PhyTag::LedBrightness => phy.led_brightness = Some(val[0]),
+				PhyTag::PresenceTimeout => phy.presence_timeout = Some(val[0]),
+				PhyTag::Opts => {
+					phy.opts = Some(PhyOptions::from_bits_retain(u16::from_be_bytes([
+						val[0], val[1],
+					])))
+				}
+				PhyTag::Curves => {
+					phy.curves = Some(PhyCurves::from_bits_retain(u32::from_be_bytes([
+						val[0], val[1], val[2], val[3],
+					])))
+				}
+				PhyTag::UsbInterfaces => phy.usb_interfaces = Some(val[0]),
+				PhyTag::LedDriver => phy.led_driver = Some(val[0]),
+				PhyTag::UsbProduct => {
+					let name = std::str::from_utf8(val)
+						.map_err(|_| PFError::Device("USB product name is not UTF-8".into()))?;
+					phy.usb_product = Some(name.trim_matches(char::from(0)).to_string());
+				}
+			}
+		}
+
+		Ok(phy)
+	}
+
+	/// One line per field, describing what writing the blob changes
+	pub fn describe(&self) -> Vec<String> {
+		let mut changes = Vec::new();
+		if let Some((vid, pid)) = self.vid_pid {
+			changes.push(format!("Set VID:PID to {:04X}:{:04X}", vid, pid));
+		}
+		if let Some(gpio) = self.led_gpio {
+			changes.push(format!("Set LED GPIO to {}", gpio));
+		}
+		if let Some(brightness) = self.led_brightness {
+			changes.push(format!("Set LED brightness to {}", brightness));
+		}
+		if let Some(timeout) = self.presence_timeout {
+			changes.push(format!("Set touch timeout to {} s", timeout));
+		}
+		if let Some(opts) = self.opts {
+			changes.push(format!(
+				"Set options: LED dimmable {}, power cycle on reset {}, LED steady {}",
+				opts.contains(PhyOptions::LED_DIMMABLE),
+				!opts.contains(PhyOptions::DISABLE_POWER_RESET),
+				opts.contains(PhyOptions::LED_STEADY)
+			));
+		}
+		if let Some(curves) = self.curves {
+			changes.push(format!(
+				"{} secp256k1",
+				if curves.contains(PhyCurves::SECP256K1) {
+					"Enable"
+				} else {
+					"Disable"
+				}
+			));
+		}
+		if let Some(interfaces) = self.usb_interfaces {
+			changes.push(format!(
+				"Set enabled USB interfaces to 0x{:02X}",
+				interfaces
+			));
+		}
+		if let Some(driver) = self.led_driver {
+			changes.push(format!("Set LED driver to {}", driver));
+		}
+		if let Some(name) = &self.usb_product {
+			changes.push(format!("Set USB product name to \"{}\"", name));
+		}
+		changes
+	}
+
+	/// Builds the blob for the fields set in a config change request.
+	///
+	/// The options word is only written when all three options are given, since the firmware
+	/// replaces it as a whole. LED settings no board could use are rejected.
+	pub fn from_input(config: &AppConfigInput) -> Result<Self, PFError> {
+		super::led::validate(config.led_driver, config.led_gpio, None)?;
+
+		let vid_pid = match (&config.vid, &config.pid) {
+			(Some(vid), Some(pid)) => Some((
+				u16::from_str_radix(vid, 16).map_err(|_| PFError::Io("Invalid VID".into()))?,
+				u16::from_str_radix(pid, 16).map_err(|_| PFError::Io("Invalid PID".into()))?,
+			)),
+			_ => None,
+		};
+
+		let opts = match (
+			config.led_dimmable,
+			config.power_cycle_on_reset,
+			config.led_steady,
+		) {
+			(Some(dim), Some(cycle), Some(steady)) => {
+				let mut opts = PhyOptions::empty();
+				opts.set(PhyOptions::LED_DIMMABLE, dim);
+				opts.set(PhyOptions::DISABLE_POWER_RESET, !cycle);
+				opts.set(PhyOptions::LED_STEADY, steady);
+				Some(opts)
+			}
+			_ => None,
+		};
+
+		let curves = config.enable_secp256k1.map(|enabled| {
+			let mut curves = PhyCurves::empty();
+			curves.set(PhyCurves::SECP256K1, enabled);
+			curves
+		});
+
+		Ok(Self {
+			vid_pid,
+			led_gpio: config.led_gpio,
+			led_brightness: config.led_brightness,
+			presence_timeout: config.touch_timeout,
+			opts,
+			curves,
+			usb_interfaces: None,
+			led_driver: config.led_driver.map(u8::from),
+			usb_product: config.product_name.clone().filter(|name| !name.is_empty()),
+			unknown: Vec::new(),
+		})
+	}
+
+	/// Copies the fields present in the blob into `config`.
+	pub fn apply_to(&self, config: &mut AppConfig) {
+		if let Some((vid, pid)) = self.vid_pid {
+			config.vid = format!("{:04X}", vid);
+			config.pid = format!("{:04X}", pid);
+		}
+		if let Some(val) = self.led_gpio {
+			config.led_gpio = val;
+		}
+		if let Some(val) = self.led_brightness {
+			config.led_brightness = val;
+		}
+		if let Some(val) = self.presence_timeout {
+			config.touch_timeout = val;
+		}
+		if let Some(name) = &self.usb_product {
+			config.product_name = name.clone();
+		}
+		if let Some(opts) = &self.opts {
+			config.led_dimmable = opts.contains(PhyOptions::LED_DIMMABLE);
+			config.power_cycle_on_reset = !opts.contains(PhyOptions::DISABLE_POWER_RESET);
+			config.led_steady = opts.contains(PhyOptions::LED_STEADY);
+		}
+		if let Some(curves) = &self.curves {
+			config.enable_secp256k1 = curves.contains(PhyCurves::SECP256K1);
+		}
+		if let Some(driver) = self.led_driver {
+			config.led_driver = Some(driver.into());
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use proptest::prelude::*;
+
+	/// PHY blob as read back from a pico-fido 7.2 key, in firmware order: VID:PID 2E8A:10FE,
+	/// LED on GPIO 25 at brightness 15, dimmable and steady LED, 15s presence timeout,
+	/// "Pico Key" product name, secp256k1 enabled, two USB interfaces and an unknown tag 0x0D.
+	const GOLDEN_READ: &[u8] = &[
+		0x00, 0x04, 0x2E, 0x8A, 0x10, 0xFE, // VID:PID
+		0x04, 0x01, 0x19, // LED GPIO
+		0x05, 0x01, 0x0F, // LED brightness
+		0x06, 0x02, 0x00, 0x0A, // Options
+		0x08, 0x01, 0x0F, // Presence timeout
+		0x09, 0x09, b'P', b'i', b'c', b'o', b' ', b'K', b'e', b'y', 0x00, // Product
+		0x0A, 0x04, 0x00, 0x00, 0x00, 0x08, // Curves
+		0x0B, 0x01, 0x03, // Enabled USB interfaces
+		0x0D, 0x01, 0x01, // Unknown
+	];
+
+	/// Blob PicoForge writes for a VID:PID, LED GPIO and product name change
+	const GOLDEN_WRITE: &[u8] = &[
+		0x00, 0x04, 0xCA, 0xFE, 0x42, 0x42, // VID:PID
+		0x04, 0x01, 0x10, // LED GPIO
+		0x09, 0x05, b'T', b'e', b's', b't', 0x00, // Product
+	];
+
+	fn unknown_tag() -> impl Strategy<Value = u8> {
+		any::<u8>().prop_filter("tag must be unknown", |tag| PhyTag::from_u8(*tag).is_none())
+	}
+
+	prop_compose! {
+		fn arb_phy()(
+			vid_pid in proptest::option::of(any::<(u16, u16)>()),
+			led_gpio in proptest::option::of(any::<u8>()),
+			led_brightness in proptest::option::of(any::<u8>()),
+			presence_timeout in proptest::option::of(any::<u8>()),
+			opts in proptest::option::of(any::<u16>()),
+			curves in proptest::option::of(any::<u32>()),
+			usb_interfaces in proptest::option::of(any::<u8>()),
+			led_driver in proptest::option::of(any::<u8>()),
+			usb_product in proptest::option::of("[ -~]{0,31}"),
+			unknown in proptest::collection::vec(
+				(unknown_tag(), proptest::collection::vec(any::<u8>(), 0..16)),
+				0..3,
+			),
+		) -> PhyConfig {
+			PhyConfig {
+				vid_pid,
+				led_gpio,
+				led_brightness,
+				presence_timeout,
+				opts: opts.map(PhyOptions::from_bits_retain),
+				curves: curves.map(PhyCurves::from_bits_retain),
+				usb_interfaces,
+				led_driver,
+				usb_product,
+				unknown,
+			}
+		}
+	}
+
+	prop_compose! {
+		fn arb_input()(
+			vid in any::<u16>(),
+			pid in any::<u16>(),
+			product_name in "[ -~]{1,31}",
+			// GPIOs every LED driver accepts
+			led_gpio in 0u8..=21,
+			led_brightness in any::<u8>(),
+			touch_timeout in any::<u8>(),
+			led_driver in proptest::option::of(any::<u8>().prop_map(LedDriver::from)),
+			led_dimmable in any::<bool>(),
+			power_cycle_on_reset in any::<bool>(),
+			led_steady in any::<bool>(),
+			enable_secp256k1 in any::<bool>(),
+		) -> AppConfigInput {
+			AppConfigInput {
+				vid: Some(format!("{:04X}", vid)),
+				pid: Some(format!("{:04X}", pid)),
+				product_name: Some(product_name),
+				led_gpio: Some(led_gpio),
+				led_brightness: Some(led_brightness),
+				touch_timeout: Some(touch_timeout),
+				led_driver,
+				led_dimmable: Some(led_dimmable),
+				power_cycle_on_reset: Some(power_cycle_on_reset),
+				led_steady: Some(led_steady),
+				enable_secp256k1: Some(enable_secp256k1),
+			}
+		}
+	}
+
+	proptest! {
+		#[test]
+		fn phy_round_trip(phy in arb_phy()) {
+			let encoded = phy.encode().unwrap();
+			prop_assert_eq!(PhyConfig::decode(&encoded).unwrap(), phy);
+		}
+
+		#[test]
+		fn config_round_trip(input in arb_input()) {
+			let encoded = PhyConfig::from_input(&input).unwrap().encode().unwrap();
+			let mut config = AppConfig::default();
+			PhyConfig::decode(&encoded).unwrap().apply_to(&mut config);
+
+			prop_assert_eq!(Some(config.vid), input.vid);
+			prop_assert_eq!(Some(config.pid), input.pid);
+			prop_assert_eq!(Some(config.product_name), input.product_name);
+			prop_assert_eq!(Some(config.led_gpio), input.led_gpio);
+			prop_assert_eq!(Some(config.led_brightness), input.led_brightness);
+			prop_assert_eq!(Some(config.touch_timeout), input.touch_timeout);
+			prop_assert_eq!(config.led_driver, input.led_driver);
+			prop_assert_eq!(Some(config.led_dimmable), input.led_dimmable);
+			prop_assert_eq!(Some(config.power_cycle_on_reset), input.power_cycle_on_reset);
+			prop_assert_eq!(Some(config.led_steady), input.led_steady);
+			prop_assert_eq!(Some(config.enable_secp256k1), input.enable_secp256k1);
+		}
+
+		#[test]
+		fn decode_never_panics(data in proptest::collection::vec(any::<u8>(), 0..64)) {
+			let _ = PhyConfig::decode(&data);
+		}
+	}
+
+	#[test]
+	fn golden_read() {
+		let phy = PhyConfig::decode(GOLDEN_READ).unwrap();
+		let mut config = AppConfig::default();
+		phy.apply_to(&mut config);
+
+		assert_eq!(config.vid, "2E8A");
+		assert_eq!(config.pid, "10FE");
+		assert_eq!(config.led_gpio, 25);
+		assert_eq!(config.led_brightness, 15);
+		assert_eq!(config.touch_timeout, 15);
+		assert_eq!(config.product_name, "Pico Key");
+		assert!(config.led_dimmable);
+		assert!(config.led_steady);
+		assert!(config.power_cycle_on_reset);
+		assert!(config.enable_secp256k1);
+		assert_eq!(config.led_driver, None);
+		assert_eq!(phy.usb_interfaces, Some(0x03));
+		assert_eq!(phy.unknown, vec![(0x0D, vec![0x01])]);
+
+		// Re-encoding keeps every tag, including the unknown one
+		let reencoded = phy.encode().unwrap();
+		assert_eq!(reencoded.len(), GOLDEN_READ.len());
+		assert_eq!(PhyConfig::decode(&reencoded).unwrap(), phy);
+	}
+
+	#[test]
+	fn golden_write() {
+		let input = AppConfigInput {
+			vid: Some("CAFE".into()),
+			pid: Some("4242".into()),
+			product_name: Some("Test".into()),
+			led_gpio: Some(16),
+			led_brightness: None,
+			touch_timeout: None,
+			led_driver: None,
+			led_dimmable: None,
+			power_cycle_on_reset: None,
+			led_steady: None,
+			enable_secp256k1: None,
+		};
+		let encoded = PhyConfig::from_input(&input).unwrap().encode().unwrap();
+		assert_eq!(encoded, GOLDEN_WRITE);
+	}
+
+	#[test]
+	fn round_trip_all_tags() {
+		let phy = PhyConfig {
+			vid_pid: Some((0x2E8A, 0x10FE)),
+			led_gpio: Some(25),
+			led_brightness: Some(8),
+			presence_timeout: Some(15),
+			opts: Some(PhyOptions::LED_DIMMABLE | PhyOptions::LED_STEADY),
+			curves: Some(PhyCurves::SECP256K1),
+			usb_interfaces: Some(0x01),
+			led_driver: Some(1),
+			usb_product: Some("Pico Key".into()),
+			unknown: vec![(0x0D, vec![0x01])],
+		};
+
+		let encoded = phy.encode().unwrap();
+		assert_eq!(PhyConfig::decode(&encoded).unwrap(), phy);
+	}
+
+	#[test]
+	fn rejects_wrong_length() {
+		assert!(PhyConfig::decode(&[PhyTag::LedGpio.code().unwrap(), 0x02, 0x01, 0x02]).is_err());
+		assert!(PhyConfig::decode(&[PhyTag::VidPid.code().unwrap(), 0x04, 0x2E]).is_err());
+	}
+
+	#[test]
+	fn rejects_long_product_name() {
+		let phy = PhyConfig {
+			usb_product: Some("x".repeat(PHY_USB_PRODUCT_MAX_LEN)),
+			..Default::default()
+		};
+		assert!(phy.encode().is_err());
+	}
+}
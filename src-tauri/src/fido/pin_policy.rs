@@ -0,0 +1,160 @@
+//! Checks of a new PIN before it is sent to the key.
+//!
+//! The key rejects a PIN it does not accept with a bare PIN_POLICY_VIOLATION, so the rules of
+//! CTAP 2.1 are checked here first: at least the minimum length the key reports, counted in
+//! Unicode code points, and at most 63 bytes of UTF-8. The PIN is padded with zeros before it is
+//! encrypted, so it cannot contain NUL either. On top of that the organisation may require a
+//! longer PIN and refuse common ones, with the policy saved in the data directory.
+
+use crate::{
+	error::PFError,
+	paths, storage,
+	types::{OrgPinPolicy, PinViolation},
+};
+use std::path::PathBuf;
+
+/// Longest PIN CTAP allows, in bytes of UTF-8
+pub const MAX_PIN_BYTES: usize = 63;
+
+const POLICY_FILE: &str = "pin_policy.json";
+
+fn policy_path() -> PathBuf {
+	paths::data_dir().join(POLICY_FILE)
+}
+
+/// PIN policy of the organisation, or the default that adds nothing to the CTAP rules
+pub fn org_policy() -> OrgPinPolicy {
+	match storage::read(&policy_path()) {
+		Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+			log::warn!("PIN policy is corrupted, using the default: {}", e);
+			OrgPinPolicy::default()
+		}),
+		Err(_) => OrgPinPolicy::default(),
+	}
+}
+
+pub fn set_org_policy(policy: &OrgPinPolicy) -> Result<(), PFError> {
+	if policy
+		.min_length
+		.is_some_and(|min| min as usize > MAX_PIN_BYTES)
+	{
+		return Err(PFError::Io(format!(
+			"A PIN cannot be required to be longer than {} characters",
+			MAX_PIN_BYTES
+		)));
+	}
+	let data = serde_json::to_vec_pretty(policy).map_err(|e| PFError::Io(e.to_string()))?;
+	storage::write(&policy_path(), &data)
+		.map_err(|e| PFError::Io(format!("Failed to save PIN policy: {}", e)))
+}
+
+/// First rule `new_pin` breaks, for a key that requires `device_min_length` code points
+pub fn violation(
+	new_pin: &str,
+	current_pin: Option<&str>,
+	device_min_length: u32,
+	policy: &OrgPinPolicy,
+) -> Option<PinViolation> {
+	let min_length = policy.min_length.unwrap_or(0).max(device_min_length);
+	if (new_pin.chars().count() as u32) < min_length {
+		return Some(PinViolation::TooShort { min_length });
+	}
+	if new_pin.len() > MAX_PIN_BYTES {
+		return Some(PinViolation::TooLong {
+			max_bytes: MAX_PIN_BYTES as u32,
+		});
+	}
+	if new_pin.contains('\0') {
+		return Some(PinViolation::NulCharacter);
+	}
+	if current_pin == Some(new_pin) {
+		return Some(PinViolation::Unchanged);
+	}
+	if policy
+		.blocklist
+		.iter()
+		.any(|blocked| blocked.trim().eq_ignore_ascii_case(new_pin))
+	{
+		return Some(PinViolation::Blocklisted);
+	}
+	None
+}
+
+/// Refuses `new_pin` if it breaks the CTAP rules for the key or the policy of the organisation
+pub fn check_new_pin(
+	new_pin: &str,
+	current_pin: Option<&str>,
+	device_min_length: u32,
+) -> Result<(), PFError> {
+	match violation(new_pin, current_pin, device_min_length, &org_policy()) {
+		Some(violation) => {
+			log::info!("New PIN refused: {}", violation);
+			Err(PFError::PinPolicyViolation(violation))
+		}
+		None => Ok(()),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn policy(min_length: Option<u32>, blocklist: &[&str]) -> OrgPinPolicy {
+		OrgPinPolicy {
+			min_length,
+			blocklist: blocklist.iter().map(|s| s.to_string()).collect(),
+		}
+	}
+
+	#[test]
+	fn length_is_counted_in_code_points() {
+		let none = OrgPinPolicy::default();
+		// 4 code points, 8 bytes
+		assert_eq!(violation("äöüß", None, 4, &none), None);
+		assert_eq!(
+			violation("äöü", None, 4, &none),
+			Some(PinViolation::TooShort { min_length: 4 })
+		);
+	}
+
+	#[test]
+	fn length_is_limited_in_bytes() {
+		let none = OrgPinPolicy::default();
+		assert_eq!(violation(&"1".repeat(63), None, 4, &none), None);
+		// 32 code points, 64 bytes
+		assert_eq!(
+			violation(&"ä".repeat(32), None, 4, &none),
+			Some(PinViolation::TooLong { max_bytes: 63 })
+		);
+	}
+
+	#[test]
+	fn stricter_minimum_wins() {
+		assert_eq!(
+			violation("123456", None, 4, &policy(Some(8), &[])),
+			Some(PinViolation::TooShort { min_length: 8 })
+		);
+		assert_eq!(
+			violation("123456", None, 8, &policy(Some(6), &[])),
+			Some(PinViolation::TooShort { min_length: 8 })
+		);
+	}
+
+	#[test]
+	fn refuses_nul_unchanged_and_blocklisted_pins() {
+		let org = policy(None, &["Password", "123456"]);
+		assert_eq!(
+			violation("12\u{0}34", None, 4, &org),
+			Some(PinViolation::NulCharacter)
+		);
+		assert_eq!(
+			violation("4711", Some("4711"), 4, &org),
+			Some(PinViolation::Unchanged)
+		);
+		assert_eq!(
+			violation("password", None, 4, &org),
+			Some(PinViolation::Blocklisted)
+		);
+		assert_eq!(violation("correct horse", Some("4711"), 4, &org), None);
+	}
+}
@@ -0,0 +1,129 @@
+//! PIN token kept for the session, so the passkey views only ask for the PIN once.
+//!
+//! The first credential management call turns the PIN into a pinUvAuthToken with the credential
+//! management permission, which later calls reuse until it was idle for `IDLE_EXPIRY`. Locking
+//! the session and unplugging the key drop it. The key itself invalidates the token whenever it
+//! issues another one, e.g. for a configuration change; the next call then needs the PIN again,
+//! without costing a PIN attempt.
+
+use super::{connect, ctap_error, hid::CtapStatus, pin_error};
+use crate::{
+	error::PFError,
+	fido::constants::Ctap2Error,
+	operation::report_phase,
+	types::{PhaseVerb, UserAction},
+};
+use ctap_hid_fido2::fidokey::pin::Permission;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long an unused token is kept
+pub const IDLE_EXPIRY: Duration = Duration::from_secs(5 * 60);
+
+struct CachedToken {
+	/// HID path of the key the token was issued by
+	device: String,
+	token: Vec<u8>,
+	last_used: Instant,
+}
+
+static TOKEN: Mutex<Option<CachedToken>> = Mutex::new(None);
+
+/// Drops the cached token, e.g. when the session gets locked
+pub fn clear() {
+	if let Ok(mut token) = TOKEN.lock()
+		&& token.take().is_some()
+	{
+		log::info!("Dropped the cached PIN token");
+	}
+}
+
+/// Drops the cached token if it was issued by the key at `path`
+pub fn forget(path: &str) {
+	if let Ok(mut token) = TOKEN.lock()
+		&& token.as_ref().is_some_and(|t| t.device == path)
+	{
+		*token = None;
+	}
+}
+
+fn cached(device: &str) -> Option<Vec<u8>> {
+	let mut token = TOKEN.lock().ok()?;
+	match token.as_mut() {
+		Some(t) if t.device == device && t.last_used.elapsed() < IDLE_EXPIRY => {
+			t.last_used = Instant::now();
+			Some(t.token.clone())
+		}
+		Some(_) => {
+			log::debug!("Cached PIN token expired or belongs to another key");
+			*token = None;
+			None
+		}
+		None => None,
+	}
+}
+
+/// Turns `pin` into a token with the credential management permission and caches it
+fn acquire(device: &str, pin: &str) -> Result<Vec<u8>, PFError> {
+	let key = connect(Some(device))?;
+	report_phase(PhaseVerb::Verify, "the PIN", UserAction::None);
+	let token =
+		match key.get_pinuv_auth_token_with_permission(pin, Permission::CredentialManagement) {
+			Ok(token) => token.key,
+			// Only keys without CTAP 2.1 tokens get the getPinToken fallback. A wrong PIN must not
+			// be sent a second time, that would cost another attempt.
+			Err(e) => match pin_error(&key, e, "PIN token") {
+				PFError::VendorUnsupported(_) => {
+					log::debug!(
+						"No token with the credential management permission, falling back to getPinToken"
+					);
+					key.get_pin_token(pin)
+						.map_err(|e| pin_error(&key, e, "PIN token"))?
+						.key
+				}
+				e => return Err(e),
+			},
+		};
+	if let Ok(mut cached) = TOKEN.lock() {
+		*cached = Some(CachedToken {
+			device: device.to_string(),
+			token: token.clone(),
+			last_used: Instant::now(),
+		});
+	}
+	Ok(token)
+}
+
+/// Whether the key rejected the token itself, rather than the request
+fn token_rejected(e: &anyhow::Error) -> bool {
+	e.downcast_ref::<CtapStatus>()
+		.is_some_and(|CtapStatus(status)| {
+			*status == Ctap2Error::PinAuthInvalid as u8 || *status == Ctap2Error::PuatRequired as u8
+		})
+}
+
+/// Runs `f` with the PIN token of the key at `device`, the cached one if the session holds it.
+/// Errors of `f` are classified as failures of `action`.
+///
+/// `pin` is only used when no token is cached, or the key no longer accepts it. Without a PIN
+/// that is a `PinRequired` error for the UI to prompt for it.
+pub fn with_token<T>(
+	device: &str,
+	pin: Option<&str>,
+	action: &str,
+	f: impl Fn(&[u8]) -> anyhow::Result<T>,
+) -> Result<T, PFError> {
+	let (token, from_cache) = match cached(device) {
+		Some(token) => (token, true),
+		None => (acquire(device, pin.ok_or(PFError::PinRequired)?)?, false),
+	};
+	match f(&token) {
+		Err(e) if from_cache && token_rejected(&e) => {
+			log::info!("The key no longer accepts the cached PIN token");
+			clear();
+			let token = acquire(device, pin.ok_or(PFError::PinRequired)?)?;
+			f(&token).map_err(|e| ctap_error(e, action))
+		}
+		result => result.map_err(|e| ctap_error(e, action)),
+	}
+}
@@ -0,0 +1,127 @@
+//! Small bundled database of well-known relying parties.
+//!
+//! RP IDs alone ("login.microsoft.com", "google.com") are not always obvious to users. The
+//! display names and account management URLs make it easier to pick the right passkey to delete,
+//! and to find where to remove it from the account as well.
+
+use crate::types::StoredCredential;
+
+struct RpMetadata {
+	/// Registrable domain, also matches its subdomains
+	rp_id: &'static str,
+	display_name: &'static str,
+	account_url: Option<&'static str>,
+}
+
+const fn rp(
+	rp_id: &'static str,
+	display_name: &'static str,
+	account_url: Option<&'static str>,
+) -> RpMetadata {
+	RpMetadata {
+		rp_id,
+		display_name,
+		account_url,
+	}
+}
+
+const KNOWN_RPS: &[RpMetadata] = &[
+	rp(
+		"google.com",
+		"Google",
+		Some("https://myaccount.google.com/signinoptions/passkeys"),
+	),
+	rp(
+		"github.com",
+		"GitHub",
+		Some("https://github.com/settings/security"),
+	),
+	rp(
+		"microsoft.com",
+		"Microsoft",
+		Some("https://account.microsoft.com/security"),
+	),
+	rp(
+		"live.com",
+		"Microsoft",
+		Some("https://account.microsoft.com/security"),
+	),
+	rp("apple.com", "Apple", Some("https://account.apple.com")),
+	rp(
+		"amazon.com",
+		"Amazon",
+		Some("https://www.amazon.com/ax/account/manage"),
+	),
+	rp(
+		"facebook.com",
+		"Facebook",
+		Some("https://accountscenter.facebook.com/password_and_security"),
+	),
+	rp(
+		"x.com",
+		"X",
+		Some("https://x.com/settings/account/login_verification"),
+	),
+	rp(
+		"twitter.com",
+		"X",
+		Some("https://x.com/settings/account/login_verification"),
+	),
+	rp(
+		"dropbox.com",
+		"Dropbox",
+		Some("https://www.dropbox.com/account/security"),
+	),
+	rp(
+		"gitlab.com",
+		"GitLab",
+		Some("https://gitlab.com/-/profile/two_factor_auth"),
+	),
+	rp(
+		"cloudflare.com",
+		"Cloudflare",
+		Some("https://dash.cloudflare.com/profile/authentication"),
+	),
+	rp(
+		"paypal.com",
+		"PayPal",
+		Some("https://www.paypal.com/myaccount/security"),
+	),
+	rp(
+		"linkedin.com",
+		"LinkedIn",
+		Some("https://www.linkedin.com/mypreferences/d/categories/sign-in-and-security"),
+	),
+	rp(
+		"npmjs.com",
+		"npm",
+		Some("https://www.npmjs.com/settings/~/tfa"),
+	),
+	rp("pypi.org", "PyPI", Some("https://pypi.org/manage/account/")),
+	rp(
+		"login.gov",
+		"Login.gov",
+		Some("https://secure.login.gov/account"),
+	),
+	rp("webauthn.io", "WebAuthn.io demo", None),
+];
+
+fn lookup(rp_id: &str) -> Option<&'static RpMetadata> {
+	let rp_id = rp_id.to_ascii_lowercase();
+	KNOWN_RPS.iter().find(|known| {
+		rp_id == known.rp_id
+			|| rp_id
+				.strip_suffix(known.rp_id)
+				.is_some_and(|prefix| prefix.ends_with('.'))
+	})
+}
+
+/// Fills in the display name and account URL of credentials for known relying parties
+pub fn enrich(credentials: &mut [StoredCredential]) {
+	for credential in credentials {
+		if let Some(known) = lookup(&credential.rp_id) {
+			credential.rp_display_name = Some(known.display_name.to_string());
+			credential.account_url = known.account_url.map(str::to_string);
+		}
+	}
+}
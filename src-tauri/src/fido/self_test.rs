@@ -0,0 +1,452 @@
+//! Registration and assertion round trip for each signature algorithm of the firmware.
+//!
+//! Every algorithm gets a throwaway non-resident credential for a dummy relying party, so the test
+//! uses no passkey slot and leaves nothing behind. The attestation of the registration and the
+//! signature of the assertion are both verified, the latter over the public key the registration
+//! returned. secp256k1 signatures cannot be verified here, so that round trip is reported as not
+//! verified rather than passed.
+//!
+//! The commands are built by hand as ctap_hid_fido2 only knows ES256 and EdDSA.
+
+use super::{
+	connect, ctap_error,
+	hid::{CTAPHID_CBOR, CtapStatus, HidTransport},
+	pin_error,
+};
+use crate::{
+	error::PFError,
+	fido::constants::*,
+	operation::{cancel_requested, report_phase},
+	types::{AlgorithmTestResult, PhaseVerb, SelfTestReport, SelfTestStatus, UserAction},
+};
+use rand::Rng;
+use ring::{digest, hmac, signature};
+use serde_cbor_2::{Deserializer, Value, from_slice, to_vec};
+use std::collections::BTreeMap;
+use x509_parser::prelude::*;
+
+/// Relying party of the test credentials. The reserved TLD keeps it from ever being a real site.
+pub const SELF_TEST_RP_ID: &str = "selftest.picoforge.invalid";
+
+/// Length of rpIdHash, flags and signCount at the start of authenticator data
+const AUTH_DATA_HEADER_LEN: usize = 37;
+const AAGUID_LEN: usize = 16;
+/// User present (UP flag)
+const FLAG_USER_PRESENT: u8 = 0x01;
+
+/// Algorithms tested, with the name they are reported as
+const ALGORITHMS: &[(CoseAlgorithm, &str)] = &[
+	(CoseAlgorithm::ES256, "ES256"),
+	(CoseAlgorithm::EdDSA, "EdDSA"),
+	(CoseAlgorithm::ES256K, "ES256K (secp256k1)"),
+];
+
+/// Credential public key, in the form ring verifies signatures with
+enum CredentialKey {
+	/// Uncompressed P-256 point
+	P256(Vec<u8>),
+	Ed25519(Vec<u8>),
+	/// secp256k1 has no verifier here
+	Unverifiable,
+}
+
+impl CredentialKey {
+	/// Whether `sig` is a valid signature of `message`, `None` if it cannot be checked
+	fn verify(&self, message: &[u8], sig: &[u8]) -> Option<bool> {
+		let (algorithm, key): (&dyn signature::VerificationAlgorithm, _) = match self {
+			Self::P256(key) => (&signature::ECDSA_P256_SHA256_ASN1, key),
+			Self::Ed25519(key) => (&signature::ED25519, key),
+			Self::Unverifiable => return None,
+		};
+		Some(
+			signature::UnparsedPublicKey::new(algorithm, key)
+				.verify(message, sig)
+				.is_ok(),
+		)
+	}
+}
+
+fn cose_int(key: &BTreeMap<Value, Value>, param: CoseKeyParam) -> Option<i128> {
+	match key.get(&Value::Integer(param as i128)) {
+		Some(Value::Integer(value)) => Some(*value),
+		_ => None,
+	}
+}
+
+fn cose_bytes(key: &BTreeMap<Value, Value>, param: CoseKeyParam) -> Option<&[u8]> {
+	match key.get(&Value::Integer(param as i128)) {
+		Some(Value::Bytes(value)) => Some(value),
+		_ => None,
+	}
+}
+
+fn parse_cose_key(key: &BTreeMap<Value, Value>) -> Result<CredentialKey, String> {
+	let alg = cose_int(key, CoseKeyParam::Alg).ok_or("COSE key has no algorithm")?;
+	let x = cose_bytes(key, CoseKeyParam::X).ok_or("COSE key has no x coordinate")?;
+	match alg {
+		a if a == CoseAlgorithm::ES256 as i128 => {
+			let y = cose_bytes(key, CoseKeyParam::Y).ok_or("COSE key has no y coordinate")?;
+			let mut point = vec![0x04];
+			point.extend(x);
+			point.extend(y);
+			Ok(CredentialKey::P256(point))
+		}
+		a if a == CoseAlgorithm::EdDSA as i128 => Ok(CredentialKey::Ed25519(x.to_vec())),
+		a if a == CoseAlgorithm::ES256K as i128 => Ok(CredentialKey::Unverifiable),
+		a => Err(format!("Unexpected credential algorithm {}", a)),
+	}
+}
+
+/// Credential ID and public key from the attested credential data of `auth_data`
+fn parse_attested_credential(auth_data: &[u8]) -> Result<(Vec<u8>, i128, CredentialKey), String> {
+	// attestedCredentialData: aaguid (16) | credentialIdLength (2) | credentialId | publicKey
+	let attested = auth_data
+		.get(AUTH_DATA_HEADER_LEN..)
+		.ok_or("authData is truncated")?;
+	let id_start = AAGUID_LEN + 2;
+	if attested.len() < id_start {
+		return Err("Attested credential data is truncated".into());
+	}
+	let id_len = u16::from_be_bytes([attested[AAGUID_LEN], attested[AAGUID_LEN + 1]]) as usize;
+	let credential_id = attested
+		.get(id_start..id_start + id_len)
+		.ok_or("Credential ID is truncated")?;
+
+	// Extensions may follow the key, only decode the first item
+	let key = Deserializer::from_slice(&attested[id_start + id_len..])
+		.into_iter::<Value>()
+		.next()
+		.and_then(Result::ok);
+	let Some(Value::Map(key)) = key else {
+		return Err("Credential public key is not a COSE key".into());
+	};
+	let alg = cose_int(&key, CoseKeyParam::Alg).unwrap_or_default();
+	Ok((credential_id.to_vec(), alg, parse_cose_key(&key)?))
+}
+
+/// Checks the rpIdHash and the UP flag of `auth_data`
+fn check_auth_data(auth_data: &[u8]) -> Result<(), String> {
+	if auth_data.len() < AUTH_DATA_HEADER_LEN {
+		return Err("authData is truncated".into());
+	}
+	let rp_id_hash = digest::digest(&digest::SHA256, SELF_TEST_RP_ID.as_bytes());
+	if auth_data[..32] != *rp_id_hash.as_ref() {
+		return Err("authData is for another relying party".into());
+	}
+	if auth_data[32] & FLAG_USER_PRESENT == 0 {
+		return Err("authData does not assert user presence".into());
+	}
+	Ok(())
+}
+
+/// Verifies a packed attestation statement over `auth_data || client_data_hash`. `None` for the
+/// "none" format and self attestation with a key that cannot be checked.
+fn verify_attestation(
+	fmt: &str,
+	statement: &BTreeMap<Value, Value>,
+	credential_key: &CredentialKey,
+	message: &[u8],
+) -> Result<Option<bool>, String> {
+	if fmt == "none" {
+		return Ok(None);
+	}
+	if fmt != "packed" {
+		return Err(format!("Unsupported attestation format {}", fmt));
+	}
+	let Some(Value::Bytes(sig)) = statement.get(&Value::Text("sig".into())) else {
+		return Err("Attestation statement has no signature".into());
+	};
+
+	// Without a certificate, the statement is signed with the credential key itself
+	let Some(Value::Array(x5c)) = statement.get(&Value::Text("x5c".into())) else {
+		return Ok(credential_key.verify(message, sig));
+	};
+	let Some(Value::Bytes(der)) = x5c.first() else {
+		return Err("Attestation certificate chain is empty".into());
+	};
+	let (_, certificate) = X509Certificate::from_der(der)
+		.map_err(|e| format!("Invalid attestation certificate: {}", e))?;
+	let attestation_key =
+		CredentialKey::P256(certificate.public_key().subject_public_key.data.to_vec());
+	Ok(attestation_key.verify(message, sig))
+}
+
+/// Builds the pinUvAuthParam of a request with `client_data_hash`, PIN protocol one
+fn pin_auth(pin_token: &[u8], client_data_hash: &[u8]) -> Value {
+	let key = hmac::Key::new(hmac::HMAC_SHA256, pin_token);
+	Value::Bytes(hmac::sign(&key, client_data_hash).as_ref()[..16].to_vec())
+}
+
+fn make_credential_payload(
+	algorithm: CoseAlgorithm,
+	client_data_hash: &[u8],
+	pin_token: &[u8],
+) -> Result<Vec<u8>, PFError> {
+	let text = |s: &str| Value::Text(s.into());
+	let rp = BTreeMap::from([(text("id"), text(SELF_TEST_RP_ID))]);
+	let user_id: [u8; 16] = rand::rng().random();
+	let user = BTreeMap::from([
+		(text("id"), Value::Bytes(user_id.to_vec())),
+		(text("name"), text("PicoForge self test")),
+	]);
+	let param = BTreeMap::from([
+		(text("alg"), Value::Integer(algorithm as i128)),
+		(text("type"), text("public-key")),
+	]);
+
+	let request = BTreeMap::from([
+		(
+			Value::Integer(MakeCredentialParam::ClientDataHash as i128),
+			Value::Bytes(client_data_hash.to_vec()),
+		),
+		(
+			Value::Integer(MakeCredentialParam::Rp as i128),
+			Value::Map(rp),
+		),
+		(
+			Value::Integer(MakeCredentialParam::User as i128),
+			Value::Map(user),
+		),
+		(
+			Value::Integer(MakeCredentialParam::PubKeyCredParams as i128),
+			Value::Array(vec![Value::Map(param)]),
+		),
+		(
+			Value::Integer(MakeCredentialParam::PinUvAuthParam as i128),
+			pin_auth(pin_token, client_data_hash),
+		),
+		(
+			Value::Integer(MakeCredentialParam::PinUvAuthProtocol as i128),
+			Value::Integer(1),
+		),
+	]);
+	let mut payload = vec![CtapCommand::MakeCredential as u8];
+	payload.extend(to_vec(&Value::Map(request)).map_err(|e| PFError::Io(e.to_string()))?);
+	Ok(payload)
+}
+
+fn get_assertion_payload(
+	credential_id: &[u8],
+	client_data_hash: &[u8],
+	pin_token: &[u8],
+) -> Result<Vec<u8>, PFError> {
+	let descriptor = BTreeMap::from([
+		(
+			Value::Text("id".into()),
+			Value::Bytes(credential_id.to_vec()),
+		),
+		(Value::Text("type".into()), Value::Text("public-key".into())),
+	]);
+	let request = BTreeMap::from([
+		(
+			Value::Integer(GetAssertionParam::RpId as i128),
+			Value::Text(SELF_TEST_RP_ID.into()),
+		),
+		(
+			Value::Integer(GetAssertionParam::ClientDataHash as i128),
+			Value::Bytes(client_data_hash.to_vec()),
+		),
+		(
+			Value::Integer(GetAssertionParam::AllowList as i128),
+			Value::Array(vec![Value::Map(descriptor)]),
+		),
+		(
+			Value::Integer(GetAssertionParam::PinUvAuthParam as i128),
+			pin_auth(pin_token, client_data_hash),
+		),
+		(
+			Value::Integer(GetAssertionParam::PinUvAuthProtocol as i128),
+			Value::Integer(1),
+		),
+	]);
+	let mut payload = vec![CtapCommand::GetAssertion as u8];
+	payload.extend(to_vec(&Value::Map(request)).map_err(|e| PFError::Io(e.to_string()))?);
+	Ok(payload)
+}
+
+fn response_map(res: &[u8], action: &str) -> Result<BTreeMap<Value, Value>, PFError> {
+	match from_slice(res) {
+		Ok(Value::Map(m)) => Ok(m),
+		_ => Err(PFError::CborDecode(format!(
+			"{} response was not a valid CBOR map",
+			action
+		))),
+	}
+}
+
+fn response_bytes(m: &BTreeMap<Value, Value>, key: i128) -> Option<&[u8]> {
+	match m.get(&Value::Integer(key)) {
+		Some(Value::Bytes(value)) => Some(value),
+		_ => None,
+	}
+}
+
+/// Registers a credential with `algorithm` and signs with it. Device errors are returned, wrong
+/// responses are a failed result.
+fn round_trip(
+	transport: &HidTransport,
+	pin_token: &[u8],
+	algorithm: CoseAlgorithm,
+	name: &str,
+) -> Result<AlgorithmTestResult, PFError> {
+	let mut result = AlgorithmTestResult {
+		algorithm: name.to_string(),
+		cose_algorithm: algorithm as i32,
+		status: SelfTestStatus::Failed,
+		attestation_verified: None,
+		assertion_verified: None,
+		message: String::new(),
+	};
+	let fail = |mut result: AlgorithmTestResult, message: String| {
+		log::warn!("{} self test failed: {}", name, message);
+		result.message = message;
+		Ok(result)
+	};
+
+	let challenge: [u8; 32] = rand::rng().random();
+	let client_data_hash = digest::digest(&digest::SHA256, &challenge);
+	report_phase(
+		PhaseVerb::Create,
+		&format!("a test {} passkey", name),
+		UserAction::TouchDevice,
+	);
+	let res = match transport.send_cbor(
+		CTAPHID_CBOR,
+		&make_credential_payload(algorithm, client_data_hash.as_ref(), pin_token)?,
+	) {
+		Ok(res) => res,
+		Err(e) => {
+			if let Some(CtapStatus(status)) = e.downcast_ref::<CtapStatus>()
+				&& *status == Ctap2Error::UnsupportedAlgorithm as u8
+			{
+				result.status = SelfTestStatus::Unsupported;
+				result.message = "The firmware does not support this algorithm".into();
+				return Ok(result);
+			}
+			return Err(ctap_error(e, &format!("{} registration", name)));
+		}
+	};
+
+	let attestation = response_map(&res, "makeCredential")?;
+	let Some(Value::Text(fmt)) = attestation.get(&Value::Integer(0x01)) else {
+		return fail(result, "Attestation has no format".into());
+	};
+	let Some(auth_data) = response_bytes(&attestation, 0x02) else {
+		return fail(result, "Attestation has no authData".into());
+	};
+	let Some(Value::Map(statement)) = attestation.get(&Value::Integer(0x03)) else {
+		return fail(result, "Attestation has no statement".into());
+	};
+	if let Err(e) = check_auth_data(auth_data) {
+		return fail(result, e);
+	}
+	let (credential_id, alg, credential_key) = match parse_attested_credential(auth_data) {
+		Ok(credential) => credential,
+		Err(e) => return fail(result, e),
+	};
+	if alg != algorithm as i128 {
+		return fail(
+			result,
+			format!("Registered a credential with algorithm {} instead", alg),
+		);
+	}
+
+	let mut message = auth_data.to_vec();
+	message.extend(client_data_hash.as_ref());
+	result.attestation_verified =
+		match verify_attestation(fmt, statement, &credential_key, &message) {
+			Ok(verified) => verified,
+			Err(e) => return fail(result, e),
+		};
+	if result.attestation_verified == Some(false) {
+		return fail(result, "Attestation signature is invalid".into());
+	}
+
+	if cancel_requested() {
+		return Err(PFError::Cancelled);
+	}
+	let challenge: [u8; 32] = rand::rng().random();
+	let client_data_hash = digest::digest(&digest::SHA256, &challenge);
+	report_phase(
+		PhaseVerb::Sign,
+		&format!("with the test {} passkey", name),
+		UserAction::TouchDevice,
+	);
+	let res = transport
+		.send_cbor(
+			CTAPHID_CBOR,
+			&get_assertion_payload(&credential_id, client_data_hash.as_ref(), pin_token)?,
+		)
+		.map_err(|e| ctap_error(e, &format!("{} assertion", name)))?;
+
+	let assertion = response_map(&res, "getAssertion")?;
+	let (Some(auth_data), Some(sig)) = (
+		response_bytes(&assertion, 0x02),
+		response_bytes(&assertion, 0x03),
+	) else {
+		return fail(result, "Assertion has no authData or signature".into());
+	};
+	if let Err(e) = check_auth_data(auth_data) {
+		return fail(result, e);
+	}
+	let mut message = auth_data.to_vec();
+	message.extend(client_data_hash.as_ref());
+	result.assertion_verified = credential_key.verify(&message, sig);
+	if result.assertion_verified == Some(false) {
+		return fail(result, "Assertion signature is invalid".into());
+	}
+
+	if result.assertion_verified.is_none() {
+		result.status = SelfTestStatus::NotVerified;
+		result.message = "Round trip completed, the signatures could not be checked here".into();
+		log::info!("{} self test completed without verification", name);
+		return Ok(result);
+	}
+	result.status = SelfTestStatus::Passed;
+	result.message = "Registration and assertion verified".into();
+	log::info!("{} self test passed", name);
+	Ok(result)
+}
+
+/// Tests a registration and an assertion with every algorithm. secp256k1 is skipped unless
+/// `secp256k1_enabled`. Needs two touches per algorithm.
+pub fn run(
+	device_path: Option<&str>,
+	pin: &str,
+	secp256k1_enabled: bool,
+) -> Result<SelfTestReport, PFError> {
+	log::info!("Running the passkey self test...");
+	let device = connect(device_path)?;
+	report_phase(PhaseVerb::Read, "PIN token", UserAction::None);
+	let pin_token = device
+		.get_pin_token(pin)
+		.map_err(|e| pin_error(&device, e, "PIN token"))?
+		.key;
+	drop(device);
+
+	let transport =
+		HidTransport::open_path(device_path).map_err(|e| ctap_error(e, "HID connection"))?;
+	let mut results = Vec::new();
+	for (algorithm, name) in ALGORITHMS {
+		if *algorithm == CoseAlgorithm::ES256K && !secp256k1_enabled {
+			results.push(AlgorithmTestResult {
+				algorithm: name.to_string(),
+				cose_algorithm: *algorithm as i32,
+				status: SelfTestStatus::Skipped,
+				attestation_verified: None,
+				assertion_verified: None,
+				message: "secp256k1 is disabled in the configuration".into(),
+			});
+			continue;
+		}
+		if cancel_requested() {
+			return Err(PFError::Cancelled);
+		}
+		results.push(round_trip(&transport, &pin_token, *algorithm, name)?);
+	}
+
+	Ok(SelfTestReport {
+		rp_id: SELF_TEST_RP_ID.into(),
+		results,
+	})
+}
@@ -0,0 +1,410 @@
+//! Device transport the FIDO functions talk through, so they can run against a scripted one.
+//!
+//! `HidTransport` is the real one. The vendor and authenticatorConfig commands are built on
+//! `send_cbor` here, so `MockTransport` exercises the same encoding as a key would receive.
+
+use super::{ctap_error, hid::*};
+use crate::{error::PFError, fido::constants::*};
+use anyhow::Result;
+use serde_cbor_2::{Value, to_vec};
+use std::collections::BTreeMap;
+
+pub trait Transport {
+	/// Sends a CTAPHID command and returns the payload of the response without its status byte.
+	/// A non-zero status is returned as `CtapStatus`.
+	fn send_cbor(&self, cmd: u8, payload: &[u8]) -> Result<Vec<u8>>;
+
+	/// Platform path of the device, which the device caches are keyed by
+	fn path(&self) -> &str;
+
+	fn vid(&self) -> u16;
+
+	fn pid(&self) -> u16;
+
+	/// USB product string of the device
+	fn product_name(&self) -> &str;
+
+	/// USB serial number string of the device, which pico-keys firmware sets to the unique board
+	/// ID, the same the Rescue Applet reports. `None` where it is not visible.
+	fn serial_number(&self) -> Option<&str>;
+
+	/// Sends a read-only vendor command, `[command] {1: sub_command}`, on the open channel.
+	fn send_vendor_command(&self, command: VendorCommand, sub_command: i128) -> Result<Vec<u8>> {
+		self.send_cbor(
+			CTAP_VENDOR_CBOR_CMD,
+			&vendor_command_payload(command, sub_command)?,
+		)
+	}
+
+	/// Sends a vendor command carrying `param` and signed with `pin_token`, for the commands the
+	/// firmware only runs for the PIN holder.
+	fn send_signed_vendor_command(
+		&self,
+		pin_token: &[u8],
+		command: VendorCommand,
+		sub_command: i128,
+		param: Option<Vec<u8>>,
+	) -> Result<Vec<u8>> {
+		self.send_cbor(
+			CTAP_VENDOR_CBOR_CMD,
+			&signed_vendor_command_payload(pin_token, command, sub_command, param)?,
+		)
+	}
+
+	fn send_vendor_config(
+		&self,
+		pin_token: &[u8],
+		vendor_cmd: VendorConfigCommand,
+		param: Value,
+	) -> Result<(), PFError> {
+		let payload = vendor_config_payload(pin_token, vendor_cmd, param)?;
+
+		// Send via HID
+		self.send_cbor(CTAPHID_CBOR, &payload).map_err(|e| {
+			log::error!("Failed to send FIDO config: {}", e);
+			ctap_error(e, &format!("vendor config {}", vendor_cmd))
+		})?;
+
+		Ok(())
+	}
+
+	/// Sends a standard authenticatorConfig sub command, signed with `pin_token`.
+	fn send_config(
+		&self,
+		pin_token: &[u8],
+		sub_command: ConfigSubCommand,
+		sub_params: Option<Value>,
+	) -> Result<(), PFError> {
+		log::debug!("Sending config sub command {:?}...", sub_command);
+		let payload = config_payload(pin_token, sub_command, sub_params)?;
+		self.send_cbor(CTAPHID_CBOR, &payload).map_err(|e| {
+			log::error!("Failed to send FIDO config: {}", e);
+			ctap_error(e, &format!("config {:?}", sub_command))
+		})?;
+		Ok(())
+	}
+
+	/// Send authenticatorConfig command to set minimum PIN length, and the RP IDs allowed to
+	/// read it through the minPinLength extension if `rp_ids` is not empty.
+	///
+	/// This bypasses the ctap-hid-fido2 library which has a bug where it sends
+	/// CBOR map keys out of order (0x01, 0x03, 0x04, 0x02) instead of the required
+	/// ascending order (0x01, 0x02, 0x03, 0x04). The pico-fido firmware strictly
+	/// enforces canonical CBOR ordering per CTAP2 spec.
+	fn send_config_set_min_pin_length(
+		&self,
+		pin_token: &[u8],
+		new_min_pin_length: u8,
+		rp_ids: &[String],
+	) -> Result<(), PFError> {
+		log::debug!(
+			"Sending setMinPINLength config command (new length: {})...",
+			new_min_pin_length
+		);
+
+		// Build subCommandParams (Key 0x02): { 0x01: newMinPINLength, 0x02: minPinLengthRPIDs }
+		let mut sub_params_map = BTreeMap::new();
+		sub_params_map.insert(
+			Value::Integer(ConfigSubCommandParam::NewMinPinLength as i128),
+			Value::Integer(new_min_pin_length as i128),
+		);
+		if !rp_ids.is_empty() {
+			sub_params_map.insert(
+				Value::Integer(ConfigSubCommandParam::MinPinLengthRPIDs as i128),
+				Value::Array(rp_ids.iter().cloned().map(Value::Text).collect()),
+			);
+		}
+		let sub_params = Value::Map(sub_params_map);
+		let sub_params_bytes = to_vec(&sub_params).map_err(|e| PFError::Io(e.to_string()))?;
+
+		// Build HMAC message for signing
+		// Per FIDO 2.1 spec: authenticate(pinUvAuthToken, 32×0xff || 0x0d || uint8(subCommand) || subCommandParams)
+		let mut message = vec![0xff; 32];
+		message.push(CtapCommand::Config as u8); // 0x0d
+		message.push(ConfigSubCommand::SetMinPinLength as u8); // 0x03
+		message.extend(&sub_params_bytes);
+
+		// Sign using provided PIN token (Protocol 1 uses HMAC-SHA256, first 16 bytes)
+		use ring::hmac;
+		let hmac_key = hmac::Key::new(hmac::HMAC_SHA256, pin_token);
+		let sig = hmac::sign(&hmac_key, &message);
+		let pin_auth = sig.as_ref()[0..16].to_vec();
+
+		// Build full authenticatorConfig map with keys in ASCENDING ORDER
+		// This is critical - the firmware parser rejects out-of-order keys with CTAP2_ERR_INVALID_CBOR
+		let mut config_map = BTreeMap::new();
+		config_map.insert(
+			Value::Integer(ConfigParam::SubCommand as i128), // 0x01
+			Value::Integer(ConfigSubCommand::SetMinPinLength as i128), // 0x03
+		);
+		config_map.insert(
+			Value::Integer(ConfigParam::SubCommandParams as i128), // 0x02
+			sub_params,
+		);
+		config_map.insert(
+			Value::Integer(ConfigParam::PinUvAuthProtocol as i128), // 0x03
+			Value::Integer(1),                                      // PIN protocol version 1
+		);
+		config_map.insert(
+			Value::Integer(ConfigParam::PinUvAuthParam as i128), // 0x04
+			Value::Bytes(pin_auth),
+		);
+
+		let config_payload_cbor =
+			to_vec(&Value::Map(config_map)).map_err(|e| PFError::Io(e.to_string()))?;
+
+		// Prepend CTAP command byte
+		let mut payload = vec![CtapCommand::Config as u8];
+		payload.extend(config_payload_cbor);
+
+		// Send via HID
+		match self.send_cbor(CTAPHID_CBOR, &payload) {
+			Ok(_) => {
+				log::info!(
+					"Successfully set minimum PIN length to {}",
+					new_min_pin_length
+				);
+				Ok(())
+			}
+			Err(e) => {
+				log::error!("Failed to send setMinPINLength config: {}", e);
+
+				// PIN policy violation - cannot decrease min PIN length
+				if e.downcast_ref::<CtapStatus>()
+					.is_some_and(|CtapStatus(status)| {
+						*status == Ctap2Error::PinPolicyViolation as u8
+					}) {
+					return Err(PFError::Device(
+						"Cannot decrease minimum PIN length. The FIDO2 security policy only allows increasing the minimum PIN length, not decreasing it. A device reset is required to lower the minimum.".into()
+					));
+				}
+
+				Err(ctap_error(e, "setMinPINLength"))
+			}
+		}
+	}
+}
+
+/// What `MockTransport` does with a request
+#[cfg(test)]
+enum Scripted {
+	Respond(Vec<u8>),
+	/// Fails with the CTAP status
+	Status(u8),
+	/// The request is lost like a dropped packet, so the key never answers
+	Drop,
+}
+
+/// Transport replaying canned responses and faults in order, recording the requests sent to it
+#[cfg(test)]
+pub struct MockTransport {
+	pub path: String,
+	serial_number: Option<String>,
+	/// Replies to the next requests, each after its delay
+	responses: std::sync::Mutex<std::collections::VecDeque<(std::time::Duration, Scripted)>>,
+	requests: std::sync::Mutex<Vec<(u8, Vec<u8>)>>,
+}
+
+#[cfg(test)]
+impl MockTransport {
+	/// Mock at `path`. Device caches are keyed by path, so tests use one path each.
+	pub fn new(path: &str) -> Self {
+		Self {
+			path: path.into(),
+			serial_number: None,
+			responses: Default::default(),
+			requests: Default::default(),
+		}
+	}
+
+	/// Reports `serial` as the USB serial number
+	pub fn serial_number(mut self, serial: &str) -> Self {
+		self.serial_number = Some(serial.into());
+		self
+	}
+
+	fn script(self, reply: Scripted) -> Self {
+		self.responses
+			.lock()
+			.unwrap()
+			.push_back((Default::default(), reply));
+		self
+	}
+
+	/// Queues a successful response carrying `value`, CBOR encoded
+	pub fn respond(self, value: Value) -> Self {
+		self.respond_raw(to_vec(&value).unwrap())
+	}
+
+	/// Queues a successful response carrying `payload`
+	pub fn respond_raw(self, payload: Vec<u8>) -> Self {
+		self.script(Scripted::Respond(payload))
+	}
+
+	/// Queues a response failing with the CTAP status `status`
+	pub fn fail(self, status: u8) -> Self {
+		self.script(Scripted::Status(status))
+	}
+
+	/// Queues a request that is never answered, failing like the HID transport does once the key
+	/// stayed silent for too long
+	pub fn drop_packet(self) -> Self {
+		self.script(Scripted::Drop)
+	}
+
+	/// Holds back the reply queued last by `delay`
+	pub fn delayed(self, delay: std::time::Duration) -> Self {
+		if let Some((after, _)) = self.responses.lock().unwrap().back_mut() {
+			*after = delay;
+		}
+		self
+	}
+
+	/// Requests sent so far, as (CTAPHID command, payload)
+	pub fn requests(&self) -> Vec<(u8, Vec<u8>)> {
+		self.requests.lock().unwrap().clone()
+	}
+
+	/// Whether every queued response was consumed
+	pub fn exhausted(&self) -> bool {
+		self.responses.lock().unwrap().is_empty()
+	}
+}
+
+#[cfg(test)]
+impl Transport for MockTransport {
+	fn send_cbor(&self, cmd: u8, payload: &[u8]) -> Result<Vec<u8>> {
+		self.requests.lock().unwrap().push((cmd, payload.to_vec()));
+		let Some((delay, reply)) = self.responses.lock().unwrap().pop_front() else {
+			return Err(anyhow::anyhow!(
+				"No response scripted for command 0x{:02X}",
+				cmd
+			));
+		};
+		std::thread::sleep(delay);
+		match reply {
+			Scripted::Respond(response) => Ok(response),
+			Scripted::Status(status) => Err(CtapStatus(status).into()),
+			Scripted::Drop => Err(std::io::Error::new(
+				std::io::ErrorKind::TimedOut,
+				"The key did not answer in time",
+			)
+			.into()),
+		}
+	}
+
+	fn path(&self) -> &str {
+		&self.path
+	}
+
+	fn vid(&self) -> u16 {
+		0x2E8A
+	}
+
+	fn pid(&self) -> u16 {
+		0x10FE
+	}
+
+	fn product_name(&self) -> &str {
+		"Mock Pico Key"
+	}
+
+	fn serial_number(&self) -> Option<&str> {
+		self.serial_number.as_deref()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_cbor_2::from_slice;
+
+	#[test]
+	fn signed_vendor_command_carries_a_verifiable_pin_auth() {
+		let token = [0x42; 32];
+		let transport = MockTransport::new("mock-signed").respond_raw(Vec::new());
+		transport
+			.send_signed_vendor_command(
+				&token,
+				VendorCommand::Backup,
+				BackupSubCommand::GetEncryptedBackup as i128,
+				Some(vec![1, 2, 3]),
+			)
+			.unwrap();
+
+		let (cmd, payload) = transport.requests().remove(0);
+		assert_eq!(cmd, CTAP_VENDOR_CBOR_CMD);
+		let Value::Map(params) = from_slice(&payload[1..]).unwrap() else {
+			panic!("Request is not a CBOR map");
+		};
+		let get = |key: VendorParam| params.get(&Value::Integer(key as i128)).unwrap();
+		let Value::Bytes(pin_auth) = get(VendorParam::PinUvAuthParam) else {
+			panic!("pinUvAuthParam is not a byte string");
+		};
+
+		let mut message = vec![0xff; 32];
+		message.push(payload[0]);
+		message.push(BackupSubCommand::GetEncryptedBackup as u8);
+		message.extend(to_vec(get(VendorParam::VendorSubParams)).unwrap());
+		use ring::hmac;
+		let key = hmac::Key::new(hmac::HMAC_SHA256, &token);
+		assert_eq!(&hmac::sign(&key, &message).as_ref()[..16], &pin_auth[..]);
+	}
+
+	#[test]
+	fn min_pin_length_decrease_is_explained() {
+		let transport =
+			MockTransport::new("mock-min-pin").fail(Ctap2Error::PinPolicyViolation as u8);
+		let Err(PFError::Device(message)) =
+			transport.send_config_set_min_pin_length(&[0; 32], 4, &[])
+		else {
+			panic!("Policy violation was not reported as a device error");
+		};
+		assert!(message.contains("Cannot decrease minimum PIN length"));
+	}
+
+	#[test]
+	fn client_pin_traces_are_redacted() {
+		let request = [CtapCommand::ClientPin as u8, 0xA1, 0x01, 0x01];
+		assert_eq!(
+			trace_payload(&request, &[0xAA; 48]),
+			"<48 bytes of clientPin, redacted>"
+		);
+		let request = [CtapCommand::GetInfo as u8];
+		assert_eq!(trace_payload(&request, &[0xA1, 0x01]), "a101");
+	}
+
+	#[test]
+	fn dropped_packet_is_a_transport_error() {
+		let transport = MockTransport::new("mock-dropped").drop_packet();
+		let result = transport.send_vendor_config(
+			&[0; 32],
+			VendorConfigCommand::AuthEncryptionEnable,
+			Value::Integer(1),
+		);
+		assert!(matches!(result, Err(PFError::TransportIo(_))));
+		assert!(transport.exhausted());
+	}
+
+	#[test]
+	fn touch_timeout_after_a_delay_is_reported() {
+		let delay = std::time::Duration::from_millis(50);
+		let transport = MockTransport::new("mock-delayed")
+			.fail(Ctap2Error::UserActionTimeout as u8)
+			.delayed(delay);
+		let started = std::time::Instant::now();
+		let result = transport.send_config(&[0; 32], ConfigSubCommand::ToggleAlwaysUv, None);
+		assert!(started.elapsed() >= delay);
+		assert!(matches!(result, Err(PFError::UserPresenceTimeout)));
+	}
+
+	#[test]
+	fn unscripted_request_fails() {
+		let transport = MockTransport::new("mock-empty");
+		assert!(
+			transport
+				.send_vendor_command(VendorCommand::Memory, MemorySubCommand::GetStats as i128)
+				.is_err()
+		);
+	}
+}
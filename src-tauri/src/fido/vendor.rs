@@ -0,0 +1,468 @@
+//! Read-only pico-fido vendor commands, sent and decoded through one typed layer.
+//!
+//! Each `VendorRequest` maps to a vendor command and sub command, and its response is decoded
+//! into the matching `VendorResponse`. Firmware without a command answers with CTAP1_ERR_INVALID
+//! _COMMAND or an invalid sub command status, which surfaces as `PFError::VendorUnsupported`.
+//!
+//! `probe_capabilities` asks each category once to learn what the firmware supports. The reboot
+//! command cannot be probed without rebooting the key, and the secure boot state is read through
+//! the Rescue Applet rather than a vendor command, so neither is part of the probe.
+//!
+//! `send_raw` bypasses the typed layer for the developer console, so sub commands that
+//! picoforge does not know yet can be tried against new firmware.
+
+use super::{
+	cache,
+	constants::*,
+	ctap_error,
+	hid::{CTAPHID_CBOR, CtapStatus},
+	phy::{PhyConfig, PhyTag},
+	transport::Transport,
+};
+use crate::{
+	error::PFError,
+	schema::registry::ProtocolConstant,
+	types::{RawVendorResponse, VendorCapabilities, VendorSupport},
+};
+use serde_cbor_2::{Value, from_slice};
+use std::collections::BTreeMap;
+
+/// Vendor commands that only read from the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VendorRequest {
+	MemoryStats,
+	PhysicalOptions,
+	/// Certificate signing request for the enterprise attestation key
+	EnterpriseAttestationCsr,
+}
+
+#[derive(Debug, Clone)]
+pub enum VendorResponse {
+	MemoryStats(cache::MemoryStats),
+	PhysicalOptions(cache::PhysicalOptions),
+	/// DER encoded CSR
+	EnterpriseAttestationCsr(Vec<u8>),
+}
+
+impl VendorRequest {
+	/// Vendor command and sub command of the request
+	pub fn command(self) -> (VendorCommand, i128) {
+		match self {
+			Self::MemoryStats => (VendorCommand::Memory, MemorySubCommand::GetStats as i128),
+			Self::PhysicalOptions => (
+				VendorCommand::PhysicalOptions,
+				PhysicalOptionsSubCommand::GetOptions as i128,
+			),
+			Self::EnterpriseAttestationCsr => (
+				VendorCommand::EnterpriseAttestation,
+				EnterpriseAttestationSubCommand::GenerateCsr as i128,
+			),
+		}
+	}
+
+	/// What the request reads, for errors and logs
+	pub fn action(self) -> &'static str {
+		match self {
+			Self::MemoryStats => "memory stats",
+			Self::PhysicalOptions => "physical options",
+			Self::EnterpriseAttestationCsr => "enterprise attestation CSR",
+		}
+	}
+
+	/// Decodes the response payload of the request
+	pub fn decode(self, res: &[u8]) -> Result<VendorResponse, PFError> {
+		Ok(match self {
+			Self::MemoryStats => VendorResponse::MemoryStats(parse_memory_stats(res)?),
+			Self::PhysicalOptions => VendorResponse::PhysicalOptions(parse_physical_options(res)?),
+			Self::EnterpriseAttestationCsr => {
+				VendorResponse::EnterpriseAttestationCsr(parse_csr(res)?)
+			}
+		})
+	}
+}
+
+/// Sends `request` over `transport` and decodes the response
+pub fn send(transport: &impl Transport, request: VendorRequest) -> Result<VendorResponse, PFError> {
+	log::debug!("Sending {} vendor command...", request.action());
+	let (command, sub_command) = request.command();
+	let res = transport
+		.send_vendor_command(command, sub_command)
+		.map_err(|e| ctap_error(e, request.action()))?;
+	request.decode(&res)
+}
+
+fn unexpected(request: VendorRequest) -> PFError {
+	PFError::CborDecode(format!(
+		"Unexpected response to the {} request",
+		request.action()
+	))
+}
+
+pub fn memory_stats(transport: &impl Transport) -> Result<cache::MemoryStats, PFError> {
+	match send(transport, VendorRequest::MemoryStats)? {
+		VendorResponse::MemoryStats(stats) => Ok(stats),
+		_ => Err(unexpected(VendorRequest::MemoryStats)),
+	}
+}
+
+pub fn physical_options(transport: &impl Transport) -> Result<cache::PhysicalOptions, PFError> {
+	match send(transport, VendorRequest::PhysicalOptions)? {
+		VendorResponse::PhysicalOptions(options) => Ok(options),
+		_ => Err(unexpected(VendorRequest::PhysicalOptions)),
+	}
+}
+
+pub fn enterprise_attestation_csr(transport: &impl Transport) -> Result<Vec<u8>, PFError> {
+	match send(transport, VendorRequest::EnterpriseAttestationCsr)? {
+		VendorResponse::EnterpriseAttestationCsr(der) => Ok(der),
+		_ => Err(unexpected(VendorRequest::EnterpriseAttestationCsr)),
+	}
+}
+
+/// Sends `cbor` as the parameters of the vendor command `command`, unchecked beyond being valid
+/// CBOR. A CTAP error status is returned in the response rather than as an error.
+pub fn send_raw(
+	transport: &impl Transport,
+	command: u8,
+	cbor: &[u8],
+) -> Result<RawVendorResponse, PFError> {
+	if !cbor.is_empty() {
+		from_slice::<Value>(cbor)
+			.map_err(|e| PFError::CborDecode(format!("Request parameters: {}", e)))?;
+	}
+	let mut payload = vec![command];
+	payload.extend_from_slice(cbor);
+
+	let (status, data) = match transport.send_cbor(CTAP_VENDOR_CBOR_CMD, &payload) {
+		Ok(data) => (0, data),
+		Err(e) => match e.downcast_ref::<CtapStatus>() {
+			Some(CtapStatus(status)) => (*status, Vec::new()),
+			None => return Err(ctap_error(e, &format!("vendor command 0x{:02X}", command))),
+		},
+	};
+	log::info!(
+		"Raw vendor command 0x{:02X} answered with status 0x{:02X}, {} bytes",
+		command,
+		status,
+		data.len()
+	);
+
+	let (diagnostic, decode_error) = if data.is_empty() {
+		(None, None)
+	} else {
+		match from_slice::<Value>(&data) {
+			Ok(value) => (serde_json::to_string_pretty(&diagnostic(&value)).ok(), None),
+			Err(e) => (None, Some(e.to_string())),
+		}
+	};
+	Ok(RawVendorResponse {
+		status,
+		data: hex::encode_upper(&data),
+		diagnostic,
+		decode_error,
+	})
+}
+
+/// Map key in diagnostic notation, as JSON only has string keys
+fn diagnostic_key(key: &Value) -> String {
+	match key {
+		Value::Text(text) => text.clone(),
+		Value::Integer(n) => n.to_string(),
+		other => diagnostic(other).to_string(),
+	}
+}
+
+/// JSON rendering of a CBOR value: byte strings as `h'..'`, tags as `{"tag", "value"}`, and
+/// integers beyond the range of JSON numbers as strings
+fn diagnostic(value: &Value) -> serde_json::Value {
+	use serde_json::Value as Json;
+	match value {
+		Value::Null => Json::Null,
+		Value::Bool(b) => Json::Bool(*b),
+		Value::Integer(n) => i64::try_from(*n)
+			.map(Json::from)
+			.or_else(|_| u64::try_from(*n).map(Json::from))
+			.unwrap_or_else(|_| Json::String(n.to_string())),
+		Value::Float(f) => Json::from(*f),
+		Value::Bytes(bytes) => Json::String(format!("h'{}'", hex::encode(bytes))),
+		Value::Text(text) => Json::String(text.clone()),
+		Value::Array(items) => Json::Array(items.iter().map(diagnostic).collect()),
+		Value::Map(entries) => Json::Object(
+			entries
+				.iter()
+				.map(|(key, value)| (diagnostic_key(key), diagnostic(value)))
+				.collect(),
+		),
+		Value::Tag(tag, value) => serde_json::json!({ "tag": tag, "value": diagnostic(value) }),
+		_ => Json::String(format!("{:?}", value)),
+	}
+}
+
+/// Logs why an optional vendor read fell back to a default, quietly if the firmware lacks it
+pub fn log_unavailable(what: &str, e: &PFError) {
+	match e {
+		PFError::VendorUnsupported(_) => log::info!("{}", e),
+		e => log::warn!("Failed to fetch {} (Vendor Cmd): {}", what, e),
+	}
+}
+
+/// Support of a category, from the outcome of a request probing it
+fn support<T>(category: &str, result: Result<T, PFError>) -> VendorSupport {
+	match result {
+		Ok(_) => VendorSupport::Supported,
+		Err(PFError::VendorUnsupported(_)) => VendorSupport::Unsupported,
+		Err(e) => {
+			log::warn!("Could not probe {} support: {}", category, e);
+			VendorSupport::Unknown
+		}
+	}
+}
+
+/// Probes which vendor command categories the firmware behind `transport` supports.
+///
+/// Backups are only handed out to the PIN holder, so the backup category is probed with an
+/// unsigned request: firmware that knows the command rejects it for the missing PIN, other
+/// firmware as an unknown command. The CSR request is left out since it may generate the
+/// attestation key, enterprise attestation support is taken from GetInfo instead.
+pub fn probe_capabilities(transport: &impl Transport) -> Result<VendorCapabilities, PFError> {
+	log::info!("Probing vendor command support...");
+	let info_res = transport
+		.send_cbor(CTAPHID_CBOR, &[CtapCommand::GetInfo as u8])
+		.map_err(|e| ctap_error(e, "GetInfo"))?;
+	let (_, firmware_version) = super::parse_get_info(&info_res)?;
+
+	let backup = match transport.send_vendor_command(
+		VendorCommand::Backup,
+		BackupSubCommand::GetEncryptedBackup as i128,
+	) {
+		Ok(_) => VendorSupport::Supported,
+		Err(e) => match ctap_error(e, "backup") {
+			PFError::VendorUnsupported(_) => VendorSupport::Unsupported,
+			PFError::Device(_) | PFError::PinRequired | PFError::PinInvalid { .. } => {
+				VendorSupport::Supported
+			}
+			e => {
+				log::warn!("Could not probe backup support: {}", e);
+				VendorSupport::Unknown
+			}
+		},
+	};
+
+	let capabilities = VendorCapabilities {
+		memory: support("memory stats", memory_stats(transport)),
+		physical_options: support("physical options", physical_options(transport)),
+		backup,
+		enterprise_attestation: match enterprise_attestation_option(&info_res) {
+			Some(_) => VendorSupport::Supported,
+			None => VendorSupport::Unsupported,
+		},
+		firmware_version,
+	};
+	log::info!("Vendor command support: {:?}", capabilities);
+	Ok(capabilities)
+}
+
+/// The `ep` option of a GetInfo response, absent if enterprise attestation is unsupported
+fn enterprise_attestation_option(info_res: &[u8]) -> Option<bool> {
+	let Ok(Value::Map(info)) = from_slice(info_res) else {
+		return None;
+	};
+	let Some(Value::Map(options)) = info.get(&Value::Integer(0x04)) else {
+		return None;
+	};
+	match options.get(&Value::Text("ep".into())) {
+		Some(Value::Bool(enabled)) => Some(*enabled),
+		_ => None,
+	}
+}
+
+pub(crate) fn parse_memory_stats(mem_res: &[u8]) -> Result<cache::MemoryStats, PFError> {
+	let mem_map: BTreeMap<i128, i128> = from_slice(mem_res).map_err(|e| {
+		log::error!("Failed to parse Memory Stats CBOR response: {}", e);
+		PFError::CborDecode(e.to_string())
+	})?;
+
+	let get = |key: MemoryResponseKey| -> Result<Option<u32>, PFError> {
+		Ok(mem_map
+			.get(&(key.value()? as i128))
+			.and_then(|value| u32::try_from(*value).ok()))
+	};
+	Ok(cache::MemoryStats {
+		used: get(MemoryResponseKey::UsedSpace)?.unwrap_or(0),
+		total: get(MemoryResponseKey::TotalSpace)?.unwrap_or(0),
+		free: get(MemoryResponseKey::FreeSpace)?,
+		files: get(MemoryResponseKey::NumFiles)?,
+		flash_size: get(MemoryResponseKey::FlashSize)?,
+	})
+}
+
+fn parse_csr(res: &[u8]) -> Result<Vec<u8>, PFError> {
+	let Ok(Value::Map(m)) = from_slice(res) else {
+		return Err(PFError::CborDecode(
+			"CSR response was not a valid CBOR map".into(),
+		));
+	};
+	match m.get(&Value::Integer(
+		EnterpriseAttestationResponseKey::Csr as i128,
+	)) {
+		Some(Value::Bytes(der)) => Ok(der.clone()),
+		_ => Err(PFError::Device("CSR missing from the response".into())),
+	}
+}
+
+/// Key of each PHY field in the physical options response, the firmware define without `PHY_`
+fn phy_key(tag: PhyTag) -> &'static str {
+	match tag {
+		PhyTag::VidPid => "vidpid",
+		PhyTag::LedGpio => "gpio",
+		PhyTag::LedBrightness => "brightness",
+		PhyTag::Opts => "opts",
+		PhyTag::PresenceTimeout => "up_btn",
+		PhyTag::UsbProduct => "usb_product",
+		PhyTag::Curves => "curves",
+		PhyTag::UsbInterfaces => "enabled_usb_itf",
+		PhyTag::LedDriver => "led_driver",
+	}
+}
+
+pub(crate) fn parse_physical_options(phy_res: &[u8]) -> Result<cache::PhysicalOptions, PFError> {
+	let Ok(Value::Map(m)) = from_slice(phy_res) else {
+		return Err(PFError::CborDecode(
+			"Physical config response was not a valid CBOR map".into(),
+		));
+	};
+	log::debug!("Parsed Physical Config map successfully");
+
+	// Fields are keyed by name, or by PHY tag on some firmware. They are put back together into
+	// the TLV blob the Rescue Applet returns, so both methods decode the fields the same way.
+	let mut blob = Vec::new();
+	for &tag in PhyTag::ALL {
+		let code = tag.code()?;
+		let Some(value) = m
+			.get(&Value::Text(phy_key(tag).into()))
+			.or_else(|| m.get(&Value::Integer(code as i128)))
+		else {
+			continue;
+		};
+		let bytes = match (value, tag.value_len()) {
+			(Value::Integer(v), Some(len)) => (*v as u64).to_be_bytes()[8 - len..].to_vec(),
+			(Value::Bytes(bytes), _) => bytes.clone(),
+			(Value::Text(text), None) => text.as_bytes().to_vec(),
+			_ => {
+				log::debug!("Ignoring PHY field {:?} of unexpected type", tag);
+				continue;
+			}
+		};
+		let len = u8::try_from(bytes.len())
+			.map_err(|_| PFError::CborDecode(format!("PHY field {:?} is too long", tag)))?;
+		blob.push(code);
+		blob.push(len);
+		blob.extend_from_slice(&bytes);
+	}
+	PhyConfig::decode(&blob)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::fido::transport::MockTransport;
+
+	fn get_info(ep: Option<bool>) -> Value {
+		let mut info = BTreeMap::new();
+		info.insert(Value::Integer(0x0E), Value::Integer(0x0702));
+		if let Some(ep) = ep {
+			let options = [(Value::Text("ep".into()), Value::Bool(ep))];
+			info.insert(
+				Value::Integer(0x04),
+				Value::Map(options.into_iter().collect()),
+			);
+		}
+		Value::Map(info)
+	}
+
+	#[test]
+	fn raw_command_renders_diagnostic_json() {
+		let response = [
+			(Value::Integer(1), Value::Bytes(vec![0xCA, 0xFE])),
+			(Value::Text("on".into()), Value::Bool(true)),
+		];
+		let transport = MockTransport::new("mock-vendor-raw")
+			.respond(Value::Map(response.into_iter().collect()))
+			.fail(Ctap2Error::InvalidSubcommand as u8);
+
+		// {1: 2}
+		let raw = send_raw(&transport, 0x42, &[0xA1, 0x01, 0x02]).unwrap();
+		assert_eq!(raw.status, 0);
+		let json: serde_json::Value = serde_json::from_str(&raw.diagnostic.unwrap()).unwrap();
+		assert_eq!(json, serde_json::json!({ "1": "h'cafe'", "on": true }));
+
+		let refused = send_raw(&transport, 0x42, &[]).unwrap();
+		assert_eq!(refused.status, Ctap2Error::InvalidSubcommand as u8);
+		assert!(refused.diagnostic.is_none());
+
+		let requests = transport.requests();
+		assert_eq!(
+			requests[0],
+			(CTAP_VENDOR_CBOR_CMD, vec![0x42, 0xA1, 0x01, 0x02])
+		);
+		assert_eq!(requests[1], (CTAP_VENDOR_CBOR_CMD, vec![0x42]));
+	}
+
+	#[test]
+	fn raw_command_rejects_invalid_cbor() {
+		let transport = MockTransport::new("mock-vendor-raw-invalid");
+		assert!(matches!(
+			send_raw(&transport, 0x42, &[0xA1]),
+			Err(PFError::CborDecode(_))
+		));
+		assert!(transport.requests().is_empty());
+	}
+
+	#[test]
+	fn unknown_command_is_reported_unsupported() {
+		let transport =
+			MockTransport::new("mock-vendor-unknown").fail(Ctap2Error::InvalidCommand as u8);
+		assert!(matches!(
+			physical_options(&transport),
+			Err(PFError::VendorUnsupported(_))
+		));
+	}
+
+	#[test]
+	fn probe_reports_each_category() {
+		let transport = MockTransport::new("mock-vendor-probe")
+			.respond(get_info(Some(false)))
+			// Backup, refused for the missing PIN
+			.fail(Ctap2Error::PuatRequired as u8)
+			.respond(Value::Map(BTreeMap::new()))
+			.fail(Ctap2Error::InvalidCommand as u8);
+
+		let capabilities = probe_capabilities(&transport).unwrap();
+		assert!(transport.exhausted());
+		assert_eq!(capabilities.firmware_version, "7.2");
+		assert_eq!(capabilities.backup, VendorSupport::Supported);
+		assert_eq!(capabilities.memory, VendorSupport::Supported);
+		assert_eq!(capabilities.physical_options, VendorSupport::Unsupported);
+		assert_eq!(
+			capabilities.enterprise_attestation,
+			VendorSupport::Supported
+		);
+	}
+
+	#[test]
+	fn probe_without_vendor_commands() {
+		let transport = MockTransport::new("mock-vendor-probe-stock")
+			.respond(get_info(None))
+			.fail(Ctap2Error::InvalidCommand as u8)
+			.fail(Ctap2Error::InvalidCommand as u8)
+			.fail(Ctap2Error::InvalidCommand as u8);
+
+		let capabilities = probe_capabilities(&transport).unwrap();
+		assert_eq!(capabilities.backup, VendorSupport::Unsupported);
+		assert_eq!(capabilities.memory, VendorSupport::Unsupported);
+		assert_eq!(
+			capabilities.enterprise_attestation,
+			VendorSupport::Unsupported
+		);
+	}
+}
@@ -0,0 +1,114 @@
+//! Hotplug detection of FIDO keys.
+//!
+//! hidapi has no portable notification API, so the list of FIDO HID interfaces is polled and
+//! compared with the previous one. Keys are told apart by their platform HID path, which also
+//! changes when a key is re-plugged.
+
+use super::hid::{self, enumerate_devices};
+use super::pin_token;
+use crate::{
+	activity::{self, ActivityDetail},
+	types::{ActivityKind, FidoHidDevice},
+};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Broadcast to every window when a FIDO key is plugged in
+pub const DEVICE_CONNECTED_EVENT: &str = "device-connected";
+/// Broadcast to every window when a FIDO key is removed
+pub const DEVICE_REMOVED_EVENT: &str = "device-removed";
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When each key was seen plugged in, keyed by HID path. Keys present at startup are missing.
+static PLUGGED_IN: Mutex<BTreeMap<String, Instant>> = Mutex::new(BTreeMap::new());
+
+/// When the key at `path` was plugged in, if it was plugged in while the app was running
+pub fn plugged_in_at(path: &str) -> Option<Instant> {
+	PLUGGED_IN.lock().ok()?.get(path).copied()
+}
+
+/// FIDO interfaces currently present, keyed by HID path
+fn fido_devices(api: &hidapi::HidApi) -> BTreeMap<String, FidoHidDevice> {
+	enumerate_devices(api)
+		.into_iter()
+		.map(|device| (device.path.clone(), device))
+		.collect()
+}
+
+/// Starts the background thread calling `connected` and `removed` as keys come and go.
+///
+/// Keys already present at startup are reported as connected, with `at_startup` set, so windows
+/// get the initial state through the same events.
+pub fn start(
+	connected: impl Fn(&FidoHidDevice, bool) + Send + 'static,
+	removed: impl Fn(&FidoHidDevice) + Send + 'static,
+) {
+	std::thread::spawn(move || {
+		let mut api = match hidapi::HidApi::new() {
+			Ok(api) => api,
+			Err(e) => {
+				log::error!(
+					"Hotplug detection disabled, could not initialize hidapi: {}",
+					e
+				);
+				return;
+			}
+		};
+
+		let mut known: BTreeMap<String, FidoHidDevice> = BTreeMap::new();
+		let mut startup = true;
+		loop {
+			if let Err(e) = api.refresh_devices() {
+				log::warn!("Failed to refresh the HID device list: {}", e);
+			} else {
+				let current = fido_devices(&api);
+				for (path, device) in &current {
+					if !known.contains_key(path) {
+						log::info!(
+							"FIDO key connected: {} ({}:{})",
+							device.product_name,
+							device.vid,
+							device.pid
+						);
+						if !startup && let Ok(mut plugged_in) = PLUGGED_IN.lock() {
+							plugged_in.insert(path.clone(), Instant::now());
+						}
+						activity::record(
+							ActivityKind::DeviceConnected,
+							format!("{} connected", device.product_name),
+							ActivityDetail {
+								device: Some(device.product_name.clone()),
+								..Default::default()
+							},
+						);
+						connected(device, startup);
+					}
+				}
+				for (path, device) in &known {
+					if !current.contains_key(path) {
+						log::info!("FIDO key removed: {}", device.product_name);
+						if let Ok(mut plugged_in) = PLUGGED_IN.lock() {
+							plugged_in.remove(path);
+						}
+						hid::forget_channel(path);
+						pin_token::forget(path);
+						activity::record(
+							ActivityKind::DeviceRemoved,
+							format!("{} removed", device.product_name),
+							ActivityDetail {
+								device: Some(device.product_name.clone()),
+								..Default::default()
+							},
+						);
+						removed(device);
+					}
+				}
+				known = current;
+				startup = false;
+			}
+			std::thread::sleep(POLL_INTERVAL);
+		}
+	});
+}
@@ -0,0 +1,172 @@
+//! Cross-checks a WebAuthn registration response, as printed by a site's debug output, against
+//! the resident credentials of the device.
+//!
+//! Accepts the JSON serialization of a `PublicKeyCredential` (`id`, `rawId`, `response` with
+//! `clientDataJSON` and `attestationObject`), as well as a bare `response` object.
+
+use crate::types::{RegistrationMatch, StoredCredential};
+use base64::{
+	Engine,
+	engine::general_purpose::{STANDARD_NO_PAD, URL_SAFE_NO_PAD},
+};
+use ring::digest;
+use serde_cbor_2::Value;
+use serde_json::Value as Json;
+
+/// Length of rpIdHash, flags and signCount at the start of authenticator data
+const AUTH_DATA_HEADER_LEN: usize = 37;
+const AAGUID_LEN: usize = 16;
+/// Attested credential data present (AT flag)
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+/// Registration details relevant to the cross-check
+#[derive(Debug, Clone)]
+pub struct Registration {
+	pub credential_id: Vec<u8>,
+	pub rp_id_hash: Option<Vec<u8>>,
+	pub aaguid: Option<Vec<u8>>,
+	pub origin: Option<String>,
+}
+
+/// Decodes base64url as well as standard base64, padded or not
+fn decode_base64(value: &str) -> Result<Vec<u8>, String> {
+	let value = value.trim().trim_end_matches('=');
+	URL_SAFE_NO_PAD
+		.decode(value)
+		.or_else(|_| STANDARD_NO_PAD.decode(value))
+		.map_err(|e| format!("Invalid base64 data: {}", e))
+}
+
+fn origin_from_client_data(client_data: &str) -> Result<Option<String>, String> {
+	let client_data = decode_base64(client_data)?;
+	let client_data: Json = serde_json::from_slice(&client_data)
+		.map_err(|e| format!("clientDataJSON is not valid JSON: {}", e))?;
+
+	if let Some(kind) = client_data.get("type").and_then(Json::as_str)
+		&& kind != "webauthn.create"
+	{
+		return Err(format!(
+			"Expected a registration (webauthn.create), got {}",
+			kind
+		));
+	}
+	Ok(client_data
+		.get("origin")
+		.and_then(Json::as_str)
+		.map(str::to_string))
+}
+
+/// Parts of the authenticator data in an attestation object
+struct AuthData {
+	rp_id_hash: Vec<u8>,
+	/// AAGUID and credential ID, if attested credential data is present
+	attested: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+/// Extracts rpIdHash, AAGUID and credential ID from the attestation object
+fn parse_attestation_object(attestation_object: &str) -> Result<AuthData, String> {
+	let attestation_object = decode_base64(attestation_object)?;
+	let Value::Map(map) = serde_cbor_2::from_slice(&attestation_object)
+		.map_err(|e| format!("attestationObject is not valid CBOR: {}", e))?
+	else {
+		return Err("attestationObject is not a CBOR map".into());
+	};
+	let Some(Value::Bytes(auth_data)) = map.get(&Value::Text("authData".into())) else {
+		return Err("attestationObject has no authData".into());
+	};
+
+	if auth_data.len() < AUTH_DATA_HEADER_LEN {
+		return Err("authData is truncated".into());
+	}
+	let rp_id_hash = auth_data[..32].to_vec();
+	if auth_data[32] & FLAG_ATTESTED_CREDENTIAL_DATA == 0 {
+		return Ok(AuthData {
+			rp_id_hash,
+			attested: None,
+		});
+	}
+
+	// attestedCredentialData: aaguid (16) | credentialIdLength (2) | credentialId | publicKey
+	let attested = &auth_data[AUTH_DATA_HEADER_LEN..];
+	let id_start = AAGUID_LEN + 2;
+	if attested.len() < id_start {
+		return Err("Attested credential data is truncated".into());
+	}
+	let id_len = u16::from_be_bytes([attested[AAGUID_LEN], attested[AAGUID_LEN + 1]]) as usize;
+	let credential_id = attested
+		.get(id_start..id_start + id_len)
+		.ok_or("Credential ID is truncated")?;
+
+	Ok(AuthData {
+		rp_id_hash,
+		attested: Some((attested[..AAGUID_LEN].to_vec(), credential_id.to_vec())),
+	})
+}
+
+/// Parses the registration JSON. The credential ID is taken from the attestation object if
+/// present, falling back to `rawId`/`id`.
+pub fn parse_registration(json: &str) -> Result<Registration, String> {
+	let root: Json =
+		serde_json::from_str(json).map_err(|e| format!("Registration is not valid JSON: {}", e))?;
+	let response = root.get("response").unwrap_or(&root);
+
+	let origin = match response.get("clientDataJSON").and_then(Json::as_str) {
+		Some(client_data) => origin_from_client_data(client_data)?,
+		None => None,
+	};
+
+	let (rp_id_hash, attested) = match response.get("attestationObject").and_then(Json::as_str) {
+		Some(attestation_object) => {
+			let auth_data = parse_attestation_object(attestation_object)?;
+			(Some(auth_data.rp_id_hash), auth_data.attested)
+		}
+		None => (None, None),
+	};
+
+	let (aaguid, credential_id) = match attested {
+		Some((aaguid, id)) => (Some(aaguid), id),
+		None => {
+			let id = root
+				.get("rawId")
+				.or_else(|| root.get("id"))
+				.and_then(Json::as_str)
+				.ok_or("Registration contains no credential ID")?;
+			(None, decode_base64(id)?)
+		}
+	};
+
+	Ok(Registration {
+		credential_id,
+		rp_id_hash,
+		aaguid,
+		origin,
+	})
+}
+
+/// Looks the registered credential up among the resident credentials of the device
+pub fn match_credentials(
+	registration: Registration,
+	credentials: &[StoredCredential],
+) -> RegistrationMatch {
+	let credential_id = hex::encode(&registration.credential_id);
+	let credential = credentials
+		.iter()
+		.find(|c| c.credential_id.eq_ignore_ascii_case(&credential_id))
+		.cloned();
+
+	let rp_id_matches = match (&credential, &registration.rp_id_hash) {
+		(Some(credential), Some(hash)) => {
+			Some(digest::digest(&digest::SHA256, credential.rp_id.as_bytes()).as_ref() == hash)
+		}
+		_ => None,
+	};
+
+	RegistrationMatch {
+		credential_id,
+		origin: registration.origin,
+		aaguid: registration.aaguid.map(hex::encode),
+		found: credential.is_some(),
+		rp_id_matches,
+		credential,
+	}
+}
@@ -0,0 +1,266 @@
+//! Firmware updates of pico-fido devices over USB mass storage.
+//!
+//! A device is rebooted into BOOTSEL mode through the Rescue Applet (or over CTAPHID), the UF2
+//! image is copied to the mass storage drive the boot ROM exposes, and once the device has
+//! restarted its firmware version is read back. Devices are updated one at a time, since the
+//! BOOTSEL drives of several devices could not be told apart.
+
+#[cfg(feature = "gui")]
+use crate::types::FirmwareUpdateParams;
+use crate::{
+	error::PFError,
+	fido, inventory,
+	operation::report_phase,
+	rescue,
+	types::{DeviceEventKind, FidoHidDevice, PhaseVerb, UserAction},
+};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// File the boot ROM puts on its mass storage drive
+const BOOTSEL_MARKER: &str = "INFO_UF2.TXT";
+
+/// Name of the image on the BOOTSEL drive, the boot ROM accepts any `.uf2` file
+const UF2_TARGET_NAME: &str = "firmware.uf2";
+
+/// Magic numbers at the start of every 512 byte UF2 block
+const UF2_MAGIC_START0: u32 = 0x0A32_4655;
+const UF2_MAGIC_START1: u32 = 0x9E5D_5157;
+const UF2_BLOCK_SIZE: usize = 512;
+/// Bytes written between two progress updates
+const UF2_COPY_CHUNK: usize = 64 * UF2_BLOCK_SIZE;
+
+const BOOTSEL_TIMEOUT: Duration = Duration::from_secs(30);
+const REBOOT_TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Calls `f` until it returns a value or `timeout` elapses
+fn wait_for<T>(timeout: Duration, mut f: impl FnMut() -> Option<T>) -> Option<T> {
+	let deadline = Instant::now() + timeout;
+	loop {
+		if let Some(value) = f() {
+			return Some(value);
+		}
+		if Instant::now() >= deadline {
+			return None;
+		}
+		thread::sleep(POLL_INTERVAL);
+	}
+}
+
+/// Directories removable drives get mounted in
+fn mount_points() -> Vec<PathBuf> {
+	if cfg!(target_os = "windows") {
+		return ('D'..='Z')
+			.map(|letter| PathBuf::from(format!("{}:\\", letter)))
+			.collect();
+	}
+
+	let mut parents = vec![PathBuf::from("/Volumes"), PathBuf::from("/media")];
+	if let Ok(user) = std::env::var("USER") {
+		parents.push(Path::new("/media").join(&user));
+		parents.push(Path::new("/run/media").join(&user));
+	}
+
+	parents
+		.iter()
+		.filter_map(|parent| std::fs::read_dir(parent).ok())
+		.flat_map(|entries| entries.filter_map(|e| e.ok().map(|e| e.path())))
+		.collect()
+}
+
+/// Mount point of a drive exposed by a device in BOOTSEL mode
+fn find_bootsel_drive() -> Option<PathBuf> {
+	mount_points()
+		.into_iter()
+		.find(|dir| dir.join(BOOTSEL_MARKER).is_file())
+}
+
+/// Reads a UF2 image and checks that it is made of valid UF2 blocks
+pub fn read_uf2(path: &str) -> Result<Vec<u8>, PFError> {
+	let image =
+		std::fs::read(path).map_err(|e| PFError::Io(format!("Failed to read {}: {}", path, e)))?;
+
+	if image.is_empty() || image.len() % UF2_BLOCK_SIZE != 0 {
+		return Err(PFError::Io(format!("{} is not a UF2 image", path)));
+	}
+	let valid = image.chunks(UF2_BLOCK_SIZE).all(|block| {
+		u32::from_le_bytes([block[0], block[1], block[2], block[3]]) == UF2_MAGIC_START0
+			&& u32::from_le_bytes([block[4], block[5], block[6], block[7]]) == UF2_MAGIC_START1
+	});
+	if !valid {
+		return Err(PFError::Io(format!("{} contains invalid UF2 blocks", path)));
+	}
+
+	Ok(image)
+}
+
+/// Copies `image` to the BOOTSEL drive once it shows up, and waits until the boot ROM has
+/// restarted into the new firmware. `progress` gets the bytes written and the image size.
+fn flash_image(image: &[u8], mut progress: impl FnMut(u32, u32)) -> Result<(), PFError> {
+	report_phase(PhaseVerb::Write, "firmware", UserAction::KeepConnected);
+	let drive = wait_for(BOOTSEL_TIMEOUT, find_bootsel_drive).ok_or_else(|| {
+		PFError::Device("No BOOTSEL drive appeared after rebooting the device".into())
+	})?;
+	log::info!("Copying {} bytes of firmware to {:?}", image.len(), drive);
+
+	let copy_error = |e: std::io::Error| PFError::Io(format!("Failed to copy firmware: {}", e));
+	let mut file = std::fs::File::create(drive.join(UF2_TARGET_NAME)).map_err(copy_error)?;
+	let total = image.len() as u32;
+	let mut written = 0;
+	for chunk in image.chunks(UF2_COPY_CHUNK) {
+		file.write_all(chunk).map_err(copy_error)?;
+		written += chunk.len() as u32;
+		progress(written, total);
+	}
+	// Flushes lazily written data. The boot ROM restarts after the last block, so the drive may
+	// already be gone.
+	if let Err(e) = file.sync_all() {
+		log::debug!("Flushing the firmware image failed: {}", e);
+	}
+	drop(file);
+
+	// The boot ROM unmounts the drive and restarts into the new firmware once the copy is done
+	wait_for(BOOTSEL_TIMEOUT, || {
+		find_bootsel_drive().is_none().then_some(())
+	})
+	.ok_or_else(|| PFError::Device("Device did not leave BOOTSEL mode".into()))
+}
+
+/// The flashed FIDO device once it is back, found by its USB port or else by its serial number.
+/// Without either it can only be told apart when it is the only one connected.
+fn find_flashed(port_path: Option<&str>, serial: Option<&str>) -> Option<FidoHidDevice> {
+	let devices = fido::list_devices().ok()?;
+	match (port_path, serial) {
+		(Some(port_path), _) => devices
+			.into_iter()
+			.find(|d| d.port_path.as_deref() == Some(port_path)),
+		(None, Some(serial)) => devices.into_iter().find(|d| {
+			fido::read_device_details(Some(&d.path))
+				.is_ok_and(|status| status.info.serial == serial)
+		}),
+		(None, None) if devices.len() == 1 => devices.into_iter().next(),
+		(None, None) => None,
+	}
+}
+
+/// Flashes the FIDO device at `device_path` (or the only connected one) with the UF2 image at
+/// `uf2_path`, reporting the copy to `progress` as bytes written and total.
+///
+/// The device is rebooted into BOOTSEL mode over CTAPHID, falling back to the Rescue Applet for
+/// firmware without the vendor reboot command. Its USB port and serial number are noted before
+/// the reboot, so the version is read back from, and the flash recorded for, that same device.
+/// Returns once its FIDO interface is back.
+pub fn flash_fido_device(
+	device_path: Option<&str>,
+	uf2_path: &str,
+	progress: impl FnMut(u32, u32),
+) -> Result<String, PFError> {
+	let image = read_uf2(uf2_path)?;
+	let serial = fido::read_device_details(device_path)
+		.ok()
+		.map(|status| status.info.serial);
+	let port_path = fido::port_path(device_path);
+	if serial.is_none()
+		&& port_path.is_none()
+		&& !fido::list_devices().is_ok_and(|devices| devices.len() == 1)
+	{
+		return Err(PFError::Device(
+			"Could not identify the device to flash, connect only that key".into(),
+		));
+	}
+
+	log::info!("Rebooting into BOOTSEL mode over CTAPHID");
+	if let Err(e) = fido::reboot_to_bootsel(device_path) {
+		log::warn!("CTAPHID reboot failed, using the Rescue Applet: {}", e);
+		match &serial {
+			Some(serial) => rescue::reboot_device_with_serial(serial, true)?,
+			None if rescue::list_devices().is_ok_and(|devices| devices.len() == 1) => {
+				rescue::reboot_device(true)?
+			}
+			None => {
+				return Err(PFError::Device(
+					"Could not reboot the device, and the Rescue Applet cannot tell it apart from the other keys".into(),
+				));
+			}
+		};
+	}
+
+	flash_image(&image, progress)?;
+
+	report_phase(PhaseVerb::Connect, "device", UserAction::KeepConnected);
+	let device = wait_for(REBOOT_TIMEOUT, || {
+		find_flashed(port_path.as_deref(), serial.as_deref())
+	})
+	.ok_or_else(|| PFError::Device("Device did not come back after flashing".into()))?;
+	let info = fido::get_fido_info(Some(&device.path))?;
+
+	if let Some(serial) = serial {
+		let image_name = Path::new(uf2_path)
+			.file_name()
+			.map(|n| n.to_string_lossy().to_string())
+			.unwrap_or_default();
+		inventory::log_device_event(
+			&serial,
+			DeviceEventKind::FirmwareFlash,
+			&format!(
+				"Flashed {} (firmware {})",
+				image_name, info.firmware_version
+			),
+		);
+	}
+	Ok(format!("Updated to firmware {}", info.firmware_version))
+}
+
+/// Batch step updating the device with serial number `serial`: reboot, flash, verify.
+#[cfg(feature = "gui")]
+pub fn update_device(params: &serde_json::Value, serial: &str) -> Result<String, PFError> {
+	let params: FirmwareUpdateParams =
+		serde_json::from_value(params.clone()).map_err(|e| PFError::Io(e.to_string()))?;
+	inventory::Inventory::load()?.check_quarantine(
+		serial,
+		"firmware update",
+		params.override_quarantine,
+	)?;
+	let image = read_uf2(&params.uf2_path)?;
+
+	log::info!("Rebooting {} into BOOTSEL mode", serial);
+	rescue::reboot_device_with_serial(serial, true)?;
+
+	flash_image(&image, |_, _| {})?;
+
+	report_phase(PhaseVerb::Verify, "firmware version", UserAction::None);
+	let device = wait_for(REBOOT_TIMEOUT, || {
+		rescue::list_devices()
+			.ok()?
+			.into_iter()
+			.find(|d| d.serial == serial)
+	})
+	.ok_or_else(|| PFError::Device("Device did not come back after flashing".into()))?;
+
+	if let Some(expected) = &params.expected_version
+		&& &device.firmware_version != expected
+	{
+		return Err(PFError::Device(format!(
+			"Device reports firmware {} instead of {}",
+			device.firmware_version, expected
+		)));
+	}
+
+	let image_name = Path::new(&params.uf2_path)
+		.file_name()
+		.map(|n| n.to_string_lossy().to_string())
+		.unwrap_or_default();
+	inventory::log_device_event(
+		serial,
+		DeviceEventKind::FirmwareFlash,
+		&format!(
+			"Flashed {} (firmware {})",
+			image_name, device.firmware_version
+		),
+	);
+
+	Ok(format!("Updated to firmware {}", device.firmware_version))
+}
@@ -0,0 +1,235 @@
+//! Pre-release validation against a physically attached pico-fido, built only with the
+//! `hardware-tests` feature:
+//!
+//! ```text
+//! PICOFORGE_TEST_PIN=<pin> cargo test --features hardware-tests hardware_tests
+//! ```
+//!
+//! Use a dedicated key with the test PIN already set, and keep it plugged in. Creating a passkey
+//! needs a touch. Passkeys and OATH accounts created here are deleted again, also when a test
+//! fails; nothing else on the key is changed. Tests take turns on the key, whatever the number of
+//! test threads.
+
+use crate::{fido, oath, openpgp, rescue, types::*};
+use ctap_hid_fido2::{
+	fidokey::make_credential::MakeCredentialArgsBuilder,
+	public_key_credential_user_entity::PublicKeyCredentialUserEntity,
+};
+use rand::Rng;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Relying party of the passkeys created by the tests, never a real site
+const TEST_RP_ID: &str = "hardware-tests.picoforge.invalid";
+const TEST_OATH_ACCOUNT: &str = "picoforge-hardware-tests";
+/// RFC 6238 test secret, "12345678901234567890"
+const TEST_OATH_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+static DEVICE: Mutex<()> = Mutex::new(());
+
+/// Exclusive use of the key for one test, surviving a failed test before it
+fn device() -> MutexGuard<'static, ()> {
+	DEVICE
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn test_pin() -> String {
+	std::env::var("PICOFORGE_TEST_PIN")
+		.expect("Set PICOFORGE_TEST_PIN to the PIN of the attached test key")
+}
+
+/// Deletes the passkey when dropped, so a failed assertion does not leave it on the key
+struct TestPasskey {
+	credential_id: String,
+}
+
+impl TestPasskey {
+	fn create(pin: &str) -> Self {
+		let device = fido::open_device(None).expect("No FIDO device attached");
+		let challenge: [u8; 32] = rand::rng().random();
+		let user_id: [u8; 16] = rand::rng().random();
+		let user = PublicKeyCredentialUserEntity::new(
+			Some(&user_id),
+			Some("hardware-test"),
+			Some("PicoForge hardware test"),
+		);
+		let args = MakeCredentialArgsBuilder::new(TEST_RP_ID, &challenge)
+			.pin(pin)
+			.user_entity(&user)
+			.resident_key()
+			.build();
+		println!("Touch the key to create the test passkey");
+		let attestation = device
+			.make_credential_with_args(&args)
+			.expect("Failed to create the test passkey");
+		Self {
+			credential_id: hex::encode(&attestation.credential_descriptor.id),
+		}
+	}
+}
+
+impl Drop for TestPasskey {
+	fn drop(&mut self) {
+		if let Err(e) = fido::delete_credential(None, Some(&test_pin()), self.credential_id.clone())
+		{
+			eprintln!(
+				"Failed to delete test passkey {}: {}",
+				self.credential_id, e
+			);
+		}
+	}
+}
+
+/// Deletes the OATH account when dropped
+struct TestOathAccount {
+	name: String,
+}
+
+impl Drop for TestOathAccount {
+	fn drop(&mut self) {
+		if let Err(e) = oath::delete_account(None, &self.name) {
+			eprintln!("Failed to delete test OATH account {}: {}", self.name, e);
+		}
+	}
+}
+
+/// Reference TOTP code of `TEST_OATH_SECRET` for the time step of `unix_time`
+fn reference_totp(unix_time: u64) -> String {
+	use ring::hmac;
+	let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, b"12345678901234567890");
+	let mac = hmac::sign(&key, &(unix_time / 30).to_be_bytes());
+	let mac = mac.as_ref();
+	let offset = (mac[mac.len() - 1] & 0x0F) as usize;
+	let value = u32::from_be_bytes([
+		mac[offset],
+		mac[offset + 1],
+		mac[offset + 2],
+		mac[offset + 3],
+	]) & 0x7FFF_FFFF;
+	format!("{:06}", value % 1_000_000)
+}
+
+#[test]
+fn reads_device_details() {
+	let _device = device();
+	let status = fido::read_device_details(None).expect("Failed to read device details");
+	assert_ne!(status.info.firmware_version, "Unknown");
+	assert!(status.info.flash_total_bytes > 0);
+	assert!(status.info.flash_used_bytes <= status.info.flash_total_bytes);
+}
+
+#[test]
+fn reads_serial_through_rescue_applet() {
+	let _device = device();
+	let serial = rescue::connected_serial().expect("Rescue Applet did not report a serial");
+	assert!(!serial.is_empty());
+}
+
+#[test]
+fn reads_fido_info_and_pin_retries() {
+	let _device = device();
+	let info = fido::get_fido_info(None).expect("Failed to read GetInfo");
+	assert_eq!(
+		info.options.get("clientPin"),
+		Some(&true),
+		"The test key must have a PIN set"
+	);
+	let retries = fido::get_pin_retries(None).expect("Failed to read PIN retries");
+	assert!(retries > 0, "The PIN of the test key is blocked");
+}
+
+#[test]
+fn passkey_lifecycle() {
+	let _device = device();
+	let pin = test_pin();
+	let (existing_before, _) =
+		fido::get_creds_metadata(None, Some(&pin)).expect("Failed to read credential metadata");
+
+	let passkey = TestPasskey::create(&pin);
+	let credentials = fido::get_credentials(None, Some(&pin)).expect("Failed to list passkeys");
+	let listed = credentials
+		.iter()
+		.find(|c| c.credential_id == passkey.credential_id)
+		.expect("Created passkey is not listed");
+	assert_eq!(listed.rp_id, TEST_RP_ID);
+	let (existing, _) = fido::get_creds_metadata(None, Some(&pin)).unwrap();
+	assert_eq!(existing, existing_before + 1);
+
+	let first = fido::get_credential_sign_count(
+		None,
+		pin.clone(),
+		TEST_RP_ID.into(),
+		passkey.credential_id.clone(),
+	)
+	.expect("Test assertion failed");
+	let second = fido::get_credential_sign_count(
+		None,
+		pin.clone(),
+		TEST_RP_ID.into(),
+		passkey.credential_id.clone(),
+	)
+	.expect("Test assertion failed");
+	assert!(
+		second.sign_count == 0 || second.sign_count > first.sign_count,
+		"Signature counter went from {} to {}",
+		first.sign_count,
+		second.sign_count
+	);
+
+	let credential_id = passkey.credential_id.clone();
+	drop(passkey);
+	let credentials = fido::get_credentials(None, Some(&pin)).unwrap();
+	assert!(credentials.iter().all(|c| c.credential_id != credential_id));
+}
+
+#[test]
+fn oath_account_lifecycle() {
+	let _device = device();
+	let credential = OathCredential {
+		issuer: None,
+		account: TEST_OATH_ACCOUNT.into(),
+		secret: TEST_OATH_SECRET.into(),
+		kind: OathKind::Totp,
+		algorithm: OathAlgorithm::Sha1,
+		digits: Some(6),
+		period: None,
+		counter: 0,
+		require_touch: false,
+	};
+	let account = TestOathAccount {
+		name: oath::add_account(None, &credential).expect("Failed to add the OATH account"),
+	};
+	assert!(
+		oath::list_accounts(None)
+			.unwrap()
+			.iter()
+			.any(|a| a.name == account.name)
+	);
+
+	let code = oath::calculate_code(None, &account.name).expect("Failed to compute the code");
+	let now = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.unwrap()
+		.as_secs();
+	// The time step may have changed in between
+	let expected = [reference_totp(now), reference_totp(now.saturating_sub(30))];
+	assert!(expected.contains(code.code.as_ref().unwrap()));
+
+	let name = account.name.clone();
+	drop(account);
+	assert!(
+		oath::list_accounts(None)
+			.unwrap()
+			.iter()
+			.all(|a| a.name != name)
+	);
+}
+
+#[test]
+fn reads_openpgp_status() {
+	let _device = device();
+	let status = openpgp::read_status().expect("Failed to read the OpenPGP card status");
+	assert_eq!(status.keys.len(), 3);
+	assert!(status.pw1_retries > 0, "The OpenPGP user PIN is blocked");
+}
@@ -0,0 +1,41 @@
+//! Constants and enums for the SmartCard-HSM compatible applet of pico-hsm firmware.
+#![allow(unused)]
+
+// The SmartCard-HSM Application ID (AID) from src/hsm/sc_hsm.c
+pub const HSM_AID: &[u8] = &[
+	0xE8, 0x2B, 0x06, 0x01, 0x04, 0x01, 0x81, 0xC3, 0x1F, 0x02, 0x01,
+];
+
+// APDU Instructions
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HsmInstruction {
+	Verify = 0x20,
+	GenerateKeyPair = 0x46,
+	Sign = 0x68,
+	ReadBinary = 0xB1,
+}
+
+/// File ID prefix of the end-entity certificate (or CV request) stored for each key
+pub const EE_CERTIFICATE_PREFIX: u8 = 0xCE;
+
+/// Tag of the offset data object used by HsmInstruction::ReadBinary (0xB1)
+pub const HSM_TAG_OFFSET: u8 = 0x54;
+
+/// Number of bytes requested per ReadBinary chunk
+pub const HSM_READ_CHUNK: usize = 0xFF;
+
+/// P2 reference of the user PIN for HsmInstruction::Verify (0x20)
+pub const HSM_USER_PIN_REF: u8 = 0x81;
+
+/// P2 Parameters (algorithm identifiers) for HsmInstruction::Sign (0x68)
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HsmSignAlgorithm {
+	EcdsaRaw = 0x70,
+	EcdsaSha1 = 0x71,
+	EcdsaSha224 = 0x72,
+	EcdsaSha256 = 0x73,
+	EcdsaSha384 = 0x74,
+	EcdsaSha512 = 0x75,
+}
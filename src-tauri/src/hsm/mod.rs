@@ -0,0 +1,145 @@
+//! Implements communication with the SmartCard-HSM applet of the pico-hsm firmware over PC/SC.
+//!
+//! For more details checkout the [pico-hsm](https://github.com/polhenarejos/pico-hsm) sources.
+
+pub mod constants;
+pub mod ssh;
+pub mod x509;
+
+use crate::{
+	card,
+	error::PFError,
+	hsm::constants::*,
+	operation::report_phase,
+	rescue::constants::{APDU_CLA_ISO, APDU_CLA_PROPRIETARY},
+	types::{PhaseVerb, UserAction},
+};
+
+/// Connects to the first available reader and selects the SmartCard-HSM Applet
+fn connect_and_select() -> Result<pcsc::Card, PFError> {
+	let (card, _) = card::connect_and_select(HSM_AID, "SmartCard-HSM")?;
+	Ok(card)
+}
+
+/// Verifies the user PIN, unlocking key usage for the current card session
+fn verify_pin(card: &pcsc::Card, pin: &str) -> Result<(), PFError> {
+	report_phase(PhaseVerb::Authenticate, "the HSM", UserAction::None);
+	// APDU: 00 20 00 81 [Lc] [PIN]
+	let mut apdu = vec![
+		APDU_CLA_ISO,
+		HsmInstruction::Verify as u8,
+		0x00,
+		HSM_USER_PIN_REF,
+		pin.len() as u8,
+	];
+	apdu.extend_from_slice(pin.as_bytes());
+
+	let resp = card::transmit(card, &apdu)?;
+	match resp.sw {
+		0x9000 => Ok(()),
+		0x6983 => Err(PFError::Device("HSM user PIN is blocked".into())),
+		sw if sw & 0xFFF0 == 0x63C0 => Err(PFError::Device(format!(
+			"Wrong HSM PIN ({} retries left)",
+			sw & 0x000F
+		))),
+		sw => Err(PFError::Device(format!(
+			"HSM PIN verification failed: {:04X}",
+			sw
+		))),
+	}
+}
+
+/// Signs a precomputed digest with the EC private key `key_id`.
+///
+/// Returns the DER encoded ECDSA signature as produced by the device.
+fn sign_digest(card: &pcsc::Card, key_id: u8, digest: &[u8]) -> Result<Vec<u8>, PFError> {
+	report_phase(
+		PhaseVerb::Sign,
+		&format!("HSM key {}", key_id),
+		UserAction::None,
+	);
+	// APDU: 80 68 [KeyID] [Algo] [Lc] [Digest] 00
+	let mut apdu = vec![
+		APDU_CLA_PROPRIETARY,
+		HsmInstruction::Sign as u8,
+		key_id,
+		HsmSignAlgorithm::EcdsaRaw as u8,
+		digest.len() as u8,
+	];
+	apdu.extend_from_slice(digest);
+	apdu.push(0x00); // Le
+
+	let resp = card::transmit(card, &apdu)?;
+	match resp.sw {
+		0x9000 => Ok(resp.data),
+		0x6982 => Err(PFError::Device("HSM key usage not authorized".into())),
+		0x6A88 => Err(PFError::Device(format!("No key found with ID {}", key_id))),
+		sw => Err(PFError::Device(format!("HSM signature failed: {:04X}", sw))),
+	}
+}
+
+/// Reads the whole content of the elementary file `fid`, chunk by chunk.
+fn read_file(card: &pcsc::Card, fid: u16) -> Result<Vec<u8>, PFError> {
+	let [fid_hi, fid_lo] = fid.to_be_bytes();
+	let mut content = Vec::new();
+
+	loop {
+		// APDU: 00 B1 [FID] [FID] 04 54 02 [Offset] [Offset] [Le]
+		let [off_hi, off_lo] = (content.len() as u16).to_be_bytes();
+		let apdu = [
+			APDU_CLA_ISO,
+			HsmInstruction::ReadBinary as u8,
+			fid_hi,
+			fid_lo,
+			0x04,
+			HSM_TAG_OFFSET,
+			0x02,
+			off_hi,
+			off_lo,
+			HSM_READ_CHUNK as u8,
+		];
+
+		let resp = card::transmit(card, &apdu)?;
+		match resp.sw {
+			// 6282: end of file reached before Le bytes
+			0x9000 | 0x6282 => {
+				let done = resp.data.len() < HSM_READ_CHUNK || resp.sw == 0x6282;
+				content.extend_from_slice(&resp.data);
+				if done {
+					return Ok(content);
+				}
+			}
+			// Offset past the end of a file that was an exact multiple of the chunk size
+			0x6B00 if !content.is_empty() => return Ok(content),
+			0x6A82 => {
+				return Err(PFError::Device(format!("File {:04X} not found", fid)));
+			}
+			sw => {
+				return Err(PFError::Device(format!(
+					"Reading file {:04X} failed: {:04X}",
+					fid, sw
+				)));
+			}
+		}
+	}
+}
+
+/// Reads the certificate stored alongside `key_id`.
+///
+/// Right after key generation this is the CV certificate request holding the public key.
+pub fn read_key_certificate(key_id: u8) -> Result<Vec<u8>, PFError> {
+	let card = connect_and_select()?;
+	read_file(&card, u16::from_be_bytes([EE_CERTIFICATE_PREFIX, key_id]))
+}
+
+/// Opens an HSM session, verifies the PIN and signs `digest` with `key_id`.
+pub fn sign_with_key(pin: &str, key_id: u8, digest: &[u8]) -> Result<Vec<u8>, PFError> {
+	let card = connect_and_select()?;
+	verify_pin(&card, pin)?;
+	log::debug!(
+		"Signing {} byte digest with HSM key {}",
+		digest.len(),
+		key_id
+	);
+	sign_digest(&card, key_id, digest)
+}
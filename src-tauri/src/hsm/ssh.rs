@@ -0,0 +1,240 @@
+//! OpenSSH certificate signing with a device-backed CA key.
+//!
+//! Builds the certificate body as described in OpenSSH's `PROTOCOL.certkeys`, hashes it on the
+//! host and lets the HSM produce the ECDSA signature with the CA key, so the CA private key never
+//! leaves the device. The CA public key embedded in the certificate is read from the HSM as well,
+//! so it always belongs to the key that signed.
+
+use crate::{
+	error::PFError,
+	hsm,
+	types::{SshCertRequest, SshCertType, SshCertificate},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use rand::Rng;
+use ring::digest;
+
+/// Extensions granted to user certificates, matching `ssh-keygen` defaults (sorted by name).
+const DEFAULT_USER_EXTENSIONS: &[&str] = &[
+	"permit-X11-forwarding",
+	"permit-agent-forwarding",
+	"permit-port-forwarding",
+	"permit-pty",
+	"permit-user-rc",
+];
+
+/// Minimal writer for the SSH wire encoding (RFC 4251)
+#[derive(Default)]
+struct SshWriter {
+	buf: Vec<u8>,
+}
+
+impl SshWriter {
+	fn put_u32(&mut self, v: u32) {
+		self.buf.extend_from_slice(&v.to_be_bytes());
+	}
+
+	fn put_u64(&mut self, v: u64) {
+		self.buf.extend_from_slice(&v.to_be_bytes());
+	}
+
+	fn put_string(&mut self, s: &[u8]) {
+		self.put_u32(s.len() as u32);
+		self.buf.extend_from_slice(s);
+	}
+
+	fn put_mpint(&mut self, v: &[u8]) {
+		let v = match v.iter().position(|b| *b != 0) {
+			Some(i) => &v[i..],
+			None => &[][..],
+		};
+		if v.first().is_some_and(|b| b & 0x80 != 0) {
+			self.put_u32(v.len() as u32 + 1);
+			self.buf.push(0x00);
+			self.buf.extend_from_slice(v);
+		} else {
+			self.put_string(v);
+		}
+	}
+
+	fn put_raw(&mut self, v: &[u8]) {
+		self.buf.extend_from_slice(v);
+	}
+}
+
+/// Reads an SSH `string` at `pos`, advancing it
+fn read_string<'a>(blob: &'a [u8], pos: &mut usize) -> Result<&'a [u8], PFError> {
+	let invalid = || PFError::Io("Malformed SSH public key".into());
+	let len_bytes = blob.get(*pos..*pos + 4).ok_or_else(invalid)?;
+	let len = u32::from_be_bytes([len_bytes[0], len_bytes[1], len_bytes[2], len_bytes[3]]) as usize;
+	let value = blob.get(*pos + 4..*pos + 4 + len).ok_or_else(invalid)?;
+	*pos += 4 + len;
+	Ok(value)
+}
+
+/// Parses an `authorized_keys` style line into its key type and decoded blob
+fn parse_public_key_line(line: &str) -> Result<(String, Vec<u8>), PFError> {
+	let mut parts = line.split_whitespace();
+	let (Some(key_type), Some(b64)) = (parts.next(), parts.next()) else {
+		return Err(PFError::Io(
+			"Expected '<type> <base64> [comment]' public key".into(),
+		));
+	};
+
+	let blob = STANDARD
+		.decode(b64)
+		.map_err(|e| PFError::Io(format!("Invalid public key encoding: {}", e)))?;
+
+	let mut pos = 0;
+	let embedded_type = read_string(&blob, &mut pos)?;
+	if embedded_type != key_type.as_bytes() {
+		return Err(PFError::Io(
+			"Public key type does not match its encoded blob".into(),
+		));
+	}
+
+	Ok((key_type.to_string(), blob))
+}
+
+/// Splits a DER encoded ECDSA signature into its `r` and `s` integers
+fn parse_der_ecdsa_signature(der: &[u8]) -> Result<(Vec<u8>, Vec<u8>), PFError> {
+	let invalid = || PFError::Device("Malformed ECDSA signature from device".into());
+
+	fn read_len(der: &[u8], pos: &mut usize) -> Option<usize> {
+		let first = *der.get(*pos)?;
+		*pos += 1;
+		match first {
+			0x00..=0x7F => Some(first as usize),
+			0x81 => {
+				let len = *der.get(*pos)? as usize;
+				*pos += 1;
+				Some(len)
+			}
+			_ => None,
+		}
+	}
+
+	let mut pos = 0;
+	if der.first() != Some(&0x30) {
+		return Err(invalid());
+	}
+	pos += 1;
+	read_len(der, &mut pos).ok_or_else(invalid)?;
+
+	let mut ints = Vec::with_capacity(2);
+	for _ in 0..2 {
+		if der.get(pos) != Some(&0x02) {
+			return Err(invalid());
+		}
+		pos += 1;
+		let len = read_len(der, &mut pos).ok_or_else(invalid)?;
+		ints.push(der.get(pos..pos + len).ok_or_else(invalid)?.to_vec());
+		pos += len;
+	}
+
+	let s = ints.pop().ok_or_else(invalid)?;
+	let r = ints.pop().ok_or_else(invalid)?;
+	Ok((r, s))
+}
+
+/// Digest algorithm used for the signature, determined by the CA key type
+fn ca_digest_algorithm(ca_key_type: &str) -> Result<&'static digest::Algorithm, PFError> {
+	match ca_key_type {
+		"ecdsa-sha2-nistp256" => Ok(&digest::SHA256),
+		"ecdsa-sha2-nistp384" => Ok(&digest::SHA384),
+		"ecdsa-sha2-nistp521" => Ok(&digest::SHA512),
+		other => Err(PFError::Io(format!(
+			"Unsupported CA key type {}. Only ECDSA CA keys can be used with the HSM.",
+			other
+		))),
+	}
+}
+
+/// Signs `request.user_public_key` with the HSM-held CA key and returns the OpenSSH certificate.
+pub fn sign_ssh_certificate(request: SshCertRequest) -> Result<SshCertificate, PFError> {
+	log::info!(
+		"Signing SSH certificate '{}' with HSM key {}",
+		request.key_id,
+		request.ca_key_ref
+	);
+
+	let (user_key_type, user_blob) = parse_public_key_line(&request.user_public_key)?;
+	let (ca_curve, ca_point) = hsm::x509::ssh_public_point(request.ca_key_ref)?;
+	let ca_key_type = format!("ecdsa-sha2-{}", ca_curve);
+	let digest_alg = ca_digest_algorithm(&ca_key_type)?;
+	let mut ca_blob = SshWriter::default();
+	ca_blob.put_string(ca_key_type.as_bytes());
+	ca_blob.put_string(ca_curve.as_bytes());
+	ca_blob.put_string(&ca_point);
+
+	if request.principals.is_empty() {
+		log::warn!("SSH certificate has no principals and will be valid for any user/host");
+	}
+
+	let cert_key_type = format!(
+		"{}-cert-v01@openssh.com",
+		user_key_type.trim_end_matches("-cert-v01@openssh.com")
+	);
+	let nonce: [u8; 32] = rand::rng().random();
+	let serial = request.serial.unwrap_or_else(|| rand::rng().random());
+
+	// The public key fields follow the key type string in the user's blob
+	let mut pos = 0;
+	read_string(&user_blob, &mut pos)?;
+	let user_key_fields = &user_blob[pos..];
+
+	let mut principals = SshWriter::default();
+	for principal in &request.principals {
+		principals.put_string(principal.as_bytes());
+	}
+
+	let mut extensions = SshWriter::default();
+	if request.cert_type == SshCertType::User {
+		for ext in DEFAULT_USER_EXTENSIONS {
+			extensions.put_string(ext.as_bytes());
+			extensions.put_string(b"");
+		}
+	}
+
+	let mut cert = SshWriter::default();
+	cert.put_string(cert_key_type.as_bytes());
+	cert.put_string(&nonce);
+	cert.put_raw(user_key_fields);
+	cert.put_u64(serial);
+	cert.put_u32(request.cert_type as u32);
+	cert.put_string(request.key_id.as_bytes());
+	cert.put_string(&principals.buf);
+	cert.put_u64(request.valid_after.unwrap_or(0));
+	cert.put_u64(request.valid_before.unwrap_or(u64::MAX));
+	cert.put_string(b""); // critical options
+	cert.put_string(&extensions.buf);
+	cert.put_string(b""); // reserved
+	cert.put_string(&ca_blob.buf);
+
+	let hash = digest::digest(digest_alg, &cert.buf);
+	let der_sig = hsm::sign_with_key(&request.pin, request.ca_key_ref, hash.as_ref())?;
+	let (r, s) = parse_der_ecdsa_signature(&der_sig)?;
+
+	let mut sig_inner = SshWriter::default();
+	sig_inner.put_mpint(&r);
+	sig_inner.put_mpint(&s);
+
+	let mut signature = SshWriter::default();
+	signature.put_string(ca_key_type.as_bytes());
+	signature.put_string(&sig_inner.buf);
+
+	cert.put_string(&signature.buf);
+
+	log::info!("SSH certificate signed successfully (serial {})", serial);
+
+	Ok(SshCertificate {
+		certificate: format!(
+			"{} {} {}",
+			cert_key_type,
+			STANDARD.encode(&cert.buf),
+			request.key_id
+		),
+		serial,
+		key_id: request.key_id,
+	})
+}
@@ -0,0 +1,378 @@
+//! Self-signed X.509 certificate generation for EC keys held by the HSM.
+//!
+//! The public key is taken from the CV certificate request the device stores next to every
+//! generated key. The TBS certificate is DER encoded on the host and signed by the device, so the
+//! private key never leaves it.
+
+use crate::{
+	card::find_tlv,
+	error::PFError,
+	hsm,
+	types::{X509CertRequest, X509Certificate},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use chrono::{DateTime, Datelike, Utc};
+use rand::Rng;
+use ring::digest;
+use std::time::{SystemTime, UNIX_EPOCH};
+use x509_parser::prelude::FromDer;
+
+// DER tags
+const TAG_BOOLEAN: u8 = 0x01;
+const TAG_INTEGER: u8 = 0x02;
+const TAG_BIT_STRING: u8 = 0x03;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_OID: u8 = 0x06;
+const TAG_UTF8_STRING: u8 = 0x0C;
+const TAG_UTC_TIME: u8 = 0x17;
+const TAG_GENERALIZED_TIME: u8 = 0x18;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_SET: u8 = 0x31;
+const TAG_SAN_EMAIL: u8 = 0x81;
+const TAG_SAN_DNS: u8 = 0x82;
+const TAG_VERSION: u8 = 0xA0;
+const TAG_EXTENSIONS: u8 = 0xA3;
+
+// Encoded object identifiers
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x02, 0x01];
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+const OID_ORGANIZATION: &[u8] = &[0x55, 0x04, 0x0A];
+const OID_SUBJECT_KEY_ID: &[u8] = &[0x55, 0x1D, 0x0E];
+const OID_KEY_USAGE: &[u8] = &[0x55, 0x1D, 0x0F];
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1D, 0x11];
+const OID_BASIC_CONSTRAINTS: &[u8] = &[0x55, 0x1D, 0x13];
+const OID_EXT_KEY_USAGE: &[u8] = &[0x55, 0x1D, 0x25];
+const OID_CLIENT_AUTH: &[u8] = &[0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x02];
+
+// CV certificate tags (BSI TR-03110)
+const CVC_TAG_PUBLIC_KEY: u16 = 0x7F49;
+const CVC_TAG_PRIME: u16 = 0x81;
+const CVC_TAG_PUBLIC_POINT: u16 = 0x86;
+
+/// EC curves the certificate can be issued for
+struct Curve {
+	name: &'static str,
+	/// Curve identifier used by SSH, as in the key type "ecdsa-sha2-nistp256"
+	ssh_name: &'static str,
+	prime_hex: &'static str,
+	point_len: usize,
+	oid: &'static [u8],
+	signature_oid: &'static [u8],
+	digest: &'static digest::Algorithm,
+}
+
+const CURVES: &[Curve] = &[
+	Curve {
+		name: "NIST P-256",
+		ssh_name: "nistp256",
+		prime_hex: "ffffffff00000001000000000000000000000000ffffffffffffffffffffffff",
+		point_len: 65,
+		oid: &[0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07],
+		signature_oid: &[0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x04, 0x03, 0x02],
+		digest: &digest::SHA256,
+	},
+	Curve {
+		name: "NIST P-384",
+		ssh_name: "nistp384",
+		prime_hex: "fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffeffffffff0000000000000000ffffffff",
+		point_len: 97,
+		oid: &[0x2B, 0x81, 0x04, 0x00, 0x22],
+		signature_oid: &[0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x04, 0x03, 0x03],
+		digest: &digest::SHA384,
+	},
+	Curve {
+		name: "NIST P-521",
+		ssh_name: "nistp521",
+		prime_hex: "01ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+		point_len: 133,
+		oid: &[0x2B, 0x81, 0x04, 0x00, 0x23],
+		signature_oid: &[0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x04, 0x03, 0x04],
+		digest: &digest::SHA512,
+	},
+];
+
+/// Short form below 128, otherwise the long form with as few length bytes as needed
+fn der_len(len: usize) -> Vec<u8> {
+	if len < 0x80 {
+		return vec![len as u8];
+	}
+	let bytes = len.to_be_bytes();
+	let skip = bytes.iter().take_while(|&&b| b == 0).count();
+	let mut out = vec![0x80 | (bytes.len() - skip) as u8];
+	out.extend_from_slice(&bytes[skip..]);
+	out
+}
+
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+	let mut out = vec![tag];
+	out.extend(der_len(content.len()));
+	out.extend_from_slice(content);
+	out
+}
+
+fn der_seq(items: &[Vec<u8>]) -> Vec<u8> {
+	der(TAG_SEQUENCE, &items.concat())
+}
+
+fn der_bit_string(content: &[u8]) -> Vec<u8> {
+	let mut bits = vec![0x00]; // no unused bits
+	bits.extend_from_slice(content);
+	der(TAG_BIT_STRING, &bits)
+}
+
+/// UTCTime for 1950-2049, GeneralizedTime otherwise (RFC 5280 section 4.1.2.5)
+fn der_time(unix_secs: u64) -> Result<Vec<u8>, PFError> {
+	let time = DateTime::<Utc>::from_timestamp(unix_secs as i64, 0)
+		.ok_or_else(|| PFError::Io(format!("Invalid timestamp {}", unix_secs)))?;
+	if (1950..2050).contains(&time.year()) {
+		Ok(der(
+			TAG_UTC_TIME,
+			time.format("%y%m%d%H%M%SZ").to_string().as_bytes(),
+		))
+	} else {
+		Ok(der(
+			TAG_GENERALIZED_TIME,
+			time.format("%Y%m%d%H%M%SZ").to_string().as_bytes(),
+		))
+	}
+}
+
+/// Builds an X.509 Name from the common name and optional organization
+fn der_name(common_name: &str, organization: Option<&str>) -> Vec<u8> {
+	let rdn = |oid: &[u8], value: &str| {
+		der(
+			TAG_SET,
+			&der_seq(&[der(TAG_OID, oid), der(TAG_UTF8_STRING, value.as_bytes())]),
+		)
+	};
+
+	let mut rdns = Vec::new();
+	if let Some(org) = organization.filter(|o| !o.is_empty()) {
+		rdns.push(rdn(OID_ORGANIZATION, org));
+	}
+	rdns.push(rdn(OID_COMMON_NAME, common_name));
+	der_seq(&rdns)
+}
+
+fn der_extension(oid: &[u8], critical: bool, value: &[u8]) -> Vec<u8> {
+	let mut items = vec![der(TAG_OID, oid)];
+	if critical {
+		items.push(der(TAG_BOOLEAN, &[0xFF]));
+	}
+	items.push(der(TAG_OCTET_STRING, value));
+	der_seq(&items)
+}
+
+/// Extracts the EC public point and its curve from the CV request stored for the key
+fn parse_cvc_public_key(cvc: &[u8]) -> Result<(&'static Curve, Vec<u8>), PFError> {
+	let public_key = find_tlv(cvc, CVC_TAG_PUBLIC_KEY)
+		.ok_or_else(|| PFError::Device("No public key found for this key".into()))?;
+	let point = find_tlv(public_key, CVC_TAG_PUBLIC_POINT).ok_or_else(|| {
+		PFError::Device("Only EC keys are supported for certificate generation".into())
+	})?;
+
+	let prime = find_tlv(public_key, CVC_TAG_PRIME).map(hex::encode);
+	let curve = CURVES
+		.iter()
+		.find(|c| match &prime {
+			Some(p) => p.as_str() == c.prime_hex,
+			None => point.len() == c.point_len,
+		})
+		.ok_or_else(|| PFError::Device("Unsupported EC curve for this key".into()))?;
+
+	Ok((curve, point.to_vec()))
+}
+
+/// Whether the file stored alongside a key holds an X.509 certificate rather than the CV request
+fn is_x509(stored: &[u8]) -> bool {
+	stored.first() == Some(&TAG_SEQUENCE)
+}
+
+/// SSH curve identifier and EC public point of the HSM key `key_id`
+pub(crate) fn ssh_public_point(key_id: u8) -> Result<(&'static str, Vec<u8>), PFError> {
+	let stored = hsm::read_key_certificate(key_id)?;
+	let (curve, point) = stored_public_key(key_id, &stored)?;
+	Ok((curve.ssh_name, point))
+}
+
+/// Curve and EC public point of the HSM key `key_id`, read from the CV request stored for it or
+/// from the X.509 certificate that replaced the request
+fn stored_public_key(key_id: u8, stored: &[u8]) -> Result<(&'static Curve, Vec<u8>), PFError> {
+	if !is_x509(stored) {
+		return parse_cvc_public_key(stored);
+	}
+
+	let (_, certificate) = x509_parser::certificate::X509Certificate::from_der(stored)
+		.map_err(|e| PFError::Device(format!("Invalid certificate for key {}: {}", key_id, e)))?;
+	let point = certificate.public_key().subject_public_key.data.to_vec();
+	let curve = CURVES
+		.iter()
+		.find(|c| c.point_len == point.len())
+		.ok_or_else(|| PFError::Device("Unsupported EC curve for this key".into()))?;
+	Ok((curve, point))
+}
+
+/// DER of a certificate given either as DER or as PEM, checked to parse as X.509
+pub(crate) fn certificate_der(certificate: &[u8]) -> Result<Vec<u8>, PFError> {
+	let der = if certificate.trim_ascii_start().starts_with(b"-----BEGIN") {
+		let (_, pem) = x509_parser::pem::parse_x509_pem(certificate)
+			.map_err(|e| PFError::Io(format!("Invalid PEM certificate: {}", e)))?;
+		pem.contents
+	} else {
+		certificate.to_vec()
+	};
+	x509_parser::certificate::X509Certificate::from_der(&der)
+		.map_err(|e| PFError::Io(format!("Invalid X.509 certificate: {}", e)))?;
+	Ok(der)
+}
+
+pub(crate) fn to_pem(der: &[u8]) -> String {
+	pem_block("CERTIFICATE", der)
+}
+
+/// PEM encoding of `der` with the given label, e.g. "CERTIFICATE REQUEST"
+pub(crate) fn pem_block(label: &str, der: &[u8]) -> String {
+	let b64 = STANDARD.encode(der);
+	let mut pem = format!("-----BEGIN {}-----\n", label);
+	for line in b64.as_bytes().chunks(64) {
+		pem.push_str(&String::from_utf8_lossy(line));
+		pem.push('\n');
+	}
+	pem.push_str(&format!("-----END {}-----\n", label));
+	pem
+}
+
+/// Issues a self-signed TLS client certificate for the HSM key `request.key_ref`.
+///
+/// Keys that already have an X.509 certificate are refused unless `request.overwrite` is set.
+pub fn generate_self_signed_certificate(
+	request: X509CertRequest,
+) -> Result<X509Certificate, PFError> {
+	log::info!(
+		"Generating self-signed certificate for HSM key {}",
+		request.key_ref
+	);
+
+	if request.common_name.is_empty() {
+		return Err(PFError::Io("Common name must not be empty".into()));
+	}
+	if request.valid_days == 0 {
+		return Err(PFError::Io("Validity must be at least one day".into()));
+	}
+
+	let stored = hsm::read_key_certificate(request.key_ref)?;
+	if is_x509(&stored) && !request.overwrite {
+		return Err(PFError::Device(
+			"This key already has an X.509 certificate, confirm overwriting it to issue a new one"
+				.into(),
+		));
+	}
+	let (curve, point) = stored_public_key(request.key_ref, &stored)?;
+	log::debug!("Key {} is a {} key", request.key_ref, curve.name);
+
+	let not_before = request.not_before.unwrap_or_else(|| {
+		SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or(0)
+	});
+	let not_after = not_before + u64::from(request.valid_days) * 86_400;
+
+	// Positive 128-bit serial number
+	let mut serial: [u8; 16] = rand::rng().random();
+	serial[0] = (serial[0] & 0x7F) | 0x40;
+
+	let signature_algorithm = der_seq(&[der(TAG_OID, curve.signature_oid)]);
+	let name = der_name(&request.common_name, request.organization.as_deref());
+	let spki = der_seq(&[
+		der_seq(&[der(TAG_OID, OID_EC_PUBLIC_KEY), der(TAG_OID, curve.oid)]),
+		der_bit_string(&point),
+	]);
+
+	let key_id = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, &point);
+	let mut extensions = vec![
+		der_extension(OID_BASIC_CONSTRAINTS, true, &der_seq(&[])),
+		// digitalSignature
+		der_extension(OID_KEY_USAGE, true, &der(TAG_BIT_STRING, &[0x07, 0x80])),
+		der_extension(
+			OID_EXT_KEY_USAGE,
+			false,
+			&der_seq(&[der(TAG_OID, OID_CLIENT_AUTH)]),
+		),
+		der_extension(
+			OID_SUBJECT_KEY_ID,
+			false,
+			&der(TAG_OCTET_STRING, key_id.as_ref()),
+		),
+	];
+
+	let alt_names: Vec<Vec<u8>> = request
+		.dns_names
+		.iter()
+		.map(|dns| der(TAG_SAN_DNS, dns.as_bytes()))
+		.chain(
+			request
+				.email_addresses
+				.iter()
+				.map(|email| der(TAG_SAN_EMAIL, email.as_bytes())),
+		)
+		.collect();
+	if !alt_names.is_empty() {
+		extensions.push(der_extension(
+			OID_SUBJECT_ALT_NAME,
+			false,
+			&der_seq(&alt_names),
+		));
+	}
+
+	let tbs = der_seq(&[
+		der(TAG_VERSION, &der(TAG_INTEGER, &[0x02])), // v3
+		der(TAG_INTEGER, &serial),
+		signature_algorithm.clone(),
+		name.clone(),
+		der_seq(&[der_time(not_before)?, der_time(not_after)?]),
+		name,
+		spki,
+		der(TAG_EXTENSIONS, &der_seq(&extensions)),
+	]);
+
+	let hash = digest::digest(curve.digest, &tbs);
+	let signature = hsm::sign_with_key(&request.pin, request.key_ref, hash.as_ref())?;
+
+	let certificate = der_seq(&[tbs, signature_algorithm, der_bit_string(&signature)]);
+
+	log::info!(
+		"Self-signed certificate for '{}' created",
+		request.common_name
+	);
+
+	Ok(X509Certificate {
+		pem: to_pem(&certificate),
+		serial: hex::encode(serial),
+		not_before,
+		not_after,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn der_len_boundaries() {
+		assert_eq!(der_len(0x7F), [0x7F]);
+		assert_eq!(der_len(0x80), [0x81, 0x80]);
+		assert_eq!(der_len(0xFF), [0x81, 0xFF]);
+		assert_eq!(der_len(0x100), [0x82, 0x01, 0x00]);
+		assert_eq!(der_len(0xFFFF), [0x82, 0xFF, 0xFF]);
+		assert_eq!(der_len(0x1_0000), [0x83, 0x01, 0x00, 0x00]);
+		assert_eq!(der_len(0xFF_FFFF), [0x83, 0xFF, 0xFF, 0xFF]);
+	}
+
+	#[test]
+	fn der_keeps_long_content() {
+		let encoded = der(TAG_OCTET_STRING, &vec![0xAB; 0x1_0000]);
+		assert_eq!(encoded[..5], [TAG_OCTET_STRING, 0x83, 0x01, 0x00, 0x00]);
+		assert_eq!(encoded.len(), 5 + 0x1_0000);
+	}
+}
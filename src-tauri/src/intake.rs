@@ -0,0 +1,148 @@
+//! Intake check of newly purchased keys, run on each key of a batch before deployment.
+//!
+//! Every key is identified through the Rescue Applet, its GetInfo data is read and the
+//! attestation of a throwaway credential is verified. The results are kept in the inventory, and
+//! keys whose AAGUID or firmware differ from the expected values (or from the rest of the batch)
+//! are flagged, to catch tampered or wrong-firmware units.
+
+use crate::{
+	error::PFError,
+	fido,
+	inventory::{self, Inventory},
+	rescue,
+	types::{DeviceEventKind, IntakeBatch, IntakeEntry, IntakeRecord, IntakeReport},
+};
+
+/// Most common value, ties going to the value seen first
+fn most_common<'a>(values: impl Iterator<Item = &'a str>) -> Option<String> {
+	let mut counts: Vec<(&str, usize)> = Vec::new();
+	for value in values {
+		match counts.iter_mut().find(|(v, _)| *v == value) {
+			Some((_, count)) => *count += 1,
+			None => counts.push((value, 1)),
+		}
+	}
+	let max = counts.iter().map(|(_, count)| *count).max()?;
+	counts
+		.into_iter()
+		.find(|(_, count)| *count == max)
+		.map(|(value, _)| value.to_string())
+}
+
+/// Flags the devices of the batch that fail their attestation or stand out
+pub fn evaluate(name: &str, batch: &IntakeBatch) -> IntakeReport {
+	let aaguid = batch
+		.expected_aaguid
+		.clone()
+		.or_else(|| most_common(batch.records.iter().map(|r| r.aaguid.as_str())));
+	let firmware_version = batch
+		.expected_firmware
+		.clone()
+		.or_else(|| most_common(batch.records.iter().map(|r| r.firmware_version.as_str())));
+
+	let entries: Vec<IntakeEntry> = batch
+		.records
+		.iter()
+		.map(|record| {
+			let mut issues = record.attestation.issues.clone();
+			if let Some(expected) = &aaguid
+				&& !record.aaguid.eq_ignore_ascii_case(expected)
+			{
+				issues.push(format!("AAGUID {} instead of {}", record.aaguid, expected));
+			}
+			if let Some(expected) = &firmware_version
+				&& &record.firmware_version != expected
+			{
+				issues.push(format!(
+					"Firmware {} instead of {}",
+					record.firmware_version, expected
+				));
+			}
+			if record.quarantined {
+				issues.push("Device is quarantined".into());
+			}
+			IntakeEntry {
+				record: record.clone(),
+				issues,
+			}
+		})
+		.collect();
+
+	let outliers = entries
+		.iter()
+		.filter(|e| !e.issues.is_empty())
+		.map(|e| e.record.serial.clone())
+		.collect();
+
+	IntakeReport {
+		batch: name.to_string(),
+		aaguid,
+		firmware_version,
+		entries,
+		outliers,
+	}
+}
+
+/// Checks the connected device and adds it to the batch `name`, creating the batch if needed.
+///
+/// Expected values given here are kept for the whole batch. Needs a touch, and the PIN if the
+/// device already has one.
+pub fn check_connected_device(
+	name: &str,
+	expected_aaguid: Option<String>,
+	expected_firmware: Option<String>,
+	pin: Option<String>,
+) -> Result<IntakeReport, PFError> {
+	// Lets failures name the port of the key even if its serial number cannot be read
+	let port_path = fido::port_path(None);
+	let at_port = |e: String| match &port_path {
+		Some(port) => PFError::Device(format!("Key in USB port {}: {}", port, e)),
+		None => PFError::Device(e),
+	};
+
+	let serial = rescue::connected_serial().ok_or_else(|| {
+		at_port("Could not read the serial number through the Rescue Applet".into())
+	})?;
+	log::info!(
+		"Intake check of {} (port {}) for batch {}",
+		serial,
+		port_path.as_deref().unwrap_or("unknown"),
+		name
+	);
+
+	let info = fido::get_fido_info(None).map_err(|e| at_port(e.to_string()))?;
+	let attestation =
+		fido::attestation::check_attestation(pin.as_deref(), &info.aaguid).map_err(at_port)?;
+
+	Inventory::update(|inventory| {
+		let record = IntakeRecord {
+			quarantined: inventory.quarantine.contains_key(&serial),
+			serial: serial.clone(),
+			aaguid: info.aaguid,
+			firmware_version: info.firmware_version,
+			port_path,
+			attestation,
+			checked_at: inventory::now(),
+		};
+
+		let batch = inventory.intake.entry(name.to_string()).or_default();
+		if expected_aaguid.is_some() {
+			batch.expected_aaguid = expected_aaguid;
+		}
+		if expected_firmware.is_some() {
+			batch.expected_firmware = expected_firmware;
+		}
+		batch.records.retain(|r| r.serial != serial);
+		batch.records.push(record);
+
+		let report = evaluate(name, batch);
+		let summary = match report.entries.iter().find(|e| e.record.serial == serial) {
+			Some(entry) if !entry.issues.is_empty() => {
+				format!("Intake check ({}): {}", name, entry.issues.join("; "))
+			}
+			_ => format!("Intake check ({}) passed", name),
+		};
+		inventory.record_event(&serial, DeviceEventKind::IntakeCheck, &summary);
+		Ok(report)
+	})
+}
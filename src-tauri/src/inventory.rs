@@ -0,0 +1,359 @@
+//! Local inventory database of what PicoForge has seen on the user's devices.
+//!
+//! Stored as JSON in the application data directory. The file is small, so it is read and
+//! rewritten as a whole. Operations on several devices run in parallel, so every change goes
+//! through `Inventory::update`, which keeps other writers out from the load to the save.
+
+#[cfg(feature = "gui")]
+use crate::types::{AppConfig, DeviceNote};
+use crate::{
+	config_schema::VersionedConfig,
+	error::PFError,
+	fido, paths, rescue, storage,
+	types::{
+		AuditRecord, DeviceEvent, DeviceEventKind, DeviceHistory, IntakeBatch, Migration,
+		QuarantineEntry, RescueDevice,
+	},
+};
+#[cfg(feature = "gui")]
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const INVENTORY_FILE: &str = "inventory.json";
+
+/// Held from loading to saving the inventory, so concurrent changes are not lost
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// Last known state of a credential, keyed by its hex credential ID
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialRecord {
+	pub rp_id: String,
+	pub sign_count: u32,
+	pub last_verified: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Inventory {
+	#[serde(default)]
+	pub credentials: BTreeMap<String, CredentialRecord>,
+	/// Last configuration read from each device, keyed by serial number
+	#[serde(default)]
+	pub device_configs: BTreeMap<String, VersionedConfig>,
+	/// Maintenance notes and history, keyed by serial number
+	#[serde(default)]
+	pub devices: BTreeMap<String, DeviceHistory>,
+	/// Devices suspected to be compromised, keyed by serial number
+	#[serde(default)]
+	pub quarantine: BTreeMap<String, QuarantineEntry>,
+	/// Intake checks of newly received devices, keyed by batch name
+	#[serde(default)]
+	pub intake: BTreeMap<String, IntakeBatch>,
+	/// Migrations from an old key to a new one, keyed by name
+	#[serde(default)]
+	pub migrations: BTreeMap<String, Migration>,
+	/// Periodic audits done on each device, keyed by serial number
+	#[serde(default)]
+	pub audits: BTreeMap<String, AuditRecord>,
+}
+
+fn inventory_path() -> PathBuf {
+	paths::data_dir().join(INVENTORY_FILE)
+}
+
+pub fn now() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or(0)
+}
+
+impl Inventory {
+	/// Loads the inventory, returning an empty one if none was saved yet.
+	pub fn load() -> Result<Self, PFError> {
+		let path = inventory_path();
+		if !path.exists() {
+			return Ok(Self::default());
+		}
+
+		let data = storage::read(&path)
+			.map_err(|e| PFError::Io(format!("Failed to read inventory: {}", e)))?;
+		serde_json::from_slice(&data)
+			.map_err(|e| PFError::Io(format!("Inventory file is corrupted: {}", e)))
+	}
+
+	fn save(&self) -> Result<(), PFError> {
+		let data = serde_json::to_vec_pretty(self).map_err(|e| PFError::Io(e.to_string()))?;
+		storage::write(&inventory_path(), &data)
+			.map_err(|e| PFError::Io(format!("Failed to write inventory: {}", e)))
+	}
+
+	/// Applies `f` to the inventory and saves it, unless `f` fails. Other changes wait until the
+	/// inventory is saved.
+	pub fn update<T>(f: impl FnOnce(&mut Self) -> Result<T, PFError>) -> Result<T, PFError> {
+		let _guard = LOCK.lock().unwrap_or_else(|p| p.into_inner());
+		let mut inventory = Self::load()?;
+		let result = f(&mut inventory)?;
+		inventory.save()?;
+		Ok(result)
+	}
+
+	/// Records the latest signature counter of a credential, returning the previous record.
+	pub fn record_sign_count(
+		&mut self,
+		credential_id: &str,
+		rp_id: &str,
+		sign_count: u32,
+	) -> Option<CredentialRecord> {
+		self.credentials.insert(
+			credential_id.to_string(),
+			CredentialRecord {
+				rp_id: rp_id.to_string(),
+				sign_count,
+				last_verified: now(),
+			},
+		)
+	}
+
+	/// Records the configuration last read from the device with the given serial number.
+	#[cfg(feature = "gui")]
+	pub fn record_config(&mut self, serial: &str, config: &AppConfig) -> Result<(), PFError> {
+		self.device_configs
+			.insert(serial.to_string(), VersionedConfig::new(config)?);
+		Ok(())
+	}
+
+	/// Configuration last read from the device, migrated to the current schema.
+	#[cfg(feature = "gui")]
+	pub fn device_config(&self, serial: &str) -> Result<Option<AppConfig>, PFError> {
+		self.device_configs
+			.get(serial)
+			.cloned()
+			.map(VersionedConfig::into_config)
+			.transpose()
+	}
+
+	/// Appends an event to the history of the device with the given serial number.
+	pub fn record_event(&mut self, serial: &str, kind: DeviceEventKind, summary: &str) {
+		self.devices
+			.entry(serial.to_string())
+			.or_default()
+			.events
+			.push(DeviceEvent {
+				kind,
+				summary: summary.to_string(),
+				timestamp: now(),
+			});
+	}
+
+	#[cfg(feature = "gui")]
+	pub fn add_note(&mut self, serial: &str, text: String) -> DeviceNote {
+		let id_bytes: [u8; 8] = rand::rng().random();
+		let note = DeviceNote {
+			id: hex::encode(id_bytes),
+			text,
+			created_at: now(),
+		};
+		self.devices
+			.entry(serial.to_string())
+			.or_default()
+			.notes
+			.push(note.clone());
+		note
+	}
+
+	/// Removes a note, returning whether it existed.
+	#[cfg(feature = "gui")]
+	pub fn delete_note(&mut self, serial: &str, note_id: &str) -> bool {
+		let Some(device) = self.devices.get_mut(serial) else {
+			return false;
+		};
+		let before = device.notes.len();
+		device.notes.retain(|note| note.id != note_id);
+		device.notes.len() != before
+	}
+
+	#[cfg(feature = "gui")]
+	pub fn history(&self, serial: &str) -> DeviceHistory {
+		self.devices.get(serial).cloned().unwrap_or_default()
+	}
+
+	/// Refuses to go on if the device with the given serial number is quarantined, unless the
+	/// user explicitly overrides it.
+	pub fn check_quarantine(
+		&self,
+		serial: &str,
+		operation: &str,
+		override_quarantine: bool,
+	) -> Result<(), PFError> {
+		let Some(entry) = self.quarantine.get(serial) else {
+			return Ok(());
+		};
+		if override_quarantine {
+			log::warn!(
+				"Running {} on quarantined device {} ({}), overridden by the user",
+				operation,
+				serial,
+				entry.reason
+			);
+			return Ok(());
+		}
+		Err(PFError::Device(format!(
+			"Device {} is quarantined: {}",
+			serial, entry.reason
+		)))
+	}
+}
+
+/// Records a device event, logging instead of failing since the operation itself already
+/// succeeded.
+pub fn log_device_event(serial: &str, kind: DeviceEventKind, summary: &str) {
+	let result = Inventory::update(|inventory| {
+		inventory.record_event(serial, kind, summary);
+		Ok(())
+	});
+	if let Err(e) = result {
+		log::warn!("Failed to record {:?} for device {}: {}", kind, serial, e);
+	}
+}
+
+/// Refuses provisioning and credential operations on a quarantined device.
+///
+/// The device at `device_path` is identified by the serial it reports over HID, or through the
+/// Rescue Applet when it is the only key on a PC/SC reader. A device that cannot be identified
+/// is refused as well unless `override_quarantine` is set, so blocking one interface or
+/// plugging in a second key cannot get a quarantined key past the check.
+pub fn check_quarantine(
+	operation: &str,
+	device_path: Option<&str>,
+	override_quarantine: Option<bool>,
+) -> Result<(), PFError> {
+	let inventory = Inventory::load()?;
+	if inventory.quarantine.is_empty() {
+		return Ok(());
+	}
+	let override_quarantine = override_quarantine.unwrap_or(false);
+	match device_serial(device_path) {
+		Some(serial) => inventory.check_quarantine(&serial, operation, override_quarantine),
+		None if override_quarantine => {
+			log::warn!(
+				"Could not identify the device for {}, the quarantine check was overridden",
+				operation
+			);
+			Ok(())
+		}
+		None => Err(PFError::Device(format!(
+			"Could not identify the device to check it against the quarantine, override the \
+			 quarantine to run {} anyway",
+			operation
+		))),
+	}
+}
+
+/// Serial number of the FIDO device at `device_path`. Falls back to the serial of the only device
+/// on a PC/SC reader when the key does not report one over HID and is the only key there, or when
+/// no path is given and no key is on HID at all.
+pub fn device_serial(device_path: Option<&str>) -> Option<String> {
+	let hid_serial = fido::read_device_details(device_path).map(|status| status.info.serial);
+	let hid_keys = fido::list_devices().ok().map(|devices| devices.len());
+	resolve_serial(hid_serial, device_path.is_some(), hid_keys, || {
+		rescue::list_devices().ok()
+	})
+}
+
+/// The serial a key reported over HID, or `None` for the placeholder of keys whose serial number
+/// is not visible over HID
+fn identified(serial: &str) -> Option<String> {
+	let serial = serial.trim();
+	(!serial.is_empty() && serial != "?").then(|| serial.to_string())
+}
+
+fn resolve_serial(
+	hid_serial: Result<String, PFError>,
+	path_given: bool,
+	hid_keys: Option<usize>,
+	rescue_devices: impl FnOnce() -> Option<Vec<RescueDevice>>,
+) -> Option<String> {
+	let rescue_fallback = match hid_serial {
+		Ok(serial) => match identified(&serial) {
+			Some(serial) => return Some(serial),
+			None => {
+				log::debug!("The device reports no serial over HID");
+				hid_keys == Some(1)
+			}
+		},
+		Err(e) => {
+			log::debug!("Could not read the serial over HID: {}", e);
+			!path_given && hid_keys == Some(0)
+		}
+	};
+	if !rescue_fallback {
+		return None;
+	}
+	match rescue_devices()?.as_slice() {
+		[device] => identified(&device.serial),
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn rescue(serial: &str) -> Option<Vec<RescueDevice>> {
+		Some(vec![RescueDevice {
+			reader: "Pico Key".into(),
+			serial: serial.into(),
+			firmware_version: "7.0".into(),
+		}])
+	}
+
+	#[test]
+	fn reported_serial_is_used() {
+		let serial = resolve_serial(Ok("ABCD".into()), true, Some(2), || rescue("EF01"));
+		assert_eq!(serial.as_deref(), Some("ABCD"));
+	}
+
+	#[test]
+	fn unknown_serial_falls_back_to_the_only_rescue_device() {
+		let serial = resolve_serial(Ok("?".into()), true, Some(1), || rescue("EF01"));
+		assert_eq!(serial.as_deref(), Some("EF01"));
+		let serial = resolve_serial(Ok(String::new()), false, Some(1), || rescue("EF01"));
+		assert_eq!(serial.as_deref(), Some("EF01"));
+	}
+
+	#[test]
+	fn unknown_serial_is_unidentified_with_several_keys() {
+		assert_eq!(
+			resolve_serial(Ok("?".into()), true, Some(2), || rescue("EF01")),
+			None
+		);
+		assert_eq!(resolve_serial(Ok("?".into()), true, Some(1), || None), None);
+		assert_eq!(
+			resolve_serial(Ok("?".into()), true, Some(1), || rescue("")),
+			None
+		);
+	}
+
+	#[test]
+	fn unreadable_key_falls_back_only_without_a_path_and_without_hid_keys() {
+		let error = || Err(PFError::Device("no key".into()));
+		assert_eq!(
+			resolve_serial(error(), false, Some(0), || rescue("EF01")).as_deref(),
+			Some("EF01")
+		);
+		assert_eq!(
+			resolve_serial(error(), true, Some(0), || rescue("EF01")),
+			None
+		);
+		assert_eq!(
+			resolve_serial(error(), false, Some(1), || rescue("EF01")),
+			None
+		);
+	}
+}
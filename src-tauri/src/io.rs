@@ -1,72 +1,2097 @@
 //! Tauri Commands to interact with the pico-fido firmware via rescue and fido protocols.
-use crate::{error::PFError, fido, rescue, types::*};
+//!
+//! Every command touching the device runs through the shared `DeviceManager`, on behalf of the
+//! window that invoked it.
+use crate::{
+	activity, api, card, cli_export,
+	commissioning::{self, Commissioning},
+	credential_export, device_state, devmode,
+	error::PFError,
+	fido, firmware, hsm, intake,
+	inventory::{self, Inventory, check_quarantine},
+	jobs::{self, JobRunner},
+	logging,
+	manager::{DeviceManager, run_for_device, run_for_window},
+	migration, oath, openpgp, operation, permissions, piv, profiles, reminders, reporting,
+	reprovision, rescue, rng, schema,
+	session::SessionManager,
+	settings_backup, simulator,
+	types::*,
+	updates, workspaces,
+};
+use std::path::Path;
+use tauri::Window;
 
+/// FIDO keys currently connected. Pass the `path` of one as `device_path` to target it when
+/// several are plugged in.
 #[tauri::command]
-pub fn read_device_details() -> Result<FullDeviceStatus, PFError> {
-	match rescue::read_device_details() {
-		Ok(status) => Ok(status),
-		Err(e) => {
-			log::warn!("Rescue method failed: {}. Falling back to FIDO...", e);
-			fido::read_device_details()
+pub fn list_devices() -> Result<Vec<FidoHidDevice>, PFError> {
+	if simulator::is_active() {
+		return simulator::list_devices();
+	}
+	fido::list_devices()
+}
+
+#[tauri::command]
+pub async fn read_device_details(
+	window: Window,
+	device_path: Option<String>,
+	transport: Option<FidoTransport>,
+) -> Result<FullDeviceStatus, PFError> {
+	let transport = transport.unwrap_or_default();
+	run_for_window(window, "read_device_details", move || {
+		if simulator::is_active() {
+			return simulator::read_device_details();
 		}
+		// The Rescue Applet cannot be matched to a HID path, read the selected key over FIDO
+		if device_path.is_some() || transport != FidoTransport::Auto {
+			return with_fido_transport(
+				transport,
+				device_path.as_deref(),
+				fido::read_device_details,
+				fido::ccid::read_device_details,
+			);
+		}
+		match rescue::read_device_details() {
+			Ok(status) => {
+				remember_config(&status);
+				Ok(status)
+			}
+			Err(e) => {
+				log::warn!("Rescue method failed: {}. Falling back to FIDO...", e);
+				with_fido_transport(
+					transport,
+					None,
+					fido::read_device_details,
+					fido::ccid::read_device_details,
+				)
+			}
+		}
+	})
+	.await?
+}
+
+/// Runs a FIDO method operation over the chosen interface. With `Auto`, CCID is only used when
+/// no key can be seen over HID, so an operation is never attempted twice.
+fn with_fido_transport<T>(
+	transport: FidoTransport,
+	device_path: Option<&str>,
+	hid: impl FnOnce(Option<&str>) -> Result<T, PFError>,
+	ccid: impl FnOnce(fido::ccid::Reader) -> Result<T, PFError>,
+) -> Result<T, PFError> {
+	let reader = match transport {
+		FidoTransport::Hid => None,
+		FidoTransport::Ccid => Some(fido::ccid::Reader::Usb),
+		FidoTransport::Nfc => Some(fido::ccid::Reader::Contactless),
+		FidoTransport::Auto => (device_path.is_none()
+			&& fido::list_devices().is_ok_and(|devices| devices.is_empty()))
+		.then_some(fido::ccid::Reader::Usb),
+	};
+	match reader {
+		Some(reader) => {
+			log::info!("Using the {:?} PC/SC reader for the FIDO method", reader);
+			ccid(reader)
+		}
+		None => hid(device_path),
+	}
+}
+
+/// Keeps the configuration of the device in the inventory. Failures only cost the history, so
+/// they are logged instead of failing the read.
+fn remember_config(status: &FullDeviceStatus) {
+	let result =
+		Inventory::update(|inventory| inventory.record_config(&status.info.serial, &status.config));
+	if let Err(e) = result {
+		log::warn!(
+			"Failed to record device configuration in the inventory: {}",
+			e
+		);
 	}
 }
 
+/// Overall state of the connected device, see `DeviceState`
 #[tauri::command]
-pub fn write_config(
+pub async fn get_device_state(window: Window) -> Result<DeviceState, PFError> {
+	run_for_window(window, "get_device_state", || {
+		if simulator::is_active() {
+			return device_state::simulated();
+		}
+		device_state::current()
+	})
+	.await
+}
+
+/// Vendor command categories the firmware of the device supports
+#[tauri::command]
+pub async fn get_vendor_capabilities(
+	window: Window,
+	device_path: Option<String>,
+) -> Result<VendorCapabilities, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_device(
+		window,
+		"get_vendor_capabilities",
+		device_path.clone(),
+		move || fido::probe_vendor_capabilities(device_path.as_deref()),
+	)
+	.await?
+}
+
+/// Attestation certificate chain of the key, from a throwaway non-resident credential. Needs a
+/// touch, and the PIN if one is set.
+#[tauri::command]
+pub async fn get_attestation_info(
+	window: Window,
+	pin: Option<String>,
+	device_path: Option<String>,
+) -> Result<AttestationInfo, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_device(
+		window,
+		"get_attestation_info",
+		device_path.clone(),
+		move || fido::attestation::attestation_info(device_path.as_deref(), pin.as_deref()),
+	)
+	.await?
+}
+
+/// Whether the device supports enterprise attestation and has it enabled
+#[tauri::command]
+pub async fn get_enterprise_attestation(
+	window: Window,
+	device_path: Option<String>,
+) -> Result<EnterpriseAttestationStatus, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_device(
+		window,
+		"get_enterprise_attestation",
+		device_path.clone(),
+		move || fido::enterprise::status(device_path.as_deref()),
+	)
+	.await?
+}
+
+/// Enables enterprise attestation. Only a reset of the FIDO application disables it again.
+#[tauri::command]
+pub async fn enable_enterprise_attestation(
+	window: Window,
+	pin: Option<String>,
+	device_path: Option<String>,
+) -> Result<(), PFError> {
+	simulator::ensure_inactive()?;
+	run_for_device(
+		window,
+		"enable_enterprise_attestation",
+		device_path.clone(),
+		move || {
+			fido::enterprise::enable(device_path.as_deref(), pin.as_deref())?;
+			if let Some(serial) = inventory::device_serial(device_path.as_deref()) {
+				inventory::log_device_event(
+					&serial,
+					DeviceEventKind::AttestationChange,
+					"Enterprise attestation enabled",
+				);
+			}
+			Ok(())
+		},
+	)
+	.await?
+}
+
+/// Certificate signing request for the enterprise attestation key of the device
+#[tauri::command]
+pub async fn generate_enterprise_attestation_csr(
+	window: Window,
+	device_path: Option<String>,
+) -> Result<EnterpriseAttestationCsr, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_device(
+		window,
+		"generate_enterprise_attestation_csr",
+		device_path.clone(),
+		move || fido::enterprise::generate_csr(device_path.as_deref()),
+	)
+	.await?
+}
+
+/// Uploads the DER or PEM certificate the organization's CA issued for the CSR of the device
+#[tauri::command]
+pub async fn upload_enterprise_attestation_certificate(
+	window: Window,
+	pin: Option<String>,
+	certificate: Vec<u8>,
+	device_path: Option<String>,
+) -> Result<String, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_device(
+		window,
+		"upload_enterprise_attestation_certificate",
+		device_path.clone(),
+		move || {
+			let subject = fido::enterprise::upload_certificate(
+				device_path.as_deref(),
+				pin.as_deref(),
+				&certificate,
+			)?;
+			if let Some(serial) = inventory::device_serial(device_path.as_deref()) {
+				inventory::log_device_event(
+					&serial,
+					DeviceEventKind::AttestationChange,
+					&format!("Enterprise attestation certificate of {} uploaded", subject),
+				);
+			}
+			Ok(format!(
+				"Attestation certificate of {} uploaded successfully",
+				subject
+			))
+		},
+	)
+	.await?
+}
+
+/// Samples the hardware RNG of the device and runs basic statistical tests on it
+#[tauri::command]
+pub async fn run_rng_diagnostic(window: Window) -> Result<RngReport, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "run_rng_diagnostic", rng::run_diagnostic).await?
+}
+
+/// Writes the configuration. With `dry_run`, only validates it and returns the changes.
+#[tauri::command]
+pub async fn write_config(
+	window: Window,
+	config: AppConfigInput,
+	method: String,
+	pin: Option<String>,
+	override_quarantine: Option<bool>,
+	device_path: Option<String>,
+	dry_run: Option<bool>,
+) -> Result<CommandOutcome, PFError> {
+	let dry_run = dry_run.unwrap_or(false);
+	// "CCID" forces the FIDO method over PC/SC, "FIDO" picks the interface on its own
+	let transport = match method.as_str() {
+		"CCID" => FidoTransport::Ccid,
+		"NFC" => FidoTransport::Nfc,
+		_ => FidoTransport::Auto,
+	};
+	run_for_window(window, "write_config", move || {
+		if simulator::is_active() {
+			if dry_run {
+				let actions = fido::describe_config(&config)?;
+				return Ok(CommandOutcome::dry_run("write_config", actions));
+			}
+			return simulator::write_config(config, pin).map(CommandOutcome::Done);
+		}
+		check_quarantine("write_config", device_path.as_deref(), override_quarantine)?;
+		match (matches!(method.as_str(), "FIDO" | "CCID" | "NFC"), dry_run) {
+			(true, true) => with_fido_transport(
+				transport,
+				device_path.as_deref(),
+				|path| fido::plan_write_config(path, &config, pin.as_deref()),
+				|reader| fido::ccid::plan_write_config(reader, &config, pin.as_deref()),
+			)
+			.map(|actions| CommandOutcome::dry_run("write_config", actions)),
+			(true, false) => with_fido_transport(
+				transport,
+				device_path.as_deref(),
+				|path| {
+					fido::write_config(path, config.clone(), pin.clone())
+						.map(CommandOutcome::Written)
+				},
+				|reader| {
+					fido::ccid::write_config(reader, config.clone(), pin.clone())
+						.map(CommandOutcome::Done)
+				},
+			),
+			(false, true) => rescue::plan_write_config(&config)
+				.map(|actions| CommandOutcome::dry_run("write_config", actions)),
+			(false, false) => rescue::write_config(config).map(CommandOutcome::Done),
+		}
+	})
+	.await?
+}
+
+/// Saves the configuration as a profile, in TOML if `path` ends with `.toml`, else in JSON
+#[tauri::command]
+pub fn save_config_profile(
+	path: String,
+	name: String,
 	config: AppConfigInput,
+) -> Result<ConfigProfile, PFError> {
+	profiles::save(Path::new(&path), &name, config)
+}
+
+#[tauri::command]
+pub fn load_config_profile(path: String) -> Result<ConfigProfile, PFError> {
+	profiles::load(Path::new(&path))
+}
+
+/// Writes the configuration of a saved profile to the connected key, like `write_config`
+#[tauri::command]
+pub async fn apply_config_profile(
+	window: Window,
+	path: String,
 	method: String,
 	pin: Option<String>,
+	override_quarantine: Option<bool>,
+	device_path: Option<String>,
+	dry_run: Option<bool>,
+) -> Result<CommandOutcome, PFError> {
+	let profile = profiles::load(Path::new(&path))?;
+	log::info!("Applying configuration profile {}", profile.name);
+	write_config(
+		window,
+		profile.config,
+		method,
+		pin,
+		override_quarantine,
+		device_path,
+		dry_run,
+	)
+	.await
+}
+
+/// Writes the profile at `profile_path` to every key plugged in from now on, see `commissioning`
+#[tauri::command]
+pub fn start_commissioning(
+	window: Window,
+	commissioning: tauri::State<'_, Commissioning>,
+	profile_path: String,
+	pin: String,
+) -> Result<CommissioningSession, PFError> {
+	simulator::ensure_inactive()?;
+	let profile = profiles::load(Path::new(&profile_path))?;
+	commissioning.start(window.label(), profile, pin)
+}
+
+/// Stops the running commissioning session, returning its log
+#[tauri::command]
+pub fn stop_commissioning(
+	commissioning: tauri::State<'_, Commissioning>,
+) -> Option<CommissioningSession> {
+	commissioning.stop()
+}
+
+/// The running commissioning session, or the latest one
+#[tauri::command]
+pub fn get_commissioning_session(
+	commissioning: tauri::State<'_, Commissioning>,
+) -> Option<CommissioningSession> {
+	commissioning.session()
+}
+
+/// Exports the log of the running or latest commissioning session, as CSV if `path` ends with
+/// `.csv`, else as JSON
+#[tauri::command]
+pub fn export_commissioning_log(
+	commissioning: tauri::State<'_, Commissioning>,
+	path: String,
+) -> Result<(), PFError> {
+	let session = commissioning
+		.session()
+		.ok_or_else(|| PFError::Io("No commissioning session to export".into()))?;
+	commissioning::export(&session, Path::new(&path))
+}
+
+/// Writes the signed report of the keys provisioned by the running or latest commissioning
+/// session, as HTML if `path` ends with `.html`, else as JSON
+#[tauri::command]
+pub fn generate_commissioning_report(
+	commissioning: tauri::State<'_, Commissioning>,
+	path: String,
+) -> Result<CommissioningReportExport, PFError> {
+	let session = commissioning
+		.session()
+		.ok_or_else(|| PFError::Io("No commissioning session to report".into()))?;
+	reporting::write(&session, Path::new(&path))
+}
+
+/// Whether the commissioning report at `path` was signed by this station and left unchanged
+#[tauri::command]
+pub fn verify_commissioning_report(path: String) -> Result<bool, PFError> {
+	reporting::verify(Path::new(&path))
+}
+
+/// Checks an LED driver and GPIO against the board, e.g. "pico2", before they are written
+#[tauri::command]
+pub fn validate_led_config(
+	driver: Option<LedDriver>,
+	gpio: Option<u8>,
+	board: Option<String>,
+) -> Result<(), PFError> {
+	fido::led::validate(driver, gpio, board.as_deref())
+}
+
+/// Blinks the LED of the key, to confirm its LED driver and GPIO work
+#[tauri::command]
+pub async fn test_led(window: Window, device_path: Option<String>) -> Result<(), PFError> {
+	simulator::ensure_inactive()?;
+	run_for_device(window, "test_led", device_path.clone(), move || {
+		fido::led::test_led(device_path.as_deref())
+	})
+	.await?
+}
+
+/// Configuration last read from the device with the given serial number, if any
+#[tauri::command]
+pub fn get_saved_device_config(serial: String) -> Result<Option<AppConfig>, PFError> {
+	Inventory::load()?.device_config(&serial)
+}
+
+/// Renders the configuration as the equivalent `pico-fido-tool.py` invocations
+#[tauri::command]
+pub fn export_config_as_cli(config: AppConfigInput) -> Result<CliExport, PFError> {
+	cli_export::export_pico_fido_tool(&config)
+}
+
+/// Enables secure boot, locking the device to it if `lock` is set. Irreversible, so it is only
+/// sent to the device whose serial number the user typed into `confirm_serial`.
+#[tauri::command]
+pub async fn enable_secure_boot(
+	window: Window,
+	lock: bool,
+	confirm_serial: String,
 ) -> Result<String, PFError> {
-	if method == "FIDO" {
-		fido::write_config(config, pin)
-	} else {
-		rescue::write_config(config)
-	}
+	simulator::ensure_inactive()?;
+	run_for_window(window, "enable_secure_boot", move || {
+		rescue::enable_secure_boot(lock, &confirm_serial)
+	})
+	.await?
+}
+
+/// Locks a device with secure boot enabled to it, so it only runs signed firmware from now on.
+/// Irreversible, so it is only sent to the device whose serial number is `confirm_serial`.
+#[tauri::command]
+pub async fn enable_secure_lock(window: Window, confirm_serial: String) -> Result<String, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "enable_secure_lock", move || {
+		rescue::enable_secure_lock(&confirm_serial)
+	})
+	.await?
 }
 
 #[tauri::command]
-pub fn enable_secure_boot(lock: bool) -> Result<String, PFError> {
-	rescue::enable_secure_boot(lock)
+pub(crate) async fn get_fido_info(
+	window: Window,
+	device_path: Option<String>,
+) -> Result<FidoDeviceInfo, PFError> {
+	run_for_device(window, "get_fido_info", device_path.clone(), move || {
+		if simulator::is_active() {
+			return simulator::get_fido_info();
+		}
+		fido::get_fido_info(device_path.as_deref())
+	})
+	.await?
 }
 
+/// Remaining PIN and UV attempts, for warning before the key gets blocked
 #[tauri::command]
-pub(crate) fn get_fido_info() -> Result<FidoDeviceInfo, String> {
-	fido::get_fido_info()
+pub async fn get_pin_status(
+	window: Window,
+	device_path: Option<String>,
+	transport: Option<FidoTransport>,
+) -> Result<PinStatus, PFError> {
+	run_for_device(window, "get_pin_status", device_path.clone(), move || {
+		if simulator::is_active() {
+			return simulator::get_pin_status();
+		}
+		with_fido_transport(
+			transport.unwrap_or_default(),
+			device_path.as_deref(),
+			fido::get_pin_status,
+			fido::ccid::get_pin_status,
+		)
+	})
+	.await?
 }
 
 #[tauri::command]
-pub(crate) fn change_fido_pin(
+pub(crate) async fn change_fido_pin(
+	window: Window,
 	current_pin: Option<String>,
 	new_pin: String,
-) -> Result<String, String> {
-	fido::change_fido_pin(current_pin, new_pin)
+	override_quarantine: Option<bool>,
+	device_path: Option<String>,
+	transport: Option<FidoTransport>,
+) -> Result<String, PFError> {
+	run_for_window(window, "change_fido_pin", move || {
+		if simulator::is_active() {
+			return simulator::change_fido_pin(current_pin, new_pin);
+		}
+		check_quarantine(
+			"change_fido_pin",
+			device_path.as_deref(),
+			override_quarantine,
+		)?;
+		with_fido_transport(
+			transport.unwrap_or_default(),
+			device_path.as_deref(),
+			|path| fido::change_fido_pin(path, current_pin.clone(), new_pin.clone()),
+			|reader| fido::ccid::change_pin(reader, current_pin.clone(), new_pin.clone()),
+		)
+	})
+	.await?
+}
+
+/// Minimum PIN length the key enforces and whether it demands a PIN change
+#[tauri::command]
+pub async fn get_pin_policy(
+	window: Window,
+	device_path: Option<String>,
+) -> Result<PinPolicy, PFError> {
+	run_for_device(window, "get_pin_policy", device_path.clone(), move || {
+		if simulator::is_active() {
+			return simulator::get_pin_policy();
+		}
+		fido::get_pin_policy(device_path.as_deref())
+	})
+	.await?
+}
+
+/// PIN rules of the organisation, checked before a new PIN is sent to the key
+#[tauri::command]
+pub fn get_org_pin_policy() -> OrgPinPolicy {
+	fido::pin_policy::org_policy()
+}
+
+#[tauri::command]
+pub fn set_org_pin_policy(policy: OrgPinPolicy) -> Result<(), PFError> {
+	fido::pin_policy::set_org_policy(&policy)
 }
 
 /// UNSTABLE!
+///
+/// `rp_ids` may read the minimum PIN length with the minPinLength extension when they register
+/// a passkey.
 #[tauri::command]
-pub(crate) fn set_min_pin_length(
+pub(crate) async fn set_min_pin_length(
+	window: Window,
 	current_pin: String,
 	min_pin_length: u8,
-) -> Result<String, String> {
-	fido::set_min_pin_length(current_pin, min_pin_length)
+	rp_ids: Option<Vec<String>>,
+	override_quarantine: Option<bool>,
+	device_path: Option<String>,
+) -> Result<PinPolicy, PFError> {
+	let rp_ids = rp_ids.unwrap_or_default();
+	run_for_window(window, "set_min_pin_length", move || {
+		if simulator::is_active() {
+			return simulator::set_min_pin_length(current_pin, min_pin_length, rp_ids);
+		}
+		check_quarantine(
+			"set_min_pin_length",
+			device_path.as_deref(),
+			override_quarantine,
+		)?;
+		fido::set_min_pin_length(device_path.as_deref(), current_pin, min_pin_length, rp_ids)
+	})
+	.await?
+}
+
+/// Requires the PIN to be changed before the key can be used again
+#[tauri::command]
+pub async fn force_pin_change(
+	window: Window,
+	pin: String,
+	override_quarantine: Option<bool>,
+	device_path: Option<String>,
+) -> Result<(), PFError> {
+	run_for_window(window, "force_pin_change", move || {
+		if simulator::is_active() {
+			return simulator::force_pin_change(&pin);
+		}
+		check_quarantine(
+			"force_pin_change",
+			device_path.as_deref(),
+			override_quarantine,
+		)?;
+		fido::force_pin_change(device_path.as_deref(), &pin)
+	})
+	.await?
+}
+
+/// Turns alwaysUv on or off, returning whether the setting changed
+#[tauri::command]
+pub async fn set_always_uv(
+	window: Window,
+	pin: String,
+	enabled: bool,
+	override_quarantine: Option<bool>,
+	device_path: Option<String>,
+) -> Result<bool, PFError> {
+	run_for_window(window, "set_always_uv", move || {
+		if simulator::is_active() {
+			return simulator::set_always_uv(&pin, enabled);
+		}
+		check_quarantine("set_always_uv", device_path.as_deref(), override_quarantine)?;
+		fido::set_always_uv(device_path.as_deref(), &pin, enabled)
+	})
+	.await?
+}
+
+#[tauri::command]
+pub async fn reboot(window: Window, to_bootsel: bool) -> Result<String, PFError> {
+	run_for_window(window, "reboot", move || {
+		if simulator::is_active() {
+			return simulator::reboot_device(to_bootsel);
+		}
+		rescue::reboot_device(to_bootsel)
+	})
+	.await?
+}
+
+/// Versions of the command payloads the backend speaks, see `api`
+#[tauri::command]
+pub fn get_api_version() -> ApiVersion {
+	api::version()
+}
+
+/// Lists the passkeys along with the slot usage. With `workspace`, only its passkeys are listed.
+///
+/// Callers of API version 1 get the plain list, and errors as plain messages.
+#[tauri::command]
+pub async fn get_credentials(
+	window: Window,
+	pin: Option<String>,
+	override_quarantine: Option<bool>,
+	device_path: Option<String>,
+	workspace: Option<String>,
+	api_version: Option<u32>,
+	transport: Option<FidoTransport>,
+) -> Result<CredentialsPayload, CommandError> {
+	let api_version = api::negotiate(api_version)?;
+	let store = read_credential_store(
+		window,
+		pin,
+		override_quarantine,
+		device_path,
+		workspace,
+		transport,
+	)
+	.await
+	.map_err(|e| api::error(api_version, e))?;
+	Ok(match api_version {
+		1 => CredentialsPayload::V1(v1::credentials(store)),
+		_ => CredentialsPayload::Current(store),
+	})
+}
+
+async fn read_credential_store(
+	window: Window,
+	pin: Option<String>,
+	override_quarantine: Option<bool>,
+	device_path: Option<String>,
+	workspace: Option<String>,
+	transport: Option<FidoTransport>,
+) -> Result<CredentialStore, PFError> {
+	let workspace = workspace.map(|name| workspaces::get(&name)).transpose()?;
+	let (mut credentials, (existing, remaining)) =
+		run_for_window(window, "get_credentials", move || {
+			if simulator::is_active() {
+				let pin = pin.unwrap_or_default();
+				let metadata = simulator::get_creds_metadata(&pin)?;
+				return Ok::<_, PFError>((simulator::get_credentials(pin)?, metadata));
+			}
+			check_quarantine(
+				"get_credentials",
+				device_path.as_deref(),
+				override_quarantine,
+			)?;
+			with_fido_transport(
+				transport.unwrap_or_default(),
+				device_path.as_deref(),
+				|path| {
+					let metadata = fido::get_creds_metadata(path, pin.as_deref())?;
+					Ok((fido::get_credentials(path, pin.as_deref())?, metadata))
+				},
+				|reader| fido::ccid::get_credentials(reader, pin.as_deref()),
+			)
+		})
+		.await??;
+	fido::rp_metadata::enrich(&mut credentials);
+	if let Some(workspace) = workspace {
+		credentials.retain(|c| workspace.contains_credential(c));
+	}
+	Ok(CredentialStore::new(credentials, existing, remaining))
+}
+
+/// Refuses to touch a credential that is not part of `workspace`
+fn check_in_workspace(
+	workspace: &Workspace,
+	credentials: Vec<StoredCredential>,
+	credential_id: &str,
+) -> Result<(), PFError> {
+	let credential = credentials
+		.into_iter()
+		.find(|c| c.credential_id.eq_ignore_ascii_case(credential_id))
+		.ok_or_else(|| {
+			PFError::Device("No passkey with this credential ID is stored on the device".into())
+		})?;
+	if !workspace.contains_credential(&credential) {
+		return Err(PFError::Device(format!(
+			"The passkey of {} for {} is not part of the workspace {}",
+			credential.user_name, credential.rp_id, workspace.name
+		)));
+	}
+	Ok(())
+}
+
+#[tauri::command]
+pub async fn delete_credential(
+	window: Window,
+	pin: Option<String>,
+	credential_id: String,
+	override_quarantine: Option<bool>,
+	device_path: Option<String>,
+	dry_run: Option<bool>,
+	workspace: Option<String>,
+) -> Result<CommandOutcome, PFError> {
+	let dry_run = dry_run.unwrap_or(false);
+	let workspace = workspace.map(|name| workspaces::get(&name)).transpose()?;
+	run_for_window(window, "delete_credential", move || {
+		if simulator::is_active() {
+			let pin = pin.unwrap_or_default();
+			if let Some(workspace) = &workspace {
+				let credentials = simulator::get_credentials(pin.clone())?;
+				check_in_workspace(workspace, credentials, &credential_id)?;
+			}
+			if dry_run {
+				let credential = simulator::get_credentials(pin)?
+					.into_iter()
+					.find(|c| c.credential_id.eq_ignore_ascii_case(&credential_id))
+					.ok_or_else(|| {
+						PFError::Device(
+							"No passkey with this credential ID is stored on the device".into(),
+						)
+					})?;
+				return Ok(CommandOutcome::dry_run(
+					"delete_credential",
+					vec![format!(
+						"Delete the passkey of {} for {}",
+						credential.user_name, credential.rp_id
+					)],
+				));
+			}
+			return simulator::delete_credential(pin, credential_id).map(CommandOutcome::Done);
+		}
+		check_quarantine(
+			"delete_credential",
+			device_path.as_deref(),
+			override_quarantine,
+		)?;
+		if let Some(workspace) = &workspace {
+			let credentials = fido::get_credentials(device_path.as_deref(), pin.as_deref())?;
+			check_in_workspace(workspace, credentials, &credential_id)?;
+		}
+		if dry_run {
+			return fido::plan_delete_credential(
+				device_path.as_deref(),
+				pin.as_deref(),
+				credential_id,
+			)
+			.map(|actions| CommandOutcome::dry_run("delete_credential", actions));
+		}
+		fido::delete_credential(device_path.as_deref(), pin.as_deref(), credential_id)
+			.map(CommandOutcome::Done)
+	})
+	.await?
+}
+
+/// Fixes the user name and display name of a passkey without re-registering it
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn update_credential(
+	window: Window,
+	pin: Option<String>,
+	credential_id: String,
+	user_name: String,
+	user_display_name: String,
+	override_quarantine: Option<bool>,
+	device_path: Option<String>,
+	workspace: Option<String>,
+) -> Result<String, PFError> {
+	let workspace = workspace.map(|name| workspaces::get(&name)).transpose()?;
+	run_for_window(window, "update_credential", move || {
+		if simulator::is_active() {
+			let pin = pin.unwrap_or_default();
+			if let Some(workspace) = &workspace {
+				let credentials = simulator::get_credentials(pin.clone())?;
+				check_in_workspace(workspace, credentials, &credential_id)?;
+			}
+			return simulator::update_credential(pin, credential_id, user_name, user_display_name);
+		}
+		check_quarantine(
+			"update_credential",
+			device_path.as_deref(),
+			override_quarantine,
+		)?;
+		if let Some(workspace) = &workspace {
+			let credentials = fido::get_credentials(device_path.as_deref(), pin.as_deref())?;
+			check_in_workspace(workspace, credentials, &credential_id)?;
+		}
+		fido::update_credential(
+			device_path.as_deref(),
+			pin.as_deref(),
+			credential_id,
+			user_name,
+			user_display_name,
+		)
+	})
+	.await?
+}
+
+/// Passkeys on the key for an export, along with its serial number if it can be identified
+fn read_credentials_for_export(
+	pin: Option<String>,
+	device_path: Option<&str>,
+	operation: &str,
+	override_quarantine: Option<bool>,
+) -> Result<(Vec<StoredCredential>, Option<String>), PFError> {
+	if simulator::is_active() {
+		return Ok((simulator::get_credentials(pin.unwrap_or_default())?, None));
+	}
+	check_quarantine(operation, device_path, override_quarantine)?;
+	let mut credentials = fido::get_credentials(device_path, pin.as_deref())?;
+	fido::rp_metadata::enrich(&mut credentials);
+	Ok((credentials, inventory::device_serial(device_path)))
+}
+
+/// Writes the passkeys on the key to `path`, as JSON or CSV
+#[tauri::command]
+pub async fn export_credentials(
+	window: Window,
+	pin: Option<String>,
+	path: String,
+	format: CredentialExportFormat,
+	device_path: Option<String>,
+	override_quarantine: Option<bool>,
+) -> Result<CredentialExportReport, PFError> {
+	run_for_window(window, "export_credentials", move || {
+		let (credentials, serial) = read_credentials_for_export(
+			pin,
+			device_path.as_deref(),
+			"export_credentials",
+			override_quarantine,
+		)?;
+		credential_export::export(credentials, serial, Path::new(&path), format)
+	})
+	.await?
+}
+
+/// Compares an earlier credential export at `path` with the passkeys on the key now
+#[tauri::command]
+pub async fn compare_credential_export(
+	window: Window,
+	pin: Option<String>,
+	path: String,
+	device_path: Option<String>,
+	override_quarantine: Option<bool>,
+) -> Result<CredentialComparison, PFError> {
+	run_for_window(window, "compare_credential_export", move || {
+		let (credentials, _) = read_credentials_for_export(
+			pin,
+			device_path.as_deref(),
+			"compare_credential_export",
+			override_quarantine,
+		)?;
+		credential_export::compare(Path::new(&path), credentials)
+	})
+	.await?
+}
+
+/// Factory resets the FIDO application, deleting all credentials and the PIN.
+///
+/// Fails unless `confirm` is set (not needed with `dry_run`). The key must be replugged first
+/// unless it was plugged in just now, and touched once the reset starts, both reported as phases.
+#[tauri::command]
+pub async fn reset_device(
+	window: Window,
+	confirm: bool,
+	override_quarantine: Option<bool>,
+	device_path: Option<String>,
+	dry_run: Option<bool>,
+) -> Result<CommandOutcome, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_device(window, "reset_device", device_path.clone(), move || {
+		check_quarantine("reset_device", device_path.as_deref(), override_quarantine)?;
+		if dry_run.unwrap_or(false) {
+			return fido::plan_reset_device(device_path.as_deref())
+				.map(|actions| CommandOutcome::dry_run("reset_device", actions));
+		}
+		let serial = inventory::device_serial(device_path.as_deref());
+		fido::reset_device(device_path.as_deref(), confirm)?;
+		if let Some(serial) = serial {
+			inventory::log_device_event(&serial, DeviceEventKind::Reset, "Factory reset");
+		}
+		Ok(CommandOutcome::Done(
+			"FIDO application reset. All credentials and the PIN were deleted.".to_string(),
+		))
+	})
+	.await?
+}
+
+#[tauri::command]
+pub async fn get_bio_sensor_info(
+	window: Window,
+	device_path: Option<String>,
+) -> Result<BioSensorInfo, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_device(
+		window,
+		"get_bio_sensor_info",
+		device_path.clone(),
+		move || fido::bio::get_sensor_info(device_path.as_deref()).map_err(PFError::Device),
+	)
+	.await?
+}
+
+/// Enrolls a fingerprint. Progress reports how many more times the sensor has to be touched.
+#[tauri::command]
+pub async fn enroll_fingerprint(
+	window: Window,
+	pin: String,
+	name: Option<String>,
+	override_quarantine: Option<bool>,
+	device_path: Option<String>,
+) -> Result<BioEnrollment, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "enroll_fingerprint", move || {
+		check_quarantine(
+			"enroll_fingerprint",
+			device_path.as_deref(),
+			override_quarantine,
+		)?;
+		fido::bio::enroll(device_path.as_deref(), &pin, name.as_deref()).map_err(PFError::Device)
+	})
+	.await?
+}
+
+#[tauri::command]
+pub async fn list_fingerprints(
+	window: Window,
+	pin: String,
+	device_path: Option<String>,
+) -> Result<Vec<BioEnrollment>, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_device(
+		window,
+		"list_fingerprints",
+		device_path.clone(),
+		move || fido::bio::list_enrollments(device_path.as_deref(), &pin).map_err(PFError::Device),
+	)
+	.await?
+}
+
+#[tauri::command]
+pub async fn rename_fingerprint(
+	window: Window,
+	pin: String,
+	template_id: String,
+	name: String,
+	override_quarantine: Option<bool>,
+	device_path: Option<String>,
+) -> Result<String, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "rename_fingerprint", move || {
+		check_quarantine(
+			"rename_fingerprint",
+			device_path.as_deref(),
+			override_quarantine,
+		)?;
+		fido::bio::rename_enrollment(device_path.as_deref(), &pin, &template_id, &name)
+			.map_err(PFError::Device)
+	})
+	.await?
+}
+
+#[tauri::command]
+pub async fn delete_fingerprint(
+	window: Window,
+	pin: String,
+	template_id: String,
+	override_quarantine: Option<bool>,
+	device_path: Option<String>,
+) -> Result<String, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "delete_fingerprint", move || {
+		check_quarantine(
+			"delete_fingerprint",
+			device_path.as_deref(),
+			override_quarantine,
+		)?;
+		fido::bio::delete_enrollment(device_path.as_deref(), &pin, &template_id)
+			.map_err(PFError::Device)
+	})
+	.await?
+}
+
+/// Checks whether the credential of a WebAuthn registration response lives on the device
+#[tauri::command]
+pub async fn match_registration(
+	window: Window,
+	pin: String,
+	registration: String,
+	override_quarantine: Option<bool>,
+	device_path: Option<String>,
+) -> Result<RegistrationMatch, PFError> {
+	let registration = fido::webauthn::parse_registration(&registration).map_err(PFError::Io)?;
+	run_for_device(
+		window,
+		"match_registration",
+		device_path.clone(),
+		move || {
+			let credentials = if simulator::is_active() {
+				simulator::get_credentials(pin)?
+			} else {
+				check_quarantine(
+					"match_registration",
+					device_path.as_deref(),
+					override_quarantine,
+				)?;
+				fido::get_credentials(device_path.as_deref(), Some(&pin))?
+			};
+			Ok(fido::webauthn::match_credentials(
+				registration,
+				&credentials,
+			))
+		},
+	)
+	.await?
+}
+
+#[tauri::command]
+pub async fn check_totp_drift(
+	window: Window,
+	name: String,
+	reference_code: String,
+	password: Option<String>,
+) -> Result<TotpDriftReport, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "check_totp_drift", move || {
+		oath::check_totp_drift(password.as_deref(), name, reference_code)
+	})
+	.await?
+}
+
+#[tauri::command]
+pub async fn list_oath_accounts(
+	window: Window,
+	workspace: Option<String>,
+	password: Option<String>,
+) -> Result<Vec<OathAccount>, PFError> {
+	simulator::ensure_inactive()?;
+	let workspace = workspace.map(|name| workspaces::get(&name)).transpose()?;
+	let mut accounts = run_for_window(window, "list_oath_accounts", move || {
+		oath::list_accounts(password.as_deref())
+	})
+	.await??;
+	if let Some(workspace) = workspace {
+		accounts.retain(|a| workspace.contains_oath_account(&a.name));
+	}
+	Ok(accounts)
+}
+
+/// Parses an otpauth:// URI into an account the user can review before adding it
+#[tauri::command]
+pub fn parse_otpauth_uri(uri: String) -> Result<OathCredential, PFError> {
+	oath::otpauth::parse(&uri)
+}
+
+/// Adds an OATH account and returns the name it is stored under
+#[tauri::command]
+pub async fn add_oath_account(
+	window: Window,
+	credential: OathCredential,
+	password: Option<String>,
+) -> Result<String, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "add_oath_account", move || {
+		oath::add_account(password.as_deref(), &credential)
+	})
+	.await?
+}
+
+#[tauri::command]
+pub async fn delete_oath_account(
+	window: Window,
+	name: String,
+	workspace: Option<String>,
+	password: Option<String>,
+) -> Result<(), PFError> {
+	simulator::ensure_inactive()?;
+	if let Some(workspace) = workspace.map(|name| workspaces::get(&name)).transpose()?
+		&& !workspace.contains_oath_account(&name)
+	{
+		return Err(PFError::Io(format!(
+			"The OATH account {} is not part of the workspace {}",
+			name, workspace.name
+		)));
+	}
+	run_for_window(window, "delete_oath_account", move || {
+		oath::delete_account(password.as_deref(), &name)
+	})
+	.await?
+}
+
+/// Computes the current codes, leaving out HOTP and touch-required accounts
+#[tauri::command]
+pub async fn calculate_oath_codes(
+	window: Window,
+	workspace: Option<String>,
+	password: Option<String>,
+) -> Result<Vec<OathCode>, PFError> {
+	simulator::ensure_inactive()?;
+	let workspace = workspace.map(|name| workspaces::get(&name)).transpose()?;
+	let mut codes = run_for_window(window, "calculate_oath_codes", move || {
+		oath::calculate_codes(password.as_deref())
+	})
+	.await??;
+	if let Some(workspace) = workspace {
+		codes.retain(|c| workspace.contains_oath_account(&c.name));
+	}
+	Ok(codes)
+}
+
+/// Computes the code of one account, waiting for a touch if needed
+#[tauri::command]
+pub async fn calculate_oath_code(
+	window: Window,
+	name: String,
+	password: Option<String>,
+) -> Result<OathCode, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "calculate_oath_code", move || {
+		oath::calculate_code(password.as_deref(), &name)
+	})
+	.await?
+}
+
+/// Sets, changes or removes (`new_password` empty or `None`) the OATH password
+#[tauri::command]
+pub async fn set_oath_password(
+	window: Window,
+	current_password: Option<String>,
+	new_password: Option<String>,
+) -> Result<(), PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "set_oath_password", move || {
+		oath::set_password(current_password.as_deref(), new_password.as_deref())
+	})
+	.await?
+}
+
+#[tauri::command]
+pub fn list_workspaces() -> Result<Vec<Workspace>, PFError> {
+	workspaces::list()
+}
+
+/// Adds a workspace, or replaces the one with the same name
+#[tauri::command]
+pub fn save_workspace(workspace: Workspace) -> Result<(), PFError> {
+	workspaces::save(workspace)
+}
+
+#[tauri::command]
+pub fn delete_workspace(name: String) -> Result<bool, PFError> {
+	workspaces::delete(&name)
+}
+
+#[tauri::command]
+pub async fn list_secure_notes(
+	window: Window,
+	pin: String,
+	device_path: Option<String>,
+) -> Result<Vec<SecureNote>, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_device(
+		window,
+		"list_secure_notes",
+		device_path.clone(),
+		move || fido::notes::list_secure_notes(device_path.as_deref(), pin),
+	)
+	.await?
+}
+
+#[tauri::command]
+pub async fn add_secure_note(
+	window: Window,
+	pin: String,
+	title: String,
+	body: String,
+	device_path: Option<String>,
+) -> Result<SecureNote, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_device(window, "add_secure_note", device_path.clone(), move || {
+		fido::notes::add_secure_note(device_path.as_deref(), pin, title, body)
+	})
+	.await?
+}
+
+#[tauri::command]
+pub async fn delete_secure_note(
+	window: Window,
+	pin: String,
+	note_id: String,
+	device_path: Option<String>,
+) -> Result<String, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_device(
+		window,
+		"delete_secure_note",
+		device_path.clone(),
+		move || fido::notes::delete_secure_note(device_path.as_deref(), pin, note_id),
+	)
+	.await?
+}
+
+/// Encrypts the application settings into `path` with a key only the connected key can derive
+#[tauri::command]
+pub async fn export_settings_backup(
+	window: Window,
+	pin: String,
+	path: String,
+) -> Result<SettingsBackupReport, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "export_settings_backup", move || {
+		settings_backup::export(&pin, Path::new(&path))
+	})
+	.await?
+}
+
+/// Replaces the application settings with the backup at `path`, made with the connected key
+#[tauri::command]
+pub async fn restore_settings_backup(
+	window: Window,
+	pin: String,
+	path: String,
+) -> Result<SettingsBackupReport, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "restore_settings_backup", move || {
+		settings_backup::restore(&pin, Path::new(&path))
+	})
+	.await?
+}
+
+/// Exports the secret of the key into `path`, encrypted under `passphrase`. Whoever holds the file
+/// and the passphrase can clone the key, so `acknowledge_risk` must be set.
+#[tauri::command]
+pub async fn create_device_backup(
+	window: Window,
+	pin: Option<String>,
+	passphrase: String,
+	path: String,
+	acknowledge_risk: bool,
+	device_path: Option<String>,
+) -> Result<DeviceBackupReport, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_device(
+		window,
+		"create_device_backup",
+		device_path.clone(),
+		move || {
+			fido::backup::create_backup(
+				device_path.as_deref(),
+				pin.as_deref(),
+				&passphrase,
+				Path::new(&path),
+				acknowledge_risk,
+			)
+		},
+	)
+	.await?
+}
+
+/// Replaces the secret of the key with the backup at `path`. Every credential made with the
+/// current secret stops working, so `confirm_replace` must be set.
+#[tauri::command]
+pub async fn restore_device_backup(
+	window: Window,
+	pin: Option<String>,
+	path: String,
+	passphrase: String,
+	confirm_replace: bool,
+	device_path: Option<String>,
+) -> Result<DeviceBackupReport, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_device(
+		window,
+		"restore_device_backup",
+		device_path.clone(),
+		move || {
+			fido::backup::restore_backup(
+				device_path.as_deref(),
+				pin.as_deref(),
+				Path::new(&path),
+				&passphrase,
+				confirm_replace,
+			)
+		},
+	)
+	.await?
+}
+
+#[tauri::command]
+pub async fn sign_ssh_certificate(
+	window: Window,
+	request: SshCertRequest,
+) -> Result<SshCertificate, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "sign_ssh_certificate", move || {
+		hsm::ssh::sign_ssh_certificate(request)
+	})
+	.await?
+}
+
+#[tauri::command]
+pub async fn generate_self_signed_certificate(
+	window: Window,
+	request: X509CertRequest,
+) -> Result<X509Certificate, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "generate_self_signed_certificate", move || {
+		hsm::x509::generate_self_signed_certificate(request)
+	})
+	.await?
+}
+
+#[tauri::command]
+pub async fn get_credential_sign_count(
+	window: Window,
+	pin: String,
+	rp_id: String,
+	credential_id: String,
+	override_quarantine: Option<bool>,
+	device_path: Option<String>,
+) -> Result<CredentialSignCount, PFError> {
+	run_for_device(
+		window,
+		"get_credential_sign_count",
+		device_path.clone(),
+		move || {
+			if simulator::is_active() {
+				return simulator::get_credential_sign_count(pin, rp_id, credential_id);
+			}
+			check_quarantine(
+				"get_credential_sign_count",
+				device_path.as_deref(),
+				override_quarantine,
+			)?;
+			fido::get_credential_sign_count(device_path.as_deref(), pin, rp_id, credential_id)
+		},
+	)
+	.await?
 }
 
 #[tauri::command]
-pub fn reboot(to_bootsel: bool) -> Result<String, PFError> {
-	rescue::reboot_device(to_bootsel)
+pub async fn verify_credential(
+	window: Window,
+	pin: String,
+	rp_id: String,
+	credential_id: String,
+	override_quarantine: Option<bool>,
+	device_path: Option<String>,
+) -> Result<CredentialVerification, PFError> {
+	run_for_device(
+		window,
+		"verify_credential",
+		device_path.clone(),
+		move || {
+			if simulator::is_active() {
+				return simulator::verify_credential(pin, rp_id, credential_id);
+			}
+			check_quarantine(
+				"verify_credential",
+				device_path.as_deref(),
+				override_quarantine,
+			)?;
+			fido::verify_credential(device_path.as_deref(), pin, rp_id, credential_id)
+		},
+	)
+	.await?
 }
 
+/// Registers a throwaway passkey with each algorithm and signs with it, verifying both signatures
 #[tauri::command]
-pub async fn get_credentials(pin: String) -> Result<Vec<StoredCredential>, String> {
-	tauri::async_runtime::spawn_blocking(move || fido::get_credentials(pin))
-		.await
-		.map_err(|e| e.to_string())?
+pub async fn run_self_test(
+	window: Window,
+	pin: String,
+	device_path: Option<String>,
+) -> Result<SelfTestReport, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_device(window, "run_self_test", device_path.clone(), move || {
+		let secp256k1_enabled = fido::read_device_details(device_path.as_deref())?
+			.config
+			.enable_secp256k1;
+		fido::self_test::run(device_path.as_deref(), &pin, secp256k1_enabled)
+	})
+	.await?
 }
 
+/// Derives the hmac-secret output of a 32 byte hex `salt` with a stored passkey
 #[tauri::command]
-pub async fn delete_credential(pin: String, credential_id: String) -> Result<String, String> {
-	tauri::async_runtime::spawn_blocking(move || fido::delete_credential(pin, credential_id))
-		.await
-		.map_err(|e| e.to_string())?
+pub async fn derive_hmac_secret(
+	window: Window,
+	pin: String,
+	rp_id: String,
+	credential_id: String,
+	salt: String,
+	device_path: Option<String>,
+) -> Result<HmacSecretOutput, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_device(
+		window,
+		"derive_hmac_secret",
+		device_path.clone(),
+		move || {
+			fido::hmac_secret::derive_hmac_secret(
+				device_path.as_deref(),
+				&pin,
+				&rp_id,
+				&credential_id,
+				&salt,
+			)
+		},
+	)
+	.await?
+}
+
+#[tauri::command]
+pub async fn get_openpgp_signature_counter(window: Window) -> Result<u32, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(
+		window,
+		"get_openpgp_signature_counter",
+		openpgp::read_signature_counter,
+	)
+	.await?
+}
+
+#[tauri::command]
+pub async fn get_openpgp_status(window: Window) -> Result<OpenPgpStatus, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "get_openpgp_status", openpgp::read_status).await?
+}
+
+/// Changes an OpenPGP PIN. For the Resetting Code, `current_pin` is the admin PIN.
+#[tauri::command]
+pub async fn change_openpgp_pin(
+	window: Window,
+	pin: OpenPgpPin,
+	current_pin: String,
+	new_pin: String,
+) -> Result<(), PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "change_openpgp_pin", move || {
+		openpgp::change_pin(pin, &current_pin, &new_pin)
+	})
+	.await?
+}
+
+#[tauri::command]
+pub async fn set_openpgp_cardholder(
+	window: Window,
+	admin_pin: String,
+	cardholder: OpenPgpCardholder,
+) -> Result<(), PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "set_openpgp_cardholder", move || {
+		openpgp::set_cardholder(&admin_pin, &cardholder)
+	})
+	.await?
+}
+
+/// Factory resets the OpenPGP applet. Fails unless `confirm` is set.
+#[tauri::command]
+pub async fn reset_openpgp(window: Window, confirm: bool) -> Result<(), PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "reset_openpgp", move || openpgp::reset(confirm)).await?
+}
+
+/// Generates a key pair in a PIV slot. `management_key` is hex, the factory default if `None`.
+#[tauri::command]
+pub async fn generate_piv_key(
+	window: Window,
+	management_key: Option<String>,
+	management_algorithm: Option<PivManagementKeyAlgorithm>,
+	slot: PivSlot,
+	algorithm: PivKeyAlgorithm,
+) -> Result<PivGeneratedKey, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "generate_piv_key", move || {
+		piv::generate_key(
+			management_key.as_deref(),
+			management_algorithm.unwrap_or_default(),
+			slot,
+			algorithm,
+		)
+	})
+	.await?
+}
+
+/// Stores a DER or PEM certificate in a PIV slot
+#[tauri::command]
+pub async fn import_piv_certificate(
+	window: Window,
+	management_key: Option<String>,
+	management_algorithm: Option<PivManagementKeyAlgorithm>,
+	slot: PivSlot,
+	certificate: Vec<u8>,
+) -> Result<(), PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "import_piv_certificate", move || {
+		piv::import_certificate(
+			management_key.as_deref(),
+			management_algorithm.unwrap_or_default(),
+			slot,
+			&certificate,
+		)
+	})
+	.await?
+}
+
+#[tauri::command]
+pub async fn export_piv_certificate(
+	window: Window,
+	slot: PivSlot,
+) -> Result<PivCertificate, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "export_piv_certificate", move || {
+		piv::export_certificate(slot)
+	})
+	.await?
+}
+
+#[tauri::command]
+pub async fn change_piv_pin(
+	window: Window,
+	pin: PivPin,
+	current_pin: String,
+	new_pin: String,
+) -> Result<(), PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "change_piv_pin", move || {
+		piv::change_pin(pin, &current_pin, &new_pin)
+	})
+	.await?
+}
+
+/// Shows `context` in a window of its own, see `permissions`
+#[tauri::command]
+pub async fn open_view(app: tauri::AppHandle, context: UiContext) -> Result<(), PFError> {
+	permissions::open_view(&app, context)
+}
+
+#[tauri::command]
+pub fn list_jobs(runner: tauri::State<'_, JobRunner>) -> Vec<JobInfo> {
+	runner.list()
+}
+
+#[tauri::command]
+pub fn resume_job(app: tauri::AppHandle, id: String) -> Result<(), PFError> {
+	jobs::resume_job(&app, &id)
+}
+
+#[tauri::command]
+pub fn current_operation(manager: tauri::State<'_, DeviceManager>) -> Option<OperationInfo> {
+	manager.current()
+}
+
+/// Page of the activity feed, newest first. Pass the ID of the oldest event shown as `before` to
+/// get the next page, and `kinds` to only get events of those kinds.
+#[tauri::command]
+pub fn get_activity(
+	before: Option<u64>,
+	limit: Option<usize>,
+	kinds: Option<Vec<ActivityKind>>,
+) -> ActivityPage {
+	activity::page(before, limit, &kinds.unwrap_or_default())
+}
+
+/// Asks the operations started by this window to stop. An operation waiting for a touch is
+/// aborted on the key, and fails with `Cancelled`.
+#[tauri::command]
+pub fn cancel_operation(
+	window: Window,
+	manager: tauri::State<'_, DeviceManager>,
+) -> Result<(), PFError> {
+	manager.request_cancel(window.label())
+}
+
+#[tauri::command]
+pub fn set_training_mode(enabled: bool, scenario: Option<TrainingScenario>) -> TrainingStatus {
+	simulator::set_training_mode(enabled, scenario.unwrap_or_default())
+}
+
+#[tauri::command]
+pub fn get_training_mode() -> TrainingStatus {
+	simulator::training_status()
+}
+
+/// Developer command for QA, only affects the training device.
+#[tauri::command]
+pub fn set_fault_injection(config: Option<FaultInjectionConfig>) {
+	simulator::set_fault_injection(config)
+}
+
+#[tauri::command]
+pub fn get_fault_injection() -> Option<FaultInjectionConfig> {
+	simulator::fault_injection()
+}
+
+/// Loads the firmware constants used to label vendor responses, from a pico-fido source tree
+/// if given, or else from the bundled snapshot closest to `firmware_version`.
+#[tauri::command]
+pub fn load_firmware_schema(
+	source_dir: Option<String>,
+	firmware_version: Option<String>,
+) -> Result<FirmwareSchema, PFError> {
+	let schema = match source_dir {
+		Some(dir) => schema::load_from_source_tree(&dir)?,
+		None => schema::load_bundled(firmware_version.as_deref()),
+	};
+	Ok(schema::set_active(schema))
+}
+
+#[tauri::command]
+pub fn get_firmware_schema() -> FirmwareSchema {
+	schema::active()
+}
+
+/// Labels a raw (hex encoded) CBOR response of the vendor command `command`
+#[tauri::command]
+pub fn label_vendor_response(
+	command: u8,
+	response: String,
+) -> Result<VendorResponseTrace, PFError> {
+	let response = hex::decode(response.trim()).map_err(|e| PFError::Io(e.to_string()))?;
+	schema::label_vendor_response(&schema::active(), command, &response)
+}
+
+/// Labels a raw (hex encoded) PHY configuration TLV blob
+#[tauri::command]
+pub fn label_phy_response(response: String) -> Result<Vec<LabeledField>, PFError> {
+	let response = hex::decode(response.trim()).map_err(|e| PFError::Io(e.to_string()))?;
+	schema::label_phy_tlv(&schema::active(), &response)
+}
+
+/// Maintenance notes and history of the device with the given serial number
+#[tauri::command]
+pub fn get_device_history(serial: String) -> Result<DeviceHistory, PFError> {
+	Ok(Inventory::load()?.history(&serial))
+}
+
+/// Serial numbers of all devices with recorded history or notes
+#[tauri::command]
+pub fn list_inventory_devices() -> Result<Vec<String>, PFError> {
+	Ok(Inventory::load()?.devices.into_keys().collect())
+}
+
+#[tauri::command]
+pub fn add_device_note(serial: String, text: String) -> Result<DeviceNote, PFError> {
+	Inventory::update(|inventory| Ok(inventory.add_note(&serial, text)))
+}
+
+#[tauri::command]
+pub fn delete_device_note(serial: String, note_id: String) -> Result<(), PFError> {
+	Inventory::update(|inventory| match inventory.delete_note(&serial, &note_id) {
+		true => Ok(()),
+		false => Err(PFError::Io(format!("Note {} not found", note_id))),
+	})
+}
+
+/// Intake check of the connected device as part of the batch `batch`. Returns the updated
+/// report of the batch.
+#[tauri::command]
+pub async fn intake_check(
+	window: Window,
+	batch: String,
+	expected_aaguid: Option<String>,
+	expected_firmware: Option<String>,
+	pin: Option<String>,
+) -> Result<IntakeReport, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "intake_check", move || {
+		intake::check_connected_device(&batch, expected_aaguid, expected_firmware, pin)
+	})
+	.await?
+}
+
+#[tauri::command]
+pub fn get_intake_report(batch: String) -> Result<IntakeReport, PFError> {
+	let inventory = Inventory::load()?;
+	let intake = inventory
+		.intake
+		.get(&batch)
+		.ok_or_else(|| PFError::Io(format!("Intake batch {} not found", batch)))?;
+	Ok(intake::evaluate(&batch, intake))
+}
+
+#[tauri::command]
+pub fn list_intake_batches() -> Result<Vec<String>, PFError> {
+	Ok(Inventory::load()?.intake.into_keys().collect())
+}
+
+#[tauri::command]
+pub fn get_reminder_settings() -> ReminderSettings {
+	reminders::settings()
+}
+
+#[tauri::command]
+pub fn set_reminder_settings(settings: ReminderSettings) -> Result<(), PFError> {
+	reminders::set_settings(&settings)
+}
+
+/// Audits overdue on the devices of the inventory
+#[tauri::command]
+pub fn list_due_audits() -> Result<Vec<AuditReminder>, PFError> {
+	Ok(reminders::due(
+		&Inventory::load()?,
+		&reminders::settings(),
+		inventory::now(),
+	))
+}
+
+/// Records that the audit of the device was done, restarting its reminder interval
+#[tauri::command]
+pub fn mark_audit_done(serial: String, kind: AuditKind) -> Result<(), PFError> {
+	reminders::mark_done(&serial, kind)
+}
+
+/// Starts the migration `name` from the connected old key, returning the checklist of accounts
+/// to re-register on the new key
+#[tauri::command]
+pub async fn start_migration(
+	window: Window,
+	name: String,
+	pin: String,
+	device_path: Option<String>,
+) -> Result<Migration, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "start_migration", move || {
+		migration::start(&name, device_path.as_deref(), pin)
+	})
+	.await?
+}
+
+/// Ticks off the accounts of the migration `name` found on the connected new key
+#[tauri::command]
+pub async fn check_migration_target(
+	window: Window,
+	name: String,
+	pin: String,
+	device_path: Option<String>,
+) -> Result<Migration, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "check_migration_target", move || {
+		migration::check_target(&name, device_path.as_deref(), pin)
+	})
+	.await?
+}
+
+#[tauri::command]
+pub fn set_migration_item_done(
+	name: String,
+	rp_id: String,
+	user_name: String,
+	done: bool,
+) -> Result<Migration, PFError> {
+	migration::set_done(&name, &rp_id, &user_name, done)
+}
+
+#[tauri::command]
+pub fn get_migration(name: String) -> Result<Migration, PFError> {
+	Inventory::load()?
+		.migrations
+		.remove(&name)
+		.ok_or_else(|| PFError::Io(format!("Migration {} not found", name)))
+}
+
+#[tauri::command]
+pub fn list_migrations() -> Result<Vec<String>, PFError> {
+	Ok(Inventory::load()?.migrations.into_keys().collect())
+}
+
+#[tauri::command]
+pub fn delete_migration(name: String) -> Result<bool, PFError> {
+	Inventory::update(|inventory| Ok(inventory.migrations.remove(&name).is_some()))
+}
+
+/// Devices reachable through the Rescue Applet on all readers
+#[tauri::command]
+pub async fn list_rescue_devices(window: Window) -> Result<Vec<RescueDevice>, PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "list_rescue_devices", rescue::list_devices).await?
+}
+
+/// Starts a background job flashing `uf2_path` onto the given devices, or onto every connected
+/// device if `serials` is not given. Returns the job ID.
+#[tauri::command]
+pub async fn start_firmware_update(
+	app: tauri::AppHandle,
+	window: Window,
+	uf2_path: String,
+	expected_version: Option<String>,
+	serials: Option<Vec<String>>,
+	override_quarantine: Option<bool>,
+) -> Result<String, PFError> {
+	simulator::ensure_inactive()?;
+	// Fail early on a bad image rather than after rebooting the first device
+	firmware::read_uf2(&uf2_path)?;
+
+	let owner = window.label().to_string();
+	let targets = match serials {
+		Some(serials) => serials,
+		None => run_for_window(window, "list_rescue_devices", rescue::list_devices)
+			.await??
+			.into_iter()
+			.map(|d| d.serial)
+			.collect(),
+	};
+	if targets.is_empty() {
+		return Err(PFError::NoDevice);
+	}
+
+	let label = format!("Firmware update of {} device(s)", targets.len());
+	let params = FirmwareUpdateParams {
+		uf2_path,
+		expected_version,
+		override_quarantine: override_quarantine.unwrap_or(false),
+	};
+	jobs::spawn_batch_job(
+		&app,
+		&owner,
+		JobKind::FirmwareFlash,
+		label,
+		targets,
+		serde_json::to_value(params).map_err(|e| PFError::Io(e.to_string()))?,
+	)
+}
+
+/// Checks GitHub for a pico-fido release newer than the firmware of the device.
+///
+/// `board` picks the image of the release, e.g. "pico2". Without it the board is guessed from
+/// the USB product name of the device.
+#[tauri::command]
+pub async fn check_firmware_update(
+	window: Window,
+	board: Option<String>,
+	device_path: Option<String>,
+) -> Result<UpdateStatus, PFError> {
+	let status = read_device_details(window, device_path, None).await?;
+	updates::check(&status, board.as_deref()).await
+}
+
+/// Flashes a single FIDO key with the UF2 image at `uf2_path`. Progress reports the copy.
+#[tauri::command]
+pub async fn flash_firmware(
+	window: Window,
+	uf2_path: String,
+	override_quarantine: Option<bool>,
+	device_path: Option<String>,
+) -> Result<String, PFError> {
+	simulator::ensure_inactive()?;
+	// Fail early on a bad image rather than after rebooting the device
+	firmware::read_uf2(&uf2_path)?;
+	run_for_window(window, "flash_firmware", move || {
+		check_quarantine(
+			"flash_firmware",
+			device_path.as_deref(),
+			override_quarantine,
+		)?;
+		firmware::flash_fido_device(device_path.as_deref(), &uf2_path, |written, total| {
+			operation::report_progress(
+				format!(
+					"Copying firmware ({} of {} KiB)",
+					written / 1024,
+					total / 1024
+				),
+				written,
+				total,
+			)
+		})
+	})
+	.await?
+}
+
+#[tauri::command]
+pub fn list_quarantined_devices() -> Result<Vec<QuarantineEntry>, PFError> {
+	Ok(Inventory::load()?.quarantine.into_values().collect())
+}
+
+/// Quarantines a device suspected to be compromised. Provisioning and credential operations on
+/// it are refused until it is released or the user overrides the check.
+#[tauri::command]
+pub fn quarantine_device(serial: String, reason: String) -> Result<QuarantineEntry, PFError> {
+	let entry = QuarantineEntry {
+		serial: serial.clone(),
+		reason,
+		since: inventory::now(),
+	};
+	Inventory::update(|inventory| {
+		inventory.quarantine.insert(serial, entry.clone());
+		Ok(entry)
+	})
+}
+
+#[tauri::command]
+pub fn release_device(serial: String) -> Result<(), PFError> {
+	Inventory::update(|inventory| match inventory.quarantine.remove(&serial) {
+		Some(_) => Ok(()),
+		None => Err(PFError::Io(format!("Device {} is not quarantined", serial))),
+	})
+}
+
+#[tauri::command]
+pub fn get_session_policy(session: tauri::State<'_, SessionManager>) -> SessionPolicy {
+	session.policy()
+}
+
+#[tauri::command]
+pub fn set_session_policy(
+	session: tauri::State<'_, SessionManager>,
+	policy: SessionPolicy,
+) -> Result<(), PFError> {
+	session.set_policy(policy)
+}
+
+#[tauri::command]
+pub fn get_log_retention() -> LogRetention {
+	logging::retention()
+}
+
+/// Saves the log retention settings, applied to the running logger right away
+#[tauri::command]
+pub fn set_log_retention(retention: LogRetention) -> Result<(), PFError> {
+	logging::set_retention(retention)
+}
+
+/// The most recent log entries, oldest first, for the in-app log viewer
+#[tauri::command]
+pub fn get_recent_logs(limit: Option<usize>) -> Vec<LogEntry> {
+	logging::recent(limit)
+}
+
+/// Writes the recent log entries to a text file, to attach to a bug report
+#[tauri::command]
+pub fn export_logs(path: String) -> Result<LogExportReport, PFError> {
+	logging::export(Path::new(&path))
+}
+
+/// Deletes all log files, e.g. after sending them to support
+#[tauri::command]
+pub fn purge_logs() -> Result<LogPurgeReport, PFError> {
+	logging::purge_logs()
+}
+
+/// Locks the session right away
+#[tauri::command]
+pub fn lock_session(app: tauri::AppHandle, session: tauri::State<'_, SessionManager>) {
+	session.lock(&app, SessionLockReason::Manual)
+}
+
+/// Unlocks developer mode for this session once the user touches the key
+#[tauri::command]
+pub async fn unlock_developer_mode(window: Window) -> Result<(), PFError> {
+	simulator::ensure_inactive()?;
+	run_for_window(window, "unlock_developer_mode", devmode::unlock).await?
+}
+
+#[tauri::command]
+pub fn lock_developer_mode() {
+	devmode::lock()
+}
+
+#[tauri::command]
+pub fn get_developer_mode() -> bool {
+	devmode::is_unlocked()
+}
+
+/// Developer console: sends a hex encoded APDU as is to the card of the key at `device_path`
+#[tauri::command]
+pub async fn send_raw_apdu(
+	window: Window,
+	apdu: String,
+	device_path: Option<String>,
+) -> Result<RawApduResponse, PFError> {
+	devmode::ensure_unlocked()?;
+	simulator::ensure_inactive()?;
+	let apdu = hex::decode(apdu.replace(' ', "")).map_err(|e| PFError::Io(e.to_string()))?;
+	run_for_device(window, "send_raw_apdu", device_path.clone(), move || {
+		log::debug!("Raw APDU: {}", hex::encode_upper(&apdu));
+		let card = card::connect_device(device_path.as_deref())?;
+		let resp = card::transmit(&card, &apdu)?;
+		Ok(RawApduResponse {
+			data: hex::encode_upper(&resp.data),
+			sw: resp.sw,
+		})
+	})
+	.await?
+}
+
+/// Developer console: sends the vendor command `cmd_byte` with the hex encoded CBOR parameters
+/// `cbor_hex`, which may be empty
+#[tauri::command]
+pub async fn send_raw_vendor(
+	window: Window,
+	cmd_byte: u8,
+	cbor_hex: String,
+	device_path: Option<String>,
+) -> Result<RawVendorResponse, PFError> {
+	devmode::ensure_unlocked()?;
+	simulator::ensure_inactive()?;
+	let cbor = hex::decode(cbor_hex.replace(' ', "")).map_err(|e| PFError::Io(e.to_string()))?;
+	run_for_device(window, "send_raw_vendor", device_path.clone(), move || {
+		log::debug!(
+			"Raw vendor command 0x{:02X}: {}",
+			cmd_byte,
+			hex::encode_upper(&cbor)
+		);
+		fido::send_raw_vendor(device_path.as_deref(), cmd_byte, &cbor)
+	})
+	.await?
+}
+
+/// Wipes the FIDO application and provisions it again with `new_pin` and the profile.
+///
+/// A failed run reports the step it stopped at, pass it as `resume_from` to continue from there.
+#[tauri::command]
+pub async fn reprovision(
+	window: Window,
+	profile: ReprovisionProfile,
+	new_pin: String,
+	resume_from: Option<ReprovisionStep>,
+	override_quarantine: Option<bool>,
+	device_path: Option<String>,
+) -> Result<ReprovisionReport, PFError> {
+	simulator::ensure_inactive()?;
+	// The key is wiped before the PIN is set, so a PIN it or the organisation refuses has to be
+	// caught here. A reset key requires at least 4 code points.
+	let min_pin_length = profile.min_pin_length.map_or(4, |min| (min as u32).max(4));
+	if let Some(violation) = fido::pin_policy::violation(
+		&new_pin,
+		None,
+		min_pin_length,
+		&fido::pin_policy::org_policy(),
+	) {
+		log::info!("New PIN refused: {}", violation);
+		return Err(PFError::PinPolicyViolation(violation));
+	}
+	run_for_device(window, "reprovision", device_path.clone(), move || {
+		if device_path.is_none() && fido::list_devices().is_ok_and(|devices| devices.len() > 1) {
+			return Err(PFError::Device(
+				"Several keys are connected, select the one to reprovision".into(),
+			));
+		}
+		check_quarantine("reprovision", device_path.as_deref(), override_quarantine)?;
+		let serial = fido::read_device_details(device_path.as_deref())
+			.ok()
+			.map(|status| status.info.serial);
+		Ok(reprovision::reprovision(
+			device_path.as_deref(),
+			serial.as_deref(),
+			&profile,
+			&new_pin,
+			resume_from,
+		))
+	})
+	.await?
 }
@@ -0,0 +1,309 @@
+//! Background runner for batch operations over many devices, such as flashing firmware.
+//!
+//! Jobs run on the blocking thread pool. Once a job completes or fails, the registered
+//! notification channels are informed, since operators tend to walk away during long runs.
+//!
+//! The job list is persisted to disk after every state change. Batch jobs record each completed
+//! device, so a run interrupted by a crash or reboot can be resumed from the next device.
+
+use crate::{
+	error::PFError,
+	inventory::now,
+	manager::DeviceManager,
+	notify::Notifier,
+	operation, paths, storage,
+	types::{JobInfo, JobKind, JobStatus, TargetResult},
+};
+use rand::Rng;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager};
+
+const JOBS_FILE: &str = "jobs.json";
+
+/// Owner used for device operations of jobs not started from a window
+const BACKGROUND_OWNER: &str = "background";
+
+/// Processes one device of a batch job, given the job parameters and the device identifier.
+/// Returns a short summary of what was done to the device.
+pub type TargetStep = fn(&serde_json::Value, &str) -> Result<String, PFError>;
+
+/// How batch jobs of one kind are run
+#[derive(Clone, Copy)]
+pub struct BatchHandler {
+	pub step: TargetStep,
+	/// Abort the job on the first failed device instead of moving on to the next one
+	pub stop_on_error: bool,
+}
+
+#[derive(Default)]
+pub struct JobRunner {
+	jobs: Mutex<Vec<JobInfo>>,
+	notifier: Notifier,
+	handlers: HashMap<JobKind, BatchHandler>,
+}
+
+fn jobs_path() -> PathBuf {
+	paths::data_dir().join(JOBS_FILE)
+}
+
+fn new_job_id() -> String {
+	let id_bytes: [u8; 8] = rand::rng().random();
+	hex::encode(id_bytes)
+}
+
+impl JobRunner {
+	/// Restores the persisted job list. Jobs that were still queued or running are marked as
+	/// interrupted.
+	pub fn load() -> Self {
+		let mut jobs: Vec<JobInfo> = match storage::read(&jobs_path()) {
+			Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+				log::error!("Job list is corrupted, starting with an empty one: {}", e);
+				Vec::new()
+			}),
+			Err(_) => Vec::new(),
+		};
+
+		for job in jobs
+			.iter_mut()
+			.filter(|j| matches!(j.status, JobStatus::Queued | JobStatus::Running))
+		{
+			log::warn!(
+				"Job {} ({}) was interrupted after {}/{} device(s)",
+				job.id,
+				job.label,
+				job.completed_targets.len(),
+				job.targets.len()
+			);
+			job.status = JobStatus::Interrupted;
+		}
+
+		let runner = Self {
+			jobs: Mutex::new(jobs),
+			..Default::default()
+		};
+		runner.persist();
+		runner
+	}
+
+	/// Registers the handler used to run and resume batch jobs of `kind`
+	pub fn with_handler(mut self, kind: JobKind, handler: BatchHandler) -> Self {
+		self.handlers.insert(kind, handler);
+		self
+	}
+
+	pub fn list(&self) -> Vec<JobInfo> {
+		self.jobs
+			.lock()
+			.map(|jobs| jobs.clone())
+			.unwrap_or_default()
+	}
+
+	fn persist(&self) {
+		let Ok(jobs) = self.jobs.lock() else {
+			return;
+		};
+		let result = serde_json::to_vec_pretty(&*jobs)
+			.map_err(|e| e.to_string())
+			.and_then(|data| storage::write(&jobs_path(), &data).map_err(|e| e.to_string()));
+		if let Err(e) = result {
+			log::error!("Failed to persist job list: {}", e);
+		}
+	}
+
+	fn insert(&self, job: JobInfo) {
+		if let Ok(mut jobs) = self.jobs.lock() {
+			jobs.push(job);
+		}
+		self.persist();
+	}
+
+	fn update(&self, id: &str, f: impl FnOnce(&mut JobInfo)) -> Option<JobInfo> {
+		let updated = {
+			let mut jobs = self.jobs.lock().ok()?;
+			let job = jobs.iter_mut().find(|j| j.id == id)?;
+			f(job);
+			job.clone()
+		};
+		self.persist();
+		Some(updated)
+	}
+
+	fn finish(&self, app: &AppHandle, id: &str, result: Result<String, PFError>) {
+		let finished = self.update(id, |job| {
+			job.finished_at = Some(now());
+			match result {
+				Ok(msg) => {
+					job.status = JobStatus::Completed;
+					job.message = Some(msg);
+				}
+				Err(e) => {
+					job.status = JobStatus::Failed;
+					job.message = Some(e.to_string());
+				}
+			}
+		});
+
+		if let Some(job) = finished {
+			log::info!("Job {} finished: {:?}", job.id, job.status);
+			self.notifier.dispatch(app, &job);
+		}
+	}
+}
+
+/// Starts a batch job running the registered step of `kind` on every target in order.
+///
+/// Device access and progress reporting are owned by the window `owner`.
+pub fn spawn_batch_job(
+	app: &AppHandle,
+	owner: &str,
+	kind: JobKind,
+	label: String,
+	targets: Vec<String>,
+	params: serde_json::Value,
+) -> Result<String, PFError> {
+	let runner = app.state::<JobRunner>();
+	if !runner.handlers.contains_key(&kind) {
+		return Err(PFError::Io(format!("No batch handler for {:?} jobs", kind)));
+	}
+
+	let id = new_job_id();
+	runner.insert(JobInfo {
+		id: id.clone(),
+		kind,
+		label,
+		status: JobStatus::Queued,
+		owner: Some(owner.to_string()),
+		message: None,
+		targets,
+		completed_targets: Vec::new(),
+		results: Vec::new(),
+		params,
+		created_at: now(),
+		finished_at: None,
+	});
+
+	run_batch(app.clone(), id.clone());
+	Ok(id)
+}
+
+/// Resumes an interrupted or failed batch job from the first device that did not complete.
+pub fn resume_job(app: &AppHandle, id: &str) -> Result<(), PFError> {
+	let runner = app.state::<JobRunner>();
+	let job = runner
+		.list()
+		.into_iter()
+		.find(|j| j.id == id)
+		.ok_or_else(|| PFError::Io(format!("Job {} not found", id)))?;
+
+	if !matches!(job.status, JobStatus::Interrupted | JobStatus::Failed) {
+		return Err(PFError::Io(format!(
+			"Job {} is {:?} and cannot be resumed",
+			id, job.status
+		)));
+	}
+	if job.targets.is_empty() || !runner.handlers.contains_key(&job.kind) {
+		return Err(PFError::Io(format!("Job {} cannot be resumed", id)));
+	}
+
+	log::info!(
+		"Resuming job {} after {}/{} device(s)",
+		id,
+		job.completed_targets.len(),
+		job.targets.len()
+	);
+	runner.update(id, |job| {
+		job.status = JobStatus::Queued;
+		job.message = None;
+		job.finished_at = None;
+	});
+
+	run_batch(app.clone(), id.to_string());
+	Ok(())
+}
+
+fn run_batch(app: AppHandle, id: String) {
+	tauri::async_runtime::spawn_blocking(move || {
+		let runner = app.state::<JobRunner>();
+		let Some(job) = runner.update(&id, |job| job.status = JobStatus::Running) else {
+			return;
+		};
+		let Some(handler) = runner.handlers.get(&job.kind).copied() else {
+			return;
+		};
+
+		let manager = app.state::<DeviceManager>();
+		let owner = job.owner.as_deref().unwrap_or(BACKGROUND_OWNER);
+		let total = job.targets.len() as u32;
+
+		let pending = job
+			.targets
+			.iter()
+			.enumerate()
+			.filter(|(_, t)| !job.completed_targets.contains(t));
+
+		let mut failed = 0;
+		for (index, target) in pending {
+			let (result, cancelled) = manager
+				.run(&app, owner, &job.label, None, || {
+					operation::report_progress(
+						format!("Processing {}", target),
+						index as u32,
+						total,
+					);
+					log::info!("Job {}: processing {}", id, target);
+					let result = (handler.step)(&job.params, target);
+					(result, manager.is_cancelled())
+				})
+				.unwrap_or_else(|busy| (Err(busy), false));
+
+			// A later run replaces the result of an earlier attempt on the same device
+			let record = |job: &mut JobInfo, success: bool, message: String| {
+				job.results.retain(|r| &r.target != target);
+				job.results.push(TargetResult {
+					target: target.clone(),
+					success,
+					message,
+				});
+			};
+
+			match result {
+				Ok(summary) => {
+					runner.update(&id, |job| {
+						job.completed_targets.push(target.clone());
+						record(job, true, summary);
+					});
+				}
+				Err(e) => {
+					log::error!("Job {}: {} failed: {}", id, target, e);
+					runner.update(&id, |job| record(job, false, e.to_string()));
+					if handler.stop_on_error {
+						runner.finish(
+							&app,
+							&id,
+							Err(PFError::Device(format!("{}: {}", target, e))),
+						);
+						return;
+					}
+					failed += 1;
+				}
+			}
+
+			// Cancellation is requested while a step holds the device, stop before the next one
+			if cancelled {
+				log::info!("Job {} cancelled after {}", id, target);
+				runner.finish(&app, &id, Err(PFError::Io("Cancelled by user".into())));
+				return;
+			}
+		}
+
+		if failed > 0 {
+			let msg = format!("{} of {} device(s) failed", failed, job.targets.len());
+			runner.finish(&app, &id, Err(PFError::Device(msg)));
+			return;
+		}
+		let msg = format!("{} device(s) processed", job.targets.len());
+		runner.finish(&app, &id, Ok(msg));
+	});
+}
@@ -1,11 +1,77 @@
 use serde::Serialize;
 
+mod activity;
+#[cfg(any(feature = "gui", test))]
+mod api;
+mod card;
+pub mod cli;
+#[cfg(feature = "gui")]
+mod cli_export;
+#[cfg(feature = "gui")]
+mod commissioning;
+mod config_schema;
+#[cfg(feature = "gui")]
+mod credential_export;
+#[cfg(any(feature = "gui", test))]
+mod csv;
+#[cfg(feature = "gui")]
+mod device_state;
+#[cfg(feature = "gui")]
+mod devmode;
 mod error;
 mod fido;
+mod firmware;
+#[cfg(all(test, feature = "hardware-tests"))]
+mod hardware_tests;
+#[cfg(feature = "gui")]
+mod hsm;
+#[cfg(feature = "gui")]
+mod intake;
+mod inventory;
+#[cfg(feature = "gui")]
 mod io;
+#[cfg(feature = "gui")]
+mod jobs;
 mod logging;
+#[cfg(feature = "gui")]
+mod manager;
+#[cfg(feature = "gui")]
+mod migration;
+#[cfg(feature = "gui")]
+mod notify;
+#[cfg(feature = "gui")]
+mod oath;
+#[cfg(feature = "gui")]
+mod openpgp;
+mod operation;
+mod paths;
+#[cfg(any(feature = "gui", test))]
+mod permissions;
+#[cfg(feature = "gui")]
+mod piv;
+mod profiles;
+#[cfg(feature = "gui")]
+mod reminders;
+#[cfg(feature = "gui")]
+mod reporting;
+#[cfg(feature = "gui")]
+mod reprovision;
 mod rescue;
+#[cfg(feature = "gui")]
+mod rng;
+mod schema;
+#[cfg(feature = "gui")]
+mod session;
+#[cfg(feature = "gui")]
+mod settings_backup;
+#[cfg(feature = "gui")]
+mod simulator;
+mod storage;
 mod types;
+#[cfg(feature = "gui")]
+mod updates;
+#[cfg(feature = "gui")]
+mod workspaces;
 
 // This will be temporary here untill moved to a dedicated module:
 
@@ -15,25 +81,194 @@ pub struct WindowState {
 	pub is_maximized: bool,
 }
 
+/// Every command of the app, see `permissions` for the windows that may invoke each
+#[cfg(feature = "gui")]
+fn commands() -> impl Fn(tauri::ipc::Invoke) -> bool + Send + Sync + 'static {
+	tauri::generate_handler![
+		io::list_devices,
+		io::read_device_details,
+		io::get_device_state,
+		io::get_vendor_capabilities,
+		io::get_attestation_info,
+		io::get_enterprise_attestation,
+		io::enable_enterprise_attestation,
+		io::generate_enterprise_attestation_csr,
+		io::upload_enterprise_attestation_certificate,
+		io::run_rng_diagnostic,
+		io::write_config,
+		io::save_config_profile,
+		io::load_config_profile,
+		io::apply_config_profile,
+		io::validate_led_config,
+		io::test_led,
+		io::start_commissioning,
+		io::stop_commissioning,
+		io::get_commissioning_session,
+		io::export_commissioning_log,
+		io::generate_commissioning_report,
+		io::verify_commissioning_report,
+		io::get_saved_device_config,
+		io::export_config_as_cli,
+		io::get_fido_info,
+		io::get_pin_status,
+		io::change_fido_pin,
+		io::get_api_version,
+		io::get_credentials,
+		io::delete_credential,
+		io::update_credential,
+		io::export_credentials,
+		io::compare_credential_export,
+		io::reset_device,
+		io::get_bio_sensor_info,
+		io::enroll_fingerprint,
+		io::list_fingerprints,
+		io::rename_fingerprint,
+		io::delete_fingerprint,
+		io::reprovision,
+		io::match_registration,
+		io::get_pin_policy,
+		io::get_org_pin_policy,
+		io::set_org_pin_policy,
+		io::set_min_pin_length,
+		io::force_pin_change,
+		io::set_always_uv,
+		io::enable_secure_boot,
+		io::enable_secure_lock,
+		io::reboot,
+		io::check_totp_drift,
+		io::list_oath_accounts,
+		io::parse_otpauth_uri,
+		io::add_oath_account,
+		io::delete_oath_account,
+		io::calculate_oath_codes,
+		io::calculate_oath_code,
+		io::set_oath_password,
+		io::list_workspaces,
+		io::save_workspace,
+		io::delete_workspace,
+		io::list_secure_notes,
+		io::add_secure_note,
+		io::delete_secure_note,
+		io::export_settings_backup,
+		io::restore_settings_backup,
+		io::create_device_backup,
+		io::restore_device_backup,
+		io::sign_ssh_certificate,
+		io::generate_self_signed_certificate,
+		io::get_credential_sign_count,
+		io::verify_credential,
+		io::derive_hmac_secret,
+		io::run_self_test,
+		io::get_openpgp_signature_counter,
+		io::get_openpgp_status,
+		io::change_openpgp_pin,
+		io::set_openpgp_cardholder,
+		io::reset_openpgp,
+		io::generate_piv_key,
+		io::import_piv_certificate,
+		io::export_piv_certificate,
+		io::change_piv_pin,
+		io::open_view,
+		io::list_jobs,
+		io::resume_job,
+		io::current_operation,
+		io::get_activity,
+		io::cancel_operation,
+		io::set_training_mode,
+		io::get_training_mode,
+		io::set_fault_injection,
+		io::get_fault_injection,
+		io::load_firmware_schema,
+		io::get_firmware_schema,
+		io::label_vendor_response,
+		io::label_phy_response,
+		io::get_device_history,
+		io::list_inventory_devices,
+		io::add_device_note,
+		io::delete_device_note,
+		io::intake_check,
+		io::get_intake_report,
+		io::list_intake_batches,
+		io::get_reminder_settings,
+		io::set_reminder_settings,
+		io::list_due_audits,
+		io::mark_audit_done,
+		io::start_migration,
+		io::check_migration_target,
+		io::set_migration_item_done,
+		io::get_migration,
+		io::list_migrations,
+		io::delete_migration,
+		io::list_rescue_devices,
+		io::start_firmware_update,
+		io::flash_firmware,
+		io::check_firmware_update,
+		io::list_quarantined_devices,
+		io::quarantine_device,
+		io::release_device,
+		io::get_session_policy,
+		io::set_session_policy,
+		io::lock_session,
+		io::get_log_retention,
+		io::set_log_retention,
+		io::get_recent_logs,
+		io::export_logs,
+		io::purge_logs,
+		io::unlock_developer_mode,
+		io::lock_developer_mode,
+		io::get_developer_mode,
+		io::send_raw_apdu,
+		io::send_raw_vendor
+	]
+}
+
+#[cfg(feature = "gui")]
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+	use tauri::Emitter;
+
 	logging::logger_init();
+	storage::recover();
 	log::info!("Initialisng PicoForge...");
 
+	let handler = commands();
 	tauri::Builder::default()
 		.plugin(tauri_plugin_shell::init())
 		.plugin(tauri_plugin_opener::init())
-		.invoke_handler(tauri::generate_handler![
-			io::read_device_details,
-			io::write_config,
-			io::get_fido_info,
-			io::change_fido_pin,
-			io::get_credentials,
-			io::delete_credential,
-			io::set_min_pin_length,
-			io::enable_secure_boot,
-			io::reboot
-		])
+		.plugin(tauri_plugin_notification::init())
+		.manage(jobs::JobRunner::load().with_handler(
+			types::JobKind::FirmwareFlash,
+			jobs::BatchHandler {
+				step: firmware::update_device,
+				stop_on_error: false,
+			},
+		))
+		.manage(manager::DeviceManager::default())
+		.manage(session::SessionManager::load())
+		.manage(commissioning::Commissioning::load())
+		.setup(|app| {
+			let handle = app.handle().clone();
+			activity::start(move |event| {
+				let _ = handle.emit(activity::ACTIVITY_EVENT, event);
+			});
+			session::start_watcher(app.handle().clone());
+			let connected = app.handle().clone();
+			let removed = app.handle().clone();
+			fido::watcher::start(
+				move |device, at_startup| {
+					let _ = connected.emit(fido::watcher::DEVICE_CONNECTED_EVENT, device);
+					if !at_startup {
+						commissioning::device_connected(&connected, device);
+					}
+				},
+				move |device| {
+					let _ = removed.emit(fido::watcher::DEVICE_REMOVED_EVENT, device);
+				},
+			);
+			reminders::start(app.handle().clone());
+			Ok(())
+		})
+		.invoke_handler(move |invoke| permissions::check(invoke, &handler))
 		.run(tauri::generate_context!())
 		.expect("error while running tauri application");
 }
@@ -1,59 +1,252 @@
-use log::LevelFilter;
+//! Logging to the console, a rotating file under the data dir and an in-memory buffer of the
+//! recent entries, which users can export to attach to bug reports.
+//!
+//! Entries logged while a device operation runs are tagged with the operation and the device it
+//! targets, see `enter`. Raw CTAP messages are logged at trace level, with PIN material redacted
+//! by the transports.
+
+use crate::{
+    activity::{self, ActivityDetail},
+    inventory, paths, storage,
+    types::{ActivityKind, LogEntry, LogRetention},
+};
+#[cfg(feature = "gui")]
+use crate::{
+    error::PFError,
+    types::{LogExportReport, LogPurgeReport},
+};
+use log::{Level, LevelFilter, Record};
 use log4rs::{
     append::{
         console::{ConsoleAppender, Target},
+        Append,
         rolling_file::{
             policy::compound::{
-                roll::delete::DeleteRoller, trigger::size::SizeTrigger, CompoundPolicy,
+                roll::{delete::DeleteRoller, fixed_window::FixedWindowRoller, Roll},
+                trigger::size::SizeTrigger,
+                CompoundPolicy,
             },
             RollingFileAppender,
         },
     },
     config::{Appender, Logger, Root},
-    encode::pattern::PatternEncoder,
+    encode::{self, pattern::PatternEncoder, Encode},
+    filter::threshold::ThresholdFilter,
+    Config, Handle,
 };
+use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::fs;
-use directories::ProjectDirs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
 
-/// Initializes log4rs with custom configuration for stdout and file logging.
-pub fn logger_init() {
-    let qual = "in";
-    let org = "suyogtandel";
-    let app = "picoforge";
-
-    // Determine the log file path using ProjectDirs for cross-platform compatibility
-    let log_file_path = {
-        let log_dir = if let Some(proj_dirs) = ProjectDirs::from(qual, org, app) {
-            proj_dirs.data_local_dir().join("logs")
-        } else {
-            eprintln!("Could not determine project directories. Falling back to local directory.");
-            std::path::PathBuf::from("logs")
+const LOG_FILE: &str = "picoforge.log";
+/// Rotated log files, `{}` being replaced by the index (0 is the newest)
+const ARCHIVE_PATTERN: &str = "picoforge.{}.log";
+const RETENTION_FILE: &str = "log_retention.json";
+
+/// Entries kept in memory for `recent`
+const BUFFER_CAPACITY: usize = 2000;
+
+static HANDLE: OnceLock<Handle> = OnceLock::new();
+static BUFFER: Mutex<VecDeque<LogEntry>> = Mutex::new(VecDeque::new());
+/// Set by the CLI, whose stdout carries the command output
+static CONSOLE_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Operation and device the entries logged on a thread are tagged with
+#[derive(Clone)]
+struct Context {
+    operation: String,
+    device: Option<String>,
+}
+
+thread_local! {
+    static CONTEXT: RefCell<Option<Context>> = const { RefCell::new(None) };
+}
+
+/// Restores the previous context of the thread when dropped
+pub struct ContextGuard(Option<Context>);
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        CONTEXT.with(|c| *c.borrow_mut() = previous);
+    }
+}
+
+/// Tags the entries logged on this thread with `operation` and `device` until the guard drops
+pub fn enter(operation: &str, device: Option<&str>) -> ContextGuard {
+    let context = Context {
+        operation: operation.to_string(),
+        device: device.map(str::to_string),
+    };
+    ContextGuard(CONTEXT.with(|c| c.borrow_mut().replace(context)))
+}
+
+fn context() -> Option<Context> {
+    CONTEXT.with(|c| c.borrow().clone())
+}
+
+/// Puts the operation and device in front of the message of each record
+#[derive(Debug)]
+struct TaggedEncoder(PatternEncoder);
+
+impl TaggedEncoder {
+    fn new(pattern: &str) -> Self {
+        Self(PatternEncoder::new(pattern))
+    }
+}
+
+impl Encode for TaggedEncoder {
+    fn encode(&self, w: &mut dyn encode::Write, record: &Record) -> anyhow::Result<()> {
+        let Some(context) = context() else {
+            return self.0.encode(w, record);
+        };
+        let tag = match &context.device {
+            Some(device) => format!("[{} {}] ", context.operation, device),
+            None => format!("[{}] ", context.operation),
         };
+        self.0.encode(
+            w,
+            &Record::builder()
+                .args(format_args!("{}{}", tag, record.args()))
+                .level(record.level())
+                .target(record.target())
+                .module_path(record.module_path())
+                .file(record.file())
+                .line(record.line())
+                .build(),
+        )
+    }
+}
+
+/// Keeps the most recent entries in memory
+#[derive(Debug)]
+struct BufferAppender;
 
-        if let Err(e) = fs::create_dir_all(&log_dir) {
-            eprintln!("Failed to create log directory at {:?}: {}", log_dir, e);
+impl Append for BufferAppender {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        let context = context();
+        let entry = LogEntry {
+            timestamp: inventory::now(),
+            level: record.level().to_string(),
+            target: record.target().to_string(),
+            operation: context.as_ref().map(|c| c.operation.clone()),
+            device: context.and_then(|c| c.device),
+            message: record.args().to_string(),
+        };
+        let mut buffer = BUFFER.lock().unwrap_or_else(|p| p.into_inner());
+        if buffer.len() == BUFFER_CAPACITY {
+            buffer.pop_front();
         }
+        buffer.push_back(entry);
+        Ok(())
+    }
+
+    fn flush(&self) {}
+}
+
+/// The most recent entries, oldest first, at most `limit` of them
+#[cfg(feature = "gui")]
+pub fn recent(limit: Option<usize>) -> Vec<LogEntry> {
+    let buffer = BUFFER.lock().unwrap_or_else(|p| p.into_inner());
+    let skip = limit.map_or(0, |limit| buffer.len().saturating_sub(limit));
+    buffer.iter().skip(skip).cloned().collect()
+}
 
-        log_dir.join("picoforge.log")
+#[cfg(feature = "gui")]
+fn format_entry(entry: &LogEntry) -> String {
+    let tag = match (&entry.operation, &entry.device) {
+        (Some(operation), Some(device)) => format!("[{} {}] ", operation, device),
+        (Some(operation), None) => format!("[{}] ", operation),
+        _ => String::new(),
     };
+    format!(
+        "[{} {} {}] {}{}",
+        entry.timestamp, entry.level, entry.target, tag, entry.message
+    )
+}
+
+/// Writes the entries in memory to `path` as text, for attaching to a bug report
+#[cfg(feature = "gui")]
+pub fn export(path: &Path) -> Result<LogExportReport, PFError> {
+    let entries = recent(None);
+    let mut text = String::new();
+    for entry in &entries {
+        text.push_str(&format_entry(entry));
+        text.push('\n');
+    }
+    storage::write(path, text.as_bytes())
+        .map_err(|e| PFError::Io(format!("Failed to export the log: {}", e)))?;
+    log::info!("Exported {} log entries to {:?}", entries.len(), path);
+    Ok(LogExportReport {
+        path: path.to_string_lossy().into_owned(),
+        entries: entries.len(),
+    })
+}
 
-    // TODO: Add session based log files or rolling log files with archiving of old files, to prevent a single log file from growing too large.
-    let size_trigger = SizeTrigger::new(10 * 1024 * 1024); // 10 MB limit
-    let roller = DeleteRoller::new();
-    let policy = CompoundPolicy::new(Box::new(size_trigger), Box::new(roller));
+fn log_dir() -> PathBuf {
+    let log_dir = paths::data_dir().join("logs");
+    if let Err(e) = fs::create_dir_all(&log_dir) {
+        eprintln!("Failed to create log directory at {:?}: {}", log_dir, e);
+    }
+    log_dir
+}
 
-    // File Appender
-    let logfile = RollingFileAppender::builder()
-        .encoder(Box::new(PatternEncoder::new(
-            "[{d(%Y-%m-%d %H:%M:%S %Z)} {l} {t}] {m}{n}",
-        )))
-        .build(log_file_path, Box::new(policy))
-        .unwrap();
+fn retention_path() -> PathBuf {
+    paths::data_dir().join(RETENTION_FILE)
+}
+
+/// Whether `path` is the current log file or one of its rotated archives
+fn is_log_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with("picoforge.") && name.ends_with(".log"))
+}
+
+/// Retention settings saved by the user, or the defaults
+pub fn retention() -> LogRetention {
+    match storage::read(&retention_path()) {
+        Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+            eprintln!("Log retention settings are corrupted, using the defaults: {}", e);
+            LogRetention::default()
+        }),
+        Err(_) => LogRetention::default(),
+    }
+}
+
+/// Feeds the warnings and errors of PicoForge itself into the activity feed
+#[derive(Debug)]
+struct ActivityAppender;
+
+impl Append for ActivityAppender {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        if !record.target().starts_with("picoforge") {
+            return Ok(());
+        }
+        let kind = match record.level() {
+            Level::Error => ActivityKind::Error,
+            _ => ActivityKind::Warning,
+        };
+        activity::record(kind, record.args().to_string(), ActivityDetail::default());
+        Ok(())
+    }
 
+    fn flush(&self) {}
+}
+
+fn build_config(retention: &LogRetention, log_to_file: bool) -> Config {
     // Console Appender
+    let target = if CONSOLE_TO_STDERR.load(Ordering::Relaxed) {
+        Target::Stderr
+    } else {
+        Target::Stdout
+    };
     let stdout = ConsoleAppender::builder()
-        .target(Target::Stdout)
-        .encoder(Box::new(PatternEncoder::new(
+        .target(target)
+        .encoder(Box::new(TaggedEncoder::new(
             "[{d(%Y-%m-%d %H:%M:%S %Z)} {h({l})} {t}] {m}{n}",
         )))
         .build();
@@ -64,16 +257,163 @@ pub fn logger_init() {
         (LevelFilter::Info, LevelFilter::Error)
     };
 
-    let config = log4rs::Config::builder()
+    let mut builder = Config::builder()
         .appender(Appender::builder().build("stdout", Box::new(stdout)))
-        .appender(Appender::builder().build("logfile", Box::new(logfile)))
-        .logger(Logger::builder().build("picoforge", app_level))
-        .build(
-            Root::builder()
-                .appenders(vec!["logfile", "stdout"])
-                .build(root_level),
+        .appender(
+            Appender::builder()
+                .filter(Box::new(ThresholdFilter::new(LevelFilter::Warn)))
+                .build("activity", Box::new(ActivityAppender)),
         )
-        .unwrap();
+        .appender(Appender::builder().build("buffer", Box::new(BufferAppender)));
+    let mut appenders = vec!["stdout", "activity", "buffer"];
+
+    if log_to_file {
+        let log_dir = log_dir();
+        let size_trigger =
+            SizeTrigger::new(u64::from(retention.max_file_size_mb.max(1)) * 1024 * 1024);
+        let roller: Box<dyn Roll> = if retention.max_archives == 0 {
+            Box::new(DeleteRoller::new())
+        } else {
+            let pattern = log_dir.join(ARCHIVE_PATTERN);
+            match FixedWindowRoller::builder().build(&pattern.to_string_lossy(), retention.max_archives) {
+                Ok(roller) => Box::new(roller),
+                Err(e) => {
+                    eprintln!("Failed to set up log rotation, old logs are deleted instead: {}", e);
+                    Box::new(DeleteRoller::new())
+                }
+            }
+        };
+        let policy = CompoundPolicy::new(Box::new(size_trigger), roller);
+
+        // File Appender
+        match RollingFileAppender::builder()
+            .encoder(Box::new(TaggedEncoder::new(
+                "[{d(%Y-%m-%d %H:%M:%S %Z)} {l} {t}] {m}{n}",
+            )))
+            .build(log_dir.join(LOG_FILE), Box::new(policy))
+        {
+            Ok(logfile) => {
+                builder = builder.appender(Appender::builder().build("logfile", Box::new(logfile)));
+                appenders.push("logfile");
+            }
+            Err(e) => eprintln!("Failed to open the log file, logging to stdout only: {}", e),
+        }
+    }
+
+    builder
+        .logger(Logger::builder().build("picoforge", app_level))
+        .build(Root::builder().appenders(appenders).build(root_level))
+        .unwrap()
+}
+
+/// Deletes rotated log files last written more than `max_age_days` ago
+fn delete_expired_archives(max_age_days: u32) {
+    let max_age = Duration::from_secs(u64::from(max_age_days) * 24 * 60 * 60);
+    let Ok(entries) = fs::read_dir(log_dir()) else {
+        return;
+    };
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if !is_log_file(&path) || path.file_name().is_some_and(|name| name == LOG_FILE) {
+            continue;
+        }
+        let expired = fs::metadata(&path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age > max_age);
+        if expired && let Err(e) = fs::remove_file(&path) {
+            eprintln!("Failed to delete expired log file {:?}: {}", path, e);
+        }
+    }
+}
+
+/// Initializes log4rs with custom configuration for stdout and file logging.
+///
+/// The log file is rotated by size and old rotated files are deleted by age, as configured by
+/// the saved `LogRetention`.
+pub fn logger_init() {
+    let retention = retention();
+    if let Some(days) = retention.max_age_days {
+        delete_expired_archives(days);
+    }
+
+    let handle = log4rs::init_config(build_config(&retention, true)).unwrap();
+    let _ = HANDLE.set(handle);
+}
+
+/// Like `logger_init`, but logs to stderr so stdout only carries the output of the CLI
+pub fn cli_logger_init() {
+    CONSOLE_TO_STDERR.store(true, Ordering::Relaxed);
+    logger_init();
+}
+
+/// Saves new retention settings and applies them to the running logger
+#[cfg(feature = "gui")]
+pub fn set_retention(retention: LogRetention) -> Result<(), PFError> {
+    if !(1..=1024).contains(&retention.max_file_size_mb) {
+        return Err(PFError::Io("The log file size must be between 1 and 1024 MB".into()));
+    }
+    if retention.max_archives > 100 {
+        return Err(PFError::Io("At most 100 rotated log files can be kept".into()));
+    }
+
+    let data = serde_json::to_vec_pretty(&retention).map_err(|e| PFError::Io(e.to_string()))?;
+    storage::write(&retention_path(), &data)
+        .map_err(|e| PFError::Io(format!("Failed to save log retention settings: {}", e)))?;
+
+    if let Some(days) = retention.max_age_days {
+        delete_expired_archives(days);
+    }
+    if let Some(handle) = HANDLE.get() {
+        handle.set_config(build_config(&retention, true));
+    }
+    log::info!("Log retention set to {:?}", retention);
+    Ok(())
+}
+
+/// Deletes the log file and all rotated ones. Logging continues into a fresh file.
+#[cfg(feature = "gui")]
+pub fn purge_logs() -> Result<LogPurgeReport, PFError> {
+    let retention = retention();
+    // The log file cannot be deleted while it is open on every platform
+    if let Some(handle) = HANDLE.get() {
+        handle.set_config(build_config(&retention, false));
+    }
+
+    let mut report = LogPurgeReport {
+        files_deleted: 0,
+        bytes_freed: 0,
+    };
+    let mut failures = Vec::new();
+    if let Ok(entries) = fs::read_dir(log_dir()) {
+        for path in entries.flatten().map(|entry| entry.path()) {
+            if !is_log_file(&path) {
+                continue;
+            }
+            let size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+            match fs::remove_file(&path) {
+                Ok(()) => {
+                    report.files_deleted += 1;
+                    report.bytes_freed += size;
+                }
+                Err(e) => failures.push(format!("{:?}: {}", path, e)),
+            }
+        }
+    }
 
-    log4rs::init_config(config).unwrap();
+    if let Some(handle) = HANDLE.get() {
+        handle.set_config(build_config(&retention, true));
+    }
+    if !failures.is_empty() {
+        return Err(PFError::Io(format!(
+            "Failed to delete some log files: {}",
+            failures.join(", ")
+        )));
+    }
+    log::info!(
+        "Purged {} log files ({} bytes)",
+        report.files_deleted,
+        report.bytes_freed
+    );
+    Ok(report)
 }
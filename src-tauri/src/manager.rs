@@ -0,0 +1,389 @@
+//! Single owner of device access, shared by all app windows.
+//!
+//! Commands from every window (and background jobs) go through the `DeviceManager`, so two windows
+//! can no longer talk to the same device at the same time. Operations naming their device run
+//! concurrently with those on other devices, up to the limit of the session policy so USB hubs and
+//! the PC/SC daemon are not overwhelmed. Operations on the same device still queue in order, and
+//! operations without a device, which may talk to any key, wait for exclusive access.
+//!
+//! A queued operation is told its position in the queue of its device with `operation-queued`, and
+//! conflicting operations start in the order they were queued.
+//! When the session policy disables queuing, an operation on a busy device fails right away with
+//! `PFError::DeviceBusy` instead.
+//!
+//! Status events are broadcast to all windows, while progress is only sent to the window that
+//! started the operation, and only that window may cancel it.
+//!
+//! While an operation runs, the phases, keepalive status and progress device code reports through
+//! `operation` are forwarded to the windows.
+
+use crate::{
+	activity::{self, ActivityDetail},
+	error::PFError,
+	inventory::now,
+	logging, operation,
+	session::SessionManager,
+	types::*,
+};
+use rand::Rng;
+use std::collections::BTreeSet;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::Instant;
+use tauri::{AppHandle, Emitter, Manager};
+
+/// Broadcast to every window when an operation acquires the device
+pub const OPERATION_STARTED_EVENT: &str = "operation-started";
+/// Broadcast to every window when an operation releases the device
+pub const OPERATION_FINISHED_EVENT: &str = "operation-finished";
+/// Sent to the owning window only
+pub const OPERATION_PROGRESS_EVENT: &str = "operation-progress";
+/// Broadcast to every window when an operation enters a new phase
+pub const OPERATION_PHASE_EVENT: &str = "operation-phase";
+/// Broadcast to every window when an operation waits for a touch, along with its phase
+pub const OPERATION_AWAITING_TOUCH_EVENT: &str = "operation-awaiting-touch";
+/// Broadcast to every window when the key reports a different keepalive status
+pub const OPERATION_KEEPALIVE_EVENT: &str = "operation-keepalive";
+/// Sent to the owning window when an operation has to wait for its device, and whenever its
+/// position in the queue changes
+pub const OPERATION_QUEUED_EVENT: &str = "operation-queued";
+
+/// Whether operations on `a` and `b` have to run one after the other
+fn conflicts(a: Option<&str>, b: Option<&str>) -> bool {
+	match (a, b) {
+		(Some(a), Some(b)) => a == b,
+		_ => true,
+	}
+}
+
+/// Operations holding a device and operations waiting for one, each oldest first
+#[derive(Default)]
+struct Queue {
+	running: Vec<OperationInfo>,
+	waiting: Vec<OperationInfo>,
+}
+
+impl Queue {
+	/// Operations running or waiting ahead of `info` that it has to wait for
+	fn position(&self, info: &OperationInfo) -> u32 {
+		let device = info.device.as_deref();
+		let running = self
+			.running
+			.iter()
+			.filter(|op| conflicts(op.device.as_deref(), device))
+			.count();
+		let waiting = self
+			.waiting
+			.iter()
+			.take_while(|op| op.id != info.id)
+			.filter(|op| conflicts(op.device.as_deref(), device))
+			.count();
+		(running + waiting) as u32
+	}
+
+	/// Whether `info` may start, with at most `limit` operations on named devices at once
+	fn admits(&self, info: &OperationInfo, limit: u32) -> bool {
+		if self.position(info) > 0 {
+			return false;
+		}
+		info.device.is_none()
+			|| self.running.iter().filter(|op| op.device.is_some()).count() < limit.max(1) as usize
+	}
+}
+
+/// Releases the device of a running operation when dropped, even if the operation panicked
+struct Running<'a> {
+	manager: &'a DeviceManager,
+	id: String,
+}
+
+impl Drop for Running<'_> {
+	fn drop(&mut self) {
+		self.manager.queue().running.retain(|op| op.id != self.id);
+		self.manager.released.notify_all();
+	}
+}
+
+#[derive(Default)]
+pub struct DeviceManager {
+	queue: Mutex<Queue>,
+	/// Signalled whenever an operation releases its device
+	released: Condvar,
+	/// IDs of the running operations asked to stop
+	cancel_requested: Mutex<BTreeSet<String>>,
+}
+
+impl DeviceManager {
+	fn queue(&self) -> MutexGuard<'_, Queue> {
+		self.queue.lock().unwrap_or_else(|p| p.into_inner())
+	}
+
+	/// Tells the owner of each waiting operation its position in the queue
+	fn report_positions(&self, app: &AppHandle) {
+		let positions: Vec<(OperationInfo, u32)> = {
+			let queue = self.queue();
+			queue
+				.waiting
+				.iter()
+				.map(|info| (info.clone(), queue.position(info)))
+				.collect()
+		};
+		for (info, position) in positions {
+			let queued = OperationQueued {
+				operation_id: info.id.clone(),
+				operation: info.operation.clone(),
+				device: info.device.clone(),
+				position,
+			};
+			if let Err(e) = app.emit_to(info.owner.as_str(), OPERATION_QUEUED_EVENT, &queued) {
+				log::warn!(
+					"Failed to send queue position to window '{}': {}",
+					info.owner,
+					e
+				);
+			}
+		}
+	}
+
+	/// Error for an operation on `device` that would have to wait, naming what keeps it busy
+	fn busy(queue: &Queue, device: Option<&str>) -> PFError {
+		let operation = queue
+			.running
+			.iter()
+			.chain(&queue.waiting)
+			.find(|op| conflicts(op.device.as_deref(), device))
+			.map(|op| op.operation.clone())
+			.unwrap_or_else(|| "another operation".into());
+		PFError::DeviceBusy { operation }
+	}
+
+	/// Runs `operation` on behalf of the window `owner`, with exclusive access to `device`, or to
+	/// all devices without one.
+	///
+	/// Blocks until operations on the same device started earlier by any window have finished, and
+	/// until fewer operations than the concurrency limit of the session policy run. If the session
+	/// policy disables queuing, fails with `PFError::DeviceBusy` instead of waiting for the device.
+	pub fn run<T>(
+		&self,
+		app: &AppHandle,
+		owner: &str,
+		operation: &str,
+		device: Option<&str>,
+		f: impl FnOnce() -> T,
+	) -> Result<T, PFError> {
+		let session = app.try_state::<SessionManager>();
+		let queue_operations = session
+			.as_ref()
+			.is_none_or(|session| session.policy().queue_operations);
+
+		let id_bytes: [u8; 8] = rand::rng().random();
+		let mut info = OperationInfo {
+			id: hex::encode(id_bytes),
+			owner: owner.to_string(),
+			operation: operation.to_string(),
+			device: device.map(str::to_string),
+			started_at: 0,
+		};
+
+		let limit = session
+			.as_ref()
+			.map_or(1, |session| session.policy().max_concurrent_devices);
+		let mut queue = self.queue();
+		let position = queue.position(&info);
+		if position > 0 && !queue_operations {
+			return Err(Self::busy(&queue, device));
+		}
+		queue.waiting.push(info.clone());
+		if position > 0 {
+			log::debug!("{} queued at position {}", operation, position);
+			let queued = OperationQueued {
+				operation_id: info.id.clone(),
+				operation: operation.to_string(),
+				device: info.device.clone(),
+				position,
+			};
+			if let Err(e) = app.emit_to(owner, OPERATION_QUEUED_EVENT, &queued) {
+				log::warn!("Failed to send queue position to window '{}': {}", owner, e);
+			}
+		}
+		while !queue.admits(&info, limit) {
+			queue = self.released.wait(queue).unwrap_or_else(|p| p.into_inner());
+		}
+		info.started_at = now();
+		queue.waiting.retain(|op| op.id != info.id);
+		queue.running.push(info.clone());
+		drop(queue);
+		let running = Running {
+			manager: self,
+			id: info.id.clone(),
+		};
+
+		if let Some(session) = session {
+			session.touch();
+		}
+
+		log::debug!("Operation {} started by window '{}'", operation, owner);
+		activity::record(
+			ActivityKind::OperationStarted,
+			format!("{} started by window '{}'", operation, owner),
+			ActivityDetail {
+				device: device.map(str::to_string),
+				operation: Some(operation.to_string()),
+				..Default::default()
+			},
+		);
+		let started = Instant::now();
+
+		let _ = app.emit(OPERATION_STARTED_EVENT, &info);
+
+		let observer = WindowObserver {
+			app: app.clone(),
+			owner: owner.to_string(),
+		};
+		let log_context = logging::enter(operation, device);
+		let result = operation::run(&info.id, Box::new(observer), f);
+		drop(log_context);
+
+		drop(running);
+		if let Ok(mut cancel_requested) = self.cancel_requested.lock() {
+			cancel_requested.remove(&info.id);
+		}
+		let _ = app.emit(OPERATION_FINISHED_EVENT, &info);
+		self.report_positions(app);
+		activity::record(
+			ActivityKind::OperationFinished,
+			format!("{} finished", operation),
+			ActivityDetail {
+				device: device.map(str::to_string),
+				operation: Some(operation.to_string()),
+				duration_ms: Some(started.elapsed().as_millis() as u64),
+			},
+		);
+		Ok(result)
+	}
+
+	/// The oldest running operation, for windows opened mid-operation
+	pub fn current(&self) -> Option<OperationInfo> {
+		self.queue().running.first().cloned()
+	}
+
+	/// Requests cancellation of the operations started by the window `requester`
+	pub fn request_cancel(&self, requester: &str) -> Result<(), PFError> {
+		let current = self.queue().running.clone();
+		let owned: Vec<&OperationInfo> =
+			current.iter().filter(|op| op.owner == requester).collect();
+		if owned.is_empty() {
+			return match current.first() {
+				Some(info) => Err(PFError::Io(format!(
+					"{} was started by another window",
+					info.operation
+				))),
+				None => Err(PFError::Io("No operation in progress".into())),
+			};
+		}
+
+		if let Ok(mut cancel_requested) = self.cancel_requested.lock() {
+			for info in owned {
+				log::info!("Cancellation of {} requested", info.operation);
+				cancel_requested.insert(info.id.clone());
+			}
+		}
+		Ok(())
+	}
+
+	/// Asks every running operation, whoever owns it, to stop because the session was locked
+	pub fn cancel_for_lock(&self) {
+		let current = self.queue().running.clone();
+		if let Ok(mut cancel_requested) = self.cancel_requested.lock() {
+			for info in current {
+				log::info!("Stopping {} because the session was locked", info.operation);
+				cancel_requested.insert(info.id);
+			}
+		}
+	}
+
+	/// Checked by long running operations between steps
+	pub fn is_cancelled(&self) -> bool {
+		let Some(id) = operation::current_id() else {
+			return false;
+		};
+		self.cancel_requested
+			.lock()
+			.is_ok_and(|cancel_requested| cancel_requested.contains(&id))
+	}
+}
+
+/// Forwards the reports of an operation to the windows
+struct WindowObserver {
+	app: AppHandle,
+	/// Label of the window that started the operation
+	owner: String,
+}
+
+impl operation::Observer for WindowObserver {
+	fn phase(&self, phase: &OperationPhase) {
+		if let Err(e) = self.app.emit(OPERATION_PHASE_EVENT, phase) {
+			log::warn!("Failed to emit operation phase: {}", e);
+		}
+		if matches!(
+			phase.user_action,
+			UserAction::TouchDevice | UserAction::TouchSensor
+		) && let Err(e) = self.app.emit(OPERATION_AWAITING_TOUCH_EVENT, phase)
+		{
+			log::warn!("Failed to emit awaiting touch: {}", e);
+		}
+	}
+
+	fn keepalive(&self, keepalive: &OperationKeepalive) {
+		if let Err(e) = self.app.emit(OPERATION_KEEPALIVE_EVENT, keepalive) {
+			log::warn!("Failed to emit keepalive status: {}", e);
+		}
+	}
+
+	fn progress(&self, progress: &OperationProgress) {
+		if let Err(e) = self
+			.app
+			.emit_to(self.owner.as_str(), OPERATION_PROGRESS_EVENT, progress)
+		{
+			log::warn!("Failed to send progress to window '{}': {}", self.owner, e);
+		}
+	}
+
+	fn cancelled(&self) -> bool {
+		self.app.state::<DeviceManager>().is_cancelled()
+	}
+}
+
+/// Runs `f` on the blocking thread pool with exclusive device access, owned by `window`.
+pub async fn run_for_window<T, F>(
+	window: tauri::Window,
+	operation: &'static str,
+	f: F,
+) -> Result<T, PFError>
+where
+	T: Send + 'static,
+	F: FnOnce() -> T + Send + 'static,
+{
+	run_for_device(window, operation, None, f).await
+}
+
+/// Like `run_for_window`, but only takes exclusive access to the key at `device_path`, so
+/// operations on other keys can run at the same time. Without a path, all keys are locked.
+pub async fn run_for_device<T, F>(
+	window: tauri::Window,
+	operation: &'static str,
+	device_path: Option<String>,
+	f: F,
+) -> Result<T, PFError>
+where
+	T: Send + 'static,
+	F: FnOnce() -> T + Send + 'static,
+{
+	let app = window.app_handle().clone();
+	let owner = window.label().to_string();
+	tauri::async_runtime::spawn_blocking(move || {
+		app.state::<DeviceManager>()
+			.run(&app, &owner, operation, device_path.as_deref(), f)
+	})
+	.await
+	.map_err(|e| PFError::Io(e.to_string()))?
+}
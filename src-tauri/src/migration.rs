@@ -0,0 +1,150 @@
+//! Migration assistant, for moving from an old key to a new one.
+//!
+//! Passkeys cannot be copied between authenticators, so the user has to register the new key
+//! with every relying party again. The resident credentials of the old key are turned into a
+//! checklist kept in the inventory, whose items are ticked off by hand or by reading the
+//! credentials of the new key.
+
+use crate::{
+	error::PFError,
+	fido,
+	inventory::{self, Inventory},
+	rescue,
+	types::{Migration, MigrationItem, StoredCredential},
+};
+
+fn same_account(item: &MigrationItem, credential: &StoredCredential) -> bool {
+	item.rp_id.eq_ignore_ascii_case(&credential.rp_id) && item.user_name == credential.user_name
+}
+
+fn read_credentials(
+	device_path: Option<&str>,
+	pin: String,
+) -> Result<Vec<StoredCredential>, PFError> {
+	let mut credentials = fido::get_credentials(device_path, Some(&pin))?;
+	fido::rp_metadata::enrich(&mut credentials);
+	Ok(credentials)
+}
+
+/// Starts the migration `name` from the connected key, replacing an earlier one of that name
+pub fn start(name: &str, device_path: Option<&str>, pin: String) -> Result<Migration, PFError> {
+	let credentials = read_credentials(device_path, pin)?;
+	// Only identifies the key if it is the only one connected
+	let source_serial = if fido::list_devices().is_ok_and(|devices| devices.len() == 1) {
+		rescue::connected_serial()
+	} else {
+		None
+	};
+
+	let mut items: Vec<MigrationItem> = Vec::new();
+	for credential in credentials {
+		if items.iter().any(|item| same_account(item, &credential)) {
+			continue;
+		}
+		items.push(MigrationItem {
+			rp_name: credential
+				.rp_display_name
+				.clone()
+				.filter(|n| !n.is_empty())
+				.unwrap_or_else(|| credential.rp_name.clone()),
+			rp_id: credential.rp_id,
+			user_name: credential.user_name,
+			account_url: credential.account_url,
+			completed_at: None,
+		});
+	}
+	items.sort_by(|a, b| a.rp_id.cmp(&b.rp_id).then(a.user_name.cmp(&b.user_name)));
+	log::info!(
+		"Started migration {} with {} accounts to re-register",
+		name,
+		items.len()
+	);
+
+	let migration = Migration {
+		source_serial,
+		target_serial: None,
+		started_at: inventory::now(),
+		items,
+	};
+	Inventory::update(|inventory| {
+		inventory
+			.migrations
+			.insert(name.to_string(), migration.clone());
+		Ok(migration)
+	})
+}
+
+fn update(name: &str, f: impl FnOnce(&mut Migration)) -> Result<Migration, PFError> {
+	Inventory::update(|inventory| {
+		let migration = inventory
+			.migrations
+			.get_mut(name)
+			.ok_or_else(|| PFError::Io(format!("Migration {} not found", name)))?;
+		f(migration);
+		Ok(migration.clone())
+	})
+}
+
+/// Marks an account as re-registered on the new key, or as still to do
+pub fn set_done(
+	name: &str,
+	rp_id: &str,
+	user_name: &str,
+	done: bool,
+) -> Result<Migration, PFError> {
+	let mut found = false;
+	let migration =
+		update(name, |migration| {
+			for item in migration.items.iter_mut().filter(|item| {
+				item.rp_id.eq_ignore_ascii_case(rp_id) && item.user_name == user_name
+			}) {
+				found = true;
+				item.completed_at = done.then(|| item.completed_at.unwrap_or_else(inventory::now));
+			}
+		})?;
+	if !found {
+		return Err(PFError::Io(format!(
+			"Migration {} has no account {} for {}",
+			name, user_name, rp_id
+		)));
+	}
+	Ok(migration)
+}
+
+/// Reads the credentials of the connected new key and ticks off the accounts found on it
+pub fn check_target(
+	name: &str,
+	device_path: Option<&str>,
+	pin: String,
+) -> Result<Migration, PFError> {
+	let credentials = read_credentials(device_path, pin)?;
+	let target_serial = if fido::list_devices().is_ok_and(|devices| devices.len() == 1) {
+		rescue::connected_serial()
+	} else {
+		None
+	};
+
+	let inventory = Inventory::load()?;
+	if let Some(serial) = &target_serial
+		&& inventory
+			.migrations
+			.get(name)
+			.is_some_and(|m| m.source_serial.as_ref() == Some(serial))
+	{
+		return Err(PFError::Device(format!(
+			"Device {} is the old key of migration {}, connect the new one",
+			serial, name
+		)));
+	}
+
+	update(name, |migration| {
+		if target_serial.is_some() {
+			migration.target_serial = target_serial;
+		}
+		for item in migration.items.iter_mut() {
+			if item.completed_at.is_none() && credentials.iter().any(|c| same_account(item, c)) {
+				item.completed_at = Some(inventory::now());
+			}
+		}
+	})
+}
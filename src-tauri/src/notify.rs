@@ -0,0 +1,87 @@
+//! Notification channels used to tell the operator that a background job finished.
+//!
+//! Every channel receives the finished job; a failing channel is logged and does not prevent the
+//! others from being notified.
+
+use crate::types::{JobInfo, JobKind, JobStatus};
+use tauri::{AppHandle, Emitter};
+use tauri_plugin_notification::NotificationExt;
+
+/// Frontend event emitted when a job completes or fails
+pub const JOB_FINISHED_EVENT: &str = "job-finished";
+
+pub trait NotificationChannel: Send + Sync {
+	fn name(&self) -> &'static str;
+	fn notify(&self, app: &AppHandle, job: &JobInfo) -> Result<(), String>;
+}
+
+/// Native desktop notification, visible while the app is in the background
+pub struct DesktopChannel;
+
+impl NotificationChannel for DesktopChannel {
+	fn name(&self) -> &'static str {
+		"desktop"
+	}
+
+	fn notify(&self, app: &AppHandle, job: &JobInfo) -> Result<(), String> {
+		let what = match job.kind {
+			JobKind::FirmwareFlash => "Firmware flashing",
+		};
+		let title = match job.status {
+			JobStatus::Failed => format!("{} failed", what),
+			_ => format!("{} completed", what),
+		};
+		let body = match &job.message {
+			Some(msg) => format!("{}: {}", job.label, msg),
+			None => job.label.clone(),
+		};
+
+		app.notification()
+			.builder()
+			.title(title)
+			.body(body)
+			.show()
+			.map_err(|e| e.to_string())
+	}
+}
+
+/// Event for the frontend to update job lists and show in-app toasts
+pub struct FrontendChannel;
+
+impl NotificationChannel for FrontendChannel {
+	fn name(&self) -> &'static str {
+		"frontend"
+	}
+
+	fn notify(&self, app: &AppHandle, job: &JobInfo) -> Result<(), String> {
+		app.emit(JOB_FINISHED_EVENT, job).map_err(|e| e.to_string())
+	}
+}
+
+pub struct Notifier {
+	channels: Vec<Box<dyn NotificationChannel>>,
+}
+
+impl Default for Notifier {
+	fn default() -> Self {
+		Self {
+			channels: vec![Box::new(DesktopChannel), Box::new(FrontendChannel)],
+		}
+	}
+}
+
+impl Notifier {
+	/// Sends the finished job to every registered channel
+	pub fn dispatch(&self, app: &AppHandle, job: &JobInfo) {
+		for channel in &self.channels {
+			if let Err(e) = channel.notify(app, job) {
+				log::warn!(
+					"Notification channel '{}' failed for job {}: {}",
+					channel.name(),
+					job.id,
+					e
+				);
+			}
+		}
+	}
+}
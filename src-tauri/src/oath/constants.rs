@@ -0,0 +1,73 @@
+//! Constants, enums and tags for the OATH (YKOATH compatible) applet of pico-fido firmware.
+#![allow(unused)]
+
+// The OATH Application ID (AID) from src/oath.c
+pub const OATH_AID: &[u8] = &[0xA0, 0x00, 0x00, 0x05, 0x27, 0x21, 0x01];
+
+/// Default TOTP time step in seconds
+pub const TOTP_DEFAULT_PERIOD: u64 = 30;
+
+/// Number of time steps checked on each side of the current step by the drift check
+pub const TOTP_DRIFT_WINDOW: i64 = 2;
+
+// APDU Instructions
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OathInstruction {
+	Put = 0x01,
+	Delete = 0x02,
+	SetCode = 0x03,
+	Reset = 0x04,
+	List = 0xA1,
+	Calculate = 0xA2,
+	Validate = 0xA3,
+	CalculateAll = 0xA4,
+	SendRemaining = 0xA5,
+}
+
+/// P2 Parameters for OathInstruction::Calculate (0xA2)
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalculateParam {
+	Full = 0x00,
+	Truncated = 0x01,
+}
+
+// TLV Tags used in requests and responses
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OathTag {
+	Name = 0x71,
+	NameList = 0x72,
+	Key = 0x73,
+	Challenge = 0x74,
+	Response = 0x75,
+	TruncatedResponse = 0x76,
+	NoResponse = 0x77,
+	Property = 0x78,
+	Version = 0x79,
+	Imf = 0x7A,
+	Algorithm = 0x7B,
+	Touch = 0x7C,
+}
+
+/// Type bits of the first Key byte, its low bits being the algorithm
+pub const OATH_TYPE_HOTP: u8 = 0x10;
+pub const OATH_TYPE_TOTP: u8 = 0x20;
+pub const OATH_HMAC_SHA1: u8 = 0x01;
+pub const OATH_HMAC_SHA256: u8 = 0x02;
+pub const OATH_HMAC_SHA512: u8 = 0x03;
+
+/// Property requiring a touch for each code, sent as a bare tag and value without length
+pub const OATH_PROP_REQUIRE_TOUCH: u8 = 0x02;
+
+/// Longest account name the applet stores
+pub const OATH_NAME_MAX_LEN: usize = 64;
+
+/// Secrets are zero padded to this length, as HMAC would do anyway
+pub const OATH_KEY_MIN_LEN: usize = 14;
+
+/// Derivation of the access key of password protected stores: PBKDF2-HMAC-SHA1, salted with
+/// the device ID from the SELECT response
+pub const OATH_PBKDF2_ITERATIONS: u32 = 1000;
+pub const OATH_ACCESS_KEY_LEN: usize = 16;
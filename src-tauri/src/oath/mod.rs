@@ -0,0 +1,666 @@
+//! Implements communication with the OATH applet of the pico-fido firmware over PC/SC.
+//!
+//! The applet speaks the YKOATH protocol. For more details checkout the [pico-fido](https://github.com/polhenarejos/pico-fido/blob/main/src/fido/oath.c) sources.
+
+pub mod constants;
+pub mod otpauth;
+
+use crate::{
+	card, error::PFError, oath::constants::*, operation::report_phase,
+	rescue::constants::APDU_CLA_ISO, types::*,
+};
+use ring::{
+	digest, hmac, pbkdf2,
+	rand::{SecureRandom, SystemRandom},
+};
+use std::num::NonZeroU32;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Connects to the first available reader and selects the OATH Applet
+fn connect_and_select() -> Result<(pcsc::Card, Vec<u8>), PFError> {
+	card::connect_and_select(OATH_AID, "OATH")
+}
+
+/// Sends an APDU and collects the full response, following `61 XX` continuations.
+///
+/// Returns the response data without the status word.
+fn transmit(card: &pcsc::Card, apdu: &[u8]) -> Result<Vec<u8>, PFError> {
+	let resp = card::transmit_with_remaining(card, apdu, OathInstruction::SendRemaining as u8)?;
+
+	match resp.sw {
+		0x9000 => Ok(resp.data),
+		0x6982 => Err(PFError::Device("OATH store is password protected".into())),
+		0x6A82 => Err(PFError::Device("OATH account not found".into())),
+		sw => {
+			log::error!("OATH command failed with SW {:04X}", sw);
+			Err(PFError::Device(format!("OATH command failed: {:04X}", sw)))
+		}
+	}
+}
+
+/// Splits data into its short-form TLVs
+fn parse_tlvs(mut data: &[u8]) -> Vec<(u8, &[u8])> {
+	let mut tlvs = Vec::new();
+	while let [tag, len, rest @ ..] = data {
+		let len = (*len as usize).min(rest.len());
+		tlvs.push((*tag, &rest[..len]));
+		data = &rest[len..];
+	}
+	tlvs
+}
+
+/// Appends a short-form TLV to the buffer
+fn push_tlv(buf: &mut Vec<u8>, tag: OathTag, value: &[u8]) {
+	buf.push(tag as u8);
+	buf.push(value.len() as u8);
+	buf.extend_from_slice(value);
+}
+
+/// Asks the device to compute the truncated OATH code of `name` for the given challenge.
+fn calculate_truncated(card: &pcsc::Card, name: &str, challenge: &[u8]) -> Result<String, PFError> {
+	let mut data = Vec::new();
+	push_tlv(&mut data, OathTag::Name, name.as_bytes());
+	push_tlv(&mut data, OathTag::Challenge, challenge);
+
+	// APDU: 00 A2 00 01 [Lc] [Name TLV] [Challenge TLV]
+	let mut apdu = vec![
+		APDU_CLA_ISO,
+		OathInstruction::Calculate as u8,
+		0x00,
+		CalculateParam::Truncated as u8,
+		data.len() as u8,
+	];
+	apdu.extend_from_slice(&data);
+
+	let resp = transmit(card, &apdu)?;
+
+	// Response: 76 05 [digits] [4 bytes truncated value]
+	match parse_tlvs(&resp).as_slice() {
+		[(tag, value)] if *tag == OathTag::TruncatedResponse as u8 => truncated_code(value),
+		_ => {
+			log::error!("Unexpected OATH calculate response: {:02X?}", resp);
+			Err(PFError::Device("Unexpected OATH calculate response".into()))
+		}
+	}
+}
+
+/// Formats the value of a truncated response, the number of digits followed by 4 bytes
+fn truncated_code(value: &[u8]) -> Result<String, PFError> {
+	let [digits, truncated @ ..] = value else {
+		return Err(PFError::Device("Empty OATH code from the device".into()));
+	};
+	let Ok(truncated) = <[u8; 4]>::try_from(truncated) else {
+		return Err(PFError::Device(format!(
+			"OATH code of {} bytes from the device",
+			truncated.len()
+		)));
+	};
+	if !(6..=8).contains(digits) {
+		return Err(PFError::Device(format!(
+			"OATH code of {} digits from the device, expected 6 to 8",
+			digits
+		)));
+	}
+	let code = (u32::from_be_bytes(truncated) & 0x7FFF_FFFF) % 10u32.pow(*digits as u32);
+	Ok(format!("{:0width$}", code, width = *digits as usize))
+}
+
+/// Computes the device codes for `step - window ..= step + window` and returns the offset
+/// matching `reference_code`, if any.
+fn scan_window(
+	card: &pcsc::Card,
+	name: &str,
+	step: i64,
+	reference_code: &str,
+) -> Result<(Vec<TotpWindowCode>, Option<i64>), PFError> {
+	let mut window = Vec::new();
+	let mut matched = None;
+
+	for offset in -TOTP_DRIFT_WINDOW..=TOTP_DRIFT_WINDOW {
+		let challenge = ((step + offset) as u64).to_be_bytes();
+		let code = calculate_truncated(card, name, &challenge)?;
+		if matched.is_none() && code == reference_code {
+			matched = Some(offset);
+		}
+		window.push(TotpWindowCode {
+			step_offset: offset,
+			code,
+		});
+	}
+
+	Ok((window, matched))
+}
+
+/// Checks the host clock against a reference TOTP code for a device-backed OATH account.
+///
+/// The device computes codes for every time step in a ±2 step window around the host time,
+/// and the reference code (as shown by the service or a phone with network time) is located
+/// in that window. When nothing matches, the window is re-scanned around the host time shifted
+/// by the local UTC offset to catch clocks that store local time as UTC.
+///
+/// Time steps are as long as the period encoded in the account name.
+pub fn check_totp_drift(
+	password: Option<&str>,
+	name: String,
+	reference_code: String,
+) -> Result<TotpDriftReport, PFError> {
+	log::info!("Checking TOTP clock drift for OATH account {}", name);
+
+	let period = name_period(&name);
+	let reference_code = reference_code.trim().to_string();
+
+	let now = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map_err(|e| PFError::Io(e.to_string()))?
+		.as_secs() as i64;
+	let utc_offset = chrono::Local::now().offset().local_minus_utc();
+
+	let card = open(password)?;
+	report_phase(
+		PhaseVerb::Read,
+		&format!("TOTP codes of {}", name),
+		UserAction::None,
+	);
+
+	let step = now / period as i64;
+	let (window, matched) = scan_window(&card, &name, step, &reference_code)?;
+
+	let mut drift_seconds = matched.map(|offset| offset * period as i64);
+	let mut timezone_mismatch = false;
+
+	if matched.is_none() && utc_offset != 0 {
+		log::debug!(
+			"No match in TOTP window, retrying with local UTC offset of {}s",
+			utc_offset
+		);
+		for shift in [-(utc_offset as i64), utc_offset as i64] {
+			let shifted_step = (now + shift) / period as i64;
+			let (_, shifted_match) = scan_window(&card, &name, shifted_step, &reference_code)?;
+			if let Some(offset) = shifted_match {
+				drift_seconds = Some(shift + offset * period as i64);
+				timezone_mismatch = true;
+				break;
+			}
+		}
+	}
+
+	let (status, message) = match (matched, timezone_mismatch) {
+		(Some(0), _) => (
+			TotpDriftStatus::InSync,
+			"Host clock is in sync with the reference code.".to_string(),
+		),
+		(Some(offset), _) => (
+			TotpDriftStatus::Drifting,
+			format!(
+				"Host clock is off by {} time step(s) ({}s). Logins may fail on services with a strict validation window.",
+				offset,
+				offset * period as i64
+			),
+		),
+		(None, true) => (
+			TotpDriftStatus::TimezoneMismatch,
+			format!(
+				"Host clock appears to be set to local time instead of UTC (off by {}s). Fix the system timezone setting.",
+				drift_seconds.unwrap_or_default()
+			),
+		),
+		(None, false) => (
+			TotpDriftStatus::NoMatch,
+			"Reference code was not found in the checked window. Verify the account name and code, or the clock is off by more than 2 time steps.".to_string(),
+		),
+	};
+
+	if status != TotpDriftStatus::InSync {
+		log::warn!("TOTP drift check for {}: {}", name, message);
+	}
+
+	Ok(TotpDriftReport {
+		account: name,
+		host_unix_time: now as u64,
+		utc_offset_seconds: utc_offset,
+		period,
+		window,
+		matched_offset: matched,
+		drift_seconds,
+		status,
+		message,
+	})
+}
+
+fn describe_kind(type_byte: u8) -> (String, String) {
+	let kind = match type_byte & 0xF0 {
+		0x10 => "HOTP",
+		0x20 => "TOTP",
+		_ => "Unknown",
+	};
+	let algorithm = match type_byte & 0x0F {
+		0x01 => "SHA1",
+		0x02 => "SHA256",
+		0x03 => "SHA512",
+		_ => "Unknown",
+	};
+	(kind.to_string(), algorithm.to_string())
+}
+
+/// Lists the accounts stored in the OATH applet
+pub fn list_accounts(password: Option<&str>) -> Result<Vec<OathAccount>, PFError> {
+	log::info!("Listing OATH accounts...");
+	let card = open(password)?;
+	report_phase(PhaseVerb::Read, "OATH accounts", UserAction::None);
+
+	// APDU: 00 A1 00 00 00
+	let apdu = [APDU_CLA_ISO, OathInstruction::List as u8, 0x00, 0x00, 0x00];
+	let resp = transmit(&card, &apdu)?;
+
+	// Response: repeated 72 [L] [kind | algorithm] [name]
+	let mut accounts = Vec::new();
+	for (tag, value) in parse_tlvs(&resp) {
+		if tag != OathTag::NameList as u8 || value.is_empty() {
+			log::debug!("Skipping OATH list entry with tag 0x{:02X}", tag);
+			continue;
+		}
+		let (kind, algorithm) = describe_kind(value[0]);
+		accounts.push(OathAccount {
+			name: String::from_utf8_lossy(&value[1..]).to_string(),
+			kind,
+			algorithm,
+		});
+	}
+	Ok(accounts)
+}
+
+/// Access key of a password protected store
+fn access_key(password: &str, device_id: &[u8]) -> [u8; OATH_ACCESS_KEY_LEN] {
+	let mut key = [0u8; OATH_ACCESS_KEY_LEN];
+	pbkdf2::derive(
+		pbkdf2::PBKDF2_HMAC_SHA1,
+		NonZeroU32::new(OATH_PBKDF2_ITERATIONS).unwrap(),
+		device_id,
+		password.as_bytes(),
+		&mut key,
+	);
+	key
+}
+
+fn random_challenge() -> Result<[u8; 8], PFError> {
+	let mut challenge = [0u8; 8];
+	SystemRandom::new()
+		.fill(&mut challenge)
+		.map_err(|_| PFError::Io("Failed to generate a challenge".into()))?;
+	Ok(challenge)
+}
+
+/// Value of `tag` in the SELECT response
+fn select_field(select: &[u8], tag: OathTag) -> Option<&[u8]> {
+	parse_tlvs(select)
+		.into_iter()
+		.find(|(t, _)| *t == tag as u8)
+		.map(|(_, value)| value)
+}
+
+/// Unlocks the store with VALIDATE if the SELECT response carries a challenge, i.e. a password
+/// is set. Both sides prove they know the access key.
+fn unlock(card: &pcsc::Card, select: &[u8], password: Option<&str>) -> Result<(), PFError> {
+	let Some(challenge) = select_field(select, OathTag::Challenge) else {
+		return Ok(());
+	};
+	let password =
+		password.ok_or_else(|| PFError::Device("OATH store is password protected".into()))?;
+	let device_id = select_field(select, OathTag::Name)
+		.ok_or_else(|| PFError::Device("Device ID missing from OATH SELECT response".into()))?;
+	let algorithm = match select_field(select, OathTag::Algorithm).and_then(|a| a.first()) {
+		Some(&OATH_HMAC_SHA256) => hmac::HMAC_SHA256,
+		Some(&OATH_HMAC_SHA512) => hmac::HMAC_SHA512,
+		_ => hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+	};
+	report_phase(PhaseVerb::Authenticate, "OATH password", UserAction::None);
+
+	let key = hmac::Key::new(algorithm, &access_key(password, device_id));
+	let own_challenge = random_challenge()?;
+	let mut data = Vec::new();
+	push_tlv(
+		&mut data,
+		OathTag::Response,
+		hmac::sign(&key, challenge).as_ref(),
+	);
+	push_tlv(&mut data, OathTag::Challenge, &own_challenge);
+
+	// APDU: 00 A3 00 00 [Lc] 75 [L] [Response] 74 08 [Challenge]
+	let mut apdu = vec![
+		APDU_CLA_ISO,
+		OathInstruction::Validate as u8,
+		0x00,
+		0x00,
+		data.len() as u8,
+	];
+	apdu.extend_from_slice(&data);
+	let resp = card::transmit_with_remaining(card, &apdu, OathInstruction::SendRemaining as u8)?;
+	match resp.sw {
+		0x9000 => {}
+		0x6984 => return Err(PFError::Device("Wrong OATH password".into())),
+		sw => {
+			return Err(PFError::Device(format!(
+				"OATH password validation failed: {:04X}",
+				sw
+			)));
+		}
+	}
+
+	// Response: 75 [L] [HMAC of our challenge]
+	let proof = select_field(&resp.data, OathTag::Response)
+		.ok_or_else(|| PFError::Device("Response missing from OATH VALIDATE".into()))?;
+	hmac::verify(&key, &own_challenge, proof)
+		.map_err(|_| PFError::Device("The OATH applet answered the challenge wrongly".into()))
+}
+
+/// Selects the OATH Applet and unlocks it with `password` if it is protected
+fn open(password: Option<&str>) -> Result<pcsc::Card, PFError> {
+	let (card, select) = connect_and_select()?;
+	unlock(&card, &select, password)?;
+	Ok(card)
+}
+
+/// Name under which an account is stored: "[period/][issuer:]account"
+fn account_name(credential: &OathCredential) -> String {
+	let mut name = String::new();
+	if credential.kind == OathKind::Totp
+		&& let Some(period) = credential.period
+		&& u64::from(period) != TOTP_DEFAULT_PERIOD
+	{
+		name.push_str(&format!("{}/", period));
+	}
+	if let Some(issuer) = &credential.issuer {
+		name.push_str(&format!("{}:", issuer));
+	}
+	name.push_str(&credential.account);
+	name
+}
+
+/// TOTP time step encoded in the account name, the default if there is none
+fn name_period(name: &str) -> u64 {
+	name.split_once('/')
+		.and_then(|(period, _)| period.parse().ok())
+		.filter(|period| *period > 0)
+		.unwrap_or(TOTP_DEFAULT_PERIOD)
+}
+
+/// Adds an account, replacing any account of the same name. Returns the name it is stored under.
+pub fn add_account(password: Option<&str>, credential: &OathCredential) -> Result<String, PFError> {
+	let name = account_name(credential);
+	if name.len() > OATH_NAME_MAX_LEN {
+		return Err(PFError::Io(format!(
+			"The account name must be at most {} bytes long",
+			OATH_NAME_MAX_LEN
+		)));
+	}
+	let digits = credential.digits.unwrap_or(6);
+	if !(6..=8).contains(&digits) {
+		return Err(PFError::Io("OATH codes must have 6 to 8 digits".into()));
+	}
+	if credential.period == Some(0) {
+		return Err(PFError::Io("TOTP period must be greater than zero".into()));
+	}
+
+	// Secrets longer than the HMAC block are hashed first, as HMAC itself would do
+	let (type_algorithm, digest_algorithm, block_len) = match credential.algorithm {
+		OathAlgorithm::Sha1 => (OATH_HMAC_SHA1, &digest::SHA1_FOR_LEGACY_USE_ONLY, 64),
+		OathAlgorithm::Sha256 => (OATH_HMAC_SHA256, &digest::SHA256, 64),
+		OathAlgorithm::Sha512 => (OATH_HMAC_SHA512, &digest::SHA512, 128),
+	};
+	let mut secret = otpauth::decode_base32(&credential.secret)?;
+	if secret.len() > block_len {
+		secret = digest::digest(digest_algorithm, &secret).as_ref().to_vec();
+	}
+	secret.resize(secret.len().max(OATH_KEY_MIN_LEN), 0);
+	let type_byte = match credential.kind {
+		OathKind::Hotp => OATH_TYPE_HOTP,
+		OathKind::Totp => OATH_TYPE_TOTP,
+	} | type_algorithm;
+
+	log::info!("Adding OATH account {}...", name);
+	let card = open(password)?;
+	report_phase(
+		PhaseVerb::Create,
+		&format!("OATH account {}", name),
+		UserAction::None,
+	);
+
+	let mut key = vec![type_byte, digits];
+	key.extend_from_slice(&secret);
+	let mut data = Vec::new();
+	push_tlv(&mut data, OathTag::Name, name.as_bytes());
+	push_tlv(&mut data, OathTag::Key, &key);
+	if credential.require_touch {
+		data.extend_from_slice(&[OathTag::Property as u8, OATH_PROP_REQUIRE_TOUCH]);
+	}
+	if credential.kind == OathKind::Hotp && credential.counter > 0 {
+		push_tlv(&mut data, OathTag::Imf, &credential.counter.to_be_bytes());
+	}
+
+	// APDU: 00 01 00 00 [Lc] 71 [Name] 73 [Key] [78 02] [7A 04 Counter]
+	let mut apdu = vec![
+		APDU_CLA_ISO,
+		OathInstruction::Put as u8,
+		0x00,
+		0x00,
+		data.len() as u8,
+	];
+	apdu.extend_from_slice(&data);
+	transmit(&card, &apdu)?;
+	Ok(name)
+}
+
+pub fn delete_account(password: Option<&str>, name: &str) -> Result<(), PFError> {
+	log::info!("Deleting OATH account {}...", name);
+	let card = open(password)?;
+	report_phase(
+		PhaseVerb::Delete,
+		&format!("OATH account {}", name),
+		UserAction::None,
+	);
+
+	let mut data = Vec::new();
+	push_tlv(&mut data, OathTag::Name, name.as_bytes());
+	// APDU: 00 02 00 00 [Lc] 71 [Name]
+	let mut apdu = vec![
+		APDU_CLA_ISO,
+		OathInstruction::Delete as u8,
+		0x00,
+		0x00,
+		data.len() as u8,
+	];
+	apdu.extend_from_slice(&data);
+	transmit(&card, &apdu)?;
+	Ok(())
+}
+
+fn unix_time() -> Result<u64, PFError> {
+	Ok(SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map_err(|e| PFError::Io(e.to_string()))?
+		.as_secs())
+}
+
+/// Computes the TOTP code of `name` for the current time step
+fn calculate_totp(card: &pcsc::Card, name: &str, now: u64) -> Result<OathCode, PFError> {
+	let period = name_period(name);
+	let step = now / period;
+	Ok(OathCode {
+		name: name.to_string(),
+		code: Some(calculate_truncated(card, name, &step.to_be_bytes())?),
+		kind: OathKind::Totp,
+		touch_required: false,
+		valid_until: Some((step + 1) * period),
+	})
+}
+
+/// Computes the codes of all accounts that need neither a touch nor a counter increment
+fn calculate_all_codes(card: &pcsc::Card, now: u64) -> Result<Vec<OathCode>, PFError> {
+	let challenge = (now / TOTP_DEFAULT_PERIOD).to_be_bytes();
+	let mut data = Vec::new();
+	push_tlv(&mut data, OathTag::Challenge, &challenge);
+	// APDU: 00 A4 00 01 0A 74 08 [Time step]
+	let mut apdu = vec![
+		APDU_CLA_ISO,
+		OathInstruction::CalculateAll as u8,
+		0x00,
+		CalculateParam::Truncated as u8,
+		data.len() as u8,
+	];
+	apdu.extend_from_slice(&data);
+	let resp = transmit(card, &apdu)?;
+
+	// Response: repeated 71 [Name] followed by 76 [Code], 77 (HOTP) or 7C (touch required)
+	let mut codes = Vec::new();
+	let mut tlvs = parse_tlvs(&resp).into_iter();
+	while let Some((tag, name)) = tlvs.next() {
+		if tag != OathTag::Name as u8 {
+			continue;
+		}
+		let name = String::from_utf8_lossy(name).to_string();
+		let Some((tag, value)) = tlvs.next() else {
+			break;
+		};
+		let code = match tag {
+			// Codes of non-default periods were computed for the wrong time step
+			t if t == OathTag::TruncatedResponse as u8
+				&& name_period(&name) != TOTP_DEFAULT_PERIOD =>
+			{
+				calculate_totp(card, &name, now)?
+			}
+			t if t == OathTag::TruncatedResponse as u8 => OathCode {
+				code: match truncated_code(value) {
+					Ok(code) => Some(code),
+					Err(e) => {
+						log::warn!("Skipping OATH code of {}: {}", name, e);
+						continue;
+					}
+				},
+				kind: OathKind::Totp,
+				touch_required: false,
+				valid_until: Some((now / TOTP_DEFAULT_PERIOD + 1) * TOTP_DEFAULT_PERIOD),
+				name,
+			},
+			t if t == OathTag::NoResponse as u8 => OathCode {
+				name,
+				code: None,
+				kind: OathKind::Hotp,
+				touch_required: false,
+				valid_until: None,
+			},
+			t if t == OathTag::Touch as u8 => OathCode {
+				name,
+				code: None,
+				kind: OathKind::Totp,
+				touch_required: true,
+				valid_until: None,
+			},
+			t => {
+				log::debug!("Skipping OATH code of {} with tag 0x{:02X}", name, t);
+				continue;
+			}
+		};
+		codes.push(code);
+	}
+	Ok(codes)
+}
+
+/// Computes the current codes of all accounts. HOTP and touch-required accounts are listed
+/// without a code, to be computed one by one with `calculate_code`.
+pub fn calculate_codes(password: Option<&str>) -> Result<Vec<OathCode>, PFError> {
+	log::info!("Computing OATH codes...");
+	let now = unix_time()?;
+	let card = open(password)?;
+	report_phase(PhaseVerb::Read, "OATH codes", UserAction::None);
+	calculate_all_codes(&card, now)
+}
+
+/// Computes the code of one account, waiting for a touch if the account requires it.
+///
+/// For HOTP accounts this advances the counter.
+pub fn calculate_code(password: Option<&str>, name: &str) -> Result<OathCode, PFError> {
+	log::info!("Computing the OATH code of {}...", name);
+	let now = unix_time()?;
+	let card = open(password)?;
+	let account = calculate_all_codes(&card, now)?
+		.into_iter()
+		.find(|c| c.name == name)
+		.ok_or_else(|| PFError::Device("OATH account not found".into()))?;
+
+	match account {
+		OathCode { code: Some(_), .. } => Ok(account),
+		OathCode {
+			kind: OathKind::Hotp,
+			..
+		} => {
+			report_phase(
+				PhaseVerb::Read,
+				&format!("HOTP code of {}", name),
+				UserAction::None,
+			);
+			Ok(OathCode {
+				code: Some(calculate_truncated(&card, name, &[])?),
+				..account
+			})
+		}
+		OathCode { .. } => {
+			report_phase(
+				PhaseVerb::Read,
+				&format!("TOTP code of {}", name),
+				UserAction::TouchDevice,
+			);
+			Ok(OathCode {
+				touch_required: true,
+				..calculate_totp(&card, name, now)?
+			})
+		}
+	}
+}
+
+/// Sets, changes (`new_password` given) or removes (`None`) the password of the store.
+///
+/// `current_password` is needed if the store is already protected.
+pub fn set_password(
+	current_password: Option<&str>,
+	new_password: Option<&str>,
+) -> Result<(), PFError> {
+	log::info!("Changing the OATH password...");
+	let (card, select) = connect_and_select()?;
+	unlock(&card, &select, current_password)?;
+	report_phase(PhaseVerb::Write, "OATH password", UserAction::None);
+
+	let mut data = Vec::new();
+	match new_password.filter(|p| !p.is_empty()) {
+		Some(password) => {
+			let device_id = select_field(&select, OathTag::Name).ok_or_else(|| {
+				PFError::Device("Device ID missing from OATH SELECT response".into())
+			})?;
+			let access_key = access_key(password, device_id);
+			let challenge = random_challenge()?;
+			let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &access_key);
+
+			let mut key_value = vec![OATH_TYPE_TOTP | OATH_HMAC_SHA1];
+			key_value.extend_from_slice(&access_key);
+			push_tlv(&mut data, OathTag::Key, &key_value);
+			push_tlv(&mut data, OathTag::Challenge, &challenge);
+			push_tlv(
+				&mut data,
+				OathTag::Response,
+				hmac::sign(&key, &challenge).as_ref(),
+			);
+		}
+		None => push_tlv(&mut data, OathTag::Key, &[]),
+	}
+
+	// APDU: 00 03 00 00 [Lc] 73 [L] [Type | Alg] [Key] 74 08 [Challenge] 75 14 [Response]
+	let mut apdu = vec![
+		APDU_CLA_ISO,
+		OathInstruction::SetCode as u8,
+		0x00,
+		0x00,
+		data.len() as u8,
+	];
+	apdu.extend_from_slice(&data);
+	transmit(&card, &apdu)?;
+	Ok(())
+}
@@ -0,0 +1,131 @@
+//! Parsing of otpauth:// URIs, as encoded in the QR codes services show when enrolling an
+//! authenticator. For more details checkout the [Key Uri Format](https://github.com/google/google-authenticator/wiki/Key-Uri-Format).
+
+use crate::{
+	error::PFError,
+	types::{OathAlgorithm, OathCredential, OathKind},
+};
+
+/// Decodes RFC 4648 base32, ignoring case, spaces and padding
+pub fn decode_base32(text: &str) -> Result<Vec<u8>, PFError> {
+	let mut out = Vec::new();
+	let mut buffer = 0u32;
+	let mut bits = 0;
+	for c in text.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+		let value = match c.to_ascii_uppercase() {
+			c @ 'A'..='Z' => c as u32 - 'A' as u32,
+			c @ '2'..='7' => c as u32 - '2' as u32 + 26,
+			c => {
+				return Err(PFError::Io(format!(
+					"Invalid character '{}' in base32 secret",
+					c
+				)));
+			}
+		};
+		buffer = (buffer << 5) | value;
+		bits += 5;
+		if bits >= 8 {
+			bits -= 8;
+			out.push((buffer >> bits) as u8);
+		}
+	}
+	if out.is_empty() {
+		return Err(PFError::Io("The secret is empty".into()));
+	}
+	Ok(out)
+}
+
+/// Decodes %XX escapes, leaving malformed ones as they are
+fn percent_decode(text: &str) -> String {
+	let bytes = text.as_bytes();
+	let mut out = Vec::with_capacity(bytes.len());
+	let mut i = 0;
+	while i < bytes.len() {
+		let escaped = (bytes[i] == b'%')
+			.then(|| text.get(i + 1..i + 3))
+			.flatten()
+			.and_then(|hex| u8::from_str_radix(hex, 16).ok());
+		match escaped {
+			Some(byte) => {
+				out.push(byte);
+				i += 3;
+			}
+			None => {
+				out.push(bytes[i]);
+				i += 1;
+			}
+		}
+	}
+	String::from_utf8_lossy(&out).to_string()
+}
+
+/// Parses `otpauth://[totp|hotp]/[Issuer:]account?secret=...&issuer=...&algorithm=...&digits=...
+/// &period=...&counter=...`
+pub fn parse(uri: &str) -> Result<OathCredential, PFError> {
+	let invalid = |what: &str| PFError::Io(format!("Invalid otpauth URI: {}", what));
+
+	let rest = uri
+		.trim()
+		.strip_prefix("otpauth://")
+		.ok_or_else(|| invalid("it must start with otpauth://"))?;
+	let (kind, rest) = rest.split_once('/').ok_or_else(|| invalid("no label"))?;
+	let kind = match kind.to_ascii_lowercase().as_str() {
+		"totp" => OathKind::Totp,
+		"hotp" => OathKind::Hotp,
+		other => return Err(invalid(&format!("unknown type {}", other))),
+	};
+	let (label, query) = rest.split_once('?').unwrap_or((rest, ""));
+	let label = percent_decode(label);
+	let (mut issuer, account) = match label.split_once(':') {
+		Some((issuer, account)) => (Some(issuer.trim().to_string()), account.trim().to_string()),
+		None => (None, label.trim().to_string()),
+	};
+
+	let mut secret = None;
+	let mut algorithm = OathAlgorithm::Sha1;
+	let mut digits = None;
+	let mut period = None;
+	let mut counter = 0;
+	for (key, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+		let value = percent_decode(&value.replace('+', " "));
+		let number = || {
+			value
+				.parse::<u32>()
+				.map_err(|_| invalid(&format!("{} must be a number", key)))
+		};
+		match key.to_ascii_lowercase().as_str() {
+			"secret" => secret = Some(value.clone()),
+			// The label issuer takes precedence, both should be the same anyway
+			"issuer" if issuer.is_none() => issuer = Some(value.clone()),
+			"algorithm" => {
+				algorithm = match value.to_ascii_uppercase().as_str() {
+					"SHA1" => OathAlgorithm::Sha1,
+					"SHA256" => OathAlgorithm::Sha256,
+					"SHA512" => OathAlgorithm::Sha512,
+					other => return Err(invalid(&format!("unsupported algorithm {}", other))),
+				}
+			}
+			"digits" => digits = Some(number()? as u8),
+			"period" => period = Some(number()?),
+			"counter" => counter = number()?,
+			_ => log::debug!("Ignoring otpauth parameter {}", key),
+		}
+	}
+
+	let secret = secret.ok_or_else(|| invalid("no secret"))?;
+	decode_base32(&secret)?;
+	if account.is_empty() {
+		return Err(invalid("no account name"));
+	}
+	Ok(OathCredential {
+		issuer: issuer.filter(|i| !i.is_empty()),
+		account,
+		secret,
+		kind,
+		algorithm,
+		digits,
+		period,
+		counter,
+		require_touch: false,
+	})
+}
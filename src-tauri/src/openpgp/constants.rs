@@ -0,0 +1,51 @@
+//! Constants, enums and data object tags for the OpenPGP card applet of pico-openpgp firmware.
+#![allow(unused)]
+
+// The OpenPGP Application ID (AID) prefix (RID D2 76 00 01 24, application 01)
+pub const OPENPGP_AID: &[u8] = &[0xD2, 0x76, 0x00, 0x01, 0x24, 0x01];
+
+// APDU Instructions
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenPgpInstruction {
+	Verify = 0x20,
+	ChangeReferenceData = 0x24,
+	ActivateFile = 0x44,
+	GetChallenge = 0x84,
+	GetData = 0xCA,
+	GetResponse = 0xC0,
+	PutData = 0xDA,
+	TerminateDf = 0xE6,
+}
+
+// PIN references for Verify (0x20) and Change Reference Data (0x24)
+/// PW1 for signing (mode 81)
+pub const OPENPGP_PW1_SIGN_REF: u8 = 0x81;
+/// PW1 for decryption and authentication (mode 82)
+pub const OPENPGP_PW1_OTHER_REF: u8 = 0x82;
+/// PW3, the admin PIN
+pub const OPENPGP_PW3_REF: u8 = 0x83;
+
+// Minimum PIN lengths of the specification
+pub const OPENPGP_PW1_MIN_LEN: usize = 6;
+pub const OPENPGP_PW3_MIN_LEN: usize = 8;
+pub const OPENPGP_PIN_MAX_LEN: usize = 127;
+
+// Data Objects (OpenPGP card specification 3.4, section 4.4.1)
+#[repr(u16)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenPgpDataObject {
+	ApplicationIdentifier = 0x004F,
+	Name = 0x005B,
+	LoginData = 0x005E,
+	CardholderRelatedData = 0x0065,
+	ApplicationRelatedData = 0x006E,
+	SecuritySupportTemplate = 0x007A,
+	DigitalSignatureCounter = 0x0093,
+	PwStatusBytes = 0x00C4,
+	Fingerprints = 0x00C5,
+	KeyGenerationDates = 0x00CD,
+	ResettingCode = 0x00D3,
+	LanguagePreferences = 0x5F2D,
+	Url = 0x5F50,
+}
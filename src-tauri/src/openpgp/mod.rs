@@ -0,0 +1,392 @@
+//! Implements communication with the OpenPGP card applet of the pico-openpgp firmware over PC/SC.
+//!
+//! For more details checkout the [pico-openpgp](https://github.com/polhenarejos/pico-openpgp) sources.
+
+pub mod constants;
+
+use crate::{
+	card,
+	error::PFError,
+	openpgp::constants::*,
+	operation::report_phase,
+	rescue::constants::APDU_CLA_ISO,
+	types::{OpenPgpCardholder, OpenPgpKeyInfo, OpenPgpPin, OpenPgpStatus, PhaseVerb, UserAction},
+};
+
+/// Connects to the first available reader and selects the OpenPGP Applet
+fn connect_and_select() -> Result<pcsc::Card, PFError> {
+	let (card, _) = card::connect_and_select(OPENPGP_AID, "OpenPGP")?;
+	Ok(card)
+}
+
+/// Reads a data object with GET DATA
+fn get_data(card: &pcsc::Card, object: OpenPgpDataObject) -> Result<Vec<u8>, PFError> {
+	// APDU: 00 CA [Tag] [Tag] 00
+	let [tag_hi, tag_lo] = (object as u16).to_be_bytes();
+	let apdu = [
+		APDU_CLA_ISO,
+		OpenPgpInstruction::GetData as u8,
+		tag_hi,
+		tag_lo,
+		0x00,
+	];
+
+	let resp = card::transmit(card, &apdu)?;
+	match resp.sw {
+		0x9000 => Ok(resp.data),
+		0x6A88 => Err(PFError::Device(format!(
+			"OpenPGP data object {:?} not available",
+			object
+		))),
+		sw => Err(PFError::Device(format!(
+			"OpenPGP GET DATA {:?} failed: {:04X}",
+			object, sw
+		))),
+	}
+}
+
+/// Reads a data object that may be absent, returning it empty then
+fn get_optional_data(card: &pcsc::Card, object: OpenPgpDataObject) -> Result<Vec<u8>, PFError> {
+	match get_data(card, object) {
+		Err(PFError::Device(e)) => {
+			log::debug!("{}", e);
+			Ok(Vec::new())
+		}
+		result => result,
+	}
+}
+
+/// Writes a data object with PUT DATA, PW3 must have been verified
+fn put_data(card: &pcsc::Card, object: OpenPgpDataObject, value: &[u8]) -> Result<(), PFError> {
+	if value.len() > 255 {
+		return Err(PFError::Io(format!("{:?} is too long", object)));
+	}
+	// APDU: 00 DA [Tag] [Tag] [Lc] [Value]
+	let [tag_hi, tag_lo] = (object as u16).to_be_bytes();
+	let mut apdu = vec![
+		APDU_CLA_ISO,
+		OpenPgpInstruction::PutData as u8,
+		tag_hi,
+		tag_lo,
+		value.len() as u8,
+	];
+	apdu.extend_from_slice(value);
+
+	let resp = card::transmit(card, &apdu)?;
+	match resp.sw {
+		0x9000 => Ok(()),
+		0x6982 => Err(PFError::Device("Admin PIN not verified".into())),
+		sw => Err(PFError::Device(format!(
+			"OpenPGP PUT DATA {:?} failed: {:04X}",
+			object, sw
+		))),
+	}
+}
+
+fn pin_name(reference: u8) -> &'static str {
+	match reference {
+		OPENPGP_PW3_REF => "admin PIN",
+		_ => "user PIN",
+	}
+}
+
+/// Error of a failed VERIFY or CHANGE REFERENCE DATA
+fn pin_error(reference: u8, sw: u16) -> PFError {
+	match sw {
+		0x6983 => PFError::Device(format!("OpenPGP {} is blocked", pin_name(reference))),
+		sw if sw & 0xFFF0 == 0x63C0 => PFError::Device(format!(
+			"Wrong OpenPGP {} ({} retries left)",
+			pin_name(reference),
+			sw & 0x000F
+		)),
+		0x6982 => PFError::Device(format!("Wrong OpenPGP {}", pin_name(reference))),
+		sw => PFError::Device(format!(
+			"OpenPGP {} verification failed: {:04X}",
+			pin_name(reference),
+			sw
+		)),
+	}
+}
+
+fn verify_pin(card: &pcsc::Card, reference: u8, pin: &str) -> Result<(), PFError> {
+	report_phase(
+		PhaseVerb::Authenticate,
+		&format!("OpenPGP {}", pin_name(reference)),
+		UserAction::None,
+	);
+	// APDU: 00 20 00 [Ref] [Lc] [PIN]
+	let mut apdu = vec![
+		APDU_CLA_ISO,
+		OpenPgpInstruction::Verify as u8,
+		0x00,
+		reference,
+		pin.len() as u8,
+	];
+	apdu.extend_from_slice(pin.as_bytes());
+
+	let resp = card::transmit(card, &apdu)?;
+	match resp.sw {
+		0x9000 => Ok(()),
+		sw => Err(pin_error(reference, sw)),
+	}
+}
+
+fn check_pin_length(pin: &str, min: usize, what: &str) -> Result<(), PFError> {
+	if !(min..=OPENPGP_PIN_MAX_LEN).contains(&pin.len()) {
+		return Err(PFError::Io(format!(
+			"The {} must be {} to {} characters long",
+			what, min, OPENPGP_PIN_MAX_LEN
+		)));
+	}
+	Ok(())
+}
+
+/// Big-endian unsigned integer of up to 4 bytes
+fn be_u32(bytes: &[u8]) -> u32 {
+	bytes.iter().fold(0u32, |acc, b| (acc << 8) | u32::from(*b))
+}
+
+/// Reads the digital signature counter, i.e. how many signatures the signing key produced.
+pub fn read_signature_counter() -> Result<u32, PFError> {
+	log::info!("Reading OpenPGP signature counter...");
+	let card = connect_and_select()?;
+	report_phase(
+		PhaseVerb::Read,
+		"OpenPGP signature counter",
+		UserAction::None,
+	);
+	let value = signature_counter(&card)?;
+	log::info!("OpenPGP signature counter: {}", value);
+	Ok(value)
+}
+
+fn signature_counter(card: &pcsc::Card) -> Result<u32, PFError> {
+	let template = get_data(card, OpenPgpDataObject::SecuritySupportTemplate)?;
+
+	// Response: [7A L] 93 03 [3 byte counter], the outer template is optional
+	let counter = card::find_tlv(&template, OpenPgpDataObject::DigitalSignatureCounter as u16)
+		.filter(|c| c.len() <= 4)
+		.ok_or_else(|| PFError::Device("Signature counter missing from OpenPGP card".into()))?;
+	Ok(be_u32(counter))
+}
+
+/// Reads the card status: identity, cardholder data, keys, counters and PIN retries
+pub fn read_status() -> Result<OpenPgpStatus, PFError> {
+	log::info!("Reading OpenPGP card status...");
+	let card = connect_and_select()?;
+	report_phase(PhaseVerb::Read, "OpenPGP card status", UserAction::None);
+
+	let app_data = get_data(&card, OpenPgpDataObject::ApplicationRelatedData)?;
+	let find = |object: OpenPgpDataObject| card::find_tlv(&app_data, object as u16);
+
+	// AID: D2 76 00 01 24 01 [Version (2)] [Manufacturer (2)] [Serial (4)] 00 00
+	let aid = find(OpenPgpDataObject::ApplicationIdentifier)
+		.filter(|aid| aid.len() >= 14)
+		.ok_or_else(|| {
+			PFError::Device("Application identifier missing from OpenPGP card".into())
+		})?;
+
+	// PW status: [PW1 validity] [Max lengths (3)] [PW1 retries] [RC retries] [PW3 retries]
+	let pw_status = find(OpenPgpDataObject::PwStatusBytes)
+		.filter(|s| s.len() >= 7)
+		.ok_or_else(|| PFError::Device("PIN status missing from OpenPGP card".into()))?;
+
+	// Fingerprints and generation dates, signature, decryption then authentication key
+	let fingerprints = find(OpenPgpDataObject::Fingerprints).unwrap_or_default();
+	let dates = find(OpenPgpDataObject::KeyGenerationDates).unwrap_or_default();
+	let keys = ["signature", "decryption", "authentication"]
+		.iter()
+		.enumerate()
+		.map(|(i, slot)| OpenPgpKeyInfo {
+			slot: slot.to_string(),
+			fingerprint: fingerprints
+				.get(i * 20..(i + 1) * 20)
+				.filter(|fp| fp.iter().any(|b| *b != 0))
+				.map(hex::encode_upper),
+			created_at: dates
+				.get(i * 4..(i + 1) * 4)
+				.map(be_u32)
+				.filter(|date| *date != 0),
+		})
+		.collect();
+
+	let cardholder = get_optional_data(&card, OpenPgpDataObject::CardholderRelatedData)?;
+	let text = |data: &[u8], object: OpenPgpDataObject| {
+		card::find_tlv(data, object as u16)
+			.map(|v| String::from_utf8_lossy(v).to_string())
+			.unwrap_or_default()
+	};
+	let url = get_optional_data(&card, OpenPgpDataObject::Url)?;
+	let login = get_optional_data(&card, OpenPgpDataObject::LoginData)?;
+
+	Ok(OpenPgpStatus {
+		version: format!("{}.{}", aid[6], aid[7]),
+		manufacturer: hex::encode_upper(&aid[8..10]),
+		serial: hex::encode_upper(&aid[10..14]),
+		cardholder_name: text(&cardholder, OpenPgpDataObject::Name),
+		language: text(&cardholder, OpenPgpDataObject::LanguagePreferences),
+		url: String::from_utf8_lossy(&url).to_string(),
+		login: String::from_utf8_lossy(&login).to_string(),
+		keys,
+		signature_counter: signature_counter(&card).unwrap_or_else(|e| {
+			log::warn!("{}", e);
+			0
+		}),
+		pw1_retries: pw_status[4],
+		reset_code_retries: pw_status[5],
+		pw3_retries: pw_status[6],
+	})
+}
+
+/// Changes a PIN. For the Resetting Code, `current_pin` is the admin PIN.
+pub fn change_pin(pin: OpenPgpPin, current_pin: &str, new_pin: &str) -> Result<(), PFError> {
+	log::info!("Changing OpenPGP {:?} PIN...", pin);
+	let card = connect_and_select()?;
+
+	let reference = match pin {
+		OpenPgpPin::User => {
+			check_pin_length(new_pin, OPENPGP_PW1_MIN_LEN, "user PIN")?;
+			OPENPGP_PW1_SIGN_REF
+		}
+		OpenPgpPin::Admin => {
+			check_pin_length(new_pin, OPENPGP_PW3_MIN_LEN, "admin PIN")?;
+			OPENPGP_PW3_REF
+		}
+		OpenPgpPin::ResetCode => {
+			check_pin_length(new_pin, OPENPGP_PW3_MIN_LEN, "Resetting Code")?;
+			verify_pin(&card, OPENPGP_PW3_REF, current_pin)?;
+			report_phase(PhaseVerb::Write, "OpenPGP Resetting Code", UserAction::None);
+			return put_data(&card, OpenPgpDataObject::ResettingCode, new_pin.as_bytes());
+		}
+	};
+
+	report_phase(
+		PhaseVerb::Write,
+		&format!("OpenPGP {}", pin_name(reference)),
+		UserAction::None,
+	);
+	// APDU: 00 24 00 [Ref] [Lc] [Current PIN] [New PIN]
+	let mut apdu = vec![
+		APDU_CLA_ISO,
+		OpenPgpInstruction::ChangeReferenceData as u8,
+		0x00,
+		reference,
+		(current_pin.len() + new_pin.len()) as u8,
+	];
+	apdu.extend_from_slice(current_pin.as_bytes());
+	apdu.extend_from_slice(new_pin.as_bytes());
+
+	let resp = card::transmit(&card, &apdu)?;
+	match resp.sw {
+		0x9000 => Ok(()),
+		sw => Err(pin_error(reference, sw)),
+	}
+}
+
+/// Writes the given cardholder data, authorized by the admin PIN
+pub fn set_cardholder(admin_pin: &str, cardholder: &OpenPgpCardholder) -> Result<(), PFError> {
+	log::info!("Writing OpenPGP cardholder data...");
+	let card = connect_and_select()?;
+	verify_pin(&card, OPENPGP_PW3_REF, admin_pin)?;
+	report_phase(
+		PhaseVerb::Write,
+		"OpenPGP cardholder data",
+		UserAction::None,
+	);
+
+	let fields = [
+		(OpenPgpDataObject::Name, &cardholder.name),
+		(OpenPgpDataObject::LanguagePreferences, &cardholder.language),
+		(OpenPgpDataObject::Url, &cardholder.url),
+		(OpenPgpDataObject::LoginData, &cardholder.login),
+	];
+	for (object, value) in fields {
+		if let Some(value) = value {
+			put_data(&card, object, value.as_bytes())?;
+		}
+	}
+	Ok(())
+}
+
+/// Factory resets the OpenPGP applet, deleting its keys and restoring the default PINs.
+///
+/// The card only accepts the reset once the admin PIN is blocked, so both PINs are blocked first
+/// with wrong attempts. Fails unless `confirm` is set.
+pub fn reset(confirm: bool) -> Result<(), PFError> {
+	if !confirm {
+		return Err(PFError::Io(
+			"Resetting the OpenPGP applet deletes all its keys, confirm to go on".into(),
+		));
+	}
+	log::warn!("Factory resetting the OpenPGP applet...");
+	let card = connect_and_select()?;
+	report_phase(PhaseVerb::Delete, "OpenPGP keys", UserAction::KeepConnected);
+
+	// Long enough to count as an attempt. Should it be the actual PIN, the reset is allowed too.
+	const WRONG_PIN: &str = "~~~~~~~~";
+	for reference in [OPENPGP_PW1_OTHER_REF, OPENPGP_PW3_REF] {
+		for _ in 0..16 {
+			match verify_pin(&card, reference, WRONG_PIN) {
+				Err(PFError::Device(e)) if e.starts_with("Wrong") => continue,
+				_ => break,
+			}
+		}
+	}
+
+	for instruction in [
+		OpenPgpInstruction::TerminateDf,
+		OpenPgpInstruction::ActivateFile,
+	] {
+		// APDU: 00 E6 00 00, then 00 44 00 00
+		let apdu = [APDU_CLA_ISO, instruction as u8, 0x00, 0x00];
+		let resp = card::transmit(&card, &apdu)?;
+		if resp.sw != 0x9000 {
+			return Err(PFError::Device(format!(
+				"OpenPGP {:?} failed: {:04X}",
+				instruction, resp.sw
+			)));
+		}
+	}
+	log::info!("OpenPGP applet reset");
+	Ok(())
+}
+
+/// Random bytes requested per GET CHALLENGE
+const CHALLENGE_CHUNK: usize = 128;
+
+/// Reads `len` bytes from the device RNG with GET CHALLENGE. `progress` gets the bytes read so far.
+pub fn get_challenge(len: usize, mut progress: impl FnMut(usize)) -> Result<Vec<u8>, PFError> {
+	log::info!("Reading {} random bytes from the OpenPGP applet...", len);
+	let card = connect_and_select()?;
+	report_phase(PhaseVerb::Read, "random numbers", UserAction::None);
+
+	let mut random = Vec::with_capacity(len);
+	while random.len() < len {
+		let chunk = CHALLENGE_CHUNK.min(len - random.len());
+		// APDU: 00 84 00 00 [Le]
+		let apdu = [
+			APDU_CLA_ISO,
+			OpenPgpInstruction::GetChallenge as u8,
+			0x00,
+			0x00,
+			chunk as u8,
+		];
+		let resp = card::transmit(&card, &apdu)?;
+		if resp.sw != 0x9000 {
+			return Err(PFError::Device(format!(
+				"OpenPGP GET CHALLENGE failed: {:04X}",
+				resp.sw
+			)));
+		}
+		if resp.data.len() != chunk {
+			return Err(PFError::Device(format!(
+				"GET CHALLENGE returned {} bytes instead of {}",
+				resp.data.len(),
+				chunk
+			)));
+		}
+		random.extend_from_slice(&resp.data);
+		progress(random.len());
+	}
+	Ok(random)
+}
@@ -0,0 +1,118 @@
+//! What the device operation running on the current thread is doing.
+//!
+//! Device code describes its phases with `report_phase`, forwards the keepalive status of the key
+//! and its progress, and checks `cancel_requested` while it waits, without having to know who runs
+//! it. Whoever runs the operation, the `DeviceManager` of the app or the CLI, receives the reports
+//! through the `Observer` it passed to `run`. Outside of an operation they go nowhere.
+
+use crate::types::*;
+use std::cell::RefCell;
+
+/// Receives the reports of the operation running on this thread
+pub trait Observer {
+	fn phase(&self, phase: &OperationPhase);
+	fn keepalive(&self, keepalive: &OperationKeepalive);
+	fn progress(&self, progress: &OperationProgress);
+	/// Whether the operation was asked to stop
+	fn cancelled(&self) -> bool;
+}
+
+thread_local! {
+	/// ID and observer of the operation running on this thread
+	static CURRENT_OPERATION: RefCell<Option<(String, Box<dyn Observer>)>> =
+		const { RefCell::new(None) };
+}
+
+/// Runs `f` as the operation `id`, sending its reports to `observer`
+#[cfg(feature = "gui")]
+pub fn run<T>(id: &str, observer: Box<dyn Observer>, f: impl FnOnce() -> T) -> T {
+	CURRENT_OPERATION.with(|c| *c.borrow_mut() = Some((id.to_string(), observer)));
+	let result = f();
+	CURRENT_OPERATION.with(|c| *c.borrow_mut() = None);
+	result
+}
+
+/// ID of the operation running on this thread
+#[cfg(feature = "gui")]
+pub fn current_id() -> Option<String> {
+	CURRENT_OPERATION.with(|c| c.borrow().as_ref().map(|(id, _)| id.clone()))
+}
+
+fn describe(verb: PhaseVerb, object: &str, user_action: UserAction) -> String {
+	let (infinitive, gerund) = match verb {
+		PhaseVerb::Connect => ("connect to", "Connecting to"),
+		PhaseVerb::Read => ("read", "Reading"),
+		PhaseVerb::Write => ("write", "Writing"),
+		PhaseVerb::Create => ("create", "Creating"),
+		PhaseVerb::Delete => ("delete", "Deleting"),
+		PhaseVerb::Authenticate => ("verify the PIN for", "Verifying the PIN for"),
+		PhaseVerb::Sign => ("sign with", "Signing with"),
+		PhaseVerb::Verify => ("verify", "Verifying"),
+		PhaseVerb::Reboot => ("reboot", "Rebooting"),
+	};
+	match user_action {
+		UserAction::None => format!("{} {}", gerund, object),
+		UserAction::TouchDevice => format!("Touch the blinking key to {} {}", infinitive, object),
+		UserAction::KeepConnected => format!("{} {}, keep the key connected", gerund, object),
+		UserAction::Reconnect => format!("Reconnect the key to {} {}", infinitive, object),
+		UserAction::TouchSensor => {
+			format!("Touch the fingerprint sensor to {} {}", infinitive, object)
+		}
+	}
+}
+
+/// Describes the phase the current operation entered. Does nothing outside of an operation.
+pub fn report_phase(verb: PhaseVerb, object: &str, user_action: UserAction) {
+	CURRENT_OPERATION.with(|c| {
+		let Some((operation_id, observer)) = &*c.borrow() else {
+			return;
+		};
+		observer.phase(&OperationPhase {
+			operation_id: operation_id.clone(),
+			verb,
+			object: object.to_string(),
+			user_action,
+			description: describe(verb, object, user_action),
+		});
+	});
+}
+
+/// Reports what the key says it is doing while the operation running on this thread waits for
+/// it. Sent by the HID transport whenever the keepalive status changes.
+pub fn report_keepalive(status: KeepaliveStatus) {
+	CURRENT_OPERATION.with(|c| {
+		let Some((operation_id, observer)) = &*c.borrow() else {
+			return;
+		};
+		observer.keepalive(&OperationKeepalive {
+			operation_id: operation_id.clone(),
+			status,
+		});
+	});
+}
+
+/// Reports how far the operation running on this thread got, `current` out of `total` steps
+pub fn report_progress(message: String, current: u32, total: u32) {
+	CURRENT_OPERATION.with(|c| {
+		let Some((operation_id, observer)) = &*c.borrow() else {
+			return;
+		};
+		observer.progress(&OperationProgress {
+			operation_id: operation_id.clone(),
+			message,
+			current,
+			total,
+		});
+	});
+}
+
+/// Whether the operation running on this thread was asked to stop.
+///
+/// For device code waiting on the key. Always false outside of an operation.
+pub fn cancel_requested() -> bool {
+	CURRENT_OPERATION.with(|c| {
+		c.borrow()
+			.as_ref()
+			.is_some_and(|(_, observer)| observer.cancelled())
+	})
+}
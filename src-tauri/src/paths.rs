@@ -0,0 +1,26 @@
+//! Locations of the files PicoForge keeps on the host.
+
+use directories::ProjectDirs;
+use std::path::PathBuf;
+
+const QUALIFIER: &str = "in";
+const ORGANIZATION: &str = "suyogtandel";
+const APPLICATION: &str = "picoforge";
+
+/// Local data directory of the application, created on demand.
+///
+/// Falls back to the working directory if the platform directories cannot be determined.
+pub fn data_dir() -> PathBuf {
+	let dir = match ProjectDirs::from(QUALIFIER, ORGANIZATION, APPLICATION) {
+		Some(proj_dirs) => proj_dirs.data_local_dir().to_path_buf(),
+		None => {
+			log::warn!("Could not determine project directories. Falling back to local directory.");
+			PathBuf::from(".")
+		}
+	};
+
+	if let Err(e) = std::fs::create_dir_all(&dir) {
+		log::error!("Failed to create data directory at {:?}: {}", dir, e);
+	}
+	dir
+}
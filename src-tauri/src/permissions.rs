@@ -0,0 +1,324 @@
+//! Allowlist of the views commands may be invoked from.
+//!
+//! Every view runs in a window of its own, opened by the backend with the label `view-<context>`.
+//! The label is fixed when the window is created and the page cannot change it, so script
+//! injected into one view cannot invoke the destructive commands of another. Guarded commands
+//! invoked from any other window, e.g. the main window, are rejected.
+//!
+//! Every invocation goes through `check` before it reaches its command. Only commands that read
+//! the device or the host may be invoked from every window, a command missing from both lists is
+//! rejected everywhere.
+
+#[cfg(feature = "gui")]
+use crate::error::PFError;
+use crate::types::UiContext;
+#[cfg(feature = "gui")]
+use tauri::{AppHandle, Manager, Runtime, WebviewUrl, WebviewWindowBuilder, ipc::Invoke};
+
+/// Label prefix of the windows showing a single view
+const VIEW_LABEL_PREFIX: &str = "view-";
+/// Label of the window the application starts with, showing the home view
+#[cfg(feature = "gui")]
+const MAIN_WINDOW: &str = "main";
+
+/// Views each guarded command may be invoked from
+const ALLOWLIST: &[(&str, &[UiContext])] = &[
+	("write_config", &[UiContext::Config]),
+	("start_commissioning", &[UiContext::Config]),
+	("change_fido_pin", &[UiContext::Config, UiContext::Security]),
+	(
+		"set_min_pin_length",
+		&[UiContext::Config, UiContext::Security],
+	),
+	(
+		"force_pin_change",
+		&[UiContext::Config, UiContext::Security],
+	),
+	("set_always_uv", &[UiContext::Config, UiContext::Security]),
+	("delete_credential", &[UiContext::Passkeys]),
+	("update_credential", &[UiContext::Passkeys]),
+	("delete_fingerprint", &[UiContext::Security]),
+	("reset_device", &[UiContext::Security]),
+	("reprovision", &[UiContext::Security]),
+	("enable_secure_boot", &[UiContext::Security]),
+	("enable_secure_lock", &[UiContext::Security]),
+	("enable_enterprise_attestation", &[UiContext::Security]),
+	(
+		"upload_enterprise_attestation_certificate",
+		&[UiContext::Security],
+	),
+	("flash_firmware", &[UiContext::Firmware]),
+	("start_firmware_update", &[UiContext::Firmware]),
+	("reset_openpgp", &[UiContext::OpenPgp]),
+	("change_openpgp_pin", &[UiContext::OpenPgp]),
+	("generate_piv_key", &[UiContext::Piv]),
+	("import_piv_certificate", &[UiContext::Piv]),
+	("change_piv_pin", &[UiContext::Piv]),
+	("purge_logs", &[UiContext::Logs]),
+	(
+		"restore_settings_backup",
+		&[UiContext::About, UiContext::Security],
+	),
+	("create_device_backup", &[UiContext::Security]),
+	("restore_device_backup", &[UiContext::Security]),
+	("send_raw_apdu", &[UiContext::Developer]),
+	("send_raw_vendor", &[UiContext::Developer]),
+	(
+		"generate_enterprise_attestation_csr",
+		&[UiContext::Security],
+	),
+	("save_config_profile", &[UiContext::Config]),
+	("apply_config_profile", &[UiContext::Config]),
+	("test_led", &[UiContext::Config]),
+	("stop_commissioning", &[UiContext::Config]),
+	("export_commissioning_log", &[UiContext::Config]),
+	("generate_commissioning_report", &[UiContext::Config]),
+	("export_credentials", &[UiContext::Passkeys]),
+	("derive_hmac_secret", &[UiContext::Passkeys]),
+	("save_workspace", &[UiContext::Passkeys]),
+	("delete_workspace", &[UiContext::Passkeys]),
+	("run_self_test", &[UiContext::Security]),
+	("enroll_fingerprint", &[UiContext::Security]),
+	("rename_fingerprint", &[UiContext::Security]),
+	("set_org_pin_policy", &[UiContext::Security]),
+	(
+		"reboot",
+		&[UiContext::Config, UiContext::Security, UiContext::Firmware],
+	),
+	("add_oath_account", &[UiContext::Security]),
+	("delete_oath_account", &[UiContext::Security]),
+	("set_oath_password", &[UiContext::Security]),
+	("add_secure_note", &[UiContext::Security]),
+	("delete_secure_note", &[UiContext::Security]),
+	(
+		"export_settings_backup",
+		&[UiContext::About, UiContext::Security],
+	),
+	("sign_ssh_certificate", &[UiContext::Security]),
+	("generate_self_signed_certificate", &[UiContext::Security]),
+	("set_openpgp_cardholder", &[UiContext::OpenPgp]),
+	("resume_job", &[UiContext::Firmware]),
+	("set_training_mode", &[UiContext::Developer]),
+	("set_fault_injection", &[UiContext::Developer]),
+	("load_firmware_schema", &[UiContext::Developer]),
+	("add_device_note", &[UiContext::About, UiContext::Security]),
+	(
+		"delete_device_note",
+		&[UiContext::About, UiContext::Security],
+	),
+	("intake_check", &[UiContext::Security]),
+	("set_reminder_settings", &[UiContext::Security]),
+	("mark_audit_done", &[UiContext::Security]),
+	("start_migration", &[UiContext::Security]),
+	("set_migration_item_done", &[UiContext::Security]),
+	("delete_migration", &[UiContext::Security]),
+	("quarantine_device", &[UiContext::Security]),
+	("release_device", &[UiContext::Security]),
+	("set_session_policy", &[UiContext::Security]),
+	("set_log_retention", &[UiContext::Logs]),
+	("export_logs", &[UiContext::Logs]),
+	("unlock_developer_mode", &[UiContext::Developer]),
+];
+
+/// Commands any window may invoke, since they only read the device or the host, or cannot do
+/// harm, like locking the session
+const UNGUARDED: &[&str] = &[
+	"list_devices",
+	"read_device_details",
+	"get_device_state",
+	"get_vendor_capabilities",
+	"get_attestation_info",
+	"get_enterprise_attestation",
+	"run_rng_diagnostic",
+	"load_config_profile",
+	"validate_led_config",
+	"get_commissioning_session",
+	"verify_commissioning_report",
+	"get_saved_device_config",
+	"export_config_as_cli",
+	"get_fido_info",
+	"get_pin_status",
+	"get_api_version",
+	"get_credentials",
+	"compare_credential_export",
+	"get_bio_sensor_info",
+	"list_fingerprints",
+	"match_registration",
+	"get_pin_policy",
+	"get_org_pin_policy",
+	"check_totp_drift",
+	"list_oath_accounts",
+	"parse_otpauth_uri",
+	"calculate_oath_codes",
+	"calculate_oath_code",
+	"list_workspaces",
+	"list_secure_notes",
+	"get_credential_sign_count",
+	"verify_credential",
+	"get_openpgp_signature_counter",
+	"get_openpgp_status",
+	"export_piv_certificate",
+	"open_view",
+	"list_jobs",
+	"current_operation",
+	"get_activity",
+	"cancel_operation",
+	"get_training_mode",
+	"get_fault_injection",
+	"get_firmware_schema",
+	"label_vendor_response",
+	"label_phy_response",
+	"get_device_history",
+	"list_inventory_devices",
+	"get_intake_report",
+	"list_intake_batches",
+	"get_reminder_settings",
+	"list_due_audits",
+	"check_migration_target",
+	"get_migration",
+	"list_migrations",
+	"list_rescue_devices",
+	"check_firmware_update",
+	"list_quarantined_devices",
+	"get_session_policy",
+	"lock_session",
+	"get_log_retention",
+	"get_recent_logs",
+	"lock_developer_mode",
+	"get_developer_mode",
+];
+
+#[cfg(feature = "gui")]
+fn context_name(context: UiContext) -> String {
+	serde_json::to_value(context)
+		.ok()
+		.and_then(|name| name.as_str().map(str::to_string))
+		.unwrap_or_default()
+}
+
+/// View of the window labelled `label`, `None` for windows not opened by `open_view`
+fn window_context(label: &str) -> Option<UiContext> {
+	let name = label.strip_prefix(VIEW_LABEL_PREFIX)?;
+	serde_json::from_value(serde_json::Value::String(name.to_string())).ok()
+}
+
+/// Shows `context` in a window of its own, focusing it if it is already open. The home view
+/// belongs to the main window.
+#[cfg(feature = "gui")]
+pub fn open_view(app: &AppHandle, context: UiContext) -> Result<(), PFError> {
+	let label = match context {
+		UiContext::Home => MAIN_WINDOW.to_string(),
+		_ => format!("{}{}", VIEW_LABEL_PREFIX, context_name(context)),
+	};
+	if let Some(window) = app.get_webview_window(&label) {
+		return window
+			.set_focus()
+			.map_err(|e| PFError::Io(format!("Failed to focus the {} window: {}", label, e)));
+	}
+	if context == UiContext::Home {
+		return Err(PFError::Io("The main window is closed".into()));
+	}
+
+	log::debug!("Opening the {:?} view in window {}", context, label);
+	WebviewWindowBuilder::new(app, &label, WebviewUrl::default())
+		.title(format!("picoforge - {:?}", context))
+		.inner_size(1280.0, 720.0)
+		.min_inner_size(500.0, 250.0)
+		.decorations(false)
+		.build()
+		.map(|_| ())
+		.map_err(|e| PFError::Io(format!("Failed to open the {:?} view: {}", context, e)))
+}
+
+/// Whether the window labelled `label` may invoke `command`. On rejection, returns the view of
+/// the window.
+fn permitted(label: &str, command: &str) -> Result<(), Option<UiContext>> {
+	if UNGUARDED.contains(&command) {
+		return Ok(());
+	}
+	let context = window_context(label);
+	let allowed = ALLOWLIST
+		.iter()
+		.find(|(name, _)| *name == command)
+		.is_some_and(|(_, allowed)| context.is_some_and(|context| allowed.contains(&context)));
+	if allowed { Ok(()) } else { Err(context) }
+}
+
+/// Passes the invocation `invoke` on to `handler` if the window that sent it may invoke the
+/// command, rejects it otherwise
+#[cfg(feature = "gui")]
+pub fn check<R: Runtime>(invoke: Invoke<R>, handler: impl Fn(Invoke<R>) -> bool) -> bool {
+	let command = invoke.message.command().to_string();
+	let label = invoke.message.webview().label().to_string();
+	match permitted(&label, &command) {
+		Ok(()) => handler(invoke),
+		Err(context) => {
+			log::warn!("Rejected {} invoked from window {}", command, label);
+			invoke
+				.resolver
+				.reject(PFError::CommandNotPermitted { command, context });
+			true
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Names of the commands registered with `generate_handler!`
+	fn registered_commands() -> Vec<&'static str> {
+		let lib = include_str!("lib.rs");
+		let start = lib.find("generate_handler![").expect("no command handler") + 18;
+		let end = start + lib[start..].find(']').expect("unterminated command list");
+		lib[start..end]
+			.split(',')
+			.map(str::trim)
+			.filter(|path| !path.is_empty())
+			.map(|path| path.rsplit("::").next().unwrap_or(path))
+			.collect()
+	}
+
+	#[test]
+	fn every_command_is_listed_once() {
+		let commands = registered_commands();
+		assert!(commands.len() > 100);
+		for command in &commands {
+			let guarded = ALLOWLIST.iter().filter(|(name, _)| name == command).count();
+			let unguarded = UNGUARDED.iter().filter(|name| *name == command).count();
+			assert_eq!(
+				guarded + unguarded,
+				1,
+				"{} has to be listed exactly once",
+				command
+			);
+		}
+		for (name, _) in ALLOWLIST {
+			assert!(commands.contains(name), "{} is not a command", name);
+		}
+		for name in UNGUARDED {
+			assert!(commands.contains(name), "{} is not a command", name);
+		}
+	}
+
+	#[test]
+	fn unlisted_commands_are_rejected_everywhere() {
+		assert_eq!(
+			permitted("view-security", "no_such_command"),
+			Err(Some(UiContext::Security))
+		);
+		assert_eq!(permitted("main", "no_such_command"), Err(None));
+	}
+
+	#[test]
+	fn guarded_commands_need_their_view() {
+		assert_eq!(permitted("view-security", "release_device"), Ok(()));
+		assert_eq!(
+			permitted("view-logs", "release_device"),
+			Err(Some(UiContext::Logs))
+		);
+		assert_eq!(permitted("main", "release_device"), Err(None));
+		assert_eq!(permitted("view-logs", "list_devices"), Ok(()));
+		assert_eq!(permitted("main", "list_devices"), Ok(()));
+	}
+}
@@ -0,0 +1,89 @@
+//! Constants and enums for the PIV applet of pico-openpgp firmware (NIST SP 800-73-4).
+#![allow(unused)]
+
+// The PIV Application ID (AID), RID A0 00 00 03 08 and PIX 00 00 10 00 01 00
+pub const PIV_AID: &[u8] = &[
+	0xA0, 0x00, 0x00, 0x03, 0x08, 0x00, 0x00, 0x10, 0x00, 0x01, 0x00,
+];
+
+// APDU Instructions
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PivInstruction {
+	Verify = 0x20,
+	ChangeReferenceData = 0x24,
+	GenerateAsymmetricKeyPair = 0x47,
+	GeneralAuthenticate = 0x87,
+	GetData = 0xCB,
+	PutData = 0xDB,
+}
+
+/// CLA bit signalling that more command chunks follow
+pub const PIV_CLA_CHAINING: u8 = 0x10;
+
+/// Largest command data sent in one chained APDU
+pub const PIV_CHUNK: usize = 0xFF;
+
+// Key references for Verify (0x20), Change Reference Data (0x24) and General Authenticate (0x87)
+pub const PIV_PIN_REF: u8 = 0x80;
+pub const PIV_PUK_REF: u8 = 0x81;
+pub const PIV_MANAGEMENT_KEY_REF: u8 = 0x9B;
+
+/// PINs and PUKs are padded with 0xFF to 8 bytes
+pub const PIV_PIN_LEN: usize = 8;
+pub const PIV_PIN_MIN_LEN: usize = 6;
+
+/// Factory default management key (3DES)
+pub const PIV_DEFAULT_MANAGEMENT_KEY: &[u8] = &[
+	0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+	0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+];
+
+// Data object tags (SP 800-73-4 part 1 and 2)
+/// Dynamic authentication template of General Authenticate
+pub const PIV_TAG_DYNAMIC_AUTH: u8 = 0x7C;
+pub const PIV_TAG_WITNESS: u8 = 0x80;
+pub const PIV_TAG_CHALLENGE: u8 = 0x81;
+pub const PIV_TAG_RESPONSE: u8 = 0x82;
+/// Control reference template of Generate Asymmetric Key Pair
+pub const PIV_TAG_CONTROL_REFERENCE: u8 = 0xAC;
+pub const PIV_TAG_ALGORITHM: u8 = 0x80;
+pub const PIV_TAG_PUBLIC_KEY: u16 = 0x7F49;
+pub const PIV_TAG_MODULUS: u16 = 0x81;
+pub const PIV_TAG_EXPONENT: u16 = 0x82;
+pub const PIV_TAG_POINT: u16 = 0x86;
+/// Tag list of Get Data and Put Data
+pub const PIV_TAG_OBJECT_ID: u8 = 0x5C;
+pub const PIV_TAG_DATA: u8 = 0x53;
+pub const PIV_TAG_CERTIFICATE: u16 = 0x70;
+pub const PIV_TAG_CERT_INFO: u8 = 0x71;
+pub const PIV_TAG_LRC: u8 = 0xFE;
+
+// Key slots, used as P2 of Generate Asymmetric Key Pair
+pub const PIV_SLOT_AUTHENTICATION: u8 = 0x9A;
+pub const PIV_SLOT_SIGNATURE: u8 = 0x9C;
+pub const PIV_SLOT_KEY_MANAGEMENT: u8 = 0x9D;
+pub const PIV_SLOT_CARD_AUTHENTICATION: u8 = 0x9E;
+
+// Certificate data objects of the slots above
+pub const PIV_OBJECT_CERT_AUTHENTICATION: [u8; 3] = [0x5F, 0xC1, 0x05];
+pub const PIV_OBJECT_CERT_SIGNATURE: [u8; 3] = [0x5F, 0xC1, 0x0A];
+pub const PIV_OBJECT_CERT_KEY_MANAGEMENT: [u8; 3] = [0x5F, 0xC1, 0x0B];
+pub const PIV_OBJECT_CERT_CARD_AUTHENTICATION: [u8; 3] = [0x5F, 0xC1, 0x01];
+
+/// P1 and P2 of Get Data and Put Data
+pub const PIV_DATA_P1: u8 = 0x3F;
+pub const PIV_DATA_P2: u8 = 0xFF;
+
+// Algorithm identifiers (SP 800-78-4 table 6-2)
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PivAlgorithmId {
+	TripleDes = 0x03,
+	Rsa2048 = 0x07,
+	Aes128 = 0x08,
+	Aes192 = 0x0A,
+	Aes256 = 0x0C,
+	EccP256 = 0x11,
+	EccP384 = 0x14,
+}
@@ -0,0 +1,429 @@
+//! Implements communication with the PIV applet of the pico-openpgp firmware over PC/SC.
+//!
+//! Key generation and certificate import need the management key, which is authenticated with the
+//! mutual challenge-response of General Authenticate. For more details checkout NIST SP 800-73-4.
+
+pub mod constants;
+
+use crate::{
+	card,
+	error::PFError,
+	hsm::x509::{certificate_der, to_pem},
+	operation::report_phase,
+	piv::constants::*,
+	rescue::constants::APDU_CLA_ISO,
+	types::{
+		PhaseVerb, PivCertificate, PivGeneratedKey, PivKeyAlgorithm, PivManagementKeyAlgorithm,
+		PivPin, PivSlot, UserAction,
+	},
+};
+use aes::cipher::{Block, BlockDecrypt, BlockEncrypt, KeyInit};
+use ring::rand::{SecureRandom, SystemRandom};
+use x509_parser::prelude::*;
+
+/// Connects to the first available reader and selects the PIV Applet
+fn connect_and_select() -> Result<pcsc::Card, PFError> {
+	let (card, _) = card::connect_and_select(PIV_AID, "PIV")?;
+	Ok(card)
+}
+
+/// Key reference and certificate data object of a slot
+fn slot_ids(slot: PivSlot) -> (u8, [u8; 3]) {
+	match slot {
+		PivSlot::Authentication => (PIV_SLOT_AUTHENTICATION, PIV_OBJECT_CERT_AUTHENTICATION),
+		PivSlot::Signature => (PIV_SLOT_SIGNATURE, PIV_OBJECT_CERT_SIGNATURE),
+		PivSlot::KeyManagement => (PIV_SLOT_KEY_MANAGEMENT, PIV_OBJECT_CERT_KEY_MANAGEMENT),
+		PivSlot::CardAuthentication => (
+			PIV_SLOT_CARD_AUTHENTICATION,
+			PIV_OBJECT_CERT_CARD_AUTHENTICATION,
+		),
+	}
+}
+
+/// BER-TLV with a one byte tag
+fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+	let mut out = vec![tag];
+	match value.len() {
+		len @ 0..=0x7F => out.push(len as u8),
+		len @ 0x80..=0xFF => out.extend_from_slice(&[0x81, len as u8]),
+		len => {
+			out.push(0x82);
+			out.extend_from_slice(&(len as u16).to_be_bytes());
+		}
+	}
+	out.extend_from_slice(value);
+	out
+}
+
+/// Sends a command, splitting its data over chained APDUs if it does not fit in one
+fn transmit_chained(
+	card: &pcsc::Card,
+	ins: PivInstruction,
+	p1: u8,
+	p2: u8,
+	data: &[u8],
+) -> Result<card::ApduResponse, PFError> {
+	let mut chunks = data.chunks(PIV_CHUNK).peekable();
+	loop {
+		let chunk = chunks.next().unwrap_or_default();
+		let last = chunks.peek().is_none();
+		// APDU: [CLA] [INS] [P1] [P2] [Lc] [Data] 00
+		let cla = if last {
+			APDU_CLA_ISO
+		} else {
+			APDU_CLA_ISO | PIV_CLA_CHAINING
+		};
+		let mut apdu = vec![cla, ins as u8, p1, p2, chunk.len() as u8];
+		apdu.extend_from_slice(chunk);
+		if last {
+			apdu.push(0x00);
+			return card::transmit(card, &apdu);
+		}
+
+		let resp = card::transmit(card, &apdu)?;
+		if resp.sw != 0x9000 {
+			return Ok(resp);
+		}
+	}
+}
+
+/// Runs one block through the cipher of the management key
+fn crypt_block<C: BlockEncrypt + BlockDecrypt + KeyInit>(
+	key: &[u8],
+	block: &[u8],
+	encrypt: bool,
+) -> Result<Vec<u8>, PFError> {
+	let cipher = C::new_from_slice(key)
+		.map_err(|_| PFError::Io("Management key has the wrong length".into()))?;
+	if block.len() != C::block_size() {
+		return Err(PFError::Device(format!(
+			"Expected a {} byte block from the PIV applet, got {}",
+			C::block_size(),
+			block.len()
+		)));
+	}
+	let mut block = Block::<C>::clone_from_slice(block);
+	if encrypt {
+		cipher.encrypt_block(&mut block);
+	} else {
+		cipher.decrypt_block(&mut block);
+	}
+	Ok(block.to_vec())
+}
+
+/// Encrypts (`true`) or decrypts one block with the given key
+type BlockCrypt = fn(&[u8], &[u8], bool) -> Result<Vec<u8>, PFError>;
+
+/// Algorithm identifier, key length and block cipher of a management key algorithm
+fn management_cipher(algorithm: PivManagementKeyAlgorithm) -> (PivAlgorithmId, usize, BlockCrypt) {
+	match algorithm {
+		PivManagementKeyAlgorithm::TripleDes => {
+			(PivAlgorithmId::TripleDes, 24, crypt_block::<des::TdesEde3>)
+		}
+		PivManagementKeyAlgorithm::Aes128 => {
+			(PivAlgorithmId::Aes128, 16, crypt_block::<aes::Aes128>)
+		}
+		PivManagementKeyAlgorithm::Aes192 => {
+			(PivAlgorithmId::Aes192, 24, crypt_block::<aes::Aes192>)
+		}
+		PivManagementKeyAlgorithm::Aes256 => {
+			(PivAlgorithmId::Aes256, 32, crypt_block::<aes::Aes256>)
+		}
+	}
+}
+
+/// Authenticates the management key with a mutual challenge-response.
+///
+/// `key` is the hex management key, the factory default if `None`.
+fn authenticate(
+	card: &pcsc::Card,
+	key: Option<&str>,
+	algorithm: PivManagementKeyAlgorithm,
+) -> Result<(), PFError> {
+	let key = match key {
+		Some(key) => hex::decode(key.trim())
+			.map_err(|_| PFError::Io("The management key must be hex encoded".into()))?,
+		None => PIV_DEFAULT_MANAGEMENT_KEY.to_vec(),
+	};
+	let (algorithm_id, key_len, crypt) = management_cipher(algorithm);
+	if key.len() != key_len {
+		return Err(PFError::Io(format!(
+			"A {:?} management key must be {} bytes long",
+			algorithm, key_len
+		)));
+	}
+	report_phase(
+		PhaseVerb::Authenticate,
+		"PIV management key",
+		UserAction::None,
+	);
+
+	// APDU: 00 87 [Alg] 9B 04 7C 02 80 00, asking the card for an encrypted witness
+	let request = tlv(PIV_TAG_DYNAMIC_AUTH, &tlv(PIV_TAG_WITNESS, &[]));
+	let resp = transmit_chained(
+		card,
+		PivInstruction::GeneralAuthenticate,
+		algorithm_id as u8,
+		PIV_MANAGEMENT_KEY_REF,
+		&request,
+	)?;
+	if resp.sw != 0x9000 {
+		return Err(PFError::Device(format!(
+			"PIV management key authentication failed: {:04X}",
+			resp.sw
+		)));
+	}
+	let witness = card::find_tlv(&resp.data, PIV_TAG_WITNESS as u16)
+		.ok_or_else(|| PFError::Device("Witness missing from PIV response".into()))?;
+	let witness = crypt(&key, witness, false)?;
+
+	let mut challenge = vec![0u8; witness.len()];
+	SystemRandom::new()
+		.fill(&mut challenge)
+		.map_err(|_| PFError::Io("Failed to generate a challenge".into()))?;
+
+	// APDU: 00 87 [Alg] 9B [Lc] 7C [L] 80 [Decrypted witness] 81 [Challenge]
+	let mut template = tlv(PIV_TAG_WITNESS, &witness);
+	template.extend(tlv(PIV_TAG_CHALLENGE, &challenge));
+	let resp = transmit_chained(
+		card,
+		PivInstruction::GeneralAuthenticate,
+		algorithm_id as u8,
+		PIV_MANAGEMENT_KEY_REF,
+		&tlv(PIV_TAG_DYNAMIC_AUTH, &template),
+	)?;
+	match resp.sw {
+		0x9000 => {}
+		0x6982 => return Err(PFError::Device("Wrong PIV management key".into())),
+		sw => {
+			return Err(PFError::Device(format!(
+				"PIV management key authentication failed: {:04X}",
+				sw
+			)));
+		}
+	}
+
+	// The card proves it holds the key too by encrypting our challenge
+	let response = card::find_tlv(&resp.data, PIV_TAG_RESPONSE as u16)
+		.ok_or_else(|| PFError::Device("Response missing from PIV response".into()))?;
+	if response != crypt(&key, &challenge, true)? {
+		return Err(PFError::Device(
+			"The PIV applet answered the challenge wrongly, it does not hold the management key"
+				.into(),
+		));
+	}
+	Ok(())
+}
+
+/// Generates a key pair in `slot`, replacing the key stored there, and returns its public key
+pub fn generate_key(
+	management_key: Option<&str>,
+	management_algorithm: PivManagementKeyAlgorithm,
+	slot: PivSlot,
+	algorithm: PivKeyAlgorithm,
+) -> Result<PivGeneratedKey, PFError> {
+	log::info!("Generating a {:?} PIV key in slot {:?}...", algorithm, slot);
+	let card = connect_and_select()?;
+	authenticate(&card, management_key, management_algorithm)?;
+	report_phase(PhaseVerb::Create, "PIV key", UserAction::None);
+
+	let algorithm_id = match algorithm {
+		PivKeyAlgorithm::Rsa2048 => PivAlgorithmId::Rsa2048,
+		PivKeyAlgorithm::EccP256 => PivAlgorithmId::EccP256,
+		PivKeyAlgorithm::EccP384 => PivAlgorithmId::EccP384,
+	};
+	// APDU: 00 47 00 [Slot] 05 AC 03 80 01 [Alg]
+	let (slot_ref, _) = slot_ids(slot);
+	let template = tlv(
+		PIV_TAG_CONTROL_REFERENCE,
+		&tlv(PIV_TAG_ALGORITHM, &[algorithm_id as u8]),
+	);
+	let resp = transmit_chained(
+		&card,
+		PivInstruction::GenerateAsymmetricKeyPair,
+		0x00,
+		slot_ref,
+		&template,
+	)?;
+	match resp.sw {
+		0x9000 => {}
+		0x6982 => {
+			return Err(PFError::Device(
+				"PIV management key not authenticated".into(),
+			));
+		}
+		0x6A80 => {
+			return Err(PFError::Device(format!(
+				"The PIV applet does not support {:?} keys",
+				algorithm
+			)));
+		}
+		sw => {
+			return Err(PFError::Device(format!(
+				"PIV key generation failed: {:04X}",
+				sw
+			)));
+		}
+	}
+
+	// Response: 7F49 [L] 81 [Modulus] 82 [Exponent] for RSA, 7F49 [L] 86 [Point] for EC
+	let public_key = card::find_tlv(&resp.data, PIV_TAG_PUBLIC_KEY)
+		.ok_or_else(|| PFError::Device("Public key missing from PIV response".into()))?;
+	let (public_key, exponent) = match algorithm {
+		PivKeyAlgorithm::Rsa2048 => (
+			card::find_tlv(public_key, PIV_TAG_MODULUS),
+			card::find_tlv(public_key, PIV_TAG_EXPONENT).map(hex::encode_upper),
+		),
+		_ => (card::find_tlv(public_key, PIV_TAG_POINT), None),
+	};
+	let public_key =
+		public_key.ok_or_else(|| PFError::Device("Malformed public key in PIV response".into()))?;
+
+	log::info!("PIV key generated in slot {:?}", slot);
+	Ok(PivGeneratedKey {
+		slot,
+		algorithm,
+		public_key: hex::encode_upper(public_key),
+		exponent,
+	})
+}
+
+/// Stores a DER or PEM certificate in `slot`, replacing the certificate stored there
+pub fn import_certificate(
+	management_key: Option<&str>,
+	management_algorithm: PivManagementKeyAlgorithm,
+	slot: PivSlot,
+	certificate: &[u8],
+) -> Result<(), PFError> {
+	log::info!("Importing a certificate into PIV slot {:?}...", slot);
+	let der = certificate_der(certificate)?;
+	let card = connect_and_select()?;
+	authenticate(&card, management_key, management_algorithm)?;
+	report_phase(PhaseVerb::Write, "PIV certificate", UserAction::None);
+
+	// Data: 5C 03 [Object] 53 [L] 70 [L] [Certificate] 71 01 00 FE 00, 71 being uncompressed
+	let (_, object) = slot_ids(slot);
+	let mut value = tlv(PIV_TAG_CERTIFICATE as u8, &der);
+	value.extend(tlv(PIV_TAG_CERT_INFO, &[0x00]));
+	value.extend(tlv(PIV_TAG_LRC, &[]));
+	let mut data = tlv(PIV_TAG_OBJECT_ID, &object);
+	data.extend(tlv(PIV_TAG_DATA, &value));
+
+	let resp = transmit_chained(
+		&card,
+		PivInstruction::PutData,
+		PIV_DATA_P1,
+		PIV_DATA_P2,
+		&data,
+	)?;
+	match resp.sw {
+		0x9000 => Ok(()),
+		0x6982 => Err(PFError::Device(
+			"PIV management key not authenticated".into(),
+		)),
+		0x6A84 => Err(PFError::Device(
+			"Not enough memory on the PIV applet for this certificate".into(),
+		)),
+		sw => Err(PFError::Device(format!(
+			"PIV certificate import failed: {:04X}",
+			sw
+		))),
+	}
+}
+
+/// Reads the certificate stored in `slot`
+pub fn export_certificate(slot: PivSlot) -> Result<PivCertificate, PFError> {
+	log::info!("Exporting the certificate of PIV slot {:?}...", slot);
+	let card = connect_and_select()?;
+	report_phase(PhaseVerb::Read, "PIV certificate", UserAction::None);
+
+	// APDU: 00 CB 3F FF 05 5C 03 [Object] 00
+	let (_, object) = slot_ids(slot);
+	let resp = transmit_chained(
+		&card,
+		PivInstruction::GetData,
+		PIV_DATA_P1,
+		PIV_DATA_P2,
+		&tlv(PIV_TAG_OBJECT_ID, &object),
+	)?;
+	match resp.sw {
+		0x9000 => {}
+		0x6A82 => {
+			return Err(PFError::Device(format!(
+				"No certificate stored in PIV slot {:?}",
+				slot
+			)));
+		}
+		sw => {
+			return Err(PFError::Device(format!(
+				"PIV certificate export failed: {:04X}",
+				sw
+			)));
+		}
+	}
+
+	// Response: 53 [L] 70 [L] [Certificate] 71 01 [Info] FE 00
+	let der = card::find_tlv(&resp.data, PIV_TAG_DATA as u16)
+		.and_then(|value| card::find_tlv(value, PIV_TAG_CERTIFICATE))
+		.ok_or_else(|| PFError::Device("Certificate missing from PIV response".into()))?
+		.to_vec();
+	let (_, cert) = X509Certificate::from_der(&der)
+		.map_err(|e| PFError::Device(format!("PIV slot holds an invalid certificate: {}", e)))?;
+
+	Ok(PivCertificate {
+		slot,
+		pem: to_pem(&der),
+		subject: cert.subject().to_string(),
+		issuer: cert.issuer().to_string(),
+		not_after: cert.validity().not_after.timestamp(),
+		der,
+	})
+}
+
+/// PIN or PUK padded with 0xFF as the applet expects
+fn padded_pin(pin: &str, what: &str) -> Result<Vec<u8>, PFError> {
+	if !(PIV_PIN_MIN_LEN..=PIV_PIN_LEN).contains(&pin.len()) {
+		return Err(PFError::Io(format!(
+			"The {} must be {} to {} characters long",
+			what, PIV_PIN_MIN_LEN, PIV_PIN_LEN
+		)));
+	}
+	let mut padded = pin.as_bytes().to_vec();
+	padded.resize(PIV_PIN_LEN, 0xFF);
+	Ok(padded)
+}
+
+/// Changes the PIN or the PUK
+pub fn change_pin(pin: PivPin, current_pin: &str, new_pin: &str) -> Result<(), PFError> {
+	log::info!("Changing PIV {:?}...", pin);
+	let (reference, what) = match pin {
+		PivPin::Pin => (PIV_PIN_REF, "PIV PIN"),
+		PivPin::Puk => (PIV_PUK_REF, "PIV PUK"),
+	};
+	let mut data = padded_pin(current_pin, &format!("current {}", what))?;
+	data.extend(padded_pin(new_pin, &format!("new {}", what))?);
+
+	let card = connect_and_select()?;
+	report_phase(PhaseVerb::Write, what, UserAction::None);
+	// APDU: 00 24 00 [Ref] 10 [Current, padded] [New, padded]
+	let resp = transmit_chained(
+		&card,
+		PivInstruction::ChangeReferenceData,
+		0x00,
+		reference,
+		&data,
+	)?;
+	match resp.sw {
+		0x9000 => Ok(()),
+		0x6983 => Err(PFError::Device(format!("{} is blocked", what))),
+		sw if sw & 0xFFF0 == 0x63C0 => Err(PFError::Device(format!(
+			"Wrong {} ({} retries left)",
+			what,
+			sw & 0x000F
+		))),
+		sw => Err(PFError::Device(format!(
+			"{} change failed: {:04X}",
+			what, sw
+		))),
+	}
+}
@@ -0,0 +1,103 @@
+//! Configuration profiles, files holding a device configuration to write to many keys.
+//!
+//! People commissioning a batch of keys write the same VID/PID, LED settings, options and product
+//! name to each of them. The configuration is saved once as a profile, in JSON or, for files that
+//! are edited by hand, in TOML (picked by the `.toml` extension), and applied to every key.
+
+#[cfg(feature = "gui")]
+use crate::inventory;
+use crate::{error::PFError, fido, storage, types::*};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+const PROFILE_FORMAT: &str = "picoforge-config-profile";
+const PROFILE_VERSION: u32 = 1;
+
+/// Profile file as written to disk
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProfileFile {
+	format: String,
+	version: u32,
+	name: String,
+	created_at: u64,
+	config: AppConfigInput,
+}
+
+fn is_toml(path: &Path) -> bool {
+	path.extension()
+		.is_some_and(|ext| ext.eq_ignore_ascii_case("toml"))
+}
+
+fn to_profile(path: &Path, file: ProfileFile) -> Result<ConfigProfile, PFError> {
+	Ok(ConfigProfile {
+		path: path.to_string_lossy().into_owned(),
+		changes: fido::describe_config(&file.config)?,
+		name: file.name,
+		created_at: file.created_at,
+		config: file.config,
+	})
+}
+
+/// SHA-256 of `config` serialized as JSON, the same for a profile saved as JSON or TOML
+#[cfg(feature = "gui")]
+pub fn config_hash(config: &AppConfigInput) -> Result<String, PFError> {
+	let data = serde_json::to_vec(config).map_err(|e| PFError::Io(e.to_string()))?;
+	Ok(hex::encode(
+		ring::digest::digest(&ring::digest::SHA256, &data).as_ref(),
+	))
+}
+
+/// Saves `config` as the profile `name` to `path`, after checking that it could be written to a key
+#[cfg(feature = "gui")]
+pub fn save(path: &Path, name: &str, config: AppConfigInput) -> Result<ConfigProfile, PFError> {
+	let name = name.trim();
+	if name.is_empty() {
+		return Err(PFError::Io("The profile needs a name".into()));
+	}
+	fido::describe_config(&config)?;
+
+	let file = ProfileFile {
+		format: PROFILE_FORMAT.into(),
+		version: PROFILE_VERSION,
+		name: name.to_string(),
+		created_at: inventory::now(),
+		config,
+	};
+	let data = if is_toml(path) {
+		toml::to_string_pretty(&file)
+			.map_err(|e| PFError::Io(e.to_string()))?
+			.into_bytes()
+	} else {
+		serde_json::to_vec_pretty(&file).map_err(|e| PFError::Io(e.to_string()))?
+	};
+	storage::write(path, &data)
+		.map_err(|e| PFError::Io(format!("Failed to write the profile: {}", e)))?;
+
+	log::info!("Saved configuration profile {} to {:?}", name, path);
+	to_profile(path, file)
+}
+
+/// Reads the profile at `path`, rejecting configurations that could not be written to a key
+pub fn load(path: &Path) -> Result<ConfigProfile, PFError> {
+	let data = storage::read(path)
+		.map_err(|e| PFError::Io(format!("Failed to read the profile: {}", e)))?;
+	let file: ProfileFile = if is_toml(path) {
+		let text = String::from_utf8(data)
+			.map_err(|_| PFError::Io("The profile is not valid UTF-8".into()))?;
+		toml::from_str(&text).map_err(|e| PFError::Io(format!("Invalid profile: {}", e)))?
+	} else {
+		serde_json::from_slice(&data).map_err(|e| PFError::Io(format!("Invalid profile: {}", e)))?
+	};
+	if file.format != PROFILE_FORMAT {
+		return Err(PFError::Io("Not a PicoForge configuration profile".into()));
+	}
+	if file.version != PROFILE_VERSION {
+		return Err(PFError::Io(format!(
+			"Configuration profile version {} is not supported",
+			file.version
+		)));
+	}
+
+	to_profile(path, file)
+}
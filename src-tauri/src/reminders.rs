@@ -0,0 +1,173 @@
+//! Reminders to audit resident credentials and rotate OTP secrets.
+//!
+//! Each device known to the inventory has its audits done at intervals set in the reminder
+//! settings. A background thread checks the inventory and notifies the user of overdue audits,
+//! through a desktop notification and an event for the frontend, once a day at most per audit.
+
+use crate::{
+	error::PFError,
+	inventory::{self, Inventory},
+	paths, storage,
+	types::{AuditKind, AuditReminder, ReminderSettings},
+};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+use tauri_plugin_notification::NotificationExt;
+
+/// Frontend event emitted with each overdue audit
+pub const AUDIT_REMINDER_EVENT: &str = "audit-reminder";
+
+const SETTINGS_FILE: &str = "reminders.json";
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Overdue audits are notified again after this long
+const RENOTIFY_INTERVAL: u64 = 24 * 60 * 60;
+const DAY: u64 = 24 * 60 * 60;
+
+fn settings_path() -> PathBuf {
+	paths::data_dir().join(SETTINGS_FILE)
+}
+
+/// Reminder settings saved by the user, or the defaults
+pub fn settings() -> ReminderSettings {
+	match storage::read(&settings_path()) {
+		Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+			log::warn!("Reminder settings are corrupted, using the defaults: {}", e);
+			ReminderSettings::default()
+		}),
+		Err(_) => ReminderSettings::default(),
+	}
+}
+
+pub fn set_settings(settings: &ReminderSettings) -> Result<(), PFError> {
+	if settings.credential_audit_days == Some(0) || settings.oath_rotation_days == Some(0) {
+		return Err(PFError::Io(
+			"Reminder intervals must be at least one day".into(),
+		));
+	}
+	let data = serde_json::to_vec_pretty(settings).map_err(|e| PFError::Io(e.to_string()))?;
+	storage::write(&settings_path(), &data)
+		.map_err(|e| PFError::Io(format!("Failed to save reminder settings: {}", e)))
+}
+
+/// Audits overdue at `now`. A device that was never audited is counted from its first event.
+pub fn due(inventory: &Inventory, settings: &ReminderSettings, now: u64) -> Vec<AuditReminder> {
+	let mut reminders = Vec::new();
+	for (serial, history) in &inventory.devices {
+		let first_seen = history.events.iter().map(|e| e.timestamp).min();
+		let audit = inventory.audits.get(serial).cloned().unwrap_or_default();
+
+		let intervals = [
+			(
+				AuditKind::CredentialAudit,
+				settings.credential_audit_days,
+				audit.credentials_audited_at,
+			),
+			(
+				AuditKind::OathRotation,
+				settings.oath_rotation_days,
+				audit.oath_rotated_at,
+			),
+		];
+		for (kind, days, last_done) in intervals {
+			let (Some(days), Some(since)) = (days, last_done.or(first_seen)) else {
+				continue;
+			};
+			let due_since = since + u64::from(days) * DAY;
+			if due_since > now {
+				continue;
+			}
+			// Sign counters are not tied to a device, every credential not verified since the
+			// last audit is worth a look
+			let stale_rp_ids = match kind {
+				AuditKind::CredentialAudit => {
+					let mut rp_ids: Vec<String> = inventory
+						.credentials
+						.values()
+						.filter(|c| c.last_verified < since)
+						.map(|c| c.rp_id.clone())
+						.collect();
+					rp_ids.sort();
+					rp_ids.dedup();
+					rp_ids
+				}
+				AuditKind::OathRotation => Vec::new(),
+			};
+			reminders.push(AuditReminder {
+				serial: serial.clone(),
+				kind,
+				last_done,
+				due_since,
+				stale_rp_ids,
+			});
+		}
+	}
+	reminders
+}
+
+/// Records that an audit of the device was done now
+pub fn mark_done(serial: &str, kind: AuditKind) -> Result<(), PFError> {
+	Inventory::update(|inventory| {
+		let audit = inventory.audits.entry(serial.to_string()).or_default();
+		let now = Some(inventory::now());
+		match kind {
+			AuditKind::CredentialAudit => audit.credentials_audited_at = now,
+			AuditKind::OathRotation => audit.oath_rotated_at = now,
+		}
+		Ok(())
+	})
+}
+
+fn notify(app: &AppHandle, reminder: &AuditReminder) {
+	let (title, action) = match reminder.kind {
+		AuditKind::CredentialAudit => ("Credential audit due", "review its passkeys"),
+		AuditKind::OathRotation => ("OTP secret rotation due", "rotate its OTP secrets"),
+	};
+	let mut body = format!("Time to {} on device {}", action, reminder.serial);
+	if !reminder.stale_rp_ids.is_empty() {
+		body.push_str(&format!(
+			". Not used lately: {}",
+			reminder.stale_rp_ids.join(", ")
+		));
+	}
+
+	if let Err(e) = app.notification().builder().title(title).body(body).show() {
+		log::warn!("Failed to show the audit reminder: {}", e);
+	}
+	if let Err(e) = app.emit(AUDIT_REMINDER_EVENT, reminder) {
+		log::warn!("Failed to emit the audit reminder: {}", e);
+	}
+}
+
+/// Starts the background thread notifying overdue audits
+pub fn start(app: AppHandle) {
+	std::thread::spawn(move || {
+		let mut notified: BTreeMap<(String, AuditKind), u64> = BTreeMap::new();
+		loop {
+			match Inventory::load() {
+				Ok(inventory) => {
+					let now = inventory::now();
+					for reminder in due(&inventory, &settings(), now) {
+						let key = (reminder.serial.clone(), reminder.kind);
+						if notified
+							.get(&key)
+							.is_some_and(|at| now.saturating_sub(*at) < RENOTIFY_INTERVAL)
+						{
+							continue;
+						}
+						log::info!(
+							"{:?} of device {} is overdue",
+							reminder.kind,
+							reminder.serial
+						);
+						notify(&app, &reminder);
+						notified.insert(key, now);
+					}
+				}
+				Err(e) => log::warn!("Audit reminders: could not load the inventory: {}", e),
+			}
+			std::thread::sleep(CHECK_INTERVAL);
+		}
+	});
+}
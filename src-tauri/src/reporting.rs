@@ -0,0 +1,286 @@
+//! Signed reports of a commissioning session, the auditable record of a roll-out.
+//!
+//! A report lists every key the session provisioned with its serial, AAGUID, firmware version,
+//! profile hash, secure boot state and minimum PIN length. It is signed with an HMAC-SHA256 over
+//! a secret kept in the data directory of this station, generated the first time a report is
+//! written, so an auditor holding the secret can tell whether the report was edited afterwards.
+//!
+//! Reports are written as JSON, or as an HTML page to print or save as PDF. The page embeds the
+//! signed JSON, and `verify` renders the page again from it, so an edit of the visible table is
+//! detected as well as one of the embedded report.
+
+use crate::{error::PFError, inventory, paths, storage, types::*};
+use rand::Rng;
+use ring::{digest, hmac};
+use std::path::{Path, PathBuf};
+
+const SECRET_FILE: &str = "report_secret.key";
+const ALGORITHM: &str = "HMAC-SHA256";
+/// ID of the script element of an HTML report holding the signed JSON
+const EMBEDDED_REPORT_ID: &str = "picoforge-report";
+
+fn secret_path() -> PathBuf {
+	paths::data_dir().join(SECRET_FILE)
+}
+
+/// Signing secret of this station, `None` if no report was signed here yet
+fn existing_secret() -> Result<Option<Vec<u8>>, PFError> {
+	match storage::read(&secret_path()) {
+		Ok(secret) if !secret.is_empty() => Ok(Some(secret)),
+		Ok(_) => Err(PFError::Io("The report signing secret is empty".into())),
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+		Err(e) => Err(PFError::Io(format!(
+			"Failed to read the report signing secret: {}",
+			e
+		))),
+	}
+}
+
+/// Signing secret of this station, created on first use
+fn secret() -> Result<Vec<u8>, PFError> {
+	match existing_secret()? {
+		Some(secret) => Ok(secret),
+		None => {
+			let secret: [u8; 32] = rand::rng().random();
+			storage::write(&secret_path(), &secret).map_err(|e| {
+				PFError::Io(format!("Failed to save the report signing secret: {}", e))
+			})?;
+			log::info!("Created the report signing secret");
+			Ok(secret.to_vec())
+		}
+	}
+}
+
+fn key_id(secret: &[u8]) -> String {
+	hex::encode(&digest::digest(&digest::SHA256, secret).as_ref()[..8])
+}
+
+fn report_bytes(report: &CommissioningReport) -> Result<Vec<u8>, PFError> {
+	serde_json::to_vec(report).map_err(|e| PFError::Io(e.to_string()))
+}
+
+/// Report of the keys `session` commissioned successfully
+pub fn build(session: &CommissioningSession) -> CommissioningReport {
+	let devices = session
+		.records
+		.iter()
+		.filter(|record| record.success)
+		.filter_map(|record| {
+			Some(ReportedDevice {
+				serial: record.serial.clone()?,
+				aaguid: record.aaguid.clone(),
+				firmware_version: record.firmware_version.clone(),
+				profile_hash: session.profile_hash.clone(),
+				secure_boot: record.secure_boot,
+				min_pin_length: record.min_pin_length,
+				commissioned_at: record.commissioned_at,
+			})
+		})
+		.collect();
+
+	CommissioningReport {
+		session_id: session.id.clone(),
+		profile_name: session.profile_name.clone(),
+		profile_hash: session.profile_hash.clone(),
+		started_at: session.started_at,
+		finished_at: session.finished_at,
+		generated_at: inventory::now(),
+		devices,
+	}
+}
+
+pub fn sign(report: CommissioningReport) -> Result<SignedCommissioningReport, PFError> {
+	let secret = secret()?;
+	let key = hmac::Key::new(hmac::HMAC_SHA256, &secret);
+	let value = hmac::sign(&key, &report_bytes(&report)?);
+	Ok(SignedCommissioningReport {
+		report,
+		signature: ReportSignature {
+			algorithm: ALGORITHM.into(),
+			key_id: key_id(&secret),
+			value: hex::encode(value.as_ref()),
+		},
+	})
+}
+
+/// Escapes text for HTML element content and attribute values
+fn html_escape(text: &str) -> String {
+	text.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+		.replace('"', "&quot;")
+}
+
+fn timestamp(seconds: u64) -> String {
+	chrono::DateTime::from_timestamp(seconds as i64, 0)
+		.map(|time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+		.unwrap_or_else(|| seconds.to_string())
+}
+
+fn optional<T: ToString>(value: &Option<T>) -> String {
+	value
+		.as_ref()
+		.map_or_else(|| "unknown".into(), |v| html_escape(&v.to_string()))
+}
+
+fn to_html(signed: &SignedCommissioningReport) -> Result<String, PFError> {
+	let report = &signed.report;
+	let mut rows = String::new();
+	for device in &report.devices {
+		let secure_boot = match device.secure_boot {
+			Some(true) => "enabled",
+			Some(false) => "disabled",
+			None => "unknown",
+		};
+		rows.push_str(&format!(
+			"<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+			html_escape(&device.serial),
+			optional(&device.aaguid),
+			optional(&device.firmware_version),
+			secure_boot,
+			optional(&device.min_pin_length),
+			timestamp(device.commissioned_at),
+		));
+	}
+
+	// Escaped so `</` in a value cannot end the script element early, `\/` is valid JSON
+	let embedded = serde_json::to_string(signed)
+		.map_err(|e| PFError::Io(e.to_string()))?
+		.replace("</", "<\\/");
+
+	Ok(format!(
+		r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>Commissioning report {session}</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; width: 100%; }}
+th, td {{ border: 1px solid #999; padding: 0.3em 0.6em; text-align: left; }}
+code {{ word-break: break-all; }}
+</style>
+</head>
+<body>
+<h1>Commissioning report</h1>
+<p>Session <code>{session}</code>, profile {profile}</p>
+<p>Profile hash (SHA-256): <code>{profile_hash}</code></p>
+<p>Started {started}, finished {finished}, report generated {generated}</p>
+<table>
+<thead><tr><th>Serial</th><th>AAGUID</th><th>Firmware</th><th>Secure boot</th><th>Minimum PIN length</th><th>Commissioned</th></tr></thead>
+<tbody>
+{rows}</tbody>
+</table>
+<p>{count} key(s). Signed with {algorithm}, key ID <code>{key_id}</code>:<br><code>{signature}</code></p>
+<script type="application/json" id="{embedded_id}">{embedded}</script>
+</body>
+</html>
+"#,
+		session = html_escape(&report.session_id),
+		profile = html_escape(&report.profile_name),
+		profile_hash = optional(&report.profile_hash),
+		started = timestamp(report.started_at),
+		finished = report
+			.finished_at
+			.map_or_else(|| "not yet".into(), timestamp),
+		generated = timestamp(report.generated_at),
+		rows = rows,
+		count = report.devices.len(),
+		algorithm = signed.signature.algorithm,
+		key_id = signed.signature.key_id,
+		signature = signed.signature.value,
+		embedded_id = EMBEDDED_REPORT_ID,
+		embedded = embedded,
+	))
+}
+
+fn is_html(path: &Path) -> bool {
+	path.extension()
+		.is_some_and(|ext| ext.eq_ignore_ascii_case("html") || ext.eq_ignore_ascii_case("htm"))
+}
+
+/// Writes the signed report of `session` to `path`, as HTML if it ends with `.html`, else as JSON
+pub fn write(
+	session: &CommissioningSession,
+	path: &Path,
+) -> Result<CommissioningReportExport, PFError> {
+	let signed = sign(build(session))?;
+	let data = if is_html(path) {
+		to_html(&signed)?.into_bytes()
+	} else {
+		serde_json::to_vec_pretty(&signed).map_err(|e| PFError::Io(e.to_string()))?
+	};
+	storage::write(path, &data)
+		.map_err(|e| PFError::Io(format!("Failed to write the commissioning report: {}", e)))?;
+
+	log::info!(
+		"Commissioning report of session {} with {} keys written to {:?}",
+		signed.report.session_id,
+		signed.report.devices.len(),
+		path
+	);
+	Ok(CommissioningReportExport {
+		path: path.to_string_lossy().into_owned(),
+		devices: signed.report.devices.len(),
+		signature: signed.signature,
+	})
+}
+
+/// Whether the report at `path` carries a valid signature of this station. An HTML report must
+/// also be the page `write` renders from the signed report, unchanged.
+pub fn verify(path: &Path) -> Result<bool, PFError> {
+	let data = storage::read(path)
+		.map_err(|e| PFError::Io(format!("Failed to read the report: {}", e)))?;
+	let (page, json) = if is_html(path) {
+		let page = String::from_utf8(data)
+			.map_err(|_| PFError::Io("The page is not a commissioning report".into()))?;
+		let start_tag = format!(
+			r#"<script type="application/json" id="{}">"#,
+			EMBEDDED_REPORT_ID
+		);
+		let json = page
+			.split_once(&start_tag)
+			.and_then(|(_, rest)| rest.split_once("</script>"))
+			.map(|(json, _)| json.as_bytes().to_vec())
+			.ok_or_else(|| PFError::Io("The page holds no signed report".into()))?;
+		(Some(page), json)
+	} else {
+		(None, data)
+	};
+	let signed: SignedCommissioningReport = serde_json::from_slice(&json)
+		.map_err(|e| PFError::Io(format!("Invalid commissioning report: {}", e)))?;
+	if signed.signature.algorithm != ALGORITHM {
+		return Err(PFError::Io(format!(
+			"Unsupported signature algorithm {}",
+			signed.signature.algorithm
+		)));
+	}
+
+	let secret = existing_secret()?.ok_or_else(|| {
+		PFError::Io(
+			"This station has no report signing secret, restore it from a settings backup".into(),
+		)
+	})?;
+	if signed.signature.key_id != key_id(&secret) {
+		log::warn!(
+			"Report signed with key {}, not with the key of this station",
+			signed.signature.key_id
+		);
+		return Ok(false);
+	}
+	let Ok(signature) = hex::decode(&signed.signature.value) else {
+		return Ok(false);
+	};
+	let key = hmac::Key::new(hmac::HMAC_SHA256, &secret);
+	if hmac::verify(&key, &report_bytes(&signed.report)?, &signature).is_err() {
+		return Ok(false);
+	}
+
+	match page {
+		Some(page) if page != to_html(&signed)? => {
+			log::warn!("The visible part of the report {:?} was edited", path);
+			Ok(false)
+		}
+		_ => Ok(true),
+	}
+}
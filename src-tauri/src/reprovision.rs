@@ -0,0 +1,160 @@
+//! Wipe-and-reprovision flow, for reissuing a returned key to someone else.
+//!
+//! Factory reset, PIN setup, minimum PIN length, configuration and verification run as one
+//! pipeline. Every step is a checkpoint: if one fails, the report names it, and the pipeline can
+//! be resumed from there once the cause is fixed, without wiping the key a second time.
+
+use crate::{
+	fido, inventory,
+	operation::{cancel_requested, report_progress},
+	types::*,
+};
+
+const STEPS: [ReprovisionStep; 5] = [
+	ReprovisionStep::Reset,
+	ReprovisionStep::SetPin,
+	ReprovisionStep::MinPinLength,
+	ReprovisionStep::ApplyConfig,
+	ReprovisionStep::Verify,
+];
+
+fn describe(step: ReprovisionStep) -> &'static str {
+	match step {
+		ReprovisionStep::Reset => "Resetting the FIDO application",
+		ReprovisionStep::SetPin => "Setting the new PIN",
+		ReprovisionStep::MinPinLength => "Setting the minimum PIN length",
+		ReprovisionStep::ApplyConfig => "Applying the configuration",
+		ReprovisionStep::Verify => "Verifying the device",
+	}
+}
+
+/// HID path of the key being reprovisioned. The path changes when the key is replugged for the
+/// reset, so the key is looked up by its USB port where the platform exposes it.
+fn current_path(
+	device_path: Option<&str>,
+	port_path: Option<&str>,
+) -> Result<Option<String>, String> {
+	let Some(port_path) = port_path else {
+		return Ok(device_path.map(str::to_string));
+	};
+	fido::list_devices()
+		.map_err(|e| e.to_string())?
+		.into_iter()
+		.find(|d| d.port_path.as_deref() == Some(port_path))
+		.map(|d| Some(d.path))
+		.ok_or_else(|| format!("The key at USB port {} is not connected", port_path))
+}
+
+/// Checks that the device ended up in the state the profile asks for
+fn verify(
+	device_path: Option<&str>,
+	profile: &ReprovisionProfile,
+) -> Result<FidoDeviceInfo, String> {
+	let info = fido::get_fido_info(device_path).map_err(|e| e.to_string())?;
+	if info.options.get("clientPin") != Some(&true) {
+		return Err("Device reports that no PIN is set".into());
+	}
+	if let Some(min) = profile.min_pin_length
+		&& info.min_pin_length < min as u32
+	{
+		return Err(format!(
+			"Device reports a minimum PIN length of {} instead of {}",
+			info.min_pin_length, min
+		));
+	}
+	Ok(info)
+}
+
+/// Runs the pipeline on the device at `device_path`, or the only connected one, starting at
+/// `resume_from` if given. Every step runs on the key at the USB port the device was at.
+///
+/// `serial` is the serial number of the device, used to record the reset in its history.
+pub fn reprovision(
+	device_path: Option<&str>,
+	serial: Option<&str>,
+	profile: &ReprovisionProfile,
+	new_pin: &str,
+	resume_from: Option<ReprovisionStep>,
+) -> ReprovisionReport {
+	let first = resume_from.unwrap_or(ReprovisionStep::Reset);
+	let port_path = fido::port_path(device_path);
+	let mut report = ReprovisionReport {
+		completed: Vec::new(),
+		failed_step: None,
+		error: None,
+		info: None,
+	};
+
+	for (index, step) in STEPS.into_iter().enumerate() {
+		if step < first {
+			continue;
+		}
+		if cancel_requested() {
+			report.failed_step = Some(step);
+			report.error = Some("Reprovisioning was cancelled".into());
+			break;
+		}
+		report_progress(describe(step).to_string(), index as u32, STEPS.len() as u32);
+
+		let path = match current_path(device_path, port_path.as_deref()) {
+			Ok(path) => path,
+			Err(e) => {
+				report.failed_step = Some(step);
+				report.error = Some(e);
+				break;
+			}
+		};
+		let path = path.as_deref();
+		let result = match step {
+			ReprovisionStep::Reset => fido::reset_device(path, true)
+				.map(|_| {
+					if let Some(serial) = serial {
+						inventory::log_device_event(
+							serial,
+							DeviceEventKind::Reset,
+							"Factory reset for reprovisioning",
+						);
+					}
+				})
+				.map_err(|e| e.to_string()),
+			ReprovisionStep::SetPin => fido::change_fido_pin(path, None, new_pin.to_string())
+				.map(|_| ())
+				.map_err(|e| e.to_string()),
+			ReprovisionStep::MinPinLength => match profile.min_pin_length {
+				Some(length) => {
+					fido::set_min_pin_length(path, new_pin.to_string(), length, Vec::new())
+						.map(|_| ())
+						.map_err(|e| e.to_string())
+				}
+				None => Ok(()),
+			},
+			ReprovisionStep::ApplyConfig => match &profile.config {
+				Some(config) => fido::write_config(path, config.clone(), Some(new_pin.to_string()))
+					.map_err(|e| e.to_string())
+					.and_then(|written| match written.issues.is_empty() {
+						true => Ok(()),
+						false => Err(written.message),
+					}),
+				None => Ok(()),
+			},
+			ReprovisionStep::Verify => verify(path, profile).map(|info| report.info = Some(info)),
+		};
+
+		if let Err(e) = result {
+			log::error!("Reprovisioning failed at {:?}: {}", step, e);
+			report.failed_step = Some(step);
+			report.error = Some(e);
+			break;
+		}
+		report.completed.push(step);
+	}
+
+	if report.failed_step.is_none() {
+		report_progress(
+			"Reprovisioning complete".into(),
+			STEPS.len() as u32,
+			STEPS.len() as u32,
+		);
+	}
+	report
+}
@@ -80,52 +80,3 @@ pub enum SecureLockParam {
 
 /// Default P2 value when not used
 pub const P2_UNUSED: u8 = 0x00;
-
-// --- 3. PHY Configuration Tags & Flags ---
-
-// PHY Tags from src/fs/phy.h
-#[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum PhyTag {
-	VidPid = 0x00,
-	LedGpio = 0x04,
-	LedBrightness = 0x05,
-	Opts = 0x06,
-	PresenceTimeout = 0x08, // Previously TAG_UP_BTN
-	UsbProduct = 0x09,
-	Curves = 0x0A,
-	LedDriver = 0x0C,
-}
-
-impl PhyTag {
-	/// Helper to convert raw u8 from device back to Enum
-	pub fn from_u8(val: u8) -> Option<Self> {
-		match val {
-			0x00 => Some(Self::VidPid),
-			0x04 => Some(Self::LedGpio),
-			0x05 => Some(Self::LedBrightness),
-			0x06 => Some(Self::Opts),
-			0x08 => Some(Self::PresenceTimeout),
-			0x09 => Some(Self::UsbProduct),
-			0x0A => Some(Self::Curves),
-			0x0C => Some(Self::LedDriver),
-			_ => None,
-		}
-	}
-}
-
-bitflags::bitflags! {
-	/// Configuration options for TAG_OPTS (Tag 0x06)
-	pub struct RescueOptions: u16 {
-		const LED_DIMMABLE = 0x02;
-		const DISABLE_POWER_RESET = 0x04;
-		const LED_STEADY = 0x08;
-	}
-}
-
-bitflags::bitflags! {
-	/// Enabled curves for TAG_CURVES (Tag 0x0A)
-	pub struct RescueCurves: u32 {
-		const SECP256K1 = 0x08;
-	}
-}
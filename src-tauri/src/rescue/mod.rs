@@ -4,30 +4,24 @@
 
 pub mod constants;
 
-use crate::{error::PFError, rescue::constants::*, types::*};
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use log;
+use crate::{
+	error::PFError, fido::phy::PhyConfig, operation::report_phase, rescue::constants::*, types::*,
+};
+#[cfg(feature = "gui")]
+use crate::{fido, inventory};
+use byteorder::{BigEndian, ReadBytesExt};
 use pcsc::{Context, Protocols, Scope, ShareMode};
 use std::io::Cursor;
 
-/// Connects to the first available reader and selects the Rescue Applet
-fn connect_and_select() -> Result<(pcsc::Card, Vec<u8>), PFError> {
-	let ctx = Context::establish(Scope::User).map_err(|e| {
+fn establish_context() -> Result<Context, PFError> {
+	Context::establish(Scope::User).map_err(|e| {
 		log::error!("Failed to establish PCSC context: {}", e);
 		PFError::Pcsc(e)
-	})?;
-
-	let mut readers_buf = [0; 2048];
-	let mut readers = ctx.list_readers(&mut readers_buf)?;
-
-	// Use the first reader found
-	let reader = readers.next().ok_or_else(|| {
-		log::info!("No Smart Card Reader found");
-		PFError::NoDevice
-	})?;
-
-	let card = ctx.connect(reader, ShareMode::Shared, Protocols::ANY)?;
+	})
+}
 
+/// Selects the Rescue Applet on a connected card and returns the select response
+fn select_applet(card: &pcsc::Card) -> Result<Vec<u8>, PFError> {
 	// Select Applet APDU: 00 A4 04 04 [Len] [AID]
 	let mut apdu = vec![
 		APDU_CLA_ISO,
@@ -51,13 +45,106 @@ fn connect_and_select() -> Result<(pcsc::Card, Vec<u8>), PFError> {
 		));
 	}
 
+	Ok(rx.to_vec())
+}
+
+/// Connects to the first available reader and selects the Rescue Applet
+fn connect_and_select() -> Result<(pcsc::Card, Vec<u8>), PFError> {
+	report_phase(PhaseVerb::Connect, "Rescue applet", UserAction::None);
+	let ctx = establish_context()?;
+
+	let mut readers_buf = [0; 2048];
+	let mut readers = ctx.list_readers(&mut readers_buf)?;
+
+	// Use the first reader found
+	let reader = readers.next().ok_or_else(|| {
+		log::info!("No Smart Card Reader found");
+		PFError::NoDevice
+	})?;
+
+	let card = ctx.connect(reader, ShareMode::Shared, Protocols::ANY)?;
+	let select_resp = select_applet(&card)?;
+
 	log::info!("Successfully connected to Rescue Applet");
-	Ok((card, rx.to_vec()))
+	Ok((card, select_resp))
+}
+
+/// Serial number reported in the select response, `None` if the firmware does not send one
+fn serial_from_select(select_resp: &[u8]) -> Option<String> {
+	// If the firmware sends 14 bytes, we have a serial. If it sends 6, we don't.
+	(select_resp.len() >= 14).then(|| hex::encode_upper(&select_resp[4..12]))
+}
+
+/// Serial number of the device on the first reader, if it can be reached through the Rescue
+/// Applet and reports one
+pub fn connected_serial() -> Option<String> {
+	let (_, select_resp) = connect_and_select().ok()?;
+	serial_from_select(&select_resp)
+}
+
+/// Lists the devices with a Rescue Applet on all readers. Readers without one are skipped.
+pub fn list_devices() -> Result<Vec<RescueDevice>, PFError> {
+	let ctx = establish_context()?;
+	let mut readers_buf = [0; 2048];
+	let mut devices = Vec::new();
+
+	for reader in ctx.list_readers(&mut readers_buf)? {
+		let reader_name = reader.to_string_lossy().to_string();
+		let select_resp = match ctx
+			.connect(reader, ShareMode::Shared, Protocols::ANY)
+			.map_err(PFError::from)
+			.and_then(|card| select_applet(&card))
+		{
+			Ok(resp) if resp.len() >= 6 => resp,
+			Ok(_) => continue,
+			Err(e) => {
+				log::debug!("Skipping reader {}: {}", reader_name, e);
+				continue;
+			}
+		};
+
+		let Some(serial) = serial_from_select(&select_resp) else {
+			log::warn!("Device on {} did not return a Serial Number", reader_name);
+			continue;
+		};
+		devices.push(RescueDevice {
+			reader: reader_name,
+			serial,
+			firmware_version: format!("{}.{}", select_resp[2], select_resp[3]),
+		});
+	}
+
+	Ok(devices)
+}
+
+/// Connects to the device with the given serial number, on whichever reader it is
+fn connect_serial(serial: &str) -> Result<(pcsc::Card, Vec<u8>), PFError> {
+	report_phase(
+		PhaseVerb::Connect,
+		&format!("device {}", serial),
+		UserAction::None,
+	);
+	let ctx = establish_context()?;
+	let mut readers_buf = [0; 2048];
+
+	for reader in ctx.list_readers(&mut readers_buf)? {
+		let Ok(card) = ctx.connect(reader, ShareMode::Shared, Protocols::ANY) else {
+			continue;
+		};
+		if let Ok(select_resp) = select_applet(&card)
+			&& serial_from_select(&select_resp).as_deref() == Some(serial)
+		{
+			return Ok((card, select_resp));
+		}
+	}
+
+	Err(PFError::NoDevice)
 }
 
 pub fn read_device_details() -> Result<FullDeviceStatus, PFError> {
 	log::info!("Reading full device details");
 	let (card, select_resp) = connect_and_select()?;
+	report_phase(PhaseVerb::Read, "device configuration", UserAction::None);
 
 	log::info!("Select Response: {:?}", select_resp);
 
@@ -72,15 +159,12 @@ pub fn read_device_details() -> Result<FullDeviceStatus, PFError> {
 	let version_minor = select_resp[3];
 
 	// FIX: Handle missing Serial Number safely
-	// If the firmware sends 14 bytes, we have a serial. If it sends 6, we don't.
-	let serial_str = if select_resp.len() >= 14 {
-		hex::encode_upper(&select_resp[4..12])
-	} else {
+	let serial_str = serial_from_select(&select_resp).unwrap_or_else(|| {
 		log::warn!(
 			"Device did not return a Serial Number (Firmware mismatch?). Using placeholder."
 		);
 		"00000000".to_string()
-	};
+	});
 
 	log::info!("Device Version: {}.{}", version_major, version_minor);
 	log::info!("Device Serial: {}", serial_str);
@@ -103,31 +187,25 @@ pub fn read_device_details() -> Result<FullDeviceStatus, PFError> {
 	}
 
 	let mut rdr = Cursor::new(&rx_flash[..rx_flash.len() - 2]);
-	let _free = rdr.read_u32::<BigEndian>().unwrap_or(0);
+	let free = rdr.read_u32::<BigEndian>().ok();
 	let used = rdr.read_u32::<BigEndian>().unwrap_or(0);
 	let total = rdr.read_u32::<BigEndian>().unwrap_or(0);
-
-	// NOTE: captured but currently unused variables
-	let _nfiles = rdr.read_u32::<BigEndian>().unwrap_or(0);
-	let _chip_size = rdr.read_u32::<BigEndian>().unwrap_or(0);
+	let files = rdr.read_u32::<BigEndian>().ok();
+	let chip_size = rdr.read_u32::<BigEndian>().ok();
+	let flash = match (free, files, chip_size) {
+		(Some(free), Some(files), Some(chip_size)) => {
+			Some(FlashBreakdown::new(free, used, total, files, chip_size))
+		}
+		_ => None,
+	};
 
 	// --- Read Secure Boot Status ---
-	let rx_secure = card.transmit(
-		&[
-			APDU_CLA_PROPRIETARY,
-			RescueInstruction::Read as u8,
-			ReadParam::SecureBootStatus as u8,
-			P2_UNUSED,
-			0x00,
-		],
-		&mut rx_buf,
-	)?;
-
-	let (sb_enabled, sb_locked) = if rx_secure.ends_with(&[0x90, 0x00]) && rx_secure.len() >= 4 {
-		(rx_secure[0] != 0, rx_secure[1] != 0)
-	} else {
+	let (sb_enabled, sb_locked) = read_secure_boot(&card).unwrap_or_else(|e| {
+		log::warn!("Failed to read secure boot status: {}", e);
 		(false, false)
-	}; // --- Read PHY Config ---
+	});
+
+	// --- Read PHY Config ---
 	let rx_phy = card.transmit(
 		&[
 			APDU_CLA_PROPRIETARY,
@@ -143,80 +221,8 @@ pub fn read_device_details() -> Result<FullDeviceStatus, PFError> {
 		return Err(PFError::Device("Failed to read config".into()));
 	}
 
-	// Parse TLV
 	let mut config = AppConfig::default();
-	let data = &rx_phy[..rx_phy.len() - 2];
-	let mut i = 0;
-	while i < data.len() {
-		if i + 2 > data.len() {
-			break;
-		}
-		let tag_byte = data[i];
-		let len = data[i + 1] as usize;
-		i += 2;
-		if i + len > data.len() {
-			break;
-		}
-		let val = &data[i..i + len];
-
-		if let Some(tag) = PhyTag::from_u8(tag_byte) {
-			match tag {
-				PhyTag::VidPid => {
-					if val.len() == 4 {
-						let vid = u16::from_be_bytes([val[0], val[1]]);
-						let pid = u16::from_be_bytes([val[2], val[3]]);
-						config.vid = format!("{:04X}", vid);
-						config.pid = format!("{:04X}", pid);
-					}
-				}
-				PhyTag::LedGpio => {
-					if !val.is_empty() {
-						config.led_gpio = val[0];
-					}
-				}
-				PhyTag::LedBrightness => {
-					if !val.is_empty() {
-						config.led_brightness = val[0];
-					}
-				}
-				PhyTag::PresenceTimeout => {
-					if !val.is_empty() {
-						config.touch_timeout = val[0];
-					}
-				}
-				PhyTag::UsbProduct => {
-					let s = std::str::from_utf8(val)
-						.unwrap_or("")
-						.trim_matches(char::from(0));
-					config.product_name = s.to_string();
-				}
-				PhyTag::Opts => {
-					if val.len() >= 2 {
-						let opts_val = u16::from_be_bytes([val[0], val[1]]);
-						let opts = RescueOptions::from_bits_truncate(opts_val);
-
-						config.led_dimmable = opts.contains(RescueOptions::LED_DIMMABLE);
-						config.power_cycle_on_reset =
-							!opts.contains(RescueOptions::DISABLE_POWER_RESET);
-						config.led_steady = opts.contains(RescueOptions::LED_STEADY);
-					}
-				}
-				PhyTag::Curves => {
-					if val.len() >= 4 {
-						let curves_val = u32::from_be_bytes([val[0], val[1], val[2], val[3]]);
-						let curves = RescueCurves::from_bits_truncate(curves_val);
-						config.enable_secp256k1 = curves.contains(RescueCurves::SECP256K1);
-					}
-				}
-				PhyTag::LedDriver => {
-					if !val.is_empty() {
-						config.led_driver = Some(val[0]);
-					}
-				}
-			}
-		}
-		i += len;
-	}
+	PhyConfig::decode(&rx_phy[..rx_phy.len() - 2])?.apply_to(&mut config);
 
 	log::info!(
 		"Successfully read device details - Serial: {}, Firmware: {}.{}",
@@ -230,112 +236,36 @@ pub fn read_device_details() -> Result<FullDeviceStatus, PFError> {
 			serial: serial_str,
 			flash_used: used / 1024,
 			flash_total: total / 1024,
+			flash_used_bytes: used,
+			flash_total_bytes: total,
 			firmware_version: format!("{}.{}", version_major, version_minor),
+			firmware: Some(FirmwareVersion {
+				major: version_major,
+				minor: version_minor,
+			}),
 		},
+		flash,
 		config,
 		secure_boot: sb_enabled,
 		secure_lock: sb_locked,
 		method: "Rescue".to_string(),
+		format_hints: FormatHints::default(),
 	})
 }
 
+#[cfg(feature = "gui")]
 pub fn write_config(config: AppConfigInput) -> Result<String, PFError> {
 	log::info!("Writing configuration to device");
 	log::debug!("Config input: {:?}", config);
+	fido::cache::invalidate();
+	report_phase(
+		PhaseVerb::Write,
+		"device configuration",
+		UserAction::KeepConnected,
+	);
 
 	// 1. Construct TLV Blob
-	let mut tlv = Vec::new();
-
-	// VID:PID (Tag 0x00)
-	if let (Some(vid_str), Some(pid_str)) = (&config.vid, &config.pid) {
-		let vid =
-			u16::from_str_radix(vid_str, 16).map_err(|_| PFError::Io("Invalid VID".into()))?;
-		let pid =
-			u16::from_str_radix(pid_str, 16).map_err(|_| PFError::Io("Invalid PID".into()))?;
-
-		tlv.push(PhyTag::VidPid as u8);
-		tlv.push(0x04);
-		tlv.write_u16::<BigEndian>(vid).unwrap();
-		tlv.write_u16::<BigEndian>(pid).unwrap();
-	}
-
-	// LED GPIO (Tag 0x04)
-	if let Some(val) = config.led_gpio {
-		tlv.push(PhyTag::LedGpio as u8);
-		tlv.push(0x01);
-		tlv.push(val);
-	}
-
-	// LED Brightness (Tag 0x05)
-	if let Some(val) = config.led_brightness {
-		tlv.push(PhyTag::LedBrightness as u8);
-		tlv.push(0x01);
-		tlv.push(val);
-	}
-
-	// Touch Timeout (Tag 0x08)
-	if let Some(val) = config.touch_timeout {
-		tlv.push(PhyTag::PresenceTimeout as u8);
-		tlv.push(0x01);
-		tlv.push(val);
-	}
-
-	// Options
-	if let (Some(dim), Some(cycle), Some(steady)) = (
-		config.led_dimmable,
-		config.power_cycle_on_reset,
-		config.led_steady,
-	) {
-		let mut opts = RescueOptions::empty();
-		if dim {
-			opts.insert(RescueOptions::LED_DIMMABLE);
-		}
-		if !cycle {
-			opts.insert(RescueOptions::DISABLE_POWER_RESET);
-		}
-		if steady {
-			opts.insert(RescueOptions::LED_STEADY);
-		}
-
-		tlv.push(PhyTag::Opts as u8);
-		tlv.push(0x02);
-		tlv.write_u16::<BigEndian>(opts.bits()).unwrap();
-	}
-
-	// Curves
-	if let Some(enabled) = config.enable_secp256k1 {
-		let mut curves = RescueCurves::empty();
-		if enabled {
-			curves.insert(RescueCurves::SECP256K1);
-		}
-
-		tlv.push(PhyTag::Curves as u8);
-		tlv.push(0x04);
-		tlv.write_u32::<BigEndian>(curves.bits()).unwrap();
-	}
-
-	// LED Driver (Tag 0x0C)
-	if let Some(val) = config.led_driver {
-		tlv.push(PhyTag::LedDriver as u8);
-		tlv.push(0x01);
-		tlv.push(val);
-	}
-
-	// Product Name (Tag 0x09)
-	if let Some(name) = config.product_name {
-		if !name.is_empty() {
-			let name_bytes = name.as_bytes();
-			let len = name_bytes.len() + 1;
-			if len > 32 {
-				return Err(PFError::Io("Product name too long".into()));
-			}
-
-			tlv.push(PhyTag::UsbProduct as u8);
-			tlv.push(len as u8);
-			tlv.extend_from_slice(name_bytes);
-			tlv.push(0x00); // Null terminator
-		}
-	}
+	let tlv = PhyConfig::from_input(&config)?.encode()?;
 
 	// 2. Connect and Send
 	if tlv.is_empty() {
@@ -345,7 +275,7 @@ pub fn write_config(config: AppConfigInput) -> Result<String, PFError> {
 
 	log::debug!("TLV payload size: {} bytes", tlv.len());
 
-	let (card, _) = connect_and_select()?;
+	let (card, select_resp) = connect_and_select()?;
 
 	// APDU: 80 1C 01 00 [Lc] [Data]
 	let mut apdu = vec![
@@ -362,6 +292,13 @@ pub fn write_config(config: AppConfigInput) -> Result<String, PFError> {
 
 	if rx.ends_with(&[0x90, 0x00]) {
 		log::info!("Configuration applied successfully");
+		if let Some(serial) = serial_from_select(&select_resp) {
+			inventory::log_device_event(
+				&serial,
+				DeviceEventKind::ConfigChange,
+				&format!("Configuration written ({} bytes of PHY data)", tlv.len()),
+			);
+		}
 		Ok("Configuration Applied Successfully".into())
 	} else {
 		log::error!("Configuration write failed: {:02X?}", rx);
@@ -369,8 +306,38 @@ pub fn write_config(config: AppConfigInput) -> Result<String, PFError> {
 	}
 }
 
+/// Validates `config` and checks that the Rescue Applet answers, returning what `write_config`
+/// would change without writing anything.
+#[cfg(feature = "gui")]
+pub fn plan_write_config(config: &AppConfigInput) -> Result<Vec<String>, PFError> {
+	let phy = PhyConfig::from_input(config)?;
+	let tlv = phy.encode()?;
+	if tlv.is_empty() {
+		return Ok(Vec::new());
+	}
+	connect_and_select()?;
+
+	let mut actions = vec![format!(
+		"Write {} bytes of PHY data through the Rescue Applet",
+		tlv.len()
+	)];
+	actions.extend(phy.describe());
+	Ok(actions)
+}
+
 pub fn reboot_device(to_bootsel: bool) -> Result<String, PFError> {
 	let (card, _) = connect_and_select()?;
+	send_reboot(&card, to_bootsel)
+}
+
+/// Reboots the device with the given serial number, on whichever reader it is
+pub fn reboot_device_with_serial(serial: &str, to_bootsel: bool) -> Result<String, PFError> {
+	let (card, _) = connect_serial(serial)?;
+	send_reboot(&card, to_bootsel)
+}
+
+fn send_reboot(card: &pcsc::Card, to_bootsel: bool) -> Result<String, PFError> {
+	report_phase(PhaseVerb::Reboot, "device", UserAction::None);
 
 	let param = if to_bootsel {
 		RebootParam::Bootsel
@@ -396,9 +363,83 @@ pub fn reboot_device(to_bootsel: bool) -> Result<String, PFError> {
 	}
 }
 
-/// UNSTABLE! (WIP)
-pub fn enable_secure_boot(lock: bool) -> Result<String, PFError> {
+/// Reads whether secure boot is enabled and whether the device is locked to it, from the OTP
+fn read_secure_boot(card: &pcsc::Card) -> Result<(bool, bool), PFError> {
+	// APDU: 80 1E 03 00 00
+	let apdu = [
+		APDU_CLA_PROPRIETARY,
+		RescueInstruction::Read as u8,
+		ReadParam::SecureBootStatus as u8,
+		P2_UNUSED,
+		0x00,
+	];
+	let mut rx_buf = [0; 256];
+	let rx = card.transmit(&apdu, &mut rx_buf)?;
+
+	if rx.ends_with(&SW_SUCCESS) && rx.len() >= 4 {
+		Ok((rx[0] != 0, rx[1] != 0))
+	} else {
+		Err(PFError::Device(format!(
+			"Reading secure boot status failed: {:02X?}",
+			rx
+		)))
+	}
+}
+
+/// Secure boot and secure lock state of the connected device
+pub fn read_secure_boot_status() -> Result<(bool, bool), PFError> {
 	let (card, _) = connect_and_select()?;
+	report_phase(PhaseVerb::Read, "secure boot status", UserAction::None);
+	read_secure_boot(&card)
+}
+
+/// Enables secure boot on the device with the serial `confirm_serial`, and locks it to secure
+/// boot if `lock` is set. Both are written to the OTP and cannot be undone, so the serial has to
+/// be typed by the user and is checked against the device before anything is sent.
+#[cfg(feature = "gui")]
+pub fn enable_secure_boot(lock: bool, confirm_serial: &str) -> Result<String, PFError> {
+	let card = connect_confirmed(confirm_serial)?;
+	write_secure_boot(&card, confirm_serial, lock)
+}
+
+/// Locks the device with the serial `confirm_serial` to secure boot, which has to be enabled
+/// already. Irreversible like `enable_secure_boot`.
+#[cfg(feature = "gui")]
+pub fn enable_secure_lock(confirm_serial: &str) -> Result<String, PFError> {
+	let card = connect_confirmed(confirm_serial)?;
+	let (secure_boot, _) = read_secure_boot(&card)?;
+	if !secure_boot {
+		return Err(PFError::Device(
+			"Secure boot must be enabled before locking the device".into(),
+		));
+	}
+	write_secure_boot(&card, confirm_serial, true)
+}
+
+/// Connects to the device with the serial typed by the user to confirm an irreversible write,
+/// refusing quarantined devices
+#[cfg(feature = "gui")]
+fn connect_confirmed(confirm_serial: &str) -> Result<pcsc::Card, PFError> {
+	let serial = confirm_serial.trim();
+	if serial.is_empty() {
+		return Err(PFError::Device(
+			"Secure boot cannot be disabled again, confirm with the serial number of the device"
+				.into(),
+		));
+	}
+	// Only the reader whose Rescue Applet reports this serial is used
+	let (card, _) = connect_serial(serial)?;
+	inventory::Inventory::load()?.check_quarantine(serial, "enable_secure_boot", false)?;
+	Ok(card)
+}
+
+#[cfg(feature = "gui")]
+fn write_secure_boot(card: &pcsc::Card, serial: &str, lock: bool) -> Result<String, PFError> {
+	report_phase(
+		PhaseVerb::Write,
+		"secure boot settings",
+		UserAction::KeepConnected,
+	);
 
 	// APDU: 80 1D [KeyIndex] [LockBool] 00
 	// KeyIndex = 0 (Default), LockBool = 1 if true
@@ -416,7 +457,20 @@ pub fn enable_secure_boot(lock: bool) -> Result<String, PFError> {
 	let rx = card.transmit(&apdu, &mut rx_buf)?;
 
 	if rx.ends_with(&[0x90, 0x00]) {
-		Ok("Secure Boot Enabled".into())
+		inventory::log_device_event(
+			serial,
+			DeviceEventKind::ConfigChange,
+			if lock {
+				"Secure boot enabled and locked"
+			} else {
+				"Secure boot enabled"
+			},
+		);
+		Ok(if lock {
+			"Secure Boot Enabled and Locked".into()
+		} else {
+			"Secure Boot Enabled".into()
+		})
 	} else {
 		Err(PFError::Device(format!("Secure Boot failed: {:02X?}", rx)))
 	}
@@ -0,0 +1,148 @@
+//! Sanity check of the hardware random number generator.
+//!
+//! Boards built at home sometimes end up with an entropy source that is stuck or heavily biased.
+//! Random bytes are read from the device and put through a few of the FIPS 140-2 / NIST SP 800-22
+//! tests. These catch a degenerate RNG, but passing them does not prove the output unpredictable.
+
+use crate::{error::PFError, openpgp, operation::report_progress, types::*};
+use std::collections::HashSet;
+
+/// Bytes sampled from the device, 64 KiBit
+pub const SAMPLE_BYTES: usize = 8192;
+
+/// Two-sided critical value of the standard normal distribution for p = 0.001
+const Z_CRITICAL: f64 = 3.29;
+
+/// Critical value of the chi-square distribution with 255 degrees of freedom for p = 0.001
+const CHI_SQUARE_CRITICAL: f64 = 330.52;
+
+/// Block size for the repeated block check, a repeat is practically impossible for random data
+const REPEAT_BLOCK: usize = 16;
+
+fn bits(data: &[u8]) -> impl Iterator<Item = bool> + '_ {
+	data.iter()
+		.flat_map(|byte| (0..8).rev().map(move |i| byte >> i & 1 == 1))
+}
+
+fn byte_counts(data: &[u8]) -> [u64; 256] {
+	let mut counts = [0u64; 256];
+	for &byte in data {
+		counts[byte as usize] += 1;
+	}
+	counts
+}
+
+fn shannon_entropy(counts: &[u64; 256], total: usize) -> f64 {
+	counts
+		.iter()
+		.filter(|&&count| count > 0)
+		.map(|&count| {
+			let p = count as f64 / total as f64;
+			-p * p.log2()
+		})
+		.sum()
+}
+
+/// Proportion of ones, as a z-score
+fn monobit(data: &[u8]) -> RngTestResult {
+	let n = (data.len() * 8) as f64;
+	let ones = bits(data).filter(|&bit| bit).count() as f64;
+	let z = (2.0 * ones - n).abs() / n.sqrt();
+	RngTestResult {
+		name: "Monobit frequency".into(),
+		statistic: z,
+		expected: format!("|z| < {}", Z_CRITICAL),
+		passed: z < Z_CRITICAL,
+	}
+}
+
+/// Number of runs of identical bits, as a z-score (NIST SP 800-22 runs test)
+fn runs(data: &[u8]) -> RngTestResult {
+	let n = (data.len() * 8) as f64;
+	let pi = bits(data).filter(|&bit| bit).count() as f64 / n;
+	let bits: Vec<bool> = bits(data).collect();
+	let runs = 1 + bits.windows(2).filter(|w| w[0] != w[1]).count();
+	let expected = 2.0 * n * pi * (1.0 - pi);
+	let z = (runs as f64 - expected).abs() / (2.0 * n.sqrt() * pi * (1.0 - pi)).max(f64::EPSILON);
+	RngTestResult {
+		name: "Runs".into(),
+		statistic: z,
+		expected: format!("|z| < {}", Z_CRITICAL),
+		passed: z < Z_CRITICAL,
+	}
+}
+
+/// Distribution of byte values against the uniform distribution
+fn chi_square(counts: &[u64; 256], total: usize) -> RngTestResult {
+	let expected = total as f64 / 256.0;
+	let chi: f64 = counts
+		.iter()
+		.map(|&count| (count as f64 - expected).powi(2) / expected)
+		.sum();
+	RngTestResult {
+		name: "Byte distribution (chi-square)".into(),
+		statistic: chi,
+		expected: format!("< {}", CHI_SQUARE_CRITICAL),
+		passed: chi < CHI_SQUARE_CRITICAL,
+	}
+}
+
+/// Repeated blocks, as returned by an RNG stuck in a short cycle or reseeded with a constant
+fn repeated_blocks(data: &[u8]) -> RngTestResult {
+	let mut seen = HashSet::new();
+	let repeats = data
+		.chunks_exact(REPEAT_BLOCK)
+		.filter(|block| !seen.insert(*block))
+		.count();
+	RngTestResult {
+		name: format!("Repeated {}-byte blocks", REPEAT_BLOCK),
+		statistic: repeats as f64,
+		expected: "0".into(),
+		passed: repeats == 0,
+	}
+}
+
+/// Runs the tests on `data`
+pub fn analyze(source: &str, data: &[u8]) -> RngReport {
+	let counts = byte_counts(data);
+	let tests = vec![
+		monobit(data),
+		runs(data),
+		chi_square(&counts, data.len()),
+		repeated_blocks(data),
+	];
+	RngReport {
+		source: source.to_string(),
+		sample_bytes: data.len(),
+		entropy_bits_per_byte: shannon_entropy(&counts, data.len()),
+		passed: tests.iter().all(|t| t.passed),
+		tests,
+	}
+}
+
+/// Samples the device RNG and checks the samples
+pub fn run_diagnostic() -> Result<RngReport, PFError> {
+	let data = openpgp::get_challenge(SAMPLE_BYTES, |read| {
+		report_progress(
+			"Reading random numbers from the device".into(),
+			read as u32,
+			SAMPLE_BYTES as u32,
+		)
+	})?;
+
+	let report = analyze("OpenPGP GET CHALLENGE", &data);
+	for test in report.tests.iter().filter(|t| !t.passed) {
+		log::warn!(
+			"RNG test '{}' failed: {} (expected {})",
+			test.name,
+			test.statistic,
+			test.expected
+		);
+	}
+	log::info!(
+		"RNG diagnostic {}: {:.4} bits of entropy per byte",
+		if report.passed { "passed" } else { "failed" },
+		report.entropy_bits_per_byte
+	);
+	Ok(report)
+}
@@ -0,0 +1,347 @@
+//! Firmware-developer mode: labels raw vendor responses using the constants of the firmware
+//! headers (`phy.h`, `ctap.h`, `cbor_vendor.c`) instead of hardcoding every tag.
+//!
+//! The constants come either from a pico-fido source tree supplied by the user, or from a
+//! snapshot bundled per firmware version.
+
+pub mod registry;
+
+#[cfg(feature = "gui")]
+use crate::error::PFError;
+use crate::types::*;
+#[cfg(feature = "gui")]
+use serde_cbor_2::Value;
+#[cfg(feature = "gui")]
+use std::{
+	path::{Path, PathBuf},
+	sync::Mutex,
+};
+
+/// Header snapshots bundled with PicoForge, oldest first
+const BUNDLED_SNAPSHOTS: &[(&str, &str)] = &[("7.2", include_str!("snapshots/pico-fido-7.2.h"))];
+
+/// Files of the firmware tree that hold the constants we care about
+#[cfg(feature = "gui")]
+const SOURCE_FILES: &[&str] = &["phy.h", "ctap.h", "cbor_vendor.c"];
+
+/// Directories never worth walking into when scanning a source tree
+#[cfg(feature = "gui")]
+const SKIPPED_DIRS: &[&str] = &[".git", "build", "pico-sdk", "mbedtls", "tinycbor"];
+
+/// Define prefixes and the group they belong to. More specific prefixes come first.
+const GROUP_PREFIXES: &[(&str, SchemaGroup)] = &[
+	("PHY_OPT_", SchemaGroup::PhyOption),
+	("PHY_", SchemaGroup::PhyTag),
+	("CTAP_VENDOR_MEMORY_", SchemaGroup::MemoryResponseKey),
+	("CTAP_VENDOR_", SchemaGroup::VendorCommand),
+	("CTAP_CONFIG_", SchemaGroup::VendorConfigCommand),
+];
+
+/// Schema used for labelling, `None` until one is loaded
+#[cfg(feature = "gui")]
+static ACTIVE_SCHEMA: Mutex<Option<FirmwareSchema>> = Mutex::new(None);
+
+/// Parses `#define NAME VALUE` lines with integer values and keeps the known groups
+fn parse_defines(source: &str) -> Vec<SchemaConstant> {
+	source
+		.lines()
+		.filter_map(|line| {
+			let mut parts = line.trim().strip_prefix("#define")?.split_whitespace();
+			let name = parts.next()?;
+			// Function-like macros carry no constant
+			if name.contains('(') {
+				return None;
+			}
+			let value = parse_integer(parts.next()?)?;
+			let group = GROUP_PREFIXES
+				.iter()
+				.find(|(prefix, _)| name.starts_with(prefix))
+				.map(|(_, group)| *group)?;
+			Some(SchemaConstant {
+				name: name.to_string(),
+				value,
+				group,
+			})
+		})
+		.collect()
+}
+
+/// Parses C integer literals like `0x6fcb19b0cbe3acfa`, `(0x05)` or `16u`
+fn parse_integer(token: &str) -> Option<u64> {
+	let token = token
+		.trim_start_matches('(')
+		.trim_end_matches(')')
+		.trim_end_matches(['u', 'U', 'l', 'L']);
+	match token
+		.strip_prefix("0x")
+		.or_else(|| token.strip_prefix("0X"))
+	{
+		Some(hex) => u64::from_str_radix(hex, 16).ok(),
+		None => token.parse().ok(),
+	}
+}
+
+/// Collects the source files of interest below `dir`
+#[cfg(feature = "gui")]
+fn find_source_files(dir: &Path, found: &mut Vec<PathBuf>) -> std::io::Result<()> {
+	for entry in std::fs::read_dir(dir)? {
+		let path = entry?.path();
+		let name = path
+			.file_name()
+			.and_then(|n| n.to_str())
+			.unwrap_or_default();
+		if path.is_dir() {
+			if !SKIPPED_DIRS.contains(&name) {
+				find_source_files(&path, found)?;
+			}
+		} else if SOURCE_FILES.contains(&name) {
+			found.push(path);
+		}
+	}
+	Ok(())
+}
+
+pub(crate) fn version_key(version: &str) -> Vec<u32> {
+	version
+		.split('.')
+		.map(|part| part.trim().parse().unwrap_or(0))
+		.collect()
+}
+
+/// Loads the constants of a pico-fido source tree
+#[cfg(feature = "gui")]
+pub fn load_from_source_tree(dir: &str) -> Result<FirmwareSchema, PFError> {
+	let mut files = Vec::new();
+	find_source_files(Path::new(dir), &mut files).map_err(|e| PFError::Io(e.to_string()))?;
+	if files.is_empty() {
+		return Err(PFError::Io(format!(
+			"No firmware headers ({}) found in {}",
+			SOURCE_FILES.join(", "),
+			dir
+		)));
+	}
+
+	let mut constants = Vec::new();
+	for file in &files {
+		log::debug!("Reading firmware constants from {:?}", file);
+		let source = std::fs::read_to_string(file).map_err(|e| PFError::Io(e.to_string()))?;
+		constants.extend(parse_defines(&source));
+	}
+	log::info!(
+		"Loaded {} firmware constants from {} files in {}",
+		constants.len(),
+		files.len(),
+		dir
+	);
+
+	Ok(FirmwareSchema {
+		firmware_version: None,
+		source: dir.to_string(),
+		constants,
+	})
+}
+
+/// Newest firmware version this build has a snapshot of
+pub fn latest_bundled_version() -> &'static str {
+	BUNDLED_SNAPSHOTS[BUNDLED_SNAPSHOTS.len() - 1].0
+}
+
+/// Loads the bundled snapshot closest to `firmware_version`: the newest one not newer than
+/// it, or the oldest one if the firmware predates all of them. Without a version the newest
+/// snapshot is used.
+pub fn load_bundled(firmware_version: Option<&str>) -> FirmwareSchema {
+	let (version, source) = match firmware_version {
+		Some(wanted) => BUNDLED_SNAPSHOTS
+			.iter()
+			.rev()
+			.find(|(version, _)| version_key(version) <= version_key(wanted))
+			.unwrap_or(&BUNDLED_SNAPSHOTS[0]),
+		None => &BUNDLED_SNAPSHOTS[BUNDLED_SNAPSHOTS.len() - 1],
+	};
+
+	FirmwareSchema {
+		firmware_version: Some(version.to_string()),
+		source: format!("bundled snapshot pico-fido {}", version),
+		constants: parse_defines(source),
+	}
+}
+
+/// Makes `schema` the one used by the labelling functions
+#[cfg(feature = "gui")]
+pub fn set_active(schema: FirmwareSchema) -> FirmwareSchema {
+	*ACTIVE_SCHEMA.lock().unwrap() = Some(schema.clone());
+	schema
+}
+
+/// Schema used for labelling, the newest bundled snapshot until another one is loaded
+#[cfg(feature = "gui")]
+pub fn active() -> FirmwareSchema {
+	ACTIVE_SCHEMA
+		.lock()
+		.unwrap()
+		.get_or_insert_with(|| load_bundled(None))
+		.clone()
+}
+
+#[cfg(feature = "gui")]
+impl FirmwareSchema {
+	fn name_of(&self, group: SchemaGroup, value: u64) -> Option<String> {
+		self.constants
+			.iter()
+			.find(|c| c.group == group && c.value == value)
+			.map(|c| c.name.clone())
+	}
+
+	fn flag_names(&self, group: SchemaGroup, bits: u64) -> Vec<String> {
+		self.constants
+			.iter()
+			.filter(|c| c.group == group && c.value != 0 && bits & c.value == c.value)
+			.map(|c| c.name.clone())
+			.collect()
+	}
+}
+
+#[cfg(feature = "gui")]
+fn field(
+	path: String,
+	label: Option<String>,
+	raw: String,
+	decoded: Option<String>,
+) -> LabeledField {
+	LabeledField {
+		path,
+		label,
+		raw,
+		decoded,
+	}
+}
+
+/// Labels a PHY configuration TLV blob (`tag`, `len`, `value...`) as returned by the rescue applet
+#[cfg(feature = "gui")]
+pub fn label_phy_tlv(schema: &FirmwareSchema, data: &[u8]) -> Result<Vec<LabeledField>, PFError> {
+	let mut fields = Vec::new();
+	let mut i = 0;
+	while i < data.len() {
+		let (tag, len) = match (data.get(i), data.get(i + 1)) {
+			(Some(tag), Some(len)) => (*tag, *len as usize),
+			_ => {
+				return Err(PFError::Device(format!(
+					"Truncated PHY tag at offset {}",
+					i
+				)));
+			}
+		};
+		let value = data.get(i + 2..i + 2 + len).ok_or_else(|| {
+			PFError::Device(format!("PHY tag 0x{:02X} overruns the response", tag))
+		})?;
+
+		let label = schema.name_of(SchemaGroup::PhyTag, tag as u64);
+		let decoded = match label.as_deref() {
+			Some("PHY_OPTS") if value.len() >= 2 => {
+				let opts = u16::from_be_bytes([value[0], value[1]]) as u64;
+				Some(schema.flag_names(SchemaGroup::PhyOption, opts).join(" | "))
+			}
+			Some("PHY_USB_PRODUCT") => Some(
+				String::from_utf8_lossy(value)
+					.trim_end_matches('\0')
+					.to_string(),
+			),
+			_ => None,
+		};
+		fields.push(field(
+			format!("0x{:02X}", tag),
+			label,
+			hex::encode_upper(value),
+			decoded,
+		));
+		i += 2 + len;
+	}
+	Ok(fields)
+}
+
+#[cfg(feature = "gui")]
+fn describe_value(value: &Value) -> String {
+	match value {
+		Value::Integer(i) => i.to_string(),
+		Value::Bytes(b) => hex::encode_upper(b),
+		Value::Text(t) => format!("\"{}\"", t),
+		other => format!("{:?}", other),
+	}
+}
+
+#[cfg(feature = "gui")]
+fn label_cbor(
+	schema: &FirmwareSchema,
+	key_group: Option<SchemaGroup>,
+	path: &str,
+	value: &Value,
+	fields: &mut Vec<LabeledField>,
+) {
+	match value {
+		Value::Map(map) => {
+			for (key, value) in map {
+				let (key_path, label) = match key {
+					Value::Integer(k) => (
+						format!("{}/{}", path, k),
+						key_group.and_then(|group| schema.name_of(group, *k as u64)),
+					),
+					Value::Text(t) => (format!("{}/{}", path, t), None),
+					other => (format!("{}/{:?}", path, other), None),
+				};
+				if let Value::Map(_) | Value::Array(_) = value {
+					label_cbor(schema, None, &key_path, value, fields);
+				} else {
+					let decoded = integer_label(schema, value);
+					fields.push(field(key_path, label, describe_value(value), decoded));
+				}
+			}
+		}
+		Value::Array(items) => {
+			for (index, item) in items.iter().enumerate() {
+				label_cbor(schema, None, &format!("{}/{}", path, index), item, fields);
+			}
+		}
+		other => fields.push(field(
+			path.to_string(),
+			None,
+			describe_value(other),
+			integer_label(schema, other),
+		)),
+	}
+}
+
+/// Vendor config command IDs are 64 bit values and never collide with ordinary numbers
+#[cfg(feature = "gui")]
+fn integer_label(schema: &FirmwareSchema, value: &Value) -> Option<String> {
+	match value {
+		Value::Integer(i) => schema.name_of(SchemaGroup::VendorConfigCommand, *i as u64),
+		_ => None,
+	}
+}
+
+/// Labels the CBOR response of the vendor command `command` (the byte prepended to the payload)
+#[cfg(feature = "gui")]
+pub fn label_vendor_response(
+	schema: &FirmwareSchema,
+	command: u8,
+	response: &[u8],
+) -> Result<VendorResponseTrace, PFError> {
+	let command_label = schema.name_of(SchemaGroup::VendorCommand, command as u64);
+	let key_group = match command_label.as_deref() {
+		Some("CTAP_VENDOR_MEMORY") => Some(SchemaGroup::MemoryResponseKey),
+		_ => None,
+	};
+
+	let mut fields = Vec::new();
+	if !response.is_empty() {
+		let value: Value = serde_cbor_2::from_slice(response)
+			.map_err(|e| PFError::Device(format!("Vendor response is not valid CBOR: {}", e)))?;
+		label_cbor(schema, key_group, "", &value, &mut fields);
+	}
+
+	Ok(VendorResponseTrace {
+		command,
+		command_label,
+		fields,
+	})
+}
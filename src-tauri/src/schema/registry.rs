@@ -0,0 +1,69 @@
+//! Values of the vendor protocol constants the device code talks with.
+//!
+//! Vendor commands, PHY tags and memory response keys are named after their firmware defines,
+//! and their values come from the newest bundled snapshot. Vendor additions are only ever
+//! appended and never renumbered, so the newest definitions also cover older firmware, which
+//! answers unknown commands with a CTAP error. Supporting the vendor additions of a new firmware
+//! release takes a new snapshot plus the handlers using them.
+
+use super::{latest_bundled_version, load_bundled};
+use crate::{error::PFError, types::*};
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+/// Constants of the newest bundled snapshot, by name
+struct ProtocolRegistry {
+	firmware_version: &'static str,
+	constants: BTreeMap<String, SchemaConstant>,
+}
+
+static REGISTRY: OnceLock<ProtocolRegistry> = OnceLock::new();
+
+fn registry() -> &'static ProtocolRegistry {
+	REGISTRY.get_or_init(|| {
+		let schema = load_bundled(None);
+		ProtocolRegistry {
+			firmware_version: latest_bundled_version(),
+			constants: schema
+				.constants
+				.into_iter()
+				.map(|c| (c.name.clone(), c))
+				.collect(),
+		}
+	})
+}
+
+/// A protocol constant whose value is defined by the firmware definitions
+pub trait ProtocolConstant: Copy + std::fmt::Debug + 'static {
+	const GROUP: SchemaGroup;
+	/// Every variant, to map values read from the device back
+	const ALL: &'static [Self];
+
+	/// Name of the firmware define, e.g. `CTAP_VENDOR_MEMORY`
+	fn definition(self) -> &'static str;
+
+	/// Value of the constant in the bundled definitions
+	fn value(self) -> Result<u64, PFError> {
+		let registry = registry();
+		registry
+			.constants
+			.get(self.definition())
+			.filter(|c| c.group == Self::GROUP)
+			.map(|c| c.value)
+			.ok_or_else(|| {
+				PFError::Device(format!(
+					"{} is not defined for pico-fido {}",
+					self.definition(),
+					registry.firmware_version
+				))
+			})
+	}
+
+	/// The constant with `value`, `None` for values these definitions do not know
+	fn from_value(value: u64) -> Option<Self> {
+		Self::ALL
+			.iter()
+			.copied()
+			.find(|c| c.value().is_ok_and(|v| v == value))
+	}
+}
@@ -0,0 +1,43 @@
+/*
+ * Constants extracted from pico-fido 7.2 (src/fs/phy.h, src/fido/ctap.h and
+ * src/fido/cbor_vendor.c). Only the defines PicoForge talks to the key and labels responses
+ * with are kept.
+ */
+
+#define PHY_VIDPID          0x0
+#define PHY_LED_GPIO        0x4
+#define PHY_LED_BTNESS      0x5
+#define PHY_OPTS            0x6
+#define PHY_UP_BTN          0x8
+#define PHY_USB_PRODUCT     0x9
+#define PHY_ENABLED_CURVES  0xA
+#define PHY_ENABLED_USB_ITF 0xB
+#define PHY_LED_DRIVER      0xC
+
+#define PHY_OPT_WCID        0x1
+#define PHY_OPT_DIMM        0x2
+#define PHY_OPT_DISABLE_POWER_RESET 0x4
+#define PHY_OPT_LED_STEADY  0x8
+
+#define CTAP_VENDOR_BACKUP          0x01
+#define CTAP_VENDOR_MSE             0x02
+#define CTAP_VENDOR_UNLOCK          0x03
+#define CTAP_VENDOR_EA              0x04
+#define CTAP_VENDOR_PHY_OPTS        0x05
+#define CTAP_VENDOR_MEMORY          0x06
+
+#define CTAP_CONFIG_AUT_ENABLE      0x03e43f56b34285e2
+#define CTAP_CONFIG_AUT_DISABLE     0x1831a40f04a25ed9
+#define CTAP_CONFIG_EA_UPLOAD       0x66f2a674c29a8dcf
+#define CTAP_CONFIG_PIN_POLICY      0x6c07d70fe96c3897
+#define CTAP_CONFIG_PHY_VIDPID      0x6fcb19b0cbe3acfa
+#define CTAP_CONFIG_PHY_LED_BTNESS  0x76a85945985d02fd
+#define CTAP_CONFIG_PHY_LED_GPIO    0x7b392a394de9f948
+#define CTAP_CONFIG_PHY_OPTS        0x269f3b09eceb805f
+
+/* cbor_vendor.c uses plain integers for the memory stats keys, named here for labelling */
+#define CTAP_VENDOR_MEMORY_FREE     0x01
+#define CTAP_VENDOR_MEMORY_USED     0x02
+#define CTAP_VENDOR_MEMORY_TOTAL    0x03
+#define CTAP_VENDOR_MEMORY_FILES    0x04
+#define CTAP_VENDOR_MEMORY_SIZE     0x05
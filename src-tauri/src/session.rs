@@ -0,0 +1,171 @@
+//! Session expiry and auto-lock, mirroring the behaviour of smart card middleware.
+//!
+//! Every device operation counts as activity. After the configured idle time, or when the
+//! desktop session gets locked, the session is locked: the running operation is asked to stop,
+//! the cached PIN token is dropped, developer mode is locked again and `session-locked` is broadcast so the windows can forget the PINs they hold. The next
+//! device operation starts a new session.
+//!
+//! Screen lock detection uses the `LockedHint` of systemd-logind and is only available on Linux.
+
+use crate::{
+	commissioning::Commissioning, devmode, error::PFError, fido, inventory::now,
+	manager::DeviceManager, paths, storage, types::*,
+};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager};
+
+/// Broadcast to every window when the session gets locked
+pub const SESSION_LOCKED_EVENT: &str = "session-locked";
+
+const SESSION_FILE: &str = "session.json";
+
+/// How often the watcher checks the idle time and screen lock state
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct SessionManager {
+	policy: Mutex<SessionPolicy>,
+	last_activity: Mutex<Instant>,
+	locked: AtomicBool,
+}
+
+fn session_path() -> PathBuf {
+	paths::data_dir().join(SESSION_FILE)
+}
+
+impl SessionManager {
+	/// Restores the saved policy. The session starts unlocked.
+	pub fn load() -> Self {
+		let policy = match storage::read(&session_path()) {
+			Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+				log::error!("Session policy is corrupted, using the default: {}", e);
+				SessionPolicy::default()
+			}),
+			Err(_) => SessionPolicy::default(),
+		};
+
+		Self {
+			policy: Mutex::new(policy),
+			last_activity: Mutex::new(Instant::now()),
+			locked: AtomicBool::new(false),
+		}
+	}
+
+	pub fn policy(&self) -> SessionPolicy {
+		self.policy.lock().map(|p| p.clone()).unwrap_or_default()
+	}
+
+	pub fn set_policy(&self, policy: SessionPolicy) -> Result<(), PFError> {
+		if !(1..=16).contains(&policy.max_concurrent_devices) {
+			return Err(PFError::Io(
+				"Between 1 and 16 devices can be operated on at the same time".into(),
+			));
+		}
+		let data = serde_json::to_vec_pretty(&policy).map_err(|e| PFError::Io(e.to_string()))?;
+		storage::write(&session_path(), &data)
+			.map_err(|e| PFError::Io(format!("Failed to save session policy: {}", e)))?;
+		if let Ok(mut current) = self.policy.lock() {
+			*current = policy;
+		}
+		Ok(())
+	}
+
+	/// Records activity, starting a new session if the previous one was locked
+	pub fn touch(&self) {
+		if let Ok(mut last) = self.last_activity.lock() {
+			*last = Instant::now();
+		}
+		if self.locked.swap(false, Ordering::SeqCst) {
+			log::info!("New session started");
+		}
+	}
+
+	pub fn is_locked(&self) -> bool {
+		self.locked.load(Ordering::SeqCst)
+	}
+
+	fn idle_time(&self) -> Duration {
+		self.last_activity
+			.lock()
+			.map(|last| last.elapsed())
+			.unwrap_or_default()
+	}
+
+	/// Locks the session, unless it already is
+	pub fn lock(&self, app: &AppHandle, reason: SessionLockReason) {
+		if self.locked.swap(true, Ordering::SeqCst) {
+			return;
+		}
+		log::info!("Locking session: {:?}", reason);
+
+		if let Some(manager) = app.try_state::<DeviceManager>() {
+			manager.cancel_for_lock();
+		}
+		// The running commissioning session holds the PIN of the keys
+		if let Some(commissioning) = app.try_state::<Commissioning>() {
+			commissioning.stop();
+		}
+		fido::pin_token::clear();
+		devmode::lock();
+
+		let event = SessionLocked {
+			reason,
+			locked_at: now(),
+		};
+		if let Err(e) = app.emit(SESSION_LOCKED_EVENT, &event) {
+			log::warn!("Failed to broadcast session lock: {}", e);
+		}
+	}
+}
+
+/// Whether the desktop session is locked, `None` if it cannot be determined
+#[cfg(target_os = "linux")]
+fn screen_locked() -> Option<bool> {
+	let session = std::env::var("XDG_SESSION_ID").unwrap_or_else(|_| "auto".into());
+	let output = std::process::Command::new("loginctl")
+		.args(["show-session", &session, "-p", "LockedHint", "--value"])
+		.output()
+		.ok()?;
+	if !output.status.success() {
+		return None;
+	}
+	match String::from_utf8_lossy(&output.stdout).trim() {
+		"yes" => Some(true),
+		"no" => Some(false),
+		_ => None,
+	}
+}
+
+#[cfg(not(target_os = "linux"))]
+fn screen_locked() -> Option<bool> {
+	None
+}
+
+/// Starts the background thread enforcing the session policy
+pub fn start_watcher(app: AppHandle) {
+	std::thread::spawn(move || {
+		let mut was_screen_locked = false;
+		loop {
+			std::thread::sleep(WATCH_INTERVAL);
+			let session = app.state::<SessionManager>();
+			let policy = session.policy();
+
+			if policy.lock_on_screen_lock {
+				let screen_locked = screen_locked().unwrap_or(false);
+				if screen_locked && !was_screen_locked {
+					session.lock(&app, SessionLockReason::ScreenLocked);
+				}
+				was_screen_locked = screen_locked;
+			}
+
+			if let Some(minutes) = policy.idle_timeout_minutes
+				&& !session.is_locked()
+				&& session.idle_time() >= Duration::from_secs(minutes as u64 * 60)
+			{
+				session.lock(&app, SessionLockReason::Idle);
+			}
+		}
+	});
+}
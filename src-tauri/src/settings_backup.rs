@@ -0,0 +1,215 @@
+//! Backups of the application settings that only the administrator's own key can restore.
+//!
+//! The inventory, workspaces and other settings files are encrypted with AES-256-GCM under a key
+//! derived through the `hmac-secret` extension of a dedicated resident credential, with a fresh
+//! salt for every backup. Restoring a provisioning workstation therefore needs the key the backup
+//! was made with, its PIN and a touch.
+
+use crate::{
+	error::PFError,
+	fido::{self, hmac_secret::SecretCredential},
+	inventory, paths, storage,
+	types::SettingsBackupReport,
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use rand::Rng;
+use ring::aead::{AES_256_GCM, Aad, LessSafeKey, Nonce, UnboundKey};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Resident credential backing the backup keys
+const BACKUP_CREDENTIAL: SecretCredential = SecretCredential {
+	rp_id: "picoforge.backup",
+	user_id: b"picoforge-settings-backup",
+	user_name: "PicoForge Settings Backup",
+	label: "the settings backup",
+};
+
+const BACKUP_FORMAT: &str = "picoforge-settings-backup";
+const BACKUP_VERSION: u32 = 1;
+
+/// Settings files included in a backup. Jobs are left out, they only make sense on the
+/// workstation that ran them.
+const BACKUP_FILES: &[&str] = &[
+	"inventory.json",
+	"workspaces.json",
+	"reminders.json",
+	"session.json",
+	"log_retention.json",
+	"pin_policy.json",
+	"report_secret.key",
+];
+
+/// Files of `BACKUP_FILES` that are not JSON, kept as base64 strings in the backup
+const BINARY_FILES: &[&str] = &["report_secret.key"];
+
+/// Backup file as written to disk. Binary fields are hex, the ciphertext is base64.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BackupEnvelope {
+	format: String,
+	version: u32,
+	created_at: u64,
+	credential_id: String,
+	salt: String,
+	nonce: String,
+	ciphertext: String,
+}
+
+/// Associated data binding the ciphertext to the format version
+fn backup_aad() -> Vec<u8> {
+	format!("{} v{}", BACKUP_FORMAT, BACKUP_VERSION).into_bytes()
+}
+
+fn backup_key(
+	pin: &str,
+	credential_id: Option<&[u8]>,
+	salt: [u8; 32],
+) -> Result<(Vec<u8>, LessSafeKey), PFError> {
+	let device = fido::connect(None)?;
+	let credential_id = match credential_id {
+		Some(id) => id.to_vec(),
+		None => BACKUP_CREDENTIAL.ensure(&device, pin)?,
+	};
+	let secret = BACKUP_CREDENTIAL.derive(&device, pin, &credential_id, salt)?;
+
+	let key = UnboundKey::new(&AES_256_GCM, &secret)
+		.map_err(|_| PFError::Io("Failed to build backup key".into()))?;
+	Ok((credential_id, LessSafeKey::new(key)))
+}
+
+/// Encrypts the settings files into `path`. Needs a touch, and the backup credential is created
+/// on the connected key the first time.
+pub fn export(pin: &str, path: &Path) -> Result<SettingsBackupReport, PFError> {
+	let data_dir = paths::data_dir();
+	let mut files = BTreeMap::new();
+	for name in BACKUP_FILES {
+		match storage::read(&data_dir.join(name)) {
+			Ok(data) if BINARY_FILES.contains(name) => {
+				files.insert(
+					name.to_string(),
+					serde_json::Value::String(STANDARD.encode(&data)),
+				);
+			}
+			Ok(data) => {
+				let content: serde_json::Value = serde_json::from_slice(&data)
+					.map_err(|e| PFError::Io(format!("{} is corrupted: {}", name, e)))?;
+				files.insert(name.to_string(), content);
+			}
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+			Err(e) => return Err(PFError::Io(format!("Failed to read {}: {}", name, e))),
+		}
+	}
+	log::info!("Backing up {} settings file(s) to {:?}", files.len(), path);
+
+	let salt: [u8; 32] = rand::rng().random();
+	let (credential_id, key) = backup_key(pin, None, salt)?;
+
+	let mut plaintext = serde_json::to_vec(&files).map_err(|e| PFError::Io(e.to_string()))?;
+	let nonce: [u8; 12] = rand::rng().random();
+	key.seal_in_place_append_tag(
+		Nonce::assume_unique_for_key(nonce),
+		Aad::from(backup_aad()),
+		&mut plaintext,
+	)
+	.map_err(|_| PFError::Io("Failed to encrypt the backup".into()))?;
+
+	let envelope = BackupEnvelope {
+		format: BACKUP_FORMAT.into(),
+		version: BACKUP_VERSION,
+		created_at: inventory::now(),
+		credential_id: hex::encode(&credential_id),
+		salt: hex::encode(salt),
+		nonce: hex::encode(nonce),
+		ciphertext: STANDARD.encode(&plaintext),
+	};
+	let data = serde_json::to_vec_pretty(&envelope).map_err(|e| PFError::Io(e.to_string()))?;
+	storage::write(path, &data)
+		.map_err(|e| PFError::Io(format!("Failed to write the backup: {}", e)))?;
+
+	Ok(SettingsBackupReport {
+		path: path.to_string_lossy().into_owned(),
+		files: files.into_keys().collect(),
+		created_at: envelope.created_at,
+	})
+}
+
+/// Decrypts the backup at `path` with the connected key and replaces the settings files it holds.
+///
+/// Nothing is written unless the whole backup decrypts. Session and log retention settings take
+/// effect at the next start.
+pub fn restore(pin: &str, path: &Path) -> Result<SettingsBackupReport, PFError> {
+	let data = storage::read(path)
+		.map_err(|e| PFError::Io(format!("Failed to read the backup: {}", e)))?;
+	let envelope: BackupEnvelope = serde_json::from_slice(&data)
+		.map_err(|_| PFError::Io("Not a PicoForge settings backup".into()))?;
+	if envelope.format != BACKUP_FORMAT {
+		return Err(PFError::Io("Not a PicoForge settings backup".into()));
+	}
+	if envelope.version != BACKUP_VERSION {
+		return Err(PFError::Io(format!(
+			"Settings backup version {} is not supported",
+			envelope.version
+		)));
+	}
+
+	let corrupted = || PFError::Io("The settings backup is corrupted".into());
+	let credential_id = hex::decode(&envelope.credential_id).map_err(|_| corrupted())?;
+	let salt: [u8; 32] = hex::decode(&envelope.salt)
+		.ok()
+		.and_then(|salt| salt.try_into().ok())
+		.ok_or_else(corrupted)?;
+	let nonce = hex::decode(&envelope.nonce).map_err(|_| corrupted())?;
+	let mut ciphertext = STANDARD
+		.decode(&envelope.ciphertext)
+		.map_err(|_| corrupted())?;
+	log::info!("Restoring settings backup {:?}", path);
+
+	let (_, key) = backup_key(pin, Some(&credential_id), salt).map_err(|e| {
+		PFError::Device(format!(
+			"Could not derive the backup key, is this the key the backup was made with? {}",
+			e
+		))
+	})?;
+	let nonce = Nonce::try_assume_unique_for_key(&nonce).map_err(|_| corrupted())?;
+	let plaintext = key
+		.open_in_place(nonce, Aad::from(backup_aad()), &mut ciphertext)
+		.map_err(|_| PFError::Io("The settings backup could not be decrypted".into()))?;
+	let files: BTreeMap<String, serde_json::Value> =
+		serde_json::from_slice(plaintext).map_err(|_| corrupted())?;
+
+	// Only known file names are written, whatever the backup holds. Everything is decoded before
+	// the first file is written.
+	let mut decoded = Vec::new();
+	for (name, content) in &files {
+		if !BACKUP_FILES.contains(&name.as_str()) {
+			log::warn!("Skipping unknown file {} in the settings backup", name);
+			continue;
+		}
+		let data = if BINARY_FILES.contains(&name.as_str()) {
+			content
+				.as_str()
+				.and_then(|content| STANDARD.decode(content).ok())
+				.ok_or_else(corrupted)?
+		} else {
+			serde_json::to_vec_pretty(content).map_err(|e| PFError::Io(e.to_string()))?
+		};
+		decoded.push((name, data));
+	}
+
+	let data_dir = paths::data_dir();
+	let mut restored = Vec::new();
+	for (name, data) in decoded {
+		storage::write(&data_dir.join(name), &data)
+			.map_err(|e| PFError::Io(format!("Failed to restore {}: {}", name, e)))?;
+		restored.push(name.clone());
+	}
+
+	log::info!("Restored {} settings file(s)", restored.len());
+	Ok(SettingsBackupReport {
+		path: path.to_string_lossy().into_owned(),
+		files: restored,
+		created_at: envelope.created_at,
+	})
+}
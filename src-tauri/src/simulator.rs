@@ -0,0 +1,613 @@
+//! Simulated pico-fido device backing the training mode.
+//!
+//! While training mode is enabled, the provisioning commands are answered by an in-memory device
+//! instead of real hardware, so new operators can practice the workflow without risking a key.
+//! Scenarios reproduce common failures such as a blocked PIN or a missed touch.
+//!
+//! For QA, faults can additionally be injected into every simulated command (dropped packets,
+//! forced CTAP errors, delayed responses) to exercise the retry and recovery paths.
+
+use crate::{
+	error::PFError,
+	fido::{
+		self,
+		constants::{AAGUID, Ctap2Error, MAX_PIN_RETRIES},
+	},
+	operation::report_phase,
+	types::*,
+};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// PIN the simulated device starts with
+const SIMULATED_PIN: &str = "123456";
+const SIMULATED_SERIAL: &str = "DEMO0000C0FFEE42";
+/// HID path the simulated device is listed with
+const SIMULATED_PATH: &str = "training";
+const SIMULATED_FIRMWARE: &str = "7.2";
+const SIMULATED_FIRMWARE_VERSION: FirmwareVersion = FirmwareVersion { major: 7, minor: 2 };
+/// Resident credentials the simulated device has room for, as pico-fido
+const SIMULATED_MAX_CREDENTIALS: u32 = 256;
+
+/// Time the simulated device waits for a touch
+const SIMULATED_TOUCH_DELAY: Duration = Duration::from_millis(1500);
+
+struct SimulatedCredential {
+	credential: StoredCredential,
+	sign_count: u32,
+}
+
+struct SimulatedDevice {
+	scenario: TrainingScenario,
+	config: AppConfig,
+	pin: Option<String>,
+	pin_retries: u8,
+	min_pin_length: u32,
+	force_pin_change: bool,
+	always_uv: bool,
+	credentials: Vec<SimulatedCredential>,
+	last_verified: HashMap<String, u32>,
+}
+
+/// `None` while training mode is off
+static DEVICE: Mutex<Option<SimulatedDevice>> = Mutex::new(None);
+
+struct FaultState {
+	config: Option<FaultInjectionConfig>,
+	/// Commands seen since the faults were configured
+	commands: u32,
+}
+
+static FAULTS: Mutex<FaultState> = Mutex::new(FaultState {
+	config: None,
+	commands: 0,
+});
+
+/// Simulated time until the host gives up on a dropped packet
+const DROPPED_PACKET_TIMEOUT: Duration = Duration::from_secs(3);
+
+fn demo_credential(rp_id: &str, rp_name: &str, user: &str, id: &str) -> SimulatedCredential {
+	SimulatedCredential {
+		credential: StoredCredential {
+			rp_id: rp_id.into(),
+			rp_name: rp_name.into(),
+			user_name: user.into(),
+			user_display_name: user.into(),
+			user_id: hex::encode(user),
+			credential_id: id.into(),
+			rp_display_name: None,
+			account_url: None,
+			cred_protect: None,
+			algorithm: None,
+		},
+		sign_count: 12,
+	}
+}
+
+impl SimulatedDevice {
+	fn new(scenario: TrainingScenario) -> Self {
+		Self {
+			scenario,
+			config: AppConfig {
+				vid: "2E8A".into(),
+				pid: "10FE".into(),
+				product_name: "Pico Key (Training)".into(),
+				led_gpio: 25,
+				led_brightness: 8,
+				touch_timeout: 30,
+				led_driver: Some(LedDriver::Pico),
+				led_dimmable: true,
+				power_cycle_on_reset: false,
+				led_steady: false,
+				enable_secp256k1: false,
+			},
+			pin: Some(SIMULATED_PIN.into()),
+			pin_retries: if scenario == TrainingScenario::PinBlocked {
+				0
+			} else {
+				MAX_PIN_RETRIES
+			},
+			min_pin_length: 4,
+			force_pin_change: false,
+			always_uv: false,
+			credentials: vec![
+				demo_credential("github.com", "GitHub", "octocat", "a1b2c3d4e5f60718"),
+				demo_credential("example.com", "Example", "alice", "0f1e2d3c4b5a6978"),
+			],
+			last_verified: HashMap::new(),
+		}
+	}
+
+	fn ctap_error(err: Ctap2Error) -> PFError {
+		match fault_error(err as u8) {
+			PFError::Device(_) => {
+				PFError::Device(format!("CTAP2 error {:?} (0x{:02X})", err, err as u8))
+			}
+			other => other,
+		}
+	}
+
+	/// Checks the PIN like the authenticator does, including the retry counter
+	fn check_pin(&mut self, pin: &str) -> Result<(), PFError> {
+		report_phase(
+			PhaseVerb::Authenticate,
+			"the training key",
+			UserAction::None,
+		);
+		if self.pin_retries == 0 {
+			return Err(Self::ctap_error(Ctap2Error::PinBlocked));
+		}
+		match &self.pin {
+			None => Err(Self::ctap_error(Ctap2Error::PinNotSet)),
+			Some(expected) if expected == pin => {
+				self.pin_retries = MAX_PIN_RETRIES;
+				Ok(())
+			}
+			Some(_) => {
+				self.pin_retries -= 1;
+				if self.pin_retries == 0 {
+					Err(Self::ctap_error(Ctap2Error::PinBlocked))
+				} else {
+					Err(PFError::PinInvalid {
+						retries_left: Some(self.pin_retries as u32),
+					})
+				}
+			}
+		}
+	}
+
+	/// Waits for the simulated touch, or times out in the touch timeout scenario
+	fn wait_for_touch(&self, object: &str) -> Result<(), PFError> {
+		report_phase(PhaseVerb::Sign, object, UserAction::TouchDevice);
+		std::thread::sleep(SIMULATED_TOUCH_DELAY);
+		if self.scenario == TrainingScenario::TouchTimeout {
+			return Err(Self::ctap_error(Ctap2Error::UserActionTimeout));
+		}
+		Ok(())
+	}
+
+	fn credential_mut(&mut self, credential_id: &str) -> Result<&mut SimulatedCredential, PFError> {
+		self.credentials
+			.iter_mut()
+			.find(|c| c.credential.credential_id == credential_id)
+			.ok_or_else(|| Self::ctap_error(Ctap2Error::NoCredentials))
+	}
+}
+
+pub fn is_active() -> bool {
+	DEVICE.lock().map(|d| d.is_some()).unwrap_or(false)
+}
+
+/// Errors out for commands the training device does not simulate
+pub fn ensure_inactive() -> Result<(), PFError> {
+	if is_active() {
+		return Err(PFError::Device(
+			"This feature is not available in training mode".into(),
+		));
+	}
+	Ok(())
+}
+
+/// Turns training mode on with a fresh simulated device, or off again.
+pub fn set_training_mode(enabled: bool, scenario: TrainingScenario) -> TrainingStatus {
+	if let Ok(mut device) = DEVICE.lock() {
+		*device = enabled.then(|| SimulatedDevice::new(scenario));
+	}
+	log::info!(
+		"Training mode {} ({:?})",
+		if enabled { "enabled" } else { "disabled" },
+		scenario
+	);
+	training_status()
+}
+
+pub fn training_status() -> TrainingStatus {
+	let scenario = DEVICE
+		.lock()
+		.ok()
+		.and_then(|d| d.as_ref().map(|d| d.scenario));
+	TrainingStatus {
+		enabled: scenario.is_some(),
+		scenario: scenario.unwrap_or(TrainingScenario::Normal),
+	}
+}
+
+/// Configures the faults injected into simulated commands, `None` clears them.
+pub fn set_fault_injection(config: Option<FaultInjectionConfig>) {
+	log::warn!(
+		"Fault injection for the training device set to {:?}",
+		config
+	);
+	if let Ok(mut faults) = FAULTS.lock() {
+		faults.config = config;
+		faults.commands = 0;
+	}
+}
+
+pub fn fault_injection() -> Option<FaultInjectionConfig> {
+	FAULTS.lock().ok().and_then(|f| f.config.clone())
+}
+
+/// Error of a CTAP status, as the real key would report it
+fn fault_error(status: u8) -> PFError {
+	match fido::ctap_status_error(status, "the request") {
+		PFError::Device(_) => PFError::Device(format!("CTAP2 error 0x{:02X}", status)),
+		other => other,
+	}
+}
+
+/// Applies the configured faults to `command`, returning the error it should fail with
+fn inject_faults(command: &str) -> Result<(), PFError> {
+	let (config, count) = {
+		let Ok(mut faults) = FAULTS.lock() else {
+			return Ok(());
+		};
+		let Some(config) = faults.config.clone() else {
+			return Ok(());
+		};
+		faults.commands += 1;
+		(config, faults.commands)
+	};
+
+	if config.delay_ms > 0 {
+		std::thread::sleep(Duration::from_millis(config.delay_ms));
+	}
+
+	if let Some(n) = config.drop_every_nth.filter(|n| *n > 0)
+		&& count % n == 0
+	{
+		log::warn!("Fault injection: dropping {} (command #{})", command, count);
+		std::thread::sleep(DROPPED_PACKET_TIMEOUT);
+		return Err(PFError::TransportIo(
+			"Device did not respond (timeout)".into(),
+		));
+	}
+
+	if let Some(fault) = config.command_errors.iter().find(|f| f.command == command) {
+		log::warn!(
+			"Fault injection: failing {} with CTAP error 0x{:02X}",
+			command,
+			fault.ctap_error
+		);
+		return Err(fault_error(fault.ctap_error));
+	}
+
+	Ok(())
+}
+
+fn with_device<T>(
+	command: &str,
+	f: impl FnOnce(&mut SimulatedDevice) -> Result<T, PFError>,
+) -> Result<T, PFError> {
+	inject_faults(command)?;
+	let mut guard = DEVICE
+		.lock()
+		.map_err(|_| PFError::Device("Training device state is poisoned".into()))?;
+	let device = guard
+		.as_mut()
+		.ok_or_else(|| PFError::Device("Training mode is not enabled".into()))?;
+	f(device)
+}
+
+/// The simulated device, listed like a connected key
+pub fn list_devices() -> Result<Vec<FidoHidDevice>, PFError> {
+	with_device("list_devices", |device| {
+		Ok(vec![FidoHidDevice {
+			path: SIMULATED_PATH.into(),
+			port_path: None,
+			vid: device.config.vid.clone(),
+			pid: device.config.pid.clone(),
+			product_name: device.config.product_name.clone(),
+		}])
+	})
+}
+
+pub fn read_device_details() -> Result<FullDeviceStatus, PFError> {
+	with_device("read_device_details", |device| {
+		report_phase(PhaseVerb::Read, "device configuration", UserAction::None);
+		Ok(FullDeviceStatus {
+			info: DeviceInfo {
+				serial: SIMULATED_SERIAL.into(),
+				flash_used: 96,
+				flash_total: 1024,
+				flash_used_bytes: 96 * 1024,
+				flash_total_bytes: 1024 * 1024,
+				firmware_version: SIMULATED_FIRMWARE.into(),
+				firmware: Some(SIMULATED_FIRMWARE_VERSION),
+			},
+			flash: Some(FlashBreakdown::new(
+				928 * 1024,
+				96 * 1024,
+				1024 * 1024,
+				14,
+				2048 * 1024,
+			)),
+			config: device.config.clone(),
+			secure_boot: false,
+			secure_lock: false,
+			method: "Training".into(),
+			format_hints: FormatHints::default(),
+		})
+	})
+}
+
+pub fn write_config(config: AppConfigInput, pin: Option<String>) -> Result<String, PFError> {
+	with_device("write_config", |device| {
+		if let Some(pin) = pin.as_deref() {
+			device.check_pin(pin)?;
+		}
+		report_phase(
+			PhaseVerb::Write,
+			"device configuration",
+			UserAction::TouchDevice,
+		);
+
+		let c = &mut device.config;
+		if let Some(v) = config.vid {
+			c.vid = v;
+		}
+		if let Some(v) = config.pid {
+			c.pid = v;
+		}
+		if let Some(v) = config.product_name {
+			c.product_name = v;
+		}
+		c.led_gpio = config.led_gpio.unwrap_or(c.led_gpio);
+		c.led_brightness = config.led_brightness.unwrap_or(c.led_brightness);
+		c.touch_timeout = config.touch_timeout.unwrap_or(c.touch_timeout);
+		c.led_driver = config.led_driver.or(c.led_driver);
+		c.led_dimmable = config.led_dimmable.unwrap_or(c.led_dimmable);
+		c.power_cycle_on_reset = config
+			.power_cycle_on_reset
+			.unwrap_or(c.power_cycle_on_reset);
+		c.led_steady = config.led_steady.unwrap_or(c.led_steady);
+		c.enable_secp256k1 = config.enable_secp256k1.unwrap_or(c.enable_secp256k1);
+
+		Ok("Configuration applied successfully (training device)".into())
+	})
+}
+
+pub fn reboot_device(_to_bootsel: bool) -> Result<String, PFError> {
+	with_device("reboot", |_| {
+		report_phase(PhaseVerb::Reboot, "device", UserAction::None);
+		Ok("Reboot command sent".into())
+	})
+}
+
+pub fn get_fido_info() -> Result<FidoDeviceInfo, PFError> {
+	with_device("get_fido_info", |device| {
+		report_phase(PhaseVerb::Read, "FIDO device info", UserAction::None);
+		let options = [
+			("rk", true),
+			("up", true),
+			("clientPin", device.pin.is_some()),
+			("credMgmt", true),
+			("largeBlobs", true),
+			("authnrCfg", true),
+			("setMinPINLength", true),
+			("alwaysUv", device.always_uv),
+		];
+		Ok(FidoDeviceInfo {
+			versions: vec!["FIDO_2_0".into(), "FIDO_2_1".into(), "U2F_V2".into()],
+			extensions: vec![
+				"credProtect".into(),
+				"hmac-secret".into(),
+				"largeBlobKey".into(),
+			],
+			aaguid: hex::encode_upper(AAGUID),
+			options: options.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+			max_msg_size: 1024,
+			pin_protocols: vec![2, 1],
+			min_pin_length: device.min_pin_length,
+			firmware_version: SIMULATED_FIRMWARE.into(),
+			firmware: SIMULATED_FIRMWARE_VERSION,
+		})
+	})
+}
+
+pub fn change_fido_pin(current_pin: Option<String>, new_pin: String) -> Result<String, PFError> {
+	with_device("change_fido_pin", |device| {
+		fido::pin_policy::check_new_pin(&new_pin, current_pin.as_deref(), device.min_pin_length)?;
+		if (new_pin.chars().count() as u32) < device.min_pin_length {
+			return Err(SimulatedDevice::ctap_error(Ctap2Error::PinPolicyViolation));
+		}
+		match current_pin {
+			Some(old) => {
+				device.check_pin(&old)?;
+				report_phase(PhaseVerb::Write, "FIDO PIN", UserAction::None);
+				device.pin = Some(new_pin);
+				device.force_pin_change = false;
+				Ok("PIN Changed Successfully".into())
+			}
+			None if device.pin.is_some() => {
+				Err(SimulatedDevice::ctap_error(Ctap2Error::NotAllowed))
+			}
+			None => {
+				report_phase(PhaseVerb::Write, "FIDO PIN", UserAction::None);
+				device.pin = Some(new_pin);
+				Ok("PIN Set Successfully".into())
+			}
+		}
+	})
+}
+
+/// Most RP IDs the simulated key lets read the minimum PIN length
+const MAX_MIN_PIN_LENGTH_RP_IDS: u32 = 8;
+
+fn pin_policy(device: &SimulatedDevice) -> PinPolicy {
+	PinPolicy {
+		min_pin_length: device.min_pin_length,
+		force_pin_change: device.force_pin_change,
+		max_rp_ids: MAX_MIN_PIN_LENGTH_RP_IDS,
+		set_min_pin_length_supported: true,
+	}
+}
+
+pub fn get_pin_policy() -> Result<PinPolicy, PFError> {
+	with_device("get_pin_policy", |device| Ok(pin_policy(device)))
+}
+
+pub fn set_min_pin_length(
+	current_pin: String,
+	min_pin_length: u8,
+	rp_ids: Vec<String>,
+) -> Result<PinPolicy, PFError> {
+	with_device("set_min_pin_length", |device| {
+		device.check_pin(&current_pin)?;
+		if u32::from(min_pin_length) < device.min_pin_length {
+			return Err(SimulatedDevice::ctap_error(Ctap2Error::PinPolicyViolation));
+		}
+		if rp_ids.len() > MAX_MIN_PIN_LENGTH_RP_IDS as usize {
+			return Err(SimulatedDevice::ctap_error(Ctap2Error::LimitExceeded));
+		}
+		report_phase(PhaseVerb::Write, "minimum PIN length", UserAction::None);
+		device.min_pin_length = min_pin_length.into();
+		Ok(pin_policy(device))
+	})
+}
+
+pub fn force_pin_change(pin: &str) -> Result<(), PFError> {
+	with_device("force_pin_change", |device| {
+		device.check_pin(pin)?;
+		report_phase(PhaseVerb::Write, "PIN change requirement", UserAction::None);
+		device.force_pin_change = true;
+		Ok(())
+	})
+}
+
+pub fn set_always_uv(pin: &str, enabled: bool) -> Result<bool, PFError> {
+	with_device("set_always_uv", |device| {
+		if device.always_uv == enabled {
+			return Ok(false);
+		}
+		device.check_pin(pin)?;
+		report_phase(PhaseVerb::Write, "alwaysUv setting", UserAction::None);
+		device.always_uv = enabled;
+		Ok(true)
+	})
+}
+
+pub fn get_pin_status() -> Result<PinStatus, PFError> {
+	with_device("get_pin_status", |device| {
+		report_phase(PhaseVerb::Read, "PIN retry counters", UserAction::None);
+		Ok(PinStatus {
+			pin_set: device.pin.is_some(),
+			pin_retries: device.pin_retries.into(),
+			uv_retries: None,
+			blocked: device.pin_retries == 0,
+			power_cycle_required: false,
+		})
+	})
+}
+
+pub fn get_pin_retries() -> Result<i32, PFError> {
+	with_device("get_pin_retries", |device| {
+		report_phase(PhaseVerb::Read, "PIN retry counter", UserAction::None);
+		Ok(device.pin_retries.into())
+	})
+}
+
+pub fn get_credentials(pin: String) -> Result<Vec<StoredCredential>, PFError> {
+	with_device("get_credentials", |device| {
+		device.check_pin(&pin)?;
+		report_phase(PhaseVerb::Read, "stored passkeys", UserAction::None);
+		Ok(device
+			.credentials
+			.iter()
+			.map(|c| c.credential.clone())
+			.collect())
+	})
+}
+
+pub fn get_creds_metadata(pin: &str) -> Result<(u32, u32), PFError> {
+	with_device("get_creds_metadata", |device| {
+		device.check_pin(pin)?;
+		report_phase(PhaseVerb::Read, "passkey slot usage", UserAction::None);
+		let existing = device.credentials.len() as u32;
+		Ok((existing, SIMULATED_MAX_CREDENTIALS.saturating_sub(existing)))
+	})
+}
+
+pub fn delete_credential(pin: String, credential_id: String) -> Result<String, PFError> {
+	with_device("delete_credential", |device| {
+		device.check_pin(&pin)?;
+		report_phase(PhaseVerb::Delete, "the passkey", UserAction::None);
+		let before = device.credentials.len();
+		device
+			.credentials
+			.retain(|c| c.credential.credential_id != credential_id);
+		if device.credentials.len() == before {
+			return Err(SimulatedDevice::ctap_error(Ctap2Error::NoCredentials));
+		}
+		Ok("Credential deleted successfully".into())
+	})
+}
+
+pub fn update_credential(
+	pin: String,
+	credential_id: String,
+	user_name: String,
+	user_display_name: String,
+) -> Result<String, PFError> {
+	with_device("update_credential", |device| {
+		device.check_pin(&pin)?;
+		report_phase(PhaseVerb::Write, "the passkey user", UserAction::None);
+		let credential = &mut device.credential_mut(&credential_id)?.credential;
+		credential.user_name = user_name;
+		credential.user_display_name = user_display_name;
+		Ok("Credential updated successfully".into())
+	})
+}
+
+pub fn get_credential_sign_count(
+	pin: String,
+	rp_id: String,
+	credential_id: String,
+) -> Result<CredentialSignCount, PFError> {
+	with_device("get_credential_sign_count", |device| {
+		device.check_pin(&pin)?;
+		device.wait_for_touch(&format!("the passkey for {}", rp_id))?;
+		let cred = device.credential_mut(&credential_id)?;
+		cred.sign_count += 1;
+		Ok(CredentialSignCount {
+			credential_id,
+			rp_id,
+			sign_count: cred.sign_count,
+		})
+	})
+}
+
+/// Same as the real verification, but tracks counters in memory to keep the inventory clean
+pub fn verify_credential(
+	pin: String,
+	rp_id: String,
+	credential_id: String,
+) -> Result<CredentialVerification, PFError> {
+	with_device("verify_credential", |device| {
+		device.check_pin(&pin)?;
+		device.wait_for_touch(&format!("the passkey for {}", rp_id))?;
+		let sign_count = {
+			let cred = device.credential_mut(&credential_id)?;
+			cred.sign_count += 1;
+			cred.sign_count
+		};
+
+		let previous_sign_count = device
+			.last_verified
+			.insert(credential_id.clone(), sign_count);
+		let sign_count_status = match previous_sign_count {
+			None => SignCountStatus::FirstSeen,
+			Some(_) => SignCountStatus::Increased,
+		};
+
+		Ok(CredentialVerification {
+			credential_id,
+			rp_id,
+			signature_valid: true,
+			sign_count,
+			previous_sign_count,
+			sign_count_status,
+		})
+	})
+}
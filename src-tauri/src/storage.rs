@@ -0,0 +1,125 @@
+//! Crash-safe storage of the state PicoForge keeps on the host.
+//!
+//! Provisioning stations are often switched off in the middle of a run, so files are never
+//! rewritten in place: the new content goes to a temporary file next to the original, is flushed
+//! to disk and then renamed over the original. A power cut leaves either the old or the new
+//! content, plus possibly the temporary file, which `recover` cleans up at the next start.
+//!
+//! Every write gets a temporary file of its own, so concurrent writers of a file cannot truncate
+//! or rename each other's half-written content. Writes do not serialize read-modify-write cycles
+//! though, stores changed from several threads hold a lock of their own around them.
+
+#[cfg(feature = "gui")]
+use crate::paths;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const TEMP_SUFFIX: &str = ".tmp";
+
+/// Number of the next temporary file of this process
+static NEXT_TEMP: AtomicU64 = AtomicU64::new(0);
+
+/// Unique temporary file next to `path`, e.g. `inventory.json.1234-7.tmp`
+fn temp_path(path: &Path) -> PathBuf {
+	let mut name = path.file_name().unwrap_or_default().to_os_string();
+	name.push(format!(
+		".{}-{}{}",
+		std::process::id(),
+		NEXT_TEMP.fetch_add(1, Ordering::Relaxed),
+		TEMP_SUFFIX
+	));
+	path.with_file_name(name)
+}
+
+/// Name of the file a temporary file was written for. Files named `<name>.tmp` were written by
+/// earlier versions, which used a single temporary file per original.
+#[cfg(feature = "gui")]
+fn original_name(temp_name: &str) -> Option<&str> {
+	let name = temp_name.strip_suffix(TEMP_SUFFIX)?;
+	let unique = |s: &str| {
+		s.split_once('-').is_some_and(|(pid, n)| {
+			!pid.is_empty()
+				&& !n.is_empty()
+				&& pid.bytes().chain(n.bytes()).all(|b| b.is_ascii_digit())
+		})
+	};
+	match name.rsplit_once('.') {
+		Some((original, id)) if unique(id) => Some(original),
+		_ => Some(name),
+	}
+}
+
+/// Flushes the directory entry of a rename to disk. Directories cannot be opened on Windows,
+/// where the rename is durable once it returns.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+	if cfg!(unix) {
+		File::open(dir)?.sync_all()?;
+	}
+	Ok(())
+}
+
+pub fn read(path: &Path) -> io::Result<Vec<u8>> {
+	fs::read(path)
+}
+
+/// Replaces the content of `path` with `data`, atomically and durably
+pub fn write(path: &Path, data: &[u8]) -> io::Result<()> {
+	let temp = temp_path(path);
+	let result = OpenOptions::new()
+		.write(true)
+		.create_new(true)
+		.open(&temp)
+		.and_then(|mut file| {
+			file.write_all(data)?;
+			file.sync_all()
+		})
+		.and_then(|()| fs::rename(&temp, path));
+	if let Err(e) = result {
+		let _ = fs::remove_file(&temp);
+		return Err(e);
+	}
+
+	match path.parent() {
+		Some(dir) if !dir.as_os_str().is_empty() => sync_dir(dir),
+		_ => Ok(()),
+	}
+}
+
+/// Cleans up writes interrupted by a crash or power cut, before any state is loaded.
+///
+/// A temporary file next to an existing original was not renamed yet, so the original still holds
+/// the last complete state and the temporary file is deleted. Without an original it was the first
+/// write of that file: it is kept if it holds complete JSON, which all persisted state is.
+#[cfg(feature = "gui")]
+pub fn recover() {
+	let Ok(entries) = fs::read_dir(paths::data_dir()) else {
+		return;
+	};
+	for temp in entries.flatten().map(|entry| entry.path()) {
+		let Some(name) = temp
+			.file_name()
+			.and_then(|name| name.to_str())
+			.and_then(original_name)
+		else {
+			continue;
+		};
+		let original = temp.with_file_name(name);
+
+		let complete = !original.exists()
+			&& fs::read(&temp)
+				.is_ok_and(|data| serde_json::from_slice::<serde_json::Value>(&data).is_ok());
+		if complete {
+			log::warn!("Recovering {:?} from an interrupted write", original);
+			if let Err(e) = fs::rename(&temp, &original) {
+				log::error!("Failed to recover {:?}: {}", original, e);
+			}
+		} else {
+			log::warn!("Discarding interrupted write of {:?}", original);
+			if let Err(e) = fs::remove_file(&temp) {
+				log::error!("Failed to delete {:?}: {}", temp, e);
+			}
+		}
+	}
+}
@@ -1,6 +1,9 @@
 #![allow(unused)]
 
+pub mod v1;
+
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 struct PForgeState {
 	device_info: DeviceInfo,
@@ -10,12 +13,63 @@ struct PForgeState {
 #[serde(rename_all = "camelCase")]
 pub struct DeviceInfo {
 	pub serial: String,
+	/// Rounded down to KiB, prefer the byte counts for display
 	pub flash_used: u32,
 	pub flash_total: u32,
+	pub flash_used_bytes: u32,
+	pub flash_total_bytes: u32,
+	/// Preformatted "major.minor", or "Unknown"
 	pub firmware_version: String,
+	pub firmware: Option<FirmwareVersion>,
+}
+
+/// Firmware version as numbers, for the frontend to render per locale
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FirmwareVersion {
+	pub major: u8,
+	pub minor: u8,
+}
+
+impl FirmwareVersion {
+	/// Unpacks the version as reported by GetInfo, major in the high byte
+	pub fn from_packed(packed: u32) -> Self {
+		Self {
+			major: (packed >> 8) as u8,
+			minor: packed as u8,
+		}
+	}
+
+	/// Parses "major.minor"
+	pub fn parse(version: &str) -> Option<Self> {
+		let (major, minor) = version.trim().split_once('.')?;
+		Some(Self {
+			major: major.parse().ok()?,
+			minor: minor.parse().ok()?,
+		})
+	}
+}
+
+/// How the raw numeric fields are meant to be rendered. The backend leaves the formatting of
+/// sizes and versions to the frontend, which knows the user's locale.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FormatHints {
+	/// Sizes are powers of two, use 1024-based units (KiB, MiB)
+	pub binary_sizes: bool,
+	/// Components to show of firmware versions, "7.2" has two
+	pub version_components: u8,
+}
+
+impl Default for FormatHints {
+	fn default() -> Self {
+		Self {
+			binary_sizes: true,
+			version_components: 2,
+		}
+	}
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct AppConfig {
 	pub vid: String,
@@ -25,14 +79,14 @@ pub struct AppConfig {
 	pub led_brightness: u8,
 	pub touch_timeout: u8,
 	#[serde(skip_serializing_if = "Option::is_none")]
-	pub led_driver: Option<u8>,
+	pub led_driver: Option<LedDriver>,
 	pub led_dimmable: bool,
 	pub power_cycle_on_reset: bool,
 	pub led_steady: bool,
 	pub enable_secp256k1: bool,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct AppConfigInput {
 	pub vid: Option<String>,
@@ -41,26 +95,141 @@ pub struct AppConfigInput {
 	pub led_gpio: Option<u8>,
 	pub led_brightness: Option<u8>,
 	pub touch_timeout: Option<u8>,
-	pub led_driver: Option<u8>,
+	pub led_driver: Option<LedDriver>,
 	pub led_dimmable: Option<bool>,
 	pub power_cycle_on_reset: Option<bool>,
 	pub led_steady: Option<bool>,
 	pub enable_secp256k1: Option<bool>,
 }
 
+/// LED drivers of the pico-keys-sdk, stored as their PHY_LED_DRIVER code
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(from = "u8", into = "u8")]
+pub enum LedDriver {
+	/// Single LED on a GPIO, dimmed with PWM
+	Pico,
+	/// Pimoroni boards with an RGB LED on three PWM pins
+	Pimoroni,
+	/// WS2812 (NeoPixel) RGB LED on a GPIO of the RP2040/RP2350
+	Ws2812,
+	/// LED of the Pico W, wired to the CYW43 wireless chip instead of a GPIO
+	Cyw43,
+	/// NeoPixel of ESP32-S3 boards
+	Neopixel,
+	/// Driver of newer firmware, kept so it survives a round trip
+	Other(u8),
+}
+
+impl From<u8> for LedDriver {
+	fn from(code: u8) -> Self {
+		match code {
+			1 => Self::Pico,
+			2 => Self::Pimoroni,
+			3 => Self::Ws2812,
+			4 => Self::Cyw43,
+			5 => Self::Neopixel,
+			code => Self::Other(code),
+		}
+	}
+}
+
+impl From<LedDriver> for u8 {
+	fn from(driver: LedDriver) -> Self {
+		match driver {
+			LedDriver::Pico => 1,
+			LedDriver::Pimoroni => 2,
+			LedDriver::Ws2812 => 3,
+			LedDriver::Cyw43 => 4,
+			LedDriver::Neopixel => 5,
+			LedDriver::Other(code) => code,
+		}
+	}
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct FullDeviceStatus {
 	pub info: DeviceInfo,
+	/// `None` if the firmware only reports the used and total space
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub flash: Option<FlashBreakdown>,
 	pub config: AppConfig,
 	pub secure_boot: bool,
 	pub secure_lock: bool,
 	pub method: String,
+	pub format_hints: FormatHints,
+}
+
+/// How the flash of the key is split up, in bytes.
+///
+/// pico-fido keeps every credential, blob and OATH entry in files of the same storage area and
+/// reports no usage per kind of file, only the totals.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FlashBreakdown {
+	/// Storage area for files
+	pub total: u32,
+	pub used: u32,
+	pub free: u32,
+	/// Files in the storage area, keys, credentials and settings alike
+	pub files: u32,
+	/// Size of the flash chip
+	pub flash_size: u32,
+	/// Flash outside the storage area, taken by the firmware
+	pub reserved: u32,
+}
+
+impl FlashBreakdown {
+	pub fn new(free: u32, used: u32, total: u32, files: u32, flash_size: u32) -> Self {
+		Self {
+			total,
+			used,
+			free,
+			files,
+			flash_size,
+			reserved: flash_size.saturating_sub(total),
+		}
+	}
+}
+
+/// A configuration rendered as `pico-fido-tool.py` invocations
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CliExport {
+	/// One invocation per setting, in the order they should run
+	pub commands: Vec<String>,
+	/// The commands joined into a shell script body
+	pub script: String,
+	/// Settings (as `AppConfigInput` field names) the upstream tool cannot set
+	pub unsupported: Vec<String>,
+}
+
+/// Device reachable through the Rescue Applet
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RescueDevice {
+	/// PC/SC reader the device is attached to
+	pub reader: String,
+	pub serial: String,
+	pub firmware_version: String,
 }
 
 // Fido stuff:
 
-#[derive(Serialize)]
+/// Interface the FIDO method talks to the device over
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum FidoTransport {
+	/// HID, falling back to CCID when the key cannot be reached over HID
+	#[default]
+	Auto,
+	Hid,
+	/// PC/SC, for systems where HID access is blocked
+	Ccid,
+	/// A contactless PC/SC reader, for keys reached over NFC
+	Nfc,
+}
+
+#[derive(Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct FidoDeviceInfo {
 	pub versions: Vec<String>,
@@ -72,10 +241,117 @@ pub struct FidoDeviceInfo {
 	// pub remaining_disc_creds: u32,
 	pub min_pin_length: u32,
 	pub firmware_version: String,
+	pub firmware: FirmwareVersion,
+}
+
+/// Remaining PIN and built-in user verification attempts, to warn before a key gets blocked
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PinStatus {
+	pub pin_set: bool,
+	pub pin_retries: u32,
+	/// `None` without built-in user verification, e.g. a fingerprint sensor
+	pub uv_retries: Option<u32>,
+	/// No PIN attempts left, only a reset makes the key usable again
+	pub blocked: bool,
+	/// Too many wrong PINs in a row, the key has to be reconnected before the next attempt
+	pub power_cycle_required: bool,
+}
+
+/// Minimum PIN length the key enforces, and whether it demands a new PIN
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PinPolicy {
+	pub min_pin_length: u32,
+	/// The PIN has to be changed before the key accepts it again
+	pub force_pin_change: bool,
+	/// Most RP IDs `set_min_pin_length` may allow to read the minimum, 0 if the key has no list
+	pub max_rp_ids: u32,
+	/// Whether the key supports setMinPINLength at all
+	pub set_min_pin_length_supported: bool,
+}
+
+/// PIN rules of the organisation, checked on top of those of CTAP before a new PIN is set
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct OrgPinPolicy {
+	/// Shortest PIN allowed, in characters, if longer than the minimum of the key
+	pub min_length: Option<u32>,
+	/// PINs refused regardless of their length, compared ignoring ASCII case
+	pub blocklist: Vec<String>,
+}
+
+/// Rule a new PIN breaks
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(tag = "rule", rename_all = "camelCase")]
+pub enum PinViolation {
+	#[serde(rename_all = "camelCase")]
+	TooShort {
+		min_length: u32,
+	},
+	#[serde(rename_all = "camelCase")]
+	TooLong {
+		max_bytes: u32,
+	},
+	NulCharacter,
+	/// Same as the current PIN
+	Unchanged,
+	Blocklisted,
 }
 
+impl std::fmt::Display for PinViolation {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			PinViolation::TooShort { min_length } => {
+				write!(f, "The PIN must have at least {} characters", min_length)
+			}
+			PinViolation::TooLong { max_bytes } => write!(
+				f,
+				"The PIN must be at most {} bytes long, fewer characters if they are not ASCII",
+				max_bytes
+			),
+			PinViolation::NulCharacter => write!(f, "The PIN cannot contain a NUL character"),
+			PinViolation::Unchanged => write!(f, "The new PIN must differ from the current one"),
+			PinViolation::Blocklisted => write!(f, "This PIN is too common and not allowed"),
+		}
+	}
+}
+
+/// How the firmware took a setting written by `write_config`, judged by reading it back
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum WriteFieldStatus {
+	Applied,
+	/// Changed, but to a different value than requested
+	Clamped,
+	/// Left at the value it had before the write
+	Rejected,
+	/// Not exposed by the physical options read-back
+	Unverified,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WriteFieldResult {
+	/// Name of the setting in `AppConfigInput`, e.g. "ledBrightness"
+	pub field: String,
+	pub requested: String,
+	/// `None` if the read-back does not include the setting
+	pub actual: Option<String>,
+	pub status: WriteFieldStatus,
+}
+
+/// Outcome of writing a configuration over the FIDO method, verified by reading it back
 #[derive(Debug, Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
+pub struct WriteReport {
+	pub message: String,
+	pub fields: Vec<WriteFieldResult>,
+	/// One line per setting the firmware rejected or clamped
+	pub issues: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct StoredCredential {
 	pub rp_id: String,
 	pub rp_name: String,
@@ -83,4 +359,1524 @@ pub struct StoredCredential {
 	pub user_display_name: String,
 	pub user_id: String,
 	pub credential_id: String,
+	/// Display name from the bundled relying party metadata
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub rp_display_name: Option<String>,
+	/// Page where the user manages the passkeys of their account at this relying party
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub account_url: Option<String>,
+	/// credProtect level the passkey was created with, 1 to 3
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub cred_protect: Option<u8>,
+	/// COSE algorithm of the passkey's public key, e.g. -7 for ES256
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub algorithm: Option<i64>,
+}
+
+/// Passkeys stored on the device along with how many slots are used and left
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialStore {
+	pub credentials: Vec<StoredCredential>,
+	/// Passkeys on the whole device, whatever the listed credentials are limited to
+	pub existing_resident_credentials: u32,
+	pub max_remaining_credentials: u32,
+	/// Listed passkeys per relying party ID
+	pub rp_counts: BTreeMap<String, u32>,
+}
+
+impl CredentialStore {
+	pub fn new(credentials: Vec<StoredCredential>, existing: u32, remaining: u32) -> Self {
+		let mut rp_counts = BTreeMap::new();
+		for credential in &credentials {
+			*rp_counts.entry(credential.rp_id.clone()).or_insert(0) += 1;
+		}
+		Self {
+			credentials,
+			existing_resident_credentials: existing,
+			max_remaining_credentials: remaining,
+			rp_counts,
+		}
+	}
+}
+
+/// `get_credentials` payload in the shape of the API version the caller asked for
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum CredentialsPayload {
+	V1(v1::Credentials),
+	Current(CredentialStore),
+}
+
+/// Error of a versioned command in the shape of the API version the caller asked for
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum CommandError {
+	V1(v1::Error),
+	Current(crate::error::PFError),
+}
+
+impl From<crate::error::PFError> for CommandError {
+	fn from(e: crate::error::PFError) -> Self {
+		CommandError::Current(e)
+	}
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialSignCount {
+	pub credential_id: String,
+	pub rp_id: String,
+	pub sign_count: u32,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum SignCountStatus {
+	/// No earlier value recorded for this credential
+	FirstSeen,
+	Increased,
+	/// The authenticator always returns 0 (no counter)
+	NotSupported,
+	/// The counter did not increase since the last check
+	Regressed,
+	/// The counter dropped back to 0
+	Reset,
+}
+
+/// Outcome of the self test of one signature algorithm
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum SelfTestStatus {
+	Passed,
+	/// The round trip completed, but the signatures could not be checked, e.g. secp256k1
+	NotVerified,
+	Failed,
+	/// The firmware rejected the algorithm
+	Unsupported,
+	/// Not tested, e.g. secp256k1 while it is disabled
+	Skipped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlgorithmTestResult {
+	/// e.g. "ES256"
+	pub algorithm: String,
+	pub cose_algorithm: i32,
+	pub status: SelfTestStatus,
+	/// `None` if the signature could not be checked, e.g. secp256k1 self attestation
+	pub attestation_verified: Option<bool>,
+	pub assertion_verified: Option<bool>,
+	pub message: String,
+}
+
+/// Registration and assertion round trip of every algorithm, with a dummy relying party
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SelfTestReport {
+	pub rp_id: String,
+	pub results: Vec<AlgorithmTestResult>,
+}
+
+/// Output of the hmac-secret extension for a salt, all hex
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HmacSecretOutput {
+	pub rp_id: String,
+	pub credential_id: String,
+	pub salt: String,
+	pub output: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialVerification {
+	pub credential_id: String,
+	pub rp_id: String,
+	pub signature_valid: bool,
+	pub sign_count: u32,
+	pub previous_sign_count: Option<u32>,
+	pub sign_count_status: SignCountStatus,
+}
+
+/// Result of looking up an imported WebAuthn registration on the device
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationMatch {
+	pub credential_id: String,
+	/// Origin from clientDataJSON, e.g. "https://github.com"
+	pub origin: Option<String>,
+	pub aaguid: Option<String>,
+	/// Whether the credential is resident on the device
+	pub found: bool,
+	/// Whether the rpIdHash of the registration matches the RP ID stored on the device
+	pub rp_id_matches: Option<bool>,
+	pub credential: Option<StoredCredential>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecureNote {
+	pub id: String,
+	pub title: String,
+	pub body: String,
+	pub created_at: u64,
+}
+
+/// Fingerprint sensor of a key built with bio support
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BioSensorInfo {
+	/// "touch", "swipe" or "unknown"
+	pub fingerprint_kind: String,
+	/// Good samples needed for one enrollment
+	pub samples_required: u32,
+	/// Maximum length of an enrollment name in bytes
+	pub max_name_length: u32,
+}
+
+/// Fingerprint enrolled on the device
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BioEnrollment {
+	/// Template ID, hex encoded
+	pub template_id: String,
+	pub name: Option<String>,
+}
+
+// Inventory stuff:
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum DeviceEventKind {
+	FirmwareFlash,
+	ConfigChange,
+	Reset,
+	IntakeCheck,
+	AttestationChange,
+	SecretBackup,
+	SecretRestore,
+}
+
+/// Whether the firmware answers a category of pico-fido vendor commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum VendorSupport {
+	Supported,
+	Unsupported,
+	/// The probe failed for another reason, e.g. the key was unplugged
+	Unknown,
+}
+
+/// Vendor command categories the firmware of a key supports
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VendorCapabilities {
+	pub firmware_version: String,
+	pub memory: VendorSupport,
+	pub physical_options: VendorSupport,
+	pub backup: VendorSupport,
+	pub enterprise_attestation: VendorSupport,
+}
+
+/// Enterprise attestation support, from the `ep` option of GetInfo
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EnterpriseAttestationStatus {
+	pub supported: bool,
+	pub enabled: bool,
+}
+
+/// Certificate signing request for the enterprise attestation key, which never leaves the device
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EnterpriseAttestationCsr {
+	pub der: Vec<u8>,
+	pub pem: String,
+}
+
+/// Entry of the maintenance history of a device
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceEvent {
+	pub kind: DeviceEventKind,
+	pub summary: String,
+	pub timestamp: u64,
+}
+
+/// Free-text maintenance note attached to a device
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceNote {
+	pub id: String,
+	pub text: String,
+	pub created_at: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceHistory {
+	#[serde(default)]
+	pub notes: Vec<DeviceNote>,
+	/// Oldest event first
+	#[serde(default)]
+	pub events: Vec<DeviceEvent>,
+}
+
+/// When the periodic audits of a device were last done
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditRecord {
+	pub credentials_audited_at: Option<u64>,
+	pub oath_rotated_at: Option<u64>,
+}
+
+/// Device that must not be provisioned or used for credential operations
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuarantineEntry {
+	pub serial: String,
+	pub reason: String,
+	pub since: u64,
+}
+
+// Intake stuff:
+
+/// Outcome of checking the attestation of a throwaway credential
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationCheck {
+	/// Attestation statement format, "packed" for pico-fido
+	pub fmt: String,
+	/// AAGUID in the authenticator data of the attestation
+	pub aaguid: String,
+	pub signature_valid: bool,
+	pub chain_valid: bool,
+	pub certificate_count: usize,
+	pub issues: Vec<String>,
+}
+
+/// Certificate of an attestation chain
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationCertificate {
+	pub subject: String,
+	pub issuer: String,
+	/// Serial number, in hex
+	pub serial: String,
+	/// Unix time in seconds
+	pub not_before: i64,
+	pub not_after: i64,
+	pub valid_now: bool,
+	pub self_signed: bool,
+	/// AAGUID of the id-fido-gen-ce-aaguid extension, if present
+	pub aaguid: Option<String>,
+}
+
+/// Attestation certificate chain of a key, leaf first, and the outcome of checking it
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationInfo {
+	/// AAGUID reported by GetInfo
+	pub expected_aaguid: String,
+	/// Whether the attested AAGUID and the one in the leaf certificate match it
+	pub aaguid_matches: bool,
+	pub certificates: Vec<AttestationCertificate>,
+	pub check: AttestationCheck,
+}
+
+/// What an intake check recorded about one device
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IntakeRecord {
+	pub serial: String,
+	/// AAGUID reported by GetInfo
+	pub aaguid: String,
+	pub firmware_version: String,
+	/// USB port the device was checked in, see `FidoHidDevice::port_path`
+	#[serde(default)]
+	pub port_path: Option<String>,
+	pub attestation: AttestationCheck,
+	pub quarantined: bool,
+	pub checked_at: u64,
+}
+
+/// Batch of newly received devices, checked one after the other
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IntakeBatch {
+	/// AAGUID all devices should report, the most common one of the batch if not set
+	pub expected_aaguid: Option<String>,
+	/// Firmware version all devices should run, the most common one of the batch if not set
+	pub expected_firmware: Option<String>,
+	/// One record per device, the latest check replacing earlier ones
+	#[serde(default)]
+	pub records: Vec<IntakeRecord>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IntakeEntry {
+	pub record: IntakeRecord,
+	/// Why the device stands out, empty if it does not
+	pub issues: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IntakeReport {
+	pub batch: String,
+	/// Expected values, or the most common ones of the batch
+	pub aaguid: Option<String>,
+	pub firmware_version: Option<String>,
+	pub entries: Vec<IntakeEntry>,
+	/// Serial numbers of the devices with issues
+	pub outliers: Vec<String>,
+}
+
+// Migration stuff:
+
+/// Account the user must re-register on the new key, passkeys cannot be copied
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MigrationItem {
+	pub rp_id: String,
+	/// Display name from the relying party metadata, or the name stored with the credential
+	pub rp_name: String,
+	pub user_name: String,
+	/// Page where the user manages the passkeys of their account, if known
+	pub account_url: Option<String>,
+	/// When the account was registered on the new key, `None` while still to do
+	pub completed_at: Option<u64>,
+}
+
+/// File format of a credential export
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CredentialExportFormat {
+	Json,
+	Csv,
+}
+
+/// Resident credentials of a key as written by `export_credentials`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialExport {
+	pub format: String,
+	pub version: u32,
+	pub exported_at: u64,
+	/// Serial number of the key, if it could be identified
+	pub serial: Option<String>,
+	pub credentials: Vec<StoredCredential>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialExportReport {
+	pub path: String,
+	pub count: usize,
+	pub serial: Option<String>,
+}
+
+/// Passkey whose user changed since the export, as (exported, current)
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialChange {
+	pub exported: StoredCredential,
+	pub current: StoredCredential,
+}
+
+/// Difference between a credential export and the passkeys on the key now
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialComparison {
+	pub exported_at: Option<u64>,
+	/// Passkeys registered since the export
+	pub added: Vec<StoredCredential>,
+	/// Passkeys in the export that are no longer on the key
+	pub removed: Vec<StoredCredential>,
+	pub changed: Vec<CredentialChange>,
+	pub unchanged: usize,
+}
+
+/// Checklist of moving from an old key to a new one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Migration {
+	/// Serial number of the old key, if the Rescue Applet reported one
+	pub source_serial: Option<String>,
+	/// Serial number of the new key, once it was checked
+	#[serde(default)]
+	pub target_serial: Option<String>,
+	pub started_at: u64,
+	pub items: Vec<MigrationItem>,
+}
+
+/// FIDO key connected over HID
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FidoHidDevice {
+	/// Platform HID path, identifies the key until it is unplugged
+	pub path: String,
+	/// USB bus and port chain, e.g. "1-4.2", where the platform exposes it
+	pub port_path: Option<String>,
+	pub vid: String,
+	pub pid: String,
+	pub product_name: String,
+}
+
+// Dry run stuff:
+
+/// What a destructive command would do, returned instead of running it when `dry_run` is set
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DryRunReport {
+	pub operation: String,
+	/// Actions in the order they would be taken, empty if there is nothing to do
+	pub actions: Vec<String>,
+}
+
+/// Result of a command supporting `dry_run`. Serialized as the plain success message when it ran,
+/// or as the `WriteReport` of a write that was read back.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum CommandOutcome {
+	Done(String),
+	Written(WriteReport),
+	DryRun(DryRunReport),
+}
+
+impl CommandOutcome {
+	pub fn dry_run(operation: &str, actions: Vec<String>) -> Self {
+		Self::DryRun(DryRunReport {
+			operation: operation.to_string(),
+			actions,
+		})
+	}
+}
+
+// Device manager stuff:
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationInfo {
+	pub id: String,
+	/// Label of the window that started the operation
+	pub owner: String,
+	pub operation: String,
+	/// HID path of the device, `None` for operations that may talk to any key
+	pub device: Option<String>,
+	pub started_at: u64,
+}
+
+/// Position of an operation waiting for its device, sent to the window that started it when it
+/// gets queued and whenever an operation ahead of it finishes
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationQueued {
+	pub operation_id: String,
+	pub operation: String,
+	pub device: Option<String>,
+	/// Operations running or waiting ahead of it
+	pub position: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationProgress {
+	pub operation_id: String,
+	pub message: String,
+	pub current: u32,
+	pub total: u32,
+}
+
+/// What the backend is doing in an operation phase
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum PhaseVerb {
+	Connect,
+	Read,
+	Write,
+	Create,
+	Delete,
+	Authenticate,
+	Sign,
+	Verify,
+	Reboot,
+}
+
+/// View a window shows, limiting the destructive commands it may invoke
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum UiContext {
+	Home,
+	Passkeys,
+	Config,
+	Security,
+	Logs,
+	About,
+	Firmware,
+	OpenPgp,
+	Piv,
+	Developer,
+}
+
+/// What the user has to do for the phase to progress
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum UserAction {
+	None,
+	TouchDevice,
+	KeepConnected,
+	Reconnect,
+	TouchSensor,
+}
+
+/// Structured description of an operation phase, for screen readers and alternative UIs
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationPhase {
+	pub operation_id: String,
+	pub verb: PhaseVerb,
+	pub object: String,
+	pub user_action: UserAction,
+	/// Plain sentence combining the above, e.g. "Touch the blinking key to sign the digest"
+	pub description: String,
+}
+
+/// Status of a CTAPHID keepalive, sent by the key while a request is pending
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum KeepaliveStatus {
+	Processing,
+	UserPresenceNeeded,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationKeepalive {
+	pub operation_id: String,
+	pub status: KeepaliveStatus,
+}
+
+/// Overall state of the connected device, for the views to key their theme off
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum DeviceState {
+	NotConnected,
+	/// No PIN set yet
+	NeedsSetup,
+	/// PIN blocked after too many wrong attempts
+	Locked,
+	Healthy,
+	/// Healthy, but running older firmware than the newest known release
+	UpdateAvailable,
+	/// The device is connected but could not be queried
+	Error,
+}
+
+// Reprovisioning stuff:
+
+/// Settings a wiped key is provisioned with
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ReprovisionProfile {
+	pub min_pin_length: Option<u8>,
+	/// Applied with the FIDO method after the PIN has been set
+	pub config: Option<AppConfigInput>,
+}
+
+/// Checkpoints of the reprovisioning pipeline, in the order they run
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ReprovisionStep {
+	Reset,
+	SetPin,
+	MinPinLength,
+	ApplyConfig,
+	Verify,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReprovisionReport {
+	/// Checkpoints passed, steps skipped because they had nothing to do included
+	pub completed: Vec<ReprovisionStep>,
+	/// Step to resume from after fixing the cause of `error`
+	pub failed_step: Option<ReprovisionStep>,
+	pub error: Option<String>,
+	/// Device state read back in the verification step
+	pub info: Option<FidoDeviceInfo>,
+}
+
+// Session stuff:
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SessionPolicy {
+	/// Lock the session after this many minutes without device operations, `None` to never
+	pub idle_timeout_minutes: Option<u32>,
+	/// Lock the session when the desktop session is locked (Linux only)
+	pub lock_on_screen_lock: bool,
+	/// Operations allowed to run at the same time on different devices
+	pub max_concurrent_devices: u32,
+	/// Queue operations on a busy device, instead of failing them with `DeviceBusy`
+	pub queue_operations: bool,
+}
+
+impl Default for SessionPolicy {
+	fn default() -> Self {
+		Self {
+			idle_timeout_minutes: Some(15),
+			lock_on_screen_lock: true,
+			max_concurrent_devices: 2,
+			queue_operations: true,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum SessionLockReason {
+	Idle,
+	ScreenLocked,
+	/// Locked from the UI
+	Manual,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionLocked {
+	pub reason: SessionLockReason,
+	pub locked_at: u64,
+}
+
+// Reminder stuff:
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReminderSettings {
+	/// Remind to review the resident credentials this many days after the last review, `None`
+	/// to never
+	pub credential_audit_days: Option<u32>,
+	/// Remind to rotate the OTP secrets this many days after the last rotation, `None` to never
+	pub oath_rotation_days: Option<u32>,
+}
+
+impl Default for ReminderSettings {
+	fn default() -> Self {
+		Self {
+			credential_audit_days: Some(90),
+			oath_rotation_days: Some(180),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AuditKind {
+	/// Review the resident credentials and delete the unused ones
+	CredentialAudit,
+	/// Re-enroll the OATH accounts with fresh secrets
+	OathRotation,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditReminder {
+	pub serial: String,
+	pub kind: AuditKind,
+	/// When the audit was last done, `None` if never since the device was first seen
+	pub last_done: Option<u64>,
+	pub due_since: u64,
+	/// Relying parties of the credentials not verified since the last audit
+	pub stale_rp_ids: Vec<String>,
+}
+
+// Logging stuff:
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogRetention {
+	/// The log file is rotated once it reaches this size
+	pub max_file_size_mb: u32,
+	/// Rotated log files kept next to the current one, `0` to delete them right away
+	pub max_archives: u32,
+	/// Rotated log files older than this are deleted on startup, `None` to keep them
+	pub max_age_days: Option<u32>,
+}
+
+impl Default for LogRetention {
+	fn default() -> Self {
+		Self {
+			max_file_size_mb: 10,
+			max_archives: 3,
+			max_age_days: Some(14),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogPurgeReport {
+	pub files_deleted: u32,
+	pub bytes_freed: u64,
+}
+
+/// Entry of the in-memory log buffer
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogEntry {
+	/// Unix time in seconds
+	pub timestamp: u64,
+	pub level: String,
+	/// Module the entry was logged from
+	pub target: String,
+	/// Device operation running when the entry was logged
+	pub operation: Option<String>,
+	pub device: Option<String>,
+	pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogExportReport {
+	pub path: String,
+	pub entries: usize,
+}
+
+// Training mode stuff:
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum TrainingScenario {
+	#[default]
+	Normal,
+	/// The PIN retry counter is exhausted
+	PinBlocked,
+	/// Every operation requiring a touch times out
+	TouchTimeout,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrainingStatus {
+	pub enabled: bool,
+	pub scenario: TrainingScenario,
+}
+
+/// Forces a CTAP error whenever `command` is run on the simulated device
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandFault {
+	/// Command name as used by the frontend, e.g. "get_credentials"
+	pub command: String,
+	pub ctap_error: u8,
+}
+
+/// Faults injected into the simulated device for QA
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FaultInjectionConfig {
+	/// Every Nth command is dropped and runs into a timeout
+	pub drop_every_nth: Option<u32>,
+	#[serde(default)]
+	pub command_errors: Vec<CommandFault>,
+	/// Added before every simulated response
+	#[serde(default)]
+	pub delay_ms: u64,
+}
+
+// Firmware schema stuff:
+
+/// Kind of firmware constant, derived from the define prefix
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum SchemaGroup {
+	/// `PHY_*` tags of the physical configuration TLV
+	PhyTag,
+	/// `PHY_OPT_*` flags of the `PHY_OPTS` tag
+	PhyOption,
+	/// `CTAP_VENDOR_*` vendor commands
+	VendorCommand,
+	/// `CTAP_CONFIG_*` 64 bit vendor config command IDs
+	VendorConfigCommand,
+	/// Keys of the memory stats response
+	MemoryResponseKey,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SchemaConstant {
+	pub name: String,
+	pub value: u64,
+	pub group: SchemaGroup,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FirmwareSchema {
+	/// Firmware version of a bundled snapshot, `None` for user supplied source trees
+	pub firmware_version: Option<String>,
+	/// Where the constants were read from
+	pub source: String,
+	pub constants: Vec<SchemaConstant>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LabeledField {
+	/// Position in the response, e.g. "/1" for CBOR map key 1 or "0x06" for a PHY tag
+	pub path: String,
+	/// Firmware constant name, if one matches
+	pub label: Option<String>,
+	pub raw: String,
+	/// Human readable interpretation of the value, if known
+	pub decoded: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VendorResponseTrace {
+	pub command: u8,
+	pub command_label: Option<String>,
+	pub fields: Vec<LabeledField>,
+}
+
+/// Response of a raw APDU sent from the developer console
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RawApduResponse {
+	/// Hex encoded response data, continuations already collected
+	pub data: String,
+	pub sw: u16,
+}
+
+/// Response to a raw vendor command sent from the developer console
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RawVendorResponse {
+	/// CTAP status, 0 on success
+	pub status: u8,
+	/// Hex encoded response, without the status byte
+	pub data: String,
+	/// Response in CBOR diagnostic notation, as pretty-printed JSON
+	pub diagnostic: Option<String>,
+	/// Why a non-empty response could not be decoded as CBOR
+	pub decode_error: Option<String>,
+}
+
+// Firmware update stuff:
+
+/// Firmware image attached to a pico-fido release
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FirmwareAsset {
+	pub name: String,
+	/// Board the image is built for, from the file name, e.g. "pico2"
+	pub board: Option<String>,
+	pub download_url: String,
+	pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateStatus {
+	/// Firmware version of the device, "major.minor"
+	pub installed_version: String,
+	pub latest_version: String,
+	pub update_available: bool,
+	/// Release page on GitHub
+	pub release_url: String,
+	pub published_at: Option<String>,
+	/// Board the asset was picked for, if it could be determined
+	pub board: Option<String>,
+	/// Image of the latest release for the board
+	pub asset: Option<FirmwareAsset>,
+	/// All images of the latest release, to pick from if the board is unknown
+	pub assets: Vec<FirmwareAsset>,
+}
+
+// RNG diagnostic stuff:
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RngTestResult {
+	pub name: String,
+	pub statistic: f64,
+	/// Range the statistic must fall in, as text
+	pub expected: String,
+	pub passed: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RngReport {
+	/// Where the samples came from, e.g. "OpenPGP GET CHALLENGE"
+	pub source: String,
+	pub sample_bytes: usize,
+	/// Shannon entropy of the byte distribution, 8 at most
+	pub entropy_bits_per_byte: f64,
+	pub tests: Vec<RngTestResult>,
+	pub passed: bool,
+}
+
+// Job stuff:
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum JobKind {
+	FirmwareFlash,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum JobStatus {
+	Queued,
+	Running,
+	Completed,
+	Failed,
+	/// The app exited while the job was queued or running
+	Interrupted,
+}
+
+/// Parameters of a bulk firmware update job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FirmwareUpdateParams {
+	pub uf2_path: String,
+	/// Firmware version the devices must report after flashing, e.g. "7.2"
+	pub expected_version: Option<String>,
+	/// Flash quarantined devices too
+	#[serde(default)]
+	pub override_quarantine: bool,
+}
+
+/// Outcome of a batch job on one device
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TargetResult {
+	pub target: String,
+	pub success: bool,
+	/// Summary on success, error message on failure
+	pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobInfo {
+	pub id: String,
+	pub kind: JobKind,
+	pub label: String,
+	pub status: JobStatus,
+	/// Window that started the job and receives its progress
+	#[serde(default)]
+	pub owner: Option<String>,
+	/// Result message on success, error message on failure
+	pub message: Option<String>,
+	/// Devices processed by batch jobs, in order
+	#[serde(default)]
+	pub targets: Vec<String>,
+	#[serde(default)]
+	pub completed_targets: Vec<String>,
+	/// Latest result per processed device, in processing order
+	#[serde(default)]
+	pub results: Vec<TargetResult>,
+	/// Job specific parameters, kept so interrupted batch jobs can be resumed
+	#[serde(default)]
+	pub params: serde_json::Value,
+	pub created_at: u64,
+	pub finished_at: Option<u64>,
+}
+
+// OATH stuff:
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum TotpDriftStatus {
+	InSync,
+	Drifting,
+	TimezoneMismatch,
+	NoMatch,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TotpWindowCode {
+	pub step_offset: i64,
+	pub code: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TotpDriftReport {
+	pub account: String,
+	pub host_unix_time: u64,
+	pub utc_offset_seconds: i32,
+	pub period: u64,
+	pub window: Vec<TotpWindowCode>,
+	pub matched_offset: Option<i64>,
+	pub drift_seconds: Option<i64>,
+	pub status: TotpDriftStatus,
+	pub message: String,
+}
+
+/// Account stored in the OATH applet
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OathAccount {
+	/// "issuer:account", prefixed with "period/" for non-default TOTP periods
+	pub name: String,
+	/// "HOTP" or "TOTP"
+	pub kind: String,
+	/// "SHA1", "SHA256" or "SHA512"
+	pub algorithm: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum OathKind {
+	/// Counter based
+	Hotp,
+	/// Time based
+	#[default]
+	Totp,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum OathAlgorithm {
+	#[default]
+	Sha1,
+	Sha256,
+	Sha512,
+}
+
+/// Account to add to the OATH applet, entered manually or parsed from an otpauth:// URI
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OathCredential {
+	pub issuer: Option<String>,
+	pub account: String,
+	/// Base32 secret as shown by the service, spaces and padding allowed
+	pub secret: String,
+	#[serde(default)]
+	pub kind: OathKind,
+	#[serde(default)]
+	pub algorithm: OathAlgorithm,
+	/// 6 if not given
+	pub digits: Option<u8>,
+	/// TOTP time step in seconds, 30 if not given
+	pub period: Option<u32>,
+	/// Initial HOTP counter
+	#[serde(default)]
+	pub counter: u32,
+	/// Whether a touch is needed to compute each code
+	#[serde(default)]
+	pub require_touch: bool,
+}
+
+/// Code computed by the OATH applet
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OathCode {
+	pub name: String,
+	/// `None` for HOTP and touch-required accounts until computed one by one
+	pub code: Option<String>,
+	pub kind: OathKind,
+	pub touch_required: bool,
+	/// Unix time from which a TOTP code is no longer valid
+	pub valid_until: Option<u64>,
+}
+
+// OpenPGP stuff:
+
+/// Key slot of the OpenPGP card
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenPgpKeyInfo {
+	/// "signature", "decryption" or "authentication"
+	pub slot: String,
+	/// Hex fingerprint, `None` if no key is stored in the slot
+	pub fingerprint: Option<String>,
+	/// Generation time as a Unix timestamp
+	pub created_at: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenPgpStatus {
+	pub serial: String,
+	/// Manufacturer ID from the AID, 000F for pico-openpgp
+	pub manufacturer: String,
+	/// Version of the OpenPGP card specification, e.g. "3.4"
+	pub version: String,
+	pub cardholder_name: String,
+	pub language: String,
+	pub url: String,
+	pub login: String,
+	pub keys: Vec<OpenPgpKeyInfo>,
+	pub signature_counter: u32,
+	pub pw1_retries: u8,
+	pub reset_code_retries: u8,
+	pub pw3_retries: u8,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+pub enum OpenPgpPin {
+	/// PW1, used for signing, decryption and authentication
+	User,
+	/// PW3, used for card administration
+	Admin,
+	/// Resetting Code, unblocks PW1 without the admin PIN
+	ResetCode,
+}
+
+/// Cardholder data to write, fields left out are not changed
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenPgpCardholder {
+	/// "Surname<<Given names" as the specification recommends
+	pub name: Option<String>,
+	/// ISO 639-1 codes in order of preference, e.g. "ende"
+	pub language: Option<String>,
+	/// URL of the public keys
+	pub url: Option<String>,
+	pub login: Option<String>,
+}
+
+// PIV stuff:
+
+/// Key slot of the PIV applet
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum PivSlot {
+	/// 9A, PIV authentication
+	Authentication,
+	/// 9C, digital signature
+	Signature,
+	/// 9D, key management
+	KeyManagement,
+	/// 9E, card authentication
+	CardAuthentication,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum PivKeyAlgorithm {
+	Rsa2048,
+	EccP256,
+	EccP384,
+}
+
+/// Algorithm of the PIV management key
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+pub enum PivManagementKeyAlgorithm {
+	#[default]
+	TripleDes,
+	Aes128,
+	Aes192,
+	Aes256,
+}
+
+/// Public key of a key pair generated on the PIV applet
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PivGeneratedKey {
+	pub slot: PivSlot,
+	pub algorithm: PivKeyAlgorithm,
+	/// Hex modulus for RSA, hex uncompressed point for EC
+	pub public_key: String,
+	/// Hex public exponent, RSA only
+	pub exponent: Option<String>,
+}
+
+/// Certificate stored in a PIV slot
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PivCertificate {
+	pub slot: PivSlot,
+	pub der: Vec<u8>,
+	pub pem: String,
+	pub subject: String,
+	pub issuer: String,
+	/// Expiry as a Unix timestamp
+	pub not_after: i64,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+pub enum PivPin {
+	Pin,
+	/// PIN Unblocking Key
+	Puk,
+}
+
+// Workspace stuff:
+
+/// View of a shared device limited to the credentials and OATH accounts of one team.
+///
+/// Patterns are matched case-insensitively, `*` matching any run of characters. An empty pattern
+/// list does not restrict its field.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Workspace {
+	pub name: String,
+	/// Relying party IDs of the credentials, e.g. "*.qa.example.com"
+	#[serde(default)]
+	pub rp_patterns: Vec<String>,
+	/// User names of the credentials, e.g. "qa-*"
+	#[serde(default)]
+	pub user_patterns: Vec<String>,
+	/// OATH account names, e.g. "QA:*"
+	#[serde(default)]
+	pub oath_patterns: Vec<String>,
+}
+
+// HSM stuff:
+
+#[repr(u32)]
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SshCertType {
+	User = 1,
+	Host = 2,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SshCertRequest {
+	pub pin: String,
+	/// Key ID of the CA key on the HSM
+	pub ca_key_ref: u8,
+	pub user_public_key: String,
+	pub key_id: String,
+	pub principals: Vec<String>,
+	pub cert_type: SshCertType,
+	pub serial: Option<u64>,
+	pub valid_after: Option<u64>,
+	pub valid_before: Option<u64>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SshCertificate {
+	pub certificate: String,
+	pub serial: u64,
+	pub key_id: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct X509CertRequest {
+	pub pin: String,
+	/// Key ID of the private key on the HSM
+	pub key_ref: u8,
+	pub common_name: String,
+	pub organization: Option<String>,
+	pub not_before: Option<u64>,
+	pub valid_days: u32,
+	#[serde(default)]
+	pub dns_names: Vec<String>,
+	#[serde(default)]
+	pub email_addresses: Vec<String>,
+	/// Issue a new certificate even though the key already has one
+	#[serde(default)]
+	pub overwrite: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct X509Certificate {
+	pub pem: String,
+	pub serial: String,
+	pub not_before: u64,
+	pub not_after: u64,
+}
+
+// API versioning stuff:
+
+/// Command payload versions the backend speaks, for the frontend to check on startup
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiVersion {
+	pub current: u32,
+	/// Bundles asking for an older version are refused
+	pub oldest_supported: u32,
+	pub app_version: String,
+}
+
+// Settings backup stuff:
+
+/// Settings backup written or restored, bound to the key it was made with
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingsBackupReport {
+	pub path: String,
+	/// Settings files in the backup, or restored from it
+	pub files: Vec<String>,
+	pub created_at: u64,
+}
+
+/// Backup of the device secret written or restored
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceBackupReport {
+	pub path: String,
+	/// Serial number of the key the backup was made from
+	pub serial: String,
+	pub aaguid: String,
+	pub created_at: u64,
+}
+
+// Configuration profile stuff:
+
+/// Device configuration saved to a file, to write the same settings to many keys
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigProfile {
+	pub name: String,
+	pub path: String,
+	pub created_at: u64,
+	pub config: AppConfigInput,
+	/// What writing the profile to a key changes, one line per setting
+	pub changes: Vec<String>,
+}
+
+// Commissioning stuff:
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum CommissioningStage {
+	Identifying,
+	Writing,
+	Verifying,
+	Done,
+	Failed,
+}
+
+/// Outcome of commissioning one key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommissioningRecord {
+	/// `None` if the key failed before it could be identified
+	pub serial: Option<String>,
+	pub aaguid: Option<String>,
+	pub port_path: Option<String>,
+	pub success: bool,
+	pub message: String,
+	/// Settings the firmware rejected or clamped, see `WriteReport`
+	#[serde(default)]
+	pub mismatches: Vec<String>,
+	#[serde(default)]
+	pub firmware_version: Option<String>,
+	#[serde(default)]
+	pub min_pin_length: Option<u32>,
+	/// `None` if other keys were connected, see `fido::read_device_details`
+	#[serde(default)]
+	pub secure_boot: Option<bool>,
+	pub commissioned_at: u64,
+}
+
+/// Keys commissioned with one profile, in the order they were plugged in
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommissioningSession {
+	pub id: String,
+	pub profile_name: String,
+	pub profile_path: String,
+	/// SHA-256 of the configuration of the profile, see `profiles::config_hash`
+	#[serde(default)]
+	pub profile_hash: Option<String>,
+	pub started_at: u64,
+	/// `None` while keys are still being commissioned
+	pub finished_at: Option<u64>,
+	#[serde(default)]
+	pub records: Vec<CommissioningRecord>,
+}
+
+// Commissioning report stuff:
+
+/// Key commissioned in the session a report covers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReportedDevice {
+	pub serial: String,
+	pub aaguid: Option<String>,
+	pub firmware_version: Option<String>,
+	pub profile_hash: Option<String>,
+	pub secure_boot: Option<bool>,
+	pub min_pin_length: Option<u32>,
+	pub commissioned_at: u64,
+}
+
+/// Record of the keys a commissioning session provisioned, for the paperwork
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommissioningReport {
+	pub session_id: String,
+	pub profile_name: String,
+	pub profile_hash: Option<String>,
+	pub started_at: u64,
+	pub finished_at: Option<u64>,
+	pub generated_at: u64,
+	/// Keys that failed commissioning are left out
+	pub devices: Vec<ReportedDevice>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReportSignature {
+	/// Always "HMAC-SHA256"
+	pub algorithm: String,
+	/// First bytes of the SHA-256 of the secret, to tell which station signed the report
+	pub key_id: String,
+	/// Hex encoded HMAC of the report serialized as compact JSON
+	pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedCommissioningReport {
+	pub report: CommissioningReport,
+	pub signature: ReportSignature,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommissioningReportExport {
+	pub path: String,
+	pub devices: usize,
+	pub signature: ReportSignature,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommissioningProgress {
+	pub session_id: String,
+	/// HID path of the key, tells apart keys commissioned at the same time
+	pub device_path: String,
+	pub port_path: Option<String>,
+	pub serial: Option<String>,
+	pub stage: CommissioningStage,
+	pub message: String,
+}
+
+// Activity stuff:
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ActivityKind {
+	DeviceConnected,
+	DeviceRemoved,
+	OperationStarted,
+	OperationFinished,
+	Warning,
+	Error,
+}
+
+/// Entry of the activity feed
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActivityEvent {
+	/// Increases with every event, older events have lower IDs
+	pub id: u64,
+	pub kind: ActivityKind,
+	/// Unix time in seconds
+	pub timestamp: u64,
+	pub summary: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub device: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub operation: Option<String>,
+	/// Only set for finished operations
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub duration_ms: Option<u64>,
+}
+
+/// Page of the activity feed, newest event first
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActivityPage {
+	pub events: Vec<ActivityEvent>,
+	/// Whether older matching events exist, to be fetched with the ID of the last event as `before`
+	pub has_more: bool,
 }
@@ -0,0 +1,51 @@
+//! Version 1 of the command payloads, as frontend bundles predating API versioning expect them.
+//!
+//! Structs here are frozen. When a payload changes shape, its previous form is kept here and the
+//! command converts to it for callers asking for version 1.
+
+use serde::Serialize;
+
+/// Passkey as listed by `get_credentials`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredCredential {
+	pub rp_id: String,
+	pub rp_name: String,
+	pub user_name: String,
+	pub user_display_name: String,
+	pub user_id: String,
+	pub credential_id: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub rp_display_name: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub account_url: Option<String>,
+}
+
+impl From<super::StoredCredential> for StoredCredential {
+	fn from(credential: super::StoredCredential) -> Self {
+		Self {
+			rp_id: credential.rp_id,
+			rp_name: credential.rp_name,
+			user_name: credential.user_name,
+			user_display_name: credential.user_display_name,
+			user_id: credential.user_id,
+			credential_id: credential.credential_id,
+			rp_display_name: credential.rp_display_name,
+			account_url: credential.account_url,
+		}
+	}
+}
+
+/// `get_credentials` answered the plain list, without the slot usage of version 2
+pub type Credentials = Vec<StoredCredential>;
+
+pub fn credentials(store: super::CredentialStore) -> Credentials {
+	store.credentials.into_iter().map(Into::into).collect()
+}
+
+/// Commands failed with the plain message, before errors were reported as `{ type, message }`
+pub type Error = String;
+
+pub fn error(e: crate::error::PFError) -> Error {
+	e.to_string()
+}
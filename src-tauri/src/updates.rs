@@ -0,0 +1,115 @@
+//! Check for newer pico-fido releases on GitHub.
+//!
+//! The releases carry one UF2 image per board, named like `pico_fido_pico2-7.2.uf2`. The image
+//! for the board of the device is picked by the board name given by the user, or else by the USB
+//! product name of the device if it matches one of the boards.
+
+use crate::{error::PFError, schema, types::*};
+use serde::Deserialize;
+
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/pico-keys/pico-fido/releases/latest";
+
+#[derive(Deserialize)]
+struct Release {
+	tag_name: String,
+	html_url: String,
+	published_at: Option<String>,
+	assets: Vec<ReleaseAsset>,
+}
+
+#[derive(Deserialize)]
+struct ReleaseAsset {
+	name: String,
+	browser_download_url: String,
+	size: u64,
+}
+
+/// Board of a release image, "pico_fido_waveshare_rp2040_zero-7.2.uf2" being built for
+/// "waveshare_rp2040_zero"
+fn board_of(asset_name: &str) -> Option<String> {
+	let stem = asset_name.strip_suffix(".uf2")?;
+	let stem = stem
+		.strip_prefix("pico_fido_")
+		.or_else(|| stem.strip_prefix("pico-fido_"))
+		.unwrap_or(stem);
+	let board = match stem.rsplit_once(['-', '_']) {
+		Some((board, version)) if version.bytes().all(|b| b.is_ascii_digit() || b == b'.') => board,
+		_ => stem,
+	};
+	(!board.is_empty()).then(|| board.to_string())
+}
+
+/// Lowercase with separators unified, so "Waveshare RP2040-Zero" matches "waveshare_rp2040_zero"
+fn normalize(name: &str) -> String {
+	name.trim().to_ascii_lowercase().replace([' ', '-'], "_")
+}
+
+fn pick_asset<'a>(
+	assets: &'a [FirmwareAsset],
+	board: Option<&str>,
+	product_name: &str,
+) -> Option<&'a FirmwareAsset> {
+	let wanted = board.map(normalize).or_else(|| {
+		let product = normalize(product_name);
+		assets
+			.iter()
+			.filter_map(|a| a.board.as_deref())
+			.find(|b| normalize(b) == product)
+			.map(normalize)
+	})?;
+	assets
+		.iter()
+		.find(|a| a.board.as_deref().map(normalize).as_deref() == Some(wanted.as_str()))
+}
+
+/// Compares the firmware of `status` with the latest release on GitHub
+pub async fn check(
+	status: &FullDeviceStatus,
+	board: Option<&str>,
+) -> Result<UpdateStatus, PFError> {
+	let installed = status.info.firmware.ok_or_else(|| {
+		PFError::Device("Could not read the firmware version of the device".into())
+	})?;
+	let installed = format!("{}.{}", installed.major, installed.minor);
+
+	log::info!(
+		"Checking GitHub for pico-fido releases newer than {}",
+		installed
+	);
+	let release: Release = reqwest::Client::new()
+		.get(LATEST_RELEASE_URL)
+		.header(reqwest::header::USER_AGENT, "picoforge")
+		.header(reqwest::header::ACCEPT, "application/vnd.github+json")
+		.send()
+		.await
+		.and_then(|response| response.error_for_status())
+		.map_err(|e| PFError::Io(format!("Failed to query GitHub releases: {}", e)))?
+		.json()
+		.await
+		.map_err(|e| PFError::Io(format!("Unexpected answer from GitHub: {}", e)))?;
+
+	let latest = release.tag_name.trim_start_matches(['v', 'V']).to_string();
+	let assets: Vec<FirmwareAsset> = release
+		.assets
+		.into_iter()
+		.filter(|a| a.name.ends_with(".uf2"))
+		.map(|a| FirmwareAsset {
+			board: board_of(&a.name),
+			name: a.name,
+			download_url: a.browser_download_url,
+			size: a.size,
+		})
+		.collect();
+	let asset = pick_asset(&assets, board, &status.config.product_name).cloned();
+
+	Ok(UpdateStatus {
+		update_available: schema::version_key(&installed) < schema::version_key(&latest),
+		installed_version: installed,
+		latest_version: latest,
+		release_url: release.html_url,
+		published_at: release.published_at,
+		board: asset.as_ref().and_then(|a| a.board.clone()),
+		asset,
+		assets,
+	})
+}
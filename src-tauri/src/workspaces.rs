@@ -0,0 +1,103 @@
+//! Workspaces, views of a shared device limited to the credentials and OATH accounts of one team.
+//!
+//! A QA team sharing a key with others only sees, and may only delete, the test credentials
+//! matching the patterns of its workspace. This is a convenience for shared test devices, not an
+//! access control: anyone with the PIN can still use the unfiltered commands.
+
+use crate::{error::PFError, paths, storage, types::*};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+const WORKSPACES_FILE: &str = "workspaces.json";
+
+/// Held from loading to saving the workspaces, so concurrent changes are not lost
+static LOCK: Mutex<()> = Mutex::new(());
+
+fn workspaces_path() -> PathBuf {
+	paths::data_dir().join(WORKSPACES_FILE)
+}
+
+fn load_all() -> Result<BTreeMap<String, Workspace>, PFError> {
+	match storage::read(&workspaces_path()) {
+		Ok(data) => serde_json::from_slice(&data)
+			.map_err(|e| PFError::Io(format!("Workspaces are corrupted: {}", e))),
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+		Err(e) => Err(PFError::Io(format!("Failed to read workspaces: {}", e))),
+	}
+}
+
+fn save_all(workspaces: &BTreeMap<String, Workspace>) -> Result<(), PFError> {
+	let data = serde_json::to_vec_pretty(workspaces).map_err(|e| PFError::Io(e.to_string()))?;
+	storage::write(&workspaces_path(), &data)
+		.map_err(|e| PFError::Io(format!("Failed to save workspaces: {}", e)))
+}
+
+pub fn list() -> Result<Vec<Workspace>, PFError> {
+	Ok(load_all()?.into_values().collect())
+}
+
+pub fn get(name: &str) -> Result<Workspace, PFError> {
+	load_all()?
+		.remove(name)
+		.ok_or_else(|| PFError::Io(format!("No workspace named {}", name)))
+}
+
+/// Adds the workspace, replacing the one with the same name
+pub fn save(workspace: Workspace) -> Result<(), PFError> {
+	if workspace.name.trim().is_empty() {
+		return Err(PFError::Io("The workspace needs a name".into()));
+	}
+	let _guard = LOCK.lock().unwrap_or_else(|p| p.into_inner());
+	let mut workspaces = load_all()?;
+	workspaces.insert(workspace.name.clone(), workspace);
+	save_all(&workspaces)
+}
+
+/// Removes the workspace, returning whether it existed
+pub fn delete(name: &str) -> Result<bool, PFError> {
+	let _guard = LOCK.lock().unwrap_or_else(|p| p.into_inner());
+	let mut workspaces = load_all()?;
+	let existed = workspaces.remove(name).is_some();
+	save_all(&workspaces)?;
+	Ok(existed)
+}
+
+/// Case-insensitive match of `value` against `pattern`, where `*` matches any run of characters
+fn glob_match(pattern: &str, value: &str) -> bool {
+	let pattern = pattern.to_lowercase();
+	let value = value.to_lowercase();
+	let mut parts = pattern.split('*');
+	let first = parts.next().unwrap_or_default();
+	let Some(mut rest) = value.strip_prefix(first) else {
+		return false;
+	};
+	let parts: Vec<&str> = parts.collect();
+	let Some((last, middle)) = parts.split_last() else {
+		// No wildcard, the whole value must match
+		return rest.is_empty();
+	};
+	for part in middle {
+		match rest.find(part) {
+			Some(index) => rest = &rest[index + part.len()..],
+			None => return false,
+		}
+	}
+	rest.ends_with(last)
+}
+
+fn matches_any(patterns: &[String], value: &str) -> bool {
+	patterns.is_empty() || patterns.iter().any(|p| glob_match(p, value))
+}
+
+impl Workspace {
+	pub fn contains_credential(&self, credential: &StoredCredential) -> bool {
+		matches_any(&self.rp_patterns, &credential.rp_id)
+			&& matches_any(&self.user_patterns, &credential.user_name)
+	}
+
+	/// Whether the OATH account stored under `name` belongs to the workspace
+	pub fn contains_oath_account(&self, name: &str) -> bool {
+		matches_any(&self.oath_patterns, name)
+	}
+}
@@ -1,5 +1,8 @@
 import type { DeviceConfig } from "$lib/device/types.svelte.ts";
 
+// Version of the command payloads this bundle was built against, see src-tauri/src/api.rs
+export const API_VERSION = 2;
+
 export const VENDORS = [
   { value: "custom", label: "Custom (Manual Entry)", vid: "", pid: "" },
   { value: "generic", label: "Generic (FEFF:FCFD)", vid: "FEFF", pid: "FCFD" },
@@ -24,6 +27,7 @@ export const LED_DRIVERS = [
   { value: "1", label: "Pico (Standard GPIO)" },
   { value: "2", label: "Pimoroni (RGB)" },
   { value: "3", label: "WS2812 (Neopixel)" },
+  { value: "4", label: "CYW43 (Pico W)" },
   { value: "5", label: "ESP32 Neopixel" },
 ];
 
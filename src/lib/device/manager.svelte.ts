@@ -1,6 +1,7 @@
 import { invoke } from "@tauri-apps/api/core";
 import { logger } from "$lib/services/log.svelte";
-import { DEFAULT_CONFIG, DEFAULT_DEVICE_INFO, VENDORS } from "$lib/device/constants.svelte";
+import { errorMessage } from "$lib/utils";
+import { API_VERSION, DEFAULT_CONFIG, DEFAULT_DEVICE_INFO, VENDORS } from "$lib/device/constants.svelte";
 import type {
   DeviceConfig,
   DeviceInfo,
@@ -9,6 +10,9 @@ import type {
   FullDeviceStatus,
   DeviceConfigInput,
   StoredCredential,
+  CredentialStore,
+  WriteReport,
+  PinPolicy,
 } from "$lib/device/types.svelte";
 
 class DeviceManager {
@@ -19,6 +23,7 @@ class DeviceManager {
   method: string = $state("");
 
   credentials: StoredCredential[] = $state([]);
+  credentialSlots: { used: number; total: number } | null = $state(null);
   unlocked = $state(false);
 
   config: DeviceConfig = $state({ ...DEFAULT_CONFIG });
@@ -158,8 +163,12 @@ class DeviceManager {
         return { success: false, msg: "No changes detected." };
       } else {
         logger.add("Sending configuration to device...", "info");
-        const response = await invoke("write_config", { config: rustConfig, method: this.method, pin });
-        logger.add(`Device Response: ${response}`, "success");
+        const response = await invoke<string | WriteReport>("write_config", { config: rustConfig, method: this.method, pin });
+        if (typeof response === "string") {
+          logger.add(`Device Response: ${response}`, "success");
+        } else {
+          logger.add(`Device Response: ${response.message}`, response.issues.length ? "warning" : "success");
+        }
 
         await this.refresh();
         return { success: true, msg: "Configuration Applied Successfully!" };
@@ -189,53 +198,64 @@ class DeviceManager {
       logger.add(res as string, "success");
       return { success: true };
     } catch (err) {
-      logger.add(`PIN Error: ${err}`, "error");
-      return { success: false, msg: err };
+      const msg = errorMessage(err);
+      logger.add(`PIN Error: ${msg}`, "error");
+      return { success: false, msg };
     }
   }
 
   async updateMinPinLength(currentPin: string, length: number) {
     try {
-      const res = await invoke("set_min_pin_length", { currentPin, minPinLength: length });
-      logger.add(res as string, "success");
+      const policy = await invoke<PinPolicy>("set_min_pin_length", { currentPin, minPinLength: length });
+      logger.add(`Minimum PIN length set to ${policy.minPinLength}`, "success");
       await this.refresh();
       return { success: true };
     } catch (err) {
-      logger.add(`Min PIN Error: ${err}`, "error");
-      return { success: false, msg: err };
+      const msg = errorMessage(err);
+      logger.add(`Min PIN Error: ${msg}`, "error");
+      return { success: false, msg };
     }
   }
 
-  async getCredentials(pin: string): Promise<{ success: boolean; data?: StoredCredential[]; msg?: string }> {
+  /** Without a PIN, the PIN token the session holds for the key is used. */
+  async getCredentials(pin?: string): Promise<{ success: boolean; data?: StoredCredential[]; msg?: string }> {
     try {
       logger.add("Fetching credentials...", "info");
-      const creds = await invoke<StoredCredential[]>("get_credentials", { pin });
+      const store = await invoke<CredentialStore>("get_credentials", { pin: pin || null, apiVersion: API_VERSION });
+      const creds = store.credentials;
 
       this.credentials = creds;
+      this.credentialSlots = {
+        used: store.existingResidentCredentials,
+        total: store.existingResidentCredentials + store.maxRemainingCredentials,
+      };
       this.unlocked = true;
 
       logger.add(`Retrieved ${creds.length} credentials.`, "success");
       return { success: true, data: creds };
     } catch (err: any) {
-      logger.add(`Failed to fetch credentials: ${err}`, "error");
-      return { success: false, msg: err };
+      const msg = errorMessage(err);
+      logger.add(`Failed to fetch credentials: ${msg}`, "error");
+      return { success: false, msg };
     }
   }
 
   lock() {
     this.credentials = [];
+    this.credentialSlots = null;
     this.unlocked = false;
   }
 
-  async deleteCredential(pin: string, credentialId: string): Promise<{ success: boolean; msg?: string }> {
+  async deleteCredential(pin: string | undefined, credentialId: string): Promise<{ success: boolean; msg?: string }> {
     try {
       logger.add("Deleting credential...", "info");
-      const res = await invoke<string>("delete_credential", { pin, credentialId });
+      const res = await invoke<string>("delete_credential", { pin: pin || null, credentialId });
       logger.add(res, "success");
       return { success: true, msg: res };
     } catch (err: any) {
-      logger.add(`Failed to delete credential: ${err}`, "error");
-      return { success: false, msg: err };
+      const msg = errorMessage(err);
+      logger.add(`Failed to delete credential: ${msg}`, "error");
+      return { success: false, msg };
     }
   }
 }
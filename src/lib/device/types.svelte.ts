@@ -26,6 +26,88 @@ export interface DeviceConfigInput {
   enableSecp256k1?: boolean;
 }
 
+/** Device configuration saved to a file, to write the same settings to many keys */
+export interface ConfigProfile {
+  name: string;
+  path: string;
+  createdAt: number;
+  config: DeviceConfigInput;
+  /** What writing the profile to a key changes, one line per setting */
+  changes: string[];
+}
+
+export type WriteFieldStatus = "Applied" | "Clamped" | "Rejected" | "Unverified";
+
+export interface WriteFieldResult {
+  /** Name of the setting in the config input, e.g. "ledBrightness" */
+  field: string;
+  requested: string;
+  actual: string | null;
+  status: WriteFieldStatus;
+}
+
+/** Outcome of a FIDO configuration write, verified by reading it back */
+export interface WriteReport {
+  message: string;
+  fields: WriteFieldResult[];
+  /** One line per setting the firmware rejected or clamped */
+  issues: string[];
+}
+
+export type CommissioningStage = "Identifying" | "Writing" | "Verifying" | "Done" | "Failed";
+
+/** Outcome of commissioning one key */
+export interface CommissioningRecord {
+  serial: string | null;
+  aaguid: string | null;
+  portPath: string | null;
+  success: boolean;
+  message: string;
+  /** Settings the firmware rejected or clamped */
+  mismatches: string[];
+  firmwareVersion: string | null;
+  minPinLength: number | null;
+  /** null if other keys were connected */
+  secureBoot: boolean | null;
+  commissionedAt: number;
+}
+
+/** Keys commissioned with one profile, in the order they were plugged in */
+export interface CommissioningSession {
+  id: string;
+  profileName: string;
+  profilePath: string;
+  /** SHA-256 of the configuration of the profile */
+  profileHash: string | null;
+  startedAt: number;
+  /** null while keys are still being commissioned */
+  finishedAt: number | null;
+  records: CommissioningRecord[];
+}
+
+export interface ReportSignature {
+  algorithm: string;
+  keyId: string;
+  value: string;
+}
+
+/** Result of generate_commissioning_report */
+export interface CommissioningReportExport {
+  path: string;
+  devices: number;
+  signature: ReportSignature;
+}
+
+/** Payload of the "commissioning-progress" event */
+export interface CommissioningProgress {
+  sessionId: string;
+  devicePath: string;
+  portPath: string | null;
+  serial: string | null;
+  stage: CommissioningStage;
+  message: string;
+}
+
 export interface DeviceInfo {
   serial: string;
   flashUsed: number;
@@ -33,8 +115,20 @@ export interface DeviceInfo {
   firmwareVersion: string;
 }
 
+/** Flash usage in bytes. pico-fido reports no usage per kind of file. */
+export interface FlashBreakdown {
+  total: number;
+  used: number;
+  free: number;
+  files: number;
+  flashSize: number;
+  reserved: number;
+}
+
 export interface FullDeviceStatus {
   info: DeviceInfo;
+  /** Missing if the firmware only reports the used and total space */
+  flash?: FlashBreakdown;
   config: DeviceConfig;
   secureBoot: boolean;
   secureLock: boolean;
@@ -59,6 +153,51 @@ export interface FidoInfo {
   firmwareVersion: string;
 }
 
+export interface PinStatus {
+  pinSet: boolean;
+  pinRetries: number;
+  uvRetries: number | null;
+  blocked: boolean;
+  powerCycleRequired: boolean;
+}
+
+export type PFErrorType =
+  | "NoDevice"
+  | "Pcsc"
+  | "Io"
+  | "Device"
+  | "ResetNotAllowed"
+  | "PinInvalid"
+  | "PinBlocked"
+  | "PinRequired"
+  | "PinPolicyViolation"
+  | "DeviceBusy"
+  | "UserPresenceTimeout"
+  | "WrongDevice"
+  | "Cancelled"
+  | "CborDecode"
+  | "VendorUnsupported"
+  | "TransportIo"
+  | "CommandNotPermitted";
+
+/** Error returned by the backend commands */
+export interface PFError {
+  type: PFErrorType;
+  message: string;
+  /** Only for PinInvalid, when the key reported its retry counter */
+  retriesLeft?: number | null;
+  /** Only for WrongDevice, the key the operation waited for */
+  productName?: string;
+  portPath?: string | null;
+  /** Only for PinPolicyViolation */
+  violation?: PinViolation;
+  /** Only for DeviceBusy, the operation holding the key */
+  operation?: string;
+  /** Only for CommandNotPermitted, the command and the view of the window that invoked it */
+  command?: string;
+  context?: string | null;
+}
+
 export interface StoredCredential {
   credentialId: string;
   rpId: string;
@@ -66,4 +205,72 @@ export interface StoredCredential {
   userId: string;
   userName: string;
   userDisplayName: string;
+  credProtect?: number;
+  algorithm?: number;
+}
+
+export interface PinPolicy {
+  minPinLength: number;
+  forcePinChange: boolean;
+  maxRpIds: number;
+  setMinPinLengthSupported: boolean;
+}
+
+export interface OrgPinPolicy {
+  minLength: number | null;
+  blocklist: string[];
+}
+
+export type PinViolation =
+  | { rule: "tooShort"; minLength: number }
+  | { rule: "tooLong"; maxBytes: number }
+  | { rule: "nulCharacter" }
+  | { rule: "unchanged" }
+  | { rule: "blocklisted" };
+
+export type CredentialExportFormat = "json" | "csv";
+
+export interface CredentialExportReport {
+  path: string;
+  count: number;
+  serial: string | null;
+}
+
+export interface CredentialComparison {
+  exportedAt: number | null;
+  added: StoredCredential[];
+  removed: StoredCredential[];
+  changed: { exported: StoredCredential; current: StoredCredential }[];
+  unchanged: number;
+}
+
+export interface CredentialStore {
+  credentials: StoredCredential[];
+  existingResidentCredentials: number;
+  maxRemainingCredentials: number;
+  rpCounts: Record<string, number>;
+}
+
+export type ActivityKind =
+  | "DeviceConnected"
+  | "DeviceRemoved"
+  | "OperationStarted"
+  | "OperationFinished"
+  | "Warning"
+  | "Error";
+
+export interface ActivityEvent {
+  id: number;
+  kind: ActivityKind;
+  /** Unix time in seconds */
+  timestamp: number;
+  summary: string;
+  device?: string;
+  operation?: string;
+  durationMs?: number;
+}
+
+export interface ActivityPage {
+  events: ActivityEvent[];
+  hasMore: boolean;
 }
@@ -11,3 +11,34 @@ export type WithoutChild<T> = T extends { child?: any } ? Omit<T, "child"> : T;
 export type WithoutChildren<T> = T extends { children?: any } ? Omit<T, "children"> : T;
 export type WithoutChildrenOrChild<T> = WithoutChildren<WithoutChild<T>>;
 export type WithElementRef<T, U extends HTMLElement = HTMLElement> = T & { ref?: U | null };
+
+/** Message of an error thrown by `invoke`, a structured `PFError` or a plain string */
+export function errorMessage(err: unknown): string {
+	if (typeof err === "string") return err;
+	if (err && typeof err === "object" && "message" in err && typeof err.message === "string") {
+		return err.message;
+	}
+	return JSON.stringify(err);
+}
+
+const RELATIVE_TIME_UNITS: [Intl.RelativeTimeFormatUnit, number][] = [
+	["year", 365 * 24 * 60 * 60],
+	["month", 30 * 24 * 60 * 60],
+	["week", 7 * 24 * 60 * 60],
+	["day", 24 * 60 * 60],
+	["hour", 60 * 60],
+	["minute", 60],
+	["second", 1],
+];
+
+/** "3 minutes ago" for a Unix timestamp in seconds, in the language of the user */
+export function formatRelativeTime(timestamp: number, now: number = Date.now() / 1000): string {
+	const format = new Intl.RelativeTimeFormat(navigator.languages, { numeric: "auto" });
+	const elapsed = timestamp - now;
+	for (const [unit, seconds] of RELATIVE_TIME_UNITS) {
+		if (Math.abs(elapsed) >= seconds || unit === "second") {
+			return format.format(Math.round(elapsed / seconds), unit);
+		}
+	}
+	return format.format(0, "second");
+}